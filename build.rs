@@ -3,6 +3,11 @@ use std::path::{Path, PathBuf};
 
 fn main() {
     let pkg_name = std::env::var("CARGO_PKG_NAME").unwrap();
+    // For when the host target triple can't be detected at runtime.
+    println!(
+        "cargo:rustc-env=CARGO_MOBILE_BUILD_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
     let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
     let install_dir = home::home_dir()
         .expect("failed to get user's home dir")
@@ -25,36 +30,88 @@ fn main() {
         ),
     }
 
-    // Copy templates
-    let bike = bicycle::Bicycle::default();
-    for rel in ["platforms", "apps"]
-        .iter()
-        .map(|prefix| Path::new("templates").join(prefix))
-    {
-        let src = manifest_dir.join(&rel);
+    // Embed templates, so that a binary installed from anywhere has them.
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let mut files = Vec::new();
+    for prefix in ["platforms", "apps"].iter() {
+        let src = manifest_dir.join("templates").join(prefix);
         println!("cargo:rerun-if-changed={}", src.display());
-        let dest = install_dir.join(rel);
-        let actions = bicycle::traverse(&src, &dest, bicycle::no_transform, None)
-            .expect("failed to traverse src templates dir");
-        if dest.is_dir() {
-            std::fs::remove_dir_all(&dest).expect("failed to delete old templates");
+        collect_files(&src, Path::new(prefix), &mut files);
+    }
+    files.sort();
+    let mut hash = Fnv::default();
+    let mut generated = String::from("static FILES: &[EmbeddedFile] = &[\n");
+    for (rel, src, executable) in &files {
+        println!("cargo:rerun-if-changed={}", src.display());
+        let contents = std::fs::read(src).expect("failed to read template file");
+        hash.write(rel.as_bytes());
+        hash.write(&contents);
+        hash.write(&[*executable as u8]);
+        generated.push_str(&format!(
+            "    EmbeddedFile {{ path: {:?}, contents: include_bytes!({:?}), executable: {} }},\n",
+            rel, src, executable
+        ));
+    }
+    generated.push_str("];\n");
+    std::fs::write(out_dir.join("templates.rs"), generated)
+        .expect("failed to write embedded templates");
+    // The crate version alone doesn't change when the templates do.
+    println!(
+        "cargo:rustc-env=CARGO_MOBILE_TEMPLATES_VERSION={}+{:08x}",
+        std::env::var("CARGO_PKG_VERSION").unwrap(),
+        hash.0 >> 32
+    );
+}
+
+// Relative paths always use `/`, since they end up in the generated code.
+fn collect_files(dir: &Path, rel: &Path, files: &mut Vec<(String, PathBuf, bool)>) {
+    println!("cargo:rerun-if-changed={}", dir.display());
+    for entry in std::fs::read_dir(dir).expect("failed to read src templates dir") {
+        let entry = entry.expect("failed to read entry in src templates dir");
+        let path = entry.path();
+        let rel = rel.join(entry.file_name());
+        if path.is_dir() {
+            collect_files(&path, &rel, files);
+        } else {
+            let rel = rel
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((rel, path.clone(), executable(&path)));
+        }
+    }
+}
+
+#[cfg(unix)]
+fn executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt as _;
+    std::fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+// Nothing's executable on Windows, which is also the only place it wouldn't
+// matter.
+#[cfg(not(unix))]
+fn executable(_path: &Path) -> bool {
+    false
+}
+
+// FNV-1a, which is stable across Rust versions, unlike `DefaultHasher`.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
         }
-        // bicycle creates directories for us, so we don't need to worry about
-        // using `create_dir_all` or anything.
-        bike.process_actions(
-            actions.iter().inspect(|action| match action {
-                bicycle::Action::CreateDirectory { dest: in_dest } => {
-                    // This is sorta gross, but not really *that* gross, so...
-                    let src = src.join(in_dest.strip_prefix(&dest).unwrap());
-                    println!("cargo:rerun-if-changed={}", src.display());
-                }
-                bicycle::Action::CopyFile { src, .. } => {
-                    println!("cargo:rerun-if-changed={}", src.display());
-                }
-                _ => (),
-            }),
-            |_| (),
-        )
-        .expect("failed to process actions");
     }
 }
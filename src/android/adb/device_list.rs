@@ -1,17 +1,20 @@
 use super::{device_name, get_prop};
 use crate::{
     android::{device::Device, env::Env, target::Target},
-    env::ExplicitEnv as _,
+    device::{self, Blocked, ChooseError, Found, Kind, Listing, State},
+    opts::NonInteractive,
+    ui,
     util::cli::{Report, Reportable},
 };
 use once_cell_regex::regex_multi_line;
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, path::Path};
 
 #[derive(Debug)]
 pub enum Error {
     DevicesFailed(super::RunCheckedError),
     NameFailed(device_name::Error),
     ModelFailed(get_prop::Error),
+    VersionFailed(get_prop::Error),
     AbiFailed(get_prop::Error),
     AbiInvalid(String),
 }
@@ -22,36 +25,377 @@ impl Reportable for Error {
         match self {
             Self::DevicesFailed(err) => err.report("Failed to run `adb devices`"),
             Self::NameFailed(err) => err.report(),
-            Self::ModelFailed(err) | Self::AbiFailed(err) => err.report(),
+            Self::ModelFailed(err) | Self::VersionFailed(err) | Self::AbiFailed(err) => {
+                err.report()
+            }
             Self::AbiInvalid(abi) => {
                 Report::error(msg, format!("{:?} isn't a valid target ABI.", abi))
             }
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DevicesFailed(..) => "android.adb.device-list.devices-failed",
+            Self::NameFailed(..) => "android.adb.device-list.name-failed",
+            Self::ModelFailed(..) => "android.adb.device-list.model-failed",
+            Self::VersionFailed(..) => "android.adb.device-list.version-failed",
+            Self::AbiFailed(..) => "android.adb.device-list.abi-failed",
+            Self::AbiInvalid(..) => "android.adb.device-list.abi-invalid",
+        }
+    }
+}
+
+// Serial numbers paired with what `adb` says about each device, i.e.
+// `device`, `unauthorized`, or `offline`.
+fn parse_entries(raw_list: &str) -> Vec<(String, String)> {
+    regex_multi_line!(r"^(\S+)\t(\S+)")
+        .captures_iter(raw_list)
+        .map(|caps| {
+            assert_eq!(caps.len(), 3);
+            (caps[1].to_owned(), caps[2].to_owned())
+        })
+        .collect()
+}
+
+fn entries(env: &Env) -> Result<Vec<(String, String)>, Error> {
+    super::query(env, &["devices"], parse_entries).map_err(Error::DevicesFailed)
+}
+
+// `emulator -list-avds` mixes in the odd log line, but AVD names can't
+// contain spaces, so those are easy to tell apart.
+fn parse_avds(raw_list: &str) -> Vec<String> {
+    raw_list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.contains(char::is_whitespace))
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// The names of the AVDs the SDK's emulator knows about, running or not. An
+/// SDK without the emulator installed simply has none.
+pub fn avds(sdk_root: &Path) -> Vec<String> {
+    bossy::Command::impure(sdk_root.join("emulator/emulator"))
+        .with_arg("-list-avds")
+        .run_and_wait_for_str(parse_avds)
+        .unwrap_or_else(|err| {
+            log::info!("failed to list AVDs: {}", err);
+            Vec::new()
+        })
+}
+
+// The emulator console answers with the name and then `OK`.
+fn parse_avd_name(raw: &str) -> Option<String> {
+    raw.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && *line != "OK")
+        .map(ToOwned::to_owned)
 }
 
+// Which AVD a running emulator was started from.
+fn avd_name(env: &Env, serial_no: &str) -> Option<String> {
+    match super::query(
+        env,
+        &["-s", serial_no, "emu", "avd", "name"],
+        parse_avd_name,
+    ) {
+        Ok(name) => name,
+        Err(err) => {
+            log::info!("failed to get the AVD name of {:?}: {:?}", serial_no, err);
+            None
+        }
+    }
+}
+
+// AVDs that aren't among the running emulators, as shut down virtual devices.
+fn shutdown_avds(avds: Vec<String>, running: &BTreeSet<String>) -> Vec<Listing> {
+    avds.into_iter()
+        .filter(|avd| !running.contains(avd))
+        .map(|avd| Listing {
+            name: avd.clone(),
+            id: avd,
+            platform: "android",
+            os_version: None,
+            arch: String::new(),
+            kind: Kind::Virtual,
+            transport: None,
+            state: State::Shutdown,
+        })
+        .collect()
+}
+
+fn device(env: &Env, serial_no: String) -> Result<Device<'static>, Error> {
+    let name = device_name(env, &serial_no).map_err(Error::NameFailed)?;
+    let model = get_prop(env, &serial_no, "ro.product.model").map_err(Error::ModelFailed)?;
+    let os_version =
+        get_prop(env, &serial_no, "ro.build.version.release").map_err(Error::VersionFailed)?;
+    let abi = get_prop(env, &serial_no, "ro.product.cpu.abi").map_err(Error::AbiFailed)?;
+    let target = Target::for_abi(&abi).ok_or_else(|| Error::AbiInvalid(abi.clone()))?;
+    // This lists every ABI the device can run, in order of preference, but
+    // isn't present before Android 5.0.
+    let abis = get_prop(env, &serial_no, "ro.product.cpu.abilist")
+        .ok()
+        .map(|abis| {
+            abis.split(',')
+                .map(|abi| abi.trim().to_owned())
+                .filter(|abi| !abi.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|abis| !abis.is_empty())
+        .unwrap_or_else(|| vec![abi]);
+    Ok(Device::new(
+        serial_no, name, model, os_version, target, abis,
+    ))
+}
+
+/// Lists the devices that are ready to use. The ones that aren't can be
+/// found with [`blocked`].
 pub fn device_list(env: &Env) -> Result<BTreeSet<Device<'static>>, Error> {
-    super::check_authorized(
-        bossy::Command::pure("adb")
-            .with_env_vars(env.explicit_env())
-            .with_args(&["devices"])
-            .run_and_wait_for_str(|raw_list| {
-                regex_multi_line!(r"^([\w\d]{6,20})	\b")
-                    .captures_iter(raw_list)
-                    .map(|caps| {
-                        assert_eq!(caps.len(), 2);
-                        let serial_no = caps.get(1).unwrap().as_str().to_owned();
-                        let name = device_name(env, &serial_no).map_err(Error::NameFailed)?;
-                        let model = get_prop(env, &serial_no, "ro.product.model")
-                            .map_err(Error::ModelFailed)?;
-                        let abi = get_prop(env, &serial_no, "ro.product.cpu.abi")
-                            .map_err(Error::AbiFailed)?;
-                        let target =
-                            Target::for_abi(&abi).ok_or_else(|| Error::AbiInvalid(abi.clone()))?;
-                        Ok(Device::new(serial_no, name, model, target))
-                    })
-                    .collect()
-            }),
-    )
-    .map_err(Error::DevicesFailed)?
+    entries(env)?
+        .into_iter()
+        .filter(|(_, state)| state == "device")
+        .map(|(serial_no, _)| device(env, serial_no))
+        .collect()
+}
+
+// What's wrong with a device in each state `adb` uses for devices that are
+// connected but can't be used, and what to do about it.
+fn problem(state: &str) -> Option<(&'static str, &'static str)> {
+    match state {
+        "unauthorized" => Some((
+            "hasn't allowed USB debugging from this computer",
+            "Unlock it and tap \"Allow\" when asked \"Allow USB debugging?\". If you don't see that, unplug it and plug it back in.",
+        )),
+        "authorizing" | "connecting" => Some((
+            "is still connecting",
+            "This usually only takes a moment.",
+        )),
+        "offline" => Some((
+            "isn't responding",
+            "Unplug it and plug it back in. If that doesn't help, run `adb kill-server` or restart it.",
+        )),
+        _ => None,
+    }
+}
+
+// Any ready device will do when none was asked for, so the blocked ones are
+// only worth waiting for if there aren't any, or if one was asked for by
+// serial number. (Blocked devices can't tell us their names.)
+fn blocked_among(entries: Vec<(String, String)>, query: Option<&str>) -> Vec<Blocked> {
+    let ready = entries.iter().any(|(_, state)| state == "device");
+    entries
+        .into_iter()
+        .filter(|(serial_no, _)| !ready || Some(serial_no.as_str()) == query)
+        .filter_map(|(serial_no, state)| {
+            problem(&state).map(|(problem, guidance)| Blocked {
+                name: serial_no,
+                problem,
+                guidance,
+            })
+        })
+        .collect()
+}
+
+/// Devices that are connected but can't be used yet, which are the ones
+/// worth waiting for before selecting a device with `query`.
+pub fn blocked(env: &Env, query: Option<&str>) -> Result<Vec<Blocked>, Error> {
+    entries(env).map(|entries| blocked_among(entries, query))
+}
+
+/// Lists every device `adb` knows about, including ones that can't be run on
+/// yet, which are described as best we can without asking them anything,
+/// followed by the AVDs that aren't running.
+pub fn listings(env: &Env) -> Result<Vec<Listing>, Error> {
+    let entries = entries(env)?;
+    let running = entries
+        .iter()
+        .filter(|(serial_no, _)| Device::kind_for_serial_no(serial_no) == Kind::Virtual)
+        .filter_map(|(serial_no, _)| avd_name(env, serial_no))
+        .collect::<BTreeSet<_>>();
+    let mut listings = entries
+        .into_iter()
+        .map(|(serial_no, state)| {
+            if state == "device" {
+                device(env, serial_no).map(|device| device.listing())
+            } else {
+                Ok(Listing {
+                    name: serial_no.clone(),
+                    kind: Device::kind_for_serial_no(&serial_no),
+                    transport: Device::transport_for_serial_no(&serial_no),
+                    id: serial_no,
+                    platform: "android",
+                    os_version: None,
+                    arch: String::new(),
+                    state: if state == "unauthorized" {
+                        State::Unauthorized
+                    } else {
+                        State::Offline
+                    },
+                })
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    listings.extend(shutdown_avds(avds(env.sdk_root()), &running));
+    Ok(listings)
+}
+
+#[derive(Debug)]
+pub enum SelectError {
+    ListFailed(Error),
+    NotFound {
+        query: String,
+        available: Vec<String>,
+    },
+    ChooseFailed(ChooseError),
+}
+
+impl Reportable for SelectError {
+    fn report(&self) -> Report {
+        match self {
+            Self::ListFailed(err) => err.report(),
+            Self::NotFound { query, available } => Report::error(
+                "Failed to select Android device",
+                if available.is_empty() {
+                    format!(
+                        "No device has the name or serial number {:?}, and no devices are connected",
+                        query
+                    )
+                } else {
+                    format!(
+                        "No device has the name or serial number {:?}; connected devices are:\n{}",
+                        query,
+                        available.join("\n")
+                    )
+                },
+            ),
+            Self::ChooseFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ListFailed(err) => err.code(),
+            Self::NotFound { .. } => "android.adb.device-list.select.not-found",
+            Self::ChooseFailed(err) => err.code(),
+        }
+    }
+}
+
+/// Picks a connected device by serial number (or the start of one) or name.
+pub fn select(
+    env: &Env,
+    query: &str,
+    non_interactive: NonInteractive,
+) -> Result<Device<'static>, SelectError> {
+    let devices = device_list(env).map_err(SelectError::ListFailed)?;
+    let available = devices
+        .iter()
+        .map(|device| format!("{} [{}]", device, device.serial_no()))
+        .collect();
+    let device = match device::find(devices, query, Device::serial_no, Device::name) {
+        Found::One(device) => device,
+        Found::Several(matches) => {
+            device::choose(matches, query, non_interactive, Device::serial_no)
+                .map_err(SelectError::ChooseFailed)?
+        }
+        Found::Nothing => {
+            return Err(SelectError::NotFound {
+                query: query.to_owned(),
+                available,
+            })
+        }
+    };
+    ui::status(format!("Using device: {}", device));
+    Ok(device)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        states,
+        query,
+        expected,
+        case(&[("a", "unauthorized")], None, &["a"]),
+        case(&[("a", "unauthorized"), ("b", "device")], None, &[]),
+        case(&[("a", "unauthorized"), ("b", "device")], Some("a"), &["a"]),
+        case(&[("a", "unauthorized"), ("b", "device")], Some("b"), &[]),
+        case(&[("a", "offline"), ("b", "recovery")], None, &["a"])
+    )]
+    fn test_blocked_among(states: &[(&str, &str)], query: Option<&str>, expected: &[&str]) {
+        let entries = states
+            .iter()
+            .map(|(serial_no, state)| (serial_no.to_string(), state.to_string()))
+            .collect();
+        let blocked = blocked_among(entries, query)
+            .into_iter()
+            .map(|blocked| blocked.name)
+            .collect::<Vec<_>>();
+        assert_eq!(blocked, expected);
+    }
+
+    #[rstest(
+        raw_list,
+        expected,
+        case(
+            include_str!("transcripts/devices.txt"),
+            &[
+                ("0A281FDD4000B5", "device"),
+                ("emulator-5554", "device"),
+                ("192.168.1.20:5555", "unauthorized"),
+                ("adb-R5CT1234-AbCdEf._adb-tls-connect._tcp", "offline"),
+            ]
+        ),
+        case(include_str!("transcripts/devices-empty.txt"), &[])
+    )]
+    fn test_parse_entries(raw_list: &str, expected: &[(&str, &str)]) {
+        let entries = parse_entries(raw_list);
+        let entries = entries
+            .iter()
+            .map(|(serial_no, state)| (serial_no.as_str(), state.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn test_parse_avds() {
+        assert_eq!(
+            parse_avds(include_str!("transcripts/list-avds.txt")),
+            vec![
+                "Pixel_6_API_34",
+                "Pixel_Tablet_API_33",
+                "wear.os_round-API_30"
+            ]
+        );
+    }
+
+    #[rstest(
+        raw,
+        expected,
+        case(include_str!("transcripts/emu-avd-name.txt"), Some("Pixel_6_API_34")),
+        case("OK\r\n", None)
+    )]
+    fn test_parse_avd_name(raw: &str, expected: Option<&str>) {
+        assert_eq!(parse_avd_name(raw).as_deref(), expected);
+    }
+
+    #[test]
+    fn test_shutdown_avds() {
+        let avds = parse_avds(include_str!("transcripts/list-avds.txt"));
+        let running = parse_avd_name(include_str!("transcripts/emu-avd-name.txt"))
+            .into_iter()
+            .collect();
+        let listings = shutdown_avds(avds, &running);
+        let names = listings
+            .iter()
+            .map(|listing| listing.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Pixel_Tablet_API_33", "wear.os_round-API_30"]);
+        assert!(listings
+            .iter()
+            .all(|listing| listing.kind == Kind::Virtual && listing.state == State::Shutdown));
+    }
 }
@@ -1,4 +1,3 @@
-use super::adb;
 use crate::{
     android::env::Env,
     util::cli::{Report, Reportable},
@@ -21,18 +20,25 @@ impl Reportable for Error {
             Self::NotMatched => Report::error(msg, "Name regex didn't match anything"),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DumpsysFailed(..) => "android.adb.device-name.dumpsys-failed",
+            Self::NotMatched => "android.adb.device-name.not-matched",
+        }
+    }
 }
 
 pub fn device_name(env: &Env, serial_no: &str) -> Result<String, Error> {
-    super::check_authorized(
-        adb(env, serial_no)
-            .with_args(&["shell", "dumpsys", "bluetooth_manager"])
-            .run_and_wait_for_str(|raw| {
-                regex!(r"\bname: (?P<name>.*)")
-                    .captures(raw)
-                    .map(|caps| caps["name"].to_owned())
-                    .ok_or_else(|| Error::NotMatched)
-            }),
+    super::query(
+        env,
+        &["-s", serial_no, "shell", "dumpsys", "bluetooth_manager"],
+        |raw| {
+            regex!(r"\bname: (?P<name>.*)")
+                .captures(raw)
+                .map(|caps| caps["name"].to_owned())
+                .ok_or_else(|| Error::NotMatched)
+        },
     )
     .map_err(Error::DumpsysFailed)?
 }
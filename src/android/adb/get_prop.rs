@@ -1,4 +1,3 @@
-use super::adb;
 use crate::{
     android::env::Env,
     util::cli::{Report, Reportable},
@@ -28,14 +27,18 @@ impl Reportable for Error {
             Self::LookupFailed { cause, .. } => cause.report(&msg),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::LookupFailed { .. } => "android.adb.get-prop.lookup-failed",
+        }
+    }
 }
 
 pub fn get_prop(env: &Env, serial_no: &str, prop: &str) -> Result<String, Error> {
-    super::check_authorized(
-        adb(env, serial_no)
-            .with_args(&["shell", "getprop", prop])
-            .run_and_wait_for_str(|s| s.trim().to_owned()),
-    )
+    super::query(env, &["-s", serial_no, "shell", "getprop", prop], |s| {
+        s.trim().to_owned()
+    })
     .map_err(|cause| Error::LookupFailed {
         prop: prop.to_owned(),
         cause,
@@ -5,8 +5,18 @@ pub mod get_prop;
 pub use self::{device_list::device_list, device_name::device_name, get_prop::get_prop};
 
 use super::env::Env;
-use crate::{env::ExplicitEnv as _, util::cli::Report};
-use std::str;
+use crate::{env::ExplicitEnv as _, ui, util::cli::Report};
+use std::{
+    ffi::OsString,
+    str,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+// Queries like `adb devices` and `adb shell getprop` finish in well under a
+// second, unless the adb server's wedged, in which case they never do.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(20);
 
 pub fn adb(env: &Env, serial_no: &str) -> bossy::Command {
     bossy::Command::pure("adb")
@@ -14,11 +24,22 @@ pub fn adb(env: &Env, serial_no: &str) -> bossy::Command {
         .with_args(&["-s", serial_no])
 }
 
+/// `adb` for `cargo android adb`, which unlike ours keeps the user's env, since
+/// `ANDROID_ADB_SERVER_PORT` and the like should apply just as they would if
+/// they'd run it themselves.
+pub fn passthrough(env: &Env, serial_no: &str, args: &[String]) -> bossy::Command {
+    bossy::Command::impure("adb")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["-s", serial_no])
+        .with_args(args)
+}
+
 #[derive(Debug)]
 pub enum RunCheckedError {
     InvalidUtf8(bossy::Error),
     Unauthorized,
     CommandFailed(bossy::Error),
+    TimedOut { restarted: bool },
 }
 
 impl RunCheckedError {
@@ -27,10 +48,87 @@ impl RunCheckedError {
             Self::InvalidUtf8(err) => Report::error(msg, err),
             Self::Unauthorized => Report::action_request(msg, "This device doesn't yet trust this computer. On the device, you should see a prompt like \"Allow USB debugging?\". Pressing \"Allow\" should fix this."),
             Self::CommandFailed(err) => Report::error(msg, err),
+            Self::TimedOut { restarted: true } => Report::action_request(msg, format!("`adb` didn't respond within {} seconds, even after its server was restarted. Try unplugging your devices, or restarting them.", QUERY_TIMEOUT.as_secs())),
+            Self::TimedOut { restarted: false } => Report::action_request(msg, format!("`adb` didn't respond within {} seconds. Its server might be stuck; run `adb kill-server` and try again.", QUERY_TIMEOUT.as_secs())),
         }
     }
 }
 
+fn restart_server(env: &Env) -> bossy::Result<()> {
+    for arg in &["kill-server", "start-server"] {
+        bossy::Command::pure("adb")
+            .with_env_vars(env.explicit_env())
+            .with_arg(arg)
+            .run_and_wait()?;
+    }
+    Ok(())
+}
+
+// Runs on its own thread, so that we can stop waiting if it hangs. `None`
+// means it didn't finish in time, in which case it's left to finish (or not)
+// on its own.
+fn run_with_timeout<T: Send + 'static>(
+    env_vars: &[(String, OsString)],
+    args: &[String],
+    parse: fn(&str) -> T,
+) -> Option<bossy::Result<T>> {
+    let command = bossy::Command::pure("adb")
+        .with_env_vars(env_vars.to_vec())
+        .with_args(args);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // Nobody's listening anymore if we gave up on it.
+        let _ = tx.send(command.run_and_wait_for_str(parse));
+    });
+    match rx.recv_timeout(QUERY_TIMEOUT) {
+        Err(RecvTimeoutError::Timeout) => None,
+        result => Some(result.expect("developer error: `adb` thread exited without a result")),
+    }
+}
+
+/// Runs a quick `adb` query and parses its output. A wedged adb server makes
+/// every query hang, so if it doesn't finish in time, the server's restarted
+/// and the query's tried once more (unless `--no-daemon-management` was
+/// passed, in which case we keep waiting).
+fn query<T: Send + 'static>(
+    env: &Env,
+    args: &[&str],
+    parse: fn(&str) -> T,
+) -> Result<T, RunCheckedError> {
+    if !env.manages_daemons() {
+        return check_authorized(
+            bossy::Command::pure("adb")
+                .with_env_vars(env.explicit_env())
+                .with_args(args)
+                .run_and_wait_for_str(parse),
+        );
+    }
+    let env_vars = env
+        .explicit_env()
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect::<Vec<_>>();
+    let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    if let Some(result) = run_with_timeout(&env_vars, &args, parse) {
+        return check_authorized(result);
+    }
+    log::warn!(
+        "`adb {}` timed out; restarting the adb server",
+        args.join(" ")
+    );
+    if let Err(err) = restart_server(env) {
+        log::warn!("failed to restart the adb server: {}", err);
+        return Err(RunCheckedError::TimedOut { restarted: false });
+    }
+    ui::status(format!(
+        "`adb` didn't respond within {} seconds, so its server was restarted",
+        QUERY_TIMEOUT.as_secs()
+    ));
+    run_with_timeout(&env_vars, &args, parse)
+        .map(check_authorized)
+        .unwrap_or(Err(RunCheckedError::TimedOut { restarted: true }))
+}
+
 fn check_authorized<T>(result: bossy::Result<T>) -> Result<T, RunCheckedError> {
     if let Err(err) = &result {
         if let Some(stderr) = err
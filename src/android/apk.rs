@@ -0,0 +1,970 @@
+use super::{
+    config::{Config, Library, Metadata},
+    env::Env,
+    jdk,
+    jnilibs::{self, JniLibs},
+    project,
+    target::{BuildError, HookError, Target},
+};
+use crate::{
+    config::build_config::{self, Resolved},
+    env::ExplicitEnv as _,
+    fingerprint::{Fingerprint, Store},
+    generated,
+    hooks::Stage,
+    opts::{Force, ForceColor, NoHooks, NoiseLevel, Profile, SkipStrip},
+    resources, ui,
+    util::{
+        cli::{Report, Reportable},
+        interrupt,
+        progress::{self, Phase},
+        proxy,
+        watchdog::{self, Limits},
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet},
+    ffi::OsString,
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+static DAEMON_STEP: &str = "gradle-daemon";
+
+pub fn gradlew(config: &Config, env: &Env) -> bossy::Command {
+    let project_dir = config.project_dir();
+    let gradlew_path = project_dir.join("gradlew");
+    let java_home = jdk::launcher_home(&project_dir);
+    let mut env_vars = env.explicit_env();
+    if let Some(java_home) = &java_home {
+        env_vars.push(("JAVA_HOME", java_home.as_ref()));
+    }
+    bossy::Command::pure(&gradlew_path)
+        .with_env_vars(env_vars)
+        // Gradle doesn't read the proxy env vars.
+        .with_args(proxy::get().jvm_args())
+        .with_arg("--project-dir")
+        .with_arg(project_dir)
+}
+
+#[derive(Debug)]
+pub enum ApkBuildError {
+    LibSymlinkCleaningFailed(jnilibs::RemoveBrokenLinksError),
+    LibBuildFailed(BuildError),
+    AssembleFailed(bossy::Error),
+    BundleFailed(bossy::Error),
+    OutputsFailed(OutputsError),
+    HookFailed(HookError),
+    BuildConfigFailed(build_config::ResolveError),
+    ConnectedTestFailed(bossy::Error),
+    Stalled(watchdog::Stalled),
+}
+
+impl Reportable for ApkBuildError {
+    fn report(&self) -> Report {
+        match self {
+            Self::LibSymlinkCleaningFailed(err) => err.report(),
+            Self::LibBuildFailed(err) => err.report(),
+            Self::AssembleFailed(err) => Report::error("Failed to assemble APK", err),
+            Self::BundleFailed(err) => Report::error("Failed to build app bundle", err),
+            Self::OutputsFailed(err) => err.report(),
+            Self::HookFailed(err) => err.report(),
+            Self::BuildConfigFailed(err) => err.report(),
+            Self::ConnectedTestFailed(err) => {
+                Report::error("Failed to run instrumented tests", err)
+            }
+            Self::Stalled(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::LibSymlinkCleaningFailed(err) => err.code(),
+            Self::LibBuildFailed(err) => err.code(),
+            Self::AssembleFailed(..) => "android.apk.apk-build.assemble-failed",
+            Self::BundleFailed(..) => "android.apk.apk-build.bundle-failed",
+            Self::OutputsFailed(err) => err.code(),
+            Self::HookFailed(err) => err.code(),
+            Self::BuildConfigFailed(err) => err.code(),
+            Self::ConnectedTestFailed(..) => "android.apk.apk-build.connected-test-failed",
+            Self::Stalled(err) => err.code(),
+        }
+    }
+}
+
+/// What Gradle packages the app as: an APK to install, or an app bundle to
+/// upload to Google Play.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Apk,
+    Aab,
+}
+
+impl Format {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Apk => "apk",
+            Self::Aab => "aab",
+        }
+    }
+
+    pub fn noun(self) -> &'static str {
+        match self {
+            Self::Apk => "APK",
+            Self::Aab => "app bundle",
+        }
+    }
+
+    // Where under `build/outputs` Gradle puts it.
+    fn dir(self) -> &'static str {
+        match self {
+            Self::Apk => "apk",
+            Self::Aab => "bundle",
+        }
+    }
+
+    // The Gradle task that packages a variant, minus the variant.
+    fn task(self) -> &'static str {
+        match self {
+            Self::Apk => "assemble",
+            Self::Aab => "bundle",
+        }
+    }
+
+    fn failed(self) -> fn(bossy::Error) -> ApkBuildError {
+        match self {
+            Self::Apk => ApkBuildError::AssembleFailed,
+            Self::Aab => ApkBuildError::BundleFailed,
+        }
+    }
+}
+
+fn assemble(
+    config: &Config,
+    env: &Env,
+    task: String,
+    noise_level: NoiseLevel,
+    skip_strip: SkipStrip,
+    no_hooks: NoHooks,
+    build_config: &Resolved,
+) -> bossy::Command {
+    let features = config.cli_features();
+    let plan = resources::current();
+    gradlew(config, env)
+        // So that the config's env reaches Gradle's own build steps too
+        .with_env_vars(config.env())
+        // And `BuildTask` runs cargo with as many jobs as Gradle has workers
+        .with_env_vars(plan.env_vars())
+        .with_args(plan.jobs().map(|jobs| format!("--max-workers={}", jobs)))
+        // The app's `build.gradle.kts` turns these into `BuildConfig` fields
+        .with_env_vars(build_config.env_vars())
+        .with_arg(task)
+        .with_arg(match noise_level {
+            NoiseLevel::Polite => "--warn",
+            NoiseLevel::LoudAndProud => "--info",
+            NoiseLevel::FranklyQuitePedantic => "--debug",
+        })
+        // Read by `BuildTask`, which passes `--no-strip` along to us
+        .with_args(if skip_strip.yes() {
+            Some("-PnoStrip")
+        } else {
+            None
+        })
+        // Likewise for `--no-hooks`
+        .with_args(if no_hooks.yes() {
+            Some("-PnoHooks")
+        } else {
+            None
+        })
+        // And `--features`
+        .with_args(if !features.is_empty() {
+            Some(format!("-Pfeatures={}", features.join(",")))
+        } else {
+            None
+        })
+        .with_args(variant_args(config))
+}
+
+// The app's `build.gradle.kts` reads the variant's application ID suffix and
+// label and icon, which host projects don't know about, but their `BuildTask` still
+// needs to pass `--variant` back to us.
+fn variant_args(config: &Config) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(variant) = config.variant() {
+        args.push(format!("-Pvariant={}", variant.name()));
+        if config.library().is_none() {
+            args.push(format!(
+                "-PapplicationIdSuffix={}",
+                variant.identifier_suffix()
+            ));
+            if let Some(display_name) = variant.display_name() {
+                args.push(format!("-PappLabel={}", display_name));
+            }
+            if variant.icon().is_some() {
+                args.push(format!(
+                    "-PappIcon=@mipmap/{}",
+                    project::variant_icon(variant)
+                ));
+            }
+        }
+    }
+    args
+}
+
+fn digest(value: impl Hash) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+// What Gradle daemons are started with, which they keep for as long as
+// they're around. Values are hashed, since the config's env can have secrets.
+fn daemon_fingerprint(config: &Config, env: &Env) -> Fingerprint {
+    let java_home = jdk::launcher_home(&config.project_dir());
+    let vars = env
+        .explicit_env()
+        .into_iter()
+        .chain(
+            java_home
+                .as_deref()
+                .map(|java_home| ("JAVA_HOME", java_home)),
+        )
+        .map(|(key, value)| (key, value.to_owned()))
+        .chain(
+            config
+                .env()
+                .iter()
+                .map(|(key, value)| (key.as_str(), OsString::from(value))),
+        );
+    vars.fold(
+        Fingerprint::new().with_value("cargo-mobile", env!("CARGO_PKG_VERSION")),
+        |fingerprint, (key, value)| fingerprint.with_value(key, digest(value)),
+    )
+}
+
+// A daemon left over from before an SDK update or a cargo-mobile upgrade
+// still has the old env, and fails in ways a fresh one wouldn't, so they're
+// all stopped whenever what we'd start one with has changed.
+fn refresh_daemons(config: &Config, env: &Env) {
+    if !env.manages_daemons() {
+        return;
+    }
+    let store = Store::new(generated::gen_dir(config.app()).join("android"));
+    let fingerprint = daemon_fingerprint(config, env);
+    if store.fresh(DAEMON_STEP, &fingerprint) {
+        return;
+    }
+    match gradlew(config, env).with_arg("--stop").run_and_wait() {
+        Ok(_) => {
+            ui::status("Stopped Gradle's daemons, since what they'd be started with has changed");
+            store.record(DAEMON_STEP, &fingerprint);
+        }
+        Err(err) => log::warn!("failed to stop stale Gradle daemons: {}", err),
+    }
+}
+
+// Gradle keeps a daemon around for the build, which stays busy for a while if
+// the client is interrupted, so it's stopped along with us.
+fn stop_daemon_on_interrupt(config: &Config, env: &Env) -> interrupt::Cleanup {
+    let stop = gradlew(config, env).with_arg("--stop");
+    interrupt::on_interrupt(move || {
+        if let Err(err) = stop.run_and_wait() {
+            log::warn!("failed to stop Gradle daemon: {}", err);
+        }
+    })
+}
+
+// Everything Gradle packages into the APK or bundle for our generated project,
+// along with the output itself in case it's been deleted.
+fn fingerprint(
+    config: &Config,
+    target: &Target<'_>,
+    format: Format,
+    profile: Profile,
+    skip_strip: SkipStrip,
+    build_config: &Resolved,
+) -> Fingerprint {
+    let project_dir = config.project_dir();
+    Fingerprint::new()
+        .with_value("profile", profile.as_str())
+        .with_value("skip-strip", skip_strip.yes())
+        .with_value("build-config", build_config.digest())
+        .with_value(
+            "variant",
+            config
+                .variant()
+                .map(|variant| variant.name())
+                .unwrap_or_default(),
+        )
+        .with_dir(config.module_dir().join("src"))
+        .with_file(config.module_dir().join("build.gradle.kts"))
+        .with_file(project_dir.join("build.gradle.kts"))
+        .with_file(project_dir.join("settings.gradle"))
+        .with_file(project_dir.join("gradle.properties"))
+        .with_dir(project_dir.join("buildSrc/src"))
+        .with_dir(output_dir(config, target, profile, format))
+}
+
+/// Builds the APK for a target. For generated projects, the Rust libs are
+/// built first, and Gradle is skipped entirely if neither they nor the rest
+/// of the project have changed since the last build (unless `force` is set).
+/// Host projects aren't ours to fingerprint, so Gradle always runs for them.
+pub fn build(
+    config: &Config,
+    metadata: &Metadata,
+    env: &Env,
+    target: &Target<'_>,
+    noise_level: NoiseLevel,
+    profile: Profile,
+    skip_strip: SkipStrip,
+    force: Force,
+    no_hooks: NoHooks,
+) -> Result<(), ApkBuildError> {
+    if config.library().is_none() {
+        target
+            .run_hooks(config, env, Stage::PreBuild, profile, no_hooks, None)
+            .map_err(ApkBuildError::HookFailed)?;
+        target
+            .build(
+                config,
+                metadata,
+                env,
+                noise_level,
+                ForceColor::Yes,
+                profile,
+                skip_strip,
+                force,
+            )
+            .map_err(ApkBuildError::LibBuildFailed)?;
+    }
+    package(
+        config,
+        env,
+        target,
+        Format::Apk,
+        noise_level,
+        profile,
+        skip_strip,
+        force,
+        no_hooks,
+    )
+}
+
+/// Packages a target's Rust libs, which generated projects need to have built
+/// already, as `format`. Host projects' Gradle builds the libs itself, by
+/// calling `cargo android build --libs-only` back.
+pub fn package(
+    config: &Config,
+    env: &Env,
+    target: &Target<'_>,
+    format: Format,
+    noise_level: NoiseLevel,
+    profile: Profile,
+    skip_strip: SkipStrip,
+    force: Force,
+    no_hooks: NoHooks,
+) -> Result<(), ApkBuildError> {
+    use heck::CamelCase as _;
+    let build_config = config
+        .build_config()
+        .resolve(profile)
+        .map_err(ApkBuildError::BuildConfigFailed)?;
+    if let Some(library) = config.library() {
+        JniLibs::remove_broken_links(config).map_err(ApkBuildError::LibSymlinkCleaningFailed)?;
+        // The host app doesn't have our ABI flavors, so its APK always
+        // contains every ABI we built.
+        let task = format!(
+            ":{}:{}{}",
+            library.app_module(),
+            format.task(),
+            profile.as_str().to_camel_case()
+        );
+        refresh_daemons(config, env);
+        let _daemon = stop_daemon_on_interrupt(config, env);
+        // Build hooks are run by the `cargo android build` that Gradle calls.
+        let command = assemble(
+            config,
+            env,
+            task,
+            noise_level,
+            skip_strip,
+            no_hooks,
+            &build_config,
+        );
+        return progress::phase(Phase::Gradle, None, || {
+            watchdog::run_and_wait(command, Limits::PACKAGE)
+        })
+        .map_err(|err| err.split(format.failed(), ApkBuildError::Stalled));
+    }
+    let store = Store::new(config.project_dir());
+    let step = format!("{}-{}", format.as_str(), target.arch);
+    let fingerprint = fingerprint(config, target, format, profile, skip_strip, &build_config);
+    if force.no() && store.fresh(&step, &fingerprint) {
+        ui::status(format!(
+            "The {} for {} is up to date, so Gradle was skipped",
+            format.noun(),
+            target.arch
+        ));
+    } else {
+        store.forget(&step);
+        repackage(
+            config,
+            env,
+            target,
+            format,
+            noise_level,
+            profile,
+            skip_strip,
+            &build_config,
+        )?;
+        store.record(&step, &fingerprint);
+    }
+    if config.hooks().unless(no_hooks).has(Stage::PostBuild) {
+        for path in
+            outputs(config, target, profile, format).map_err(ApkBuildError::OutputsFailed)?
+        {
+            target
+                .run_hooks(
+                    config,
+                    env,
+                    Stage::PostBuild,
+                    profile,
+                    no_hooks,
+                    Some(&path),
+                )
+                .map_err(ApkBuildError::HookFailed)?;
+        }
+    }
+    Ok(())
+}
+
+// Packages Rust libs that were already built, skipping the Rust build task so
+// that Gradle only has to merge `jniLibs` and repackage.
+fn repackage(
+    config: &Config,
+    env: &Env,
+    target: &Target<'_>,
+    format: Format,
+    noise_level: NoiseLevel,
+    profile: Profile,
+    skip_strip: SkipStrip,
+    build_config: &Resolved,
+) -> Result<(), ApkBuildError> {
+    use heck::CamelCase as _;
+    JniLibs::remove_broken_links(config).map_err(ApkBuildError::LibSymlinkCleaningFailed)?;
+    let variant = format!(
+        "{}{}",
+        target.arch.to_camel_case(),
+        profile.as_str().to_camel_case()
+    );
+    refresh_daemons(config, env);
+    let _daemon = stop_daemon_on_interrupt(config, env);
+    let command = assemble(
+        config,
+        env,
+        format!("{}{}", format.task(), variant),
+        noise_level,
+        skip_strip,
+        NoHooks::Yes,
+        build_config,
+    )
+    .with_args(&["-x", &format!("rustBuild{}", variant)]);
+    progress::phase(Phase::Gradle, None, || {
+        watchdog::run_and_wait(command, Limits::PACKAGE)
+    })
+    .map_err(|err| err.split(format.failed(), ApkBuildError::Stalled))?;
+    Ok(())
+}
+
+/// Builds the debug APK and its instrumented tests for `target`, and runs them
+/// on the device `serial_no` names. For generated projects the Rust libs are
+/// built first, like for [`build`]; host projects' `BuildTask` builds them.
+/// Gradle fails when tests do, so a [`ApkBuildError::ConnectedTestFailed`]
+/// doesn't mean the tests didn't run.
+pub fn connected_test(
+    config: &Config,
+    metadata: &Metadata,
+    env: &Env,
+    target: &Target<'_>,
+    noise_level: NoiseLevel,
+    serial_no: &str,
+) -> Result<(), ApkBuildError> {
+    use heck::CamelCase as _;
+    let profile = Profile::Debug;
+    let build_config = config
+        .build_config()
+        .resolve(profile)
+        .map_err(ApkBuildError::BuildConfigFailed)?;
+    JniLibs::remove_broken_links(config).map_err(ApkBuildError::LibSymlinkCleaningFailed)?;
+    let command = if let Some(library) = config.library() {
+        assemble(
+            config,
+            env,
+            format!(":{}:connectedDebugAndroidTest", library.app_module()),
+            noise_level,
+            SkipStrip::No,
+            NoHooks::Yes,
+            &build_config,
+        )
+    } else {
+        target
+            .build(
+                config,
+                metadata,
+                env,
+                noise_level,
+                ForceColor::Yes,
+                profile,
+                SkipStrip::No,
+                Force::No,
+            )
+            .map_err(ApkBuildError::LibBuildFailed)?;
+        let variant = format!(
+            "{}{}",
+            target.arch.to_camel_case(),
+            profile.as_str().to_camel_case()
+        );
+        assemble(
+            config,
+            env,
+            format!("connected{}AndroidTest", variant),
+            noise_level,
+            SkipStrip::No,
+            NoHooks::Yes,
+            &build_config,
+        )
+        .with_args(&["-x", &format!("rustBuild{}", variant)])
+    }
+    // Gradle runs the tests on every connected device unless told otherwise.
+    .with_env_var("ANDROID_SERIAL", serial_no);
+    refresh_daemons(config, env);
+    let _daemon = stop_daemon_on_interrupt(config, env);
+    progress::phase(Phase::Gradle, None, || {
+        watchdog::run_and_wait(command, Limits::PACKAGE)
+    })
+    .map_err(|err| err.split(ApkBuildError::ConnectedTestFailed, ApkBuildError::Stalled))?;
+    Ok(())
+}
+
+/// Builds only the generated module of a host project, which in turn runs
+/// `cargo android build --libs-only` for the specified targets.
+pub fn build_module(
+    config: &Config,
+    env: &Env,
+    library: &Library,
+    target_names: &[String],
+    noise_level: NoiseLevel,
+    profile: Profile,
+    skip_strip: SkipStrip,
+    no_hooks: NoHooks,
+) -> Result<(), ApkBuildError> {
+    use heck::CamelCase as _;
+    JniLibs::remove_broken_links(config).map_err(ApkBuildError::LibSymlinkCleaningFailed)?;
+    let task = format!(
+        ":{}:assemble{}",
+        library.module(),
+        profile.as_str().to_camel_case()
+    );
+    let build_config = config
+        .build_config()
+        .resolve(profile)
+        .map_err(ApkBuildError::BuildConfigFailed)?;
+    refresh_daemons(config, env);
+    let _daemon = stop_daemon_on_interrupt(config, env);
+    let command = assemble(
+        config,
+        env,
+        task,
+        noise_level,
+        skip_strip,
+        no_hooks,
+        &build_config,
+    )
+    .with_args(if !target_names.is_empty() {
+        Some(format!("-PrustTargets={}", target_names.join(",")))
+    } else {
+        None
+    });
+    progress::phase(Phase::Gradle, None, || {
+        watchdog::run_and_wait(command, Limits::PACKAGE)
+    })
+    .map_err(|err| err.split(ApkBuildError::AssembleFailed, ApkBuildError::Stalled))?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum OutputsError {
+    MetadataMissing {
+        dir: PathBuf,
+    },
+    ReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    ParseFailed {
+        path: PathBuf,
+        cause: serde_json::Error,
+    },
+    NoOutputs {
+        path: PathBuf,
+    },
+    NoBundle {
+        dir: PathBuf,
+    },
+}
+
+impl Reportable for OutputsError {
+    fn report(&self) -> Report {
+        let msg = "Failed to locate build output";
+        match self {
+            Self::MetadataMissing { dir } => Report::error(
+                msg,
+                format!(
+                    "Neither \"output-metadata.json\" nor \"output.json\" were present in {:?}",
+                    dir
+                ),
+            ),
+            Self::ReadFailed { path, cause } => {
+                Report::error(msg, format!("Failed to read {:?}: {}", path, cause))
+            }
+            Self::ParseFailed { path, cause } => {
+                Report::error(msg, format!("Failed to parse {:?}: {}", path, cause))
+            }
+            Self::NoOutputs { path } => {
+                Report::error(msg, format!("{:?} didn't list any outputs", path))
+            }
+            Self::NoBundle { dir } => {
+                Report::error(msg, format!("No app bundle was present in {:?}", dir))
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::MetadataMissing { .. } => "android.apk.outputs.metadata-missing",
+            Self::ReadFailed { .. } => "android.apk.outputs.read-failed",
+            Self::ParseFailed { .. } => "android.apk.outputs.parse-failed",
+            Self::NoOutputs { .. } => "android.apk.outputs.no-outputs",
+            Self::NoBundle { .. } => "android.apk.outputs.no-bundle",
+        }
+    }
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, OutputsError> {
+    let bytes = fs::read(path).map_err(|cause| OutputsError::ReadFailed {
+        path: path.to_owned(),
+        cause,
+    })?;
+    serde_json::from_slice(&bytes).map_err(|cause| OutputsError::ParseFailed {
+        path: path.to_owned(),
+        cause,
+    })
+}
+
+pub fn output_dir(
+    config: &Config,
+    target: &Target<'_>,
+    profile: Profile,
+    format: Format,
+) -> PathBuf {
+    use heck::CamelCase as _;
+    match (config.library(), format) {
+        (Some(library), _) => library.app_module_dir().join(format!(
+            "build/outputs/{}/{}",
+            format.dir(),
+            profile.as_str()
+        )),
+        (None, Format::Apk) => config.project_dir().join(format!(
+            "app/build/outputs/apk/{}/{}",
+            target.arch,
+            profile.as_str()
+        )),
+        // Bundles go in a dir named after the whole variant.
+        (None, Format::Aab) => config.project_dir().join(format!(
+            "app/build/outputs/bundle/{}{}",
+            target.arch,
+            profile.as_str().to_camel_case()
+        )),
+    }
+}
+
+/// Lists what Gradle produced for a target, according to Gradle itself.
+pub fn outputs(
+    config: &Config,
+    target: &Target<'_>,
+    profile: Profile,
+    format: Format,
+) -> Result<Vec<PathBuf>, OutputsError> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Element {
+        output_file: PathBuf,
+    }
+
+    #[derive(Deserialize)]
+    struct OutputMetadata {
+        elements: Vec<Element>,
+    }
+
+    #[derive(Deserialize)]
+    struct LegacyOutput {
+        path: PathBuf,
+    }
+
+    let dir = output_dir(config, target, profile, format);
+    if format == Format::Aab {
+        // Gradle doesn't write any metadata for bundles, but there's only ever
+        // the one in a variant's dir.
+        let bundles = fs::read_dir(&dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().map_or(false, |ext| ext == "aab"))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        log::info!("bundles found in {:?}: {:?}", dir, bundles);
+        return if bundles.is_empty() {
+            Err(OutputsError::NoBundle { dir })
+        } else {
+            Ok(bundles)
+        };
+    }
+    // AGP 4.1 and later write `output-metadata.json`, while older versions
+    // write `output.json` (which has an entirely different schema).
+    let modern = dir.join("output-metadata.json");
+    let legacy = dir.join("output.json");
+    let (path, files) = if modern.is_file() {
+        let metadata = read_json::<OutputMetadata>(&modern)?;
+        let files = metadata
+            .elements
+            .into_iter()
+            .map(|element| element.output_file)
+            .collect::<Vec<_>>();
+        (modern, files)
+    } else if legacy.is_file() {
+        let outputs = read_json::<Vec<LegacyOutput>>(&legacy)?;
+        let files = outputs
+            .into_iter()
+            .map(|output| output.path)
+            .collect::<Vec<_>>();
+        (legacy, files)
+    } else {
+        return Err(OutputsError::MetadataMissing { dir });
+    };
+    log::info!("outputs listed in {:?}: {:?}", path, files);
+    if files.is_empty() {
+        Err(OutputsError::NoOutputs { path })
+    } else {
+        Ok(files.into_iter().map(|file| dir.join(file)).collect())
+    }
+}
+
+#[derive(Debug)]
+pub enum AbisError {
+    ReadFailed { path: PathBuf, cause: io::Error },
+    NotAnApk { path: PathBuf },
+}
+
+impl Reportable for AbisError {
+    fn report(&self) -> Report {
+        match self {
+            Self::ReadFailed { path, cause } => {
+                Report::error(format!("Failed to read APK {:?}", path), cause)
+            }
+            Self::NotAnApk { path } => Report::error(
+                format!("Failed to read APK {:?}", path),
+                "It isn't a valid zip archive, so it can't be an APK",
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadFailed { .. } => "android.apk.abis.read-failed",
+            Self::NotAnApk { .. } => "android.apk.abis.not-an-apk",
+        }
+    }
+}
+
+/// The ABIs the APK at `path` has native libs for, which is empty if it
+/// doesn't have any (and can thus be installed anywhere).
+pub fn abis(path: &Path) -> Result<BTreeSet<String>, AbisError> {
+    let file = fs::File::open(path).map_err(|cause| AbisError::ReadFailed {
+        path: path.to_owned(),
+        cause,
+    })?;
+    let archive = zip::ZipArchive::new(file).map_err(|_| AbisError::NotAnApk {
+        path: path.to_owned(),
+    })?;
+    Ok(archive
+        .file_names()
+        .filter_map(|name| {
+            let mut components = name.split('/');
+            match (components.next(), components.next(), components.next()) {
+                (Some("lib"), Some(abi), Some(file)) if !file.is_empty() => Some(abi.to_owned()),
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Artifact {
+    pub format: Format,
+    /// This is `None` for artifacts containing every ABI.
+    pub abi: Option<String>,
+    pub profile: &'static str,
+    pub path: PathBuf,
+}
+
+impl Artifact {
+    pub fn new(
+        config: &Config,
+        target: &Target<'_>,
+        format: Format,
+        profile: Profile,
+        path: PathBuf,
+    ) -> Self {
+        Self {
+            format,
+            abi: if config.library().is_some() {
+                None
+            } else {
+                Some(target.abi.to_owned())
+            },
+            profile: profile.as_str(),
+            path,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CopyError {
+    Ambiguous {
+        out: PathBuf,
+        count: usize,
+    },
+    DirCreationFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    CopyFailed {
+        src: PathBuf,
+        dest: PathBuf,
+        cause: io::Error,
+    },
+}
+
+impl Reportable for CopyError {
+    fn report(&self) -> Report {
+        let msg = "Failed to copy build output";
+        match self {
+            Self::Ambiguous { out, count } => Report::action_request(
+                msg,
+                format!(
+                    "{} artifacts were built, but the destination {:?} isn't a directory; please specify a directory (i.e. with a trailing slash) instead.",
+                    count, out
+                ),
+            ),
+            Self::DirCreationFailed { path, cause } => Report::error(
+                msg,
+                format!("Failed to create directory {:?}: {}", path, cause),
+            ),
+            Self::CopyFailed { src, dest, cause } => Report::error(
+                msg,
+                format!("Failed to copy {:?} to {:?}: {}", src, dest, cause),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Ambiguous { .. } => "android.apk.copy.ambiguous",
+            Self::DirCreationFailed { .. } => "android.apk.copy.dir-creation-failed",
+            Self::CopyFailed { .. } => "android.apk.copy.copy-failed",
+        }
+    }
+}
+
+/// Copies artifacts to `out`, which is treated as a directory if it already is
+/// one or ends with a path separator, and as a file path otherwise. The
+/// artifact paths are updated to point to the copies.
+pub fn copy_artifacts(artifacts: &mut [Artifact], out: &Path) -> Result<(), CopyError> {
+    let out_is_dir = out.is_dir() || out.to_string_lossy().ends_with(std::path::MAIN_SEPARATOR);
+    if !out_is_dir && artifacts.len() > 1 {
+        return Err(CopyError::Ambiguous {
+            out: out.to_owned(),
+            count: artifacts.len(),
+        });
+    }
+    let dir = if out_is_dir {
+        Some(out)
+    } else {
+        out.parent().filter(|parent| !parent.as_os_str().is_empty())
+    };
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir).map_err(|cause| CopyError::DirCreationFailed {
+            path: dir.to_owned(),
+            cause,
+        })?;
+    }
+    for artifact in artifacts {
+        let dest = if out_is_dir {
+            out.join(
+                artifact
+                    .path
+                    .file_name()
+                    .expect("developer error: artifact had no file name"),
+            )
+        } else {
+            out.to_owned()
+        };
+        log::info!("copying {:?} to {:?}", artifact.path, dest);
+        fs::copy(&artifact.path, &dest).map_err(|cause| CopyError::CopyFailed {
+            src: artifact.path.clone(),
+            dest: dest.clone(),
+            cause,
+        })?;
+        artifact.path = dest;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+    use std::io::Write as _;
+
+    #[test]
+    fn test_abis() {
+        let dir = TempDir::new("apk-abis-test").unwrap();
+        let path = dir.path().join("app.apk");
+        let mut apk = zip::ZipWriter::new(fs::File::create(&path).unwrap());
+        for name in &[
+            "AndroidManifest.xml",
+            "lib/arm64-v8a/libapp.so",
+            "lib/x86_64/libapp.so",
+            "lib/x86_64/libc++_shared.so",
+        ] {
+            apk.start_file(*name, Default::default()).unwrap();
+            apk.write_all(b"contents").unwrap();
+        }
+        apk.finish().unwrap();
+        assert_eq!(
+            abis(&path).unwrap(),
+            vec!["arm64-v8a".to_owned(), "x86_64".to_owned()]
+                .into_iter()
+                .collect()
+        );
+        let not_an_apk = dir.path().join("not.apk");
+        fs::write(&not_an_apk, "not a zip").unwrap();
+        assert!(matches!(abis(&not_an_apk), Err(AbisError::NotAnApk { .. })));
+    }
+}
@@ -1,28 +1,43 @@
 use crate::{
     android::{
         adb,
+        apk::{self, ApkBuildError, Artifact},
         config::{Config, Metadata},
-        device::{Device, RunError, StacktraceError},
+        device::{ApkInstallError, Device, ForwardError, RunError, StacktraceError, TransferError},
         env::{Env, Error as EnvError},
-        target::{BuildError, CompileLibError, Target},
+        inspect, instrumented, jdk, ndk, parallel, project,
+        target::{BuildError, CompileLibError, HookError, Target},
         NAME,
     },
+    api::android::{self as api, ensure_init},
+    assets,
     config::{
-        metadata::{self, Metadata as OmniMetadata},
-        Config as OmniConfig, LoadOrGenError,
+        app::Platform, build_config, metadata, variant, CheckError, Config as OmniConfig,
+        LoadOrGenError,
     },
     define_device_prompt,
-    device::PromptError,
-    opts, os,
-    target::{call_for_targets_with_fallback, TargetInvalid, TargetTrait as _},
+    device::{self, LastDevice, PromptError},
+    dot_cargo,
+    env::Format,
+    features, opts, os,
+    plan::Writer,
+    preflight, regen, resources,
+    sizes::{self, Sizes},
+    target::{self, call_for_targets_with_fallback, get_targets, TargetInvalid, TargetTrait as _},
+    ui,
     util::{
+        self,
         cli::{
             self, Exec, GlobalFlags, Report, Reportable, TextWrapper, VERSION_LONG, VERSION_SHORT,
         },
-        prompt,
+        net, prompt, proxy, timings,
     },
+    watch::{self, Trigger},
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
 };
-use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -36,13 +51,27 @@ use structopt::StructOpt;
 pub struct Input {
     #[structopt(flatten)]
     flags: GlobalFlags,
+    #[structopt(flatten)]
+    package: cli::Package,
+    #[structopt(flatten)]
+    daemon_management: cli::NoDaemonManagement,
     #[structopt(subcommand)]
     command: Command,
 }
 
 impl Input {
+    /// Gradle daemons and the adb server are managed as usual, since
+    /// `--no-daemon-management` only comes with `cargo android`. The package
+    /// was already picked by `cargo mobile`, which runs this from its dir.
     pub fn new(flags: GlobalFlags, command: Command) -> Self {
-        Self { flags, command }
+        Self {
+            flags,
+            package: Default::default(),
+            daemon_management: cli::NoDaemonManagement {
+                no_daemon_management: opts::NoDaemonManagement::No,
+            },
+            command,
+        }
     }
 }
 
@@ -52,42 +81,400 @@ pub enum Command {
     Open,
     #[structopt(name = "check", about = "Checks if code compiles for target(s)")]
     Check {
-        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::name_list())]
+        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::possible_values(), hide_possible_values = true, help = "Targets to use, by name, alias, or triple; see `cargo android targets`")]
         targets: Vec<String>,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
     },
     #[structopt(name = "build", about = "Builds dynamic libraries for target(s)")]
     Build {
-        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::name_list())]
+        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::possible_values(), hide_possible_values = true, help = "Targets to use, by name, alias, or triple; see `cargo android targets`")]
+        targets: Vec<String>,
+        #[structopt(flatten)]
+        profile: cli::Profile,
+        #[structopt(flatten)]
+        skip_strip: cli::SkipStrip,
+        #[structopt(flatten)]
+        force: cli::Force,
+        #[structopt(flatten)]
+        no_hooks: cli::NoHooks,
+        #[structopt(flatten)]
+        timings: cli::Timings,
+        #[structopt(flatten)]
+        size_report: cli::SizeReport,
+        #[structopt(flatten)]
+        show_assets: cli::ShowAssets,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+        #[structopt(
+            long = "libs-only",
+            help = "Only build the dynamic libraries, even when integrating into a host project"
+        )]
+        libs_only: bool,
+        #[structopt(
+            long = "apk",
+            help = "Package the libraries into APK(s) too, and print their paths",
+            conflicts_with = "libs-only"
+        )]
+        apk: bool,
+        #[structopt(
+            long = "aab",
+            help = "Package the libraries into app bundle(s) too, and print their paths",
+            conflicts_with = "libs-only"
+        )]
+        aab: bool,
+        #[structopt(
+            long = "out",
+            help = "Copy the packaged APK(s) or app bundle(s) to this file or directory (end with a separator to force a directory); implies `--apk` unless `--aab` is passed",
+            parse(from_os_str),
+            conflicts_with = "libs-only"
+        )]
+        out: Option<PathBuf>,
+        #[structopt(
+            long = "json",
+            help = "Print the packaged artifacts' paths as JSON; implies `--apk` unless `--aab` is passed",
+            conflicts_with = "libs-only"
+        )]
+        json: bool,
+        #[structopt(
+            long = "jobs-targets",
+            help = "How many targets to build at once [default: the number of targets, up to 2]"
+        )]
+        jobs_targets: Option<usize>,
+    },
+    #[structopt(
+        name = "apk",
+        about = "Builds APKs for target(s) and prints their paths; the same as `build --apk`"
+    )]
+    Apk {
+        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::possible_values(), hide_possible_values = true, help = "Targets to use, by name, alias, or triple; see `cargo android targets`")]
         targets: Vec<String>,
         #[structopt(flatten)]
         profile: cli::Profile,
+        #[structopt(flatten)]
+        skip_strip: cli::SkipStrip,
+        #[structopt(flatten)]
+        force: cli::Force,
+        #[structopt(flatten)]
+        no_hooks: cli::NoHooks,
+        #[structopt(flatten)]
+        timings: cli::Timings,
+        #[structopt(flatten)]
+        size_report: cli::SizeReport,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+        #[structopt(
+            long = "out",
+            help = "Copy the built APK(s) to this file or directory (end with a separator to force a directory)",
+            parse(from_os_str)
+        )]
+        out: Option<PathBuf>,
+        #[structopt(long = "json", help = "Print artifact paths as JSON")]
+        json: bool,
+    },
+    #[structopt(
+        name = "inspect",
+        about = "Lists the native libs and manifest values in an APK or app bundle, and flags anything suspicious"
+    )]
+    Inspect {
+        #[structopt(
+            name = "path",
+            help = "APK or app bundle to inspect [default: the most recently built one]",
+            parse(from_os_str)
+        )]
+        path: Option<PathBuf>,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+        #[structopt(long = "json", help = "Print what's inside as JSON")]
+        json: bool,
+    },
+    #[structopt(
+        name = "install",
+        about = "Installs an APK on a connected device, without building anything"
+    )]
+    Install {
+        #[structopt(
+            long = "device",
+            help = "Device to install on, specified by serial number or name [default: prompts if several are connected]"
+        )]
+        device: Option<String>,
+        #[structopt(flatten)]
+        device_timeout: cli::DeviceTimeout,
+        #[structopt(
+            long = "apk",
+            help = "APK to install, i.e. one built by CI [default: the last one `cargo android apk` built for the device]"
+        )]
+        apk: Option<PathBuf>,
+        #[structopt(flatten)]
+        profile: cli::Profile,
+        #[structopt(flatten)]
+        variant: cli::Variant,
     },
-    #[structopt(name = "run", about = "Deploys APK to connected device")]
+    #[structopt(
+        name = "launch",
+        about = "Launches the installed app on a connected device, without reinstalling it"
+    )]
+    Launch {
+        #[structopt(
+            long = "device",
+            help = "Device to launch on, specified by serial number or name [default: prompts if several are connected]"
+        )]
+        device: Option<String>,
+        #[structopt(flatten)]
+        device_timeout: cli::DeviceTimeout,
+        #[structopt(
+            long = "activity",
+            help = "Activity to launch, which is relative to the app ID if it starts with `.` [default: the app's main activity]"
+        )]
+        activity: Option<String>,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+    },
+    #[structopt(
+        name = "run",
+        about = "Builds, installs, and launches the app on a connected device; like `apk`, `install`, and `launch` in one go"
+    )]
     Run {
+        #[structopt(
+            long = "device",
+            help = "Device to run on, specified by serial number or name, or `ask` to prompt [default: the device last run on if it's connected, or else prompts if several are]"
+        )]
+        device: Option<String>,
+        #[structopt(flatten)]
+        device_timeout: cli::DeviceTimeout,
         #[structopt(flatten)]
         profile: cli::Profile,
+        #[structopt(flatten)]
+        skip_strip: cli::SkipStrip,
+        #[structopt(flatten)]
+        force: cli::Force,
+        #[structopt(flatten)]
+        no_hooks: cli::NoHooks,
+        #[structopt(flatten)]
+        no_crash_check: cli::NoCrashCheck,
+        #[structopt(flatten)]
+        timings: cli::Timings,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+        #[structopt(
+            long = "watch",
+            help = "Rebuild and redeploy whenever the crate's sources change",
+            parse(from_flag = opts::Watch::from_bool),
+        )]
+        watch: opts::Watch,
+        #[structopt(
+            long = "port",
+            help = "Forward this port on the device to the same one on this machine, for a dev server, or `auto` to pick a free one"
+        )]
+        port: Option<net::Port>,
+        #[structopt(
+            long = "verify",
+            help = "Make sure the forwarded port reaches this machine, by connecting to it from the device",
+            requires = "port"
+        )]
+        verify: bool,
+    },
+    #[structopt(
+        name = "test",
+        about = "Runs the Android project's instrumented tests on a connected device"
+    )]
+    Test {
+        #[structopt(
+            long = "instrumented",
+            help = "Run the instrumented tests in the app module's `src/androidTest`, i.e. Espresso tests"
+        )]
+        instrumented: bool,
+        #[structopt(
+            long = "device",
+            help = "Device to test on, specified by serial number or name, or `ask` to prompt [default: the device last run on if it's connected, or else prompts if several are]"
+        )]
+        device: Option<String>,
+        #[structopt(flatten)]
+        device_timeout: cli::DeviceTimeout,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+        #[structopt(long = "json", help = "Print test results as JSON")]
+        json: bool,
     },
     #[structopt(name = "st", about = "Displays a detailed stacktrace for a device")]
     Stacktrace,
+    #[structopt(
+        name = "pull",
+        about = "Copies a file out of the app's data on a connected device; the app has to be a debug build"
+    )]
+    Pull {
+        #[structopt(
+            name = "remote",
+            help = "File to pull, relative to the app's data dir, i.e. `databases/app.db`; wildcards have to match exactly one file"
+        )]
+        remote: String,
+        #[structopt(
+            name = "local",
+            help = "Where to put it [default: the current dir, under the same name]"
+        )]
+        local: Option<PathBuf>,
+        #[structopt(
+            long = "device",
+            help = "Device to pull from, specified by serial number or name [default: prompts if several are connected]"
+        )]
+        device: Option<String>,
+        #[structopt(flatten)]
+        device_timeout: cli::DeviceTimeout,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+    },
+    #[structopt(
+        name = "push",
+        about = "Copies a file into the app's data on a connected device; the app has to be a debug build"
+    )]
+    Push {
+        #[structopt(name = "local", help = "File to push")]
+        local: PathBuf,
+        #[structopt(
+            name = "remote",
+            help = "Where to put it, relative to the app's data dir; ending it with `/` keeps the file's name"
+        )]
+        remote: String,
+        #[structopt(
+            long = "device",
+            help = "Device to push to, specified by serial number or name [default: prompts if several are connected]"
+        )]
+        device: Option<String>,
+        #[structopt(flatten)]
+        device_timeout: cli::DeviceTimeout,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+    },
+    #[structopt(
+        name = "adb",
+        about = "Runs `adb` on a connected device, with the device already picked; i.e. `cargo android adb -- shell`"
+    )]
+    Adb {
+        #[structopt(
+            long = "device",
+            help = "Device to use, specified by serial number or name, or `ask` to prompt [default: the device last run on if it's connected, or else prompts if several are]"
+        )]
+        device: Option<String>,
+        #[structopt(flatten)]
+        device_timeout: cli::DeviceTimeout,
+        #[structopt(
+            name = "args",
+            last = true,
+            help = "Arguments for `adb`, which have to come after `--`"
+        )]
+        args: Vec<String>,
+    },
     #[structopt(name = "list", about = "Lists connected devices")]
     List,
+    #[structopt(
+        name = "devices",
+        about = "Lists connected devices and emulators in detail, including ones that aren't ready"
+    )]
+    Devices {
+        #[structopt(long = "json", help = "Print devices as JSON")]
+        json: bool,
+        #[structopt(long = "names", hidden = true)]
+        names: bool,
+    },
+    #[structopt(
+        name = "targets",
+        about = "Lists the targets you can build for, and which ones are installed"
+    )]
+    Targets {
+        #[structopt(long = "json", help = "Print targets as JSON")]
+        json: bool,
+    },
+    #[structopt(
+        name = "env",
+        about = "Prints the environment builds for a target run with"
+    )]
+    Env {
+        #[structopt(name = "target", default_value = Target::DEFAULT_KEY, possible_values = Target::possible_values(), hide_possible_values = true, help = "Target to use, by name, alias, or triple; see `cargo android targets`")]
+        target: String,
+        #[structopt(
+            long = "shell",
+            help = "Print commands that set the environment in your shell",
+            conflicts_with = "json"
+        )]
+        shell: bool,
+        #[structopt(long = "json", help = "Print the environment as JSON")]
+        json: bool,
+        #[structopt(flatten)]
+        profile: cli::Profile,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+    },
+    #[structopt(
+        name = "config-gen",
+        about = "Adds each target's linker settings to `.cargo/config.toml`, so plain `cargo build --target <triple>` works too"
+    )]
+    ConfigGen,
+    #[structopt(
+        name = "completions",
+        about = "Prints a completion script for your shell"
+    )]
+    Completions {
+        #[structopt(flatten)]
+        completions: cli::Completions,
+    },
 }
 
 #[derive(Debug)]
 pub enum Error {
     EnvInitFailed(EnvError),
     DevicePromptFailed(PromptError<adb::device_list::Error>),
+    DeviceSelectFailed(adb::device_list::SelectError),
+    DeviceWaitFailed(device::WaitError<adb::device_list::Error>),
     TargetInvalid(TargetInvalid),
     ConfigFailed(LoadOrGenError),
+    ConfigCheckFailed(CheckError),
+    RegenFailed(regen::Error),
+    PreflightFailed(preflight::Error),
+    AssetsFailed(assets::Error),
+    JdkSelectFailed(jdk::Error),
+    GradleHeapFailed(jdk::Error),
+    GradleProxyFailed(jdk::Error),
+    VariantFailed(variant::Unknown),
     MetadataFailed(metadata::Error),
     Unsupported,
     ProjectDirAbsent { project_dir: PathBuf },
     OpenFailed(bossy::Error),
     CheckFailed(CompileLibError),
     BuildFailed(BuildError),
+    ParallelBuildFailed(parallel::Error),
+    HookFailed(HookError),
+    ApkBuildFailed(ApkBuildError),
+    ApkOutputsFailed(apk::OutputsError),
+    ApkCopyFailed(apk::CopyError),
+    InspectFailed(inspect::Error),
+    InstallFailed(ApkInstallError),
+    LaunchFailed(RunError),
     RunFailed(RunError),
+    PortUnavailable(net::Error),
+    ForwardFailed(ForwardError),
+    TestKindMissing,
+    TestFailed(instrumented::Error),
+    WatchFailed(watch::Error),
     StacktraceFailed(StacktraceError),
+    TransferFailed(TransferError),
+    PassthroughFailed(util::passthrough::Error),
     ListFailed(adb::device_list::Error),
+    BuildEnvFailed(ndk::MissingToolError),
+    SizesFailed(sizes::Error),
+    BuildConfigFailed(build_config::ResolveError),
+    DotCargoLoadFailed(dot_cargo::LoadError),
+    DotCargoGenFailed(ndk::MissingToolError),
+    DotCargoWriteFailed(dot_cargo::WriteError),
 }
 
 impl Reportable for Error {
@@ -95,8 +482,18 @@ impl Reportable for Error {
         match self {
             Self::EnvInitFailed(err) => err.report(),
             Self::DevicePromptFailed(err) => err.report(),
+            Self::DeviceSelectFailed(err) => err.report(),
+            Self::DeviceWaitFailed(err) => err.report(),
             Self::TargetInvalid(err) => Report::error("Specified target was invalid", err),
             Self::ConfigFailed(err) => err.report(),
+            Self::ConfigCheckFailed(err) => err.report(),
+            Self::RegenFailed(err) => err.report(),
+            Self::PreflightFailed(err) => err.report(),
+            Self::AssetsFailed(err) => err.report(),
+            Self::JdkSelectFailed(err) => err.report(),
+            Self::GradleHeapFailed(err) => err.report(),
+            Self::GradleProxyFailed(err) => err.report(),
+            Self::VariantFailed(err) => err.report(),
             Self::MetadataFailed(err) => err.report(),
             Self::Unsupported => Report::error("Android is marked as unsupported in your Cargo.toml metadata", "If your project should support Android, modify your Cargo.toml, then run `cargo mobile init` and try again."),
             Self::ProjectDirAbsent { project_dir } => Report::action_request(
@@ -109,13 +506,96 @@ impl Reportable for Error {
             Self::OpenFailed(err) => Report::error("Failed to open project in Android Studio", err),
             Self::CheckFailed(err) => err.report(),
             Self::BuildFailed(err) => err.report(),
+            Self::ParallelBuildFailed(err) => err.report(),
+            Self::HookFailed(err) => err.report(),
+            Self::ApkBuildFailed(err) => err.report(),
+            Self::ApkOutputsFailed(err) => err.report(),
+            Self::ApkCopyFailed(err) => err.report(),
+            Self::InspectFailed(err) => err.report(),
+            Self::InstallFailed(err) => err.report(),
+            Self::LaunchFailed(err) => err.report(),
             Self::RunFailed(err) => err.report(),
+            Self::PortUnavailable(err) => err.report(),
+            Self::ForwardFailed(err) => err.report(),
+            Self::TestKindMissing => Report::action_request(
+                "Only instrumented tests can be run on devices",
+                "Pass `--instrumented` to run the tests in the app module's `src/androidTest` dir; Rust tests that don't need a device can be run with `cargo test`.",
+            ),
+            Self::TestFailed(err) => err.report(),
+            Self::WatchFailed(err) => err.report(),
             Self::StacktraceFailed(err) => err.report(),
+            Self::TransferFailed(err) => err.report(),
+            Self::PassthroughFailed(err) => err.report(),
             Self::ListFailed(err) => err.report(),
+            Self::BuildEnvFailed(err) => {
+                Report::error("Failed to locate required build tool", err)
+            }
+            Self::SizesFailed(err) => err.report(),
+            Self::BuildConfigFailed(err) => err.report(),
+            Self::DotCargoLoadFailed(err) => err.report(),
+            Self::DotCargoGenFailed(err) => {
+                Report::error("Failed to generate Android cargo config", err)
+            }
+            Self::DotCargoWriteFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::EnvInitFailed(err) => err.code(),
+            Self::DevicePromptFailed(err) => err.code(),
+            Self::DeviceSelectFailed(err) => err.code(),
+            Self::DeviceWaitFailed(err) => err.code(),
+            Self::TargetInvalid(..) => "android.cli.target-invalid",
+            Self::ConfigFailed(err) => err.code(),
+            Self::ConfigCheckFailed(err) => err.code(),
+            Self::RegenFailed(err) => err.code(),
+            Self::PreflightFailed(err) => err.code(),
+            Self::AssetsFailed(err) => err.code(),
+            Self::JdkSelectFailed(err) => err.code(),
+            Self::GradleHeapFailed(err) => err.code(),
+            Self::GradleProxyFailed(err) => err.code(),
+            Self::VariantFailed(err) => err.code(),
+            Self::MetadataFailed(err) => err.code(),
+            Self::Unsupported => "android.cli.unsupported",
+            Self::ProjectDirAbsent { .. } => "android.cli.project-dir-absent",
+            Self::OpenFailed(..) => "android.cli.open-failed",
+            Self::CheckFailed(err) => err.code(),
+            Self::BuildFailed(err) => err.code(),
+            Self::ParallelBuildFailed(err) => err.code(),
+            Self::HookFailed(err) => err.code(),
+            Self::ApkBuildFailed(err) => err.code(),
+            Self::ApkOutputsFailed(err) => err.code(),
+            Self::ApkCopyFailed(err) => err.code(),
+            Self::InspectFailed(err) => err.code(),
+            Self::InstallFailed(err) => err.code(),
+            Self::LaunchFailed(err) => err.code(),
+            Self::RunFailed(err) => err.code(),
+            Self::PortUnavailable(err) => err.code(),
+            Self::ForwardFailed(err) => err.code(),
+            Self::TestKindMissing => "android.cli.test-kind-missing",
+            Self::TestFailed(err) => err.code(),
+            Self::WatchFailed(err) => err.code(),
+            Self::StacktraceFailed(err) => err.code(),
+            Self::TransferFailed(err) => err.code(),
+            Self::PassthroughFailed(err) => err.code(),
+            Self::ListFailed(err) => err.code(),
+            Self::BuildEnvFailed(..) => "android.cli.build-env-failed",
+            Self::SizesFailed(err) => err.code(),
+            Self::BuildConfigFailed(err) => err.code(),
+            Self::DotCargoLoadFailed(err) => err.code(),
+            Self::DotCargoGenFailed(..) => "android.cli.dot-cargo-gen-failed",
+            Self::DotCargoWriteFailed(err) => err.code(),
         }
     }
 }
 
+/// Every device `adb` knows about, for listing alongside other platforms'.
+pub fn device_listings() -> Result<Vec<device::Listing>, Error> {
+    let env = Env::new().map_err(Error::EnvInitFailed)?;
+    adb::device_list::listings(&env).map_err(Error::ListFailed)
+}
+
 impl Exec for Input {
     type Report = Error;
 
@@ -123,35 +603,269 @@ impl Exec for Input {
         self.flags
     }
 
+    fn package(&self) -> &cli::Package {
+        &self.package
+    }
+
     fn exec(self, wrapper: &TextWrapper) -> Result<(), Self::Report> {
         define_device_prompt!(adb::device_list, adb::device_list::Error, Android);
         fn detect_target_ok<'a>(env: &Env) -> Option<&'a Target<'a>> {
             device_prompt(env).map(|device| device.target()).ok()
         }
 
+        // `last` is the device to go with if it's connected and nothing else
+        // was asked for.
+        fn select_device(
+            env: &Env,
+            non_interactive: opts::NonInteractive,
+            device_timeout: u64,
+            query: Option<&str>,
+            last: Option<&LastDevice>,
+        ) -> Result<Device<'static>, Error> {
+            let (query, last) = match query {
+                Some(query) if query == device::ASK => (None, None),
+                Some(query) => (Some(query), None),
+                None => (None, last.and_then(LastDevice::id)),
+            };
+            device::wait_until_ready(non_interactive, Duration::from_secs(device_timeout), || {
+                adb::device_list::blocked(env, query)
+            })
+            .map_err(Error::DeviceWaitFailed)?;
+            if let Some(query) = query {
+                return adb::device_list::select(env, query, non_interactive)
+                    .map_err(Error::DeviceSelectFailed);
+            }
+            if let Some(last) = last {
+                let devices = adb::device_list(env).map_err(Error::ListFailed)?;
+                if let Some(device) = devices
+                    .into_iter()
+                    .find(|device| device.serial_no() == last)
+                {
+                    ui::status(format!(
+                        "Using previously selected device {} (serial {}); pass `--device` to change",
+                        device,
+                        device.serial_no()
+                    ));
+                    return Ok(device);
+                }
+            }
+            device_prompt(env).map_err(Error::DevicePromptFailed)
+        }
+
+        // `features` are the ones passed with `--features`, which get merged
+        // into the metadata along with the config's (and the variant's).
         fn with_config(
             non_interactive: opts::NonInteractive,
             wrapper: &TextWrapper,
+            features: &[String],
+            variant: Option<&str>,
             f: impl FnOnce(&Config, &Metadata) -> Result<(), Error>,
         ) -> Result<(), Error> {
-            let (config, _origin) = OmniConfig::load_or_gen(".", non_interactive, wrapper)
-                .map_err(Error::ConfigFailed)?;
-            let metadata =
-                OmniMetadata::load(&config.app().root_dir()).map_err(Error::MetadataFailed)?;
-            if metadata.android().supported() {
-                f(config.android(), metadata.android())
-            } else {
-                Err(Error::Unsupported)
+            let (config, _origin) = OmniConfig::load_or_gen(
+                ".",
+                non_interactive,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Writer::Disk,
+                wrapper,
+            )
+            .map_err(Error::ConfigFailed)?;
+            let (config, metadata) = api::from_config(&config, features, variant)?;
+            f(&config, &metadata)
+        }
+
+        // A typo'd feature would otherwise only come up once cargo gets going.
+        fn check_features(config: &Config, metadata: &Metadata, env: &Env) {
+            features::warn_unknown(
+                &config.app().manifest_path(),
+                config.app().name(),
+                env,
+                metadata.features().unwrap_or_default(),
+            );
+        }
+
+        // Gradle fails with something cryptic like "Unsupported class file
+        // major version" when its JDK is too new, so we point it at one that
+        // isn't. Host projects aren't ours to change, so we only say which.
+        fn select_jdk(config: &Config) -> Result<(), Error> {
+            let project_dir = config.project_dir();
+            let requirement = jdk::Requirement::for_project(&project_dir);
+            let selection = jdk::select(Some(&project_dir), &requirement);
+            let msg = selection.describe(&requirement);
+            match &selection {
+                jdk::Selection::Default(_) => log::info!("{}", msg),
+                jdk::Selection::Found { jdk, .. } => {
+                    let home = jdk
+                        .home()
+                        .expect("developer error: found a JDK without a home");
+                    if config.library().is_some() {
+                        ui::report(&Report::action_request(
+                            format!("Gradle {} can't run on your JDK", requirement.gradle()),
+                            format!(
+                                "{}. Set `org.gradle.java.home={}` in your project's gradle.properties to use it.",
+                                msg,
+                                home.display()
+                            ),
+                        ));
+                    } else {
+                        jdk::write_java_home(&project_dir, home).map_err(Error::JdkSelectFailed)?;
+                        ui::status(msg);
+                    }
+                }
+                jdk::Selection::NotFound { .. } => ui::report(&Report::action_request(
+                    format!("Gradle {} can't run on your JDK", requirement.gradle()),
+                    format!("{}. {}.", msg, requirement.install_hint()),
+                )),
             }
+            Ok(())
         }
 
-        fn ensure_init(config: &Config) -> Result<(), Error> {
-            if !config.project_dir_exists() {
-                Err(Error::ProjectDirAbsent {
-                    project_dir: config.project_dir(),
-                })
+        // The heap goes in `gradle.properties`, which for host projects
+        // isn't ours to change, so they're only told what to set.
+        fn configure_resources(
+            config: &Config,
+            jobs: Option<resources::Setting>,
+        ) -> Result<(), Error> {
+            let plan = resources::configure(jobs, config.app().jobs(), Some(config.gradle_heap()));
+            let heap = match plan.gradle_heap_mib() {
+                Some(heap) => heap,
+                None => return Ok(()),
+            };
+            if config.library().is_some() {
+                log::warn!(
+                    "set `org.gradle.jvmargs=-Xmx{}m` in your project's gradle.properties to give Gradle {} MiB of heap",
+                    heap,
+                    heap
+                );
+            } else if jdk::write_heap(&config.project_dir(), heap)
+                .map_err(Error::GradleHeapFailed)?
+            {
+                ui::status(format!(
+                    "Set Gradle's heap to {} MiB in gradle.properties",
+                    heap
+                ));
+            }
+            Ok(())
+        }
+
+        // Proxy credentials don't belong on Gradle's command line, so they go
+        // in `gradle.properties` too, with the same caveat for host projects.
+        fn configure_proxy(config: &Config) -> Result<(), Error> {
+            let proxy = proxy::get();
+            if config.library().is_some() {
+                if proxy.has_credentials() {
+                    log::warn!(
+                        "set `systemProp.https.proxyUser` and `systemProp.https.proxyPassword` in `~/.gradle/gradle.properties` for Gradle to authenticate with your proxy"
+                    );
+                }
+            } else if jdk::write_proxy(&config.project_dir(), proxy)
+                .map_err(Error::GradleProxyFailed)?
+            {
+                log::info!("updated proxy settings in gradle.properties");
+            }
+            Ok(())
+        }
+
+        fn report_sizes(
+            config: &Config,
+            sizes: Sizes,
+            profile: opts::Profile,
+            format: sizes::Format,
+        ) -> Result<(), Error> {
+            match sizes.summarize(config.app(), super::NAME, profile) {
+                Some(summary) => {
+                    summary.print(format);
+                    summary
+                        .check(config.size_policy())
+                        .map_err(Error::SizesFailed)
+                }
+                None => Ok(()),
+            }
+        }
+
+        // What `build` packages the libs into, if anything. Asking for where
+        // the artifacts went only makes sense if there are some, so that
+        // means APKs unless bundles were asked for.
+        fn package_formats(build_apk: bool, build_aab: bool, reporting: bool) -> Vec<apk::Format> {
+            let mut formats = Vec::new();
+            if build_apk || (reporting && !build_aab) {
+                formats.push(apk::Format::Apk);
+            }
+            if build_aab {
+                formats.push(apk::Format::Aab);
+            }
+            formats
+        }
+
+        fn collect_artifacts(
+            config: &Config,
+            target: &Target<'_>,
+            format: apk::Format,
+            profile: opts::Profile,
+            artifacts: &mut Vec<Artifact>,
+        ) -> Result<(), Error> {
+            let paths =
+                apk::outputs(config, target, profile, format).map_err(Error::ApkOutputsFailed)?;
+            artifacts.extend(
+                paths
+                    .into_iter()
+                    .map(|path| Artifact::new(config, target, format, profile, path)),
+            );
+            Ok(())
+        }
+
+        // Copies the artifacts to `out` if it's given, and prints where they
+        // ended up along with how big they are.
+        fn report_artifacts(
+            config: &Config,
+            mut artifacts: Vec<Artifact>,
+            mut sizes: Sizes,
+            profile: opts::Profile,
+            size_report: sizes::Format,
+            out: Option<&Path>,
+            json: bool,
+        ) -> Result<(), Error> {
+            if let Some(out) = out {
+                apk::copy_artifacts(&mut artifacts, out).map_err(Error::ApkCopyFailed)?;
+            }
+            for artifact in &mut artifacts {
+                if let Ok(path) = artifact.path.canonicalize() {
+                    artifact.path = path;
+                }
+                if let Some(name) = artifact.path.file_name() {
+                    sizes
+                        .measure_package(name.to_string_lossy(), &artifact.path)
+                        .map_err(Error::SizesFailed)?;
+                }
+            }
+            let sizes = sizes.summarize(config.app(), super::NAME, profile);
+            if json {
+                // The summaries go in the JSON instead of being printed over
+                // it.
+                println!(
+                    "{}",
+                    serde_json::json!({
+                        "artifacts": artifacts,
+                        "timings": timings::summarize(),
+                        "sizes": sizes,
+                    })
+                );
             } else {
-                Ok(())
+                for artifact in &artifacts {
+                    println!("{}", artifact.path.display());
+                }
+                if let Some(sizes) = &sizes {
+                    sizes.print(size_report);
+                }
+            }
+            match sizes {
+                Some(sizes) => sizes
+                    .check(config.size_policy())
+                    .map_err(Error::SizesFailed),
+                None => Ok(()),
             }
         }
 
@@ -164,17 +878,107 @@ impl Exec for Input {
                 GlobalFlags {
                     noise_level,
                     non_interactive,
+                    format,
+                    ignore_version_mismatch,
+                    jobs,
+                    ..
+                },
+            daemon_management:
+                cli::NoDaemonManagement {
+                    no_daemon_management,
                 },
             command,
+            ..
         } = self;
-        let env = Env::new().map_err(Error::EnvInitFailed)?;
+        let format = format.unwrap_or_default();
+        // This shouldn't depend on having the SDK installed.
+        if let Command::Completions {
+            completions: cli::Completions { shell },
+        } = command
+        {
+            cli::print_completions::<Input>(NAME, shell, true);
+            return Ok(());
+        }
+        // Nor should this, since it's useful for figuring out what to install.
+        if let Command::Targets { json } = command {
+            target::print_listings(&target::listings::<Target>(), json);
+            return Ok(());
+        }
+        // An APK can be inspected on a machine that couldn't have built it.
+        if let Command::Inspect {
+            path,
+            variant: cli::Variant { variant },
+            json,
+        } = &command
+        {
+            let inspect = |path: &Path, so_name: Option<&str>, ignore: Option<&assets::Ignore>| {
+                inspect::inspect(path, so_name, ignore)
+                    .map(|inspection| inspection.print(*json || format.json()))
+                    .map_err(Error::InspectFailed)
+            };
+            return match path {
+                Some(path) => inspect(path, None, None),
+                None => {
+                    regen::if_version_mismatched(wrapper, non_interactive, ignore_version_mismatch)
+                        .map_err(Error::RegenFailed)?;
+                    with_config(
+                        non_interactive,
+                        wrapper,
+                        &[],
+                        variant.as_deref(),
+                        |config, _| {
+                            let path = inspect::latest(config).map_err(Error::InspectFailed)?;
+                            let ignore =
+                                assets::Ignore::load(config.app()).map_err(Error::AssetsFailed)?;
+                            inspect(&path, Some(&config.so_name()), Some(&ignore))
+                        },
+                    )
+                }
+            };
+        }
+        let env = Env::new()
+            .map_err(Error::EnvInitFailed)?
+            .with_no_daemon_management(no_daemon_management);
+        // When Gradle calls us back with `--libs-only`, it's already in the
+        // middle of building the project.
+        if matches!(
+            command,
+            Command::Build {
+                libs_only: false,
+                ..
+            } | Command::Apk { .. }
+                | Command::Run { .. }
+                | Command::Test { .. }
+        ) {
+            regen::if_stale(wrapper, non_interactive, ignore_version_mismatch)
+                .map_err(Error::RegenFailed)?;
+        }
+        // These don't build anything, but still go by what's in `gen/`.
+        if matches!(
+            command,
+            Command::Open
+                | Command::Install { apk: None, .. }
+                | Command::Launch { .. }
+                | Command::Stacktrace
+        ) {
+            regen::if_version_mismatched(wrapper, non_interactive, ignore_version_mismatch)
+                .map_err(Error::RegenFailed)?;
+        }
         match command {
-            Command::Open => with_config(non_interactive, wrapper, |config, _| {
+            Command::Open => with_config(non_interactive, wrapper, &[], None, |config, _| {
                 ensure_init(config)?;
                 open_in_android_studio(config)
             }),
-            Command::Check { targets } => {
-                with_config(non_interactive, wrapper, |config, metadata| {
+            Command::Check {
+                targets,
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &features,
+                variant.as_deref(),
+                |config, metadata| {
                     let force_color = opts::ForceColor::Yes;
                     call_for_targets_with_fallback(
                         targets.iter(),
@@ -187,47 +991,558 @@ impl Exec for Input {
                         },
                     )
                     .map_err(Error::TargetInvalid)?
-                })
-            }
+                },
+            ),
             Command::Build {
                 targets,
                 profile: cli::Profile { profile },
-            } => with_config(non_interactive, wrapper, |config, metadata| {
-                ensure_init(config)?;
-                let force_color = opts::ForceColor::Yes;
-                call_for_targets_with_fallback(
-                    targets.iter(),
-                    &detect_target_ok,
-                    &env,
-                    |target: &Target| {
+                skip_strip: cli::SkipStrip { skip_strip },
+                force: cli::Force { force },
+                no_hooks: cli::NoHooks { no_hooks },
+                timings: cli::Timings { timings },
+                size_report: cli::SizeReport { size_report },
+                show_assets: cli::ShowAssets { show_assets },
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
+                libs_only,
+                jobs_targets,
+                apk: build_apk,
+                aab: build_aab,
+                out,
+                json,
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &features,
+                variant.as_deref(),
+                |config, metadata| {
+                    ensure_init(config)?;
+                    let formats = package_formats(build_apk, build_aab, out.is_some() || json);
+                    // Gradle's already building by the time it calls us back.
+                    if !libs_only {
+                        if show_assets {
+                            assets::show(config.app()).map_err(Error::AssetsFailed)?;
+                        }
+                        preflight::check(config.app()).map_err(Error::PreflightFailed)?;
+                        configure_resources(config, jobs)?;
+                        configure_proxy(config)?;
+                        check_features(config, metadata, &env);
+                        if config.library().is_some() || !formats.is_empty() {
+                            select_jdk(config)?;
+                        }
+                    }
+                    let _timings = timings::start(&config.project_dir(), timings, noise_level);
+                    if config.library().is_some() && !formats.is_empty() {
+                        // The host app contains every ABI, and building it
+                        // builds the module, which calls us back with
+                        // `--libs-only`.
+                        let targets = get_targets(targets.iter(), Some((&detect_target_ok, &env)))
+                            .map_err(Error::TargetInvalid)?;
+                        let mut artifacts = Vec::new();
+                        for format in formats {
+                            apk::package(
+                                config,
+                                &env,
+                                targets[0],
+                                format,
+                                noise_level,
+                                profile,
+                                skip_strip,
+                                force,
+                                no_hooks,
+                            )
+                            .map_err(Error::ApkBuildFailed)?;
+                            collect_artifacts(config, targets[0], format, profile, &mut artifacts)?;
+                        }
+                        return report_artifacts(
+                            config,
+                            artifacts,
+                            Sizes::new(),
+                            profile,
+                            size_report,
+                            out.as_deref(),
+                            json,
+                        );
+                    }
+                    if let Some(library) = config.library().filter(|_| !libs_only) {
+                        // The module's `BuildTask` calls us back with `--libs-only`
+                        return apk::build_module(
+                            config,
+                            &env,
+                            library,
+                            &targets,
+                            noise_level,
+                            profile,
+                            skip_strip,
+                            no_hooks,
+                        )
+                        .map_err(Error::ApkBuildFailed);
+                    }
+                    let targets = get_targets(targets.iter(), Some((&detect_target_ok, &env)))
+                        .map_err(Error::TargetInvalid)?;
+                    let sizes = api::build(
+                        config,
+                        metadata,
+                        &env,
+                        &targets,
+                        api::BuildOptions {
+                            noise_level,
+                            profile,
+                            skip_strip,
+                            force,
+                            no_hooks,
+                            jobs: jobs_targets,
+                        },
+                    )?;
+                    // Gradle is in the middle of packaging when it calls us
+                    // back, so the sizes are for it to worry about.
+                    if libs_only {
+                        return Ok(());
+                    }
+                    if formats.is_empty() {
+                        return report_sizes(config, sizes, profile, size_report);
+                    }
+                    // The libs were just built, so this is only packaging.
+                    let mut artifacts = Vec::new();
+                    for target in &targets {
+                        for &format in &formats {
+                            apk::package(
+                                config,
+                                &env,
+                                target,
+                                format,
+                                noise_level,
+                                profile,
+                                skip_strip,
+                                force,
+                                no_hooks,
+                            )
+                            .map_err(Error::ApkBuildFailed)?;
+                            collect_artifacts(config, target, format, profile, &mut artifacts)?;
+                        }
+                    }
+                    report_artifacts(
+                        config,
+                        artifacts,
+                        sizes,
+                        profile,
+                        size_report,
+                        out.as_deref(),
+                        json,
+                    )
+                },
+            ),
+            Command::Apk {
+                targets,
+                profile: cli::Profile { profile },
+                skip_strip: cli::SkipStrip { skip_strip },
+                force: cli::Force { force },
+                no_hooks: cli::NoHooks { no_hooks },
+                timings: cli::Timings { timings },
+                size_report: cli::SizeReport { size_report },
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
+                out,
+                json,
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &features,
+                variant.as_deref(),
+                |config, metadata| {
+                    ensure_init(config)?;
+                    preflight::check(config.app()).map_err(Error::PreflightFailed)?;
+                    configure_resources(config, jobs)?;
+                    configure_proxy(config)?;
+                    check_features(config, metadata, &env);
+                    select_jdk(config)?;
+                    let _timings = timings::start(&config.project_dir(), timings, noise_level);
+                    let mut targets = get_targets(targets.iter(), Some((&detect_target_ok, &env)))
+                        .map_err(Error::TargetInvalid)?;
+                    if config.library().is_some() {
+                        // The host app's APK contains every ABI, so there's only
+                        // one APK to build.
+                        targets.truncate(1);
+                    }
+                    let mut artifacts = Vec::new();
+                    let mut sizes = Sizes::new();
+                    for target in targets {
+                        apk::build(
+                            config,
+                            metadata,
+                            &env,
+                            target,
+                            noise_level,
+                            profile,
+                            skip_strip,
+                            force,
+                            no_hooks,
+                        )
+                        .map_err(Error::ApkBuildFailed)?;
                         target
-                            .build(config, metadata, &env, noise_level, force_color, profile)
-                            .map_err(Error::BuildFailed)
-                    },
-                )
-                .map_err(Error::TargetInvalid)?
-            }),
+                            .measure_lib(config, &mut sizes)
+                            .map_err(Error::SizesFailed)?;
+                        collect_artifacts(
+                            config,
+                            target,
+                            apk::Format::Apk,
+                            profile,
+                            &mut artifacts,
+                        )?;
+                    }
+                    report_artifacts(
+                        config,
+                        artifacts,
+                        sizes,
+                        profile,
+                        size_report,
+                        out.as_deref(),
+                        json,
+                    )
+                },
+            ),
             Command::Run {
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
                 profile: cli::Profile { profile },
-            } => with_config(non_interactive, wrapper, |config, _| {
-                ensure_init(config)?;
-                device_prompt(&env)
-                    .map_err(Error::DevicePromptFailed)?
-                    .run(config, &env, noise_level, profile)
-                    .map_err(Error::RunFailed)
-            }),
-            Command::Stacktrace => with_config(non_interactive, wrapper, |config, _| {
+                skip_strip: cli::SkipStrip { skip_strip },
+                force: cli::Force { force },
+                no_hooks: cli::NoHooks { no_hooks },
+                no_crash_check: cli::NoCrashCheck { no_crash_check },
+                timings: cli::Timings { timings },
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
+                watch,
+                port,
+                verify,
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &features,
+                variant.as_deref(),
+                |config, metadata| {
+                    ensure_init(config)?;
+                    preflight::check(config.app()).map_err(Error::PreflightFailed)?;
+                    configure_resources(config, jobs)?;
+                    configure_proxy(config)?;
+                    check_features(config, metadata, &env);
+                    select_jdk(config)?;
+                    let port = port
+                        .map(net::pick)
+                        .transpose()
+                        .map_err(Error::PortUnavailable)?;
+                    // The app's build gets the port, so it knows where to look.
+                    let with_port;
+                    let config = match port {
+                        Some(port) => {
+                            with_port =
+                                config.clone().with_env_var(net::PORT_VAR, port.to_string());
+                            &with_port
+                        }
+                        None => config,
+                    };
+                    let last = LastDevice::new(config.app().root_dir(), Platform::Android);
+                    let device = select_device(
+                        &env,
+                        non_interactive,
+                        device_timeout,
+                        device.as_deref(),
+                        Some(&last),
+                    )?;
+                    if let Some(port) = port {
+                        device
+                            .forward(&env, port, verify)
+                            .map_err(Error::ForwardFailed)?;
+                    }
+                    if watch.yes() {
+                        let manifest_path = config.app().manifest_path();
+                        watch::watch(&manifest_path, &[], &env, wrapper, |trigger| {
+                            // Fingerprints keep Gradle out of it when only the
+                            // Rust changed, so a full rebuild just ignores them.
+                            let force = match trigger {
+                                Trigger::Changed(_) => force,
+                                Trigger::Rebuild => opts::Force::Yes,
+                            };
+                            let _timings =
+                                timings::start(&config.project_dir(), timings, noise_level);
+                            device
+                                .deploy(
+                                    config,
+                                    metadata,
+                                    &env,
+                                    noise_level,
+                                    profile,
+                                    skip_strip,
+                                    force,
+                                    no_hooks,
+                                    no_crash_check,
+                                )
+                                .map(|()| last.record(device.serial_no()))
+                        })
+                        .map_err(Error::WatchFailed)
+                    } else {
+                        let _timings = timings::start(&config.project_dir(), timings, noise_level);
+                        device
+                            .run(
+                                config,
+                                metadata,
+                                &env,
+                                noise_level,
+                                profile,
+                                skip_strip,
+                                force,
+                                no_hooks,
+                                no_crash_check,
+                            )
+                            .map(|()| last.record(device.serial_no()))
+                            .map_err(Error::RunFailed)
+                    }
+                },
+            ),
+            // An APK from elsewhere doesn't need a project to go with it.
+            Command::Install {
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+                apk: Some(apk),
+                ..
+            } => select_device(
+                &env,
+                non_interactive,
+                device_timeout,
+                device.as_deref(),
+                None,
+            )?
+            .install(&env, &apk)
+            .map_err(Error::InstallFailed),
+            Command::Install {
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+                apk: None,
+                profile: cli::Profile { profile },
+                variant: cli::Variant { variant },
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &[],
+                variant.as_deref(),
+                |config, _| {
+                    ensure_init(config)?;
+                    select_device(
+                        &env,
+                        non_interactive,
+                        device_timeout,
+                        device.as_deref(),
+                        None,
+                    )?
+                    .install_apk(config, &env, profile)
+                    .map(|_| ())
+                    .map_err(Error::InstallFailed)
+                },
+            ),
+            Command::Launch {
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+                activity,
+                variant: cli::Variant { variant },
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &[],
+                variant.as_deref(),
+                |config, _| {
+                    select_device(
+                        &env,
+                        non_interactive,
+                        device_timeout,
+                        device.as_deref(),
+                        None,
+                    )?
+                    .launch(config, &env, activity.as_deref())
+                    .map_err(Error::LaunchFailed)
+                },
+            ),
+            Command::Test {
+                instrumented,
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
+                json,
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &features,
+                variant.as_deref(),
+                |config, metadata| {
+                    if !instrumented {
+                        return Err(Error::TestKindMissing);
+                    }
+                    ensure_init(config)?;
+                    preflight::check(config.app()).map_err(Error::PreflightFailed)?;
+                    configure_resources(config, jobs)?;
+                    configure_proxy(config)?;
+                    check_features(config, metadata, &env);
+                    select_jdk(config)?;
+                    let last = LastDevice::new(config.app().root_dir(), Platform::Android);
+                    let device = select_device(
+                        &env,
+                        non_interactive,
+                        device_timeout,
+                        device.as_deref(),
+                        Some(&last),
+                    )?;
+                    let result = instrumented::run(
+                        config,
+                        metadata,
+                        &env,
+                        &device,
+                        noise_level,
+                        json || format.json(),
+                    );
+                    // The tests ran on it either way, so it's the one to go
+                    // back to.
+                    if !matches!(result, Err(instrumented::Error::TestFailed(_))) {
+                        last.record(device.serial_no());
+                    }
+                    result.map_err(Error::TestFailed)
+                },
+            ),
+            Command::Stacktrace => with_config(non_interactive, wrapper, &[], None, |config, _| {
                 ensure_init(config)?;
                 device_prompt(&env)
                     .map_err(Error::DevicePromptFailed)?
                     .stacktrace(config, &env)
                     .map_err(Error::StacktraceFailed)
             }),
+            Command::Pull {
+                remote,
+                local,
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+                variant: cli::Variant { variant },
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &[],
+                variant.as_deref(),
+                |config, _| {
+                    select_device(
+                        &env,
+                        non_interactive,
+                        device_timeout,
+                        device.as_deref(),
+                        None,
+                    )?
+                    .pull(config, &env, &remote, local.as_deref())
+                    .map_err(Error::TransferFailed)
+                },
+            ),
+            Command::Push {
+                local,
+                remote,
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+                variant: cli::Variant { variant },
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &[],
+                variant.as_deref(),
+                |config, _| {
+                    select_device(
+                        &env,
+                        non_interactive,
+                        device_timeout,
+                        device.as_deref(),
+                        None,
+                    )?
+                    .push(config, &env, &local, &remote)
+                    .map_err(Error::TransferFailed)
+                },
+            ),
+            Command::Adb {
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+                args,
+            } => with_config(non_interactive, wrapper, &[], None, |config, _| {
+                let last = LastDevice::new(config.app().root_dir(), Platform::Android);
+                let device = select_device(
+                    &env,
+                    non_interactive,
+                    device_timeout,
+                    device.as_deref(),
+                    Some(&last),
+                )?;
+                util::passthrough::run(adb::passthrough(&env, device.serial_no(), &args))
+                    .map_err(Error::PassthroughFailed)
+            }),
             Command::List => adb::device_list(&env)
                 .map_err(Error::ListFailed)
                 .map(|device_list| {
                     prompt::list_display_only(device_list.iter(), device_list.len());
                 }),
+            Command::Devices { json, names } => adb::device_list::listings(&env)
+                .map_err(Error::ListFailed)
+                .map(|listings| {
+                    if names {
+                        device::print_names(&listings)
+                    } else {
+                        device::print_listings(&listings, json)
+                    }
+                }),
+            Command::Env {
+                target,
+                shell,
+                json,
+                profile: cli::Profile { profile },
+                variant: cli::Variant { variant },
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &[],
+                variant.as_deref(),
+                |config, _| {
+                    let targets =
+                        get_targets(std::iter::once(&target), Some((&detect_target_ok, &env)))
+                            .map_err(Error::TargetInvalid)?;
+                    let mut build_env = targets[0]
+                        .build_env(config, &env)
+                        .map_err(Error::BuildEnvFailed)?;
+                    config
+                        .build_config()
+                        .resolve(profile)
+                        .map_err(Error::BuildConfigFailed)?
+                        .apply(&mut build_env);
+                    print!("{}", build_env.render(Format::from_flags(shell, json)));
+                    Ok(())
+                },
+            ),
+            Command::ConfigGen => {
+                with_config(non_interactive, wrapper, &[], None, |config, _| {
+                    let mut dot_cargo = dot_cargo::DotCargo::load(config.app(), Writer::Disk)
+                        .map_err(Error::DotCargoLoadFailed)?;
+                    project::insert_cargo_config(config, &env, &mut dot_cargo)
+                        .map_err(Error::DotCargoGenFailed)?;
+                    let conflicts = dot_cargo
+                        .write(config.app(), None, Writer::Disk)
+                        .map_err(Error::DotCargoWriteFailed)?;
+                    if conflicts.is_empty() {
+                        println!("Your `.cargo/config.toml` has every target's linker settings");
+                    } else {
+                        println!("Left these alone, since they're already set in your `.cargo/config.toml`:");
+                        for conflict in conflicts {
+                            println!("    {}", conflict);
+                        }
+                        println!("Remove them and run this again if you'd like ours instead.");
+                    }
+                    Ok(())
+                })
+            }
+            Command::Completions { .. } | Command::Targets { .. } | Command::Inspect { .. } => {
+                unreachable!(
+                    "developer error: completions, targets, and inspections are printed before env init"
+                )
+            }
         }
     }
 }
@@ -1,27 +1,47 @@
 use crate::{
-    config::app::App,
+    config::{
+        self,
+        app::App,
+        build_config::BuildConfig,
+        rustflags::{self, RustFlags},
+        variant::Variant,
+    },
+    entry_point, features,
+    hooks::{self, Hooks},
+    resources, sizes,
     util::{self, cli::Report},
 };
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fmt::{self, Display},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 const DEFAULT_MIN_SDK_VERSION: u32 = 24;
 const DEFAULT_VULKAN_VALIDATION: bool = true;
+const DEFAULT_DEBUG_DEBUGGABLE: bool = true;
+const DEFAULT_DEBUG_PROFILEABLE: bool = true;
+const DEFAULT_RELEASE_DEBUGGABLE: bool = false;
+const DEFAULT_RELEASE_PROFILEABLE: bool = false;
+const DEFAULT_CARGO_CONFIG: bool = false;
 static DEFAULT_PROJECT_DIR: &str = "gen/android";
+static DEFAULT_LIBRARY_MODULE: &str = "rust";
+static DEFAULT_LIBRARY_APP_MODULE: &str = "app";
 
 const fn default_true() -> bool {
     true
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Metadata {
     #[serde(default = "default_true")]
     supported: bool,
     #[serde(default)]
     features: Option<Vec<String>>,
+    // Only ever set by merging in the config.
+    #[serde(skip)]
+    no_default_features: Option<bool>,
 }
 
 impl Default for Metadata {
@@ -29,6 +49,7 @@ impl Default for Metadata {
         Self {
             supported: true,
             features: None,
+            no_default_features: None,
         }
     }
 }
@@ -39,12 +60,28 @@ impl Metadata {
     }
 
     pub fn no_default_features(&self) -> bool {
-        self.features.is_some()
+        self.no_default_features
+            .unwrap_or_else(|| self.features.is_some())
     }
 
     pub fn features(&self) -> Option<&[String]> {
         self.features.as_deref()
     }
+
+    /// Merges in the features from the config and the command line, which
+    /// take precedence over these.
+    pub fn with_config(mut self, config: &Config) -> Self {
+        let (no_default_features, features) = features::merge(
+            super::NAME,
+            self.features(),
+            config.features.as_deref(),
+            config.no_default_features,
+            &config.cli_features,
+        );
+        self.no_default_features = Some(no_default_features);
+        self.features = features;
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -85,9 +122,43 @@ impl Display for ProjectDirInvalid {
     }
 }
 
+#[derive(Debug)]
+pub enum LibraryInvalid {
+    HostProjectDirNormalizationFailed {
+        host_project_dir: String,
+        cause: util::NormalizationError,
+    },
+    ModuleNameInvalid {
+        key: &'static str,
+        module: String,
+    },
+}
+
+impl Display for LibraryInvalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HostProjectDirNormalizationFailed {
+                host_project_dir,
+                cause,
+            } => write!(
+                f,
+                "`host-project-dir` {:?} couldn't be normalized: {}",
+                host_project_dir, cause
+            ),
+            Self::ModuleNameInvalid { key, module } => write!(
+                f,
+                "`{}` {:?} isn't a valid Gradle module name; it can't be empty or contain any of `/\\:`",
+                key, module
+            ),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     ProjectDirInvalid(ProjectDirInvalid),
+    LibraryInvalid(LibraryInvalid),
+    RustflagsInvalid(rustflags::Error),
 }
 
 impl Error {
@@ -97,10 +168,30 @@ impl Error {
                 msg,
                 format!("`{}.project-dir` invalid: {}", super::NAME, err),
             ),
+            Self::LibraryInvalid(err) => {
+                Report::error(msg, format!("`{}.library` invalid: {}", super::NAME, err))
+            }
+            Self::RustflagsInvalid(err) => err.report(msg),
         }
     }
 }
 
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildTypeRaw {
+    debuggable: Option<bool>,
+    profileable: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LibraryRaw {
+    host_project_dir: String,
+    module: Option<String>,
+    app_module: Option<String>,
+    app_id: Option<String>,
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Raw {
@@ -109,6 +200,162 @@ pub struct Raw {
     project_dir: Option<String>,
     no_default_features: Option<bool>,
     features: Option<Vec<String>>,
+    env: Option<BTreeMap<String, String>>,
+    debug: Option<BuildTypeRaw>,
+    release: Option<BuildTypeRaw>,
+    library: Option<LibraryRaw>,
+    hooks: Option<hooks::Raw>,
+    size_report: Option<sizes::Raw>,
+    cargo_config: Option<bool>,
+    rustflags: Option<Vec<String>>,
+    link_args: Option<Vec<String>>,
+    target: Option<BTreeMap<String, rustflags::Raw>>,
+    gradle_heap_mib: Option<resources::Setting>,
+    entry_symbols: Option<Vec<String>>,
+}
+
+/// The keys each table under `android` can have, for suggesting what an
+/// unknown key was meant to be.
+pub fn known_keys(table: &[&str]) -> &'static [&'static str] {
+    match table {
+        [] => config::known_fields::<Raw>(),
+        ["debug"] | ["release"] => config::known_fields::<BuildTypeRaw>(),
+        ["library"] => config::known_fields::<LibraryRaw>(),
+        ["hooks"] => config::known_fields::<hooks::Raw>(),
+        ["size-report"] => config::known_fields::<sizes::Raw>(),
+        ["target", _] => config::known_fields::<rustflags::Raw>(),
+        _ => &[],
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildType {
+    debuggable: bool,
+    profileable: bool,
+}
+
+impl BuildType {
+    fn from_raw(
+        name: &str,
+        raw: Option<BuildTypeRaw>,
+        default_debuggable: bool,
+        default_profileable: bool,
+    ) -> Self {
+        let raw = raw.unwrap_or_default();
+        let debuggable = raw.debuggable.unwrap_or_else(|| {
+            log::info!(
+                "`{}.{}.debuggable` not set; defaulting to {}",
+                super::NAME,
+                name,
+                default_debuggable
+            );
+            default_debuggable
+        });
+        let profileable = raw.profileable.unwrap_or_else(|| {
+            log::info!(
+                "`{}.{}.profileable` not set; defaulting to {}",
+                super::NAME,
+                name,
+                default_profileable
+            );
+            default_profileable
+        });
+        Self {
+            debuggable,
+            profileable,
+        }
+    }
+}
+
+/// Integrates the Rust lib into an existing Android project as a single Gradle
+/// module, rather than generating a standalone project.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Library {
+    host_project_dir: PathBuf,
+    module: String,
+    app_module: String,
+    app_id: String,
+}
+
+impl Library {
+    fn module_name(key: &'static str, module: String) -> Result<String, LibraryInvalid> {
+        if !module.is_empty() && !module.contains(|c| matches!(c, '/' | '\\' | ':')) {
+            Ok(module)
+        } else {
+            Err(LibraryInvalid::ModuleNameInvalid { key, module })
+        }
+    }
+
+    fn from_raw(app: &App, raw: LibraryRaw) -> Result<Self, LibraryInvalid> {
+        let host_project_dir = util::normalize_path(app.prefix_path(&raw.host_project_dir))
+            .map_err(|cause| LibraryInvalid::HostProjectDirNormalizationFailed {
+                host_project_dir: raw.host_project_dir.clone(),
+                cause,
+            })?;
+        let module = Self::module_name(
+            "module",
+            raw.module.unwrap_or_else(|| {
+                log::info!(
+                    "`{}.library.module` not set; defaulting to {:?}",
+                    super::NAME,
+                    DEFAULT_LIBRARY_MODULE
+                );
+                DEFAULT_LIBRARY_MODULE.to_owned()
+            }),
+        )?;
+        let app_module = Self::module_name(
+            "app-module",
+            raw.app_module.unwrap_or_else(|| {
+                log::info!(
+                    "`{}.library.app-module` not set; defaulting to {:?}",
+                    super::NAME,
+                    DEFAULT_LIBRARY_APP_MODULE
+                );
+                DEFAULT_LIBRARY_APP_MODULE.to_owned()
+            }),
+        )?;
+        let app_id = raw.app_id.unwrap_or_else(|| {
+            let app_id = format!("{}.{}", app.reverse_domain(), app.names().identifier());
+            log::info!(
+                "`{}.library.app-id` not set; defaulting to {:?}",
+                super::NAME,
+                app_id
+            );
+            app_id
+        });
+        Ok(Self {
+            host_project_dir,
+            module,
+            app_module,
+            app_id,
+        })
+    }
+
+    pub fn host_project_dir(&self) -> &Path {
+        &self.host_project_dir
+    }
+
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    pub fn module_dir(&self) -> PathBuf {
+        self.host_project_dir.join(&self.module)
+    }
+
+    pub fn app_module(&self) -> &str {
+        &self.app_module
+    }
+
+    pub fn app_module_dir(&self) -> PathBuf {
+        self.host_project_dir.join(&self.app_module)
+    }
+
+    pub fn app_id(&self) -> &str {
+        &self.app_id
+    }
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -119,6 +366,31 @@ pub struct Config {
     min_sdk_version: u32,
     vulkan_validation: bool,
     project_dir: PathBuf,
+    debug: BuildType,
+    release: BuildType,
+    library: Option<Library>,
+    #[serde(skip_serializing)]
+    hooks: Hooks,
+    #[serde(skip_serializing)]
+    size_policy: sizes::Policy,
+    #[serde(skip_serializing)]
+    no_default_features: Option<bool>,
+    #[serde(skip_serializing)]
+    features: Option<Vec<String>>,
+    #[serde(skip_serializing)]
+    env: BTreeMap<String, String>,
+    #[serde(skip_serializing)]
+    cli_features: Vec<String>,
+    #[serde(skip_serializing)]
+    variant: Option<Variant>,
+    #[serde(skip_serializing)]
+    cargo_config: bool,
+    #[serde(skip_serializing)]
+    rustflags: RustFlags,
+    #[serde(skip_serializing)]
+    gradle_heap: resources::Setting,
+    #[serde(skip_serializing)]
+    entry_symbols: Vec<String>,
 }
 
 impl Config {
@@ -180,11 +452,73 @@ impl Config {
             Ok(DEFAULT_PROJECT_DIR.into())
         }?;
 
+        let debug = BuildType::from_raw(
+            "debug",
+            raw.debug,
+            DEFAULT_DEBUG_DEBUGGABLE,
+            DEFAULT_DEBUG_PROFILEABLE,
+        );
+        let release = BuildType::from_raw(
+            "release",
+            raw.release,
+            DEFAULT_RELEASE_DEBUGGABLE,
+            DEFAULT_RELEASE_PROFILEABLE,
+        );
+
+        let library = raw
+            .library
+            .map(|library| Library::from_raw(&app, library))
+            .transpose()
+            .map_err(Error::LibraryInvalid)?;
+
+        let cargo_config = raw.cargo_config.unwrap_or_else(|| {
+            log::info!(
+                "`{}.cargo-config` not set; defaulting to {}",
+                super::NAME,
+                DEFAULT_CARGO_CONFIG
+            );
+            DEFAULT_CARGO_CONFIG
+        });
+
+        let rustflags = RustFlags::from_raw(
+            super::NAME,
+            rustflags::Raw {
+                rustflags: raw.rustflags,
+                link_args: raw.link_args,
+            },
+            raw.target,
+            |triple| triple.ends_with("-android") || triple.ends_with("-androideabi"),
+        )
+        .map_err(Error::RustflagsInvalid)?;
+
+        // A host project starts the app however it likes.
+        let entry_symbols = raw.entry_symbols.unwrap_or_else(|| {
+            if library.is_some() {
+                Vec::new()
+            } else {
+                vec![entry_point::ANDROID_DEFAULT.to_owned()]
+            }
+        });
+
         Ok(Self {
             app,
             min_sdk_version,
             vulkan_validation,
             project_dir,
+            debug,
+            release,
+            library,
+            hooks: Hooks::from_raw(raw.hooks),
+            size_policy: sizes::Policy::from_raw(raw.size_report),
+            no_default_features: raw.no_default_features,
+            features: raw.features,
+            env: raw.env.unwrap_or_default(),
+            cli_features: Vec::new(),
+            variant: None,
+            cargo_config,
+            rustflags,
+            gradle_heap: raw.gradle_heap_mib.unwrap_or_default(),
+            entry_symbols,
         })
     }
 
@@ -193,20 +527,136 @@ impl Config {
     }
 
     pub fn so_name(&self) -> String {
-        format!("lib{}.so", self.app().name_snake())
+        format!("lib{}.so", self.app().names().lib_name())
     }
 
     pub fn min_sdk_version(&self) -> u32 {
         self.min_sdk_version
     }
 
+    pub fn library(&self) -> Option<&Library> {
+        self.library.as_ref()
+    }
+
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    pub fn size_policy(&self) -> sizes::Policy {
+        self.size_policy
+    }
+
+    /// Whether `cargo mobile init` adds our linker settings to the app's
+    /// `.cargo/config.toml`, so plain `cargo build --target <triple>` works.
+    pub fn cargo_config(&self) -> bool {
+        self.cargo_config
+    }
+
+    /// The Gradle daemon's heap, in MiB.
+    pub fn gradle_heap(&self) -> resources::Setting {
+        self.gradle_heap
+    }
+
+    /// What the built lib has to export for the app to start.
+    pub fn entry_symbols(&self) -> &[String] {
+        &self.entry_symbols
+    }
+
+    /// Extra env vars for cargo, hooks, and Gradle.
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    /// Sets an env var on top of the config's, like the dev-server port
+    /// `run --port` picked.
+    pub fn with_env_var(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Extra flags for rustc, from `rustflags`, `link-args`, and
+    /// `target.<triple>`.
+    pub fn rustflags(&self) -> &RustFlags {
+        &self.rustflags
+    }
+
+    /// Features passed with `--features` (along with the variant's), which
+    /// Gradle needs to hand back to us when it calls us to build the libs.
+    pub fn cli_features(&self) -> &[String] {
+        &self.cli_features
+    }
+
+    pub fn with_cli_features(mut self, features: Vec<String>) -> Self {
+        self.cli_features = features;
+        self
+    }
+
+    pub fn variant(&self) -> Option<&Variant> {
+        self.variant.as_ref()
+    }
+
+    /// Overlays the variant's env and features. This has to come after
+    /// [`Self::with_cli_features`].
+    pub fn with_variant(mut self, variant: Option<Variant>) -> Self {
+        if let Some(variant) = &variant {
+            self.env.extend(variant.env().clone());
+            for feature in variant.features() {
+                if !self.cli_features.contains(feature) {
+                    self.cli_features.push(feature.clone());
+                }
+            }
+        }
+        self.variant = variant;
+        self
+    }
+
+    /// The app's build config with the variant's overlaid.
+    pub fn build_config(&self) -> BuildConfig {
+        self.app.build_config().with_variant(self.variant())
+    }
+
+    /// The application ID the app gets installed as, which for generated
+    /// projects includes the variant's suffix.
+    pub fn app_id(&self) -> String {
+        if let Some(library) = self.library() {
+            library.app_id().to_owned()
+        } else {
+            format!(
+                "{}.{}{}",
+                self.app.reverse_domain(),
+                self.app.names().identifier(),
+                self.variant()
+                    .map(Variant::identifier_suffix)
+                    .unwrap_or_default()
+            )
+        }
+    }
+
+    /// The root of the Gradle project, which is where `gradlew` lives.
     pub fn project_dir(&self) -> PathBuf {
-        self.app
-            .prefix_path(&self.project_dir)
-            .join(self.app().name())
+        if let Some(library) = self.library() {
+            library.host_project_dir().to_owned()
+        } else {
+            self.app
+                .prefix_path(&self.project_dir)
+                .join(self.app().name())
+        }
+    }
+
+    /// The Gradle module that the Rust lib gets packaged into.
+    pub fn module_dir(&self) -> PathBuf {
+        if let Some(library) = self.library() {
+            library.module_dir()
+        } else {
+            self.project_dir().join("app")
+        }
     }
 
     pub fn project_dir_exists(&self) -> bool {
-        self.project_dir().is_dir()
+        if self.library().is_some() {
+            self.module_dir().is_dir()
+        } else {
+            self.project_dir().is_dir()
+        }
     }
 }
@@ -1,54 +1,82 @@
 use super::{
     adb,
-    config::Config,
+    apk::{self, ApkBuildError},
+    config::{Config, Metadata},
     env::Env,
-    jnilibs::{self, JniLibs},
-    target::{BuildError, Target},
+    jnilibs,
+    target::{HookError, Target},
 };
 use crate::{
+    crash::{self, Crashed},
+    device::{self, Kind, Listing, State, Transport},
     env::ExplicitEnv as _,
-    opts::{NoiseLevel, Profile},
+    hooks::Stage,
+    opts::{Force, NoCrashCheck, NoHooks, NoiseLevel, Profile, SkipStrip},
+    transfer, ui,
     util::{
         self,
         cli::{Report, Reportable},
+        net,
+        progress::{self, Phase},
+        timings,
+        watchdog::{self, Limits},
     },
 };
-use std::fmt::{self, Display};
-
-fn gradlew(config: &Config, env: &Env) -> bossy::Command {
-    let gradlew_path = config.project_dir().join("gradlew");
-    bossy::Command::pure(&gradlew_path)
-        .with_env_vars(env.explicit_env())
-        .with_arg("--project-dir")
-        .with_arg(config.project_dir())
-}
-
-#[derive(Debug)]
-pub enum ApkBuildError {
-    LibSymlinkCleaningFailed(jnilibs::RemoveBrokenLinksError),
-    LibBuildFailed(BuildError),
-    AssembleFailed(bossy::Error),
-}
-
-impl Reportable for ApkBuildError {
-    fn report(&self) -> Report {
-        match self {
-            Self::LibSymlinkCleaningFailed(err) => err.report(),
-            Self::LibBuildFailed(err) => err.report(),
-            Self::AssembleFailed(err) => Report::error("Failed to assemble APK", err),
-        }
-    }
-}
+use std::{
+    fmt::{self, Display},
+    fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
 
 #[derive(Debug)]
 pub enum ApkInstallError {
+    OutputsFailed(apk::OutputsError),
+    AbisFailed(apk::AbisError),
+    AbiMismatch {
+        path: PathBuf,
+        apk_abis: Vec<String>,
+        device: String,
+        device_abis: Vec<String>,
+        target: &'static str,
+    },
     InstallFailed(bossy::Error),
+    Stalled(watchdog::Stalled),
 }
 
 impl Reportable for ApkInstallError {
     fn report(&self) -> Report {
         match self {
+            Self::OutputsFailed(err) => err.report(),
+            Self::AbisFailed(err) => err.report(),
+            Self::AbiMismatch {
+                path,
+                apk_abis,
+                device,
+                device_abis,
+                target,
+            } => Report::action_request(
+                format!("APK {:?} can't run on {}", path, device),
+                format!(
+                    "The APK only has native libs for {}, but {} can only run {}. You can build an APK for it with `cargo android apk {}`.",
+                    apk_abis.join(", "),
+                    device,
+                    device_abis.join(", "),
+                    target
+                ),
+            ),
             Self::InstallFailed(err) => Report::error("Failed to install APK", err),
+            Self::Stalled(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::OutputsFailed(err) => err.code(),
+            Self::AbisFailed(err) => err.code(),
+            Self::AbiMismatch { .. } => "android.device.apk-install.abi-mismatch",
+            Self::InstallFailed(..) => "android.device.apk-install.install-failed",
+            Self::Stalled(err) => err.code(),
         }
     }
 }
@@ -60,6 +88,8 @@ pub enum RunError {
     StartFailed(bossy::Error),
     WakeScreenFailed(bossy::Error),
     LogcatFailed(bossy::Error),
+    HookFailed(HookError),
+    Crashed(Crashed),
 }
 
 impl Reportable for RunError {
@@ -70,10 +100,166 @@ impl Reportable for RunError {
             Self::StartFailed(err) => Report::error("Failed to start app on device", err),
             Self::WakeScreenFailed(err) => Report::error("Failed to wake device screen", err),
             Self::LogcatFailed(err) => Report::error("Failed to log output", err),
+            Self::HookFailed(err) => err.report(),
+            Self::Crashed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ApkBuildFailed(err) => err.code(),
+            Self::ApkInstallFailed(err) => err.code(),
+            Self::StartFailed(..) => "android.device.run.start-failed",
+            Self::WakeScreenFailed(..) => "android.device.run.wake-screen-failed",
+            Self::LogcatFailed(..) => "android.device.run.logcat-failed",
+            Self::HookFailed(err) => err.code(),
+            Self::Crashed(err) => err.code(),
         }
     }
 }
 
+#[derive(Debug)]
+pub enum ForwardError {
+    ReverseFailed(bossy::Error),
+    ListenFailed(std::io::Error),
+    Unreached { port: u16, device: String },
+}
+
+impl Reportable for ForwardError {
+    fn report(&self) -> Report {
+        match self {
+            Self::ReverseFailed(err) => {
+                Report::error("Failed to forward the port from the device", err)
+            }
+            Self::ListenFailed(err) => {
+                Report::error("Failed to listen on the forwarded port to verify it", err)
+            }
+            Self::Unreached { port, device } => Report::action_request(
+                format!("Nothing came through the forward from {}", device),
+                format!(
+                    "Connecting to port {} on the device didn't reach this machine. The device needs `nc` for this check, which Android 7 and newer have; if it's older, leave off `--verify`.",
+                    port
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReverseFailed(..) => "android.device.forward.reverse-failed",
+            Self::ListenFailed(..) => "android.device.forward.listen-failed",
+            Self::Unreached { .. } => "android.device.forward.unreached",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TransferError {
+    RunAsFailed(bossy::Error),
+    NotDebuggable { app_id: String },
+    NotInstalled { app_id: String, device: String },
+    Match(transfer::Error),
+    RemoteMissing { remote: String },
+    PullFailed { remote: String, cause: bossy::Error },
+    WriteFailed { path: PathBuf, cause: io::Error },
+    ReadFailed { path: PathBuf, cause: io::Error },
+    PushFailed { remote: String, cause: bossy::Error },
+}
+
+impl Reportable for TransferError {
+    fn report(&self) -> Report {
+        match self {
+            Self::RunAsFailed(err) => Report::error("Failed to get at the app's data", err),
+            Self::NotDebuggable { app_id } => Report::action_request(
+                format!("`{}` isn't debuggable, so its data can't be reached", app_id),
+                "Android only lets `run-as` into the data of debuggable apps, which release builds aren't. Install a debug build with `cargo android install` and try again.",
+            ),
+            Self::NotInstalled { app_id, device } => Report::action_request(
+                format!("`{}` isn't installed on {}", app_id, device),
+                "Install it with `cargo android install` and try again.",
+            ),
+            Self::Match(err) => err.report(),
+            Self::RemoteMissing { remote } => Report::error(
+                format!("There's no file at {:?} in the app's data", remote),
+                "Paths are relative to the app's data dir, i.e. `databases/app.db`.",
+            ),
+            Self::PullFailed { remote, cause } => {
+                Report::error(format!("Failed to pull {:?}", remote), cause)
+            }
+            Self::WriteFailed { path, cause } => {
+                Report::error(format!("Failed to write {:?}", path), cause)
+            }
+            Self::ReadFailed { path, cause } => {
+                Report::error(format!("Failed to read {:?}", path), cause)
+            }
+            Self::PushFailed { remote, cause } => {
+                Report::error(format!("Failed to push to {:?}", remote), cause)
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::RunAsFailed(..) => "android.device.transfer.run-as-failed",
+            Self::NotDebuggable { .. } => "android.device.transfer.not-debuggable",
+            Self::NotInstalled { .. } => "android.device.transfer.not-installed",
+            Self::Match(err) => err.code(),
+            Self::RemoteMissing { .. } => "android.device.transfer.remote-missing",
+            Self::PullFailed { .. } => "android.device.transfer.pull-failed",
+            Self::WriteFailed { .. } => "android.device.transfer.write-failed",
+            Self::ReadFailed { .. } => "android.device.transfer.read-failed",
+            Self::PushFailed { .. } => "android.device.transfer.push-failed",
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum RunAsProblem {
+    NotDebuggable,
+    NotInstalled,
+}
+
+// `run-as` says what's wrong in a few different ways across Android versions,
+// i.e. "run-as: package not debuggable: com.example.app" or "run-as: unknown
+// package: com.example.app".
+fn run_as_problem(output: &str) -> Option<RunAsProblem> {
+    if output.contains("not debuggable") {
+        Some(RunAsProblem::NotDebuggable)
+    } else if output.contains("unknown package") || output.contains("is unknown") {
+        Some(RunAsProblem::NotInstalled)
+    } else {
+        None
+    }
+}
+
+// `adb shell` hands its args to the device's shell joined by spaces, so
+// anything with spaces or quotes in it has to be quoted.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// Quotes everything but the wildcards, so the device's shell expands those
+// and nothing else.
+fn glob_quote(pattern: &str) -> String {
+    let mut quoted = String::new();
+    let mut literal = String::new();
+    for c in pattern.chars() {
+        if c == '*' || c == '?' {
+            if !literal.is_empty() {
+                quoted.push_str(&shell_quote(&literal));
+                literal.clear();
+            }
+            quoted.push(c);
+        } else {
+            literal.push(c);
+        }
+    }
+    if !literal.is_empty() {
+        quoted.push_str(&shell_quote(&literal));
+    }
+    quoted
+}
+
 #[derive(Debug)]
 pub enum StacktraceError {
     PipeFailed(util::PipeError),
@@ -85,6 +271,77 @@ impl Reportable for StacktraceError {
             Self::PipeFailed(err) => Report::error("Failed to pipe stacktrace output", err),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::PipeFailed(err) => err.code(),
+        }
+    }
+}
+
+// `pidof` prints nothing (and fails, on newer versions of Android) if the app
+// isn't running. Anything other than pids means it's not there to ask, like on
+// Android 6 and older.
+fn parse_pids(output: &str) -> Option<Vec<u32>> {
+    output
+        .split_whitespace()
+        .map(|pid| pid.parse().ok())
+        .collect()
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct LogLine<'a> {
+    pid: u32,
+    level: &'a str,
+    tag: &'a str,
+    /// Everything from the level on, since the dates, times, and ids don't
+    /// help anybody reading an excerpt.
+    text: &'a str,
+}
+
+fn next_field<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let trimmed = rest.trim_start();
+    let (field, tail) = trimmed.split_at(trimmed.find(char::is_whitespace)?);
+    *rest = tail;
+    Some(field)
+}
+
+// Lines in logcat's `threadtime` format look like this:
+// `10-15 12:00:00.123  4321  4321 E AndroidRuntime: FATAL EXCEPTION: main`
+fn parse_log_line(line: &str) -> Option<LogLine<'_>> {
+    let mut rest = line;
+    let _date = next_field(&mut rest)?;
+    let _time = next_field(&mut rest)?;
+    let pid = next_field(&mut rest)?.parse().ok()?;
+    let _tid = next_field(&mut rest)?;
+    let text = rest.trim();
+    let level = next_field(&mut rest)?;
+    let tag = rest.split(':').next()?.trim();
+    Some(LogLine {
+        pid,
+        level,
+        tag,
+        text,
+    })
+}
+
+// The errors logged by the app's own processes, along with the native crash
+// dumps `debuggerd` logs for them (under its own pid). If we never saw the app
+// running, the best we can do is the Java crash handler's output for it.
+fn crash_excerpt<'a>(log: &'a str, app_id: &str, pids: &[u32]) -> Vec<(&'a str, LogLine<'a>)> {
+    let lines = log
+        .lines()
+        .filter_map(|line| parse_log_line(line).map(|parsed| (line, parsed)))
+        .filter(|(line, parsed)| {
+            (parsed.level == "E" || parsed.level == "F")
+                && (pids.contains(&parsed.pid)
+                    || parsed.tag == "DEBUG"
+                    || (pids.is_empty() && parsed.tag == "AndroidRuntime")
+                    || line.contains(app_id))
+        })
+        .collect::<Vec<_>>();
+    let skip = lines.len().saturating_sub(crash::EXCERPT_LINES);
+    lines.into_iter().skip(skip).collect()
 }
 
 #[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
@@ -92,16 +349,25 @@ pub struct Device<'a> {
     serial_no: String,
     name: String,
     model: String,
+    os_version: String,
     target: &'a Target<'a>,
+    abis: Vec<String>,
 }
 
+// Identical phones have identical names, so everything that could tell them
+// apart is shown along with it.
 impl<'a> Display for Device<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.name)?;
+        write!(f, "{} (", self.name)?;
         if self.model != self.name {
-            write!(f, " ({})", self.model)?;
+            write!(f, "{}, ", self.model)?;
         }
-        Ok(())
+        write!(f, "Android {}, ", self.os_version)?;
+        match self.transport() {
+            Some(transport) => write!(f, "{}", transport)?,
+            None => write!(f, "emulator")?,
+        }
+        write!(f, ", {})", device::short_id(&self.serial_no))
     }
 }
 
@@ -110,69 +376,292 @@ impl<'a> Device<'a> {
         serial_no: String,
         name: String,
         model: String,
+        os_version: String,
         target: &'a Target<'a>,
+        abis: Vec<String>,
     ) -> Self {
         Self {
             serial_no,
             name,
             model,
+            os_version,
             target,
+            abis,
         }
     }
 
+    pub fn serial_no(&self) -> &str {
+        &self.serial_no
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn target(&self) -> &'a Target<'a> {
         self.target
     }
 
+    // Emulators are the only devices `adb` names like this.
+    pub(super) fn kind_for_serial_no(serial_no: &str) -> Kind {
+        if serial_no.starts_with("emulator-") {
+            Kind::Virtual
+        } else {
+            Kind::Physical
+        }
+    }
+
+    // `adb` names devices connected over Wi-Fi after their address, or after
+    // their mDNS service when paired with a code.
+    pub(super) fn transport_for_serial_no(serial_no: &str) -> Option<Transport> {
+        if Self::kind_for_serial_no(serial_no) == Kind::Virtual {
+            None
+        } else if serial_no.contains(':') || serial_no.contains("._adb-tls-connect.") {
+            Some(Transport::Wifi)
+        } else {
+            Some(Transport::Usb)
+        }
+    }
+
+    fn transport(&self) -> Option<Transport> {
+        Self::transport_for_serial_no(&self.serial_no)
+    }
+
+    pub fn listing(&self) -> Listing {
+        Listing {
+            name: if self.model == self.name {
+                self.name.clone()
+            } else {
+                format!("{} ({})", self.name, self.model)
+            },
+            id: self.serial_no.clone(),
+            platform: "android",
+            os_version: Some(self.os_version.clone()),
+            arch: self.target.abi.to_owned(),
+            kind: Self::kind_for_serial_no(&self.serial_no),
+            transport: self.transport(),
+            state: State::Online,
+        }
+    }
+
     fn adb(&self, env: &Env) -> bossy::Command {
         adb::adb(env, &self.serial_no)
     }
 
-    fn build_apk(
+    /// Installs the APK at `apk_path`, as long as it has native libs for an
+    /// ABI the device can run (or none at all).
+    pub fn install(&self, env: &Env, apk_path: &Path) -> Result<(), ApkInstallError> {
+        let apk_abis = apk::abis(apk_path).map_err(ApkInstallError::AbisFailed)?;
+        if !apk_abis.is_empty() && !self.abis.iter().any(|abi| apk_abis.contains(abi)) {
+            return Err(ApkInstallError::AbiMismatch {
+                path: apk_path.to_owned(),
+                apk_abis: apk_abis.into_iter().collect(),
+                device: self.to_string(),
+                device_abis: self.abis.clone(),
+                target: self.target.triple,
+            });
+        }
+        ui::status(format!("Installing {:?} on {}...", apk_path, self));
+        let command = self.adb(env).with_arg("install").with_arg(apk_path);
+        progress::phase(Phase::Install, None, || {
+            watchdog::run_and_wait(command, Limits::INSTALL)
+        })
+        .map_err(|err| err.split(ApkInstallError::InstallFailed, ApkInstallError::Stalled))?;
+        Ok(())
+    }
+
+    /// Installs the APKs that were last built for the device's target.
+    pub fn install_apk(
         &self,
         config: &Config,
         env: &Env,
-        noise_level: NoiseLevel,
         profile: Profile,
-    ) -> Result<(), ApkBuildError> {
-        use heck::CamelCase as _;
-        JniLibs::remove_broken_links(config).map_err(ApkBuildError::LibSymlinkCleaningFailed)?;
-        let flavor = self.target.arch.to_camel_case();
-        let build_ty = profile.as_str().to_camel_case();
-        gradlew(config, env)
-            .with_arg(format!("assemble{}{}", flavor, build_ty))
-            .with_arg(match noise_level {
-                NoiseLevel::Polite => "--warn",
-                NoiseLevel::LoudAndProud => "--info",
-                NoiseLevel::FranklyQuitePedantic => "--debug",
-            })
+    ) -> Result<Vec<PathBuf>, ApkInstallError> {
+        // TODO: how to handle signed APKs?
+        let apk_paths = apk::outputs(config, self.target, profile, apk::Format::Apk)
+            .map_err(ApkInstallError::OutputsFailed)?;
+        for apk_path in &apk_paths {
+            self.install(env, apk_path)?;
+        }
+        Ok(apk_paths)
+    }
+
+    /// Forwards `port` on the device to the same port on this machine, so
+    /// the app can reach a dev server on `localhost`. With `verify`, we then
+    /// connect to it from the device and make sure it came through to us,
+    /// which only works before the dev server's listening on it.
+    pub fn forward(&self, env: &Env, port: u16, verify: bool) -> Result<(), ForwardError> {
+        let spec = format!("tcp:{}", port);
+        ui::status(format!(
+            "Forwarding port {} on {} to this machine...",
+            port, self
+        ));
+        self.adb(env)
+            .with_args(&["reverse", &spec, &spec])
             .run_and_wait()
-            .map_err(ApkBuildError::AssembleFailed)?;
+            .map_err(ForwardError::ReverseFailed)?;
+        if verify {
+            let reached = net::reached(port, || {
+                // Whether `nc` worked doesn't matter, only whether we heard
+                // from it.
+                let _ = self
+                    .adb(env)
+                    .with_args(&["shell", "nc", "-w", "1", "127.0.0.1"])
+                    .with_args(&[port.to_string(), "</dev/null".to_owned()])
+                    .run_and_wait_for_output();
+            })
+            .map_err(ForwardError::ListenFailed)?;
+            if !reached {
+                return Err(ForwardError::Unreached {
+                    port,
+                    device: self.to_string(),
+                });
+            }
+            log::info!("verified that port {} on {} reaches us", port, self);
+        }
+        Ok(())
+    }
+
+    // Runs `args` as the app, which is the only way into its data dir, and
+    // starts there.
+    fn run_as(&self, env: &Env, app_id: &str, args: &[&str]) -> bossy::Command {
+        self.adb(env)
+            .with_args(&["shell", "run-as", app_id])
+            .with_args(args)
+    }
+
+    fn check_run_as(&self, env: &Env, app_id: &str) -> Result<(), TransferError> {
+        let result = self
+            .run_as(env, app_id, &["true"])
+            .run_and_wait_for_output();
+        let output = match &result {
+            Ok(output) => Some(output),
+            Err(err) => err.output(),
+        }
+        .map(|output| {
+            format!(
+                "{}{}",
+                String::from_utf8_lossy(output.stdout()),
+                String::from_utf8_lossy(output.stderr())
+            )
+        })
+        .unwrap_or_default();
+        match run_as_problem(&output) {
+            Some(RunAsProblem::NotDebuggable) => Err(TransferError::NotDebuggable {
+                app_id: app_id.to_owned(),
+            }),
+            Some(RunAsProblem::NotInstalled) => Err(TransferError::NotInstalled {
+                app_id: app_id.to_owned(),
+                device: self.to_string(),
+            }),
+            None => result.map(|_| ()).map_err(TransferError::RunAsFailed),
+        }
+    }
+
+    // The file `remote` names, if it's a wildcard that matches exactly one.
+    fn resolve_remote(
+        &self,
+        env: &Env,
+        app_id: &str,
+        remote: &str,
+    ) -> Result<String, TransferError> {
+        if !transfer::has_wildcard(remote) {
+            return Ok(remote.to_owned());
+        }
+        let script = format!("ls -d -- {}", glob_quote(remote));
+        // `ls` fails when nothing matches, which is the same as an empty
+        // list to us.
+        let matches = self
+            .run_as(env, app_id, &["sh", "-c", &shell_quote(&script)])
+            .run_and_wait_for_str(|output| {
+                output
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        transfer::only_match(remote, matches).map_err(TransferError::Match)
+    }
+
+    /// Copies `remote`, relative to the app's data dir, to `local`. The app
+    /// has to be debuggable, since that's what `run-as` needs.
+    pub fn pull(
+        &self,
+        config: &Config,
+        env: &Env,
+        remote: &str,
+        local: Option<&Path>,
+    ) -> Result<(), TransferError> {
+        let app_id = config.app_id();
+        self.check_run_as(env, &app_id)?;
+        let remote = self.resolve_remote(env, &app_id, remote)?;
+        self.run_as(env, &app_id, &["test", "-f", &shell_quote(&remote)])
+            .run_and_wait_for_output()
+            .map_err(|_| TransferError::RemoteMissing {
+                remote: remote.clone(),
+            })?;
+        // `exec-out` keeps the bytes intact, where `shell` would mangle line
+        // endings on some devices.
+        let output = self
+            .adb(env)
+            .with_args(&["exec-out", "run-as", &app_id, "cat", &shell_quote(&remote)])
+            .run_and_wait_for_output()
+            .map_err(|cause| TransferError::PullFailed {
+                remote: remote.clone(),
+                cause,
+            })?;
+        let path = transfer::local_path(&remote, local);
+        fs::write(&path, output.stdout()).map_err(|cause| TransferError::WriteFailed {
+            path: path.clone(),
+            cause,
+        })?;
+        transfer::report_pulled(&remote, self, &path, output.stdout().len() as u64);
         Ok(())
     }
 
-    fn install_apk(
+    /// Copies `local` to `remote`, relative to the app's data dir, or into
+    /// it if it ends with a `/`.
+    pub fn push(
         &self,
         config: &Config,
         env: &Env,
-        profile: Profile,
-    ) -> Result<(), ApkInstallError> {
-        let flavor = self.target.arch;
-        let build_ty = profile.as_str();
-        let apk_suffix = match profile {
-            Profile::Debug => build_ty,
-            // TODO: how to handle signed APKs?
-            Profile::Release => "release-unsigned",
-        };
-        let apk_path = config.project_dir().join(format!(
-            "app/build/outputs/apk/{}/{}/app-{}-{}.apk",
-            flavor, build_ty, flavor, apk_suffix
-        ));
+        local: &Path,
+        remote: &str,
+    ) -> Result<(), TransferError> {
+        let app_id = config.app_id();
+        self.check_run_as(env, &app_id)?;
+        let contents = fs::read(local).map_err(|cause| TransferError::ReadFailed {
+            path: local.to_owned(),
+            cause,
+        })?;
+        let remote = transfer::remote_path(local, remote);
+        let script = format!("cat > {}", shell_quote(&remote));
         self.adb(env)
-            .with_arg("install")
-            .with_arg(apk_path)
-            .run_and_wait()
-            .map_err(ApkInstallError::InstallFailed)?;
+            .with_args(&[
+                "exec-in",
+                "run-as",
+                &app_id,
+                "sh",
+                "-c",
+                &shell_quote(&script),
+            ])
+            .with_stdin_piped()
+            .run()
+            .and_then(|mut handle| {
+                if let Some(stdin) = handle.stdin() {
+                    if let Err(err) = stdin.write_all(&contents) {
+                        log::warn!("failed to pipe {:?} to {}: {}", local, self, err);
+                    }
+                }
+                handle.wait_for_output()
+            })
+            .map_err(|cause| TransferError::PushFailed {
+                remote: remote.clone(),
+                cause,
+            })?;
+        transfer::report_pushed(local, self, &remote, contents.len() as u64);
         Ok(())
     }
 
@@ -183,27 +672,278 @@ impl<'a> Device<'a> {
         Ok(())
     }
 
-    pub fn run(
+    /// Starts the app that's already installed, using `activity` if it's
+    /// given. Activities starting with `.` are relative to the app ID.
+    pub fn launch(
         &self,
         config: &Config,
         env: &Env,
+        activity: Option<&str>,
+    ) -> Result<(), RunError> {
+        let app_id = config.app_id();
+        match (activity, config.library()) {
+            (Some(activity), _) => {
+                let activity = format!("{}/{}", app_id, activity);
+                self.adb(env)
+                    .with_args(&["shell", "am", "start", "-n", &activity])
+                    .run_and_wait()
+                    .map_err(RunError::StartFailed)?;
+            }
+            // We don't know what the host app's launcher activity is called,
+            // so we let `monkey` find it for us.
+            (None, Some(_)) => {
+                self.adb(env)
+                    .with_args(&["shell", "monkey", "-p", &app_id])
+                    .with_args(&["-c", "android.intent.category.LAUNCHER", "1"])
+                    .run_and_wait()
+                    .map_err(RunError::StartFailed)?;
+            }
+            (None, None) => {
+                let activity = format!("{}/android.app.NativeActivity", app_id);
+                self.adb(env)
+                    .with_args(&["shell", "am", "start", "-n", &activity])
+                    .run_and_wait()
+                    .map_err(RunError::StartFailed)?;
+            }
+        }
+        self.wake_screen(env).map_err(RunError::WakeScreenFailed)
+    }
+
+    // The device's clock, in seconds since the epoch, which `logcat -T` takes
+    // to only print what was logged since then.
+    fn log_time(&self, env: &Env) -> Option<String> {
+        self.adb(env)
+            .with_args(&["shell", "date", "+%s"])
+            .run_and_wait_for_str(|output| output.trim().to_owned())
+            .map_err(|err| log::warn!("failed to get the time on {}: {}", self, err))
+            .ok()
+            .filter(|time| !time.is_empty() && time.chars().all(|c| c.is_ascii_digit()))
+    }
+
+    fn pids(&self, env: &Env, app_id: &str) -> Option<Vec<u32>> {
+        let result = self
+            .adb(env)
+            .with_args(&["shell", "pidof", app_id])
+            .run_and_wait_for_output();
+        let stdout = match &result {
+            Ok(output) => output.stdout(),
+            Err(err) => match err.output() {
+                Some(output) => output.stdout(),
+                None => {
+                    log::warn!("failed to run `pidof` on {}: {}", self, err);
+                    return None;
+                }
+            },
+        };
+        parse_pids(&String::from_utf8_lossy(stdout))
+    }
+
+    fn ndk_stack(&self, config: &Config, env: &Env) -> bossy::Command {
+        bossy::Command::pure("ndk-stack")
+            .with_env_vars(env.explicit_env())
+            .with_env_var(
+                "PATH",
+                util::prepend_to_path(env.ndk.home().display(), env.path()),
+            )
+            .with_arg("-sym")
+            .with_arg({
+                let jnilibs_path = jnilibs::path(config, *self.target);
+                config
+                    .app()
+                    // ndk-stack can't seem to handle spaces in args, no matter
+                    // how I try to quote or escape them... so, instead of
+                    // mandating that the entire path not contain spaces, we'll
+                    // just use a relative path! (Host projects can live outside
+                    // of the app root, in which case we're out of luck.)
+                    .unprefix_path(&jnilibs_path)
+                    .unwrap_or(jnilibs_path)
+            })
+    }
+
+    // Failing to symbolicate is only logged, since the raw crash dump is still
+    // worth showing.
+    fn symbolicate(&self, config: &Config, env: &Env, log: &str) -> Option<String> {
+        let result = self
+            .ndk_stack(config, env)
+            .with_stdin_piped()
+            .with_stdout(bossy::Stdio::piped())
+            .run()
+            .and_then(|mut handle| {
+                if let Some(stdin) = handle.stdin() {
+                    if let Err(err) = stdin.write_all(log.as_bytes()) {
+                        log::warn!("failed to pipe crash dump to `ndk-stack`: {}", err);
+                    }
+                }
+                handle.wait_for_output()
+            });
+        match result {
+            Ok(output) => {
+                let symbolicated = String::from_utf8_lossy(output.stdout()).trim().to_owned();
+                Some(symbolicated).filter(|symbolicated| !symbolicated.is_empty())
+            }
+            Err(err) => {
+                log::warn!("failed to symbolicate crash dump: {}", err);
+                None
+            }
+        }
+    }
+
+    // What the app logged since `log_time`, cut down to the crash, with native
+    // crash dumps symbolicated if `ndk-stack` can manage it.
+    fn crash_log(
+        &self,
+        config: &Config,
+        env: &Env,
+        log_time: Option<&str>,
+        pids: &[u32],
+    ) -> Option<String> {
+        let mut logcat = self
+            .adb(env)
+            .with_args(&["logcat", "-d", "-v", "threadtime"]);
+        if let Some(log_time) = log_time {
+            logcat.add_arg("-T").add_arg(format!("{}.000", log_time));
+        }
+        let log = logcat
+            .run_and_wait_for_str(|output| output.to_owned())
+            .map_err(|err| log::warn!("failed to read logcat on {}: {}", self, err))
+            .ok()?;
+        let lines = crash_excerpt(&log, &config.app_id(), pids);
+        if lines.is_empty() {
+            return None;
+        }
+        let mut excerpt = lines
+            .iter()
+            .map(|(_, parsed)| parsed.text)
+            .collect::<Vec<_>>()
+            .join("\n");
+        if lines.iter().any(|(_, parsed)| parsed.tag == "DEBUG") {
+            let dump = lines
+                .iter()
+                .filter(|(_, parsed)| parsed.tag == "DEBUG")
+                .map(|(line, _)| *line)
+                .collect::<Vec<_>>()
+                .join("\n");
+            if let Some(symbolicated) = self.symbolicate(config, env, &dump) {
+                excerpt.push_str("\n\nSymbolicated by `ndk-stack`:\n\n");
+                excerpt.push_str(&symbolicated);
+            }
+        }
+        Some(excerpt)
+    }
+
+    /// Watches the app that was just launched for a few seconds, and returns
+    /// what it logged on the way down if it died in the meantime.
+    /// `log_time` is from before it was launched, so that older crashes
+    /// aren't mixed in.
+    fn check_crash(
+        &self,
+        config: &Config,
+        env: &Env,
+        log_time: Option<&str>,
+    ) -> Result<(), Crashed> {
+        let app_id = config.app_id();
+        let mut seen = Vec::new();
+        let died = crash::died(|| {
+            self.pids(env, &app_id).map(|pids| {
+                for pid in &pids {
+                    if !seen.contains(pid) {
+                        seen.push(*pid);
+                    }
+                }
+                !pids.is_empty()
+            })
+        });
+        if died {
+            Err(Crashed::new(self.to_string())
+                .with_excerpt(self.crash_log(config, env, log_time, &seen))
+                .with_hint(Some(
+                    "`cargo android st` prints the full stacktrace of a native crash.".to_owned(),
+                )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Builds, installs, and starts the app, and then makes sure it doesn't
+    /// crash right away unless `no_crash_check` is set.
+    pub fn deploy(
+        &self,
+        config: &Config,
+        metadata: &Metadata,
+        env: &Env,
         noise_level: NoiseLevel,
         profile: Profile,
+        skip_strip: SkipStrip,
+        force: Force,
+        no_hooks: NoHooks,
+        no_crash_check: NoCrashCheck,
     ) -> Result<(), RunError> {
-        self.build_apk(config, env, noise_level, profile)
-            .map_err(RunError::ApkBuildFailed)?;
-        self.install_apk(config, env, profile)
+        apk::build(
+            config,
+            metadata,
+            env,
+            self.target,
+            noise_level,
+            profile,
+            skip_strip,
+            force,
+            no_hooks,
+        )
+        .map_err(RunError::ApkBuildFailed)?;
+        self.target
+            .run_hooks(config, env, Stage::PreRun, profile, no_hooks, None)
+            .map_err(RunError::HookFailed)?;
+        let apk_paths = self
+            .install_apk(config, env, profile)
             .map_err(RunError::ApkInstallFailed)?;
-        let activity = format!(
-            "{}.{}/android.app.NativeActivity",
-            config.app().reverse_domain(),
-            config.app().name_snake(),
-        );
-        self.adb(env)
-            .with_args(&["shell", "am", "start", "-n", &activity])
-            .run_and_wait()
-            .map_err(RunError::StartFailed)?;
-        self.wake_screen(env).map_err(RunError::WakeScreenFailed)?;
+        let log_time = if no_crash_check.no() {
+            self.log_time(env)
+        } else {
+            None
+        };
+        progress::phase(Phase::Launch, None, || self.launch(config, env, None))?;
+        if no_crash_check.no() {
+            self.check_crash(config, env, log_time.as_deref())
+                .map_err(RunError::Crashed)?;
+        }
+        for apk_path in &apk_paths {
+            self.target
+                .run_hooks(
+                    config,
+                    env,
+                    Stage::PostRun,
+                    profile,
+                    no_hooks,
+                    Some(apk_path),
+                )
+                .map_err(RunError::HookFailed)?;
+        }
+        Ok(())
+    }
+
+    pub fn run(
+        &self,
+        config: &Config,
+        metadata: &Metadata,
+        env: &Env,
+        noise_level: NoiseLevel,
+        profile: Profile,
+        skip_strip: SkipStrip,
+        force: Force,
+        no_hooks: NoHooks,
+        no_crash_check: NoCrashCheck,
+    ) -> Result<(), RunError> {
+        self.deploy(
+            config,
+            metadata,
+            env,
+            noise_level,
+            profile,
+            skip_strip,
+            force,
+            no_hooks,
+            no_crash_check,
+        )?;
         let filter = format!(
             "{}:{}",
             config.app().name(),
@@ -213,6 +953,7 @@ impl<'a> Device<'a> {
                 NoiseLevel::FranklyQuitePedantic => "V",
             },
         );
+        timings::print_summary();
         adb::adb(env, &self.serial_no)
             .with_args(&["logcat", "-v", "color", "-s", &filter])
             .run_and_wait()
@@ -223,26 +964,111 @@ impl<'a> Device<'a> {
     pub fn stacktrace(&self, config: &Config, env: &Env) -> Result<(), StacktraceError> {
         // -d = print and exit
         let logcat_command = adb::adb(env, &self.serial_no).with_args(&["logcat", "-d"]);
-        let stack_command = bossy::Command::pure("ndk-stack")
-            .with_env_vars(env.explicit_env())
-            .with_env_var(
-                "PATH",
-                util::prepend_to_path(env.ndk.home().display(), env.path()),
-            )
-            .with_arg("-sym")
-            .with_arg(
-                config
-                    .app()
-                    // ndk-stack can't seem to handle spaces in args, no matter
-                    // how I try to quote or escape them... so, instead of
-                    // mandating that the entire path not contain spaces, we'll
-                    // just use a relative path!
-                    .unprefix_path(jnilibs::path(config, *self.target))
-                    .expect("developer error: jnilibs subdir not prefixed"),
-            );
-        if !util::pipe(logcat_command, stack_command).map_err(StacktraceError::PipeFailed)? {
-            println!("  -- no stacktrace --");
+        let stack_command = self.ndk_stack(config, env);
+        if !util::pipe(logcat_command, stack_command)
+            .map_err(StacktraceError::PipeFailed)?
+            .piped()
+        {
+            ui::status(format!("  -- no stacktrace --"));
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        output,
+        expected,
+        case("4321\n", Some(vec![4321])),
+        case("4321 4350\n", Some(vec![4321, 4350])),
+        case("", Some(vec![])),
+        case("/system/bin/sh: pidof: not found\n", None)
+    )]
+    fn test_parse_pids(output: &str, expected: Option<Vec<u32>>) {
+        assert_eq!(parse_pids(output), expected);
+    }
+
+    #[rstest(
+        line,
+        expected,
+        case(
+            "10-15 12:00:00.123  4321  4321 E AndroidRuntime: FATAL EXCEPTION: main",
+            Some(LogLine { pid: 4321, level: "E", tag: "AndroidRuntime", text: "E AndroidRuntime: FATAL EXCEPTION: main" })
+        ),
+        case(
+            "10-15 12:00:00.456  4400  4400 F DEBUG   : pid: 4321, tid: 4339, name: main  >>> com.example.app <<<",
+            Some(LogLine { pid: 4400, level: "F", tag: "DEBUG", text: "F DEBUG   : pid: 4321, tid: 4339, name: main  >>> com.example.app <<<" })
+        ),
+        case("--------- beginning of crash", None)
+    )]
+    fn test_parse_log_line(line: &str, expected: Option<LogLine<'_>>) {
+        assert_eq!(parse_log_line(line), expected);
+    }
+
+    static LOG: &str = "--------- beginning of main
+10-15 12:00:00.100  4321  4339 I my-app  : starting up
+10-15 12:00:00.110  4321  4339 E my-app  : thread '<unnamed>' panicked at 'oh no', src/lib.rs:12:5
+10-15 12:00:00.120   999   999 E SomethingElse: unrelated
+10-15 12:00:00.130  4321  4339 F libc    : Fatal signal 6 (SIGABRT), code -1 (SI_QUEUE) in tid 4339 (main), pid 4321 (com.example.app)
+10-15 12:00:00.200  4400  4400 F DEBUG   : *** *** *** *** *** *** *** *** *** *** *** *** *** *** *** ***
+10-15 12:00:00.210  4400  4400 F DEBUG   : pid: 4321, tid: 4339, name: main  >>> com.example.app <<<
+10-15 12:00:00.220   512   530 E ActivityManager: Process com.example.app (pid 4321) has died
+";
+
+    #[test]
+    fn test_crash_excerpt() {
+        let texts = |pids: &[u32]| {
+            crash_excerpt(LOG, "com.example.app", pids)
+                .into_iter()
+                .map(|(_, parsed)| parsed.text)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            texts(&[4321]),
+            vec![
+                "E my-app  : thread '<unnamed>' panicked at 'oh no', src/lib.rs:12:5",
+                "F libc    : Fatal signal 6 (SIGABRT), code -1 (SI_QUEUE) in tid 4339 (main), pid 4321 (com.example.app)",
+                "F DEBUG   : *** *** *** *** *** *** *** *** *** *** *** *** *** *** *** ***",
+                "F DEBUG   : pid: 4321, tid: 4339, name: main  >>> com.example.app <<<",
+                "E ActivityManager: Process com.example.app (pid 4321) has died",
+            ]
+        );
+        // Without the pid, the panic can't be told apart from anybody else's.
+        assert_eq!(texts(&[]).len(), 4);
+    }
+    #[rstest(
+        output,
+        expected,
+        case(
+            "run-as: package not debuggable: com.example.app\n",
+            Some(RunAsProblem::NotDebuggable)
+        ),
+        case(
+            "run-as: unknown package: com.example.app\n",
+            Some(RunAsProblem::NotInstalled)
+        ),
+        case(
+            "run-as: package 'com.example.app' is unknown\n",
+            Some(RunAsProblem::NotInstalled)
+        ),
+        case("", None)
+    )]
+    fn test_run_as_problem(output: &str, expected: Option<RunAsProblem>) {
+        assert_eq!(run_as_problem(output), expected);
+    }
+
+    #[rstest(
+        pattern,
+        expected,
+        case("databases/app.db", "'databases/app.db'"),
+        case("files/*.db", "'files/'*'.db'"),
+        case("it's/?", r"'it'\''s/'?")
+    )]
+    fn test_glob_quote(pattern: &str, expected: &str) {
+        assert_eq!(glob_quote(pattern), expected);
+    }
+}
@@ -0,0 +1,388 @@
+use super::{
+    adb,
+    env::{self, Env},
+    jdk, ndk,
+    target::Target,
+};
+use crate::{
+    config::Config,
+    doctor::{Fix, Item, Section, SectionName, Stage},
+    os,
+    target::TargetTrait as _,
+    util,
+};
+use once_cell_regex::regex;
+use std::path::{Path, PathBuf};
+
+// What `cargo mobile setup` creates an AVD with, if there are no devices.
+static AVD_NAME: &str = "cargo-mobile";
+const AVD_API_LEVEL: u32 = 30;
+
+// The SDK's command-line tools are rarely on the `PATH`, so we look for them
+// where the SDK Manager installs them, and fall back to the `PATH` otherwise.
+fn sdk_tool(sdk_root: Option<&Path>, name: &str) -> String {
+    sdk_root
+        .into_iter()
+        .flat_map(|sdk_root| {
+            vec![
+                sdk_root.join("cmdline-tools/latest/bin").join(name),
+                sdk_root.join("tools/bin").join(name),
+            ]
+        })
+        .find(|path| path.is_file())
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| name.to_owned())
+}
+
+// The emulator's much faster when its system image matches the host.
+fn system_image() -> String {
+    format!(
+        "system-images;android-{};google_apis;{}",
+        AVD_API_LEVEL,
+        if cfg!(target_arch = "aarch64") {
+            "arm64-v8a"
+        } else {
+            "x86_64"
+        }
+    )
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn check_sdk(section: &mut Section) -> Option<PathBuf> {
+    match env::sdk_root() {
+        Ok(sdk_root) => {
+            section.push(Item::pass(format!("SDK found at {:?}", sdk_root)));
+            Some(sdk_root)
+        }
+        Err(err) => {
+            section.push(Item::fail(err).with_hint(
+                "Install the Android SDK (e.g. via Android Studio's SDK Manager), then `export ANDROID_SDK_ROOT=<path/to/sdk>`",
+            ).with_docs("https://developer.android.com/studio").with_fix(Fix::manual(Stage::Android)));
+            None
+        }
+    }
+}
+
+fn check_sdk_vars(section: &mut Section) {
+    let sdk_root = std::env::var_os("ANDROID_SDK_ROOT").map(PathBuf::from);
+    let android_home = std::env::var_os("ANDROID_HOME").map(PathBuf::from);
+    match (sdk_root, android_home) {
+        (Some(sdk_root), Some(android_home)) => {
+            if same_dir(&sdk_root, &android_home) {
+                section.push(Item::pass(
+                    "`ANDROID_SDK_ROOT` and `ANDROID_HOME` point to the same directory",
+                ));
+            } else {
+                section.push(
+                    Item::warn(format!(
+                        "`ANDROID_SDK_ROOT` ({:?}) and `ANDROID_HOME` ({:?}) point to different directories; `ANDROID_SDK_ROOT` will be used",
+                        sdk_root, android_home
+                    ))
+                    .with_hint("`export ANDROID_HOME=\"$ANDROID_SDK_ROOT\"`"),
+                );
+            }
+        }
+        (None, Some(_)) => section.push(
+            Item::warn("Only `ANDROID_HOME` is set, which is deprecated")
+                .with_hint("`export ANDROID_SDK_ROOT=\"$ANDROID_HOME\"`"),
+        ),
+        // Either everything is fine, or `check_sdk` already complained.
+        _ => (),
+    }
+}
+
+// Nothing can be installed with `sdkmanager` until this is done, Gradle
+// included.
+fn check_licenses(section: &mut Section, sdk_root: Option<&Path>) {
+    let sdk_root = match sdk_root {
+        Some(sdk_root) => sdk_root,
+        None => return,
+    };
+    if sdk_root.join("licenses/android-sdk-license").is_file() {
+        section.push(Item::pass("The Android SDK license has been accepted"));
+    } else {
+        let sdkmanager = sdk_tool(Some(sdk_root), "sdkmanager");
+        section.push(
+            Item::fail("The Android SDK license hasn't been accepted, so nothing can be installed")
+                .with_hint(format!("`{} --licenses`", sdkmanager))
+                .with_fix(Fix::run(
+                    Stage::Android,
+                    &[sdkmanager.as_str(), "--licenses"],
+                )),
+        );
+    }
+}
+
+fn check_adb(section: &mut Section, sdk_root: Option<&Path>) {
+    let sdk_adb = sdk_root.map(|sdk_root| sdk_root.join("platform-tools/adb"));
+    // `adb` is always invoked via `PATH`, so that's what we check here.
+    let adb_path = os::command_path("adb")
+        .ok()
+        .and_then(|output| {
+            output
+                .stdout_str()
+                .ok()
+                .map(|path| PathBuf::from(path.trim()))
+        })
+        .filter(|path| !path.as_os_str().is_empty());
+    match adb_path {
+        Some(adb_path) => {
+            let version = bossy::Command::impure("adb")
+                .with_arg("version")
+                .run_and_wait_for_str(|output| {
+                    regex!(r"Android Debug Bridge version ([\d.]+)")
+                        .captures(output)
+                        .map(|caps| caps[1].to_owned())
+                })
+                .ok()
+                .flatten();
+            section.push(Item::pass(format!(
+                "adb {} found at {:?}",
+                version.as_deref().unwrap_or("(unknown version)"),
+                adb_path
+            )));
+            if let Some(sdk_adb) = sdk_adb.filter(|sdk_adb| !same_dir(sdk_adb, &adb_path)) {
+                section.push(
+                    Item::warn(format!(
+                        "The `adb` on your `PATH` isn't the one from your SDK ({:?})",
+                        sdk_adb
+                    ))
+                    .with_hint("`export PATH=\"$ANDROID_SDK_ROOT/platform-tools:$PATH\"`"),
+                );
+            }
+        }
+        None => {
+            let (hint, fix) = if sdk_adb.map(|sdk_adb| sdk_adb.is_file()).unwrap_or_default() {
+                (
+                    "`export PATH=\"$ANDROID_SDK_ROOT/platform-tools:$PATH\"`",
+                    Fix::manual(Stage::Android),
+                )
+            } else {
+                (
+                    "`sdkmanager \"platform-tools\"`, then `export PATH=\"$ANDROID_SDK_ROOT/platform-tools:$PATH\"`",
+                    Fix::run(
+                        Stage::Android,
+                        &[sdk_tool(sdk_root, "sdkmanager").as_str(), "platform-tools"],
+                    ),
+                )
+            };
+            section.push(
+                Item::fail("`adb` wasn't found on your `PATH`")
+                    .with_hint(hint)
+                    .with_fix(fix),
+            );
+        }
+    }
+}
+
+fn check_ndk(section: &mut Section, sdk_root: Option<&Path>) {
+    match ndk::Env::new() {
+        Ok(ndk) => section.push(Item::pass(format!(
+            "NDK {} found at {:?}",
+            ndk.version()
+                .map(|version| version.to_string())
+                .unwrap_or_else(|_| "(unknown version)".to_owned()),
+            ndk.home(),
+        ))),
+        Err(err) => {
+            let sdkmanager = sdk_tool(sdk_root, "sdkmanager");
+            let install = Fix::run(Stage::Android, &[sdkmanager.as_str(), "ndk-bundle"]);
+            let (hint, fix) = match &err {
+                ndk::Error::NdkHomeNotSet(_) | ndk::Error::NdkHomeNotADir => {
+                    ("`sdkmanager \"ndk-bundle\"`, then `export NDK_HOME=\"$ANDROID_SDK_ROOT/ndk-bundle\"`", install)
+                }
+                ndk::Error::VersionTooLow { .. } => {
+                    ("`sdkmanager \"ndk-bundle\"` to update to the latest NDK", install)
+                }
+                _ => (
+                    "`sdkmanager --uninstall \"ndk-bundle\" && sdkmanager \"ndk-bundle\"` to reinstall the NDK",
+                    Fix::run(Stage::Android, &[sdkmanager.as_str(), "--uninstall", "ndk-bundle"])
+                        .then(&[sdkmanager.as_str(), "ndk-bundle"]),
+                ),
+            };
+            section.push(
+                Item::fail(err)
+                    .with_hint(hint)
+                    .with_docs("https://developer.android.com/studio/projects/install-ndk")
+                    .with_fix(fix),
+            );
+        }
+    }
+}
+
+fn check_rust_targets(section: &mut Section) {
+    match util::installed_targets() {
+        Ok(installed) => {
+            let missing = Target::all()
+                .values()
+                .map(|target| target.triple())
+                .filter(|triple| !installed.iter().any(|installed| installed == *triple))
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                section.push(Item::pass("All Android Rust targets are installed"));
+            } else {
+                section.push(
+                    Item::fail(format!(
+                        "Missing Rust targets {}",
+                        util::list_display(&missing[..])
+                    ))
+                    .with_hint(format!("`rustup target add {}`", missing.join(" ")))
+                    .with_fix(Fix::run(
+                        Stage::Rust,
+                        ["rustup", "target", "add"].iter().chain(&missing),
+                    )),
+                );
+            }
+        }
+        Err(err) => section.push(
+            Item::fail(format!("Failed to list installed Rust targets: {}", err))
+                .with_hint("Install `rustup` from https://rustup.rs"),
+        ),
+    }
+}
+
+fn check_jdk(section: &mut Section) {
+    // Builds use the Gradle the project's pinned to, so that's what matters
+    // when run in one.
+    let project_dir = Config::check(".")
+        .ok()
+        .map(|config| config.android().project_dir())
+        .filter(|project_dir| project_dir.is_dir());
+    let requirement = project_dir
+        .as_deref()
+        .map(jdk::Requirement::for_project)
+        .unwrap_or_else(jdk::Requirement::pinned);
+    let selection = jdk::select(project_dir.as_deref(), &requirement);
+    let msg = selection.describe(&requirement);
+    match &selection {
+        jdk::Selection::Default(_) => section.push(Item::pass(msg)),
+        jdk::Selection::Found { jdk, .. } => section.push(
+            Item::warn(msg).with_hint(format!(
+                "Builds will point Gradle at it automatically; to use it everywhere, set `JAVA_HOME` to {:?}",
+                jdk.home().expect("developer error: found a JDK without a home")
+            )),
+        ),
+        jdk::Selection::NotFound { .. } => section.push(
+            Item::fail(msg)
+                .with_hint(requirement.install_hint())
+                .with_fix(Fix::manual(Stage::Android)),
+        ),
+    }
+}
+
+fn check_devices(section: &mut Section, sdk_root: Option<&Path>) {
+    let devices = Env::new().ok().and_then(|env| {
+        adb::device_list(&env)
+            .ok()
+            .map(|list| list.iter().map(ToString::to_string).collect::<Vec<_>>())
+    });
+    let avds = sdk_root.map(adb::device_list::avds).unwrap_or_default();
+    match devices {
+        Some(devices) if !devices.is_empty() => section.push(Item::pass(format!(
+            "Connected devices: {}",
+            util::list_display(&devices[..])
+        ))),
+        _ if !avds.is_empty() => section.push(Item::pass(format!(
+            "No devices connected, but found AVDs {}",
+            util::list_display(&avds[..])
+        ))),
+        _ => {
+            let image = system_image();
+            section.push(
+                Item::warn("No connected devices or AVDs were found")
+                    .with_hint(format!("Connect a device with USB debugging enabled, or create an AVD with `sdkmanager \"{}\" && avdmanager create avd -n {} -k \"{}\"`", image, AVD_NAME, image))
+                    .with_fix(
+                        Fix::run(Stage::Devices, &[sdk_tool(sdk_root, "sdkmanager").as_str(), &image])
+                            .then(&[
+                                sdk_tool(sdk_root, "avdmanager").as_str(),
+                                "create",
+                                "avd",
+                                "-n",
+                                AVD_NAME,
+                                "-k",
+                                &image,
+                            ]),
+                    ),
+            )
+        }
+    }
+}
+
+// Cargo resolves linkers with a slash in them relative to the directory the
+// `.cargo` directory is in, and looks up the rest on the `PATH`.
+fn resolve_linker(linker: &str, config_path: &Path) -> Option<PathBuf> {
+    if linker.contains('/') {
+        let base = config_path.parent().and_then(Path::parent)?;
+        Some(base.join(linker))
+    } else {
+        None
+    }
+}
+
+// Only checked when run in a project, since that's where the cargo config
+// that matters is.
+fn check_cargo_config(section: &mut Section) {
+    let (config, env) = match (Config::check("."), Env::new()) {
+        (Ok(config), Ok(env)) => (config, env),
+        _ => return,
+    };
+    let mut configured_any = false;
+    let mut disagreements = false;
+    for target in Target::all().values() {
+        let (ours, configured) = match target.configured_linker(config.android(), &env) {
+            Ok(linkers) => linkers,
+            // `check_ndk` already complained.
+            Err(_) => return,
+        };
+        let (linker, path) = match configured {
+            Some(configured) => configured,
+            None => continue,
+        };
+        configured_any = true;
+        let resolved = resolve_linker(&linker, &path);
+        if let Some(resolved) = resolved.as_ref().filter(|resolved| !resolved.is_file()) {
+            disagreements = true;
+            section.push(
+                Item::fail(format!(
+                    "{:?} sets the `{}` linker to {:?}, which doesn't exist",
+                    path, target.triple, resolved
+                ))
+                .with_hint("The NDK might've moved; remove the entry and run `cargo android config-gen` to regenerate it"),
+            );
+        } else if !resolved.map_or(false, |resolved| same_dir(&resolved, &ours)) {
+            disagreements = true;
+            section.push(
+                Item::warn(format!(
+                    "{:?} sets the `{}` linker to {:?}, rather than {:?}",
+                    path, target.triple, linker, ours
+                ))
+                .with_hint("Builds use the one in the cargo config; if that's not on purpose, remove it and run `cargo android config-gen`"),
+            );
+        }
+    }
+    if configured_any && !disagreements {
+        section.push(Item::pass(
+            "The linkers in your cargo config match your NDK",
+        ));
+    }
+}
+
+pub fn check() -> Section {
+    let mut section = Section::new(SectionName::Android, "Android");
+    let sdk_root = check_sdk(&mut section);
+    check_sdk_vars(&mut section);
+    check_licenses(&mut section, sdk_root.as_deref());
+    check_adb(&mut section, sdk_root.as_deref());
+    check_ndk(&mut section, sdk_root.as_deref());
+    check_rust_targets(&mut section);
+    check_cargo_config(&mut section);
+    check_jdk(&mut section);
+    check_devices(&mut section, sdk_root.as_deref());
+    section
+}
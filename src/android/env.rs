@@ -1,11 +1,12 @@
 use super::ndk;
 use crate::{
     env::{Env as CoreEnv, Error as CoreError, ExplicitEnv},
+    opts::NoDaemonManagement,
     util::cli::{Report, Reportable},
 };
 use std::{
     fmt::{self, Display},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 #[derive(Debug)]
@@ -41,6 +42,15 @@ impl Reportable for Error {
             _ => Report::error("Failed to initialize Android environment", self),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::CoreEnvError(err) => err.code(),
+            Self::AndroidSdkRootNotSet(_) => "android.env.sdk-root-not-set",
+            Self::AndroidSdkRootNotADir => "android.env.sdk-root-not-a-dir",
+            Self::NdkEnvError(err) => err.code(),
+        }
+    }
 }
 
 impl Error {
@@ -49,60 +59,70 @@ impl Error {
     }
 }
 
+pub fn sdk_root() -> Result<PathBuf, Error> {
+    std::env::var("ANDROID_SDK_ROOT")
+        .map_err(Error::AndroidSdkRootNotSet)
+        .map(PathBuf::from)
+        .and_then(|sdk_root| {
+            if sdk_root.is_dir() {
+                Ok(sdk_root)
+            } else {
+                Err(Error::AndroidSdkRootNotADir)
+            }
+        })
+        .or_else(|err| {
+            if let Some(android_home) = std::env::var("ANDROID_HOME")
+                .ok()
+                .map(PathBuf::from)
+                .filter(|android_home| android_home.is_dir())
+            {
+                log::warn!("`ANDROID_SDK_ROOT` isn't set; falling back to `ANDROID_HOME`, which is deprecated");
+                Ok(android_home)
+            } else {
+                Err(err)
+            }
+        })
+}
+
 #[derive(Debug)]
 pub struct Env {
     base: CoreEnv,
     sdk_root: PathBuf,
     pub ndk: ndk::Env,
+    no_daemon_management: NoDaemonManagement,
 }
 
 impl Env {
     pub fn new() -> Result<Self, Error> {
         let base = CoreEnv::new().map_err(Error::CoreEnvError)?;
-        let sdk_root = std::env::var("ANDROID_SDK_ROOT")
-            .map_err(Error::AndroidSdkRootNotSet)
-            .map(PathBuf::from)
-            .and_then(|sdk_root| {
-                if sdk_root.is_dir() {
-                    Ok(sdk_root)
-                } else {
-                    Err(Error::AndroidSdkRootNotADir)
-                }
-            })
-            .or_else(|err| {
-                if let Some(android_home) = std::env::var("ANDROID_HOME")
-                    .ok()
-                    .map(PathBuf::from)
-                    .filter(|android_home| android_home.is_dir())
-                {
-                    log::warn!("`ANDROID_SDK_ROOT` isn't set; falling back to `ANDROID_HOME`, which is deprecated");
-                    Ok(android_home)
-                } else {
-                    Err(err)
-                }
-            })
-            .or_else(|err| {
-                if let Some(android_home) = std::env::var("ANDROID_HOME")
-                    .ok()
-                    .map(PathBuf::from)
-                    .filter(|android_home| android_home.is_dir())
-                {
-                    log::warn!("`ANDROID_SDK_ROOT` isn't set; falling back to `ANDROID_HOME`, which is deprecated");
-                    Ok(android_home)
-                } else {
-                    Err(err)
-                }
-            })?;
+        let sdk_root = sdk_root()?;
         Ok(Self {
             base,
             sdk_root,
             ndk: ndk::Env::new().map_err(Error::NdkEnvError)?,
+            no_daemon_management: NoDaemonManagement::No,
         })
     }
 
+    pub fn with_no_daemon_management(mut self, no_daemon_management: NoDaemonManagement) -> Self {
+        self.no_daemon_management = no_daemon_management;
+        self
+    }
+
     pub fn path(&self) -> &str {
         self.base.path()
     }
+
+    pub fn sdk_root(&self) -> &Path {
+        &self.sdk_root
+    }
+
+    /// Whether we can stop Gradle daemons and restart the adb server when
+    /// they look stale, which is the case unless `--no-daemon-management` was
+    /// passed.
+    pub fn manages_daemons(&self) -> bool {
+        self.no_daemon_management.no()
+    }
 }
 
 impl ExplicitEnv for Env {
@@ -0,0 +1,532 @@
+//! What actually made it into an APK or app bundle: which ABIs have native
+//! libs, whether they're stripped, and what the manifest ended up saying once
+//! Gradle was done merging it. Anything that looks off is flagged, so that a
+//! debuggable release or a lib missing for one ABI is caught before it ships.
+
+use super::{config::Config, env};
+use crate::{
+    assets,
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use once_cell_regex::{exports::regex::Regex, regex};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Read as _},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// Stripped Rust libs are rarely this big, while ones with debug info almost
+// always are.
+const UNSTRIPPED_THRESHOLD: u64 = 1024 * 1024;
+
+// Sizes in the archive are just what it claims, so they're never trusted
+// with more than this up front.
+const MAX_PREALLOCATION: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    NoArtifact { dir: PathBuf },
+    ReadFailed { path: PathBuf, cause: io::Error },
+    NotAnArchive { path: PathBuf },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::NoArtifact { dir } => Report::action_request(
+                "There's nothing to inspect",
+                format!(
+                    "No APKs or app bundles were found in {:?}; build one with `cargo android apk`, or pass the path to one.",
+                    dir
+                ),
+            ),
+            Self::ReadFailed { path, cause } => {
+                Report::error(format!("Failed to read {:?}", path), cause)
+            }
+            Self::NotAnArchive { path } => Report::error(
+                format!("Failed to read {:?}", path),
+                "It isn't a valid zip archive, so it can't be an APK or app bundle",
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NoArtifact { .. } => "android.inspect.no-artifact",
+            Self::ReadFailed { .. } => "android.inspect.read-failed",
+            Self::NotAnArchive { .. } => "android.inspect.not-an-archive",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Apk,
+    Aab,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Lib {
+    abi: String,
+    name: String,
+    size: u64,
+    /// `None` if the lib couldn't be read, i.e. because it isn't an ELF file.
+    debug_info: Option<bool>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Manifest {
+    package: Option<String>,
+    version_code: Option<String>,
+    version_name: Option<String>,
+    min_sdk: Option<String>,
+    target_sdk: Option<String>,
+    debuggable: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Inspection {
+    path: PathBuf,
+    kind: Kind,
+    size: u64,
+    libs: Vec<Lib>,
+    /// This is `None` for app bundles, whose manifest is a protobuf that only
+    /// `bundletool` can read, and for APKs if `aapt2` isn't installed.
+    manifest: Option<Manifest>,
+    warnings: Vec<String>,
+}
+
+fn find_artifacts(dir: &Path, found: &mut Vec<(SystemTime, PathBuf)>) {
+    for path in fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+    {
+        if path.is_dir() {
+            find_artifacts(&path, found);
+        } else if path
+            .extension()
+            .map_or(false, |ext| ext == "apk" || ext == "aab")
+        {
+            if let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+                found.push((modified, path));
+            }
+        }
+    }
+}
+
+/// The most recently built APK or app bundle.
+pub fn latest(config: &Config) -> Result<PathBuf, Error> {
+    let dir = config
+        .library()
+        .map(|library| library.app_module_dir())
+        .unwrap_or_else(|| config.project_dir().join("app"))
+        .join("build/outputs");
+    let mut found = Vec::new();
+    find_artifacts(&dir, &mut found);
+    found
+        .into_iter()
+        .max()
+        .map(|(_, path)| path)
+        .ok_or(Error::NoArtifact { dir })
+}
+
+fn read_uint(bytes: &[u8], at: usize, len: usize) -> Option<u64> {
+    let bytes = bytes.get(at..at + len)?;
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| value << 8 | u64::from(*byte)),
+    )
+}
+
+// Whether an ELF file has DWARF debug info, going by its section names. Every
+// ABI Android supports is little-endian.
+fn has_debug_info(elf: &[u8]) -> Option<bool> {
+    if elf.get(..4)? != b"\x7fELF" {
+        return None;
+    }
+    let wide = match elf.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    // Where the section header table is, and then its entry size, entry
+    // count, and which entry holds the section names, one after another.
+    let (word, table_at, counts_at) = if wide {
+        (8, 0x28, 0x3a)
+    } else {
+        (4, 0x20, 0x2e)
+    };
+    let shoff = read_uint(elf, table_at, word)? as usize;
+    let shentsize = read_uint(elf, counts_at, 2)? as usize;
+    let shnum = read_uint(elf, counts_at + 2, 2)? as usize;
+    let shstrndx = read_uint(elf, counts_at + 4, 2)? as usize;
+    let (offset, size) = if wide { (0x18, 0x20) } else { (0x10, 0x14) };
+    let section = |index: usize| {
+        let at = shoff + index * shentsize;
+        Some((
+            read_uint(elf, at, 4)? as usize,
+            read_uint(elf, at + offset, word)? as usize,
+            read_uint(elf, at + size, word)? as usize,
+        ))
+    };
+    let (_, names_at, names_len) = section(shstrndx)?;
+    let names = elf.get(names_at..names_at + names_len)?;
+    let mut found = false;
+    for index in 0..shnum {
+        let (name, _, _) = section(index)?;
+        let name = names.get(name..)?;
+        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+        found |= name == b".debug_info" || name == b".zdebug_info";
+    }
+    Some(found)
+}
+
+fn manifest_from_badging(badging: &str) -> Manifest {
+    let get = |re: &Regex| re.captures(badging).map(|caps| caps[1].to_owned());
+    Manifest {
+        package: get(regex!(r"(?m)^package: .*?\bname='([^']*)'")),
+        version_code: get(regex!(r"(?m)^package: .*?\bversionCode='([^']*)'")),
+        version_name: get(regex!(r"(?m)^package: .*?\bversionName='([^']*)'")),
+        min_sdk: get(regex!(r"(?m)^sdkVersion:'([^']*)'")),
+        target_sdk: get(regex!(r"(?m)^targetSdkVersion:'([^']*)'")),
+        debuggable: regex!(r"(?m)^application-debuggable\s*$").is_match(badging),
+    }
+}
+
+// The newest `aapt2` in the SDK's build tools, if there are any installed.
+fn aapt2() -> Option<PathBuf> {
+    let name = if cfg!(windows) { "aapt2.exe" } else { "aapt2" };
+    let build_tools = env::sdk_root().ok()?.join("build-tools");
+    fs::read_dir(build_tools)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path().join(name))
+        .filter(|path| path.is_file())
+        .max_by_key(|path| {
+            // i.e. `34.0.0` or `35.0.0-rc1`
+            path.parent()
+                .and_then(Path::file_name)
+                .map(|version| {
+                    version
+                        .to_string_lossy()
+                        .split(|c: char| c == '.' || c == '-')
+                        .map(|part| part.parse::<u32>().unwrap_or_default())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        })
+}
+
+fn badging(path: &Path) -> Option<String> {
+    let aapt2 = aapt2()?;
+    bossy::Command::impure(&aapt2)
+        .with_args(&["dump", "badging"])
+        .with_arg(path)
+        .run_and_wait_for_str(|output| output.to_owned())
+        .map_err(|err| log::info!("`aapt2 dump badging` failed: {}", err))
+        .ok()
+}
+
+// The entry's contents, or `None` if it couldn't be read.
+fn read_entry(archive: &mut zip::ZipArchive<fs::File>, name: &str) -> Option<Vec<u8>> {
+    let mut entry = archive.by_name(name).ok()?;
+    let mut bytes = Vec::with_capacity(entry.size().min(MAX_PREALLOCATION) as usize);
+    entry.read_to_end(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+fn libs(archive: &mut zip::ZipArchive<fs::File>, names: &[String], kind: Kind) -> Vec<Lib> {
+    let mut libs = names
+        .iter()
+        .filter_map(|entry| {
+            let components = entry.split('/').collect::<Vec<_>>();
+            // App bundles have a dir for each module, i.e. `base/lib/...`.
+            let components = match kind {
+                Kind::Apk => &components[..],
+                Kind::Aab => components.get(1..)?,
+            };
+            match components {
+                ["lib", abi, name] if name.ends_with(".so") => {
+                    let size = archive.by_name(entry).ok()?.size();
+                    Some(Lib {
+                        abi: abi.to_string(),
+                        name: name.to_string(),
+                        size,
+                        debug_info: read_entry(archive, entry).and_then(|elf| has_debug_info(&elf)),
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    libs.sort_by(|a, b| (&a.abi, &a.name).cmp(&(&b.abi, &b.name)));
+    libs
+}
+
+// Gradle names release artifacts after the build type, i.e.
+// `app-universal-release.apk`.
+fn release(path: &Path, manifest: Option<&Manifest>) -> bool {
+    path.file_name()
+        .map_or(false, |name| name.to_string_lossy().contains("release"))
+        || manifest.map_or(false, |manifest| !manifest.debuggable)
+}
+
+fn warnings(
+    path: &Path,
+    libs: &[Lib],
+    manifest: Option<&Manifest>,
+    so_name: Option<&str>,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let release = release(path, manifest);
+    if release && manifest.map_or(false, |manifest| manifest.debuggable) {
+        warnings.push("This is a release build, but it's debuggable".to_owned());
+    }
+    let mut by_abi = BTreeMap::<&str, BTreeSet<&str>>::new();
+    for lib in libs {
+        by_abi.entry(&lib.abi).or_default().insert(&lib.name);
+    }
+    let all = by_abi.values().flatten().copied().collect::<BTreeSet<_>>();
+    for (abi, names) in &by_abi {
+        for missing in all.difference(names) {
+            warnings.push(format!(
+                "{} is missing from {}, but the other ABIs have it",
+                missing, abi
+            ));
+        }
+    }
+    if let Some(so_name) = so_name {
+        if !all.contains(so_name) {
+            warnings.push(format!("Your app's lib, {}, isn't in here", so_name));
+        }
+    }
+    if release {
+        for lib in libs {
+            if lib.debug_info == Some(true) && lib.size > UNSTRIPPED_THRESHOLD {
+                warnings.push(format!(
+                    "{}/{} has debug info and is {}, so it probably wasn't stripped",
+                    lib.abi,
+                    lib.name,
+                    util::display_size(lib.size)
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+/// Reads what's in the APK or app bundle at `path`. `so_name` is the app's
+/// own lib, which is flagged if it's missing, and `ignore` is what should've
+/// been left out of the assets.
+pub fn inspect(
+    path: &Path,
+    so_name: Option<&str>,
+    ignore: Option<&assets::Ignore>,
+) -> Result<Inspection, Error> {
+    let read_failed = |cause| Error::ReadFailed {
+        path: path.to_owned(),
+        cause,
+    };
+    let file = fs::File::open(path).map_err(read_failed)?;
+    let size = file.metadata().map_err(read_failed)?.len();
+    let mut archive = zip::ZipArchive::new(file).map_err(|_| Error::NotAnArchive {
+        path: path.to_owned(),
+    })?;
+    let names = archive.file_names().map(str::to_owned).collect::<Vec<_>>();
+    let kind = if path.extension().map_or(false, |ext| ext == "aab") {
+        Kind::Aab
+    } else {
+        Kind::Apk
+    };
+    let libs = libs(&mut archive, &names, kind);
+    let manifest = match kind {
+        Kind::Apk => badging(path).map(|badging| manifest_from_badging(&badging)),
+        Kind::Aab => None,
+    };
+    let mut warnings = warnings(path, &libs, manifest.as_ref(), so_name);
+    if let Some(ignore) = ignore {
+        let assets = names.iter().filter_map(|name| {
+            let name = match kind {
+                Kind::Apk => Some(name.as_str()),
+                Kind::Aab => name.splitn(2, '/').nth(1),
+            };
+            name?.strip_prefix("assets/")
+        });
+        warnings.extend(ignore.check_packaged(assets));
+    }
+    Ok(Inspection {
+        path: path.to_owned(),
+        kind,
+        size,
+        libs,
+        manifest,
+        warnings,
+    })
+}
+
+impl Inspection {
+    pub fn print(&self, json: bool) {
+        if json {
+            println!("{}", serde_json::json!(self));
+            return;
+        }
+        println!(
+            "{} ({})",
+            self.path.display(),
+            util::display_size(self.size)
+        );
+        match &self.manifest {
+            Some(manifest) => {
+                let or_unknown =
+                    |value: &Option<String>| value.clone().unwrap_or_else(|| "unknown".to_owned());
+                println!("    package: {}", or_unknown(&manifest.package));
+                println!(
+                    "    version: {} ({})",
+                    or_unknown(&manifest.version_name),
+                    or_unknown(&manifest.version_code)
+                );
+                println!(
+                    "    min SDK: {}, target SDK: {}",
+                    or_unknown(&manifest.min_sdk),
+                    or_unknown(&manifest.target_sdk)
+                );
+                println!(
+                    "    debuggable: {}",
+                    if manifest.debuggable { "yes" } else { "no" }
+                );
+            }
+            None if self.kind == Kind::Aab => {
+                println!("    manifest: app bundles' manifests can only be read by `bundletool`")
+            }
+            None => println!(
+                "    manifest: couldn't be read, since `aapt2` wasn't found in your SDK's build tools"
+            ),
+        }
+        if self.libs.is_empty() {
+            println!("    no native libs");
+        }
+        let width = self
+            .libs
+            .iter()
+            .map(|lib| lib.abi.len() + lib.name.len() + 1)
+            .max()
+            .unwrap_or_default();
+        for lib in &self.libs {
+            println!(
+                "    {:width$}  {:>10}  {}",
+                format!("{}/{}", lib.abi, lib.name),
+                util::display_size(lib.size),
+                match lib.debug_info {
+                    Some(true) => "has debug info",
+                    Some(false) => "stripped",
+                    None => "unreadable",
+                },
+                width = width
+            );
+        }
+        for warning in &self.warnings {
+            println!("warning: {}", warning);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Just the parts of the header and section table we read: a section
+    // header string table, and one section named `name`.
+    fn elf(name: &str) -> Vec<u8> {
+        let names = format!("\0.shstrtab\0{}\0", name);
+        let shoff = 0x40 + names.len();
+        let mut elf = vec![0; shoff + 3 * 0x40];
+        elf[..5].copy_from_slice(b"\x7fELF\x02");
+        elf[0x28..0x30].copy_from_slice(&(shoff as u64).to_le_bytes());
+        elf[0x3a..0x3c].copy_from_slice(&0x40u16.to_le_bytes());
+        elf[0x3c..0x3e].copy_from_slice(&3u16.to_le_bytes());
+        elf[0x3e..0x40].copy_from_slice(&1u16.to_le_bytes());
+        elf[0x40..shoff].copy_from_slice(names.as_bytes());
+        let shstrtab = shoff + 0x40;
+        elf[shstrtab..shstrtab + 4].copy_from_slice(&1u32.to_le_bytes());
+        elf[shstrtab + 0x18..shstrtab + 0x20].copy_from_slice(&0x40u64.to_le_bytes());
+        elf[shstrtab + 0x20..shstrtab + 0x28].copy_from_slice(&(names.len() as u64).to_le_bytes());
+        let section = shoff + 0x80;
+        elf[section..section + 4].copy_from_slice(&11u32.to_le_bytes());
+        elf
+    }
+
+    #[test]
+    fn test_has_debug_info() {
+        assert_eq!(has_debug_info(&elf(".debug_info")), Some(true));
+        assert_eq!(has_debug_info(&elf(".text")), Some(false));
+        assert_eq!(has_debug_info(b"not an ELF file"), None);
+    }
+
+    #[test]
+    fn test_manifest_from_badging() {
+        let badging = "package: name='com.example.app' versionCode='3' versionName='1.2' platformBuildVersionName='14'\nsdkVersion:'24'\ntargetSdkVersion:'34'\napplication-label:'App'\napplication-debuggable\nnative-code: 'arm64-v8a' 'x86_64'\n";
+        assert_eq!(
+            manifest_from_badging(badging),
+            Manifest {
+                package: Some("com.example.app".to_owned()),
+                version_code: Some("3".to_owned()),
+                version_name: Some("1.2".to_owned()),
+                min_sdk: Some("24".to_owned()),
+                target_sdk: Some("34".to_owned()),
+                debuggable: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_warnings() {
+        let lib = |abi: &str, name: &str, size, debug_info| Lib {
+            abi: abi.to_owned(),
+            name: name.to_owned(),
+            size,
+            debug_info: Some(debug_info),
+        };
+        let libs = [
+            lib("arm64-v8a", "libapp.so", 40 << 20, true),
+            lib("arm64-v8a", "libc++_shared.so", 1 << 20, false),
+            lib("x86_64", "libapp.so", 512 << 10, false),
+        ];
+        let manifest = Manifest {
+            debuggable: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            warnings(
+                Path::new("app-universal-release.apk"),
+                &libs,
+                Some(&manifest),
+                Some("libapp.so")
+            ),
+            vec![
+                "This is a release build, but it's debuggable",
+                "libc++_shared.so is missing from x86_64, but the other ABIs have it",
+                "arm64-v8a/libapp.so has debug info and is 40.0 MiB, so it probably wasn't stripped",
+            ]
+        );
+        assert_eq!(
+            warnings(
+                Path::new("app-universal-debug.apk"),
+                &libs[..1],
+                Some(&manifest),
+                Some("libgame.so")
+            ),
+            vec!["Your app's lib, libgame.so, isn't in here"]
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! Instrumented tests, which are the ones in the Android project's
+//! `androidTest` source set, like Espresso tests. Gradle runs them on the
+//! device and writes JUnit XML reports, which we read back to say what passed.
+
+use super::{
+    apk::{self, ApkBuildError},
+    config::{Config, Metadata},
+    device::Device,
+    env::Env,
+};
+use crate::{
+    opts::NoiseLevel,
+    util::cli::{Report, Reportable},
+};
+use once_cell_regex::{exports::regex::Captures, regex};
+use serde::Serialize;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    ResultsClearFailed { dir: PathBuf, cause: io::Error },
+    TestFailed(ApkBuildError),
+    ResultsReadFailed { path: PathBuf, cause: io::Error },
+    NoResults { dir: PathBuf },
+    TestsFailed { failed: usize, total: usize },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ResultsClearFailed { dir, cause } => Report::error(
+                format!("Failed to clear old test results in {:?}", dir),
+                cause,
+            ),
+            Self::TestFailed(err) => err.report(),
+            Self::ResultsReadFailed { path, cause } => {
+                Report::error(format!("Failed to read test results {:?}", path), cause)
+            }
+            Self::NoResults { dir } => Report::action_request(
+                "No instrumented tests ran",
+                format!(
+                    "Gradle didn't write any results to {:?}. Instrumented tests go in the app module's `src/androidTest` dir, and need a `testInstrumentationRunner`.",
+                    dir
+                ),
+            ),
+            Self::TestsFailed { failed, total } => Report::error(
+                "Instrumented tests failed",
+                format!("{} of {} failed", failed, total),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ResultsClearFailed { .. } => "android.instrumented.results-clear-failed",
+            Self::TestFailed(err) => err.code(),
+            Self::ResultsReadFailed { .. } => "android.instrumented.results-read-failed",
+            Self::NoResults { .. } => "android.instrumented.no-results",
+            Self::TestsFailed { .. } => "android.instrumented.tests-failed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TestCase {
+    class: String,
+    name: String,
+    outcome: Outcome,
+    time_secs: Option<f64>,
+    /// The failure, stack trace and all.
+    message: Option<String>,
+}
+
+impl TestCase {
+    // Just the message, without the stack trace.
+    fn summary(&self) -> Option<&str> {
+        let message = self.message.as_deref()?;
+        let end = regex!(r"(?m)^\s*at ")
+            .find(message)
+            .map_or(message.len(), |frame| frame.start());
+        Some(message[..end].trim_end())
+    }
+}
+
+fn unescape(s: &str) -> String {
+    regex!(r"&(lt|gt|amp|quot|apos|#x[0-9a-fA-F]+|#[0-9]+);")
+        .replace_all(s, |caps: &Captures<'_>| match &caps[1] {
+            "lt" => "<".to_owned(),
+            "gt" => ">".to_owned(),
+            "amp" => "&".to_owned(),
+            "quot" => "\"".to_owned(),
+            "apos" => "'".to_owned(),
+            code => {
+                let c = match code.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => code[1..].parse().ok(),
+                };
+                c.and_then(std::char::from_u32)
+                    .map(String::from)
+                    .unwrap_or_else(|| caps[0].to_owned())
+            }
+        })
+        .into_owned()
+}
+
+fn attr(attrs: &str, name: &str) -> Option<String> {
+    regex!(r#"([\w:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+        .captures_iter(attrs)
+        .find(|caps| &caps[1] == name)
+        .and_then(|caps| caps.get(2).or_else(|| caps.get(3)))
+        .map(|value| unescape(value.as_str()))
+}
+
+// The JUnit XML that Gradle writes is regular enough that this is all it
+// takes to read the test cases out of it.
+fn parse_report(xml: &str) -> Vec<TestCase> {
+    regex!(r"(?s)<testcase\b([^>]*?)(?:/>|>(.*?)</testcase>)")
+        .captures_iter(xml)
+        .map(|caps| {
+            let attrs = &caps[1];
+            let body = caps.get(2).map(|body| body.as_str()).unwrap_or_default();
+            let failure =
+                regex!(r"(?s)<(?:failure|error)\b([^>]*?)(?:/>|>(.*?)</(?:failure|error)>)")
+                    .captures(body);
+            let (outcome, message) = match failure {
+                Some(failure) => (
+                    Outcome::Failed,
+                    failure
+                        .get(2)
+                        .map(|text| unescape(text.as_str()).trim().to_owned())
+                        .filter(|text| !text.is_empty())
+                        .or_else(|| attr(&failure[1], "message")),
+                ),
+                None if body.contains("<skipped") => (Outcome::Skipped, None),
+                None => (Outcome::Passed, None),
+            };
+            TestCase {
+                class: attr(attrs, "classname").unwrap_or_default(),
+                name: attr(attrs, "name").unwrap_or_default(),
+                outcome,
+                time_secs: attr(attrs, "time").and_then(|time| time.parse().ok()),
+                message,
+            }
+        })
+        .collect()
+}
+
+// Where Gradle writes the reports for connected tests.
+fn results_dir(config: &Config) -> PathBuf {
+    config
+        .library()
+        .map(|library| library.app_module_dir())
+        .unwrap_or_else(|| config.module_dir())
+        .join("build/outputs/androidTest-results/connected")
+}
+
+// Reports end up nested under the variant and flavor, in ways that vary
+// between versions of the Android Gradle plugin, so we look everywhere.
+fn read_results(dir: &Path) -> Result<Vec<TestCase>, Error> {
+    let mut cases = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(cases),
+        Err(cause) => {
+            return Err(Error::ResultsReadFailed {
+                path: dir.to_owned(),
+                cause,
+            })
+        }
+    };
+    let mut paths = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            cases.extend(read_results(&path)?);
+        } else if path.extension().map_or(false, |ext| ext == "xml") {
+            let xml = fs::read_to_string(&path)
+                .map_err(|cause| Error::ResultsReadFailed { path, cause })?;
+            cases.extend(parse_report(&xml));
+        }
+    }
+    Ok(cases)
+}
+
+fn count(cases: &[TestCase], outcome: Outcome) -> usize {
+    cases.iter().filter(|case| case.outcome == outcome).count()
+}
+
+fn print_summary(cases: &[TestCase]) {
+    for case in cases {
+        let label = match case.outcome {
+            Outcome::Passed => "PASS",
+            Outcome::Failed => "FAIL",
+            Outcome::Skipped => "SKIP",
+        };
+        println!("{} {}.{}", label, case.class, case.name);
+        if let Some(summary) = case.summary() {
+            for line in summary.lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+    println!(
+        "{} passed, {} failed, {} skipped",
+        count(cases, Outcome::Passed),
+        count(cases, Outcome::Failed),
+        count(cases, Outcome::Skipped)
+    );
+}
+
+/// Runs the instrumented tests on `device`, and prints how each one went.
+pub fn run(
+    config: &Config,
+    metadata: &Metadata,
+    env: &Env,
+    device: &Device<'_>,
+    noise_level: NoiseLevel,
+    json: bool,
+) -> Result<(), Error> {
+    let dir = results_dir(config);
+    // Otherwise a test that's since been deleted would still be reported.
+    if dir.is_dir() {
+        fs::remove_dir_all(&dir).map_err(|cause| Error::ResultsClearFailed {
+            dir: dir.clone(),
+            cause,
+        })?;
+    }
+    let ran = apk::connected_test(
+        config,
+        metadata,
+        env,
+        device.target(),
+        noise_level,
+        device.serial_no(),
+    );
+    let cases = read_results(&dir)?;
+    match ran {
+        Err(ApkBuildError::ConnectedTestFailed(err)) if !cases.is_empty() => {
+            log::info!("Gradle failed, which failing tests make it do: {}", err)
+        }
+        Err(err) => return Err(Error::TestFailed(err)),
+        Ok(()) if cases.is_empty() => return Err(Error::NoResults { dir }),
+        Ok(()) => (),
+    }
+    let failed = count(&cases, Outcome::Failed);
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({
+                "device": device.serial_no(),
+                "passed": count(&cases, Outcome::Passed),
+                "failed": failed,
+                "skipped": count(&cases, Outcome::Skipped),
+                "tests": cases,
+            })
+        );
+    } else {
+        print_summary(&cases);
+    }
+    if failed > 0 {
+        Err(Error::TestsFailed {
+            failed,
+            total: cases.len(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static REPORT: &str = r#"<?xml version='1.0' encoding='UTF-8' ?>
+<testsuite name="com.example.app.GlueTest" tests="3" failures="1" errors="0" skipped="1" time="1.25">
+  <properties>
+    <property name="device" value="Pixel_6(AVD) - 14" />
+  </properties>
+  <testcase name="loadsLib" classname="com.example.app.GlueTest" time="0.5" />
+  <testcase name="callsIntoRust" classname="com.example.app.GlueTest" time="0.75">
+    <failure>java.lang.AssertionError: expected:&lt;4&gt; but was:&lt;5&gt;
+	at org.junit.Assert.fail(Assert.java:89)
+	at com.example.app.GlueTest.callsIntoRust(GlueTest.kt:21)
+</failure>
+  </testcase>
+  <testcase name="flaky" classname="com.example.app.GlueTest" time="0"><skipped /></testcase>
+</testsuite>
+"#;
+
+    #[test]
+    fn test_parse_report() {
+        let cases = parse_report(REPORT);
+        assert_eq!(
+            cases
+                .iter()
+                .map(|case| (case.name.as_str(), case.outcome))
+                .collect::<Vec<_>>(),
+            vec![
+                ("loadsLib", Outcome::Passed),
+                ("callsIntoRust", Outcome::Failed),
+                ("flaky", Outcome::Skipped),
+            ]
+        );
+        assert_eq!(cases[0].class, "com.example.app.GlueTest");
+        assert_eq!(cases[0].time_secs, Some(0.5));
+        assert_eq!(
+            cases[1].summary(),
+            Some("java.lang.AssertionError: expected:<4> but was:<5>")
+        );
+        assert!(cases[1]
+            .message
+            .as_deref()
+            .unwrap()
+            .contains("GlueTest.kt:21"));
+    }
+
+    #[test]
+    fn test_failure_message_attr() {
+        let cases = parse_report(
+            r#"<testcase name="a" classname="B"><failure message="it &amp; broke" /></testcase>"#,
+        );
+        assert_eq!(cases[0].outcome, Outcome::Failed);
+        assert_eq!(cases[0].message.as_deref(), Some("it & broke"));
+    }
+}
@@ -0,0 +1,791 @@
+//! Finding a JDK that the project's version of Gradle can run on, since the
+//! system Java being too old or too new is the most common reason a fresh
+//! machine fails to build.
+//!
+//! Gradle uses `org.gradle.java.home` from `gradle.properties` if it's set,
+//! and otherwise `JAVA_HOME`, and otherwise whatever `java` is on the `PATH`.
+//! If that JDK isn't compatible, we look where JDKs are usually installed
+//! (Android Studio's bundled one included), and point `org.gradle.java.home`
+//! at the first compatible one we find.
+
+use crate::util::{
+    cli::{Report, Reportable},
+    proxy::{self, Proxy},
+};
+use once_cell_regex::regex;
+use std::{
+    ffi::OsString,
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// This needs to stay in sync with the Gradle version pinned in
+// `templates/platforms/android-studio/gradle/wrapper/gradle-wrapper.properties`.
+static GRADLE_VERSION: &str = "5.1.1";
+
+static JAVA_HOME_KEY: &str = "org.gradle.java.home";
+
+static JVM_ARGS_KEY: &str = "org.gradle.jvmargs";
+
+// The first Gradle version that runs on each JDK, from
+// https://docs.gradle.org/current/userguide/compatibility.html
+static FIRST_GRADLE_FOR_JDK: &[(u32, (u32, u32))] = &[
+    (8, (2, 0)),
+    (9, (4, 3)),
+    (10, (4, 7)),
+    (11, (5, 0)),
+    (12, (5, 4)),
+    (13, (6, 0)),
+    (14, (6, 3)),
+    (15, (6, 7)),
+    (16, (7, 0)),
+    (17, (7, 3)),
+    (18, (7, 5)),
+    (19, (7, 6)),
+    (20, (8, 3)),
+    (21, (8, 5)),
+    (22, (8, 8)),
+    (23, (8, 10)),
+];
+
+// The JDKs that package managers have, which is what we suggest installing.
+static LTS_VERSIONS: &[u32] = &[21, 17, 11, 8];
+
+#[derive(Debug)]
+pub enum Error {
+    ReadFailed { path: PathBuf, cause: io::Error },
+    WriteFailed { path: PathBuf, cause: io::Error },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ReadFailed { path, cause } => Report::error(
+                format!("Failed to read Gradle properties {:?}", path),
+                cause,
+            ),
+            Self::WriteFailed { path, cause } => Report::error(
+                format!("Failed to write Gradle properties {:?}", path),
+                cause,
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadFailed { .. } => "android.jdk.read-failed",
+            Self::WriteFailed { .. } => "android.jdk.write-failed",
+        }
+    }
+}
+
+/// Which JDKs a version of Gradle can run on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Requirement {
+    gradle: String,
+    min: u32,
+    max: u32,
+}
+
+impl Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Gradle {} requires JDK {} through {}",
+            self.gradle, self.min, self.max
+        )
+    }
+}
+
+impl Requirement {
+    /// `None` if `gradle` isn't a version we can make sense of.
+    pub fn for_gradle(gradle: &str) -> Option<Self> {
+        let caps = regex!(r"^(\d+)\.(\d+)").captures(gradle)?;
+        let version = (caps[1].parse::<u32>().ok()?, caps[2].parse::<u32>().ok()?);
+        let max = FIRST_GRADLE_FOR_JDK
+            .iter()
+            .filter(|(_, first)| *first <= version)
+            .map(|(jdk, _)| *jdk)
+            .max()?;
+        let min = if version.0 >= 9 { 17 } else { 8 };
+        Some(Self {
+            gradle: gradle.to_owned(),
+            min,
+            max,
+        })
+    }
+
+    /// The requirement of the Gradle version we generate projects with.
+    pub fn pinned() -> Self {
+        Self::for_gradle(GRADLE_VERSION).expect("developer error: `GRADLE_VERSION` invalid")
+    }
+
+    /// The requirement of the Gradle version the project in `project_dir` is
+    /// pinned to, or [`Requirement::pinned`] if that can't be found out.
+    pub fn for_project(project_dir: &Path) -> Self {
+        gradle_version(project_dir)
+            .and_then(|version| Self::for_gradle(&version))
+            .unwrap_or_else(Self::pinned)
+    }
+
+    pub fn gradle(&self) -> &str {
+        &self.gradle
+    }
+
+    pub fn contains(&self, version: u32) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+
+    pub fn install_hint(&self) -> String {
+        let version = LTS_VERSIONS
+            .iter()
+            .copied()
+            .find(|version| self.contains(*version))
+            .unwrap_or(self.max);
+        format!(
+            "Install a JDK between {} and {} (e.g. `{}`), then set `JAVA_HOME` to point to it",
+            self.min,
+            self.max,
+            if cfg!(target_os = "macos") {
+                format!("brew install openjdk@{}", version)
+            } else {
+                format!("sudo apt install openjdk-{}-jdk", version)
+            },
+        )
+    }
+}
+
+fn parse_gradle_version(wrapper_properties: &str) -> Option<String> {
+    regex!(r"gradle-(\d+(?:\.\d+)+)-(?:all|bin)\.zip")
+        .captures(wrapper_properties)
+        .map(|caps| caps[1].to_owned())
+}
+
+/// The Gradle version the project in `project_dir` is pinned to.
+pub fn gradle_version(project_dir: &Path) -> Option<String> {
+    let path = project_dir.join("gradle/wrapper/gradle-wrapper.properties");
+    fs::read_to_string(&path)
+        .map_err(|err| log::info!("failed to read {:?}: {}", path, err))
+        .ok()
+        .and_then(|properties| parse_gradle_version(&properties))
+}
+
+/// Where a JDK was found.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Source {
+    GradleProperties,
+    JavaHome,
+    Path,
+    JavaHomeTool,
+    AndroidStudio,
+    Homebrew,
+    Sdkman,
+    System,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GradleProperties => write!(f, "`{}` in gradle.properties", JAVA_HOME_KEY),
+            Self::JavaHome => write!(f, "`JAVA_HOME`"),
+            Self::Path => write!(f, "your `PATH`"),
+            Self::JavaHomeTool => write!(f, "`/usr/libexec/java_home`"),
+            Self::AndroidStudio => write!(f, "Android Studio"),
+            Self::Homebrew => write!(f, "Homebrew"),
+            Self::Sdkman => write!(f, "SDKMAN!"),
+            Self::System => write!(f, "the system JDKs"),
+        }
+    }
+}
+
+// `java -version` prints to stderr, for reasons unknown to mankind.
+fn parse_version(output: &str) -> Option<u32> {
+    let caps = regex!(r#"version "(\d+)(?:\.(\d+))?"#).captures(output)?;
+    let major = caps[1].parse::<u32>().ok()?;
+    // Versions before 9 look like "1.8.0_201"
+    if major == 1 {
+        caps.get(2)?.as_str().parse::<u32>().ok()
+    } else {
+        Some(major)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Jdk {
+    java: PathBuf,
+    // `None` for the `java` on the `PATH`, since it's usually a symlink (or
+    // on macOS, a stub) that doesn't say where the JDK is.
+    home: Option<PathBuf>,
+    version: u32,
+    source: Source,
+}
+
+impl Display for Jdk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.home {
+            Some(home) => write!(
+                f,
+                "JDK {} at {:?} (from {})",
+                self.version, home, self.source
+            ),
+            None => write!(f, "JDK {} (from {})", self.version, self.source),
+        }
+    }
+}
+
+impl Jdk {
+    fn probe(java: PathBuf, home: Option<PathBuf>, source: Source) -> Option<Self> {
+        let output = bossy::Command::impure(&java)
+            .with_arg("-version")
+            .run_and_wait_for_output()
+            .map_err(|err| log::info!("failed to run {:?}: {}", java, err))
+            .ok()?;
+        let version = parse_version(&String::from_utf8_lossy(output.stderr()));
+        if version.is_none() {
+            log::warn!("failed to determine the version of {:?}", java);
+        }
+        Some(Self {
+            java,
+            home,
+            version: version?,
+            source,
+        })
+    }
+
+    fn probe_home(home: PathBuf, source: Source) -> Option<Self> {
+        let java = home.join("bin/java");
+        if java.is_file() {
+            Self::probe(java, Some(home), source)
+        } else {
+            None
+        }
+    }
+
+    pub fn home(&self) -> Option<&Path> {
+        self.home.as_deref()
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    fn same_as(&self, other: &Self) -> bool {
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        canonical(&self.java) == canonical(&other.java)
+    }
+}
+
+fn properties_path(project_dir: &Path) -> PathBuf {
+    project_dir.join("gradle.properties")
+}
+
+/// The JDK `gradle.properties` points Gradle at, if any.
+pub fn configured_home(project_dir: &Path) -> Option<PathBuf> {
+    let path = properties_path(project_dir);
+    let file = fs::File::open(&path).ok()?;
+    java_properties::read(io::BufReader::new(file))
+        .map_err(|err| log::warn!("failed to parse {:?}: {}", path, err))
+        .ok()?
+        .remove(JAVA_HOME_KEY)
+        .filter(|home| !home.trim().is_empty())
+        .map(PathBuf::from)
+}
+
+/// What `gradlew` should have as its `JAVA_HOME`, which is the JDK Gradle's
+/// going to run on anyway, so that the wrapper doesn't start on one that's
+/// incompatible. `gradlew` is run with a clean environment, so this has to be
+/// passed along explicitly.
+pub fn launcher_home(project_dir: &Path) -> Option<OsString> {
+    configured_home(project_dir)
+        .map(PathBuf::into_os_string)
+        .or_else(|| std::env::var_os("JAVA_HOME"))
+}
+
+// The JDK Gradle would use if we didn't intervene.
+fn default_jdk(project_dir: Option<&Path>) -> Option<Jdk> {
+    if let Some(home) = project_dir.and_then(configured_home) {
+        Jdk::probe_home(home, Source::GradleProperties)
+    } else if let Some(home) = std::env::var_os("JAVA_HOME") {
+        Jdk::probe_home(PathBuf::from(home), Source::JavaHome)
+    } else {
+        Jdk::probe(PathBuf::from("java"), None, Source::Path)
+    }
+}
+
+// `/usr/libexec/java_home -V` lists every JDK it knows about on stderr, with
+// lines like `    11.0.12, x86_64:	"Java SE 11.0.12"	/Library/...` or
+// `    17.0.2 (arm64) "Oracle Corporation" - "OpenJDK 17.0.2" /Library/...`.
+fn parse_java_home_listing(output: &str) -> Vec<(u32, PathBuf)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let caps = regex!(r"^\s+(\d+)(?:\.(\d+))?\S*.*?\s(/.*)$").captures(line)?;
+            let major = caps[1].parse::<u32>().ok()?;
+            let version = if major == 1 {
+                caps.get(2)?.as_str().parse::<u32>().ok()?
+            } else {
+                major
+            };
+            Some((version, PathBuf::from(caps[3].trim())))
+        })
+        .collect()
+}
+
+fn java_home_tool() -> Vec<PathBuf> {
+    if !cfg!(target_os = "macos") {
+        return Vec::new();
+    }
+    bossy::Command::impure("/usr/libexec/java_home")
+        .with_arg("-V")
+        .run_and_wait_for_output()
+        .map(|output| {
+            parse_java_home_listing(&String::from_utf8_lossy(output.stderr()))
+                .into_iter()
+                .map(|(_, home)| home)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Newest first, so that when several would do, we use the most recent one.
+fn subdirs(dir: &Path, filter: impl Fn(&str) -> bool) -> Vec<PathBuf> {
+    let mut dirs = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_name().to_str().map_or(false, &filter))
+                .map(|entry| entry.path())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    dirs.sort();
+    dirs.reverse();
+    dirs
+}
+
+fn android_studio_homes(home_dir: Option<&Path>) -> Vec<PathBuf> {
+    // The bundled JDK was `jre` before Android Studio 2022.2, and `jbr` since.
+    let bundled = ["jbr", "jre", "jre/jdk"];
+    let installs: Vec<PathBuf> = if cfg!(target_os = "macos") {
+        std::iter::once(PathBuf::from("/Applications"))
+            .chain(home_dir.map(|home_dir| home_dir.join("Applications")))
+            .map(|apps| apps.join("Android Studio.app/Contents"))
+            .collect()
+    } else {
+        vec![
+            PathBuf::from("/opt/android-studio"),
+            PathBuf::from("/usr/local/android-studio"),
+            PathBuf::from("/snap/android-studio/current/android-studio"),
+        ]
+        .into_iter()
+        .chain(home_dir.map(|home_dir| home_dir.join("android-studio")))
+        .collect()
+    };
+    installs
+        .iter()
+        .flat_map(|install| {
+            bundled.iter().map(move |bundled| {
+                let home = install.join(bundled);
+                if cfg!(target_os = "macos") {
+                    home.join("Contents/Home")
+                } else {
+                    home
+                }
+            })
+        })
+        .collect()
+}
+
+fn homebrew_homes() -> Vec<PathBuf> {
+    ["/opt/homebrew", "/usr/local", "/home/linuxbrew/.linuxbrew"]
+        .iter()
+        .flat_map(|prefix| {
+            subdirs(&Path::new(prefix).join("opt"), |name| {
+                name.starts_with("openjdk")
+            })
+        })
+        .map(|formula| {
+            if cfg!(target_os = "macos") {
+                formula.join("libexec/openjdk.jdk/Contents/Home")
+            } else {
+                formula.join("libexec")
+            }
+        })
+        .collect()
+}
+
+fn sdkman_homes(home_dir: Option<&Path>) -> Vec<PathBuf> {
+    std::env::var_os("SDKMAN_DIR")
+        .map(PathBuf::from)
+        .or_else(|| home_dir.map(|home_dir| home_dir.join(".sdkman")))
+        .map(|sdkman_dir| {
+            subdirs(&sdkman_dir.join("candidates/java"), |name| {
+                name != "current"
+            })
+        })
+        .unwrap_or_default()
+}
+
+fn system_homes() -> Vec<PathBuf> {
+    if cfg!(target_os = "macos") {
+        // `java_home` already covers these.
+        Vec::new()
+    } else {
+        subdirs(Path::new("/usr/lib/jvm"), |_| true)
+    }
+}
+
+// Where to look for JDKs, in the order we'd rather use them. Android
+// Studio's comes first after the ones the system knows about, since it's
+// what the IDE builds with.
+fn candidates() -> impl Iterator<Item = (PathBuf, Source)> {
+    let home_dir = std::env::var_os("HOME").map(PathBuf::from);
+    let tagged =
+        |homes: Vec<PathBuf>, source: Source| homes.into_iter().map(move |home| (home, source));
+    tagged(java_home_tool(), Source::JavaHomeTool)
+        .chain(tagged(
+            android_studio_homes(home_dir.as_deref()),
+            Source::AndroidStudio,
+        ))
+        .chain(tagged(homebrew_homes(), Source::Homebrew))
+        .chain(tagged(sdkman_homes(home_dir.as_deref()), Source::Sdkman))
+        .chain(tagged(system_homes(), Source::System))
+}
+
+#[derive(Debug)]
+pub enum Selection {
+    /// The JDK Gradle would use anyway is compatible.
+    Default(Jdk),
+    /// The JDK Gradle would use isn't compatible (or there isn't one), but
+    /// this one is.
+    Found { default: Option<Jdk>, jdk: Jdk },
+    /// There's no compatible JDK to be found.
+    NotFound { default: Option<Jdk> },
+}
+
+impl Selection {
+    /// What Gradle should use, if there's anything it can.
+    pub fn jdk(&self) -> Option<&Jdk> {
+        match self {
+            Self::Default(jdk) | Self::Found { jdk, .. } => Some(jdk),
+            Self::NotFound { .. } => None,
+        }
+    }
+
+    /// Which JDK Gradle will use and why, or why there's none it can.
+    pub fn describe(&self, requirement: &Requirement) -> String {
+        let incompatible = |default: &Option<Jdk>| match default {
+            Some(default) => format!("{} isn't compatible; {}", default, requirement),
+            None => format!(
+                "there's no JDK in `JAVA_HOME` or on your `PATH`, and {}",
+                requirement
+            ),
+        };
+        match self {
+            Self::Default(jdk) => {
+                format!("{} is compatible with Gradle {}", jdk, requirement.gradle())
+            }
+            Self::Found { default, jdk } => {
+                format!("Using {}, since {}", jdk, incompatible(default))
+            }
+            Self::NotFound { default } => {
+                format!("No compatible JDK was found, and {}", incompatible(default))
+            }
+        }
+    }
+}
+
+/// Finds a JDK that satisfies `requirement`, starting with the one Gradle
+/// would use for the project in `project_dir` (if there is one). Nothing is
+/// changed.
+pub fn select(project_dir: Option<&Path>, requirement: &Requirement) -> Selection {
+    let default = default_jdk(project_dir);
+    match default {
+        Some(default) if requirement.contains(default.version) => Selection::Default(default),
+        default => {
+            let jdk = candidates()
+                .filter_map(|(home, source)| Jdk::probe_home(home, source))
+                .filter(|jdk| {
+                    default
+                        .as_ref()
+                        .map_or(true, |default| !jdk.same_as(default))
+                })
+                .inspect(|jdk| log::info!("found {}", jdk))
+                .find(|jdk| requirement.contains(jdk.version));
+            match jdk {
+                Some(jdk) => Selection::Found { default, jdk },
+                None => Selection::NotFound { default },
+            }
+        }
+    }
+}
+
+fn escape_value(value: &str) -> String {
+    value.replace('\\', "\\\\")
+}
+
+fn is_line_for(key: &str, line: &str) -> bool {
+    let line = line.trim_start();
+    line.strip_prefix(key).map_or(false, |rest| {
+        rest.is_empty() || rest.starts_with(|c: char| c == '=' || c == ':' || c.is_whitespace())
+    })
+}
+
+// Everything else in the file (comments included) is left as it was.
+fn set_property(properties: &str, key: &str, value: &str) -> String {
+    let line = format!("{}={}", key, escape_value(value));
+    let mut replaced = false;
+    let mut lines = properties
+        .lines()
+        .filter_map(|existing| {
+            if !is_line_for(key, existing) {
+                Some(existing.to_owned())
+            } else if !replaced {
+                replaced = true;
+                Some(line.clone())
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if !replaced {
+        lines.push(line);
+    }
+    let mut properties = lines.join("\n");
+    properties.push('\n');
+    properties
+}
+
+fn remove_property(properties: &str, key: &str) -> String {
+    if !properties
+        .lines()
+        .any(|existing| is_line_for(key, existing))
+    {
+        return properties.to_owned();
+    }
+    let mut properties = properties
+        .lines()
+        .filter(|existing| !is_line_for(key, existing))
+        .collect::<Vec<_>>()
+        .join("\n");
+    properties.push('\n');
+    properties
+}
+
+fn set_java_home(properties: &str, home: &Path) -> String {
+    set_property(properties, JAVA_HOME_KEY, &home.display().to_string())
+}
+
+// Only the max heap size is ours to change, so any other JVM args are kept.
+fn with_heap(jvm_args: &str, heap_mib: u64) -> String {
+    let heap = format!("-Xmx{}m", heap_mib);
+    let mut args = jvm_args
+        .split_whitespace()
+        .filter(|arg| !arg.starts_with("-Xmx"))
+        .collect::<Vec<_>>();
+    args.insert(0, &heap);
+    args.join(" ")
+}
+
+fn read_properties(path: &Path) -> Result<String, Error> {
+    match fs::read_to_string(path) {
+        Ok(properties) => Ok(properties),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(cause) => Err(Error::ReadFailed {
+            path: path.to_owned(),
+            cause,
+        }),
+    }
+}
+
+/// Points Gradle at the JDK in `home` for the project in `project_dir`.
+pub fn write_java_home(project_dir: &Path, home: &Path) -> Result<(), Error> {
+    let path = properties_path(project_dir);
+    let properties = read_properties(&path)?;
+    fs::write(&path, set_java_home(&properties, home))
+        .map_err(|cause| Error::WriteFailed { path, cause })
+}
+
+/// Sets the max heap in `org.gradle.jvmargs` for the project in
+/// `project_dir`, returning whether that changed anything. Gradle starts a
+/// new daemon when it does, since a daemon's JVM args can't change.
+pub fn write_heap(project_dir: &Path, heap_mib: u64) -> Result<bool, Error> {
+    let path = properties_path(project_dir);
+    let properties = read_properties(&path)?;
+    let jvm_args = java_properties::read(properties.as_bytes())
+        .map_err(|err| log::warn!("failed to parse {:?}: {}", path, err))
+        .ok()
+        .and_then(|mut properties| properties.remove(JVM_ARGS_KEY))
+        .unwrap_or_default();
+    let updated = with_heap(&jvm_args, heap_mib);
+    if updated == jvm_args {
+        return Ok(false);
+    }
+    fs::write(&path, set_property(&properties, JVM_ARGS_KEY, &updated))
+        .map(|()| true)
+        .map_err(|cause| Error::WriteFailed { path, cause })
+}
+
+/// Puts the proxy settings (credentials included) in `gradle.properties` for
+/// the project in `project_dir`, and removes any that no longer apply,
+/// returning whether that changed anything.
+pub fn write_proxy(project_dir: &Path, proxy: &Proxy) -> Result<bool, Error> {
+    let path = properties_path(project_dir);
+    let properties = read_properties(&path)?;
+    let settings = proxy.gradle_properties();
+    let updated = proxy::GRADLE_KEYS
+        .iter()
+        .fold(properties.clone(), |updated, key| {
+            match settings.iter().find(|(setting, _)| setting == key) {
+                Some((_, value)) => set_property(&updated, key, value),
+                None => remove_property(&updated, key),
+            }
+        });
+    if updated == properties {
+        return Ok(false);
+    }
+    fs::write(&path, updated)
+        .map(|()| true)
+        .map_err(|cause| Error::WriteFailed { path, cause })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        output,
+        expected,
+        case(
+            "java version \"1.8.0_201\"\nJava(TM) SE Runtime Environment (build 1.8.0_201-b09)",
+            Some(8)
+        ),
+        case(
+            "openjdk version \"11.0.12\" 2021-07-20\nOpenJDK Runtime Environment",
+            Some(11)
+        ),
+        case("openjdk version \"21\" 2023-09-19", Some(21)),
+        case(
+            "The operation couldn't be completed. Unable to locate a Java Runtime.",
+            None
+        )
+    )]
+    fn test_parse_version(output: &str, expected: Option<u32>) {
+        assert_eq!(parse_version(output), expected);
+    }
+
+    #[rstest(
+        gradle,
+        expected,
+        case("5.1.1", Some((8, 11))),
+        case("6.7", Some((8, 15))),
+        case("7.6.1", Some((8, 19))),
+        case("8.5", Some((8, 21))),
+        case("9.0", Some((17, 23))),
+        case("latest", None)
+    )]
+    fn test_requirement_for_gradle(gradle: &str, expected: Option<(u32, u32)>) {
+        assert_eq!(
+            Requirement::for_gradle(gradle).map(|requirement| (requirement.min, requirement.max)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_parse_gradle_version() {
+        assert_eq!(
+            parse_gradle_version(
+                "distributionBase=GRADLE_USER_HOME\ndistributionUrl=https\\://services.gradle.org/distributions/gradle-5.1.1-all.zip\n"
+            )
+            .as_deref(),
+            Some("5.1.1")
+        );
+    }
+
+    #[test]
+    fn test_parse_java_home_listing() {
+        let output = "Matching Java Virtual Machines (3):\n    17.0.2 (arm64) \"Oracle Corporation\" - \"OpenJDK 17.0.2\" /Library/Java/JavaVirtualMachines/openjdk-17.0.2.jdk/Contents/Home\n    11.0.12, x86_64:\t\"Java SE 11.0.12\"\t/Library/Java/JavaVirtualMachines/jdk-11.0.12.jdk/Contents/Home\n    1.8.0_292 (x86_64) \"AdoptOpenJDK\" - \"AdoptOpenJDK 8\" /Library/Java/JavaVirtualMachines/adoptopenjdk-8.jdk/Contents/Home\n/Library/Java/JavaVirtualMachines/openjdk-17.0.2.jdk/Contents/Home\n";
+        assert_eq!(
+            parse_java_home_listing(output),
+            vec![
+                (
+                    17,
+                    PathBuf::from(
+                        "/Library/Java/JavaVirtualMachines/openjdk-17.0.2.jdk/Contents/Home"
+                    )
+                ),
+                (
+                    11,
+                    PathBuf::from(
+                        "/Library/Java/JavaVirtualMachines/jdk-11.0.12.jdk/Contents/Home"
+                    )
+                ),
+                (
+                    8,
+                    PathBuf::from(
+                        "/Library/Java/JavaVirtualMachines/adoptopenjdk-8.jdk/Contents/Home"
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[rstest(
+        properties,
+        expected,
+        case("", "org.gradle.java.home=/opt/jbr\n"),
+        case(
+            "# JVM args\norg.gradle.jvmargs=-Xmx1536m",
+            "# JVM args\norg.gradle.jvmargs=-Xmx1536m\norg.gradle.java.home=/opt/jbr\n"
+        ),
+        case(
+            "org.gradle.java.home=/old\norg.gradle.jvmargs=-Xmx1536m\n  org.gradle.java.home : /older\n",
+            "org.gradle.java.home=/opt/jbr\norg.gradle.jvmargs=-Xmx1536m\n"
+        ),
+        case(
+            "org.gradle.java.homes=/unrelated\n",
+            "org.gradle.java.homes=/unrelated\norg.gradle.java.home=/opt/jbr\n"
+        )
+    )]
+    fn test_set_java_home(properties: &str, expected: &str) {
+        assert_eq!(set_java_home(properties, Path::new("/opt/jbr")), expected);
+    }
+
+    #[rstest(
+        jvm_args,
+        expected,
+        case("", "-Xmx1024m"),
+        case("-Xmx1536m", "-Xmx1024m"),
+        case("-Xmx1536m -Dfile.encoding=UTF-8", "-Xmx1024m -Dfile.encoding=UTF-8"),
+        case("-XX:+UseParallelGC", "-Xmx1024m -XX:+UseParallelGC")
+    )]
+    fn test_with_heap(jvm_args: &str, expected: &str) {
+        assert_eq!(with_heap(jvm_args, 1024), expected);
+    }
+
+    #[rstest(
+        properties,
+        expected,
+        case("org.gradle.jvmargs=-Xmx1536m", "org.gradle.jvmargs=-Xmx1536m"),
+        case(
+            "systemProp.https.proxyUser=me\norg.gradle.jvmargs=-Xmx1536m",
+            "org.gradle.jvmargs=-Xmx1536m\n"
+        ),
+        case(
+            "systemProp.https.proxyUsers=unrelated\n",
+            "systemProp.https.proxyUsers=unrelated\n"
+        )
+    )]
+    fn test_remove_property(properties: &str, expected: &str) {
+        assert_eq!(
+            remove_property(properties, "systemProp.https.proxyUser"),
+            expected
+        );
+    }
+}
@@ -1,12 +1,17 @@
-use super::{config::Config, target::Target};
+use super::{config::Config, ndk, target::Target};
 use crate::{
     target::TargetTrait as _,
+    ui,
     util::{
+        self,
         cli::{Report, Reportable},
         ln,
     },
 };
-use std::path::{Path, PathBuf};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 #[derive(Debug)]
 pub enum RemoveBrokenLinksError {
@@ -41,6 +46,14 @@ impl Reportable for RemoveBrokenLinksError {
             ),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadDirFailed { .. } => "android.jnilibs.remove-broken-links.read-dir-failed",
+            Self::EntryFailed { .. } => "android.jnilibs.remove-broken-links.entry-failed",
+            Self::RemoveFailed { .. } => "android.jnilibs.remove-broken-links.remove-failed",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -60,12 +73,84 @@ impl Reportable for SymlinkLibError {
             Self::SymlinkFailed(err) => Report::error(msg, err),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::SourceMissing(..) => "android.jnilibs.symlink-lib.source-missing",
+            Self::SymlinkFailed(..) => "android.jnilibs.symlink-lib.symlink-failed",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StripLibError {
+    SourceMissing(PathBuf),
+    MissingTool(ndk::MissingToolError),
+    RemoveFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    CopyFailed {
+        src: PathBuf,
+        dest: PathBuf,
+        cause: io::Error,
+    },
+    StripFailed(bossy::Error),
+    MetadataFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+}
+
+impl Reportable for StripLibError {
+    fn report(&self) -> Report {
+        let msg = "Failed to strip lib";
+        match self {
+            Self::SourceMissing(src) => Report::error(
+                msg,
+                format!("The lib to strip is {:?}, but nothing exists there", src),
+            ),
+            Self::MissingTool(err) => Report::error(msg, err),
+            Self::RemoveFailed { path, cause } => Report::error(
+                msg,
+                format!("Failed to remove old lib at {:?}: {}", path, cause),
+            ),
+            Self::CopyFailed { src, dest, cause } => Report::error(
+                msg,
+                format!("Failed to copy {:?} to {:?}: {}", src, dest, cause),
+            ),
+            Self::StripFailed(err) => Report::error(msg, err),
+            Self::MetadataFailed { path, cause } => {
+                Report::error(msg, format!("Failed to get size of {:?}: {}", path, cause))
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::SourceMissing(..) => "android.jnilibs.strip-lib.source-missing",
+            Self::MissingTool(..) => "android.jnilibs.strip-lib.missing-tool",
+            Self::RemoveFailed { .. } => "android.jnilibs.strip-lib.remove-failed",
+            Self::CopyFailed { .. } => "android.jnilibs.strip-lib.copy-failed",
+            Self::StripFailed(..) => "android.jnilibs.strip-lib.strip-failed",
+            Self::MetadataFailed { .. } => "android.jnilibs.strip-lib.metadata-failed",
+        }
+    }
+}
+
+fn file_size(path: &Path) -> Result<u64, StripLibError> {
+    fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .map_err(|cause| StripLibError::MetadataFailed {
+            path: path.to_owned(),
+            cause,
+        })
 }
 
 pub fn path(config: &Config, target: Target<'_>) -> PathBuf {
     config
-        .project_dir()
-        .join(format!("app/src/main/jniLibs/{}", &target.abi))
+        .module_dir()
+        .join(format!("src/main/jniLibs/{}", &target.abi))
 }
 
 #[derive(Debug)]
@@ -131,4 +216,49 @@ impl JniLibs {
             Err(SymlinkLibError::SourceMissing(src.to_owned()))
         }
     }
+
+    // Replaces the lib's symlink with a stripped copy, and keeps an unstripped
+    // copy alongside it with a `.debug` suffix for symbolication. Gradle only
+    // packages `*.so` files, so the `.debug` copy never ends up in the APK.
+    pub fn strip_lib(&self, ndk: &ndk::Env, src: &Path) -> Result<(), StripLibError> {
+        if !src.is_file() {
+            return Err(StripLibError::SourceMissing(src.to_owned()));
+        }
+        let file_name = src
+            .file_name()
+            .expect("developer error: file had no file name");
+        let dest = self.path.join(file_name);
+        let debug = self
+            .path
+            .join(format!("{}.debug", file_name.to_string_lossy()));
+        for path in [&dest, &debug].iter() {
+            // `symlink_metadata` so we catch broken symlinks too
+            if fs::symlink_metadata(path).is_ok() {
+                fs::remove_file(path).map_err(|cause| StripLibError::RemoveFailed {
+                    path: path.to_path_buf(),
+                    cause,
+                })?;
+            }
+        }
+        fs::copy(src, &debug).map_err(|cause| StripLibError::CopyFailed {
+            src: src.to_owned(),
+            dest: debug.clone(),
+            cause,
+        })?;
+        log::info!("stripping lib {:?} into {:?}", debug, dest);
+        bossy::Command::impure(ndk.strip_path().map_err(StripLibError::MissingTool)?)
+            .with_arg("--strip-unneeded")
+            .with_arg("-o")
+            .with_arg(&dest)
+            .with_arg(&debug)
+            .run_and_wait()
+            .map_err(StripLibError::StripFailed)?;
+        ui::status(format!(
+            "Stripped {:?}: {} -> {}",
+            file_name,
+            util::display_size(file_size(&debug)?),
+            util::display_size(file_size(&dest)?),
+        ));
+        Ok(())
+    }
 }
@@ -1,11 +1,17 @@
-mod adb;
+pub(crate) mod adb;
+mod apk;
 pub mod cli;
 pub(crate) mod config;
-mod device;
+pub(crate) mod device;
+pub mod doctor;
 pub(crate) mod env;
+mod inspect;
+mod instrumented;
+pub(crate) mod jdk;
 mod jnilibs;
 mod ndk;
+pub(crate) mod parallel;
 pub(crate) mod project;
-mod target;
+pub(crate) mod target;
 
 pub static NAME: &str = "android";
@@ -1,10 +1,13 @@
 use super::target::Target;
-use crate::util::cli::{Report, Reportable};
+use crate::util::{
+    self,
+    cli::{Report, Reportable},
+};
 use once_cell_regex::regex_multi_line;
 use std::{
     collections::HashSet,
     fmt::{self, Display},
-    fs::File,
+    fs::{self, File},
     io,
     num::ParseIntError,
     path::{Path, PathBuf},
@@ -16,24 +19,36 @@ const MIN_NDK_VERSION: Version = Version {
     minor: 0,
 };
 
-#[cfg(target_os = "macos")]
-pub fn host_tag() -> &'static str {
-    "darwin-x86_64"
+// The host tags we're willing to use, in order of preference. Older NDKs only
+// ship `darwin-x86_64`, which still works on Apple Silicon via Rosetta.
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+pub fn host_tags() -> &'static [&'static str] {
+    &["darwin-arm64", "darwin-x86_64"]
+}
+
+#[cfg(all(target_os = "macos", not(target_arch = "aarch64")))]
+pub fn host_tags() -> &'static [&'static str] {
+    &["darwin-x86_64"]
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+pub fn host_tags() -> &'static [&'static str] {
+    &["linux-aarch64", "linux-arm64", "linux-x86_64"]
 }
 
-#[cfg(target_os = "linux")]
-pub fn host_tag() -> &'static str {
-    "linux-x86_64"
+#[cfg(all(target_os = "linux", not(target_arch = "aarch64")))]
+pub fn host_tags() -> &'static [&'static str] {
+    &["linux-x86_64"]
 }
 
 #[cfg(all(windows, target_pointer_width = "32"))]
-pub fn host_tag() -> &'static str {
-    "windows"
+pub fn host_tags() -> &'static [&'static str] {
+    &["windows"]
 }
 
 #[cfg(all(windows, target_pointer_width = "64"))]
-pub fn host_tag() -> &'static str {
-    "windows-x86_64"
+pub fn host_tags() -> &'static [&'static str] {
+    &["windows-x86_64", "windows"]
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -181,6 +196,14 @@ pub enum Error {
         you_have: Version,
         you_need: Version,
     },
+    PrebuiltDirMissing {
+        path: PathBuf,
+    },
+    HostTagNotFound {
+        prebuilt_root: PathBuf,
+        tried: &'static [&'static str],
+        found: Vec<String>,
+    },
 }
 
 impl Display for Error {
@@ -204,6 +227,28 @@ impl Display for Error {
                 you_need,
                 you_have,
             ),
+            Self::PrebuiltDirMissing { path } => write!(
+                f,
+                "The NDK's prebuilt toolchain directory {:?} doesn't exist; your NDK installation might be incomplete.",
+                path,
+            ),
+            Self::HostTagNotFound {
+                prebuilt_root,
+                tried,
+                found,
+            } => {
+                write!(
+                    f,
+                    "None of the prebuilt toolchains for this host ({}) were found in {:?}; ",
+                    util::list_display(tried),
+                    prebuilt_root,
+                )?;
+                if found.is_empty() {
+                    write!(f, "that directory is empty.")
+                } else {
+                    write!(f, "only found {}.", util::list_display(&found[..]))
+                }
+            }
         }
     }
 }
@@ -212,6 +257,17 @@ impl Reportable for Error {
     fn report(&self) -> Report {
         Report::error("Failed to initialize NDK environment", self)
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NdkHomeNotSet(..) => "android.ndk.ndk-home-not-set",
+            Self::NdkHomeNotADir => "android.ndk.ndk-home-not-a-dir",
+            Self::VersionLookupFailed(..) => "android.ndk.version-lookup-failed",
+            Self::VersionTooLow { .. } => "android.ndk.version-too-low",
+            Self::PrebuiltDirMissing { .. } => "android.ndk.prebuilt-dir-missing",
+            Self::HostTagNotFound { .. } => "android.ndk.host-tag-not-found",
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -228,11 +284,49 @@ impl Reportable for RequiredLibsError {
     fn report(&self) -> Report {
         Report::error("Failed to get list of required libs", self)
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::MissingTool(..) => "android.ndk.required-libs.missing-tool",
+            Self::ReadElfFailed(..) => "android.ndk.required-libs.read-elf-failed",
+            Self::InvalidUtf8(..) => "android.ndk.required-libs.invalid-utf8",
+        }
+    }
+}
+
+fn find_prebuilt_dir(ndk_home: &Path) -> Result<PathBuf, Error> {
+    let prebuilt_root = ndk_home.join("toolchains/llvm/prebuilt");
+    let entries = fs::read_dir(&prebuilt_root).map_err(|_| Error::PrebuiltDirMissing {
+        path: prebuilt_root.clone(),
+    })?;
+    let found = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    log::info!(
+        "found prebuilt toolchains {:?} in {:?}",
+        found,
+        prebuilt_root
+    );
+    host_tags()
+        .iter()
+        .find(|tag| found.iter().any(|found| found == *tag))
+        .map(|tag| {
+            log::info!("using prebuilt toolchain for host tag {:?}", tag);
+            prebuilt_root.join(tag)
+        })
+        .ok_or_else(|| Error::HostTagNotFound {
+            prebuilt_root,
+            tried: host_tags(),
+            found,
+        })
 }
 
 #[derive(Debug)]
 pub struct Env {
     ndk_home: PathBuf,
+    prebuilt_dir: PathBuf,
 }
 
 impl Env {
@@ -247,16 +341,18 @@ impl Env {
                     Err(Error::NdkHomeNotADir)
                 }
             })?;
-        let env = Self { ndk_home };
-        let version = env.version().map_err(Error::VersionLookupFailed)?;
-        if version >= MIN_NDK_VERSION {
-            Ok(env)
-        } else {
-            Err(Error::VersionTooLow {
+        let version = Self::version_at(&ndk_home).map_err(Error::VersionLookupFailed)?;
+        if version < MIN_NDK_VERSION {
+            return Err(Error::VersionTooLow {
                 you_have: version,
                 you_need: MIN_NDK_VERSION,
-            })
+            });
         }
+        let prebuilt_dir = find_prebuilt_dir(&ndk_home)?;
+        Ok(Self {
+            ndk_home,
+            prebuilt_dir,
+        })
     }
 
     pub fn home(&self) -> &Path {
@@ -264,7 +360,11 @@ impl Env {
     }
 
     pub fn version(&self) -> Result<Version, VersionError> {
-        let path = self.ndk_home.join("source.properties");
+        Self::version_at(&self.ndk_home)
+    }
+
+    fn version_at(ndk_home: &Path) -> Result<Version, VersionError> {
+        let path = ndk_home.join("source.properties");
         let file = File::open(&path).map_err(|cause| VersionError::OpenFailed {
             path: path.clone(),
             cause,
@@ -306,17 +406,12 @@ impl Env {
         }
     }
 
-    pub fn prebuilt_dir(&self) -> Result<PathBuf, MissingToolError> {
-        MissingToolError::check_dir(
-            self.ndk_home
-                .join(format!("toolchains/llvm/prebuilt/{}", host_tag())),
-            // TODO: shove this square peg into a squarer hole
-            "prebuilt toolchain",
-        )
+    pub fn prebuilt_dir(&self) -> &Path {
+        &self.prebuilt_dir
     }
 
     pub fn tool_dir(&self) -> Result<PathBuf, MissingToolError> {
-        MissingToolError::check_dir(self.prebuilt_dir()?.join("bin"), "tools")
+        MissingToolError::check_dir(self.prebuilt_dir().join("bin"), "tools")
     }
 
     pub fn compiler_path(
@@ -355,6 +450,14 @@ impl Env {
         )
     }
 
+    pub fn strip_path(&self) -> Result<PathBuf, MissingToolError> {
+        MissingToolError::check_file(self.tool_dir()?.join("llvm-strip"), "llvm-strip")
+    }
+
+    pub fn nm_path(&self) -> Result<PathBuf, MissingToolError> {
+        MissingToolError::check_file(self.tool_dir()?.join("llvm-nm"), "llvm-nm")
+    }
+
     fn readelf_path(&self, triple: &str) -> Result<PathBuf, MissingToolError> {
         MissingToolError::check_file(
             self.tool_dir()?.join(format!("{}-readelf", triple)),
@@ -0,0 +1,190 @@
+use super::{
+    config::{Config, Metadata},
+    env::Env,
+    target::{BuildError, CompileLibError, Target},
+};
+use crate::{
+    opts::{Force, ForceColor, NoiseLevel, Profile, SkipStrip},
+    ui,
+    util::{
+        cli::{Report, Reportable},
+        progress::{self, Phase},
+    },
+};
+use std::{sync::mpsc, thread};
+
+// What cargo says when another invocation is holding the target dir.
+static LOCK_WAIT: &str = "Blocking waiting for file lock";
+
+#[derive(Debug)]
+pub struct Error {
+    failures: Vec<(String, BuildError)>,
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        Report::error(
+            format!(
+                "Failed to build for {} target{}",
+                self.failures.len(),
+                if self.failures.len() == 1 { "" } else { "s" }
+            ),
+            self.failures
+                .iter()
+                .map(|(triple, err)| {
+                    let report = err.report();
+                    format!("{}: {}: {}", triple, report.msg(), report.details())
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+
+    fn code(&self) -> &'static str {
+        "android.parallel.failed"
+    }
+}
+
+/// How many targets to build at once when the user doesn't say.
+pub fn default_jobs(target_count: usize) -> usize {
+    target_count.min(2).max(1)
+}
+
+// At default verbosity, each build's output is held back and printed in one
+// piece once it's done, since interleaved compiler output is unreadable.
+// Otherwise it's streamed with a prefix on every line, or as progress events
+// if those are on.
+fn build_lib(
+    target: &Target<'_>,
+    config: &Config,
+    metadata: &Metadata,
+    env: &Env,
+    noise_level: NoiseLevel,
+    force_color: ForceColor,
+    profile: Profile,
+) -> Result<(), CompileLibError> {
+    let triple = target.triple;
+    let mut held = Vec::new();
+    let result = target.compile_lib_with_output(
+        config,
+        metadata,
+        env,
+        noise_level,
+        force_color,
+        profile,
+        |line| {
+            let line = String::from_utf8_lossy(line);
+            if progress::enabled() {
+                progress::output(Some(triple), &line);
+            } else if line.contains(LOCK_WAIT) {
+                ui::status(format!(
+                    "[{}] Waiting for another build to release the cargo lock...",
+                    triple
+                ));
+            } else if noise_level.polite() {
+                held.push(line.into_owned());
+            } else {
+                ui::status(format!("[{}] {}", triple, line));
+            }
+        },
+    );
+    // Held lines go out as one message, so other builds can't interleave.
+    if !held.is_empty() {
+        let held = held
+            .iter()
+            .map(|line| format!("[{}] {}", triple, line))
+            .collect::<Vec<_>>()
+            .join("\n");
+        ui::status(held);
+    }
+    result
+}
+
+/// Builds for several targets at once, running up to `jobs` cargo invocations
+/// concurrently. Libs are only symlinked into place once every build has
+/// finished, and every failure is reported rather than just the first.
+pub fn build(
+    targets: &[&Target<'_>],
+    jobs: usize,
+    config: &Config,
+    metadata: &Metadata,
+    env: &Env,
+    noise_level: NoiseLevel,
+    force_color: ForceColor,
+    profile: Profile,
+    skip_strip: SkipStrip,
+    force: Force,
+) -> Result<(), Error> {
+    let (tx, rx) = mpsc::channel();
+    let mut failures = Vec::new();
+    let mut built = Vec::new();
+    thread::scope(|scope| {
+        let mut pending = targets.iter();
+        let mut running = 0;
+        loop {
+            while running < jobs.max(1) {
+                let target = match pending.next() {
+                    Some(target) => target,
+                    None => break,
+                };
+                ui::status(format!("[{}] Building...", target.triple));
+                let tx = tx.clone();
+                scope.spawn(move || {
+                    let result = build_lib(
+                        target,
+                        config,
+                        metadata,
+                        env,
+                        noise_level,
+                        force_color,
+                        profile,
+                    );
+                    let _ = tx.send((target.triple, result));
+                });
+                running += 1;
+            }
+            if running == 0 {
+                break;
+            }
+            let (triple, result) = rx
+                .recv()
+                .expect("developer error: build thread hung up without reporting");
+            running -= 1;
+            match result {
+                Ok(()) => built.push(triple),
+                Err(err) => failures.push((triple.to_owned(), BuildError::BuildFailed(err))),
+            }
+        }
+    });
+    for target in targets {
+        if built.contains(&target.triple) {
+            if let Err(err) = progress::phase(Phase::CopyLibs, Some(target.triple), || {
+                target.symlink_libs(config, metadata, &env.ndk, profile, skip_strip, force)
+            }) {
+                failures.push((target.triple.to_owned(), BuildError::SymlinkLibsFailed(err)));
+            }
+        }
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        // Keep the report in the order the targets were given in.
+        failures.sort_by_key(|(triple, _)| {
+            targets
+                .iter()
+                .position(|target| target.triple == triple.as_str())
+        });
+        Err(Error { failures })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(target_count, expected, case(0, 1), case(1, 1), case(2, 2), case(4, 2))]
+    fn test_default_jobs(target_count: usize, expected: usize) {
+        assert_eq!(default_jobs(target_count), expected);
+    }
+}
@@ -1,29 +1,43 @@
 use super::{config::Config, env::Env, ndk, target::Target};
 use crate::{
+    assets,
+    config::variant::Variant,
     dot_cargo,
+    plan::Writer,
     target::TargetTrait as _,
     templating::{self, Pack},
+    ui,
     util::{
         self,
         cli::{Report, Reportable},
         ln,
+        progress::{self, Phase},
     },
+    version::{self, Version},
 };
-use std::{fs, path::PathBuf};
+use std::path::{Path, PathBuf};
 
 pub static TEMPLATE_PACK: &str = "android-studio";
+pub static LIBRARY_TEMPLATE_PACK: &str = "android-studio-module";
 
 #[derive(Debug)]
 pub enum Error {
     RustupFailed(bossy::Error),
     MissingPack(templating::LookupError),
-    TemplateProcessingFailed(bicycle::ProcessingError),
+    TemplateProcessingFailed(templating::ProcessingError),
     DirectoryCreationFailed {
         path: PathBuf,
         cause: std::io::Error,
     },
     AssetDirSymlinkFailed(ln::Error),
+    AssetIgnoreFailed(assets::Error),
+    VariantIconCopyFailed {
+        src: PathBuf,
+        dest: PathBuf,
+        cause: std::io::Error,
+    },
     DotCargoGenFailed(ndk::MissingToolError),
+    VersionFailed(version::Error),
 }
 
 impl Reportable for Error {
@@ -41,35 +55,80 @@ impl Reportable for Error {
             Self::AssetDirSymlinkFailed(err) => {
                 Report::error("Asset dir couldn't be symlinked into Android project", err)
             }
+            Self::AssetIgnoreFailed(err) => err.report(),
+            Self::VariantIconCopyFailed { src, dest, cause } => Report::error(
+                "Failed to copy variant icon into Android project",
+                format!("Failed to copy {:?} to {:?}: {}", src, dest, cause),
+            ),
             Self::DotCargoGenFailed(err) => {
                 Report::error("Failed to generate Android cargo config", err)
             }
+            Self::VersionFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::RustupFailed(..) => "android.project.rustup-failed",
+            Self::MissingPack(..) => "android.project.missing-pack",
+            Self::TemplateProcessingFailed(..) => "android.project.template-processing-failed",
+            Self::DirectoryCreationFailed { .. } => "android.project.directory-creation-failed",
+            Self::AssetDirSymlinkFailed(..) => "android.project.asset-dir-symlink-failed",
+            Self::AssetIgnoreFailed(err) => err.code(),
+            Self::VariantIconCopyFailed { .. } => "android.project.variant-icon-copy-failed",
+            Self::DotCargoGenFailed(..) => "android.project.dot-cargo-gen-failed",
+            Self::VersionFailed(err) => err.code(),
         }
     }
 }
 
-pub fn gen(
+/// The launcher icon resource for a variant with its own icon.
+pub fn variant_icon(variant: &Variant) -> String {
+    use heck::SnekCase as _;
+    format!("ic_launcher_{}", variant.name().to_snek_case())
+}
+
+// In library mode, everything we generate stays inside of the module
+// directory, since the rest of the host project isn't ours to touch.
+pub fn template_dest(config: &Config) -> PathBuf {
+    if config.library().is_some() {
+        config.module_dir()
+    } else {
+        config.project_dir()
+    }
+}
+
+/// Renders the project's templates into `out_dir` as though it were
+/// [`template_dest`], without any of the rest of what generating the project
+/// involves.
+pub fn render(
     config: &Config,
-    env: &Env,
+    version: &Version,
     bike: &bicycle::Bicycle,
     filter: &templating::Filter,
-    dot_cargo: &mut dot_cargo::DotCargo,
+    out_dir: &Path,
+    writer: Writer,
 ) -> Result<(), Error> {
-    println!("Installing Android toolchains...");
-    Target::install_all().map_err(Error::RustupFailed)?;
-    println!("Generating Android Studio project...");
-    let src = Pack::lookup_platform(TEMPLATE_PACK)
+    let pack = if config.library().is_some() {
+        LIBRARY_TEMPLATE_PACK
+    } else {
+        TEMPLATE_PACK
+    };
+    let dest = template_dest(config);
+    let src = Pack::lookup_platform(pack)
         .map_err(Error::MissingPack)?
         .expect_local();
-    let dest = config.project_dir();
-    bike.filter_and_process(
-        src,
-        &dest,
+    let ignore = assets::Ignore::load(config.app()).map_err(Error::AssetIgnoreFailed)?;
+    templating::process(
+        bike,
+        &src,
+        out_dir,
         |map| {
             map.insert(
                 "root-dir-rel",
-                util::relativize_path(config.app().root_dir(), config.project_dir()),
+                util::relativize_path(config.app().root_dir(), &dest),
             );
+            map.insert("version", version);
             map.insert("targets", Target::all().values().collect::<Vec<_>>());
             map.insert("target-names", Target::all().keys().collect::<Vec<_>>());
             map.insert(
@@ -79,29 +138,115 @@ pub fn gen(
                     .map(|target| target.arch)
                     .collect::<Vec<_>>(),
             );
+            map.insert("ignored-assets", ignore.packaged_globs());
         },
-        filter.fun(),
+        filter,
+        writer,
     )
-    .map_err(Error::TemplateProcessingFailed)?;
+    .map_err(Error::TemplateProcessingFailed)
+}
+
+/// Adds each target's linker settings to the app's cargo config, which is only
+/// done when asked to, since they're absolute paths into this machine's NDK.
+pub fn insert_cargo_config(
+    config: &Config,
+    env: &Env,
+    dot_cargo: &mut dot_cargo::DotCargo,
+) -> Result<(), ndk::MissingToolError> {
+    for target in Target::all().values() {
+        dot_cargo.insert_target(
+            target.triple.to_owned(),
+            target.generate_cargo_config(config, env)?,
+        );
+    }
+    Ok(())
+}
 
-    let dest = dest.join("app/src/main/assets/");
-    fs::create_dir_all(&dest).map_err(|cause| Error::DirectoryCreationFailed {
-        path: dest.clone(),
-        cause,
-    })?;
-    ln::force_symlink_relative(config.app().asset_dir(), dest, ln::TargetStyle::Directory)
+pub fn gen(
+    config: &Config,
+    env: &Env,
+    version: &Version,
+    bike: &bicycle::Bicycle,
+    filter: &templating::Filter,
+    dot_cargo: &mut dot_cargo::DotCargo,
+    writer: Writer,
+) -> Result<(), Error> {
+    ui::status(format!("Installing Android toolchains..."));
+    writer
+        .run("install Android toolchains with `rustup`", || {
+            progress::phase(Phase::RustupTargets, None, Target::install_all)
+        })
+        .map_err(Error::RustupFailed)?;
+    if let Some(library) = config.library() {
+        ui::status(format!(
+            "Generating Gradle module {:?} in {:?}...",
+            library.module(),
+            library.host_project_dir()
+        ));
+    } else {
+        ui::status(format!("Generating Android Studio project..."));
+        // The host project owns its `versionCode` in library mode.
+        version
+            .check_and_record(&config.project_dir(), writer)
+            .map_err(Error::VersionFailed)?;
+    }
+    render(
+        config,
+        version,
+        bike,
+        filter,
+        &template_dest(config),
+        writer,
+    )?;
+
+    let dest = config.module_dir().join("src/main/assets/");
+    writer
+        .create_dir_all(&dest)
+        .map_err(|cause| Error::DirectoryCreationFailed {
+            path: dest.clone(),
+            cause,
+        })?;
+    let asset_dir = config.app().asset_dir();
+    writer
+        .link(
+            &asset_dir,
+            &dest.join(asset_dir.file_name().unwrap_or_default()),
+            || ln::force_symlink_relative(&asset_dir, &dest, ln::TargetStyle::Directory),
+        )
         .map_err(Error::AssetDirSymlinkFailed)?;
 
-    {
-        for target in Target::all().values() {
-            dot_cargo.insert_target(
-                target.triple.to_owned(),
-                target
-                    .generate_cargo_config(config, &env)
-                    .map_err(Error::DotCargoGenFailed)?,
-            );
+    // Launchers scale icons down just fine, so there's no need to generate
+    // every density. The host app's icons aren't ours to mess with.
+    if config.library().is_none() {
+        let dir = config.module_dir().join("src/main/res/mipmap-xxxhdpi");
+        for variant in config.app().variants() {
+            if let Some(icon) = variant.icon() {
+                writer
+                    .create_dir_all(&dir)
+                    .map_err(|cause| Error::DirectoryCreationFailed {
+                        path: dir.clone(),
+                        cause,
+                    })?;
+                let src = config.app().prefix_path(icon);
+                let dest = dir.join(format!("{}.png", variant_icon(variant)));
+                writer
+                    .copy(&src, &dest)
+                    .map_err(|cause| Error::VariantIconCopyFailed { src, dest, cause })?;
+            }
         }
     }
 
+    if config.cargo_config() {
+        insert_cargo_config(config, env, dot_cargo).map_err(Error::DotCargoGenFailed)?;
+    }
+
+    if let Some(library) = config.library() {
+        ui::status(format!(
+            "To finish integrating, add the module to your host project's `settings.gradle`:\n    include(\":{module}\")\nand depend on it in `{app_module}/build.gradle`:\n    dependencies {{\n        implementation(project(\":{module}\"))\n    }}",
+            module = library.module(),
+            app_module = library.app_module(),
+        ));
+    }
+
     Ok(())
 }
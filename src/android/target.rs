@@ -5,17 +5,33 @@ use super::{
     ndk,
 };
 use crate::{
-    dot_cargo::DotCargoTarget,
-    opts::{ForceColor, NoiseLevel, Profile},
-    target::TargetTrait,
+    config::{build_config, rustflags},
+    device::Kind,
+    dot_cargo::{self, DotCargoTarget},
+    entry_point,
+    env::BuildEnv,
+    fingerprint::{Fingerprint, Store},
+    hooks::{self, Stage},
+    opts::{Force, ForceColor, NoHooks, NoiseLevel, Profile, SkipStrip},
+    resources,
+    sizes::{self, Sizes},
+    target::{self, TargetTrait},
+    ui,
     util::{
         cli::{Report, Reportable},
+        progress::{self, Phase},
+        watchdog::{self, Limits},
         CargoCommand,
     },
 };
 use once_cell_regex::exports::once_cell::sync::OnceCell;
 use serde::Serialize;
-use std::{collections::BTreeMap, fmt, io, str};
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    str,
+};
 
 #[derive(Clone, Copy, Debug)]
 pub enum CargoMode {
@@ -44,27 +60,43 @@ impl CargoMode {
 #[derive(Debug)]
 pub enum CompileLibError {
     MissingTool(ndk::MissingToolError),
+    BuildConfigFailed(build_config::ResolveError),
     CargoFailed {
         mode: CargoMode,
         cause: bossy::Error,
     },
+    Stalled(watchdog::Stalled),
 }
 
 impl Reportable for CompileLibError {
     fn report(&self) -> Report {
         match self {
             Self::MissingTool(err) => Report::error("Failed to locate required build tool", err),
+            Self::BuildConfigFailed(err) => err.report(),
             Self::CargoFailed { mode, cause } => {
                 Report::error(format!("`Failed to run `cargo {}`", mode), cause)
             }
+            Self::Stalled(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::MissingTool(..) => "android.target.compile-lib.missing-tool",
+            Self::BuildConfigFailed(err) => err.code(),
+            Self::CargoFailed { .. } => "android.target.compile-lib.cargo-failed",
+            Self::Stalled(err) => err.code(),
         }
     }
 }
 
 #[derive(Debug)]
 pub enum SymlinkLibsError {
+    LibMissing { path: PathBuf, lib_name: String },
+    EntryPointMissing(entry_point::Error),
     JniLibsCreationFailed(io::Error),
     SymlinkFailed(jnilibs::SymlinkLibError),
+    StripFailed(jnilibs::StripLibError),
     RequiredLibsFailed(ndk::RequiredLibsError),
     LibcxxSharedPathFailed(ndk::MissingToolError),
 }
@@ -72,16 +104,41 @@ pub enum SymlinkLibsError {
 impl Reportable for SymlinkLibsError {
     fn report(&self) -> Report {
         match self {
+            Self::LibMissing { path, lib_name } => Report::action_request(
+                "The built lib wasn't where it was expected",
+                format!(
+                    "Nothing was found at {:?}, which is where cargo puts a lib named \"{}\". If that's not what your lib is called, check `[lib] name` in `Cargo.toml`; otherwise, make sure `crate-type` under `[lib]` includes `\"cdylib\"`.",
+                    path, lib_name
+                ),
+            ),
+            Self::EntryPointMissing(err) => err.report(),
             Self::JniLibsCreationFailed(err) => {
                 Report::error("Failed to create \"jniLibs\" directory", err)
             }
             Self::SymlinkFailed(err) => err.report(),
+            Self::StripFailed(err) => err.report(),
             Self::RequiredLibsFailed(err) => err.report(),
             Self::LibcxxSharedPathFailed(err) => {
                 Report::error("Failed to locate \"libc++_shared.so\"", err)
             }
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::LibMissing { .. } => "android.target.symlink-libs.lib-missing",
+            Self::EntryPointMissing(err) => err.code(),
+            Self::JniLibsCreationFailed(..) => {
+                "android.target.symlink-libs.jni-libs-creation-failed"
+            }
+            Self::SymlinkFailed(err) => err.code(),
+            Self::StripFailed(err) => err.code(),
+            Self::RequiredLibsFailed(err) => err.code(),
+            Self::LibcxxSharedPathFailed(..) => {
+                "android.target.symlink-libs.libcxx-shared-path-failed"
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -97,6 +154,35 @@ impl Reportable for BuildError {
             Self::SymlinkLibsFailed(err) => err.report(),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::BuildFailed(err) => err.code(),
+            Self::SymlinkLibsFailed(err) => err.code(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HookError {
+    BuildEnvFailed(ndk::MissingToolError),
+    HookFailed(hooks::Error),
+}
+
+impl Reportable for HookError {
+    fn report(&self) -> Report {
+        match self {
+            Self::BuildEnvFailed(err) => Report::error("Failed to locate required build tool", err),
+            Self::HookFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::BuildEnvFailed(..) => "android.target.hook.build-env-failed",
+            Self::HookFailed(err) => err.code(),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
@@ -111,6 +197,20 @@ pub struct Target<'a> {
 impl<'a> TargetTrait<'a> for Target<'a> {
     const DEFAULT_KEY: &'static str = "aarch64";
 
+    // Arches as Android names them, and ABIs as Gradle and `jniLibs` do.
+    const ALIASES: &'static [(&'static str, &'static str)] = &[
+        ("arm64", "aarch64"),
+        ("arm64-v8a", "aarch64"),
+        ("arm", "armv7"),
+        ("armeabi-v7a", "armv7"),
+        ("x86", "i686"),
+    ];
+
+    fn possible_values() -> &'static [&'static str] {
+        static VALUES: OnceCell<Vec<&'static str>> = OnceCell::new();
+        VALUES.get_or_init(target::possible_values::<Target<'static>>)
+    }
+
     fn all() -> &'a BTreeMap<&'a str, Self> {
         static TARGETS: OnceCell<BTreeMap<&'static str, Target<'static>>> = OnceCell::new();
         TARGETS.get_or_init(|| {
@@ -166,6 +266,15 @@ impl<'a> TargetTrait<'a> for Target<'a> {
     fn arch(&'a self) -> &'a str {
         self.arch
     }
+
+    // x86 Android devices are vanishingly rare, so those targets are really
+    // only for the emulator.
+    fn kind(&'a self) -> Kind {
+        match self.arch {
+            "x86" | "x86_64" => Kind::Virtual,
+            _ => Kind::Physical,
+        }
+    }
 }
 
 impl<'a> Target<'a> {
@@ -181,6 +290,32 @@ impl<'a> Target<'a> {
         Self::all().values().find(|target| target.abi == abi)
     }
 
+    fn linker(&self, config: &Config, env: &Env) -> Result<PathBuf, ndk::MissingToolError> {
+        // Using clang as the linker seems to be the only way to get the right library search paths...
+        env.ndk.compiler_path(
+            ndk::Compiler::Clang,
+            self.clang_triple(),
+            config.min_sdk_version(),
+        )
+    }
+
+    fn rustflags() -> Vec<String> {
+        vec![
+            "-Clink-arg=-landroid".to_owned(),
+            "-Clink-arg=-llog".to_owned(),
+            "-Clink-arg=-lOpenSLES".to_owned(),
+        ]
+    }
+
+    // i.e. `CARGO_TARGET_AARCH64_LINUX_ANDROID_LINKER`
+    fn cargo_var(&self, key: &str) -> String {
+        format!(
+            "CARGO_TARGET_{}_{}",
+            self.triple.to_uppercase().replace('-', "_"),
+            key
+        )
+    }
+
     pub fn generate_cargo_config(
         &self,
         config: &Config,
@@ -191,28 +326,118 @@ impl<'a> Target<'a> {
             .binutil_path(ndk::Binutil::Ar, self.binutils_triple())?
             .display()
             .to_string();
-        // Using clang as the linker seems to be the only way to get the right library search paths...
-        let linker = env
-            .ndk
-            .compiler_path(
-                ndk::Compiler::Clang,
-                self.clang_triple(),
-                config.min_sdk_version(),
-            )?
-            .display()
-            .to_string();
+        let linker = self.linker(config, env)?.display().to_string();
         Ok(DotCargoTarget {
             ar: Some(ar),
             linker: Some(linker),
-            rustflags: vec![
-                "-Clink-arg=-landroid".to_owned(),
-                "-Clink-arg=-llog".to_owned(),
-                "-Clink-arg=-lOpenSLES".to_owned(),
-            ],
+            rustflags: Self::rustflags(),
         })
     }
 
-    fn compile_lib(
+    /// The linker we'd use, and the one cargo's config files say to use
+    /// instead (along with which file says so) if they say anything.
+    pub fn configured_linker(
+        &self,
+        config: &Config,
+        env: &Env,
+    ) -> Result<(PathBuf, Option<(String, PathBuf)>), ndk::MissingToolError> {
+        let configured = dot_cargo::configured_target(config.app().root_dir(), self.triple);
+        Ok((self.linker(config, env)?, configured.linker))
+    }
+
+    /// Everything cargo is run with when building for this target, which
+    /// includes the config's `env`.
+    pub fn build_env(&self, config: &Config, env: &Env) -> Result<BuildEnv, ndk::MissingToolError> {
+        let min_sdk_version = config.min_sdk_version();
+        let mut build_env = BuildEnv::new(env)
+            .with_var("ANDROID_NATIVE_API_LEVEL", min_sdk_version.to_string())
+            .with_var(
+                "TARGET_AR",
+                env.ndk
+                    .binutil_path(ndk::Binutil::Ar, self.binutils_triple())?,
+            )
+            .with_var(
+                "TARGET_CC",
+                env.ndk.compiler_path(
+                    ndk::Compiler::Clang,
+                    self.clang_triple(),
+                    min_sdk_version,
+                )?,
+            )
+            .with_var(
+                "TARGET_CXX",
+                env.ndk.compiler_path(
+                    ndk::Compiler::Clangxx,
+                    self.clang_triple(),
+                    min_sdk_version,
+                )?,
+            );
+        // Whatever the cargo config says wins, so that building with us and
+        // building with plain cargo can't end up linking differently.
+        let dir = std::env::current_dir().unwrap_or_else(|_| config.app().root_dir().to_owned());
+        let configured = dot_cargo::configured_target(&dir, self.triple);
+        if configured.linker.is_none() {
+            build_env.insert(self.cargo_var("LINKER"), self.linker(config, env)?);
+        }
+        if configured.rustflags.is_none() {
+            build_env.insert(self.cargo_var("RUSTFLAGS"), Self::rustflags().join(" "));
+        }
+        for (key, value) in config.env() {
+            build_env.insert(key.as_str(), value);
+        }
+        // This overrides all of the above, so they're merged into it.
+        let base = match configured.rustflags {
+            Some((flags, path)) => (format!("cargo config at {:?}", path), flags),
+            None => ("defaults".to_owned(), Self::rustflags()),
+        };
+        if let Some(encoded) = config
+            .rustflags()
+            .encoded(self.triple, Some(base), config.env())
+        {
+            build_env.insert(rustflags::VAR, encoded);
+        }
+        Ok(build_env)
+    }
+
+    /// Runs the configured hooks for `stage` with this target's build env.
+    /// Post-build hooks get the lib as their artifact unless told otherwise.
+    pub fn run_hooks(
+        &self,
+        config: &Config,
+        env: &Env,
+        stage: Stage,
+        profile: Profile,
+        no_hooks: NoHooks,
+        artifact: Option<&Path>,
+    ) -> Result<(), HookError> {
+        let hooks = config.hooks().unless(no_hooks);
+        if !hooks.has(stage) {
+            return Ok(());
+        }
+        let build_env = self
+            .build_env(config, env)
+            .map_err(HookError::BuildEnvFailed)?;
+        let lib_path = self.lib_path(config, profile);
+        let artifact = artifact.or_else(|| {
+            if stage == Stage::PostBuild {
+                Some(lib_path.as_path())
+            } else {
+                None
+            }
+        });
+        hooks
+            .run(
+                stage,
+                config.app().root_dir(),
+                &build_env,
+                self.triple,
+                profile,
+                artifact,
+            )
+            .map_err(HookError::HookFailed)
+    }
+
+    fn cargo_command(
         &self,
         config: &Config,
         metadata: &Metadata,
@@ -221,12 +446,19 @@ impl<'a> Target<'a> {
         force_color: ForceColor,
         profile: Profile,
         mode: CargoMode,
-    ) -> Result<(), CompileLibError> {
-        let min_sdk_version = config.min_sdk_version();
+    ) -> Result<bossy::Command, CompileLibError> {
+        let mut build_env = self
+            .build_env(config, env)
+            .map_err(CompileLibError::MissingTool)?;
+        config
+            .build_config()
+            .resolve(profile)
+            .map_err(CompileLibError::BuildConfigFailed)?
+            .apply(&mut build_env);
         // Force color, since gradle would otherwise give us uncolored output
         // (which Android Studio makes red, which is extra gross!)
         let color = if force_color.yes() { "always" } else { "auto" };
-        CargoCommand::new(mode.as_str())
+        Ok(CargoCommand::new(mode.as_str())
             .with_verbose(noise_level.pedantic())
             .with_package(Some(config.app().name()))
             .with_manifest_path(Some(config.app().manifest_path()))
@@ -234,30 +466,85 @@ impl<'a> Target<'a> {
             .with_no_default_features(metadata.no_default_features())
             .with_features(metadata.features())
             .with_release(profile.release())
-            .into_command_pure(env)
-            .with_env_var("ANDROID_NATIVE_API_LEVEL", min_sdk_version.to_string())
-            .with_env_var(
-                "TARGET_AR",
-                env.ndk
-                    .binutil_path(ndk::Binutil::Ar, self.binutils_triple())
-                    .map_err(CompileLibError::MissingTool)?,
-            )
-            .with_env_var(
-                "TARGET_CC",
-                env.ndk
-                    .compiler_path(ndk::Compiler::Clang, self.clang_triple(), min_sdk_version)
-                    .map_err(CompileLibError::MissingTool)?,
-            )
-            .with_env_var(
-                "TARGET_CXX",
-                env.ndk
-                    .compiler_path(ndk::Compiler::Clangxx, self.clang_triple(), min_sdk_version)
-                    .map_err(CompileLibError::MissingTool)?,
+            .with_jobs(resources::current().jobs())
+            .into_command_pure(&build_env)
+            .with_args(&["--color", color]))
+    }
+
+    // `run` is what runs cargo, which is up to the watchdog either way.
+    fn run_cargo(
+        &self,
+        config: &Config,
+        metadata: &Metadata,
+        env: &Env,
+        noise_level: NoiseLevel,
+        force_color: ForceColor,
+        profile: Profile,
+        mode: CargoMode,
+        run: impl FnOnce(bossy::Command) -> Result<(), watchdog::Error>,
+    ) -> Result<(), CompileLibError> {
+        let command = self.cargo_command(
+            config,
+            metadata,
+            env,
+            noise_level,
+            force_color,
+            profile,
+            mode,
+        )?;
+        progress::phase(Phase::Cargo, Some(self.triple), || run(command)).map_err(|err| {
+            err.split(
+                |cause| CompileLibError::CargoFailed { mode, cause },
+                CompileLibError::Stalled,
             )
-            .with_args(&["--color", color])
-            .run_and_wait()
-            .map_err(|cause| CompileLibError::CargoFailed { mode, cause })?;
-        Ok(())
+        })
+    }
+
+    fn compile_lib(
+        &self,
+        config: &Config,
+        metadata: &Metadata,
+        env: &Env,
+        noise_level: NoiseLevel,
+        force_color: ForceColor,
+        profile: Profile,
+        mode: CargoMode,
+    ) -> Result<(), CompileLibError> {
+        self.run_cargo(
+            config,
+            metadata,
+            env,
+            noise_level,
+            force_color,
+            profile,
+            mode,
+            |command| watchdog::run_and_wait(command, Limits::COMPILE),
+        )
+    }
+
+    /// Builds the lib just like [`Target::build`] does, but passes each line
+    /// cargo prints to `on_line` instead of through, for when several targets
+    /// are built at once. The lib still has to be symlinked into place.
+    pub(super) fn compile_lib_with_output(
+        &self,
+        config: &Config,
+        metadata: &Metadata,
+        env: &Env,
+        noise_level: NoiseLevel,
+        force_color: ForceColor,
+        profile: Profile,
+        on_line: impl FnMut(&[u8]) + Send,
+    ) -> Result<(), CompileLibError> {
+        self.run_cargo(
+            config,
+            metadata,
+            env,
+            noise_level,
+            force_color,
+            profile,
+            CargoMode::Build,
+            |command| watchdog::run_and_wait_with_stderr(command, Limits::COMPILE, on_line),
+        )
     }
 
     pub fn check(
@@ -279,24 +566,111 @@ impl<'a> Target<'a> {
         )
     }
 
+    fn lib_path(&self, config: &Config, profile: Profile) -> PathBuf {
+        config.app().prefix_path(format!(
+            "target/{}/{}/{}",
+            &self.triple,
+            profile.as_str(),
+            config.so_name(),
+        ))
+    }
+
+    // Covers the lib and everything in this target's `jniLibs` dir, so a
+    // rebuilt lib or a deleted symlink are both noticed.
+    fn jnilibs_fingerprint(
+        &self,
+        config: &Config,
+        metadata: &Metadata,
+        profile: Profile,
+        skip_strip: SkipStrip,
+    ) -> Fingerprint {
+        Fingerprint::new()
+            .with_value("profile", profile.as_str())
+            .with_value("strip", profile.release() && skip_strip.no())
+            .with_value(
+                "features",
+                metadata
+                    .features()
+                    .map(|features| features.join(","))
+                    .unwrap_or_default(),
+            )
+            .with_value("no-default-features", metadata.no_default_features())
+            .with_file(self.lib_path(config, profile))
+            .with_dir(jnilibs::path(config, *self))
+    }
+
+    /// Measures the lib in this target's `jniLibs` dir, which is what Gradle
+    /// packages, along with the unstripped copy if it was stripped. The lib's
+    /// only a symlink if it wasn't, in which case any unstripped copy is left
+    /// over from an earlier build.
+    pub fn measure_lib(&self, config: &Config, sizes: &mut Sizes) -> Result<(), sizes::Error> {
+        let so_name = config.so_name();
+        let path = jnilibs::path(config, *self).join(&so_name);
+        let stripped = std::fs::symlink_metadata(&path)
+            .map(|metadata| !metadata.file_type().is_symlink())
+            .unwrap_or_default();
+        let unstripped = path.with_file_name(format!("{}.debug", so_name));
+        sizes.measure(
+            format!("{} ({})", so_name, self.abi),
+            &path,
+            Some(unstripped.as_path()).filter(|_| stripped),
+        )
+    }
+
     pub fn symlink_libs(
         &self,
         config: &Config,
+        metadata: &Metadata,
         ndk: &ndk::Env,
         profile: Profile,
+        skip_strip: SkipStrip,
+        force: Force,
     ) -> Result<(), SymlinkLibsError> {
+        let src = self.lib_path(config, profile);
+        if !src.is_file() {
+            return Err(SymlinkLibsError::LibMissing {
+                path: src,
+                lib_name: config.app().names().lib_name().to_owned(),
+            });
+        }
+
+        let store = Store::new(config.project_dir());
+        let step = format!("jnilibs-{}", self.triple);
+        if force.no()
+            && store.fresh(
+                &step,
+                &self.jnilibs_fingerprint(config, metadata, profile, skip_strip),
+            )
+        {
+            ui::status(format!("jniLibs for {} are up to date", self.triple));
+            return Ok(());
+        }
+        store.forget(&step);
+
+        match ndk.nm_path() {
+            Ok(nm) => entry_point::check(
+                bossy::Command::impure(nm).with_args(&["-D", "--defined-only"]),
+                src.clone(),
+                config.entry_symbols(),
+                false,
+                "android.entry-symbols",
+            )
+            .map_err(SymlinkLibsError::EntryPointMissing)?,
+            Err(err) => log::warn!("not checking for the lib's entry point: {}", err),
+        }
+
         let jnilibs =
             JniLibs::create(config, *self).map_err(SymlinkLibsError::JniLibsCreationFailed)?;
 
-        let src = config.app().prefix_path(format!(
-            "target/{}/{}/{}",
-            &self.triple,
-            profile.as_str(),
-            config.so_name(),
-        ));
-        jnilibs
-            .symlink_lib(&src)
-            .map_err(SymlinkLibsError::SymlinkFailed)?;
+        if profile.release() && skip_strip.no() {
+            jnilibs
+                .strip_lib(ndk, &src)
+                .map_err(SymlinkLibsError::StripFailed)?;
+        } else {
+            jnilibs
+                .symlink_lib(&src)
+                .map_err(SymlinkLibsError::SymlinkFailed)?;
+        }
 
         let needs_cxx_shared = ndk
             .required_libs(&src, self.binutils_triple())
@@ -312,6 +686,10 @@ impl<'a> Target<'a> {
                 .map_err(SymlinkLibsError::SymlinkFailed)?;
         }
 
+        store.record(
+            &step,
+            &self.jnilibs_fingerprint(config, metadata, profile, skip_strip),
+        );
         Ok(())
     }
 
@@ -323,6 +701,8 @@ impl<'a> Target<'a> {
         noise_level: NoiseLevel,
         force_color: ForceColor,
         profile: Profile,
+        skip_strip: SkipStrip,
+        force: Force,
     ) -> Result<(), BuildError> {
         self.compile_lib(
             config,
@@ -334,7 +714,9 @@ impl<'a> Target<'a> {
             CargoMode::Build,
         )
         .map_err(BuildError::BuildFailed)?;
-        self.symlink_libs(config, &env.ndk, profile)
-            .map_err(BuildError::SymlinkLibsFailed)
+        progress::phase(Phase::CopyLibs, Some(self.triple), || {
+            self.symlink_libs(config, metadata, &env.ndk, profile, skip_strip, force)
+        })
+        .map_err(BuildError::SymlinkLibsFailed)
     }
 }
@@ -0,0 +1,446 @@
+//! The core operations, for tools that embed cargo-mobile instead of running
+//! it and scraping its output. Nothing here prompts, prints, or exits: errors
+//! are returned as the same `Reportable` types the CLI reports, and messages
+//! go through [`ui`](crate::ui), which prints them unless told otherwise.
+//!
+//! [`init`] generates a project, and [`android`] and [`apple`] (on macOS)
+//! find devices and build for them.
+//!
+//! ```no_run
+//! use cargo_mobile::{
+//!     api::android::{self, BuildOptions},
+//!     ui,
+//!     util::cli::{Report, Reportable as _},
+//! };
+//!
+//! fn main() -> Result<(), Report> {
+//!     ui::set(ui::Logged);
+//!     let env = android::env().map_err(|err| err.report())?;
+//!     for device in android::devices(&env).map_err(|err| err.report())? {
+//!         println!("{} ({})", device, device.serial_no());
+//!     }
+//!     let (config, metadata) =
+//!         android::load_config("path/to/app", &[], None).map_err(|err| err.report())?;
+//!     android::ensure_init(&config).map_err(|err| err.report())?;
+//!     let targets = android::targets(&["aarch64"]).map_err(|err| err.report())?;
+//!     android::build(&config, &metadata, &env, &targets, BuildOptions::default())
+//!         .map_err(|err| err.report())?;
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{
+    config::{app::Platform, Config},
+    init, opts,
+    plan::Writer,
+    templating::PostInitPolicy,
+    util::cli,
+};
+use std::path::Path;
+
+#[derive(Clone, Debug, Default)]
+pub struct InitOptions {
+    /// Only used when there's no `mobile.toml` yet, which is made from
+    /// these and the dir's name.
+    pub name: Option<String>,
+    pub domain: Option<String>,
+    pub development_team: Option<String>,
+    pub template_pack: Option<String>,
+    /// Empty means every platform.
+    pub platforms: Vec<Platform>,
+    pub template_vars: Vec<(String, String)>,
+    /// Overwrites files outside of `gen` that were edited since they were
+    /// generated, instead of keeping them.
+    pub force: bool,
+    pub run_post_init_hooks: bool,
+}
+
+/// Generates the project in `dir`, making a `mobile.toml` first if there
+/// isn't one. Dev tools aren't installed and nothing is opened afterwards.
+pub fn init(dir: impl AsRef<Path>, options: InitOptions) -> Result<Config, init::Error> {
+    let InitOptions {
+        name,
+        domain,
+        development_team,
+        template_pack,
+        platforms,
+        template_vars,
+        force,
+        run_post_init_hooks,
+    } = options;
+    init::exec(
+        &cli::wrapper(None),
+        opts::NonInteractive::Yes,
+        opts::SkipDevTools::Yes,
+        opts::ReinstallDeps::No,
+        opts::OpenInEditor::No,
+        opts::NoGit::No,
+        opts::Force::from_bool(force),
+        None,
+        name,
+        domain,
+        development_team,
+        template_pack,
+        &platforms,
+        &template_vars,
+        if run_post_init_hooks {
+            PostInitPolicy::Run
+        } else {
+            PostInitPolicy::Skip
+        },
+        Writer::Disk,
+        dir,
+    )
+}
+
+pub mod android {
+    pub use crate::android::{
+        cli::Error,
+        config::{Config, Metadata},
+        device::Device,
+        env::Env,
+        target::Target,
+    };
+    use crate::{
+        android::{adb, parallel},
+        config::{metadata::Metadata as OmniMetadata, Config as OmniConfig},
+        hooks::Stage,
+        opts::{self, Force, NoHooks, NoiseLevel, Profile, SkipStrip},
+        resources,
+        sizes::Sizes,
+        target::parse_target,
+    };
+    use std::path::Path;
+
+    pub fn env() -> Result<Env, Error> {
+        Env::new().map_err(Error::EnvInitFailed)
+    }
+
+    /// Every device that's connected and ready to use.
+    pub fn devices(env: &Env) -> Result<Vec<Device<'static>>, Error> {
+        adb::device_list(env)
+            .map(|devices| devices.into_iter().collect())
+            .map_err(Error::ListFailed)
+    }
+
+    /// The Android half of `config`, with `features` (on top of the config's
+    /// own) and `variant` applied. It's an error if Android is marked as
+    /// unsupported.
+    pub fn from_config(
+        config: &OmniConfig,
+        features: &[String],
+        variant: Option<&str>,
+    ) -> Result<(Config, Metadata), Error> {
+        let variant = config
+            .app()
+            .variant(variant)
+            .map_err(Error::VariantFailed)?
+            .cloned();
+        let metadata =
+            OmniMetadata::load(&config.app().root_dir()).map_err(Error::MetadataFailed)?;
+        let config = config
+            .android()
+            .clone()
+            .with_cli_features(features.to_vec())
+            .with_variant(variant);
+        let metadata = metadata.android().clone().with_config(&config);
+        if metadata.supported() {
+            Ok((config, metadata))
+        } else {
+            Err(Error::Unsupported)
+        }
+    }
+
+    /// Loads the config for the app in `dir`, which has to exist already; see
+    /// [`from_config`] for the rest.
+    pub fn load_config(
+        dir: impl AsRef<Path>,
+        features: &[String],
+        variant: Option<&str>,
+    ) -> Result<(Config, Metadata), Error> {
+        let config = OmniConfig::check(dir).map_err(Error::ConfigCheckFailed)?;
+        from_config(&config, features, variant)
+    }
+
+    /// Makes sure the project has been generated.
+    pub fn ensure_init(config: &Config) -> Result<(), Error> {
+        if !config.project_dir_exists() {
+            Err(Error::ProjectDirAbsent {
+                project_dir: config.project_dir(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Targets by name, alias, or triple.
+    pub fn targets(names: &[impl AsRef<str>]) -> Result<Vec<&'static Target<'static>>, Error> {
+        names
+            .iter()
+            .map(|name| parse_target(name.as_ref()))
+            .collect::<Result<_, _>>()
+            .map_err(Error::TargetInvalid)
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct BuildOptions {
+        pub noise_level: NoiseLevel,
+        pub profile: Profile,
+        pub skip_strip: SkipStrip,
+        pub force: Force,
+        pub no_hooks: NoHooks,
+        /// How many targets to build at once; `None` picks for you.
+        pub jobs: Option<usize>,
+    }
+
+    /// A debug build that skips whatever's up to date.
+    impl Default for BuildOptions {
+        fn default() -> Self {
+            Self {
+                noise_level: NoiseLevel::Polite,
+                profile: Profile::Debug,
+                skip_strip: SkipStrip::No,
+                force: Force::No,
+                no_hooks: NoHooks::No,
+                jobs: None,
+            }
+        }
+    }
+
+    /// Builds the libs for `targets` and links them into the project, running
+    /// the build hooks around them. Returns the sizes of what was built, which
+    /// `Sizes::summarize` compares against the last build.
+    pub fn build(
+        config: &Config,
+        metadata: &Metadata,
+        env: &Env,
+        targets: &[&Target<'_>],
+        options: BuildOptions,
+    ) -> Result<Sizes, Error> {
+        let BuildOptions {
+            noise_level,
+            profile,
+            skip_strip,
+            force,
+            no_hooks,
+            jobs,
+        } = options;
+        let force_color = opts::ForceColor::Yes;
+        let run_hooks = |stage: Stage| {
+            targets.iter().try_for_each(|target| {
+                target
+                    .run_hooks(config, env, stage, profile, no_hooks, None)
+                    .map_err(Error::HookFailed)
+            })
+        };
+        // Each build at once needs its own memory, which a constrained
+        // machine doesn't have to spare.
+        let jobs = jobs.unwrap_or_else(|| {
+            if resources::current().constrained() {
+                1
+            } else {
+                parallel::default_jobs(targets.len())
+            }
+        });
+        if jobs > 1 && targets.len() > 1 {
+            run_hooks(Stage::PreBuild)?;
+            parallel::build(
+                targets,
+                jobs,
+                config,
+                metadata,
+                env,
+                noise_level,
+                force_color,
+                profile,
+                skip_strip,
+                force,
+            )
+            .map_err(Error::ParallelBuildFailed)?;
+            run_hooks(Stage::PostBuild)?;
+        } else {
+            for target in targets {
+                target
+                    .run_hooks(config, env, Stage::PreBuild, profile, no_hooks, None)
+                    .map_err(Error::HookFailed)?;
+                target
+                    .build(
+                        config,
+                        metadata,
+                        env,
+                        noise_level,
+                        force_color,
+                        profile,
+                        skip_strip,
+                        force,
+                    )
+                    .map_err(Error::BuildFailed)?;
+                target
+                    .run_hooks(config, env, Stage::PostBuild, profile, no_hooks, None)
+                    .map_err(Error::HookFailed)?;
+            }
+        }
+        let mut sizes = Sizes::new();
+        for target in targets {
+            target
+                .measure_lib(config, &mut sizes)
+                .map_err(Error::SizesFailed)?;
+        }
+        Ok(sizes)
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub mod apple {
+    pub use crate::{
+        apple::{
+            cli::Error,
+            config::{Config, Metadata},
+            device::Device,
+            target::Target,
+        },
+        env::Env,
+    };
+    use crate::{
+        apple::{device, packages, profiles, target::check_build_std, xcode},
+        config::{metadata::Metadata as OmniMetadata, Config as OmniConfig},
+        hooks::Stage,
+        opts::{Force, NoHooks, NoiseLevel, Profile},
+        sizes::Sizes,
+        target::parse_target,
+    };
+    use std::path::Path;
+
+    pub fn env() -> Result<Env, Error> {
+        Env::new().map_err(Error::EnvInitFailed)
+    }
+
+    /// Every device that's connected and ready to use, not counting
+    /// simulators.
+    pub fn devices(env: &Env) -> Result<Vec<Device<'static>>, Error> {
+        device::device_list(env)
+            .map(|devices| devices.into_iter().collect())
+            .map_err(Error::ListFailed)
+    }
+
+    /// The Apple half of `config`, with `features` (on top of the config's
+    /// own) and `variant` applied. It's an error if iOS is marked as
+    /// unsupported.
+    pub fn from_config(
+        config: &OmniConfig,
+        features: &[String],
+        variant: Option<&str>,
+    ) -> Result<(Config, Metadata), Error> {
+        let variant = config
+            .app()
+            .variant(variant)
+            .map_err(Error::VariantFailed)?
+            .cloned();
+        let metadata =
+            OmniMetadata::load(&config.app().root_dir()).map_err(Error::MetadataFailed)?;
+        let config = config
+            .apple()
+            .clone()
+            .with_cli_features(features.to_vec())
+            .with_variant(variant);
+        let metadata = metadata.apple().clone().with_config(&config);
+        if metadata.supported() {
+            Ok((config, metadata))
+        } else {
+            Err(Error::Unsupported)
+        }
+    }
+
+    /// Loads the config for the app in `dir`, which has to exist already; see
+    /// [`from_config`] for the rest.
+    pub fn load_config(
+        dir: impl AsRef<Path>,
+        features: &[String],
+        variant: Option<&str>,
+    ) -> Result<(Config, Metadata), Error> {
+        let config = OmniConfig::check(dir).map_err(Error::ConfigCheckFailed)?;
+        from_config(&config, features, variant)
+    }
+
+    /// Makes sure the project has been generated.
+    pub fn ensure_init(config: &Config) -> Result<(), Error> {
+        if !config.project_dir_exists() {
+            Err(Error::ProjectDirAbsent {
+                project_dir: config.project_dir(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Targets by name, alias, or triple.
+    pub fn targets(names: &[impl AsRef<str>]) -> Result<Vec<&'static Target<'static>>, Error> {
+        names
+            .iter()
+            .map(|name| parse_target(name.as_ref()))
+            .collect::<Result<_, _>>()
+            .map_err(Error::TargetInvalid)
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct BuildOptions {
+        pub noise_level: NoiseLevel,
+        pub profile: Profile,
+        pub force: Force,
+        pub no_hooks: NoHooks,
+    }
+
+    /// A debug build that skips whatever's up to date.
+    impl Default for BuildOptions {
+        fn default() -> Self {
+            Self {
+                noise_level: NoiseLevel::Polite,
+                profile: Profile::Debug,
+                force: Force::No,
+                no_hooks: NoHooks::No,
+            }
+        }
+    }
+
+    /// Checks Xcode and resolves Swift packages, then builds the app for
+    /// `targets` with `xcodebuild`, running the build hooks around each.
+    /// Returns the sizes of what was built, which `Sizes::summarize` compares
+    /// against the last build.
+    pub fn build(
+        config: &Config,
+        env: &Env,
+        targets: &[&Target<'_>],
+        options: BuildOptions,
+    ) -> Result<Sizes, Error> {
+        let BuildOptions {
+            noise_level,
+            profile,
+            force,
+            no_hooks,
+        } = options;
+        // Missing or mis-selected Xcodes otherwise fail much later, with
+        // misleading errors about missing SDKs.
+        xcode::check(env, config.min_xcode_version()).map_err(Error::XcodeCheckFailed)?;
+        check_build_std(config).map_err(Error::BuildStdCheckFailed)?;
+        packages::resolve(config, env).map_err(Error::PackageResolveFailed)?;
+        let mut sizes = Sizes::new();
+        for target in targets {
+            if !target.is_simulator() {
+                profiles::check(config, None).map_err(Error::ProfileCheckFailed)?;
+            }
+            target
+                .run_hooks(config, env, Stage::PreBuild, profile, no_hooks, None)
+                .map_err(Error::HookFailed)?;
+            target
+                .build(config, env, noise_level, profile, force)
+                .map_err(Error::BuildFailed)?;
+            target
+                .measure(config, profile, &mut sizes)
+                .map_err(Error::SizesFailed)?;
+            target
+                .run_hooks(config, env, Stage::PostBuild, profile, no_hooks, None)
+                .map_err(Error::HookFailed)?;
+        }
+        Ok(sizes)
+    }
+}
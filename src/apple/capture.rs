@@ -0,0 +1,165 @@
+use super::{config::Config, console, simctl::Simulator};
+use crate::{
+    env::{Env, ExplicitEnv as _},
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    DirCreationFailed { dir: PathBuf, cause: io::Error },
+    ScreenshotFailed(bossy::Error),
+    PresenceCheckFailed(bossy::Error),
+    IdevicescreenshotMissing,
+    DeviceScreenshotFailed(bossy::Error),
+    DeviceRecordingUnsupported,
+    RecordFailed(bossy::Error),
+    OutputMissing { path: PathBuf },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::DirCreationFailed { dir, cause } => Report::error(
+                "Failed to create output directory",
+                format!("Failed to create {:?}: {}", dir, cause),
+            ),
+            Self::ScreenshotFailed(err) => Report::error("Failed to take screenshot", err),
+            Self::PresenceCheckFailed(err) => {
+                Report::error("Failed to check for `idevicescreenshot`", err)
+            }
+            Self::IdevicescreenshotMissing => Report::action_request(
+                "Taking screenshots on devices requires `idevicescreenshot`",
+                "`devicectl` can't take screenshots, so install libimobiledevice with `brew install libimobiledevice` and try again.",
+            ),
+            Self::DeviceScreenshotFailed(err) => Report::error(
+                "Failed to take screenshot",
+                format!(
+                    "{}\nThe device needs to be unlocked, trust this computer, and have its developer disk image mounted, which happens when you run something on it from Xcode.",
+                    err
+                ),
+            ),
+            Self::DeviceRecordingUnsupported => Report::action_request(
+                "Recording the screen of a device isn't supported",
+                "There's no command-line tool for it; use the device's built-in screen recording, or QuickTime Player's \"New Movie Recording\" with the device selected as the camera.",
+            ),
+            Self::RecordFailed(err) => Report::error("Failed to record video", err),
+            Self::OutputMissing { path } => Report::error(
+                "Capture appears to be missing",
+                format!("Nothing was written to {:?}", path),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DirCreationFailed { .. } => "apple.capture.dir-creation-failed",
+            Self::ScreenshotFailed(..) => "apple.capture.screenshot-failed",
+            Self::PresenceCheckFailed(..) => "apple.capture.presence-check-failed",
+            Self::IdevicescreenshotMissing => "apple.capture.idevicescreenshot-missing",
+            Self::DeviceScreenshotFailed(..) => "apple.capture.device-screenshot-failed",
+            Self::DeviceRecordingUnsupported => "apple.capture.device-recording-unsupported",
+            Self::RecordFailed(..) => "apple.capture.record-failed",
+            Self::OutputMissing { .. } => "apple.capture.output-missing",
+        }
+    }
+}
+
+/// Where to capture the screen from.
+#[derive(Clone, Copy, Debug)]
+pub enum Source<'a> {
+    Simulator(&'a Simulator),
+    /// A physical device, by UDID.
+    Device(&'a str),
+}
+
+/// A fresh path in the Xcode project's build dir, so that repeated captures
+/// don't clobber each other.
+pub fn default_path(config: &Config, extension: &str) -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    config.project_dir().join(format!(
+        "build/captures/{}-{}.{}",
+        config.app().name(),
+        timestamp,
+        extension
+    ))
+}
+
+fn create_parent(path: &Path) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|cause| Error::DirCreationFailed {
+            dir: dir.to_owned(),
+            cause,
+        })?;
+    }
+    Ok(())
+}
+
+fn report_saved(path: &Path) -> Result<(), Error> {
+    let len = fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .ok()
+        .filter(|len| *len > 0)
+        .ok_or_else(|| Error::OutputMissing {
+            path: path.to_owned(),
+        })?;
+    println!("Saved {} ({})", path.display(), util::display_size(len));
+    Ok(())
+}
+
+fn simctl_io(env: &Env, simulator: &Simulator) -> bossy::Command {
+    bossy::Command::pure("xcrun")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["simctl", "io", simulator.udid()])
+}
+
+pub fn screenshot(env: &Env, source: Source<'_>, out: &Path) -> Result<(), Error> {
+    create_parent(out)?;
+    match source {
+        Source::Simulator(simulator) => simctl_io(env, simulator)
+            .with_arg("screenshot")
+            .with_arg(out)
+            .run_and_wait_for_output()
+            .map_err(Error::ScreenshotFailed)?,
+        Source::Device(udid) => {
+            if !util::command_present("idevicescreenshot").map_err(Error::PresenceCheckFailed)? {
+                return Err(Error::IdevicescreenshotMissing);
+            }
+            bossy::Command::pure("idevicescreenshot")
+                .with_env_vars(env.explicit_env())
+                .with_args(&["-u", udid])
+                .with_arg(out)
+                .run_and_wait_for_output()
+                .map_err(Error::DeviceScreenshotFailed)?
+        }
+    };
+    report_saved(out)
+}
+
+/// Records until the user hits Ctrl-C, after which `simctl` finishes writing
+/// the video before exiting.
+pub fn record(env: &Env, source: Source<'_>, out: &Path) -> Result<(), Error> {
+    let simulator = match source {
+        Source::Simulator(simulator) => simulator,
+        Source::Device(_) => return Err(Error::DeviceRecordingUnsupported),
+    };
+    create_parent(out)?;
+    println!("Recording; press Ctrl-C to stop.");
+    console::run_until_interrupted(
+        simctl_io(env, simulator)
+            .with_args(&["recordVideo", "--codec=h264", "--force"])
+            .with_arg(out),
+    )
+    .map_err(Error::RecordFailed)?;
+    report_saved(out)
+}
@@ -0,0 +1,236 @@
+use super::{config::Config, target::Target};
+use crate::{
+    env::{Env, ExplicitEnv as _},
+    opts,
+    target::TargetTrait as _,
+    ui,
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    RemoveFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    NoHomeDir(util::NoHomeDir),
+    DerivedDataReadFailed {
+        dir: PathBuf,
+        cause: io::Error,
+    },
+    CargoCleanFailed {
+        triple: &'static str,
+        cause: bossy::Error,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        let msg = "Failed to clean Apple build products";
+        match self {
+            Self::RemoveFailed { path, cause } => {
+                Report::error(msg, format!("Failed to remove {:?}: {}", path, cause))
+            }
+            Self::NoHomeDir(err) => Report::error(msg, err),
+            Self::DerivedDataReadFailed { dir, cause } => Report::error(
+                msg,
+                format!("Failed to list contents of {:?}: {}", dir, cause),
+            ),
+            Self::CargoCleanFailed { triple, cause } => Report::error(
+                msg,
+                format!("Failed to run `cargo clean --target {}`: {}", triple, cause),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::RemoveFailed { .. } => "apple.clean.remove-failed",
+            Self::NoHomeDir(..) => "apple.clean.no-home-dir",
+            Self::DerivedDataReadFailed { .. } => "apple.clean.derived-data-read-failed",
+            Self::CargoCleanFailed { .. } => "apple.clean.cargo-clean-failed",
+        }
+    }
+}
+
+// Every triple we could have built for, regardless of what the host is.
+fn triples() -> BTreeSet<&'static str> {
+    ["arm64", "x86_64"]
+        .iter()
+        .flat_map(|arch| {
+            Target::for_arch(arch)
+                .copied()
+                .into_iter()
+                .chain(Target::for_simulator_arch(arch))
+                .chain(Target::for_macos_arch(arch))
+                .chain(Target::for_catalyst_arch(arch))
+        })
+        .map(|target| target.triple)
+        .collect()
+}
+
+// Best effort, since this is just for reporting.
+fn size(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| size(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or_default(),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+#[derive(Debug, Default)]
+struct Removed {
+    paths: Vec<(PathBuf, u64)>,
+}
+
+impl Removed {
+    fn remove(&mut self, path: PathBuf) -> Result<(), Error> {
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(()),
+        };
+        let size = size(&path);
+        if metadata.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .map_err(|cause| Error::RemoveFailed {
+            path: path.clone(),
+            cause,
+        })?;
+        ui::status(format!(
+            "Removed {} ({})",
+            path.display(),
+            util::display_size(size)
+        ));
+        self.paths.push((path, size));
+        Ok(())
+    }
+
+    fn total(&self) -> u64 {
+        self.paths.iter().map(|(_, size)| size).sum()
+    }
+}
+
+// Xcode names these dirs after the project plus a hash of its path, so the
+// only reliable way to find ours is by the path recorded inside.
+fn global_derived_data_dirs(config: &Config) -> Result<Vec<PathBuf>, Error> {
+    let dir = util::home_dir()
+        .map_err(Error::NoHomeDir)?
+        .join("Library/Developer/Xcode/DerivedData");
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let candidates = [
+        config.xcodeproj_path(),
+        config.workspace_path(),
+        config
+            .project_dir()
+            .join(format!("{}.xcworkspace", config.product_name())),
+    ];
+    let prefix = format!("{}-", config.product_name());
+    let entries = fs::read_dir(&dir).map_err(|cause| Error::DerivedDataReadFailed {
+        dir: dir.clone(),
+        cause,
+    })?;
+    Ok(entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().starts_with(&prefix))
+                .unwrap_or_default()
+        })
+        .filter(|path| {
+            plist::Value::from_file(path.join("info.plist"))
+                .ok()
+                .and_then(|info| {
+                    info.as_dictionary()
+                        .and_then(|info| info.get("WorkspacePath"))
+                        .and_then(|path| path.as_string())
+                        .map(|workspace| {
+                            candidates
+                                .iter()
+                                .any(|candidate| candidate == Path::new(workspace))
+                        })
+                })
+                .unwrap_or_default()
+        })
+        .collect())
+}
+
+/// Removes the project's build products and the Rust libs it links against.
+/// The DerivedData dirs are deleted outright, which is more thorough than
+/// `xcodebuild clean` since that leaves the index and module caches behind.
+pub fn clean(
+    config: &Config,
+    env: &Env,
+    full: opts::FullClean,
+    derived_data: opts::CleanDerivedData,
+) -> Result<(), Error> {
+    let mut removed = Removed::default();
+    removed.remove(config.simulator_derived_data_dir())?;
+    removed.remove(config.desktop_derived_data_dir())?;
+    if derived_data.yes() {
+        for dir in global_derived_data_dirs(config)? {
+            removed.remove(dir)?;
+        }
+    }
+    for triple in triples() {
+        for profile in &[opts::Profile::Debug, opts::Profile::Release] {
+            removed.remove(config.lib_path(triple, *profile))?;
+        }
+    }
+    let universal_dir = config
+        .universal_simulator_lib_path(opts::Profile::Debug)
+        .parent()
+        .and_then(Path::parent)
+        .map(Path::to_owned);
+    if let Some(universal_dir) = universal_dir {
+        removed.remove(universal_dir)?;
+    }
+    if full.yes() {
+        for triple in triples() {
+            let dir = config.app().prefix_path("target").join(triple);
+            if !dir.is_dir() {
+                continue;
+            }
+            let size = size(&dir);
+            bossy::Command::pure("cargo")
+                .with_env_vars(env.explicit_env())
+                .with_args(&["clean", "--target", triple])
+                .with_arg("--manifest-path")
+                .with_arg(config.app().prefix_path("Cargo.toml"))
+                .run_and_wait()
+                .map_err(|cause| Error::CargoCleanFailed { triple, cause })?;
+            ui::status(format!(
+                "Cleaned {} ({})",
+                dir.display(),
+                util::display_size(size)
+            ));
+            removed.paths.push((dir, size));
+        }
+    }
+    if removed.paths.is_empty() {
+        ui::status("Nothing to clean.");
+    } else {
+        ui::status(format!("Reclaimed {}", util::display_size(removed.total())));
+    }
+    Ok(())
+}
@@ -1,29 +1,48 @@
 use crate::{
+    api::apple::{self as api, ensure_init},
     apple::{
+        capture, clean,
         config::{Config, Metadata},
-        device::{Device, RunError},
-        ios_deploy, rust_version_check,
-        target::{ArchiveError, BuildError, CheckError, CompileLibError, ExportError, Target},
-        NAME,
+        desktop,
+        device::{self, Device, RunError},
+        dsym, export, icons, identities, inspect,
+        install::{self, Destination},
+        lipo, packages, profiles, rust_version_check, schemes, simctl, sources, symbolicate,
+        target::{
+            check_build_std, ArchiveError, BuildEnvError, BuildError, BuildStdError, CheckError,
+            CompileLibError, ExportError, HookError, Target,
+        },
+        teams, transfer, xcframework, xcode, NAME,
     },
+    assets,
     config::{
-        metadata::{self, Metadata as OmniMetadata},
+        app::Platform, build_config, metadata, variant, CheckError as ConfigCheckError,
         Config as OmniConfig, LoadOrGenError,
     },
     define_device_prompt,
-    device::PromptError,
-    env::{Env, Error as EnvError},
+    device::{LastDevice, PromptError, WaitError},
+    env::{Env, Error as EnvError, Format},
+    features,
+    hooks::Stage,
     opts, os,
-    target::{call_for_targets_with_fallback, TargetInvalid, TargetTrait as _},
+    plan::Writer,
+    preflight, regen, resources, sizes,
+    target::{self, call_for_targets_with_fallback, get_targets, TargetInvalid, TargetTrait as _},
+    ui,
     util::{
         self,
         cli::{
             self, Exec, GlobalFlags, Report, Reportable, TextWrapper, VERSION_LONG, VERSION_SHORT,
         },
-        prompt,
+        prompt, timings,
     },
+    watch::{self, Trigger},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
 };
-use std::{collections::HashMap, ffi::OsStr, path::PathBuf};
 use structopt::{clap::AppSettings, StructOpt};
 
 #[derive(Debug, StructOpt)]
@@ -37,13 +56,21 @@ use structopt::{clap::AppSettings, StructOpt};
 pub struct Input {
     #[structopt(flatten)]
     flags: GlobalFlags,
+    #[structopt(flatten)]
+    package: cli::Package,
     #[structopt(subcommand)]
     command: Command,
 }
 
 impl Input {
+    /// The package was already picked by `cargo mobile`, which runs this from
+    /// its dir.
     pub fn new(flags: GlobalFlags, command: Command) -> Self {
-        Self { flags, command }
+        Self {
+            flags,
+            package: Default::default(),
+            command,
+        }
     }
 }
 
@@ -51,12 +78,32 @@ fn macos_from_platform(platform: &str) -> bool {
     platform == "macOS"
 }
 
-fn profile_from_configuration(configuration: &str) -> opts::Profile {
-    if configuration == "release" {
-        opts::Profile::Release
-    } else {
-        opts::Profile::Debug
-    }
+fn catalyst_from_is_maccatalyst(is_maccatalyst: &str) -> bool {
+    is_maccatalyst == "YES"
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct Selection {
+    #[structopt(
+        long = "scheme",
+        help = "Xcode scheme to build [default: the generated `<app name>_iOS`]"
+    )]
+    scheme: Option<String>,
+    #[structopt(
+        long = "configuration",
+        help = "Xcode build configuration to use, which also picks the cargo profile via `apple.configurations` [default: `debug`, or `release` with `--release`]",
+        conflicts_with = "profile"
+    )]
+    configuration: Option<String>,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct Identity {
+    #[structopt(
+        long = "identity",
+        help = "Codesigning identity to use with manual signing, by name or SHA-1 [default: `apple.identity`, or whatever Xcode picks]"
+    )]
+    identity: Option<String>,
 }
 
 #[derive(Clone, Debug, StructOpt)]
@@ -65,30 +112,475 @@ pub enum Command {
     Open,
     #[structopt(name = "check", about = "Checks if code compiles for target(s)")]
     Check {
-        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::name_list())]
+        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::possible_values(), hide_possible_values = true, help = "Targets to use, by name, alias, or triple; see `cargo apple targets`")]
         targets: Vec<String>,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
     },
     #[structopt(name = "build", about = "Builds static libraries for target(s)")]
     Build {
-        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::name_list())]
+        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::possible_values(), hide_possible_values = true, help = "Targets to use, by name, alias, or triple; see `cargo apple targets`")]
         targets: Vec<String>,
         #[structopt(flatten)]
         profile: cli::Profile,
+        #[structopt(flatten)]
+        selection: Selection,
+        #[structopt(flatten)]
+        no_hooks: cli::NoHooks,
+        #[structopt(flatten)]
+        timings: cli::Timings,
+        #[structopt(flatten)]
+        size_report: cli::SizeReport,
+        #[structopt(flatten)]
+        show_assets: cli::ShowAssets,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+        #[structopt(flatten)]
+        identity: Identity,
+        #[structopt(flatten)]
+        force: cli::Force,
     },
-    #[structopt(name = "archive", about = "Builds and archives for targets(s)")]
+    #[structopt(
+        name = "archive",
+        about = "Builds and archives for targets(s), then exports an IPA"
+    )]
     Archive {
-        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::name_list())]
+        #[structopt(name = "targets", default_value = Target::DEFAULT_KEY, possible_values = Target::possible_values(), hide_possible_values = true, help = "Targets to use, by name, alias, or triple; see `cargo apple targets`")]
         targets: Vec<String>,
         #[structopt(flatten)]
         profile: cli::Profile,
+        #[structopt(flatten)]
+        selection: Selection,
+        #[structopt(flatten)]
+        no_hooks: cli::NoHooks,
+        #[structopt(flatten)]
+        timings: cli::Timings,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+        #[structopt(
+            long = "export-method",
+            help = "How the IPA will be distributed",
+            default_value = "development",
+            possible_values = export::Method::name_list()
+        )]
+        export_method: export::Method,
+        #[structopt(long = "archive-path", help = "Where to put the `.xcarchive`")]
+        archive_path: Option<PathBuf>,
+        #[structopt(long = "out", help = "Directory to export the IPA to")]
+        out: Option<PathBuf>,
+        #[structopt(long = "no-export", help = "Stop after creating the `.xcarchive`")]
+        no_export: bool,
+        #[structopt(flatten)]
+        identity: Identity,
+    },
+    #[structopt(
+        name = "xcframework",
+        about = "Builds all iOS targets and bundles them into an XCFramework"
+    )]
+    Xcframework {
+        #[structopt(flatten)]
+        profile: cli::Profile,
+        #[structopt(flatten)]
+        force: cli::Force,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+        #[structopt(
+            long = "out",
+            help = "Where to put the `.xcframework` [default: in the Xcode project's build dir]"
+        )]
+        out: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "symbolicate",
+        about = "Symbolicates a crash log using the dSYMs saved by `archive`"
+    )]
+    Symbolicate {
+        #[structopt(name = "crash-log", help = "`.ips`, `.crash`, or `.txt` crash log")]
+        crash_log: PathBuf,
+        #[structopt(
+            long = "dsyms",
+            help = "Directory to look for dSYMs in [default: where `archive` saves them]"
+        )]
+        dsyms: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "upload",
+        about = "Uploads IPA to App Store Connect (for TestFlight or App Store release)"
+    )]
+    Upload {
+        #[structopt(long = "ipa", help = "IPA to upload [default: the last one exported]")]
+        ipa: Option<PathBuf>,
+        #[structopt(
+            long = "api-key",
+            help = "Path to App Store Connect API key (`AuthKey_<key ID>.p8`)",
+            env = "APP_STORE_CONNECT_API_KEY_PATH",
+            hide_env_values = true
+        )]
+        api_key: PathBuf,
+        #[structopt(
+            long = "key-id",
+            help = "App Store Connect API key ID",
+            env = "APP_STORE_CONNECT_KEY_ID",
+            hide_env_values = true
+        )]
+        key_id: String,
+        #[structopt(
+            long = "issuer-id",
+            help = "App Store Connect API issuer ID",
+            env = "APP_STORE_CONNECT_ISSUER_ID",
+            hide_env_values = true
+        )]
+        issuer_id: String,
+    },
+    #[structopt(
+        name = "inspect",
+        about = "Shows how a built app or IPA was signed, and what's in it"
+    )]
+    Inspect {
+        #[structopt(
+            name = "path",
+            help = "`.app` or `.ipa` to inspect [default: the most recently built one]",
+            parse(from_os_str)
+        )]
+        path: Option<PathBuf>,
+        #[structopt(long = "json", help = "Print what's inside as JSON")]
+        json: bool,
+    },
+    #[structopt(
+        name = "install",
+        about = "Installs an app on a connected device or simulator, without building anything"
+    )]
+    Install {
+        #[structopt(
+            long = "app",
+            help = "`.app` or `.ipa` to install, i.e. one built by CI [default: the last one `cargo apple build` built for the device or simulator]"
+        )]
+        app: Option<PathBuf>,
+        #[structopt(flatten)]
+        profile: cli::Profile,
+        #[structopt(
+            long = "simulator",
+            help = "Install on an iOS Simulator instead, optionally specified by name or UDID"
+        )]
+        simulator: Option<Option<String>>,
+        #[structopt(
+            long = "device",
+            help = "Connected device to install on, specified by name or UDID",
+            conflicts_with = "simulator"
+        )]
+        device: Option<String>,
+        #[structopt(flatten)]
+        device_timeout: cli::DeviceTimeout,
+    },
+    #[structopt(
+        name = "launch",
+        about = "Launches the installed app on a connected device or simulator, without reinstalling it"
+    )]
+    Launch {
+        #[structopt(
+            long = "bundle-id",
+            help = "Bundle ID of the app to launch [default: this app's]"
+        )]
+        bundle_id: Option<String>,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+        #[structopt(
+            long = "simulator",
+            help = "Launch on an iOS Simulator instead, optionally specified by name or UDID"
+        )]
+        simulator: Option<Option<String>>,
+        #[structopt(
+            long = "device",
+            help = "Connected device to launch on, specified by name or UDID",
+            conflicts_with = "simulator"
+        )]
+        device: Option<String>,
+        #[structopt(flatten)]
+        device_timeout: cli::DeviceTimeout,
+        #[structopt(
+            long = "no-log",
+            help = "Don't stream logs once the app is launched",
+            parse(from_flag = opts::SkipLog::from_bool),
+        )]
+        skip_log: opts::SkipLog,
+        #[structopt(
+            long = "kill-on-exit",
+            help = "Terminate the app when you stop streaming logs",
+            conflicts_with = "skip-log",
+            parse(from_flag = opts::KillOnExit::from_bool),
+        )]
+        kill_on_exit: opts::KillOnExit,
     },
-    #[structopt(name = "run", about = "Deploys IPA to connected device")]
+    #[structopt(
+        name = "run",
+        about = "Builds, installs, and launches the app on a connected device or simulator; like `build`, `install`, and `launch` in one go"
+    )]
     Run {
         #[structopt(flatten)]
         profile: cli::Profile,
+        #[structopt(flatten)]
+        selection: Selection,
+        #[structopt(flatten)]
+        no_hooks: cli::NoHooks,
+        #[structopt(flatten)]
+        no_crash_check: cli::NoCrashCheck,
+        #[structopt(flatten)]
+        timings: cli::Timings,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+        #[structopt(
+            long = "simulator",
+            help = "Run on an iOS Simulator instead, optionally specified by name or UDID"
+        )]
+        simulator: Option<Option<String>>,
+        #[structopt(
+            long = "device",
+            help = "Connected device to run on, specified by name or UDID, or `ask` to prompt [default: the device last run on, if it's connected]",
+            conflicts_with = "simulator"
+        )]
+        device: Option<String>,
+        #[structopt(flatten)]
+        device_timeout: cli::DeviceTimeout,
+        #[structopt(
+            long = "macos",
+            help = "Run on this Mac instead, using the target from `apple.desktop`",
+            conflicts_with_all = &["simulator", "device"]
+        )]
+        macos: bool,
+        #[structopt(
+            long = "no-log",
+            help = "Don't stream logs once the app is launched",
+            parse(from_flag = opts::SkipLog::from_bool),
+        )]
+        skip_log: opts::SkipLog,
+        #[structopt(
+            long = "kill-on-exit",
+            help = "Terminate the app when you stop streaming logs",
+            conflicts_with = "skip-log",
+            parse(from_flag = opts::KillOnExit::from_bool),
+        )]
+        kill_on_exit: opts::KillOnExit,
+        #[structopt(flatten)]
+        identity: Identity,
+        #[structopt(
+            long = "watch",
+            help = "Rebuild and redeploy whenever the crate's sources change",
+            conflicts_with = "macos",
+            parse(from_flag = opts::Watch::from_bool),
+        )]
+        watch: opts::Watch,
+    },
+    #[structopt(
+        name = "clean",
+        about = "Removes Xcode build products and the Rust libs they link against"
+    )]
+    Clean {
+        #[structopt(
+            long = "full",
+            help = "Also run `cargo clean` for every Apple target",
+            parse(from_flag = opts::FullClean::from_bool),
+        )]
+        full: opts::FullClean,
+        #[structopt(
+            long = "derived-data",
+            help = "Also remove this project's dirs in Xcode's global DerivedData",
+            parse(from_flag = opts::CleanDerivedData::from_bool),
+        )]
+        derived_data: opts::CleanDerivedData,
+    },
+    #[structopt(
+        name = "screenshot",
+        about = "Takes a screenshot of the booted simulator, or a connected device"
+    )]
+    Screenshot {
+        #[structopt(
+            long = "simulator",
+            help = "Booted simulator to use, specified by name or UDID [default: the booted one]"
+        )]
+        simulator: Option<String>,
+        #[structopt(
+            long = "device",
+            help = "Connected device to use instead, specified by name or UDID",
+            conflicts_with = "simulator"
+        )]
+        device: Option<String>,
+        #[structopt(
+            long = "out",
+            help = "Where to save the PNG [default: in the Xcode project's build dir]"
+        )]
+        out: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "record",
+        about = "Records a video of the booted simulator until you hit Ctrl-C"
+    )]
+    Record {
+        #[structopt(
+            long = "simulator",
+            help = "Booted simulator to use, specified by name or UDID [default: the booted one]"
+        )]
+        simulator: Option<String>,
+        #[structopt(
+            long = "device",
+            help = "Connected device to use instead, specified by name or UDID",
+            conflicts_with = "simulator"
+        )]
+        device: Option<String>,
+        #[structopt(
+            long = "out",
+            help = "Where to save the MP4 [default: in the Xcode project's build dir]"
+        )]
+        out: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "pull",
+        about = "Copies a file out of the app's data on a booted simulator or a connected device"
+    )]
+    Pull {
+        #[structopt(
+            name = "remote",
+            help = "File to pull, relative to the app's data dir, i.e. `Documents/app.db`; wildcards have to match exactly one file, and only work on simulators"
+        )]
+        remote: String,
+        #[structopt(
+            name = "local",
+            help = "Where to put it [default: the current dir, under the same name]"
+        )]
+        local: Option<PathBuf>,
+        #[structopt(
+            long = "simulator",
+            help = "Booted simulator to use, specified by name or UDID [default: the booted one]"
+        )]
+        simulator: Option<String>,
+        #[structopt(
+            long = "device",
+            help = "Connected device to use instead, specified by name or UDID",
+            conflicts_with = "simulator"
+        )]
+        device: Option<String>,
+    },
+    #[structopt(
+        name = "push",
+        about = "Copies a file into the app's data on a booted simulator or a connected device"
+    )]
+    Push {
+        #[structopt(name = "local", help = "File to push")]
+        local: PathBuf,
+        #[structopt(
+            name = "remote",
+            help = "Where to put it, relative to the app's data dir; ending it with `/` keeps the file's name"
+        )]
+        remote: String,
+        #[structopt(
+            long = "simulator",
+            help = "Booted simulator to use, specified by name or UDID [default: the booted one]"
+        )]
+        simulator: Option<String>,
+        #[structopt(
+            long = "device",
+            help = "Connected device to use instead, specified by name or UDID",
+            conflicts_with = "simulator"
+        )]
+        device: Option<String>,
+    },
+    #[structopt(
+        name = "simctl",
+        about = "Runs `xcrun simctl` with the simulator already filled in; i.e. `cargo apple simctl -- launch com.example.app`"
+    )]
+    Simctl {
+        #[structopt(
+            long = "simulator",
+            help = "Simulator to use, specified by name or UDID [default: the booted one, or else prompts if there are several]"
+        )]
+        simulator: Option<String>,
+        #[structopt(
+            name = "args",
+            last = true,
+            help = "Arguments for `simctl`, which have to come after `--`; the simulator's UDID is added after subcommands that take a device, unless one's given"
+        )]
+        args: Vec<String>,
     },
     #[structopt(name = "list", about = "Lists connected devices")]
-    List,
+    List {
+        #[structopt(long = "simulators", help = "List available simulators instead")]
+        simulators: bool,
+    },
+    #[structopt(
+        name = "targets",
+        about = "Lists the targets you can build for, and which ones are installed"
+    )]
+    Targets {
+        #[structopt(long = "json", help = "Print targets as JSON")]
+        json: bool,
+    },
+    #[structopt(
+        name = "devices",
+        about = "Lists connected devices and available simulators in detail"
+    )]
+    Devices {
+        #[structopt(long = "json", help = "Print devices as JSON")]
+        json: bool,
+        #[structopt(long = "names", hidden = true)]
+        names: bool,
+    },
+    #[structopt(
+        name = "teams",
+        about = "Lists detected development teams and their IDs"
+    )]
+    Teams,
+    #[structopt(
+        name = "identities",
+        about = "Lists codesigning identities, including expired ones"
+    )]
+    Identities,
+    #[structopt(
+        name = "profiles",
+        about = "Lists installed provisioning profiles, including expired ones"
+    )]
+    Profiles,
+    #[structopt(
+        name = "icons",
+        about = "Regenerates the app icon set and launch screen from `mobile.toml`"
+    )]
+    Icons,
+    #[structopt(
+        name = "env",
+        about = "Prints the environment Xcode's build phase builds a target with"
+    )]
+    Env {
+        #[structopt(name = "target", default_value = Target::DEFAULT_KEY, possible_values = Target::possible_values(), hide_possible_values = true, help = "Target to use, by name, alias, or triple; see `cargo apple targets`")]
+        target: String,
+        #[structopt(
+            long = "shell",
+            help = "Print commands that set the environment in your shell",
+            conflicts_with = "json"
+        )]
+        shell: bool,
+        #[structopt(long = "json", help = "Print the environment as JSON")]
+        json: bool,
+        #[structopt(flatten)]
+        profile: cli::Profile,
+        #[structopt(flatten)]
+        variant: cli::Variant,
+    },
+    #[structopt(
+        name = "completions",
+        about = "Prints a completion script for your shell"
+    )]
+    Completions {
+        #[structopt(flatten)]
+        completions: cli::Completions,
+    },
     #[structopt(
         name = "xcode-script",
         about = "Compiles static lib (should only be called by Xcode!)",
@@ -101,20 +593,28 @@ pub enum Command {
             parse(from_str = macos_from_platform),
         )]
         macos: bool,
-        #[structopt(long = "sdk-root", help = "Value of `SDKROOT` env var")]
-        sdk_root: PathBuf,
         #[structopt(
-            long = "configuration",
-            help = "Value of `CONFIGURATION` env var",
-            parse(from_str = profile_from_configuration),
+            long = "is-maccatalyst",
+            help = "Value of `IS_MACCATALYST` env var",
+            parse(from_str = catalyst_from_is_maccatalyst),
         )]
-        profile: opts::Profile,
+        catalyst: Option<bool>,
+        #[structopt(long = "platform-name", help = "Value of `PLATFORM_NAME` env var")]
+        platform_name: Option<String>,
+        #[structopt(long = "sdk-root", help = "Value of `SDKROOT` env var")]
+        sdk_root: PathBuf,
+        #[structopt(long = "configuration", help = "Value of `CONFIGURATION` env var")]
+        configuration: String,
         #[structopt(
             long = "force-color",
             help = "Value of `FORCE_COLOR` env var",
             parse(from_flag = opts::ForceColor::from_bool),
         )]
         force_color: opts::ForceColor,
+        #[structopt(flatten)]
+        features: cli::Features,
+        #[structopt(flatten)]
+        variant: cli::Variant,
         #[structopt(
             name = "ARCHS",
             help = "Value of `ARCHS` env var",
@@ -129,9 +629,16 @@ pub enum Command {
 pub enum Error {
     EnvInitFailed(EnvError),
     RustVersionCheckFailed(util::RustVersionError),
-    DevicePromptFailed(PromptError<ios_deploy::DeviceListError>),
+    DevicePromptFailed(PromptError<device::DeviceListError>),
+    DeviceSelectFailed(device::SelectError),
+    DeviceWaitFailed(WaitError<device::DeviceListError>),
     TargetInvalid(TargetInvalid),
     ConfigFailed(LoadOrGenError),
+    ConfigCheckFailed(ConfigCheckError),
+    RegenFailed(regen::Error),
+    PreflightFailed(preflight::Error),
+    AssetsFailed(assets::Error),
+    VariantFailed(variant::Unknown),
     MetadataFailed(metadata::Error),
     Unsupported,
     ProjectDirAbsent { project_dir: PathBuf },
@@ -139,16 +646,47 @@ pub enum Error {
     CheckFailed(CheckError),
     BuildFailed(BuildError),
     ArchiveFailed(ArchiveError),
+    DsymsFailed(dsym::Error),
+    SymbolicateFailed(symbolicate::Error),
     ExportFailed(ExportError),
+    IpaMissing { old: PathBuf, new: PathBuf },
+    XcframeworkFailed(xcframework::Error),
+    UploadFailed(upload::Error),
     RunFailed(RunError),
-    ListFailed(ios_deploy::DeviceListError),
+    ListFailed(device::DeviceListError),
+    DevicesFailed(device::ListingsError),
+    SimulatorSelectFailed(simctl::SelectError),
+    SimulatorRunFailed(simctl::RunError),
+    DesktopRunFailed(desktop::RunError),
+    CaptureFailed(capture::Error),
+    TransferFailed(transfer::Error),
+    PassthroughFailed(util::passthrough::Error),
+    WatchFailed(watch::Error),
+    CleanFailed(clean::Error),
+    SimulatorListFailed(simctl::DeviceListError),
+    TeamsFailed(teams::Error),
+    IdentitiesFailed(identities::Error),
+    IdentityCheckFailed(identities::CheckError),
+    ProfilesFailed(profiles::Error),
+    ProfileCheckFailed(profiles::CheckError),
+    XcodeCheckFailed(xcode::Error),
+    BuildStdCheckFailed(BuildStdError),
+    PackageResolveFailed(packages::ResolveError),
+    SchemeCheckFailed(schemes::Error),
+    IconsFailed(icons::Error),
     NoHomeDir(util::NoHomeDir),
     CargoEnvFailed(bossy::Error),
-    SdkRootInvalid { sdk_root: PathBuf },
-    IncludeDirInvalid { include_dir: PathBuf },
-    MacosSdkRootInvalid { macos_sdk_root: PathBuf },
+    SdkRootFailed(bossy::Error),
+    BuildEnvFailed(BuildEnvError),
+    HookFailed(HookError),
     ArchInvalid { arch: String },
     CompileLibFailed(CompileLibError),
+    LipoFailed(lipo::Error),
+    SizesFailed(sizes::Error),
+    AppInvalid(install::Error),
+    InspectFailed(inspect::Error),
+    BuildConfigFailed(build_config::ResolveError),
+    SourcesInvalid(sources::Error),
 }
 
 impl Reportable for Error {
@@ -157,8 +695,15 @@ impl Reportable for Error {
             Self::EnvInitFailed(err) => err.report(),
             Self::RustVersionCheckFailed(err) => err.report(),
             Self::DevicePromptFailed(err) => err.report(),
+            Self::DeviceSelectFailed(err) => err.report(),
+            Self::DeviceWaitFailed(err) => err.report(),
             Self::TargetInvalid(err) => Report::error("Specified target was invalid", err),
             Self::ConfigFailed(err) => err.report(),
+            Self::ConfigCheckFailed(err) => err.report(),
+            Self::RegenFailed(err) => err.report(),
+            Self::PreflightFailed(err) => err.report(),
+            Self::AssetsFailed(err) => err.report(),
+            Self::VariantFailed(err) => err.report(),
             Self::MetadataFailed(err) => err.report(),
             Self::Unsupported => Report::error("iOS is marked as unsupported in your Cargo.toml metadata", "If your project should support Android, modify your Cargo.toml, then run `cargo mobile init` and try again."),
             Self::ProjectDirAbsent { project_dir } => Report::action_request(
@@ -169,32 +714,132 @@ impl Reportable for Error {
             Self::CheckFailed(err) => err.report(),
             Self::BuildFailed(err) => err.report(),
             Self::ArchiveFailed(err) => err.report(),
+            Self::DsymsFailed(err) => err.report(),
+            Self::SymbolicateFailed(err) => err.report(),
             Self::ExportFailed(err) => err.report(),
+            Self::IpaMissing { old, new } => Report::error(
+                "IPA appears to be missing",
+                format!("Not found at either {:?} or {:?}", old, new),
+            ),
+            Self::XcframeworkFailed(err) => err.report(),
+            Self::UploadFailed(err) => err.report(),
             Self::RunFailed(err) => err.report(),
             Self::ListFailed(err) => err.report(),
+            Self::DevicesFailed(err) => err.report(),
+            Self::SimulatorSelectFailed(err) => err.report(),
+            Self::SimulatorRunFailed(err) => err.report(),
+            Self::DesktopRunFailed(err) => err.report(),
+            Self::CaptureFailed(err) => err.report(),
+            Self::TransferFailed(err) => err.report(),
+            Self::PassthroughFailed(err) => err.report(),
+            Self::WatchFailed(err) => err.report(),
+            Self::CleanFailed(err) => err.report(),
+            Self::SimulatorListFailed(err) => err.report(),
+            Self::TeamsFailed(err) => Report::error("Failed to find development teams", err),
+            Self::IdentitiesFailed(err) => {
+                Report::error("Failed to find codesigning identities", err)
+            }
+            Self::IdentityCheckFailed(err) => err.report(),
+            Self::ProfilesFailed(err) => {
+                Report::error("Failed to find provisioning profiles", err)
+            }
+            Self::ProfileCheckFailed(err) => err.report(),
+            Self::XcodeCheckFailed(err) => err.report(),
+            Self::BuildStdCheckFailed(err) => err.report(),
+            Self::PackageResolveFailed(err) => err.report(),
+            Self::SchemeCheckFailed(err) => err.report(),
+            Self::IconsFailed(err) => err.report(),
             Self::NoHomeDir(err) => Report::error("Failed to load cargo env profile", err),
             Self::CargoEnvFailed(err) => Report::error("Failed to load cargo env profile", err),
-            Self::SdkRootInvalid { sdk_root } => Report::error(
-                "SDK root provided by Xcode was invalid",
-                format!("{:?} doesn't exist or isn't a directory", sdk_root),
-            ),
-            Self::IncludeDirInvalid { include_dir } => Report::error(
-                "Include dir was invalid",
-                format!("{:?} doesn't exist or isn't a directory", include_dir),
-            ),
-            Self::MacosSdkRootInvalid { macos_sdk_root } => Report::error(
-                "macOS SDK root was invalid",
-                format!("{:?} doesn't exist or isn't a directory", macos_sdk_root),
-            ),
+            Self::SdkRootFailed(err) => Report::error("Failed to locate SDK", err),
+            Self::BuildEnvFailed(err) => err.report(),
+            Self::HookFailed(err) => err.report(),
             Self::ArchInvalid { arch } => Report::error(
                 "Arch specified by Xcode was invalid",
                 format!("{:?} isn't a known arch", arch),
             ),
             Self::CompileLibFailed(err) => err.report(),
+            Self::LipoFailed(err) => err.report(),
+            Self::SizesFailed(err) => err.report(),
+            Self::AppInvalid(err) => err.report(),
+            Self::InspectFailed(err) => err.report(),
+            Self::BuildConfigFailed(err) => err.report(),
+            Self::SourcesInvalid(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::EnvInitFailed(err) => err.code(),
+            Self::RustVersionCheckFailed(err) => err.code(),
+            Self::DevicePromptFailed(err) => err.code(),
+            Self::DeviceSelectFailed(err) => err.code(),
+            Self::DeviceWaitFailed(err) => err.code(),
+            Self::TargetInvalid(..) => "apple.cli.target-invalid",
+            Self::ConfigFailed(err) => err.code(),
+            Self::ConfigCheckFailed(err) => err.code(),
+            Self::RegenFailed(err) => err.code(),
+            Self::PreflightFailed(err) => err.code(),
+            Self::AssetsFailed(err) => err.code(),
+            Self::VariantFailed(err) => err.code(),
+            Self::MetadataFailed(err) => err.code(),
+            Self::Unsupported => "apple.cli.unsupported",
+            Self::ProjectDirAbsent { .. } => "apple.cli.project-dir-absent",
+            Self::OpenFailed(..) => "apple.cli.open-failed",
+            Self::CheckFailed(err) => err.code(),
+            Self::BuildFailed(err) => err.code(),
+            Self::ArchiveFailed(err) => err.code(),
+            Self::DsymsFailed(err) => err.code(),
+            Self::SymbolicateFailed(err) => err.code(),
+            Self::ExportFailed(err) => err.code(),
+            Self::IpaMissing { .. } => "apple.cli.ipa-missing",
+            Self::XcframeworkFailed(err) => err.code(),
+            Self::UploadFailed(err) => err.code(),
+            Self::RunFailed(err) => err.code(),
+            Self::ListFailed(err) => err.code(),
+            Self::DevicesFailed(err) => err.code(),
+            Self::SimulatorSelectFailed(err) => err.code(),
+            Self::SimulatorRunFailed(err) => err.code(),
+            Self::DesktopRunFailed(err) => err.code(),
+            Self::CaptureFailed(err) => err.code(),
+            Self::TransferFailed(err) => err.code(),
+            Self::PassthroughFailed(err) => err.code(),
+            Self::WatchFailed(err) => err.code(),
+            Self::CleanFailed(err) => err.code(),
+            Self::SimulatorListFailed(err) => err.code(),
+            Self::TeamsFailed(..) => "apple.cli.teams-failed",
+            Self::IdentitiesFailed(..) => "apple.cli.identities-failed",
+            Self::IdentityCheckFailed(err) => err.code(),
+            Self::ProfilesFailed(..) => "apple.cli.profiles-failed",
+            Self::ProfileCheckFailed(err) => err.code(),
+            Self::XcodeCheckFailed(err) => err.code(),
+            Self::BuildStdCheckFailed(err) => err.code(),
+            Self::PackageResolveFailed(err) => err.code(),
+            Self::SchemeCheckFailed(err) => err.code(),
+            Self::IconsFailed(err) => err.code(),
+            Self::NoHomeDir(..) => "apple.cli.no-home-dir",
+            Self::CargoEnvFailed(..) => "apple.cli.cargo-env-failed",
+            Self::SdkRootFailed(..) => "apple.cli.sdk-root-failed",
+            Self::BuildEnvFailed(err) => err.code(),
+            Self::HookFailed(err) => err.code(),
+            Self::ArchInvalid { .. } => "apple.cli.arch-invalid",
+            Self::CompileLibFailed(err) => err.code(),
+            Self::LipoFailed(err) => err.code(),
+            Self::SizesFailed(err) => err.code(),
+            Self::AppInvalid(err) => err.code(),
+            Self::InspectFailed(err) => err.code(),
+            Self::BuildConfigFailed(err) => err.code(),
+            Self::SourcesInvalid(err) => err.code(),
         }
     }
 }
 
+/// Every device and simulator, for listing alongside other platforms'.
+pub fn device_listings() -> Result<Vec<crate::device::Listing>, Error> {
+    let env = Env::new().map_err(Error::EnvInitFailed)?;
+    device::listings(&env).map_err(Error::DevicesFailed)
+}
+
 impl Exec for Input {
     type Report = Error;
 
@@ -202,40 +847,185 @@ impl Exec for Input {
         self.flags
     }
 
+    fn package(&self) -> &cli::Package {
+        &self.package
+    }
+
     fn exec(self, wrapper: &TextWrapper) -> Result<(), Self::Report> {
-        define_device_prompt!(ios_deploy::device_list, ios_deploy::DeviceListError, iOS);
+        define_device_prompt!(device::device_list, device::DeviceListError, iOS);
         fn detect_target_ok<'a>(env: &Env) -> Option<&'a Target<'a>> {
             device_prompt(env).map(|device| device.target()).ok()
         }
 
+        // `last` is the device to go with if it's connected and nothing else
+        // was asked for.
+        fn select_device<'a>(
+            env: &Env,
+            non_interactive: opts::NonInteractive,
+            device_timeout: u64,
+            query: Option<&str>,
+            last: Option<&LastDevice>,
+        ) -> Result<Device<'a>, Error> {
+            let (query, last) = match query {
+                Some(query) if query == crate::device::ASK => (None, None),
+                Some(query) => (Some(query), None),
+                None => (None, last.and_then(LastDevice::id)),
+            };
+            crate::device::wait_until_ready(
+                non_interactive,
+                Duration::from_secs(device_timeout),
+                || device::blocked(env, query),
+            )
+            .map_err(Error::DeviceWaitFailed)?;
+            if let Some(query) = query {
+                return device::select(env, query, non_interactive)
+                    .map_err(Error::DeviceSelectFailed);
+            }
+            if let Some(last) = last {
+                let devices = device::device_list(env).map_err(Error::ListFailed)?;
+                if let Some(device) = devices.into_iter().find(|device| device.id() == last) {
+                    ui::status(format!(
+                        "Using previously selected device {} (UDID {}); pass `--device` to change",
+                        device,
+                        device.id()
+                    ));
+                    return Ok(device);
+                }
+            }
+            device_prompt(env).map_err(Error::DevicePromptFailed)
+        }
+
+        // The path defaults to whatever was last built for the destination,
+        // which needs the config, so it's passed in lazily.
+        fn install(
+            env: &Env,
+            non_interactive: opts::NonInteractive,
+            simulator: Option<Option<String>>,
+            device: Option<String>,
+            device_timeout: u64,
+            path: impl FnOnce(bool) -> PathBuf,
+        ) -> Result<(), Error> {
+            if let Some(query) = simulator {
+                let simulator = simctl::select(env, query.as_deref(), non_interactive)
+                    .map_err(Error::SimulatorSelectFailed)?;
+                let app_path = install::app_bundle(env, &path(true)).map_err(Error::AppInvalid)?;
+                install::check(
+                    &app_path,
+                    Destination {
+                        name: &simulator.to_string(),
+                        arch: Target::simulator().arch,
+                        simulator: true,
+                    },
+                )
+                .map_err(Error::AppInvalid)?;
+                simctl::boot(env, &simulator).map_err(Error::SimulatorRunFailed)?;
+                simctl::install(env, &simulator, &app_path).map_err(Error::SimulatorRunFailed)
+            } else {
+                select_device(
+                    env,
+                    non_interactive,
+                    device_timeout,
+                    device.as_deref(),
+                    None,
+                )?
+                .install(env, &path(false))
+                .map_err(Error::RunFailed)
+            }
+        }
+
+        // `features` are the ones passed with `--features`, which get merged
+        // into the metadata along with the config's (and the variant's).
         fn with_config(
             non_interactive: opts::NonInteractive,
             wrapper: &TextWrapper,
+            features: &[String],
+            variant: Option<&str>,
             f: impl FnOnce(&Config, &Metadata) -> Result<(), Error>,
         ) -> Result<(), Error> {
-            let (config, _origin) = OmniConfig::load_or_gen(".", non_interactive, wrapper)
-                .map_err(Error::ConfigFailed)?;
-            let metadata =
-                OmniMetadata::load(&config.app().root_dir()).map_err(Error::MetadataFailed)?;
-            if metadata.apple().supported() {
-                f(config.apple(), metadata.apple())
-            } else {
-                Err(Error::Unsupported)
+            let (config, _origin) = OmniConfig::load_or_gen(
+                ".",
+                non_interactive,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Writer::Disk,
+                wrapper,
+            )
+            .map_err(Error::ConfigFailed)?;
+            let (config, metadata) = api::from_config(&config, features, variant)?;
+            f(&config, &metadata)
+        }
+
+        // A typo'd feature would otherwise only come up once cargo gets going,
+        // which for Xcode is after a good deal of other work.
+        fn check_features(config: &Config, metadata: &Metadata, env: &Env) {
+            features::warn_unknown(
+                &config.app().manifest_path(),
+                config.app().name(),
+                env,
+                &metadata.features(),
+            );
+        }
+
+        // The `PATH` env var Xcode gives us is missing any additions made by
+        // the user's profile, so we'll manually add cargo's `PATH`.
+        fn with_cargo_path(env: Env) -> Result<Env, Error> {
+            Ok(env.prepend_to_path(
+                util::home_dir()
+                    .map_err(Error::NoHomeDir)?
+                    .join(".cargo/bin"),
+            ))
+        }
+
+        fn select_identity(config: &Config, identity: Option<String>) -> Result<Config, Error> {
+            let mut config = config.clone();
+            if let Some(identity) = identity {
+                config.set_identity(identity);
+            }
+            if let Some(identity) = config.identity() {
+                identities::check(identity).map_err(Error::IdentityCheckFailed)?;
             }
+            Ok(config)
         }
 
-        fn ensure_init(config: &Config) -> Result<(), Error> {
-            if !config.project_dir_exists() {
-                Err(Error::ProjectDirAbsent {
-                    project_dir: config.project_dir(),
-                })
-            } else {
-                Ok(())
+        fn select_build(
+            config: &Config,
+            env: &Env,
+            selection: Selection,
+            profile: opts::Profile,
+        ) -> Result<(Config, opts::Profile), Error> {
+            let Selection {
+                scheme,
+                configuration,
+            } = selection;
+            schemes::check(config, env, scheme.as_deref(), configuration.as_deref())
+                .map_err(Error::SchemeCheckFailed)?;
+            let mut config = config.clone();
+            if let Some(scheme) = scheme {
+                config.set_scheme(scheme);
             }
+            // The build phase picks the cargo profile from the configuration,
+            // so we have to agree with it.
+            let profile = match configuration {
+                Some(configuration) => {
+                    let profile = config.profile_for_configuration(&configuration);
+                    config.set_configuration(configuration);
+                    profile
+                }
+                None => profile,
+            };
+            Ok((config, profile))
         }
 
         fn open_in_xcode(config: &Config) -> Result<(), Error> {
-            os::open_file_with("Xcode", config.project_dir()).map_err(Error::OpenFailed)
+            let path = if config.uses_pods() {
+                config.workspace_path()
+            } else {
+                config.project_dir()
+            };
+            os::open_file_with("Xcode", path).map_err(Error::OpenFailed)
         }
 
         let version_check = || rust_version_check(wrapper).map_err(Error::RustVersionCheckFailed);
@@ -245,174 +1035,805 @@ impl Exec for Input {
                 GlobalFlags {
                     noise_level,
                     non_interactive,
+                    format,
+                    ignore_version_mismatch,
+                    jobs,
+                    ..
                 },
             command,
+            ..
         } = self;
+        let format = format.unwrap_or_default();
         let env = Env::new().map_err(Error::EnvInitFailed)?;
+        // Missing or mis-selected Xcodes otherwise fail much later, with
+        // misleading errors about missing SDKs. Building std without nightly
+        // would likewise only fail once Xcode gets around to calling cargo.
+        let toolchain_check = |config: &Config| {
+            xcode::check(&env, config.min_xcode_version()).map_err(Error::XcodeCheckFailed)?;
+            check_build_std(config).map_err(Error::BuildStdCheckFailed)
+        };
+        let resolve_packages =
+            |config: &Config| packages::resolve(config, &env).map_err(Error::PackageResolveFailed);
+        if matches!(
+            command,
+            Command::Build { .. } | Command::Archive { .. } | Command::Run { .. }
+        ) {
+            regen::if_stale(wrapper, non_interactive, ignore_version_mismatch)
+                .map_err(Error::RegenFailed)?;
+        }
+        // These don't build anything, but still go by what's in `gen/`.
+        if matches!(
+            command,
+            Command::Open
+                | Command::Symbolicate { dsyms: None, .. }
+                | Command::Upload { ipa: None, .. }
+                | Command::Inspect { path: None, .. }
+                | Command::Install { app: None, .. }
+                | Command::Launch { .. }
+                | Command::Clean { .. }
+        ) {
+            regen::if_version_mismatched(wrapper, non_interactive, ignore_version_mismatch)
+                .map_err(Error::RegenFailed)?;
+        }
         match command {
             Command::Open => {
                 version_check()?;
-                with_config(non_interactive, wrapper, |config, _| {
+                with_config(non_interactive, wrapper, &[], None, |config, _| {
                     ensure_init(config)?;
+                    toolchain_check(config)?;
                     open_in_xcode(config)
                 })
             }
-            Command::Check { targets } => {
+            Command::Check {
+                targets,
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
+            } => {
                 version_check()?;
-                with_config(non_interactive, wrapper, |config, metadata| {
+                with_config(
+                    non_interactive,
+                    wrapper,
+                    &features,
+                    variant.as_deref(),
+                    |config, metadata| {
+                        call_for_targets_with_fallback(
+                            targets.iter(),
+                            &detect_target_ok,
+                            &env,
+                            |target: &Target| {
+                                target
+                                    .check(config, metadata, &env, noise_level)
+                                    .map_err(Error::CheckFailed)
+                            },
+                        )
+                        .map_err(Error::TargetInvalid)?
+                    },
+                )
+            }
+            Command::Build {
+                targets,
+                profile: cli::Profile { profile },
+                selection,
+                no_hooks: cli::NoHooks { no_hooks },
+                timings: cli::Timings { timings },
+                size_report: cli::SizeReport { size_report },
+                show_assets: cli::ShowAssets { show_assets },
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
+                identity: Identity { identity },
+                force: cli::Force { force },
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &features,
+                variant.as_deref(),
+                |config, metadata| {
+                    version_check()?;
+                    ensure_init(config)?;
+                    if show_assets {
+                        assets::show(config.app()).map_err(Error::AssetsFailed)?;
+                    }
+                    preflight::check(config.app()).map_err(Error::PreflightFailed)?;
+                    resources::configure(jobs, config.app().jobs(), None);
+                    check_features(config, metadata, &env);
+                    let _timings = timings::start(&config.project_dir(), timings, noise_level);
+                    let (config, profile) = select_build(config, &env, selection, profile)?;
+                    let config = &select_identity(&config, identity)?;
+                    let targets = get_targets(targets.iter(), Some((&detect_target_ok, &env)))
+                        .map_err(Error::TargetInvalid)?;
+                    let sizes = api::build(
+                        config,
+                        &env,
+                        &targets,
+                        api::BuildOptions {
+                            noise_level,
+                            profile,
+                            force,
+                            no_hooks,
+                        },
+                    )?;
+                    match sizes.summarize(config.app(), super::NAME, profile) {
+                        Some(summary) => {
+                            summary.print(size_report);
+                            summary
+                                .check(config.size_policy())
+                                .map_err(Error::SizesFailed)
+                        }
+                        None => Ok(()),
+                    }
+                },
+            ),
+            Command::Archive {
+                targets,
+                profile: cli::Profile { profile },
+                selection,
+                no_hooks: cli::NoHooks { no_hooks },
+                timings: cli::Timings { timings },
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
+                export_method,
+                archive_path,
+                out,
+                no_export,
+                identity: Identity { identity },
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &features,
+                variant.as_deref(),
+                |config, metadata| {
+                    version_check()?;
+                    ensure_init(config)?;
+                    preflight::check(config.app()).map_err(Error::PreflightFailed)?;
+                    resources::configure(jobs, config.app().jobs(), None);
+                    check_features(config, metadata, &env);
+                    let _timings = timings::start(&config.project_dir(), timings, noise_level);
+                    toolchain_check(config)?;
+                    let (config, profile) = select_build(config, &env, selection, profile)?;
+                    resolve_packages(&config)?;
+                    let config = &select_identity(&config, identity)?;
+                    let archive_path = archive_path.unwrap_or_else(|| config.archive_path());
+                    let export_dir = out.unwrap_or_else(|| config.export_dir());
                     call_for_targets_with_fallback(
                         targets.iter(),
                         &detect_target_ok,
                         &env,
                         |target: &Target| {
+                            if !target.is_simulator() {
+                                profiles::check(config, None).map_err(Error::ProfileCheckFailed)?;
+                            }
+                            target
+                                .run_hooks(config, &env, Stage::PreBuild, profile, no_hooks, None)
+                                .map_err(Error::HookFailed)?;
+                            target
+                                .build(config, &env, noise_level, profile, opts::Force::No)
+                                .map_err(Error::BuildFailed)?;
                             target
-                                .check(config, metadata, &env, noise_level)
-                                .map_err(Error::CheckFailed)
+                                .archive(config, &env, noise_level, profile, &archive_path)
+                                .map_err(Error::ArchiveFailed)?;
+                            target
+                                .run_hooks(
+                                    config,
+                                    &env,
+                                    Stage::PostBuild,
+                                    profile,
+                                    no_hooks,
+                                    Some(&archive_path),
+                                )
+                                .map_err(Error::HookFailed)?;
+                            let dsyms =
+                                dsym::collect(config, &archive_path).map_err(Error::DsymsFailed)?;
+                            if !dsyms.is_empty() {
+                                ui::status(format!(
+                                    "dSYMs saved to {}",
+                                    config.dsym_dir().display()
+                                ));
+                            }
+                            dsym::run_hook(config, &dsyms).map_err(Error::DsymsFailed)?;
+                            if no_export {
+                                println!("{}", archive_path.display());
+                                return Ok(());
+                            }
+                            target
+                                .export(
+                                    config,
+                                    &env,
+                                    noise_level,
+                                    &archive_path,
+                                    export_method,
+                                    &export_dir,
+                                )
+                                .map_err(Error::ExportFailed)?;
+                            let ipa_path = config
+                                .ipa_path_in(&export_dir)
+                                .map_err(|(old, new)| Error::IpaMissing { old, new })?;
+                            println!("{}", ipa_path.display());
+                            Ok(())
                         },
                     )
                     .map_err(Error::TargetInvalid)?
+                },
+            ),
+            Command::Xcframework {
+                profile: cli::Profile { profile },
+                force: cli::Force { force },
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
+                out,
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &features,
+                variant.as_deref(),
+                |config, metadata| {
+                    version_check()?;
+                    toolchain_check(config)?;
+                    let path = xcframework::create(
+                        config,
+                        metadata,
+                        &env,
+                        noise_level,
+                        profile,
+                        force,
+                        out,
+                    )
+                    .map_err(Error::XcframeworkFailed)?;
+                    println!("{}", path.display());
+                    Ok(())
+                },
+            ),
+            Command::Symbolicate { crash_log, dsyms } => {
+                with_config(non_interactive, wrapper, &[], None, |config, _| {
+                    let dsym_dir = dsyms.unwrap_or_else(|| config.dsym_dir());
+                    symbolicate::symbolicate(&crash_log, &dsym_dir)
+                        .map_err(Error::SymbolicateFailed)
                 })
             }
-            Command::Build {
-                targets,
-                profile: cli::Profile { profile },
-            } => with_config(non_interactive, wrapper, |config, _| {
-                version_check()?;
-                ensure_init(config)?;
-                call_for_targets_with_fallback(
-                    targets.iter(),
-                    &detect_target_ok,
-                    &env,
-                    |target: &Target| {
-                        target
-                            .build(config, &env, noise_level, profile)
-                            .map_err(Error::BuildFailed)
-                    },
-                )
-                .map_err(Error::TargetInvalid)?
+            Command::Upload {
+                ipa,
+                api_key,
+                key_id,
+                issuer_id,
+            } => with_config(non_interactive, wrapper, &[], None, |config, _| {
+                toolchain_check(config)?;
+                let ipa_path = match ipa {
+                    Some(ipa) => ipa,
+                    None => config
+                        .ipa_path()
+                        .map_err(|(old, new)| Error::IpaMissing { old, new })?,
+                };
+                let api_key = ApiKey {
+                    key_path: api_key,
+                    key_id,
+                    issuer_id,
+                };
+                upload::upload(&env, &ipa_path, &api_key).map_err(Error::UploadFailed)
             }),
-            Command::Archive {
-                targets,
+            Command::Inspect { path, json } => {
+                let inspect = |path: &Path, ignore: Option<&assets::Ignore>| {
+                    inspect::inspect(&env, path, ignore)
+                        .map(|inspection| inspection.print(json || format.json()))
+                        .map_err(Error::InspectFailed)
+                };
+                match path {
+                    Some(path) => inspect(&path, None),
+                    None => with_config(non_interactive, wrapper, &[], None, |config, _| {
+                        let path = inspect::latest(config).map_err(Error::InspectFailed)?;
+                        let ignore =
+                            assets::Ignore::load(config.app()).map_err(Error::AssetsFailed)?;
+                        inspect(&path, Some(&ignore))
+                    }),
+                }
+            }
+            Command::Run {
                 profile: cli::Profile { profile },
-            } => with_config(non_interactive, wrapper, |config, _| {
-                version_check()?;
+                selection,
+                no_hooks: cli::NoHooks { no_hooks },
+                no_crash_check: cli::NoCrashCheck { no_crash_check },
+                timings: cli::Timings { timings },
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
+                simulator,
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+                macos,
+                skip_log,
+                kill_on_exit,
+                identity: Identity { identity },
+                watch,
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &features,
+                variant.as_deref(),
+                |config, metadata| {
+                    version_check()?;
+                    ensure_init(config)?;
+                    preflight::check(config.app()).map_err(Error::PreflightFailed)?;
+                    resources::configure(jobs, config.app().jobs(), None);
+                    check_features(config, metadata, &env);
+                    // Watching starts a new session for each deploy instead.
+                    let _timings = timings::start(&config.project_dir(), timings, noise_level);
+                    toolchain_check(config)?;
+                    let (config, profile) = select_build(config, &env, selection, profile)?;
+                    let config = &config;
+                    if macos {
+                        desktop::run(config, &env, noise_level, profile, no_hooks)
+                            .map_err(Error::DesktopRunFailed)
+                    } else if let Some(query) = simulator {
+                        resolve_packages(config)?;
+                        let simulator = simctl::select(&env, query.as_deref(), non_interactive)
+                            .map_err(Error::SimulatorSelectFailed)?;
+                        if watch.yes() {
+                            simctl::prepare(&env, &simulator).map_err(Error::SimulatorRunFailed)?;
+                            let extra = sources::Sources::from_config(config)
+                                .map_err(Error::SourcesInvalid)?
+                                .watched();
+                            let mut first = true;
+                            let manifest_path = config.app().manifest_path();
+                            watch::watch(&manifest_path, &extra, &env, wrapper, |trigger| {
+                                // Xcode only rebuilds what's changed, so a full
+                                // rebuild means starting from scratch.
+                                if let Trigger::Rebuild = trigger {
+                                    let dir = config.simulator_derived_data_dir();
+                                    if !first && dir.is_dir() {
+                                        if let Err(err) = fs::remove_dir_all(&dir) {
+                                            log::warn!("failed to remove {:?}: {}", dir, err);
+                                        }
+                                    }
+                                }
+                                first = false;
+                                let _timings =
+                                    timings::start(&config.project_dir(), timings, noise_level);
+                                simctl::deploy(
+                                    config,
+                                    &env,
+                                    noise_level,
+                                    profile,
+                                    &simulator,
+                                    opts::SkipLog::Yes,
+                                    no_hooks,
+                                    no_crash_check,
+                                )
+                            })
+                            .map_err(Error::WatchFailed)
+                        } else {
+                            simctl::run(
+                                config,
+                                &env,
+                                noise_level,
+                                profile,
+                                &simulator,
+                                skip_log,
+                                kill_on_exit,
+                                no_hooks,
+                                no_crash_check,
+                            )
+                            .map_err(Error::SimulatorRunFailed)
+                        }
+                    } else {
+                        resolve_packages(config)?;
+                        // Simulators don't care about identities, so this only
+                        // matters for devices.
+                        let config = &select_identity(config, identity)?;
+                        let last = LastDevice::new(config.app().root_dir(), Platform::Apple);
+                        let device = select_device(
+                            &env,
+                            non_interactive,
+                            device_timeout,
+                            device.as_deref(),
+                            Some(&last),
+                        )?;
+                        profiles::check(config, Some(device.id()))
+                            .map_err(Error::ProfileCheckFailed)?;
+                        if watch.yes() {
+                            // Devices always go through the whole archive and
+                            // export dance, so both triggers do the same thing.
+                            let extra = sources::Sources::from_config(config)
+                                .map_err(Error::SourcesInvalid)?
+                                .watched();
+                            let manifest_path = config.app().manifest_path();
+                            watch::watch(&manifest_path, &extra, &env, wrapper, |_| {
+                                let _timings =
+                                    timings::start(&config.project_dir(), timings, noise_level);
+                                device
+                                    .run(
+                                        config,
+                                        &env,
+                                        noise_level,
+                                        non_interactive,
+                                        profile,
+                                        opts::SkipLog::Yes,
+                                        opts::KillOnExit::No,
+                                        no_hooks,
+                                        no_crash_check,
+                                    )
+                                    .map(|()| last.record(device.id()))
+                            })
+                            .map_err(Error::WatchFailed)
+                        } else {
+                            device
+                                .run(
+                                    config,
+                                    &env,
+                                    noise_level,
+                                    non_interactive,
+                                    profile,
+                                    skip_log,
+                                    kill_on_exit,
+                                    no_hooks,
+                                    no_crash_check,
+                                )
+                                .map(|()| last.record(device.id()))
+                                .map_err(Error::RunFailed)
+                        }
+                    }
+                },
+            ),
+            // An app from elsewhere doesn't need a project to go with it.
+            Command::Install {
+                app: Some(app),
+                simulator,
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+                ..
+            } => install(
+                &env,
+                non_interactive,
+                simulator,
+                device,
+                device_timeout,
+                |_| app,
+            ),
+            Command::Install {
+                app: None,
+                profile: cli::Profile { profile },
+                simulator,
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+            } => with_config(non_interactive, wrapper, &[], None, |config, _| {
                 ensure_init(config)?;
-                call_for_targets_with_fallback(
-                    targets.iter(),
-                    &detect_target_ok,
+                install(
                     &env,
-                    |target: &Target| {
-                        target
-                            .build(config, &env, noise_level, profile)
-                            .map_err(Error::BuildFailed)?;
-                        target
-                            .archive(config, &env, noise_level, profile)
-                            .map_err(Error::ArchiveFailed)
+                    non_interactive,
+                    simulator,
+                    device,
+                    device_timeout,
+                    |simulator| {
+                        if simulator {
+                            config.simulator_app_path(profile)
+                        } else {
+                            config.device_app_path(profile)
+                        }
                     },
                 )
-                .map_err(Error::TargetInvalid)?
             }),
-            Command::Run {
-                profile: cli::Profile { profile },
-            } => with_config(non_interactive, wrapper, |config, _| {
-                version_check()?;
-                ensure_init(config)?;
-                device_prompt(&env)
-                    .map_err(Error::DevicePromptFailed)?
-                    .run(config, &env, noise_level, non_interactive, profile)
-                    .map_err(Error::RunFailed)
+            Command::Launch {
+                bundle_id,
+                variant: cli::Variant { variant },
+                simulator,
+                device,
+                device_timeout: cli::DeviceTimeout { device_timeout },
+                skip_log,
+                kill_on_exit,
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &[],
+                variant.as_deref(),
+                |config, _| {
+                    let bundle_id = bundle_id.unwrap_or_else(|| config.bundle_id());
+                    if let Some(query) = simulator {
+                        let simulator = simctl::select(&env, query.as_deref(), non_interactive)
+                            .map_err(Error::SimulatorSelectFailed)?;
+                        simctl::boot(&env, &simulator).map_err(Error::SimulatorRunFailed)?;
+                        simctl::launch(config, &env, &simulator, &bundle_id, skip_log)
+                            .map_err(Error::SimulatorRunFailed)?;
+                        if skip_log.yes() {
+                            return Ok(());
+                        }
+                        simctl::follow(config, &env, &simulator, &bundle_id, kill_on_exit)
+                            .map_err(Error::SimulatorRunFailed)
+                    } else {
+                        select_device(
+                            &env,
+                            non_interactive,
+                            device_timeout,
+                            device.as_deref(),
+                            None,
+                        )?
+                        .launch(
+                            config,
+                            &env,
+                            &bundle_id,
+                            skip_log,
+                            kill_on_exit,
+                            // Apps launched on their own may well be meant to
+                            // exit, and `launch` doesn't check on simulators
+                            // either.
+                            opts::NoCrashCheck::Yes,
+                        )
+                        .map_err(Error::RunFailed)
+                    }
+                },
+            ),
+            Command::Clean { full, derived_data } => {
+                with_config(non_interactive, wrapper, &[], None, |config, _| {
+                    ensure_init(config)?;
+                    clean::clean(config, &env, full, derived_data).map_err(Error::CleanFailed)
+                })
+            }
+            Command::Screenshot {
+                simulator,
+                device,
+                out,
+            } => with_config(non_interactive, wrapper, &[], None, |config, _| {
+                let out = out.unwrap_or_else(|| capture::default_path(config, "png"));
+                if let Some(query) = device {
+                    let device = device::select(&env, &query, non_interactive)
+                        .map_err(Error::DeviceSelectFailed)?;
+                    capture::screenshot(&env, capture::Source::Device(device.id()), &out)
+                } else {
+                    let simulator =
+                        simctl::select_booted(&env, simulator.as_deref(), non_interactive)
+                            .map_err(Error::SimulatorSelectFailed)?;
+                    capture::screenshot(&env, capture::Source::Simulator(&simulator), &out)
+                }
+                .map_err(Error::CaptureFailed)
             }),
-            Command::List => ios_deploy::device_list(&env)
-                .map_err(Error::ListFailed)
-                .map(|device_list| {
-                    prompt::list_display_only(device_list.iter(), device_list.len());
+            Command::Record {
+                simulator,
+                device,
+                out,
+            } => with_config(non_interactive, wrapper, &[], None, |config, _| {
+                let out = out.unwrap_or_else(|| capture::default_path(config, "mp4"));
+                if let Some(query) = device {
+                    let device = device::select(&env, &query, non_interactive)
+                        .map_err(Error::DeviceSelectFailed)?;
+                    capture::record(&env, capture::Source::Device(device.id()), &out)
+                } else {
+                    let simulator =
+                        simctl::select_booted(&env, simulator.as_deref(), non_interactive)
+                            .map_err(Error::SimulatorSelectFailed)?;
+                    capture::record(&env, capture::Source::Simulator(&simulator), &out)
+                }
+                .map_err(Error::CaptureFailed)
+            }),
+            Command::Pull {
+                remote,
+                local,
+                simulator,
+                device,
+            } => with_config(non_interactive, wrapper, &[], None, |config, _| {
+                let bundle_id = config.bundle_id();
+                if let Some(query) = device {
+                    let device = device::select(&env, &query, non_interactive)
+                        .map_err(Error::DeviceSelectFailed)?;
+                    let endpoint = transfer::Endpoint::Device(&device);
+                    transfer::pull(&env, endpoint, &bundle_id, &remote, local.as_deref())
+                } else {
+                    let simulator =
+                        simctl::select_booted(&env, simulator.as_deref(), non_interactive)
+                            .map_err(Error::SimulatorSelectFailed)?;
+                    let endpoint = transfer::Endpoint::Simulator(&simulator);
+                    transfer::pull(&env, endpoint, &bundle_id, &remote, local.as_deref())
+                }
+                .map_err(Error::TransferFailed)
+            }),
+            Command::Push {
+                local,
+                remote,
+                simulator,
+                device,
+            } => with_config(non_interactive, wrapper, &[], None, |config, _| {
+                let bundle_id = config.bundle_id();
+                if let Some(query) = device {
+                    let device = device::select(&env, &query, non_interactive)
+                        .map_err(Error::DeviceSelectFailed)?;
+                    transfer::push(
+                        &env,
+                        transfer::Endpoint::Device(&device),
+                        &bundle_id,
+                        &local,
+                        &remote,
+                    )
+                } else {
+                    let simulator =
+                        simctl::select_booted(&env, simulator.as_deref(), non_interactive)
+                            .map_err(Error::SimulatorSelectFailed)?;
+                    transfer::push(
+                        &env,
+                        transfer::Endpoint::Simulator(&simulator),
+                        &bundle_id,
+                        &local,
+                        &remote,
+                    )
+                }
+                .map_err(Error::TransferFailed)
+            }),
+            Command::Simctl { simulator, args } => {
+                let simulator = simctl::select(&env, simulator.as_deref(), non_interactive)
+                    .map_err(Error::SimulatorSelectFailed)?;
+                util::passthrough::run(simctl::passthrough(&env, &simulator, &args))
+                    .map_err(Error::PassthroughFailed)
+            }
+            Command::List { simulators } => {
+                if simulators {
+                    simctl::device_list(&env)
+                        .map_err(Error::SimulatorListFailed)
+                        .map(|simulators| {
+                            prompt::list_display_only(simulators.iter(), simulators.len());
+                        })
+                } else {
+                    device::device_list(&env)
+                        .map_err(Error::ListFailed)
+                        .map(|device_list| {
+                            prompt::list_display_only(device_list.iter(), device_list.len());
+                        })
+                }
+            }
+            Command::Devices { json, names } => device::listings(&env)
+                .map_err(Error::DevicesFailed)
+                .map(|listings| {
+                    if names {
+                        // Simulators can't be picked with `--device`.
+                        crate::device::print_names(
+                            listings
+                                .iter()
+                                .filter(|listing| listing.kind == crate::device::Kind::Physical),
+                        )
+                    } else {
+                        crate::device::print_listings(&listings, json)
+                    }
+                }),
+            Command::Targets { json } => {
+                target::print_listings(&target::listings::<Target>(), json);
+                Ok(())
+            }
+            Command::Identities => identities::find_identities()
+                .map_err(Error::IdentitiesFailed)
+                .map(|identities| {
+                    prompt::list_display_only(identities.iter(), identities.len());
+                }),
+            Command::Profiles => profiles::find_profiles()
+                .map_err(Error::ProfilesFailed)
+                .map(|profiles| {
+                    prompt::list_display_only(profiles.iter(), profiles.len());
+                }),
+            Command::Teams => teams::find_development_teams()
+                .map_err(Error::TeamsFailed)
+                .map(|teams| {
+                    prompt::list_display_only(teams.iter(), teams.len());
                 }),
+            Command::Icons => with_config(non_interactive, wrapper, &[], None, |config, _| {
+                ensure_init(config)?;
+                icons::gen(config, true).map_err(Error::IconsFailed)
+            }),
+            Command::Env {
+                target,
+                shell,
+                json,
+                profile: cli::Profile { profile },
+                variant: cli::Variant { variant },
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &[],
+                variant.as_deref(),
+                |config, _| {
+                    let env = with_cargo_path(env)?;
+                    let build_config = config
+                        .build_config()
+                        .resolve(profile)
+                        .map_err(Error::BuildConfigFailed)?;
+                    call_for_targets_with_fallback(
+                        std::iter::once(&target),
+                        &detect_target_ok,
+                        &env,
+                        |target: &Target| {
+                            let sdk_root = target.sdk_root(&env).map_err(Error::SdkRootFailed)?;
+                            let mut build_env = target
+                                .build_env(config, &env, &sdk_root)
+                                .map_err(Error::BuildEnvFailed)?;
+                            build_config.apply(&mut build_env);
+                            print!("{}", build_env.render(Format::from_flags(shell, json)));
+                            Ok(())
+                        },
+                    )
+                    .map_err(Error::TargetInvalid)?
+                },
+            ),
+            Command::Completions {
+                completions: cli::Completions { shell },
+            } => {
+                cli::print_completions::<Input>(NAME, shell, true);
+                Ok(())
+            }
             Command::XcodeScript {
                 macos,
+                catalyst,
+                platform_name,
                 sdk_root,
-                profile,
+                configuration,
                 force_color,
+                features: cli::Features { features },
+                variant: cli::Variant { variant },
                 arches,
-            } => with_config(non_interactive, wrapper, |config, metadata| {
-                // The `PATH` env var Xcode gives us is missing any additions
-                // made by the user's profile, so we'll manually add cargo's
-                // `PATH`.
-                let env = env.prepend_to_path(
-                    util::home_dir()
-                        .map_err(Error::NoHomeDir)?
-                        .join(".cargo/bin"),
-                );
-
-                if !sdk_root.is_dir() {
-                    return Err(Error::SdkRootInvalid { sdk_root });
-                }
-                let include_dir = sdk_root.join("usr/include");
-                if !include_dir.is_dir() {
-                    return Err(Error::IncludeDirInvalid { include_dir });
-                }
-
-                let mut host_env = HashMap::<&str, &OsStr>::new();
-
-                // Host flags that are used by build scripts
-                let macos_isysroot = {
-                    let macos_sdk_root =
-                        sdk_root.join("../../../../MacOSX.platform/Developer/SDKs/MacOSX.sdk");
-                    if !macos_sdk_root.is_dir() {
-                        return Err(Error::MacosSdkRootInvalid { macos_sdk_root });
-                    }
-                    format!("-isysroot {}", macos_sdk_root.display())
-                };
-                host_env.insert("MAC_FLAGS", macos_isysroot.as_ref());
-                host_env.insert("CFLAGS_x86_64_apple_darwin", macos_isysroot.as_ref());
-                host_env.insert("CXXFLAGS_x86_64_apple_darwin", macos_isysroot.as_ref());
+            } => with_config(
+                non_interactive,
+                wrapper,
+                &features,
+                variant.as_deref(),
+                |config, metadata| {
+                    let profile = config.profile_for_configuration(&configuration);
+                    let build_config = config
+                        .build_config()
+                        .resolve(profile)
+                        .map_err(Error::BuildConfigFailed)?;
 
-                host_env.insert(
-                    "OBJC_INCLUDE_PATH_x86_64_apple_darwin",
-                    include_dir.as_os_str(),
-                );
+                    let env = with_cargo_path(env)?;
 
-                host_env.insert("RUST_BACKTRACE", "1".as_ref());
-
-                let macos_target = Target::macos();
-
-                let isysroot = format!("-isysroot {}", sdk_root.display());
-
-                for arch in arches {
-                    // Set target-specific flags
-                    let triple = match arch.as_str() {
-                        "arm64" => "aarch64_apple_ios",
-                        "x86_64" => "x86_64_apple_ios",
-                        _ => return Err(Error::ArchInvalid { arch }),
+                    let simulator = if let Some(platform_name) = platform_name {
+                        platform_name == "iphonesimulator"
+                    } else {
+                        // Projects generated before we passed `PLATFORM_NAME`
+                        // still give us `SDKROOT`, which looks like
+                        // `.../iPhoneSimulator14.2.sdk` for the simulator.
+                        sdk_root
+                            .file_name()
+                            .map(|name| name.to_string_lossy().starts_with("iPhoneSimulator"))
+                            .unwrap_or_default()
                     };
-                    let cflags = format!("CFLAGS_{}", triple);
-                    let cxxflags = format!("CFLAGS_{}", triple);
-                    let objc_include_path = format!("OBJC_INCLUDE_PATH_{}", triple);
-                    let mut target_env = host_env.clone();
-                    target_env.insert(cflags.as_ref(), isysroot.as_ref());
-                    target_env.insert(cxxflags.as_ref(), isysroot.as_ref());
-                    target_env.insert(objc_include_path.as_ref(), include_dir.as_ref());
 
-                    let target = if macos {
-                        &macos_target
-                    } else {
-                        Target::for_arch(&arch).ok_or_else(|| Error::ArchInvalid {
+                    let mut targets = Vec::with_capacity(arches.len());
+                    for arch in arches {
+                        // Catalyst builds claim to be for macOS, so that has to be
+                        // checked first.
+                        let target = if catalyst.unwrap_or_default() {
+                            Target::for_catalyst_arch(&arch)
+                        } else if macos {
+                            Target::for_macos_arch(&arch)
+                        } else if simulator {
+                            Target::for_simulator_arch(&arch)
+                        } else {
+                            Target::for_arch(&arch).copied()
+                        }
+                        .ok_or_else(|| Error::ArchInvalid {
                             arch: arch.to_owned(),
-                        })?
-                    };
-                    target
-                        .compile_lib(
+                        })?;
+
+                        let mut build_env = target
+                            .build_env(config, &env, &sdk_root)
+                            .map_err(Error::BuildEnvFailed)?;
+                        build_config.apply(&mut build_env);
+                        target
+                            .compile_lib(
+                                config,
+                                metadata,
+                                noise_level,
+                                force_color,
+                                profile,
+                                &build_env,
+                            )
+                            .map_err(Error::CompileLibFailed)?;
+                        targets.push(target);
+                    }
+
+                    // Building for "Any iOS Simulator Device" can require all of
+                    // the simulator slices in one lib. `build --force` clears the
+                    // fingerprints before Xcode gets here, so this redoes it too.
+                    if simulator {
+                        lipo::create_universal_simulator_lib(
                             config,
-                            metadata,
-                            noise_level,
-                            force_color,
+                            &targets,
                             profile,
-                            &env,
-                            target_env,
+                            opts::Force::No,
                         )
-                        .map_err(Error::CompileLibFailed)?;
-                }
-                Ok(())
-            }),
+                        .map_err(Error::LipoFailed)?;
+                    }
+                    Ok(())
+                },
+            ),
         }
     }
 }
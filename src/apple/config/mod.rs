@@ -2,29 +2,51 @@ mod raw;
 
 pub use self::raw::*;
 
+use super::{
+    entitlements::{self, Entitlements},
+    icons, info_plist,
+    packages::{self, Package},
+    pods::Pod,
+    xcode,
+};
 use crate::{
-    config::app::App,
+    config::{
+        app::App,
+        build_config::BuildConfig,
+        rustflags::{self, RustFlags},
+        variant::Variant,
+    },
+    entry_point, features,
+    hooks::Hooks,
+    opts, sizes,
     util::{self, cli::Report},
 };
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fmt::{self, Display},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 static DEFAULT_PROJECT_DIR: &str = "gen/apple";
+pub const DEFAULT_MIN_XCODE_VERSION: (u32, u32) = (11, 0);
+pub const DEFAULT_IOS_DEPLOYMENT_TARGET: (u32, u32) = (9, 0);
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct Platform {
     #[serde(default)]
     features: Option<Vec<String>>,
     #[serde(default)]
     frameworks: Option<Vec<String>>,
+    // Only ever set by merging in the config.
+    #[serde(skip)]
+    no_default_features: Option<bool>,
 }
 
 impl Platform {
     pub fn no_default_features(&self) -> bool {
-        self.features.is_some()
+        self.no_default_features
+            .unwrap_or_else(|| self.features.is_some())
     }
 
     pub fn features(&self) -> Option<&[String]> {
@@ -34,13 +56,32 @@ impl Platform {
     pub fn frameworks(&self) -> &[String] {
         self.frameworks.as_deref().unwrap_or_else(|| &[])
     }
+
+    fn with_config(
+        mut self,
+        name: &str,
+        features: Option<&[String]>,
+        no_default_features: Option<bool>,
+        cli_features: &[String],
+    ) -> Self {
+        let (no_default_features, features) = features::merge(
+            name,
+            self.features(),
+            features,
+            no_default_features,
+            cli_features,
+        );
+        self.no_default_features = Some(no_default_features);
+        self.features = features;
+        self
+    }
 }
 
 const fn default_true() -> bool {
     true
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Metadata {
     #[serde(default = "default_true")]
     supported: bool,
@@ -72,6 +113,37 @@ impl Metadata {
     pub fn macos(&self) -> &Platform {
         &self.macos
     }
+
+    /// Merges in the features from the config and the command line, which
+    /// take precedence over these.
+    pub fn with_config(self, config: &Config) -> Self {
+        Self {
+            supported: self.supported,
+            ios: self.ios.with_config(
+                "iOS",
+                config.ios_features.as_deref(),
+                config.ios_no_default_features,
+                &config.cli_features,
+            ),
+            macos: self.macos.with_config(
+                "macOS",
+                config.macos_features.as_deref(),
+                config.macos_no_default_features,
+                &config.cli_features,
+            ),
+        }
+    }
+
+    /// Every feature either platform gets built with.
+    pub fn features(&self) -> Vec<String> {
+        let mut features = self.ios.features().unwrap_or_default().to_vec();
+        for feature in self.macos.features().unwrap_or_default() {
+            if !features.contains(feature) {
+                features.push(feature.clone());
+            }
+        }
+        features
+    }
 }
 
 #[derive(Debug)]
@@ -110,7 +182,31 @@ impl Display for ProjectDirInvalid {
 pub enum Error {
     DevelopmentTeamMissing,
     DevelopmentTeamEmpty,
+    MinXcodeVersionInvalid {
+        version: String,
+    },
+    IosDeploymentTargetInvalid {
+        version: String,
+    },
+    InfoPlistInvalid(info_plist::Error),
+    LaunchScreenColorInvalid(icons::ColorInvalid),
+    EntitlementsInvalid(entitlements::Error),
+    PackageInvalid(packages::Error),
+    ConfigurationReserved {
+        configuration: String,
+    },
+    ConfigurationProfileInvalid {
+        configuration: String,
+        profile: String,
+    },
     ProjectDirInvalid(ProjectDirInvalid),
+    BuildStdTargetInvalid {
+        triple: String,
+    },
+    BuildStdEmpty {
+        triple: String,
+    },
+    RustflagsInvalid(rustflags::Error),
 }
 
 impl Error {
@@ -123,10 +219,76 @@ impl Error {
             Self::DevelopmentTeamEmpty => {
                 Report::error(msg, format!("`{}.development-team` is empty", super::NAME))
             }
+            Self::MinXcodeVersionInvalid { version } => Report::error(
+                msg,
+                format!(
+                    "`{}.min-xcode-version` {:?} isn't a valid version; it should look like \"12.4\"",
+                    super::NAME,
+                    version
+                ),
+            ),
+            Self::IosDeploymentTargetInvalid { version } => Report::error(
+                msg,
+                format!(
+                    "`{}.ios-deployment-target` {:?} isn't a valid version; it should look like \"13.0\"",
+                    super::NAME,
+                    version
+                ),
+            ),
+            Self::InfoPlistInvalid(err) => Report::error(msg, err),
+            Self::EntitlementsInvalid(err) => Report::error(
+                msg,
+                format!("`{}.entitlements` invalid: {}", super::NAME, err),
+            ),
+            Self::PackageInvalid(err) => Report::error(
+                msg,
+                format!("`{}.packages` invalid: {}", super::NAME, err),
+            ),
+            Self::ConfigurationReserved { configuration } => Report::error(
+                msg,
+                format!(
+                    "`{}.configurations` contains {:?}, which is always generated and can't be remapped",
+                    super::NAME,
+                    configuration
+                ),
+            ),
+            Self::ConfigurationProfileInvalid {
+                configuration,
+                profile,
+            } => Report::error(
+                msg,
+                format!(
+                    "`{}.configurations` maps {:?} to {:?}, but it has to be either \"debug\" or \"release\"",
+                    super::NAME,
+                    configuration,
+                    profile
+                ),
+            ),
+            Self::LaunchScreenColorInvalid(err) => Report::error(
+                msg,
+                format!("`{}.launch-screen-color` invalid: {}", super::NAME, err),
+            ),
             Self::ProjectDirInvalid(err) => Report::error(
                 msg,
                 format!("`{}.project-dir` invalid: {}", super::NAME, err),
             ),
+            Self::BuildStdTargetInvalid { triple } => Report::error(
+                msg,
+                format!(
+                    "`{}.build-std` has {:?}, which isn't an Apple target triple",
+                    super::NAME,
+                    triple
+                ),
+            ),
+            Self::BuildStdEmpty { triple } => Report::error(
+                msg,
+                format!(
+                    "`{}.build-std.{}` is empty; list the crates to build, i.e. [\"std\", \"panic_abort\"]",
+                    super::NAME,
+                    triple
+                ),
+            ),
+            Self::RustflagsInvalid(err) => err.report(msg),
         }
     }
 }
@@ -137,7 +299,67 @@ pub struct Config {
     #[serde(skip_serializing)]
     app: App,
     development_team: String,
+    signing_style: SigningStyle,
+    #[serde(skip_serializing)]
+    identity: Option<String>,
+    desktop: Desktop,
+    #[serde(skip_serializing)]
+    min_xcode_version: (u32, u32),
+    ios_deployment_target: String,
     project_dir: String,
+    icon: Option<String>,
+    #[serde(skip_serializing)]
+    launch_screen_color: icons::Color,
+    #[serde(skip_serializing)]
+    entitlements: Entitlements,
+    #[serde(skip_serializing)]
+    info_plist: info_plist::Properties,
+    #[serde(skip_serializing)]
+    xcframework_headers: Option<String>,
+    #[serde(skip_serializing)]
+    cbindgen: bool,
+    #[serde(skip_serializing)]
+    pods: Vec<Pod>,
+    #[serde(skip_serializing)]
+    packages: Vec<Package>,
+    #[serde(skip_serializing)]
+    sources: Vec<String>,
+    #[serde(skip_serializing)]
+    resources: Vec<String>,
+    #[serde(skip_serializing)]
+    bridging_header: Option<String>,
+    #[serde(skip_serializing)]
+    dsym_upload_command: Option<String>,
+    #[serde(skip_serializing)]
+    entry_symbols: Vec<String>,
+    #[serde(skip_serializing)]
+    configurations: BTreeMap<String, opts::Profile>,
+    #[serde(skip_serializing)]
+    scheme_override: Option<String>,
+    #[serde(skip_serializing)]
+    configuration_override: Option<String>,
+    #[serde(skip_serializing)]
+    hooks: Hooks,
+    #[serde(skip_serializing)]
+    size_policy: sizes::Policy,
+    #[serde(skip_serializing)]
+    ios_no_default_features: Option<bool>,
+    #[serde(skip_serializing)]
+    ios_features: Option<Vec<String>>,
+    #[serde(skip_serializing)]
+    macos_no_default_features: Option<bool>,
+    #[serde(skip_serializing)]
+    macos_features: Option<Vec<String>>,
+    #[serde(skip_serializing)]
+    env: BTreeMap<String, String>,
+    #[serde(skip_serializing)]
+    cli_features: Vec<String>,
+    #[serde(skip_serializing)]
+    variant: Option<Variant>,
+    #[serde(skip_serializing)]
+    build_std: BTreeMap<String, Vec<String>>,
+    #[serde(skip_serializing)]
+    rustflags: RustFlags,
 }
 
 impl Config {
@@ -148,6 +370,141 @@ impl Config {
             return Err(Error::DevelopmentTeamEmpty);
         }
 
+        let signing_style = raw.signing_style.unwrap_or_else(|| {
+            let signing_style = SigningStyle::default();
+            log::info!(
+                "`{}.signing-style` not set; defaulting to {}",
+                super::NAME,
+                signing_style.as_str()
+            );
+            signing_style
+        });
+
+        let desktop = raw.desktop.unwrap_or_else(|| {
+            let desktop = Desktop::default();
+            log::info!(
+                "`{}.desktop` not set; defaulting to {}",
+                super::NAME,
+                desktop.as_str()
+            );
+            desktop
+        });
+
+        let min_xcode_version = raw
+            .min_xcode_version
+            .map(|version| {
+                xcode::parse_version(&version)
+                    .ok_or_else(|| Error::MinXcodeVersionInvalid { version })
+            })
+            .unwrap_or_else(|| {
+                log::info!(
+                    "`{}.min-xcode-version` not set; defaulting to {}.{}",
+                    super::NAME,
+                    DEFAULT_MIN_XCODE_VERSION.0,
+                    DEFAULT_MIN_XCODE_VERSION.1
+                );
+                Ok(DEFAULT_MIN_XCODE_VERSION)
+            })?;
+
+        let (major, minor) = raw
+            .ios_deployment_target
+            .map(|version| {
+                xcode::parse_version(&version)
+                    .ok_or_else(|| Error::IosDeploymentTargetInvalid { version })
+            })
+            .unwrap_or_else(|| {
+                log::info!(
+                    "`{}.ios-deployment-target` not set; defaulting to {}.{}",
+                    super::NAME,
+                    DEFAULT_IOS_DEPLOYMENT_TARGET.0,
+                    DEFAULT_IOS_DEPLOYMENT_TARGET.1
+                );
+                Ok(DEFAULT_IOS_DEPLOYMENT_TARGET)
+            })?;
+        let ios_deployment_target = format!("{}.{}", major, minor);
+
+        let launch_screen_color = raw
+            .launch_screen_color
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map_err(Error::LaunchScreenColorInvalid)?
+            .unwrap_or_default();
+
+        let entitlements = raw
+            .entitlements
+            .map(Entitlements::from_raw)
+            .transpose()
+            .map_err(Error::EntitlementsInvalid)?
+            .unwrap_or_default();
+
+        let info_plist = raw
+            .info_plist
+            .as_ref()
+            .map(info_plist::Properties::from_toml)
+            .transpose()
+            .map_err(Error::InfoPlistInvalid)?
+            .unwrap_or_default();
+
+        let packages = raw
+            .packages
+            .unwrap_or_default()
+            .into_iter()
+            .map(Package::from_raw)
+            .collect::<Result<_, _>>()
+            .map_err(Error::PackageInvalid)?;
+
+        let configurations = raw
+            .configurations
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(configuration, profile)| {
+                if configuration.eq_ignore_ascii_case("debug")
+                    || configuration.eq_ignore_ascii_case("release")
+                {
+                    return Err(Error::ConfigurationReserved { configuration });
+                }
+                let profile = match profile.as_str() {
+                    "debug" => opts::Profile::Debug,
+                    "release" => opts::Profile::Release,
+                    _ => {
+                        return Err(Error::ConfigurationProfileInvalid {
+                            configuration,
+                            profile,
+                        })
+                    }
+                };
+                Ok((configuration, profile))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let build_std = raw
+            .build_std
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(triple, crates)| {
+                if !triple.contains("-apple-") {
+                    Err(Error::BuildStdTargetInvalid { triple })
+                } else if crates.is_empty() {
+                    Err(Error::BuildStdEmpty { triple })
+                } else {
+                    Ok((triple, crates))
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        // Desktop builds are left alone, so only iOS triples make sense.
+        let rustflags = RustFlags::from_raw(
+            super::NAME,
+            rustflags::Raw {
+                rustflags: raw.rustflags,
+                link_args: raw.link_args,
+            },
+            raw.target,
+            |triple| triple.contains("-apple-ios"),
+        )
+        .map_err(Error::RustflagsInvalid)?;
+
         let project_dir = raw
             .project_dir
             .map(|project_dir| {
@@ -178,7 +535,41 @@ impl Config {
         Ok(Self {
             app,
             development_team: raw.development_team,
+            signing_style,
+            identity: raw.identity,
+            desktop,
+            min_xcode_version,
+            ios_deployment_target,
             project_dir,
+            icon: raw.icon,
+            launch_screen_color,
+            entitlements,
+            info_plist,
+            xcframework_headers: raw.xcframework_headers,
+            cbindgen: raw.cbindgen.unwrap_or_default(),
+            pods: raw.pods.unwrap_or_default(),
+            packages,
+            sources: raw.sources.unwrap_or_default(),
+            resources: raw.resources.unwrap_or_default(),
+            bridging_header: raw.bridging_header,
+            dsym_upload_command: raw.dsym_upload_command,
+            entry_symbols: raw
+                .entry_symbols
+                .unwrap_or_else(|| vec![entry_point::APPLE_DEFAULT.to_owned()]),
+            configurations,
+            scheme_override: None,
+            configuration_override: None,
+            hooks: Hooks::from_raw(raw.hooks),
+            size_policy: sizes::Policy::from_raw(raw.size_report),
+            ios_no_default_features: raw.ios_no_default_features,
+            ios_features: raw.ios_features,
+            macos_no_default_features: raw.macos_no_default_features,
+            macos_features: raw.macos_features,
+            env: raw.env.unwrap_or_default(),
+            cli_features: Vec::new(),
+            variant: None,
+            build_std,
+            rustflags,
         })
     }
 
@@ -186,6 +577,212 @@ impl Config {
         &self.app
     }
 
+    /// What the Xcode project, its targets, and the built app are named.
+    pub fn product_name(&self) -> &str {
+        self.app.names().xcode_product_name()
+    }
+
+    pub fn development_team(&self) -> &str {
+        &self.development_team
+    }
+
+    pub fn signing_style(&self) -> SigningStyle {
+        self.signing_style
+    }
+
+    /// The codesigning identity to use instead of letting Xcode pick, as
+    /// either a name or a SHA-1.
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+
+    pub fn set_identity(&mut self, identity: String) {
+        self.identity = Some(identity);
+    }
+
+    pub fn desktop(&self) -> Desktop {
+        self.desktop
+    }
+
+    pub fn min_xcode_version(&self) -> (u32, u32) {
+        self.min_xcode_version
+    }
+
+    /// The oldest iOS the app runs on, as `<major>.<minor>`.
+    pub fn ios_deployment_target(&self) -> &str {
+        &self.ios_deployment_target
+    }
+
+    pub fn icon(&self) -> Option<PathBuf> {
+        self.icon.as_ref().map(|icon| self.app.prefix_path(icon))
+    }
+
+    pub fn launch_screen_color(&self) -> icons::Color {
+        self.launch_screen_color
+    }
+
+    pub fn entitlements(&self) -> &Entitlements {
+        &self.entitlements
+    }
+
+    pub fn info_plist(&self) -> &info_plist::Properties {
+        &self.info_plist
+    }
+
+    /// Whether to generate XCFramework headers with `cbindgen`.
+    pub fn cbindgen(&self) -> bool {
+        self.cbindgen
+    }
+
+    pub fn xcframework_headers_dir(&self) -> PathBuf {
+        match &self.xcframework_headers {
+            Some(dir) => self.app.prefix_path(dir),
+            None if self.cbindgen => self.project_dir().join("build/include"),
+            None => self
+                .project_dir()
+                .join(format!("Sources/{}/bindings", self.product_name())),
+        }
+    }
+
+    pub fn pods(&self) -> &[Pod] {
+        &self.pods
+    }
+
+    pub fn uses_pods(&self) -> bool {
+        !self.pods.is_empty()
+    }
+
+    /// Swift packages the iOS app depends on.
+    pub fn packages(&self) -> &[Package] {
+        &self.packages
+    }
+
+    /// Extra native source paths or globs, relative to the app root.
+    pub fn extra_sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    /// Extra resource paths or globs, relative to the app root.
+    pub fn extra_resources(&self) -> &[String] {
+        &self.resources
+    }
+
+    pub fn bridging_header(&self) -> Option<&str> {
+        self.bridging_header.as_deref()
+    }
+
+    /// Run for each dSYM after archiving, with `{dsym}` replaced by its path.
+    pub fn dsym_upload_command(&self) -> Option<&str> {
+        self.dsym_upload_command.as_deref()
+    }
+
+    /// What the built lib has to export for the app to start.
+    pub fn entry_symbols(&self) -> &[String] {
+        &self.entry_symbols
+    }
+
+    /// Extra build configurations, and the cargo profile each one uses.
+    pub fn configurations(&self) -> &BTreeMap<String, opts::Profile> {
+        &self.configurations
+    }
+
+    /// The crates to build from source for `triple`, if it has no prebuilt
+    /// std (or one that won't do).
+    pub fn build_std(&self, triple: &str) -> Option<&[String]> {
+        self.build_std.get(triple).map(Vec::as_slice)
+    }
+
+    /// Every target that builds std from source.
+    pub fn build_std_triples(&self) -> impl Iterator<Item = &str> {
+        self.build_std.keys().map(String::as_str)
+    }
+
+    /// Extra flags for rustc, from `rustflags`, `link-args`, and
+    /// `target.<triple>`.
+    pub fn rustflags(&self) -> &RustFlags {
+        &self.rustflags
+    }
+
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    pub fn size_policy(&self) -> sizes::Policy {
+        self.size_policy
+    }
+
+    /// Extra env vars for cargo, hooks, and Xcode.
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    /// Features passed with `--features` (along with the variant's), which
+    /// Xcode needs to hand back to us when its build phase calls us to build
+    /// the lib.
+    pub fn cli_features(&self) -> &[String] {
+        &self.cli_features
+    }
+
+    pub fn with_cli_features(mut self, features: Vec<String>) -> Self {
+        self.cli_features = features;
+        self
+    }
+
+    pub fn variant(&self) -> Option<&Variant> {
+        self.variant.as_ref()
+    }
+
+    /// Overlays the variant's env and features. This has to come after
+    /// [`Self::with_cli_features`].
+    pub fn with_variant(mut self, variant: Option<Variant>) -> Self {
+        if let Some(variant) = &variant {
+            self.env.extend(variant.env().clone());
+            for feature in variant.features() {
+                if !self.cli_features.contains(feature) {
+                    self.cli_features.push(feature.clone());
+                }
+            }
+        }
+        self.variant = variant;
+        self
+    }
+
+    /// The app's build config with the variant's overlaid.
+    pub fn build_config(&self) -> BuildConfig {
+        self.app.build_config().with_variant(self.variant())
+    }
+
+    /// The name of the app icon set for a variant with its own icon.
+    pub fn variant_icon_set(variant: &Variant) -> String {
+        format!("AppIcon-{}", variant.name())
+    }
+
+    /// The cargo profile to build the lib with when Xcode builds
+    /// `configuration`.
+    pub fn profile_for_configuration(&self, configuration: &str) -> opts::Profile {
+        match self.configurations.get(configuration) {
+            Some(profile) => *profile,
+            None if configuration == "release" => opts::Profile::Release,
+            None => opts::Profile::Debug,
+        }
+    }
+
+    /// The configuration to pass to `xcodebuild`, which is the generated one
+    /// for `profile` unless another was selected.
+    pub fn configuration(&self, profile: opts::Profile) -> String {
+        self.configuration_override
+            .clone()
+            .unwrap_or_else(|| profile.as_str().to_owned())
+    }
+
+    pub fn set_configuration(&mut self, configuration: String) {
+        self.configuration_override = Some(configuration);
+    }
+
+    pub fn set_scheme(&mut self, scheme: String) {
+        self.scheme_override = Some(scheme);
+    }
+
     pub fn project_dir(&self) -> PathBuf {
         self.app.prefix_path(&self.project_dir)
     }
@@ -194,11 +791,39 @@ impl Config {
         self.project_dir().is_dir()
     }
 
+    pub fn asset_catalog_dir(&self) -> PathBuf {
+        self.project_dir().join("Assets.xcassets")
+    }
+
+    pub fn launch_screen_path(&self) -> PathBuf {
+        self.project_dir().join("LaunchScreen.storyboard")
+    }
+
+    pub fn podfile_path(&self) -> PathBuf {
+        self.project_dir().join("Podfile")
+    }
+
+    pub fn xcodeproj_path(&self) -> PathBuf {
+        self.project_dir()
+            .join(format!("{}.xcodeproj", self.product_name()))
+    }
+
+    pub fn pbxproj_path(&self) -> PathBuf {
+        self.xcodeproj_path().join("project.pbxproj")
+    }
+
+    /// CocoaPods creates its own workspace, which has to be used instead of
+    /// the project's built-in one so that the pods get built too.
     pub fn workspace_path(&self) -> PathBuf {
-        self.project_dir().join(format!(
-            "{}.xcodeproj/project.xcworkspace/",
-            self.app.name()
-        ))
+        if self.uses_pods() {
+            self.project_dir()
+                .join(format!("{}.xcworkspace/", self.product_name()))
+        } else {
+            self.project_dir().join(format!(
+                "{}.xcodeproj/project.xcworkspace/",
+                self.product_name()
+            ))
+        }
     }
 
     pub fn archive_dir(&self) -> PathBuf {
@@ -209,15 +834,42 @@ impl Config {
         self.project_dir().join("build")
     }
 
+    pub fn archive_path(&self) -> PathBuf {
+        self.archive_dir()
+            .join(format!("{}.xcarchive", self.scheme()))
+    }
+
+    pub fn dsym_dir(&self) -> PathBuf {
+        self.project_dir().join("build/dSYMs")
+    }
+
+    pub fn xcframework_path(&self) -> PathBuf {
+        self.project_dir()
+            .join(format!("build/{}.xcframework", self.product_name()))
+    }
+
+    pub fn log_path(&self, action: &str) -> PathBuf {
+        self.project_dir()
+            .join(format!("build/logs/xcodebuild-{}.log", action))
+    }
+
+    pub fn simulator_console_path(&self) -> PathBuf {
+        self.project_dir().join("build/logs/simulator-console.log")
+    }
+
     pub fn export_plist_path(&self) -> PathBuf {
-        self.project_dir().join("ExportOptions.plist")
+        self.export_dir().join("ExportOptions.plist")
     }
 
     pub fn ipa_path(&self) -> Result<PathBuf, (PathBuf, PathBuf)> {
-        let path = |tail: &str| self.export_dir().join(format!("{}.ipa", tail));
+        self.ipa_path_in(&self.export_dir())
+    }
+
+    pub fn ipa_path_in(&self, export_dir: &Path) -> Result<PathBuf, (PathBuf, PathBuf)> {
+        let path = |tail: &str| export_dir.join(format!("{}.ipa", tail));
         let old = path(&self.scheme());
         // It seems like the format changed recently?
-        let new = path(self.app.name());
+        let new = path(self.product_name());
         std::iter::once(&old)
             .chain(std::iter::once(&new))
             .filter(|path| {
@@ -232,10 +884,91 @@ impl Config {
 
     pub fn app_path(&self) -> PathBuf {
         self.export_dir()
-            .join(format!("Payload/{}.app", self.app.name()))
+            .join(format!("Payload/{}.app", self.product_name()))
+    }
+
+    pub fn lib_path(&self, triple: &str, profile: opts::Profile) -> PathBuf {
+        self.app.prefix_path(format!(
+            "target/{}/{}/lib{}.a",
+            triple,
+            profile.as_str(),
+            self.app.names().lib_name()
+        ))
+    }
+
+    /// Where `lipo` puts the fat archive when Xcode builds more than one
+    /// simulator arch at once.
+    pub fn universal_simulator_lib_path(&self, profile: opts::Profile) -> PathBuf {
+        self.lib_path("universal-apple-ios-sim", profile)
+    }
+
+    pub fn device_derived_data_dir(&self) -> PathBuf {
+        self.project_dir().join("build/device")
+    }
+
+    pub fn device_app_path(&self, profile: opts::Profile) -> PathBuf {
+        self.device_derived_data_dir().join(format!(
+            "Build/Products/{}-iphoneos/{}.app",
+            self.configuration(profile),
+            self.product_name()
+        ))
+    }
+
+    pub fn simulator_derived_data_dir(&self) -> PathBuf {
+        self.project_dir().join("build/simulator")
+    }
+
+    pub fn simulator_app_path(&self, profile: opts::Profile) -> PathBuf {
+        self.simulator_derived_data_dir().join(format!(
+            "Build/Products/{}-iphonesimulator/{}.app",
+            self.configuration(profile),
+            self.product_name()
+        ))
+    }
+
+    pub fn desktop_derived_data_dir(&self) -> PathBuf {
+        self.project_dir().join("build/desktop")
+    }
+
+    pub fn desktop_app_path(&self, profile: opts::Profile) -> PathBuf {
+        let configuration = self.configuration(profile);
+        let products_dir = if self.desktop == Desktop::Catalyst {
+            format!("{}-maccatalyst", configuration)
+        } else {
+            configuration
+        };
+        self.desktop_derived_data_dir().join(format!(
+            "Build/Products/{}/{}.app",
+            products_dir,
+            self.product_name()
+        ))
+    }
+
+    /// This includes the variant's suffix, if there is one.
+    pub fn bundle_id(&self) -> String {
+        format!(
+            "{}.{}{}",
+            self.app.reverse_domain(),
+            self.product_name(),
+            self.variant()
+                .map(Variant::identifier_suffix)
+                .unwrap_or_default()
+        )
     }
 
     pub fn scheme(&self) -> String {
-        format!("{}_iOS", self.app.name())
+        self.scheme_override
+            .clone()
+            .unwrap_or_else(|| format!("{}_iOS", self.product_name()))
+    }
+
+    /// Catalyst builds are just the iOS app built for the Mac, so they share
+    /// its scheme.
+    pub fn desktop_scheme(&self) -> String {
+        if self.desktop == Desktop::MacOs {
+            format!("{}_macOS", self.product_name())
+        } else {
+            self.scheme()
+        }
     }
 }
@@ -1,10 +1,18 @@
 use crate::{
-    apple::teams,
-    util::{cli::TextWrapper, prompt},
+    apple::{entitlements, packages, pods, teams},
+    config::{self, rustflags},
+    hooks, sizes,
+    util::{
+        cli::{Report, TextWrapper},
+        prompt,
+    },
 };
 use colored::{Color, Colorize as _};
 use serde::{Deserialize, Serialize};
-use std::fmt::{self, Display};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
 
 #[derive(Debug)]
 pub enum DetectError {
@@ -42,32 +50,190 @@ impl Display for PromptError {
     }
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SigningStyle {
+    Automatic,
+    Manual,
+}
+
+impl Default for SigningStyle {
+    fn default() -> Self {
+        Self::Automatic
+    }
+}
+
+impl SigningStyle {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Automatic => "automatic",
+            Self::Manual => "manual",
+        }
+    }
+}
+
+/// What the Apple project gets for running on the desktop, if anything.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Desktop {
+    #[serde(rename = "macos")]
+    MacOs,
+    Catalyst,
+    None,
+}
+
+impl Default for Desktop {
+    fn default() -> Self {
+        Self::MacOs
+    }
+}
+
+impl Desktop {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MacOs => "macos",
+            Self::Catalyst => "catalyst",
+            Self::None => "none",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Raw {
     pub development_team: String,
+    pub signing_style: Option<SigningStyle>,
+    pub identity: Option<String>,
+    pub desktop: Option<Desktop>,
+    pub min_xcode_version: Option<String>,
+    pub ios_deployment_target: Option<String>,
     pub project_dir: Option<String>,
     pub ios_no_default_features: Option<bool>,
     pub ios_features: Option<Vec<String>>,
     pub macos_no_default_features: Option<bool>,
     pub macos_features: Option<Vec<String>>,
+    pub env: Option<BTreeMap<String, String>>,
+    pub icon: Option<String>,
+    pub launch_screen_color: Option<String>,
+    pub entitlements: Option<entitlements::Raw>,
+    pub info_plist: Option<toml::value::Table>,
+    pub xcframework_headers: Option<String>,
+    pub cbindgen: Option<bool>,
+    pub pods: Option<Vec<pods::Pod>>,
+    pub packages: Option<Vec<packages::Raw>>,
+    pub sources: Option<Vec<String>>,
+    pub resources: Option<Vec<String>>,
+    pub bridging_header: Option<String>,
+    pub dsym_upload_command: Option<String>,
+    pub entry_symbols: Option<Vec<String>>,
+    pub configurations: Option<BTreeMap<String, String>>,
+    pub hooks: Option<hooks::Raw>,
+    pub size_report: Option<sizes::Raw>,
+    pub build_std: Option<BTreeMap<String, Vec<String>>>,
+    pub rustflags: Option<Vec<String>>,
+    pub link_args: Option<Vec<String>>,
+    pub target: Option<BTreeMap<String, rustflags::Raw>>,
+}
+
+/// The keys each table under `apple` can have, for suggesting what an unknown
+/// key was meant to be. `info-plist` and `entitlements.raw` are passed along
+/// as-is, so anything goes there.
+pub fn known_keys(table: &[&str]) -> &'static [&'static str] {
+    match table {
+        [] => config::known_fields::<Raw>(),
+        ["entitlements"] => config::known_fields::<entitlements::Raw>(),
+        ["pods", _] => config::known_fields::<pods::Pod>(),
+        ["packages", _] => config::known_fields::<packages::Raw>(),
+        ["hooks"] => config::known_fields::<hooks::Raw>(),
+        ["size-report"] => config::known_fields::<sizes::Raw>(),
+        ["target", _] => config::known_fields::<rustflags::Raw>(),
+        _ => &[],
+    }
+}
+
+/// Warns if the team ID doesn't match any codesigning identity on this machine.
+/// This is never fatal, since the cert could just live on somebody else's
+/// machine (or CI).
+pub fn check_development_team(wrapper: &TextWrapper, development_team: &str) {
+    match teams::has_codesigning_identity(development_team) {
+        Ok(true) => (),
+        Ok(false) => Report::action_request(
+            format!(
+                "No valid codesigning identity was found for development team {:?}",
+                development_team
+            ),
+            "Signing will fail on this machine unless you install a certificate for this team. If the team ID is wrong, run `cargo apple teams` to see which teams were detected.",
+        )
+        .print(wrapper),
+        Err(err) => Report::action_request(
+            format!(
+                "Failed to check development team {:?} against your codesigning identities",
+                development_team
+            ),
+            err,
+        )
+        .print(wrapper),
+    }
 }
 
 impl Raw {
-    pub fn detect() -> Result<Self, DetectError> {
-        let development_teams =
-            teams::find_development_teams().map_err(DetectError::DeveloperTeamLookupFailed)?;
-        Ok(Self {
-            development_team: development_teams
-                .get(0)
-                .map(|development_team| development_team.id.clone())
-                .ok_or_else(|| DetectError::DeveloperTeamsEmpty)?,
+    fn new(development_team: String) -> Self {
+        Self {
+            development_team,
+            signing_style: None,
+            identity: None,
+            desktop: None,
+            min_xcode_version: None,
+            ios_deployment_target: None,
             project_dir: None,
             ios_no_default_features: None,
             ios_features: None,
             macos_no_default_features: None,
             macos_features: None,
-        })
+            env: None,
+            icon: None,
+            launch_screen_color: None,
+            entitlements: None,
+            info_plist: None,
+            xcframework_headers: None,
+            cbindgen: None,
+            pods: None,
+            packages: None,
+            sources: None,
+            resources: None,
+            bridging_header: None,
+            dsym_upload_command: None,
+            entry_symbols: None,
+            configurations: None,
+            hooks: None,
+            size_report: None,
+            build_std: None,
+            rustflags: None,
+            link_args: None,
+            target: None,
+        }
+    }
+
+    /// A placeholder team, for configs that only exist to render templates
+    /// with.
+    pub fn sample() -> Self {
+        Self::new("ABCDE12345".to_owned())
+    }
+
+    /// Uses a team ID that was explicitly specified, skipping detection and
+    /// prompting altogether.
+    pub fn from_development_team(wrapper: &TextWrapper, development_team: String) -> Self {
+        check_development_team(wrapper, &development_team);
+        Self::new(development_team)
+    }
+
+    pub fn detect() -> Result<Self, DetectError> {
+        let development_teams =
+            teams::find_development_teams().map_err(DetectError::DeveloperTeamLookupFailed)?;
+        development_teams
+            .get(0)
+            .map(|development_team| Self::new(development_team.id.clone()))
+            .ok_or_else(|| DetectError::DeveloperTeamsEmpty)
     }
 
     pub fn prompt(wrapper: &TextWrapper) -> Result<Self, PromptError> {
@@ -135,13 +301,6 @@ impl Raw {
                 }
             }
         };
-        Ok(Self {
-            development_team,
-            project_dir: None,
-            ios_no_default_features: None,
-            ios_features: None,
-            macos_no_default_features: None,
-            macos_features: None,
-        })
+        Ok(Self::new(development_team))
     }
 }
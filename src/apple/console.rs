@@ -0,0 +1,27 @@
+use crate::util::{interrupt, timings};
+
+/// Runs a command until it exits or the user hits Ctrl-C, giving it the chance
+/// to finish up after the interrupt. Being interrupted isn't considered a
+/// failure.
+pub fn run_until_interrupted(command: bossy::Command) -> bossy::Result<()> {
+    // Ctrl-C is delivered to the whole foreground process group, so the
+    // command gets it too; we just need to survive it ourselves so that we can
+    // clean up afterwards.
+    let _survive = interrupt::survive();
+    match command.run_and_wait() {
+        Ok(_) => Ok(()),
+        Err(_) if interrupt::interrupted() => {
+            println!();
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Runs a log streaming command until it exits or the user hits Ctrl-C. The
+/// timings summary is printed first, since it'd otherwise be lost in the logs.
+pub fn stream(command: bossy::Command) -> bossy::Result<()> {
+    timings::print_summary();
+    println!("Streaming logs; press Ctrl-C to stop.");
+    run_until_interrupted(command)
+}
@@ -4,7 +4,7 @@ mod xcode_plugin;
 use self::update::{Outdated, OutdatedError};
 use super::system_profile::{self, DeveloperTools};
 use crate::{
-    opts,
+    opts, ui,
     util::{
         self,
         cli::{Report, TextWrapper},
@@ -45,7 +45,7 @@ pub fn install(
         let found = util::command_present(package)
             .map_err(|source| Error::PresenceCheckFailed { package, source })?;
         if !found || reinstall_deps.yes() {
-            println!("Installing `{}`...", package);
+            ui::status(format!("Installing `{}`...", package));
             // reinstall works even if it's not installed yet, and will upgrade
             // if it's already installed!
             bossy::Command::impure_parse("brew reinstall")
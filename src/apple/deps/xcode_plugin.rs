@@ -1,5 +1,5 @@
 use crate::{
-    opts,
+    opts, ui,
     util::{
         self,
         cli::{Report, TextWrapper},
@@ -233,7 +233,7 @@ pub fn install(
     )?;
     log::info!("`rust-xcode-plugin` installation status: {:?}", status);
     if status.stale() {
-        println!("Installing `rust-xcode-plugin`...");
+        ui::status("Installing `rust-xcode-plugin`...");
         repo.update("https://github.com/BrainiumLLC/rust-xcode-plugin.git")
             .map_err(Error::UpdateFailed)?;
         if check_uuid(wrapper, xcode_version, repo.path(), &xcode_app_dir)? {
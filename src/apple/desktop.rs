@@ -0,0 +1,93 @@
+use super::{
+    config::Config,
+    console,
+    target::{BuildError, HookError, Target},
+};
+use crate::{
+    env::Env,
+    hooks::Stage,
+    opts,
+    target::TargetTrait as _,
+    ui,
+    util::cli::{Report, Reportable},
+};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum RunError {
+    Disabled,
+    RustupFailed(bossy::Error),
+    BuildFailed(BuildError),
+    AppMissing { path: PathBuf },
+    AppFailed(bossy::Error),
+    HookFailed(HookError),
+}
+
+impl Reportable for RunError {
+    fn report(&self) -> Report {
+        match self {
+            Self::Disabled => Report::action_request(
+                "Desktop builds are disabled for this project",
+                "Set `apple.desktop` to `macos` or `catalyst` in your `mobile.toml`, then run `cargo mobile init` to add it to the Xcode project.",
+            ),
+            Self::RustupFailed(err) => Report::error("Failed to `rustup` desktop toolchain", err),
+            Self::BuildFailed(err) => err.report(),
+            Self::AppMissing { path } => Report::error(
+                "App bundle appears to be missing",
+                format!("Not found at {:?}", path),
+            ),
+            Self::AppFailed(err) => Report::error("App exited with an error", err),
+            Self::HookFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Disabled => "apple.desktop.run.disabled",
+            Self::RustupFailed(..) => "apple.desktop.run.rustup-failed",
+            Self::BuildFailed(err) => err.code(),
+            Self::AppMissing { .. } => "apple.desktop.run.app-missing",
+            Self::AppFailed(..) => "apple.desktop.run.app-failed",
+            Self::HookFailed(err) => err.code(),
+        }
+    }
+}
+
+/// Builds the desktop variant and runs it in the foreground, so its output
+/// goes straight to the terminal. It's signed ad-hoc, so this works without
+/// a development team.
+pub fn run(
+    config: &Config,
+    env: &Env,
+    noise_level: opts::NoiseLevel,
+    profile: opts::Profile,
+    no_hooks: opts::NoHooks,
+) -> Result<(), RunError> {
+    let target = Target::desktop(config.desktop()).ok_or(RunError::Disabled)?;
+    let run_hooks = |stage: Stage, artifact: Option<&Path>| {
+        target
+            .run_hooks(config, env, stage, profile, no_hooks, artifact)
+            .map_err(RunError::HookFailed)
+    };
+    target.install().map_err(RunError::RustupFailed)?;
+    run_hooks(Stage::PreBuild, None)?;
+    ui::status("Building app...");
+    target
+        .build_for_desktop(config, env, noise_level, profile)
+        .map_err(RunError::BuildFailed)?;
+    let app_path = config.desktop_app_path(profile);
+    if !app_path.is_dir() {
+        return Err(RunError::AppMissing { path: app_path });
+    }
+    run_hooks(Stage::PostBuild, Some(&app_path))?;
+    run_hooks(Stage::PreRun, None)?;
+    ui::status("Launching app...");
+    // The user's env is passed through, since that's how you'd set things
+    // like `RUST_LOG`.
+    console::stream(bossy::Command::impure(
+        app_path.join("Contents/MacOS").join(config.product_name()),
+    ))
+    .map_err(RunError::AppFailed)?;
+    // The app runs in the foreground, so it's already exited by now.
+    run_hooks(Stage::PostRun, Some(&app_path))
+}
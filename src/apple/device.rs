@@ -1,18 +1,215 @@
 use super::{
     config::Config,
-    ios_deploy,
-    target::{ArchiveError, BuildError, ExportError, Target},
+    devicectl, export,
+    install::{self, Destination},
+    ios_deploy, simctl,
+    target::{ArchiveError, BuildError, ExportError, HookError, Target},
 };
 use crate::{
+    device::{self, Blocked, ChooseError, Found, Kind, Listing, State, Transport},
     env::{Env, ExplicitEnv as _},
-    opts,
+    hooks::Stage,
+    opts, ui, util,
     util::cli::{Report, Reportable},
 };
 use std::{
+    collections::BTreeSet,
     fmt::{self, Display},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
+#[derive(Debug)]
+pub enum DeviceListError {
+    DevicectlFailed(devicectl::DeviceListError),
+    IosDeployFailed(ios_deploy::DeviceListError),
+    CommandPresentFailed(bossy::Error),
+    NoBackend,
+}
+
+impl Reportable for DeviceListError {
+    fn report(&self) -> Report {
+        match self {
+            Self::DevicectlFailed(err) => err.report(),
+            Self::IosDeployFailed(err) => err.report(),
+            Self::CommandPresentFailed(err) => {
+                Report::error("Failed to check for `ios-deploy`", err)
+            }
+            Self::NoBackend => Report::action_request(
+                "Running on iOS devices requires Xcode 15 or `ios-deploy`",
+                "Update Xcode to get `devicectl`, or install `ios-deploy` with `brew install ios-deploy`. You can also run `cargo apple open` and run from Xcode instead.",
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DevicectlFailed(err) => err.code(),
+            Self::IosDeployFailed(err) => err.code(),
+            Self::CommandPresentFailed(..) => "apple.device.device-list.command-present-failed",
+            Self::NoBackend => "apple.device.device-list.no-backend",
+        }
+    }
+}
+
+/// Lists devices from `devicectl` and `ios-deploy`, whichever are available.
+/// When both find the same device, the `devicectl` one wins.
+pub fn device_list<'a>(env: &Env) -> Result<BTreeSet<Device<'a>>, DeviceListError> {
+    let devicectl_available = devicectl::available(env);
+    let ios_deploy_available =
+        util::command_present("ios-deploy").map_err(DeviceListError::CommandPresentFailed)?;
+    if !devicectl_available && !ios_deploy_available {
+        return Err(DeviceListError::NoBackend);
+    }
+    let mut devices = if devicectl_available {
+        devicectl::device_list(env).map_err(DeviceListError::DevicectlFailed)?
+    } else {
+        BTreeSet::new()
+    };
+    if ios_deploy_available {
+        for device in ios_deploy::device_list(env).map_err(DeviceListError::IosDeployFailed)? {
+            if !devices
+                .iter()
+                .any(|other| other.id.eq_ignore_ascii_case(&device.id))
+            {
+                devices.insert(device);
+            }
+        }
+    }
+    Ok(devices)
+}
+
+/// Devices that are connected but can't be used yet, which are the ones
+/// worth waiting for before selecting a device with `query`. Any ready device
+/// will do when none was asked for, so then they're only worth waiting for if
+/// there aren't any.
+pub fn blocked(env: &Env, query: Option<&str>) -> Result<Vec<Blocked>, DeviceListError> {
+    let devices = device_list(env)?;
+    let candidates = devices
+        .iter()
+        .filter(|device| query.map_or(true, |query| device.matches(query)))
+        .collect::<Vec<_>>();
+    let blocked = candidates
+        .iter()
+        .filter_map(|device| device.blocked(env))
+        .collect::<Vec<_>>();
+    if query.is_none() && blocked.len() < candidates.len() {
+        Ok(Vec::new())
+    } else {
+        Ok(blocked)
+    }
+}
+
+#[derive(Debug)]
+pub enum ListingsError {
+    DevicesFailed(DeviceListError),
+    SimulatorsFailed(simctl::DeviceListError),
+}
+
+impl Reportable for ListingsError {
+    fn report(&self) -> Report {
+        match self {
+            Self::DevicesFailed(err) => err.report(),
+            Self::SimulatorsFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DevicesFailed(err) => err.code(),
+            Self::SimulatorsFailed(err) => err.code(),
+        }
+    }
+}
+
+/// Lists connected devices followed by available simulators. Not having any
+/// way to deploy to devices just means there aren't any to list.
+pub fn listings(env: &Env) -> Result<Vec<Listing>, ListingsError> {
+    let devices = match device_list(env) {
+        Ok(devices) => devices,
+        Err(DeviceListError::NoBackend) => {
+            log::info!(
+                "neither `devicectl` nor `ios-deploy` is available; only listing simulators"
+            );
+            BTreeSet::new()
+        }
+        Err(err) => return Err(ListingsError::DevicesFailed(err)),
+    };
+    let simulators = simctl::device_list(env).map_err(ListingsError::SimulatorsFailed)?;
+    Ok(devices
+        .iter()
+        .map(Device::listing)
+        .chain(simulators.iter().map(simctl::Simulator::listing))
+        .collect())
+}
+
+#[derive(Debug)]
+pub enum SelectError {
+    ListFailed(DeviceListError),
+    NotFound {
+        query: String,
+        available: Vec<String>,
+    },
+    ChooseFailed(ChooseError),
+}
+
+impl Reportable for SelectError {
+    fn report(&self) -> Report {
+        match self {
+            Self::ListFailed(err) => err.report(),
+            Self::NotFound { query, available } => Report::error(
+                "Failed to select iOS device",
+                if available.is_empty() {
+                    format!(
+                        "No device has the name or UDID {:?}, and no devices are connected",
+                        query
+                    )
+                } else {
+                    format!(
+                        "No device has the name or UDID {:?}; connected devices are:\n{}",
+                        query,
+                        available.join("\n")
+                    )
+                },
+            ),
+            Self::ChooseFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ListFailed(err) => err.code(),
+            Self::NotFound { .. } => "apple.device.select.not-found",
+            Self::ChooseFailed(err) => err.code(),
+        }
+    }
+}
+
+/// Picks a connected device by UDID (or the start of one) or name.
+pub fn select<'a>(
+    env: &Env,
+    query: &str,
+    non_interactive: opts::NonInteractive,
+) -> Result<Device<'a>, SelectError> {
+    let devices = device_list(env).map_err(SelectError::ListFailed)?;
+    let available = devices
+        .iter()
+        .map(|device| format!("{} [{}]", device, device.id))
+        .collect();
+    let device = match device::find(devices, query, Device::id, |device| &device.name) {
+        Found::One(device) => device,
+        Found::Several(matches) => device::choose(matches, query, non_interactive, Device::id)
+            .map_err(SelectError::ChooseFailed)?,
+        Found::Nothing => {
+            return Err(SelectError::NotFound {
+                query: query.to_owned(),
+                available,
+            })
+        }
+    };
+    ui::status(format!("Using device: {}", device));
+    Ok(device)
+}
+
 #[derive(Debug)]
 pub enum RunError {
     BuildFailed(BuildError),
@@ -20,7 +217,13 @@ pub enum RunError {
     ExportFailed(ExportError),
     IpaMissing { old: PathBuf, new: PathBuf },
     UnzipFailed(bossy::Error),
+    Unpaired { name: String },
     DeployFailed(ios_deploy::RunAndDebugError),
+    DevicectlFailed(devicectl::RunError),
+    HookFailed(HookError),
+    AppInvalid(install::Error),
+    LaunchUnsupported { name: String },
+    KillOnExitUnsupported { name: String },
 }
 
 impl Reportable for RunError {
@@ -34,22 +237,70 @@ impl Reportable for RunError {
                 format!("Not found at either {:?} or {:?}", old, new),
             ),
             Self::UnzipFailed(err) => Report::error("Failed to unzip archive", err),
+            Self::Unpaired { name } => Report::action_request(
+                format!("{} isn't paired with this computer", name),
+                "Unlock your device and connect it, then tap \"Trust\" when asked whether to trust this computer. Then try again!",
+            ),
             Self::DeployFailed(err) => err.report(),
+            Self::DevicectlFailed(err) => err.report(),
+            Self::HookFailed(err) => err.report(),
+            Self::AppInvalid(err) => err.report(),
+            Self::LaunchUnsupported { name } => Report::action_request(
+                format!("Can't launch apps on {} without installing them", name),
+                "`ios-deploy` can only launch apps as it installs them, so this needs `devicectl`, which comes with Xcode 15. You can use `cargo apple run` instead.",
+            ),
+            Self::KillOnExitUnsupported { name } => Report::action_request(
+                format!("Can't terminate apps on {}", name),
+                "`ios-deploy` has no way to terminate an app, so `--kill-on-exit` needs `devicectl`, which comes with Xcode 15. Leave off `--kill-on-exit`, or stop the app from the device.",
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::BuildFailed(err) => err.code(),
+            Self::ArchiveFailed(err) => err.code(),
+            Self::ExportFailed(err) => err.code(),
+            Self::IpaMissing { .. } => "apple.device.run.ipa-missing",
+            Self::UnzipFailed(..) => "apple.device.run.unzip-failed",
+            Self::Unpaired { .. } => "apple.device.run.unpaired",
+            Self::DeployFailed(err) => err.code(),
+            Self::DevicectlFailed(err) => err.code(),
+            Self::HookFailed(err) => err.code(),
+            Self::AppInvalid(err) => err.code(),
+            Self::LaunchUnsupported { .. } => "apple.device.run.launch-unsupported",
+            Self::KillOnExitUnsupported { .. } => "apple.device.run.kill-on-exit-unsupported",
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Backend {
+    Devicectl,
+    IosDeploy,
+}
+
 #[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Device<'a> {
     id: String,
     name: String,
     model: String,
     target: &'a Target<'a>,
+    backend: Backend,
+    paired: bool,
+    os_version: Option<String>,
+    transport: Transport,
 }
 
+// Identical phones can have identical names, so everything that could tell
+// them apart is shown along with it.
 impl<'a> Display for Device<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} ({})", self.name, self.model)
+        write!(f, "{} ({}, ", self.name, self.model)?;
+        if let Some(os_version) = &self.os_version {
+            write!(f, "iOS {}, ", os_version)?;
+        }
+        write!(f, "{}, {})", self.transport, device::short_id(&self.id))
     }
 }
 
@@ -60,13 +311,144 @@ impl<'a> Device<'a> {
             name,
             model,
             target,
+            backend: Backend::IosDeploy,
+            paired: true,
+            os_version: None,
+            // We only ask `ios-deploy` about USB devices.
+            transport: Transport::Usb,
+        }
+    }
+
+    /// `ios-deploy` doesn't tell us the OS version, so only `devicectl`
+    /// devices have one.
+    pub(super) fn with_devicectl(
+        mut self,
+        paired: bool,
+        os_version: Option<String>,
+        transport: Transport,
+    ) -> Self {
+        self.backend = Backend::Devicectl;
+        self.paired = paired;
+        self.os_version = os_version;
+        self.transport = transport;
+        self
+    }
+
+    pub fn listing(&self) -> Listing {
+        Listing {
+            name: self.name.clone(),
+            id: self.id.clone(),
+            platform: "ios",
+            os_version: self.os_version.clone(),
+            arch: self.target.arch.to_owned(),
+            kind: Kind::Physical,
+            transport: Some(self.transport),
+            state: if self.paired {
+                State::Online
+            } else {
+                State::Unauthorized
+            },
         }
     }
 
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
     pub fn target(&self) -> &'a Target<'a> {
         self.target
     }
 
+    fn matches(&self, query: &str) -> bool {
+        self.name.eq_ignore_ascii_case(query)
+            || self
+                .id
+                .to_ascii_lowercase()
+                .starts_with(&query.to_ascii_lowercase())
+    }
+
+    // Only `devicectl` can tell us whether a device is locked, and
+    // `ios-deploy` only lists paired devices.
+    fn blocked(&self, env: &Env) -> Option<Blocked> {
+        if !self.paired {
+            Some(Blocked {
+                name: self.name.clone(),
+                problem: "doesn't trust this computer",
+                guidance: "Unlock it, and tap \"Trust\" when asked whether to trust this computer. If you don't see that, unplug it and plug it back in.",
+            })
+        } else if self.backend == Backend::Devicectl && devicectl::locked(env, &self.id) {
+            Some(Blocked {
+                name: self.name.clone(),
+                problem: "is locked",
+                guidance: "Unlock it with your passcode, Face ID, or Touch ID.",
+            })
+        } else {
+            None
+        }
+    }
+
+    fn check_paired(&self) -> Result<(), RunError> {
+        if self.paired {
+            Ok(())
+        } else {
+            Err(RunError::Unpaired {
+                name: self.name.clone(),
+            })
+        }
+    }
+
+    /// Installs the `.app` or `.ipa` at `path`, as long as it was built for
+    /// this device.
+    pub fn install(&self, env: &Env, path: &Path) -> Result<(), RunError> {
+        self.check_paired()?;
+        let app_path = install::app_bundle(env, path).map_err(RunError::AppInvalid)?;
+        install::check(
+            &app_path,
+            Destination {
+                name: &self.name,
+                arch: self.target.arch,
+                simulator: false,
+            },
+        )
+        .map_err(RunError::AppInvalid)?;
+        match self.backend {
+            Backend::Devicectl => devicectl::install(env, &self.id, &self.name, &app_path)
+                .map_err(RunError::DevicectlFailed),
+            Backend::IosDeploy => {
+                ios_deploy::install(env, &self.id, &app_path).map_err(RunError::DeployFailed)
+            }
+        }
+    }
+
+    /// Launches the installed app with the bundle ID `bundle_id`.
+    pub fn launch(
+        &self,
+        config: &Config,
+        env: &Env,
+        bundle_id: &str,
+        skip_log: opts::SkipLog,
+        kill_on_exit: opts::KillOnExit,
+        no_crash_check: opts::NoCrashCheck,
+    ) -> Result<(), RunError> {
+        self.check_paired()?;
+        match self.backend {
+            Backend::Devicectl => devicectl::launch(
+                config,
+                env,
+                &self.id,
+                &self.name,
+                bundle_id,
+                skip_log,
+                kill_on_exit,
+                no_crash_check,
+            )
+            .map_err(RunError::DevicectlFailed),
+            Backend::IosDeploy => Err(RunError::LaunchUnsupported {
+                name: self.name.clone(),
+            }),
+        }
+    }
+
     pub fn run(
         &self,
         config: &Config,
@@ -74,21 +456,46 @@ impl<'a> Device<'a> {
         noise_level: opts::NoiseLevel,
         non_interactive: opts::NonInteractive,
         profile: opts::Profile,
+        skip_log: opts::SkipLog,
+        kill_on_exit: opts::KillOnExit,
+        no_hooks: opts::NoHooks,
+        no_crash_check: opts::NoCrashCheck,
     ) -> Result<(), RunError> {
+        // There's no point in building if we can't install, or if we can't
+        // do what was asked once we have.
+        self.check_paired()?;
+        if kill_on_exit.yes() && self.backend == Backend::IosDeploy {
+            return Err(RunError::KillOnExitUnsupported {
+                name: self.name.clone(),
+            });
+        }
+        let run_hooks = |stage: Stage, artifact: Option<&Path>| {
+            self.target
+                .run_hooks(config, env, stage, profile, no_hooks, artifact)
+                .map_err(RunError::HookFailed)
+        };
+        run_hooks(Stage::PreBuild, None)?;
         // TODO: These steps are run unconditionally, which is slooooooow
-        println!("Building app...");
+        ui::status("Building app...");
         self.target
-            .build(config, env, noise_level, profile)
+            .build(config, env, noise_level, profile, opts::Force::No)
             .map_err(RunError::BuildFailed)?;
-        println!("Archiving app...");
+        ui::status("Archiving app...");
         self.target
-            .archive(config, env, noise_level, profile)
+            .archive(config, env, noise_level, profile, &config.archive_path())
             .map_err(RunError::ArchiveFailed)?;
-        println!("Exporting app...");
+        ui::status("Exporting app...");
         self.target
-            .export(config, env, noise_level)
+            .export(
+                config,
+                env,
+                noise_level,
+                &config.archive_path(),
+                export::Method::Development,
+                &config.export_dir(),
+            )
             .map_err(RunError::ExportFailed)?;
-        println!("Extracting IPA...");
+        ui::status("Extracting IPA...");
         bossy::Command::pure("unzip")
             .with_env_vars(env.explicit_env())
             .with_args(if noise_level.pedantic() {
@@ -106,8 +513,30 @@ impl<'a> Device<'a> {
             .with_arg(&config.export_dir())
             .run_and_wait()
             .map_err(RunError::UnzipFailed)?;
-        ios_deploy::run_and_debug(config, env, non_interactive, &self.id)
-            .map_err(RunError::DeployFailed)?;
-        Ok(())
+        let app_path = config.app_path();
+        run_hooks(Stage::PostBuild, Some(&app_path))?;
+        run_hooks(Stage::PreRun, None)?;
+        match self.backend {
+            Backend::Devicectl => devicectl::install_and_launch(
+                config,
+                env,
+                &self.id,
+                &self.name,
+                skip_log,
+                kill_on_exit,
+                no_crash_check,
+            )
+            .map_err(RunError::DevicectlFailed)?,
+            // `ios-deploy` stays attached with a debugger, which already
+            // shows a crash for what it is.
+            Backend::IosDeploy => {
+                log::info!("deploying using `ios-deploy`");
+                ios_deploy::run_and_debug(config, env, non_interactive, &self.id, skip_log)
+                    .map_err(RunError::DeployFailed)?
+            }
+        }
+        // Unless `skip_log` is set, we're attached to the app until it exits
+        // (or the user stops us), so this runs after the session ends.
+        run_hooks(Stage::PostRun, Some(&app_path))
     }
 }
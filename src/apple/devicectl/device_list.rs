@@ -0,0 +1,192 @@
+use super::{devicectl, run_json, JsonError};
+use crate::{
+    apple::{device::Device, target::Target},
+    device::Transport,
+    env::Env,
+    util::cli::{Report, Reportable},
+};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+
+#[derive(Debug)]
+pub enum DeviceListError {
+    DetectionFailed(JsonError),
+    ArchInvalid(String),
+}
+
+impl Reportable for DeviceListError {
+    fn report(&self) -> Report {
+        let msg = "Failed to detect connected iOS devices";
+        match self {
+            Self::DetectionFailed(err) => Report::error(msg, err),
+            Self::ArchInvalid(arch) => {
+                Report::error(msg, format!("{:?} isn't a valid target arch.", arch))
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DetectionFailed(..) => "apple.devicectl.device-list.device-list.detection-failed",
+            Self::ArchInvalid(..) => "apple.devicectl.device-list.device-list.arch-invalid",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectionProperties {
+    pairing_state: String,
+    tunnel_state: String,
+    transport_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeviceProperties {
+    name: String,
+    os_version_number: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CpuType {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HardwareProperties {
+    cpu_type: CpuType,
+    marketing_name: String,
+    platform: String,
+    udid: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DevicectlDevice {
+    connection_properties: ConnectionProperties,
+    device_properties: DeviceProperties,
+    hardware_properties: HardwareProperties,
+}
+
+#[derive(Debug, Deserialize)]
+struct DevicectlDeviceList {
+    devices: Vec<DevicectlDevice>,
+}
+
+pub fn device_list<'a>(env: &Env) -> Result<BTreeSet<Device<'a>>, DeviceListError> {
+    run_json::<DevicectlDeviceList>(devicectl(env).with_args(&["list", "devices"]))
+        .map_err(DeviceListError::DetectionFailed)
+        .and_then(devices)
+}
+
+fn devices<'a>(list: DevicectlDeviceList) -> Result<BTreeSet<Device<'a>>, DeviceListError> {
+    list.devices
+        .into_iter()
+        // Devices that were paired once but aren't around anymore stick
+        // around as "unavailable".
+        .filter(|device| {
+            device.hardware_properties.platform == "iOS"
+                && device.connection_properties.tunnel_state != "unavailable"
+        })
+        .map(|device| {
+            let paired = device.connection_properties.pairing_state == "paired";
+            // Anything that isn't a cable is the network, whatever `devicectl`
+            // decides to call it.
+            let transport = match device.connection_properties.transport_type.as_deref() {
+                Some("wired") | None => Transport::Usb,
+                Some(_) => Transport::Wifi,
+            };
+            let HardwareProperties {
+                cpu_type,
+                marketing_name,
+                udid,
+                ..
+            } = device.hardware_properties;
+            let DeviceProperties {
+                name,
+                os_version_number,
+            } = device.device_properties;
+            Target::for_arch(&cpu_type.name)
+                .map(|target| {
+                    Device::new(udid, name, marketing_name, target).with_devicectl(
+                        paired,
+                        os_version_number,
+                        transport,
+                    )
+                })
+                .ok_or_else(|| DeviceListError::ArchInvalid(cpu_type.name))
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LockState {
+    passcode_required: bool,
+}
+
+/// Whether the device with the UDID `id` has to be unlocked before anything
+/// can be installed or launched. Xcode versions that can't tell us are
+/// assumed to have an unlocked device, since installing will complain anyway.
+pub fn locked(env: &Env, id: &str) -> bool {
+    run_json::<LockState>(devicectl(env).with_args(&[
+        "device",
+        "info",
+        "lockState",
+        "--device",
+        id,
+    ]))
+    .map(|state| state.passcode_required)
+    .unwrap_or_else(|err| {
+        log::info!("failed to get lock state of device {:?}: {}", id, err);
+        false
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::parse_json, *};
+    use crate::device::State;
+
+    #[test]
+    fn test_devices() {
+        let list = parse_json(include_str!("transcripts/list-devices.json")).unwrap();
+        assert_eq!(
+            devices(list)
+                .unwrap()
+                .iter()
+                .map(|device| (device.to_string(), device.listing().state))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "Fish Pad (iPad Air (5th generation), iOS 17.1, Wi-Fi, …002E)".to_owned(),
+                    State::Online
+                ),
+                (
+                    "New Phone (iPhone 14, USB, …801E)".to_owned(),
+                    State::Unauthorized
+                ),
+                (
+                    "Fish Phone (iPhone 15 Pro, iOS 17.2.1, USB, …001C)".to_owned(),
+                    State::Online
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lock_state() {
+        let state = parse_json::<LockState>(include_str!("transcripts/lock-state.json")).unwrap();
+        assert!(state.passcode_required);
+    }
+
+    #[test]
+    fn test_parse_failure() {
+        assert!(matches!(
+            parse_json::<LockState>(r#"{ "info": { "outcome": "failed" } }"#),
+            Err(JsonError::ParseFailed(..))
+        ));
+    }
+}
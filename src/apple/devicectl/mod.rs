@@ -0,0 +1,89 @@
+mod device_list;
+mod run;
+
+pub use self::{device_list::*, run::*};
+
+use crate::{
+    env::{Env, ExplicitEnv as _},
+    util,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::PathBuf,
+};
+
+/// `devicectl` ships with Xcode 15 and later.
+pub fn available(env: &Env) -> bool {
+    let found = bossy::Command::pure("xcrun")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["--find", "devicectl"])
+        .run_and_wait_for_output()
+        .is_ok();
+    log::info!("`devicectl` {}found", if found { "" } else { "not " });
+    found
+}
+
+fn devicectl(env: &Env) -> bossy::Command {
+    bossy::Command::pure("xcrun")
+        .with_env_vars(env.explicit_env())
+        .with_arg("devicectl")
+}
+
+#[derive(Debug)]
+pub enum JsonError {
+    DirCreationFailed { path: PathBuf, cause: io::Error },
+    CommandFailed(bossy::Error),
+    ReadFailed { path: PathBuf, cause: io::Error },
+    ParseFailed(serde_json::Error),
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DirCreationFailed { path, cause } => write!(
+                f,
+                "Failed to create directory for `devicectl` output at {:?}: {}",
+                path, cause
+            ),
+            Self::CommandFailed(err) => write!(f, "`devicectl` failed: {}", err),
+            Self::ReadFailed { path, cause } => write!(
+                f,
+                "Failed to read `devicectl` output at {:?}: {}",
+                path, cause
+            ),
+            Self::ParseFailed(err) => write!(f, "Failed to parse `devicectl` output: {}", err),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Output<T> {
+    result: T,
+}
+
+fn parse_json<T: DeserializeOwned>(raw: &str) -> Result<T, JsonError> {
+    serde_json::from_str::<Output<T>>(raw)
+        .map(|output| output.result)
+        .map_err(JsonError::ParseFailed)
+}
+
+// `devicectl` only writes JSON to a file, and doesn't accept `/dev/stdout`.
+// The file goes in a directory of its own, so that it's removed along with the
+// directory whether or not `devicectl` succeeded.
+fn run_json<T: DeserializeOwned>(command: bossy::Command) -> Result<T, JsonError> {
+    let dir = util::TempDir::new("devicectl")
+        .map_err(|(path, cause)| JsonError::DirCreationFailed { path, cause })?;
+    let path = dir.path().join("output.json");
+    command
+        .with_args(&["--quiet", "--json-output"])
+        .with_arg(&path)
+        .run_and_wait_for_output()
+        .map_err(JsonError::CommandFailed)?;
+    let raw = fs::read_to_string(&path).map_err(|cause| JsonError::ReadFailed {
+        path: path.clone(),
+        cause,
+    })?;
+    parse_json(&raw)
+}
@@ -0,0 +1,277 @@
+use super::{devicectl, run_json, JsonError};
+use crate::{
+    apple::{config::Config, console},
+    crash::{self, Crashed},
+    env::{Env, ExplicitEnv as _},
+    opts, ui, util,
+    util::{
+        cli::{Report, Reportable},
+        interrupt,
+        progress::{self, Phase},
+    },
+};
+use serde::Deserialize;
+use std::{path::Path, time::Instant};
+
+#[derive(Debug)]
+pub enum RunError {
+    Locked { name: String },
+    InstallFailed(bossy::Error),
+    LaunchFailed(bossy::Error),
+    CommandPresentFailed(bossy::Error),
+    LogStreamFailed(bossy::Error),
+    TerminateFailed(TerminateError),
+    Crashed(Crashed),
+}
+
+impl Reportable for RunError {
+    fn report(&self) -> Report {
+        match self {
+            Self::Locked { name } => Report::action_request(
+                format!("{} is locked", name),
+                "Unlock your device, and if you're asked whether to trust this computer, tap \"Trust\". Then try again!",
+            ),
+            Self::InstallFailed(err) => Report::error("Failed to install app on device", err),
+            Self::LaunchFailed(err) => Report::error("Failed to launch app on device", err),
+            Self::CommandPresentFailed(err) => {
+                Report::error("Failed to check for `idevicesyslog`", err)
+            }
+            Self::LogStreamFailed(err) => Report::error("Failed to stream device logs", err),
+            Self::TerminateFailed(err) => err.report(),
+            Self::Crashed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Locked { .. } => "apple.devicectl.run.run.locked",
+            Self::InstallFailed(..) => "apple.devicectl.run.run.install-failed",
+            Self::LaunchFailed(..) => "apple.devicectl.run.run.launch-failed",
+            Self::CommandPresentFailed(..) => "apple.devicectl.run.run.command-present-failed",
+            Self::LogStreamFailed(..) => "apple.devicectl.run.run.log-stream-failed",
+            Self::TerminateFailed(err) => err.code(),
+            Self::Crashed(err) => err.code(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TerminateError {
+    ListFailed(JsonError),
+    SignalFailed(bossy::Error),
+}
+
+impl Reportable for TerminateError {
+    fn report(&self) -> Report {
+        let msg = "Failed to terminate app on device";
+        match self {
+            Self::ListFailed(err) => Report::error(msg, err),
+            Self::SignalFailed(err) => Report::error(msg, err),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ListFailed(..) => "apple.devicectl.run.terminate.list-failed",
+            Self::SignalFailed(..) => "apple.devicectl.run.terminate.signal-failed",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Process {
+    executable: Option<String>,
+    process_identifier: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProcessList {
+    running_processes: Vec<Process>,
+}
+
+// The app's own processes, out of everything running on the device.
+fn app_processes(config: &Config, env: &Env, id: &str) -> Result<Vec<Process>, JsonError> {
+    run_json::<ProcessList>(devicectl(env).with_args(&[
+        "device",
+        "info",
+        "processes",
+        "--device",
+        id,
+    ]))
+    .map(|list| own_processes(list.running_processes, config.product_name()))
+}
+
+fn own_processes(processes: Vec<Process>, product_name: &str) -> Vec<Process> {
+    let executable = format!("/{0}.app/{0}", product_name);
+    processes
+        .into_iter()
+        .filter(|process| {
+            process
+                .executable
+                .as_deref()
+                .map(|path| path.ends_with(&executable))
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn terminate(config: &Config, env: &Env, id: &str) -> Result<(), TerminateError> {
+    let processes = app_processes(config, env, id).map_err(TerminateError::ListFailed)?;
+    for process in &processes {
+        devicectl(env)
+            .with_args(&["device", "process", "terminate", "--device", id])
+            .with_args(&["--pid", &process.process_identifier.to_string()])
+            .run_and_wait_for_output()
+            .map_err(TerminateError::SignalFailed)?;
+    }
+    Ok(())
+}
+
+fn is_locked(err: &bossy::Error) -> bool {
+    err.output()
+        .map(|output| {
+            let output = format!(
+                "{}{}",
+                String::from_utf8_lossy(output.stdout()),
+                String::from_utf8_lossy(output.stderr())
+            )
+            .to_lowercase();
+            output.contains("locked") || output.contains("passcode")
+        })
+        .unwrap_or_default()
+}
+
+pub fn install_and_launch(
+    config: &Config,
+    env: &Env,
+    id: &str,
+    name: &str,
+    skip_log: opts::SkipLog,
+    kill_on_exit: opts::KillOnExit,
+    no_crash_check: opts::NoCrashCheck,
+) -> Result<(), RunError> {
+    install(env, id, name, &config.app_path())?;
+    launch(
+        config,
+        env,
+        id,
+        name,
+        &config.bundle_id(),
+        skip_log,
+        kill_on_exit,
+        no_crash_check,
+    )
+}
+
+/// Installs the app bundle at `app_path` on the device with the UDID `id`.
+pub fn install(env: &Env, id: &str, name: &str, app_path: &Path) -> Result<(), RunError> {
+    ui::status("Installing app on device...");
+    let install = devicectl(env)
+        .with_args(&["device", "install", "app", "--device", id])
+        .with_arg(app_path);
+    progress::phase(Phase::Install, None, || install.run_and_wait_for_output()).map_err(|err| {
+        if is_locked(&err) {
+            RunError::Locked {
+                name: name.to_owned(),
+            }
+        } else {
+            RunError::InstallFailed(err)
+        }
+    })?;
+    Ok(())
+}
+
+const CRASH_REPORT_HINT: &str = "Its crash report can be exported from Xcode's Devices and Simulators window, and `cargo apple symbolicate` can symbolicate it using the dSYMs saved by `archive`.";
+
+/// Launches the installed app with the bundle ID `bundle_id`, and then
+/// streams its logs unless `skip_log` is set. Unless `no_crash_check` is
+/// set, the app has to stay up for [`crash::WINDOW`] after launching.
+pub fn launch(
+    config: &Config,
+    env: &Env,
+    id: &str,
+    name: &str,
+    bundle_id: &str,
+    skip_log: opts::SkipLog,
+    kill_on_exit: opts::KillOnExit,
+    no_crash_check: opts::NoCrashCheck,
+) -> Result<(), RunError> {
+    // `idevicesyslog` can follow the app without being attached to it, so
+    // it's preferred over `--console` when it's installed.
+    let syslog = !skip_log.yes()
+        && util::command_present("idevicesyslog").map_err(RunError::CommandPresentFailed)?;
+    let console = !skip_log.yes() && !syslog;
+    ui::status("Launching app...");
+    let launch = devicectl(env)
+        .with_args(&["device", "process", "launch", "--terminate-existing"])
+        .with_args(&["--device", id])
+        .with_arg(bundle_id);
+    if console {
+        log::info!("streaming device logs using `devicectl --console`");
+        let launched_at = Instant::now();
+        // Held until we know whether it was Ctrl-C that stopped it.
+        let _survive = interrupt::survive();
+        console::stream(launch.with_arg("--console")).map_err(RunError::LaunchFailed)?;
+        // `--console` stays attached until the app exits, so returning on its
+        // own this soon means the app didn't last.
+        if !no_crash_check.yes()
+            && !interrupt::interrupted()
+            && launched_at.elapsed() < crash::WINDOW
+        {
+            return Err(RunError::Crashed(Crashed::new(name).with_hint(Some(
+                format!("Its output is above. {}", CRASH_REPORT_HINT),
+            ))));
+        }
+    } else {
+        progress::phase(Phase::Launch, None, || launch.run_and_wait())
+            .map_err(RunError::LaunchFailed)?;
+        if !no_crash_check.yes() {
+            let died = crash::died(|| match app_processes(config, env, id) {
+                Ok(processes) => Some(!processes.is_empty()),
+                Err(err) => {
+                    log::warn!("failed to list running processes on device: {}", err);
+                    None
+                }
+            });
+            if died {
+                return Err(RunError::Crashed(
+                    Crashed::new(name).with_hint(Some(CRASH_REPORT_HINT.to_owned())),
+                ));
+            }
+        }
+    }
+    if syslog {
+        log::info!("streaming device logs using `idevicesyslog`");
+        console::stream(
+            bossy::Command::pure("idevicesyslog")
+                .with_env_vars(env.explicit_env())
+                .with_args(&["--udid", id])
+                .with_args(&["--process", config.product_name()]),
+        )
+        .map_err(RunError::LogStreamFailed)?;
+    }
+    if !skip_log.yes() && kill_on_exit.yes() {
+        ui::status("Terminating app...");
+        terminate(config, env, id).map_err(RunError::TerminateFailed)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::parse_json, *};
+
+    #[test]
+    fn test_own_processes() {
+        let list = parse_json::<ProcessList>(include_str!("transcripts/processes.json")).unwrap();
+        assert_eq!(
+            own_processes(list.running_processes, "Fish")
+                .iter()
+                .map(|process| process.process_identifier)
+                .collect::<Vec<_>>(),
+            vec![4123]
+        );
+    }
+}
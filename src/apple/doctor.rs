@@ -0,0 +1,223 @@
+use super::{
+    config::DEFAULT_MIN_XCODE_VERSION,
+    device, devicectl, identities,
+    simctl::{self, Simulator},
+    target::Target,
+    xcode,
+};
+use crate::{
+    config::Config,
+    doctor::{Fix, Item, Section, SectionName, Stage},
+    env::Env,
+    target::TargetTrait as _,
+    util,
+};
+
+fn check_xcode(section: &mut Section, env: &Env) {
+    // Builds hold Xcode to the project's minimum, so that's what matters when
+    // run in one.
+    let min_version = Config::check(".")
+        .ok()
+        .map(|config| config.apple().min_xcode_version())
+        .unwrap_or(DEFAULT_MIN_XCODE_VERSION);
+    match xcode::check(env, min_version) {
+        Ok(xcode) => section.push(Item::pass(format!(
+            "Xcode {}.{} is selected at {:?}",
+            xcode.version().0,
+            xcode.version().1,
+            xcode.developer_dir()
+        ))),
+        Err(xcode::Error::CommandLineToolsOnly { developer_dir }) => section.push(
+            Item::fail(format!(
+                "Only the Command Line Tools are selected (at {:?}), but full Xcode is required",
+                developer_dir
+            ))
+            .with_hint("Install Xcode from the App Store, then `sudo xcode-select --switch /Applications/Xcode.app`")
+            .with_docs("https://developer.apple.com/xcode/")
+            .with_fix(Fix::manual(Stage::Xcode)),
+        ),
+        Err(xcode::Error::TooLow {
+            you_have,
+            you_need,
+            developer_dir,
+        }) => section.push(
+            Item::fail(format!(
+                "Xcode {}.{} at {:?} is older than the minimum supported version, {}.{}",
+                you_have.0, you_have.1, developer_dir, you_need.0, you_need.1
+            ))
+            .with_hint("Update Xcode from the App Store, or select a newer one with `sudo xcode-select --switch <path/to/Xcode.app>`")
+            .with_fix(Fix::manual(Stage::Xcode)),
+        ),
+        Err(xcode::Error::SelectFailed(err)) => section.push(
+            Item::fail(format!("Failed to find Xcode: {}", err))
+                .with_hint("Install Xcode from the App Store, then `sudo xcode-select --switch /Applications/Xcode.app`")
+                .with_fix(Fix::manual(Stage::Xcode)),
+        ),
+        Err(xcode::Error::VersionFailed(err)) => {
+            section.push(Item::fail(format!("Failed to check Xcode version: {}", err)))
+        }
+        Err(xcode::Error::VersionInvalid { output }) => section.push(Item::warn(format!(
+            "Couldn't find a version in `xcodebuild -version` output: {:?}",
+            output
+        ))),
+    }
+}
+
+fn check_simctl(section: &mut Section, env: &Env) -> Vec<Simulator> {
+    match simctl::device_list(env) {
+        Ok(simulators) => {
+            section.push(Item::pass("`xcrun simctl` works"));
+            simulators
+        }
+        Err(simctl::DeviceListError::DetectionFailed(err)) => {
+            section.push(
+                Item::fail(format!("`xcrun simctl` doesn't work: {}", err))
+                    .with_hint(
+                        "Open Xcode once to finish installing its components, or run `sudo xcodebuild -runFirstLaunch`",
+                    )
+                    .with_fix(Fix::run(Stage::Xcode, &["sudo", "xcodebuild", "-runFirstLaunch"])),
+            );
+            Vec::new()
+        }
+        Err(simctl::DeviceListError::ParseFailed(err)) => {
+            section.push(Item::warn(format!(
+                "Failed to parse simulator list from `simctl`: {}",
+                err
+            )));
+            Vec::new()
+        }
+    }
+}
+
+fn check_rust_targets(section: &mut Section) {
+    match util::installed_targets() {
+        Ok(installed) => {
+            // `Target::all` only includes the `-sim` target on arm64 hosts,
+            // which are the only ones that need it.
+            let missing = Target::all()
+                .values()
+                .map(|target| target.triple())
+                .filter(|triple| !installed.iter().any(|installed| installed == *triple))
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                section.push(Item::pass("All iOS Rust targets are installed"));
+            } else {
+                section.push(
+                    Item::fail(format!(
+                        "Missing Rust targets {}",
+                        util::list_display(&missing[..])
+                    ))
+                    .with_hint(format!("`rustup target add {}`", missing.join(" ")))
+                    .with_fix(Fix::run(
+                        Stage::Rust,
+                        ["rustup", "target", "add"].iter().chain(&missing),
+                    )),
+                );
+            }
+        }
+        Err(err) => section.push(
+            Item::fail(format!("Failed to list installed Rust targets: {}", err))
+                .with_hint("Install `rustup` from https://rustup.rs"),
+        ),
+    }
+}
+
+fn check_identities(section: &mut Section) {
+    match identities::find_identities() {
+        Ok(identities) => {
+            let valid = identities
+                .iter()
+                .filter(|identity| identity.valid())
+                .count();
+            if valid > 0 {
+                section.push(Item::pass(format!(
+                    "{} valid codesigning identit{} found",
+                    valid,
+                    if valid == 1 { "y" } else { "ies" }
+                )));
+            } else if identities.is_empty() {
+                section.push(
+                    Item::warn(
+                        "No codesigning identities were found, so you can only run on simulators",
+                    )
+                    .with_hint(identities::ACCOUNTS_HINT)
+                    .with_docs("https://developer.apple.com/support/certificates/"),
+                );
+            } else {
+                section.push(
+                    Item::warn("All of your codesigning identities are expired or revoked, so you can only run on simulators")
+                        .with_hint(identities::ACCOUNTS_HINT),
+                );
+            }
+        }
+        Err(err) => section.push(Item::fail(err)),
+    }
+}
+
+// Returns whether there's any way to deploy to devices.
+fn check_deploy_tools(section: &mut Section, env: &Env) -> bool {
+    let devicectl = devicectl::available(env);
+    let ios_deploy = util::command_present("ios-deploy").unwrap_or_default();
+    match (devicectl, ios_deploy) {
+        (true, true) => section.push(Item::pass("`devicectl` and `ios-deploy` are available")),
+        (true, false) => section.push(Item::pass("`devicectl` is available")),
+        (false, true) => section.push(Item::pass("`ios-deploy` is available")),
+        (false, false) => section.push(
+            Item::warn(
+                "Neither `devicectl` nor `ios-deploy` is available, so you can't run on devices",
+            )
+            .with_hint("Update to Xcode 15 or later, or `brew install ios-deploy`"),
+        ),
+    }
+    devicectl || ios_deploy
+}
+
+fn check_devices(section: &mut Section, env: &Env, can_deploy: bool, simulators: &[Simulator]) {
+    let devices = if can_deploy {
+        device::device_list(env)
+            .map(|list| list.iter().map(ToString::to_string).collect::<Vec<_>>())
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    if !devices.is_empty() {
+        section.push(Item::pass(format!(
+            "Connected devices: {}",
+            util::list_display(&devices[..])
+        )));
+    } else if !simulators.is_empty() {
+        section.push(Item::pass(format!(
+            "No devices connected, but {} simulator{} available",
+            simulators.len(),
+            if simulators.len() == 1 {
+                " is"
+            } else {
+                "s are"
+            }
+        )));
+    } else {
+        section.push(
+            Item::warn("No connected devices or available simulators were found")
+                .with_hint("Connect a device, unlock it, and tap \"Trust\" to pair it with this Mac, or install a simulator runtime in Xcode under Settings > Platforms")
+                .with_fix(Fix::manual(Stage::Devices)),
+        );
+    }
+}
+
+pub fn check() -> Section {
+    let mut section = Section::new(SectionName::Apple, "Apple");
+    let env = match Env::new() {
+        Ok(env) => env,
+        Err(err) => {
+            section.push(Item::fail(format!("Failed to initialize env: {}", err)));
+            return section;
+        }
+    };
+    check_xcode(&mut section, &env);
+    let simulators = check_simctl(&mut section, &env);
+    check_rust_targets(&mut section);
+    check_identities(&mut section);
+    let can_deploy = check_deploy_tools(&mut section, &env);
+    check_devices(&mut section, &env, can_deploy, &simulators);
+    section
+}
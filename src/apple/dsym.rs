@@ -0,0 +1,216 @@
+use super::config::Config;
+use crate::{
+    ui,
+    util::cli::{Report, Reportable},
+};
+use once_cell_regex::regex;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    ReadDirFailed { dir: PathBuf, cause: io::Error },
+    DirCreationFailed { dir: PathBuf, cause: io::Error },
+    RemoveFailed { path: PathBuf, cause: io::Error },
+    CopyFailed { src: PathBuf, cause: bossy::Error },
+    HookFailed { dsym: PathBuf, cause: bossy::Error },
+    UuidLookupFailed { dsym: PathBuf, cause: bossy::Error },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ReadDirFailed { dir, cause } => Report::error(
+                "Failed to collect dSYMs",
+                format!("Failed to list contents of {:?}: {}", dir, cause),
+            ),
+            Self::DirCreationFailed { dir, cause } => Report::error(
+                "Failed to collect dSYMs",
+                format!("Failed to create directory {:?}: {}", dir, cause),
+            ),
+            Self::RemoveFailed { path, cause } => Report::error(
+                "Failed to collect dSYMs",
+                format!("Failed to remove old dSYM {:?}: {}", path, cause),
+            ),
+            Self::CopyFailed { src, cause } => Report::error(
+                "Failed to collect dSYMs",
+                format!("Failed to copy {:?}: {}", src, cause),
+            ),
+            Self::HookFailed { dsym, cause } => Report::error(
+                format!(
+                    "`{}.dsym-upload-command` failed for {:?}",
+                    super::NAME,
+                    dsym
+                ),
+                cause,
+            ),
+            Self::UuidLookupFailed { dsym, cause } => {
+                Report::error(format!("Failed to get UUIDs of {:?}", dsym), cause)
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadDirFailed { .. } => "apple.dsym.read-dir-failed",
+            Self::DirCreationFailed { .. } => "apple.dsym.dir-creation-failed",
+            Self::RemoveFailed { .. } => "apple.dsym.remove-failed",
+            Self::CopyFailed { .. } => "apple.dsym.copy-failed",
+            Self::HookFailed { .. } => "apple.dsym.hook-failed",
+            Self::UuidLookupFailed { .. } => "apple.dsym.uuid-lookup-failed",
+        }
+    }
+}
+
+fn list_dsyms(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let read_dir_failed = |cause| Error::ReadDirFailed {
+        dir: dir.to_owned(),
+        cause,
+    };
+    let mut dsyms = fs::read_dir(dir)
+        .map_err(read_dir_failed)?
+        .map(|entry| entry.map(|entry| entry.path()).map_err(read_dir_failed))
+        .filter(|path| {
+            path.as_ref()
+                .map(|path| {
+                    path.extension()
+                        .map(|ext| ext == "dSYM")
+                        .unwrap_or_default()
+                })
+                .unwrap_or(true)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    dsyms.sort();
+    Ok(dsyms)
+}
+
+/// Copies the dSYMs out of an archive into `Config::dsym_dir`, replacing any
+/// from a previous archive, and returns their new paths.
+pub fn collect(config: &Config, archive_path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let dest_dir = config.dsym_dir();
+    fs::create_dir_all(&dest_dir).map_err(|cause| Error::DirCreationFailed {
+        dir: dest_dir.clone(),
+        cause,
+    })?;
+    list_dsyms(&archive_path.join("dSYMs"))?
+        .into_iter()
+        .map(|src| {
+            let dest = dest_dir.join(
+                src.file_name()
+                    .expect("developer error: dSYM had no file name"),
+            );
+            if dest.exists() {
+                fs::remove_dir_all(&dest).map_err(|cause| Error::RemoveFailed {
+                    path: dest.clone(),
+                    cause,
+                })?;
+            }
+            // `ditto` preserves everything a bundle could contain, which
+            // `fs::copy` one file at a time wouldn't.
+            bossy::Command::impure("ditto")
+                .with_arg(&src)
+                .with_arg(&dest)
+                .run_and_wait()
+                .map_err(|cause| Error::CopyFailed { src, cause })?;
+            Ok(dest)
+        })
+        .collect()
+}
+
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', r"'\''"))
+}
+
+/// Runs `apple.dsym-upload-command` once per dSYM, with `{dsym}` replaced by
+/// its path.
+pub fn run_hook(config: &Config, dsyms: &[PathBuf]) -> Result<(), Error> {
+    if let Some(template) = config.dsym_upload_command() {
+        for dsym in dsyms {
+            let command = template.replace("{dsym}", &quote(dsym));
+            ui::status(format!("Running `{}`...", command));
+            bossy::Command::impure("sh")
+                .with_args(&["-c", &command])
+                .with_env_var("DSYM_PATH", dsym)
+                .run_and_wait()
+                .map_err(|cause| Error::HookFailed {
+                    dsym: dsym.clone(),
+                    cause,
+                })?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_uuids(output: &str) -> Vec<(String, String, PathBuf)> {
+    regex!(r"(?m)^UUID: ([0-9A-F-]+) \((\w+)\) (.+)$")
+        .captures_iter(output)
+        .map(|caps| {
+            (
+                caps[1].to_owned(),
+                caps[2].to_owned(),
+                PathBuf::from(&caps[3]),
+            )
+        })
+        .collect()
+}
+
+/// A single architecture's debug info inside a dSYM.
+#[derive(Debug)]
+pub struct Slice {
+    pub uuid: String,
+    pub arch: String,
+    pub dwarf_path: PathBuf,
+}
+
+/// Every slice of every dSYM in `dir`, so crash logs can be matched by UUID.
+pub fn slices(dir: &Path) -> Result<Vec<Slice>, Error> {
+    let mut slices = Vec::new();
+    for dsym in list_dsyms(dir)? {
+        let output = bossy::Command::impure("dwarfdump")
+            .with_arg("--uuid")
+            .with_arg(&dsym)
+            .run_and_wait_for_str(|output| output.to_owned())
+            .map_err(|cause| Error::UuidLookupFailed {
+                dsym: dsym.clone(),
+                cause,
+            })?;
+        slices.extend(
+            parse_uuids(&output)
+                .into_iter()
+                .map(|(uuid, arch, dwarf_path)| Slice {
+                    uuid,
+                    arch,
+                    dwarf_path,
+                }),
+        );
+    }
+    Ok(slices)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_uuids() {
+        let output = "UUID: 1B2A3C4D-5E6F-7081-92A3-B4C5D6E7F809 (arm64) /tmp/foo.app.dSYM/Contents/Resources/DWARF/foo\n";
+        assert_eq!(
+            parse_uuids(output),
+            vec![(
+                "1B2A3C4D-5E6F-7081-92A3-B4C5D6E7F809".to_owned(),
+                "arm64".to_owned(),
+                PathBuf::from("/tmp/foo.app.dSYM/Contents/Resources/DWARF/foo"),
+            )]
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote(Path::new("/tmp/it's.dSYM")), r"'/tmp/it'\''s.dSYM'");
+    }
+}
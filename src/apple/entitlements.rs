@@ -0,0 +1,321 @@
+use super::info_plist;
+use crate::util::cli::{Report, TextWrapper};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+use std::fmt::{self, Display};
+
+static ASSOCIATED_DOMAIN_SERVICES: &[&str] = &[
+    "applinks",
+    "webcredentials",
+    "activitycontinuation",
+    "appclips",
+];
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApsEnvironment {
+    Development,
+    Production,
+}
+
+impl ApsEnvironment {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Development => "development",
+            Self::Production => "production",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Raw {
+    pub push_notifications: Option<ApsEnvironment>,
+    pub app_groups: Option<Vec<String>>,
+    pub keychain_access_groups: Option<Vec<String>>,
+    pub associated_domains: Option<Vec<String>>,
+    /// Anything else, keyed by the entitlement's real name.
+    pub raw: Option<toml::value::Table>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    RawInvalid(info_plist::Error),
+    AssociatedDomainInvalid { domain: String },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RawInvalid(err) => write!(f, "{}", err),
+            Self::AssociatedDomainInvalid { domain } => write!(
+                f,
+                "Associated domain {:?} should look like \"<service>:<domain>\", where the service is one of {}",
+                domain,
+                ASSOCIATED_DOMAIN_SERVICES.join(", ")
+            ),
+        }
+    }
+}
+
+// Entitlements that only work if the provisioning profile grants them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Capability {
+    Push,
+    AppGroups,
+    KeychainSharing,
+    AssociatedDomains,
+}
+
+impl Capability {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Push => "Push Notifications",
+            Self::AppGroups => "App Groups",
+            Self::KeychainSharing => "Keychain Sharing",
+            Self::AssociatedDomains => "Associated Domains",
+        }
+    }
+
+    // These are the keys Xcode uses in `SystemCapabilities`, which are what
+    // tell automatic signing to enable the capability on the App ID.
+    fn system_capability(&self) -> &'static str {
+        match self {
+            Self::Push => "com.apple.Push",
+            Self::AppGroups => "com.apple.ApplicationGroups.iOS",
+            Self::KeychainSharing => "com.apple.Keychain",
+            Self::AssociatedDomains => "com.apple.SafariKeychain",
+        }
+    }
+
+    fn requires_profile(&self) -> bool {
+        !matches!(self, Self::KeychainSharing)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Entitlements {
+    properties: Map<String, JsonValue>,
+    capabilities: Vec<Capability>,
+}
+
+impl Entitlements {
+    pub fn from_raw(raw: Raw) -> Result<Self, Error> {
+        let mut properties = raw
+            .raw
+            .as_ref()
+            .map(|table| {
+                info_plist::convert_table(&format!("{}.entitlements.raw", super::NAME), table)
+            })
+            .transpose()
+            .map_err(Error::RawInvalid)?
+            .unwrap_or_default();
+        let mut capabilities = Vec::new();
+        let mut insert = |key: &str, value: JsonValue, capability| {
+            if properties.insert(key.to_owned(), value).is_some() {
+                log::warn!(
+                    "`{}.entitlements.raw.{}` is overridden by its typed equivalent",
+                    super::NAME,
+                    key
+                );
+            }
+            capabilities.push(capability);
+        };
+        if let Some(aps_environment) = raw.push_notifications {
+            insert(
+                "aps-environment",
+                json!(aps_environment.as_str()),
+                Capability::Push,
+            );
+        }
+        if let Some(app_groups) = raw.app_groups {
+            insert(
+                "com.apple.security.application-groups",
+                json!(app_groups),
+                Capability::AppGroups,
+            );
+        }
+        if let Some(keychain_access_groups) = raw.keychain_access_groups {
+            // Without a prefix, the group is only visible to this app.
+            let groups = keychain_access_groups
+                .into_iter()
+                .map(|group| {
+                    if group.starts_with("$(") {
+                        group
+                    } else {
+                        format!("$(AppIdentifierPrefix){}", group)
+                    }
+                })
+                .collect::<Vec<_>>();
+            insert(
+                "keychain-access-groups",
+                json!(groups),
+                Capability::KeychainSharing,
+            );
+        }
+        if let Some(associated_domains) = raw.associated_domains {
+            for domain in &associated_domains {
+                let mut parts = domain.splitn(2, ':');
+                let valid = match (parts.next(), parts.next()) {
+                    (Some(service), Some(host)) => {
+                        ASSOCIATED_DOMAIN_SERVICES.contains(&service) && !host.is_empty()
+                    }
+                    _ => false,
+                };
+                if !valid {
+                    return Err(Error::AssociatedDomainInvalid {
+                        domain: domain.clone(),
+                    });
+                }
+            }
+            insert(
+                "com.apple.developer.associated-domains",
+                json!(associated_domains),
+                Capability::AssociatedDomains,
+            );
+        }
+        Ok(Self {
+            properties,
+            capabilities,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty()
+    }
+
+    /// The entitlements plist contents, rendered as JSON for `xcodegen`.
+    pub fn properties(&self) -> String {
+        JsonValue::Object(self.properties.clone()).to_string()
+    }
+
+    /// The `SystemCapabilities` target attribute, rendered as JSON for
+    /// `xcodegen`.
+    pub fn system_capabilities(&self) -> String {
+        JsonValue::Object(
+            self.capabilities
+                .iter()
+                .map(|capability| {
+                    (
+                        capability.system_capability().to_owned(),
+                        json!({ "enabled": 1 }),
+                    )
+                })
+                .collect(),
+        )
+        .to_string()
+    }
+
+    /// Signing fails with a pretty opaque error when the profile is missing a
+    /// capability, so we give a heads up ahead of time.
+    pub fn warn_about_provisioning(&self, wrapper: &TextWrapper) {
+        let names = self
+            .capabilities
+            .iter()
+            .filter(|capability| capability.requires_profile())
+            .map(Capability::name)
+            .collect::<Vec<_>>();
+        if !names.is_empty() {
+            Report::action_request(
+                format!(
+                    "Your entitlements require a provisioning profile with these capabilities: {}",
+                    names.join(", ")
+                ),
+                "With automatic signing, Xcode enables them on your App ID when it updates your profile, as long as your team is allowed to use them (e.g. personal teams can't use push notifications). With manual signing, enable them on your App ID in the Apple Developer portal and then regenerate your profile.",
+            )
+            .print(wrapper);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn entitlements(raw: &str) -> Result<Entitlements, Error> {
+        Entitlements::from_raw(toml::from_str(raw).unwrap())
+    }
+
+    #[rstest(
+        raw,
+        expected,
+        case("", json!({})),
+        case(
+            r#"push-notifications = "production""#,
+            json!({ "aps-environment": "production" })
+        ),
+        case(
+            r#"keychain-access-groups = ["shared", "$(TeamIdentifierPrefix)team"]"#,
+            json!({
+                "keychain-access-groups": [
+                    "$(AppIdentifierPrefix)shared",
+                    "$(TeamIdentifierPrefix)team"
+                ]
+            })
+        ),
+        case(
+            r#"
+            app-groups = ["group.com.example.fish"]
+            [raw]
+            "com.apple.developer.icloud-services" = ["CloudKit"]
+            "#,
+            json!({
+                "com.apple.developer.icloud-services": ["CloudKit"],
+                "com.apple.security.application-groups": ["group.com.example.fish"]
+            })
+        ),
+        // The typed key wins over the raw one.
+        case(
+            r#"
+            push-notifications = "production"
+            [raw]
+            aps-environment = "development"
+            "#,
+            json!({ "aps-environment": "production" })
+        )
+    )]
+    fn test_properties(raw: &str, expected: JsonValue) {
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(&entitlements(raw).unwrap().properties()).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest(
+        raw,
+        expected,
+        case("", json!({})),
+        case(
+            r#"
+            push-notifications = "development"
+            keychain-access-groups = ["shared"]
+            "#,
+            json!({
+                "com.apple.Push": { "enabled": 1 },
+                "com.apple.Keychain": { "enabled": 1 }
+            })
+        )
+    )]
+    fn test_system_capabilities(raw: &str, expected: JsonValue) {
+        let capabilities = entitlements(raw).unwrap().system_capabilities();
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(&capabilities).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest(
+        domain,
+        valid,
+        case("applinks:example.com", true),
+        case("webcredentials:example.com", true),
+        case("example.com", false),
+        case("applinks:", false),
+        case("deeplinks:example.com", false)
+    )]
+    fn test_associated_domains(domain: &str, valid: bool) {
+        let result = entitlements(&format!("associated-domains = [{:?}]", domain));
+        assert_eq!(result.is_ok(), valid);
+    }
+}
@@ -0,0 +1,125 @@
+use super::config::Config;
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::PathBuf,
+    str::FromStr,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Method {
+    AppStore,
+    AdHoc,
+    Development,
+}
+
+impl Default for Method {
+    fn default() -> Self {
+        Self::Development
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct MethodInvalid(String);
+
+impl Display for MethodInvalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} isn't a valid export method; expected one of {}",
+            self.0,
+            Method::name_list().join(", ")
+        )
+    }
+}
+
+impl FromStr for Method {
+    type Err = MethodInvalid;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "app-store" => Ok(Self::AppStore),
+            "ad-hoc" => Ok(Self::AdHoc),
+            "development" => Ok(Self::Development),
+            _ => Err(MethodInvalid(s.to_owned())),
+        }
+    }
+}
+
+impl Method {
+    pub fn name_list() -> &'static [&'static str] {
+        &["app-store", "ad-hoc", "development"]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AppStore => "app-store",
+            Self::AdHoc => "ad-hoc",
+            Self::Development => "development",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OptionsWriteError {
+    path: PathBuf,
+    cause: io::Error,
+}
+
+impl Display for OptionsWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to write export options to {:?}: {}",
+            self.path, self.cause
+        )
+    }
+}
+
+/// Export options are generated from config on every export, rather than
+/// being a template, so that changing the team or signing style doesn't
+/// require regenerating the project.
+pub fn write_options(config: &Config, method: Method) -> Result<PathBuf, OptionsWriteError> {
+    let path = config.export_plist_path();
+    let contents = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>method</key>
+    <string>{}</string>
+    <key>teamID</key>
+    <string>{}</string>
+    <key>signingStyle</key>
+    <string>{}</string>{}
+</dict>
+</plist>
+"#,
+        method.as_str(),
+        config.development_team(),
+        config.signing_style().as_str(),
+        config
+            .identity()
+            .map(|identity| format!(
+                "\n    <key>signingCertificate</key>\n    <string>{}</string>",
+                bicycle::handlebars::html_escape(identity)
+            ))
+            .unwrap_or_default(),
+    );
+    log::info!("writing export options to {:?}", path);
+    path.parent()
+        .map(fs::create_dir_all)
+        .transpose()
+        .and_then(|_| fs::write(&path, contents))
+        .map_err(|cause| OptionsWriteError {
+            path: path.clone(),
+            cause,
+        })?;
+    Ok(path)
+}
@@ -0,0 +1,360 @@
+use super::config::Config;
+use crate::{
+    ui,
+    util::cli::{Report, Reportable},
+};
+use image::{imageops::FilterType, GenericImageView as _};
+use serde_json::json;
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const SOURCE_SIZE: u32 = 1024;
+
+/// An sRGB color, written like `#1e90ff`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Color {
+    red: u8,
+    green: u8,
+    blue: u8,
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Self {
+            red: 0xff,
+            green: 0xff,
+            blue: 0xff,
+        }
+    }
+}
+
+impl Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+#[derive(Debug)]
+pub struct ColorInvalid(String);
+
+impl Display for ColorInvalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} isn't a valid color; it should look like \"#1e90ff\"",
+            self.0
+        )
+    }
+}
+
+impl FromStr for Color {
+    type Err = ColorInvalid;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ColorInvalid(s.to_owned());
+        let hex = s.strip_prefix('#').ok_or_else(invalid)?;
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let component = |range: std::ops::Range<usize>| {
+            u8::from_str_radix(&hex[range], 16).map_err(|_| invalid())
+        };
+        Ok(Self {
+            red: component(0..2)?,
+            green: component(2..4)?,
+            blue: component(4..6)?,
+        })
+    }
+}
+
+impl Color {
+    fn components(self) -> (f32, f32, f32) {
+        let component = |c: u8| f32::from(c) / 255.0;
+        (
+            component(self.red),
+            component(self.green),
+            component(self.blue),
+        )
+    }
+}
+
+struct Icon {
+    idiom: &'static str,
+    // In points; this is a string since iPad Pro uses 83.5.
+    size: &'static str,
+    scale: u32,
+    pixels: u32,
+}
+
+macro_rules! icon {
+    ($idiom:literal, $size:literal, $scale:literal, $pixels:literal) => {
+        Icon {
+            idiom: $idiom,
+            size: $size,
+            scale: $scale,
+            pixels: $pixels,
+        }
+    };
+}
+
+static ICONS: &[Icon] = &[
+    icon!("iphone", "20", 2, 40),
+    icon!("iphone", "20", 3, 60),
+    icon!("iphone", "29", 2, 58),
+    icon!("iphone", "29", 3, 87),
+    icon!("iphone", "40", 2, 80),
+    icon!("iphone", "40", 3, 120),
+    icon!("iphone", "60", 2, 120),
+    icon!("iphone", "60", 3, 180),
+    icon!("ipad", "20", 1, 20),
+    icon!("ipad", "20", 2, 40),
+    icon!("ipad", "29", 1, 29),
+    icon!("ipad", "29", 2, 58),
+    icon!("ipad", "40", 1, 40),
+    icon!("ipad", "40", 2, 80),
+    icon!("ipad", "76", 1, 76),
+    icon!("ipad", "76", 2, 152),
+    icon!("ipad", "83.5", 2, 167),
+    icon!("ios-marketing", "1024", 1, 1024),
+];
+
+impl Icon {
+    fn file_name(&self) -> String {
+        format!("icon-{}x{}.png", self.pixels, self.pixels)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IconNotConfigured,
+    SourceOpenFailed {
+        path: PathBuf,
+        cause: image::ImageError,
+    },
+    SourceSizeInvalid {
+        path: PathBuf,
+        width: u32,
+        height: u32,
+    },
+    SourceHasAlpha {
+        path: PathBuf,
+    },
+    ResizeFailed {
+        path: PathBuf,
+        cause: image::ImageError,
+    },
+    WriteFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        let msg = "Failed to generate iOS icons";
+        match self {
+            Self::IconNotConfigured => Report::action_request(
+                "No app icon is configured",
+                format!("Set `{}.icon` in your `mobile.toml` to the path of a {}x{} PNG.", super::NAME, SOURCE_SIZE, SOURCE_SIZE),
+            ),
+            Self::SourceOpenFailed { path, cause } => Report::error(
+                msg,
+                format!("Failed to open icon source {:?}: {}", path, cause),
+            ),
+            Self::SourceSizeInvalid {
+                path,
+                width,
+                height,
+            } => Report::error(
+                msg,
+                format!(
+                    "Icon source {:?} is {}x{}, but it needs to be exactly {}x{}",
+                    path, width, height, SOURCE_SIZE, SOURCE_SIZE
+                ),
+            ),
+            Self::SourceHasAlpha { path } => Report::action_request(
+                "App icons can't have an alpha channel",
+                format!("The App Store rejects icons with transparency, so please flatten {:?} onto an opaque background and export it without alpha.", path),
+            ),
+            Self::ResizeFailed { path, cause } => {
+                Report::error(msg, format!("Failed to write icon {:?}: {}", path, cause))
+            }
+            Self::WriteFailed { path, cause } => {
+                Report::error(msg, format!("Failed to write {:?}: {}", path, cause))
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::IconNotConfigured => "apple.icons.icon-not-configured",
+            Self::SourceOpenFailed { .. } => "apple.icons.source-open-failed",
+            Self::SourceSizeInvalid { .. } => "apple.icons.source-size-invalid",
+            Self::SourceHasAlpha { .. } => "apple.icons.source-has-alpha",
+            Self::ResizeFailed { .. } => "apple.icons.resize-failed",
+            Self::WriteFailed { .. } => "apple.icons.write-failed",
+        }
+    }
+}
+
+fn write(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+    path.parent()
+        .map(fs::create_dir_all)
+        .transpose()
+        .and_then(|_| fs::write(path, contents))
+        .map_err(|cause| Error::WriteFailed {
+            path: path.to_owned(),
+            cause,
+        })
+}
+
+fn contents_json(value: serde_json::Value) -> String {
+    serde_json::to_string_pretty(&value).expect("developer error: JSON failed to serialize")
+}
+
+fn gen_app_icon_set(source_path: &Path, dest: &Path) -> Result<(), Error> {
+    let source = image::open(source_path).map_err(|cause| Error::SourceOpenFailed {
+        path: source_path.to_owned(),
+        cause,
+    })?;
+    let (width, height) = source.dimensions();
+    if (width, height) != (SOURCE_SIZE, SOURCE_SIZE) {
+        return Err(Error::SourceSizeInvalid {
+            path: source_path.to_owned(),
+            width,
+            height,
+        });
+    }
+    if source.color().has_alpha() {
+        return Err(Error::SourceHasAlpha {
+            path: source_path.to_owned(),
+        });
+    }
+    let source = source.to_rgb8();
+    fs::create_dir_all(dest).map_err(|cause| Error::WriteFailed {
+        path: dest.to_owned(),
+        cause,
+    })?;
+    let mut images = Vec::new();
+    let mut written = Vec::new();
+    for icon in ICONS {
+        let file_name = icon.file_name();
+        // iPhone and iPad share a few sizes, so there's no need to write them
+        // twice.
+        if !written.contains(&icon.pixels) {
+            let path = dest.join(&file_name);
+            log::info!("writing {}x{} icon to {:?}", icon.pixels, icon.pixels, path);
+            image::imageops::resize(&source, icon.pixels, icon.pixels, FilterType::Lanczos3)
+                .save(&path)
+                .map_err(|cause| Error::ResizeFailed { path, cause })?;
+            written.push(icon.pixels);
+        }
+        images.push(json!({
+            "size": format!("{0}x{0}", icon.size),
+            "idiom": icon.idiom,
+            "filename": file_name,
+            "scale": format!("{}x", icon.scale),
+        }));
+    }
+    write(
+        &dest.join("Contents.json"),
+        contents_json(json!({
+            "images": images,
+            "info": { "version": 1, "author": "xcode" },
+        })),
+    )
+}
+
+fn launch_screen(color: Color) -> String {
+    let (red, green, blue) = color.components();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<document type="com.apple.InterfaceBuilder3.CocoaTouch.Storyboard.XIB" version="3.0" toolsVersion="17701" targetRuntime="iOS.CocoaTouch" propertyAccessControl="none" useAutolayout="YES" launchScreen="YES" useTraitCollections="YES" useSafeAreas="YES" colorMatched="YES" initialViewController="01J-lp-oVM">
+    <dependencies>
+        <plugIn identifier="com.apple.InterfaceBuilder.IBCocoaTouchPlugin" version="17703"/>
+        <capability name="Safe area layout guides" minToolsVersion="9.0"/>
+        <capability name="documents saved in the Xcode 8 format" minToolsVersion="8.0"/>
+    </dependencies>
+    <scenes>
+        <scene sceneID="EHf-IW-A2E">
+            <objects>
+                <viewController id="01J-lp-oVM" sceneMemberID="viewController">
+                    <view key="view" contentMode="scaleToFill" id="Ze5-6b-2t3">
+                        <rect key="frame" x="0.0" y="0.0" width="375" height="667"/>
+                        <autoresizingMask key="autoresizingMask" widthSizable="YES" heightSizable="YES"/>
+                        <viewLayoutGuide key="safeArea" id="6Tk-OE-BBY"/>
+                        <color key="backgroundColor" red="{:.3}" green="{:.3}" blue="{:.3}" alpha="1" colorSpace="custom" customColorSpace="sRGB"/>
+                    </view>
+                </viewController>
+                <placeholder placeholderIdentifier="IBFirstResponder" id="iYj-Kq-Ea1" userLabel="First Responder" sceneMemberID="firstResponder"/>
+            </objects>
+            <point key="canvasLocation" x="53" y="375"/>
+        </scene>
+    </scenes>
+</document>
+"#,
+        red, green, blue
+    )
+}
+
+/// Writes the asset catalog and launch screen into the Xcode project. The app
+/// icon set is only generated when `apple.icon` is set, unless `required` is
+/// true, in which case a missing icon is an error. Variants with their own icon
+/// each get their own icon set.
+pub fn gen(config: &Config, required: bool) -> Result<(), Error> {
+    let catalog = config.asset_catalog_dir();
+    write(
+        &catalog.join("Contents.json"),
+        contents_json(json!({ "info": { "version": 1, "author": "xcode" } })),
+    )?;
+    match config.icon() {
+        Some(icon) => {
+            ui::status("Generating app icons...");
+            gen_app_icon_set(&icon, &catalog.join("AppIcon.appiconset"))?;
+        }
+        None if required => return Err(Error::IconNotConfigured),
+        None => (),
+    }
+    for variant in config.app().variants() {
+        if let Some(icon) = variant.icon() {
+            ui::status(format!(
+                "Generating app icons for variant {:?}...",
+                variant.name()
+            ));
+            gen_app_icon_set(
+                &config.app().prefix_path(icon),
+                &catalog.join(format!("{}.appiconset", Config::variant_icon_set(variant))),
+            )?;
+        }
+    }
+    write(
+        &config.launch_screen_path(),
+        launch_screen(config.launch_screen_color()),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        raw,
+        expected,
+        case("#1e90ff", Some((0x1e, 0x90, 0xff))),
+        case("#FFFFFF", Some((0xff, 0xff, 0xff))),
+        case("1e90ff", None),
+        case("#1e90f", None),
+        case("#1e90fg", None)
+    )]
+    fn test_parse_color(raw: &str, expected: Option<(u8, u8, u8)>) {
+        assert_eq!(
+            raw.parse::<Color>().ok(),
+            expected.map(|(red, green, blue)| Color { red, green, blue })
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use super::teams;
+use crate::util::cli::{Report, Reportable};
+use once_cell_regex::regex;
+use openssl::{nid::Nid, x509::X509};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+pub static ACCOUNTS_HINT: &str = "You can create or download certificates in Xcode, under Preferences > Accounts > Manage Certificates. Run `cargo apple identities` to see what's available.";
+
+#[derive(Debug, Eq, PartialEq)]
+struct Listing {
+    sha1: String,
+    name: String,
+    problem: Option<String>,
+}
+
+// Without `-v`, expired and revoked identities are included, with the reason
+// they're invalid in parens. The list is printed twice (once in full, then
+// again with only the valid ones), so we stop after the first.
+fn parse_find_identity(output: &str) -> Vec<Listing> {
+    let full = output
+        .split("Valid identities only")
+        .next()
+        .unwrap_or_default();
+    regex!(r#"(?m)^\s*\d+\) ([0-9A-F]{40}) "(.+?)"(?: \((.+)\))?\s*$"#)
+        .captures_iter(full)
+        .map(|caps| Listing {
+            sha1: caps[1].to_owned(),
+            name: caps[2].to_owned(),
+            problem: caps.get(3).map(|problem| problem.as_str().to_owned()),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct Identity {
+    pub sha1: String,
+    pub name: String,
+    pub team_id: Option<String>,
+    pub expires: Option<String>,
+    problem: Option<String>,
+    duplicate: bool,
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.name, self.sha1)?;
+        if let Some(team_id) = &self.team_id {
+            write!(f, ", team {}", team_id)?;
+        }
+        if let Some(expires) = &self.expires {
+            write!(f, ", expires {}", expires)?;
+        }
+        if let Some(problem) = self.problem() {
+            write!(f, " ({})", problem)?;
+        }
+        if self.duplicate {
+            write!(f, " (duplicate name; select by SHA-1)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Identity {
+    fn problem(&self) -> Option<&'static str> {
+        self.problem.as_deref().map(|problem| {
+            if problem.contains("EXPIRED") {
+                "expired"
+            } else if problem.contains("REVOKED") {
+                "revoked"
+            } else {
+                "invalid"
+            }
+        })
+    }
+
+    pub fn valid(&self) -> bool {
+        self.problem.is_none()
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        self.sha1.eq_ignore_ascii_case(query) || self.name == query
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    FindIdentityFailed(bossy::Error),
+    FindCertificateFailed(bossy::Error),
+    CertParseFailed(openssl::error::ErrorStack),
+    CertFieldInvalid(teams::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FindIdentityFailed(err) => {
+                write!(f, "Failed to list codesigning identities: {}", err)
+            }
+            Self::FindCertificateFailed(err) => write!(f, "Failed to look up certificate: {}", err),
+            Self::CertParseFailed(err) => write!(f, "Failed to parse certificate: {}", err),
+            Self::CertFieldInvalid(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// `-Z` prints each cert's SHA-1 before its PEM, which is how we pair certs up
+// with identities.
+fn find_certs(name: &str) -> Result<HashMap<String, X509>, Error> {
+    let output = bossy::Command::impure("security")
+        .with_args(&["find-certificate", "-a", "-Z", "-p", "-c", name])
+        .run_and_wait_for_str(|output| output.to_owned())
+        .map_err(Error::FindCertificateFailed)?;
+    regex!(
+        r"SHA-1 hash: ([0-9A-F]{40})\s+(-----BEGIN CERTIFICATE-----[^-]+-----END CERTIFICATE-----)"
+    )
+    .captures_iter(&output)
+    .map(|caps| {
+        X509::from_pem(caps[2].as_bytes())
+            .map(|cert| (caps[1].to_owned(), cert))
+            .map_err(Error::CertParseFailed)
+    })
+    .collect()
+}
+
+pub fn find_identities() -> Result<Vec<Identity>, Error> {
+    let listings = bossy::Command::impure("security")
+        .with_args(&["find-identity", "-p", "codesigning"])
+        .run_and_wait_for_str(parse_find_identity)
+        .map_err(Error::FindIdentityFailed)?;
+    let mut certs = HashMap::new();
+    let mut identities = Vec::with_capacity(listings.len());
+    for listing in &listings {
+        if !certs.contains_key(&listing.sha1) {
+            certs.extend(find_certs(&listing.name)?);
+        }
+        let (team_id, expires) = certs
+            .get(&listing.sha1)
+            .map(|cert| {
+                teams::get_x509_field(cert.subject_name(), Nid::ORGANIZATIONALUNITNAME)
+                    .map(|team_id| (Some(team_id), Some(cert.not_after().to_string())))
+                    .map_err(Error::CertFieldInvalid)
+            })
+            .transpose()?
+            .unwrap_or_default();
+        identities.push(Identity {
+            sha1: listing.sha1.clone(),
+            name: listing.name.clone(),
+            team_id,
+            expires,
+            problem: listing.problem.clone(),
+            duplicate: listings
+                .iter()
+                .any(|other| other.name == listing.name && other.sha1 != listing.sha1),
+        });
+    }
+    Ok(identities)
+}
+
+#[derive(Debug)]
+pub enum CheckError {
+    LookupFailed(Error),
+    Missing { query: String },
+    Invalid { identity: Identity },
+}
+
+impl Reportable for CheckError {
+    fn report(&self) -> Report {
+        match self {
+            Self::LookupFailed(err) => Report::error("Failed to check codesigning identity", err),
+            Self::Missing { query } => Report::action_request(
+                format!("Codesigning identity {:?} isn't installed", query),
+                ACCOUNTS_HINT,
+            ),
+            Self::Invalid { identity } => Report::action_request(
+                format!(
+                    "Codesigning identity {:?} is {}",
+                    identity.name,
+                    identity.problem().unwrap_or("invalid")
+                ),
+                ACCOUNTS_HINT,
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::LookupFailed(..) => "apple.identities.check.lookup-failed",
+            Self::Missing { .. } => "apple.identities.check.missing",
+            Self::Invalid { .. } => "apple.identities.check.invalid",
+        }
+    }
+}
+
+/// Makes sure the selected identity exists and hasn't expired, since
+/// `xcodebuild` only finds out once it's done building.
+pub fn check(query: &str) -> Result<(), CheckError> {
+    let identities = find_identities().map_err(CheckError::LookupFailed)?;
+    // An expired cert lingering next to its renewed replacement is fine.
+    if identities
+        .iter()
+        .any(|identity| identity.matches(query) && identity.valid())
+    {
+        return Ok(());
+    }
+    match identities
+        .into_iter()
+        .find(|identity| identity.matches(query))
+    {
+        Some(identity) => Err(CheckError::Invalid { identity }),
+        None => Err(CheckError::Missing {
+            query: query.to_owned(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_find_identity() {
+        let output = r#"
+Policy: Code Signing
+  Matching identities
+  1) 1111111111111111111111111111111111111111 "Apple Development: Jane Doe (ABCDE12345)"
+  2) 2222222222222222222222222222222222222222 "Apple Development: Jane Doe (ABCDE12345)" (CSSMERR_TP_CERT_EXPIRED)
+     2 identities found
+
+  Valid identities only
+  1) 1111111111111111111111111111111111111111 "Apple Development: Jane Doe (ABCDE12345)"
+     1 valid identities found
+"#;
+        assert_eq!(
+            parse_find_identity(output),
+            vec![
+                Listing {
+                    sha1: "1111111111111111111111111111111111111111".to_owned(),
+                    name: "Apple Development: Jane Doe (ABCDE12345)".to_owned(),
+                    problem: None,
+                },
+                Listing {
+                    sha1: "2222222222222222222222222222222222222222".to_owned(),
+                    name: "Apple Development: Jane Doe (ABCDE12345)".to_owned(),
+                    problem: Some("CSSMERR_TP_CERT_EXPIRED".to_owned()),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use crate::{config::build_config, version::Version};
+use serde_json::{json, Map, Value as JsonValue};
+use std::fmt::{self, Display};
+use toml::Value as TomlValue;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Error {
+    TypeUnsupported { key: String, ty: &'static str },
+    UsageDescriptionInvalid { key: String },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TypeUnsupported { key, ty } => write!(
+                f,
+                "`{}` is a {}, but only strings, booleans, arrays, and tables are supported",
+                key, ty
+            ),
+            Self::UsageDescriptionInvalid { key } => write!(
+                f,
+                "`{}` must be a non-empty string, since it's shown to the user when asking for permission",
+                key
+            ),
+        }
+    }
+}
+
+fn convert(key: &str, value: &TomlValue) -> Result<JsonValue, Error> {
+    let unsupported = |ty| Error::TypeUnsupported {
+        key: key.to_owned(),
+        ty,
+    };
+    match value {
+        TomlValue::String(s) => Ok(JsonValue::String(s.clone())),
+        TomlValue::Boolean(b) => Ok(JsonValue::Bool(*b)),
+        TomlValue::Array(array) => array
+            .iter()
+            .enumerate()
+            .map(|(index, value)| convert(&format!("{}[{}]", key, index), value))
+            .collect::<Result<_, _>>()
+            .map(JsonValue::Array),
+        TomlValue::Table(table) => convert_table(key, table).map(JsonValue::Object),
+        TomlValue::Integer(_) => Err(unsupported("integer")),
+        TomlValue::Float(_) => Err(unsupported("float")),
+        TomlValue::Datetime(_) => Err(unsupported("datetime")),
+    }
+}
+
+pub fn convert_table(
+    prefix: &str,
+    table: &toml::value::Table,
+) -> Result<Map<String, JsonValue>, Error> {
+    table
+        .iter()
+        .map(|(name, value)| {
+            let key = format!("{}.{}", prefix, name);
+            if name.ends_with("UsageDescription")
+                && value.as_str().map(str::trim).unwrap_or_default().is_empty()
+            {
+                return Err(Error::UsageDescriptionInvalid { key });
+            }
+            convert(&key, value).map(|value| (name.clone(), value))
+        })
+        .collect()
+}
+
+/// Keys from `apple.info-plist`, validated and ready to be merged into the
+/// generated Info.plist.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Properties(Map<String, JsonValue>);
+
+impl Properties {
+    pub fn from_toml(table: &toml::value::Table) -> Result<Self, Error> {
+        convert_table(&format!("{}.info-plist", super::NAME), table).map(Self)
+    }
+
+    fn merge_into(&self, mut base: Map<String, JsonValue>) -> Map<String, JsonValue> {
+        for (key, value) in &self.0 {
+            if let Some(default) = base.get(key) {
+                if default != value {
+                    log::warn!(
+                        "`{}.info-plist.{}` overrides the generated value {}",
+                        super::NAME,
+                        key,
+                        default
+                    );
+                }
+            }
+            base.insert(key.clone(), value.clone());
+        }
+        base
+    }
+
+    /// The iOS Info.plist properties, rendered as JSON, which `xcodegen`
+    /// happily accepts as YAML. Build config values end up under
+    /// `CargoMobileBuildConfig`, expanded from the env vars `xcodebuild` is
+    /// run with.
+    pub fn ios<'a>(
+        &self,
+        version: &Version,
+        build_config_keys: impl IntoIterator<Item = &'a str>,
+    ) -> String {
+        let defaults = json!({
+            "CFBundleDisplayName": "$(CARGO_MOBILE_DISPLAY_NAME)",
+            "CFBundleShortVersionString": version.name,
+            "CFBundleVersion": version.code.to_string(),
+            "LSRequiresIPhoneOS": true,
+            "UILaunchStoryboardName": "LaunchScreen",
+            "UIRequiredDeviceCapabilities": ["arm64", "metal"],
+            "UISupportedInterfaceOrientations": [
+                "UIInterfaceOrientationPortrait",
+                "UIInterfaceOrientationLandscapeLeft",
+                "UIInterfaceOrientationLandscapeRight",
+            ],
+            "UISupportedInterfaceOrientations~ipad": [
+                "UIInterfaceOrientationPortrait",
+                "UIInterfaceOrientationPortraitUpsideDown",
+                "UIInterfaceOrientationLandscapeLeft",
+                "UIInterfaceOrientationLandscapeRight",
+            ],
+        });
+        let mut defaults = match defaults {
+            JsonValue::Object(defaults) => defaults,
+            _ => unreachable!(),
+        };
+        let build_config = build_config_keys
+            .into_iter()
+            .map(|key| {
+                (
+                    key.to_owned(),
+                    JsonValue::String(format!("$({})", build_config::var_name(key))),
+                )
+            })
+            .collect::<Map<_, _>>();
+        if !build_config.is_empty() {
+            defaults.insert(
+                "CargoMobileBuildConfig".to_owned(),
+                JsonValue::Object(build_config),
+            );
+        }
+        JsonValue::Object(self.merge_into(defaults)).to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        raw,
+        expected,
+        case(
+            r#"NSCameraUsageDescription = "Scan QR codes"
+UIBackgroundModes = ["audio"]
+ITSAppUsesNonExemptEncryption = false
+[NSAppTransportSecurity]
+NSAllowsLocalNetworking = true"#,
+            Ok(json!({
+                "NSCameraUsageDescription": "Scan QR codes",
+                "UIBackgroundModes": ["audio"],
+                "ITSAppUsesNonExemptEncryption": false,
+                "NSAppTransportSecurity": { "NSAllowsLocalNetworking": true },
+            }))
+        ),
+        case(
+            r#"NSMicrophoneUsageDescription = " ""#,
+            Err(Error::UsageDescriptionInvalid {
+                key: "apple.info-plist.NSMicrophoneUsageDescription".to_owned(),
+            })
+        ),
+        case(
+            r#"NSLocalNetworkUsageDescription = true"#,
+            Err(Error::UsageDescriptionInvalid {
+                key: "apple.info-plist.NSLocalNetworkUsageDescription".to_owned(),
+            })
+        ),
+        case(
+            r#"[Foo]
+Bar = [1]"#,
+            Err(Error::TypeUnsupported {
+                key: "apple.info-plist.Foo.Bar[0]".to_owned(),
+                ty: "integer",
+            })
+        )
+    )]
+    fn test_from_toml(raw: &str, expected: Result<JsonValue, Error>) {
+        let table = toml::from_str::<toml::value::Table>(raw).unwrap();
+        assert_eq!(
+            Properties::from_toml(&table).map(|properties| JsonValue::Object(properties.0)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_ios_overrides_defaults() {
+        let table = toml::from_str::<toml::value::Table>(
+            r#"UISupportedInterfaceOrientations = ["UIInterfaceOrientationPortrait"]"#,
+        )
+        .unwrap();
+        let version = Version {
+            name: "1.4.2".to_owned(),
+            code: 42,
+        };
+        let ios = serde_json::from_str::<JsonValue>(
+            &Properties::from_toml(&table)
+                .unwrap()
+                .ios(&version, vec!["API_URL"]),
+        )
+        .unwrap();
+        assert_eq!(
+            ios["UISupportedInterfaceOrientations"],
+            json!(["UIInterfaceOrientationPortrait"])
+        );
+        assert_eq!(ios["LSRequiresIPhoneOS"], json!(true));
+        assert_eq!(ios["CFBundleVersion"], json!("42"));
+        assert_eq!(
+            ios["CargoMobileBuildConfig"],
+            json!({ "API_URL": "$(CARGO_MOBILE_CFG_API_URL)" })
+        );
+    }
+}
@@ -0,0 +1,400 @@
+//! What actually made it into a built app: how it was signed and with which
+//! entitlements, what its Info.plist says, which architectures it has, and
+//! the provisioning profile it carries. It's all read from the bundle itself,
+//! so only the signing identity needs Xcode's tools; anything that looks off
+//! is flagged, so that a debuggable distribution build is caught before it's
+//! uploaded.
+
+use super::{
+    config::Config,
+    install,
+    macho::{self, Slice},
+    profiles::{self, Profile},
+};
+use crate::{
+    assets,
+    env::{Env, ExplicitEnv as _},
+    opts::Profile as BuildProfile,
+    util::cli::{Report, Reportable},
+};
+use once_cell_regex::regex;
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    NoArtifact { dir: PathBuf },
+    BundleFailed(install::Error),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::NoArtifact { dir } => Report::action_request(
+                "There's nothing to inspect",
+                format!(
+                    "No apps or IPAs were found in {:?}; build one with `cargo apple build` or `cargo apple archive`, or pass the path to one.",
+                    dir
+                ),
+            ),
+            Self::BundleFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NoArtifact { .. } => "apple.inspect.no-artifact",
+            Self::BundleFailed(err) => err.code(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Signing {
+    identifier: Option<String>,
+    team_id: Option<String>,
+    adhoc: bool,
+    /// The certificate it was signed with, according to `codesign`, which is
+    /// `None` if `codesign` isn't around.
+    authority: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddedProfile {
+    name: String,
+    uuid: String,
+    app_id: String,
+    team_ids: Vec<String>,
+    days_left: i64,
+    development: bool,
+}
+
+impl From<&Profile> for EmbeddedProfile {
+    fn from(profile: &Profile) -> Self {
+        Self {
+            name: profile.name.clone(),
+            uuid: profile.uuid.clone(),
+            app_id: profile.app_id.clone(),
+            team_ids: profile.team_ids.clone(),
+            days_left: profile.days_left(),
+            development: profile.get_task_allow,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Inspection {
+    path: PathBuf,
+    bundle_id: Option<String>,
+    version: Option<String>,
+    build: Option<String>,
+    min_os: Option<String>,
+    archs: Vec<String>,
+    /// `None` if it isn't signed.
+    signing: Option<Signing>,
+    entitlements: Option<plist::Dictionary>,
+    /// `None` for simulator builds, which don't need one.
+    profile: Option<EmbeddedProfile>,
+    warnings: Vec<String>,
+}
+
+/// The most recently built app or IPA.
+pub fn latest(config: &Config) -> Result<PathBuf, Error> {
+    let mut candidates = config.ipa_path().into_iter().collect::<Vec<_>>();
+    for profile in &[BuildProfile::Debug, BuildProfile::Release] {
+        candidates.push(config.device_app_path(*profile));
+        candidates.push(config.simulator_app_path(*profile));
+    }
+    candidates
+        .into_iter()
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.modified());
+            modified.ok().map(|modified| (modified, path))
+        })
+        .max()
+        .map(|(_, path)| path)
+        .ok_or_else(|| Error::NoArtifact {
+            dir: config.export_dir(),
+        })
+}
+
+fn string(info: &plist::Dictionary, key: &str) -> Option<String> {
+    info.get(key)
+        .and_then(plist::Value::as_string)
+        .map(str::to_owned)
+}
+
+fn parse_authority(output: &str) -> Option<String> {
+    // The first one's the signing certificate; the rest are its issuers.
+    regex!(r"(?m)^Authority=(.+)$")
+        .captures(output)
+        .map(|caps| caps[1].trim().to_owned())
+}
+
+fn authority(env: &Env, app: &Path) -> Option<String> {
+    bossy::Command::pure("codesign")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["-d", "-vv"])
+        .with_arg(app)
+        .run_and_wait_for_output()
+        .map_err(|err| log::info!("`codesign` couldn't say who signed {:?}: {}", app, err))
+        .ok()
+        .and_then(|output| parse_authority(&String::from_utf8_lossy(output.stderr())))
+}
+
+fn describe(value: &plist::Value) -> String {
+    match value {
+        plist::Value::Boolean(b) => b.to_string(),
+        plist::Value::String(s) => s.clone(),
+        plist::Value::Array(values) => values.iter().map(describe).collect::<Vec<_>>().join(", "),
+        other => format!("{:?}", other),
+    }
+}
+
+fn warnings(
+    archs: &[Slice],
+    signing: Option<&Signing>,
+    entitlements: Option<&plist::Dictionary>,
+    profile: Option<&EmbeddedProfile>,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    // Distribution profiles don't allow debugging, and neither do the
+    // certificates they go with.
+    let distribution = signing
+        .and_then(|signing| signing.authority.as_deref())
+        .map_or(false, |authority| authority.contains("Distribution"))
+        || profile.map_or(false, |profile| !profile.development);
+    let get_task_allow = entitlements
+        .and_then(|entitlements| entitlements.get("get-task-allow"))
+        .and_then(plist::Value::as_boolean)
+        .unwrap_or_default();
+    if distribution && get_task_allow {
+        warnings.push(
+            "This is a distribution build, but it has the `get-task-allow` entitlement, which App Store Connect rejects"
+                .to_owned(),
+        );
+    }
+    if !archs.iter().any(|slice| slice.arch == "arm64") {
+        warnings.push(format!(
+            "There's no arm64 slice, just {}, so it won't run on devices or Apple silicon simulators",
+            archs
+                .iter()
+                .map(Slice::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    if let Some(profile) = profile {
+        if profile.days_left < 0 {
+            warnings.push(format!(
+                "The embedded provisioning profile {:?} has expired, so it won't install",
+                profile.name
+            ));
+        }
+    }
+    warnings
+}
+
+// Every file in the bundle, relative to it.
+fn bundle_files(dir: &Path, rel: &str, files: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let name = format!("{}{}", rel, entry.file_name().to_string_lossy());
+        if entry.path().is_dir() {
+            bundle_files(&entry.path(), &format!("{}/", name), files);
+        } else {
+            files.push(name);
+        }
+    }
+}
+
+/// Reads what's in the app bundle or IPA at `path`. `ignore` is what
+/// should've been left out of the assets.
+pub fn inspect(
+    env: &Env,
+    path: &Path,
+    ignore: Option<&assets::Ignore>,
+) -> Result<Inspection, Error> {
+    let app = install::app_bundle(env, path).map_err(Error::BundleFailed)?;
+    let info_plist = app.join("Info.plist");
+    let info = plist::Value::from_file(&info_plist)
+        .map_err(|cause| {
+            Error::BundleFailed(install::Error::InfoPlistInvalid {
+                path: info_plist,
+                cause,
+            })
+        })?
+        .into_dictionary()
+        .unwrap_or_default();
+    let (executable, bytes) = install::executable(&app).map_err(Error::BundleFailed)?;
+    let macho_invalid = |cause| {
+        Error::BundleFailed(install::Error::MachOInvalid {
+            path: executable.clone(),
+            cause,
+        })
+    };
+    let archs = macho::slices(&bytes).map_err(macho_invalid)?;
+    let signature = macho::signature(&bytes).map_err(macho_invalid)?;
+    let entitlements = signature
+        .as_ref()
+        .and_then(|signature| signature.entitlements.as_deref())
+        .and_then(|xml| {
+            plist::Value::from_reader_xml(xml)
+                .map_err(|err| log::warn!("failed to parse embedded entitlements: {}", err))
+                .ok()
+        })
+        .and_then(plist::Value::into_dictionary);
+    let signing = signature.map(|signature| Signing {
+        identifier: signature.identifier,
+        team_id: signature.team_id,
+        adhoc: signature.adhoc,
+        authority: authority(env, &app),
+    });
+    let embedded = app.join("embedded.mobileprovision");
+    let profile = if embedded.is_file() {
+        profiles::read_embedded(&embedded)
+            .map_err(|err| log::warn!("{}", err))
+            .ok()
+            .as_ref()
+            .map(EmbeddedProfile::from)
+    } else {
+        None
+    };
+    let mut warnings = warnings(
+        &archs,
+        signing.as_ref(),
+        entitlements.as_ref(),
+        profile.as_ref(),
+    );
+    if let Some(ignore) = ignore {
+        let mut files = Vec::new();
+        bundle_files(&app, "", &mut files);
+        warnings.extend(ignore.check_packaged(files.iter().map(String::as_str)));
+    }
+    Ok(Inspection {
+        path: path.to_owned(),
+        bundle_id: string(&info, "CFBundleIdentifier"),
+        version: string(&info, "CFBundleShortVersionString"),
+        build: string(&info, "CFBundleVersion"),
+        min_os: string(&info, "MinimumOSVersion")
+            .or_else(|| string(&info, "LSMinimumSystemVersion")),
+        archs: archs.iter().map(Slice::to_string).collect(),
+        signing,
+        entitlements,
+        profile,
+        warnings,
+    })
+}
+
+impl Inspection {
+    pub fn print(&self, json: bool) {
+        if json {
+            println!("{}", serde_json::json!(self));
+            return;
+        }
+        let or_unknown = |value: &Option<String>| value.as_deref().unwrap_or("unknown").to_owned();
+        println!("{}", self.path.display());
+        println!("    bundle ID: {}", or_unknown(&self.bundle_id));
+        println!(
+            "    version: {} ({})",
+            or_unknown(&self.version),
+            or_unknown(&self.build)
+        );
+        println!("    minimum OS: {}", or_unknown(&self.min_os));
+        println!("    architectures: {}", self.archs.join(", "));
+        match &self.signing {
+            Some(signing) if signing.adhoc => println!("    signed: ad hoc"),
+            Some(signing) => println!(
+                "    signed: {}, team {}",
+                or_unknown(&signing.authority),
+                or_unknown(&signing.team_id)
+            ),
+            None => println!("    signed: no"),
+        }
+        match &self.entitlements {
+            Some(entitlements) if !entitlements.is_empty() => {
+                println!("    entitlements:");
+                for (key, value) in entitlements {
+                    println!("        {}: {}", key, describe(value));
+                }
+            }
+            _ => println!("    entitlements: none"),
+        }
+        match &self.profile {
+            Some(profile) => println!(
+                "    provisioning profile: {} [{}], {}",
+                profile.name,
+                profile.uuid,
+                match profile.days_left {
+                    days if days < 0 => "expired".to_owned(),
+                    days => format!("expires in {} days", days),
+                }
+            ),
+            None => println!("    provisioning profile: none"),
+        }
+        for warning in &self.warnings {
+            println!("warning: {}", warning);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn slice(arch: &str) -> Slice {
+        Slice {
+            arch: arch.to_owned(),
+            platform: None,
+        }
+    }
+
+    fn profile(development: bool, days_left: i64) -> EmbeddedProfile {
+        EmbeddedProfile {
+            name: "App Store".to_owned(),
+            uuid: "1234".to_owned(),
+            app_id: "ABCDE12345.com.example.app".to_owned(),
+            team_ids: vec!["ABCDE12345".to_owned()],
+            days_left,
+            development,
+        }
+    }
+
+    #[test]
+    fn test_parse_authority() {
+        let output = "Executable=/tmp/App.app/App\nIdentifier=com.example.app\nAuthority=Apple Distribution: Example Inc (ABCDE12345)\nAuthority=Apple Worldwide Developer Relations Certification Authority\nAuthority=Apple Root CA\nTeamIdentifier=ABCDE12345\n";
+        assert_eq!(
+            parse_authority(output).as_deref(),
+            Some("Apple Distribution: Example Inc (ABCDE12345)")
+        );
+        assert_eq!(parse_authority("Signature=adhoc\n"), None);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let mut entitlements = plist::Dictionary::new();
+        entitlements.insert("get-task-allow".to_owned(), true.into());
+        assert_eq!(
+            warnings(
+                &[slice("x86_64")],
+                None,
+                Some(&entitlements),
+                Some(&profile(false, -1))
+            ),
+            vec![
+                "This is a distribution build, but it has the `get-task-allow` entitlement, which App Store Connect rejects",
+                "There's no arm64 slice, just x86_64, so it won't run on devices or Apple silicon simulators",
+                "The embedded provisioning profile \"App Store\" has expired, so it won't install",
+            ]
+        );
+        assert!(warnings(
+            &[slice("arm64")],
+            None,
+            Some(&entitlements),
+            Some(&profile(true, 30))
+        )
+        .is_empty());
+    }
+}
@@ -0,0 +1,300 @@
+//! Installing an app that wasn't necessarily built here, i.e. one from CI. It's
+//! checked against where it's going first, since `devicectl` and `simctl` don't
+//! say much about why an app for the wrong arch or platform won't install.
+
+use super::macho::{self, Platform, Slice};
+use crate::{
+    env::{Env, ExplicitEnv as _},
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    ExtensionInvalid {
+        path: PathBuf,
+    },
+    DirCreationFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    UnzipFailed {
+        path: PathBuf,
+        cause: bossy::Error,
+    },
+    PayloadMissing {
+        path: PathBuf,
+    },
+    InfoPlistInvalid {
+        path: PathBuf,
+        cause: plist::Error,
+    },
+    ExecutableMissing {
+        path: PathBuf,
+    },
+    ReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    MachOInvalid {
+        path: PathBuf,
+        cause: macho::Error,
+    },
+    Mismatch {
+        path: PathBuf,
+        slices: Vec<Slice>,
+        destination: String,
+        wanted: String,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ExtensionInvalid { path } => Report::error(
+                format!("Can't install {:?}", path),
+                "Only `.app` bundles and `.ipa` archives can be installed",
+            ),
+            Self::DirCreationFailed { path, cause } => {
+                Report::error(format!("Failed to create directory {:?}", path), cause)
+            }
+            Self::UnzipFailed { path, cause } => {
+                Report::error(format!("Failed to extract IPA {:?}", path), cause)
+            }
+            Self::PayloadMissing { path } => Report::error(
+                format!("Failed to find the app in IPA {:?}", path),
+                "There's no `.app` bundle in its `Payload` directory",
+            ),
+            Self::InfoPlistInvalid { path, cause } => {
+                Report::error(format!("Failed to read {:?}", path), cause)
+            }
+            Self::ExecutableMissing { path } => Report::error(
+                format!("Failed to read {:?}", path),
+                "`CFBundleExecutable` is missing",
+            ),
+            Self::ReadFailed { path, cause } => {
+                Report::error(format!("Failed to read executable {:?}", path), cause)
+            }
+            Self::MachOInvalid { path, cause } => {
+                Report::error(format!("Failed to read executable {:?}", path), cause)
+            }
+            Self::Mismatch {
+                path,
+                slices,
+                destination,
+                wanted,
+            } => Report::action_request(
+                format!("{:?} can't run on {}", path, destination),
+                format!(
+                    "It was built for {}, but {} needs {}. Apps built for devices can't run on simulators (and vice versa), so it has to be built for the right one.",
+                    slices
+                        .iter()
+                        .map(Slice::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    destination,
+                    wanted
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ExtensionInvalid { .. } => "apple.install.extension-invalid",
+            Self::DirCreationFailed { .. } => "apple.install.dir-creation-failed",
+            Self::UnzipFailed { .. } => "apple.install.unzip-failed",
+            Self::PayloadMissing { .. } => "apple.install.payload-missing",
+            Self::InfoPlistInvalid { .. } => "apple.install.info-plist-invalid",
+            Self::ExecutableMissing { .. } => "apple.install.executable-missing",
+            Self::ReadFailed { .. } => "apple.install.read-failed",
+            Self::MachOInvalid { .. } => "apple.install.mach-o-invalid",
+            Self::Mismatch { .. } => "apple.install.mismatch",
+        }
+    }
+}
+
+/// Where an app is being installed.
+#[derive(Clone, Copy, Debug)]
+pub struct Destination<'a> {
+    pub name: &'a str,
+    pub arch: &'a str,
+    pub simulator: bool,
+}
+
+impl Display for Destination<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Destination<'_> {
+    fn platform(&self) -> Platform {
+        if self.simulator {
+            Platform::IosSimulator
+        } else {
+            Platform::Ios
+        }
+    }
+
+    // Binaries that don't say what platform they're for are given the benefit
+    // of the doubt.
+    fn accepts(&self, slice: &Slice) -> bool {
+        slice.arch == self.arch
+            && slice
+                .platform
+                .map_or(true, |platform| platform == self.platform())
+    }
+}
+
+/// An `.app` bundle, which is removed when dropped if we extracted it.
+#[derive(Debug)]
+pub struct AppBundle {
+    path: PathBuf,
+    _extracted: Option<util::TempDir>,
+}
+
+impl Deref for AppBundle {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for AppBundle {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn extract_ipa(env: &Env, ipa: &Path) -> Result<AppBundle, Error> {
+    let dir = util::TempDir::new("install")
+        .map_err(|(path, cause)| Error::DirCreationFailed { path, cause })?;
+    bossy::Command::pure("unzip")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["-q", "-o"])
+        .with_arg(ipa)
+        .with_arg("-d")
+        .with_arg(dir.path())
+        .run_and_wait()
+        .map_err(|cause| Error::UnzipFailed {
+            path: ipa.to_owned(),
+            cause,
+        })?;
+    let path = fs::read_dir(dir.path().join("Payload"))
+        .ok()
+        .and_then(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .find(|path| path.extension().map_or(false, |ext| ext == "app"))
+        })
+        .ok_or_else(|| Error::PayloadMissing {
+            path: ipa.to_owned(),
+        })?;
+    Ok(AppBundle {
+        path,
+        _extracted: Some(dir),
+    })
+}
+
+/// Finds the `.app` bundle to install from `path`, which can be the bundle
+/// itself, or an `.ipa` to extract it from.
+pub fn app_bundle(env: &Env, path: &Path) -> Result<AppBundle, Error> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("app") if path.is_dir() => Ok(AppBundle {
+            path: path.to_owned(),
+            _extracted: None,
+        }),
+        Some("ipa") => extract_ipa(env, path),
+        _ => Err(Error::ExtensionInvalid {
+            path: path.to_owned(),
+        }),
+    }
+}
+
+/// Reads the executable in the app bundle at `app`, returning its path too.
+pub fn executable(app: &Path) -> Result<(PathBuf, Vec<u8>), Error> {
+    let info_plist = app.join("Info.plist");
+    let executable = plist::Value::from_file(&info_plist)
+        .map_err(|cause| Error::InfoPlistInvalid {
+            path: info_plist.clone(),
+            cause,
+        })?
+        .as_dictionary()
+        .and_then(|info| info.get("CFBundleExecutable"))
+        .and_then(|executable| executable.as_string())
+        .map(|executable| app.join(executable))
+        .ok_or(Error::ExecutableMissing { path: info_plist })?;
+    let bytes = fs::read(&executable).map_err(|cause| Error::ReadFailed {
+        path: executable.clone(),
+        cause,
+    })?;
+    Ok((executable, bytes))
+}
+
+/// Checks that the executable in the app bundle at `app` has a slice that can
+/// run on `destination`.
+pub fn check(app: &Path, destination: Destination<'_>) -> Result<(), Error> {
+    let (executable, bytes) = executable(app)?;
+    let slices = macho::slices(&bytes).map_err(|cause| Error::MachOInvalid {
+        path: executable,
+        cause,
+    })?;
+    log::info!("{:?} was built for {:?}", app, slices);
+    if slices.iter().any(|slice| destination.accepts(slice)) {
+        Ok(())
+    } else {
+        Err(Error::Mismatch {
+            path: app.to_owned(),
+            slices,
+            destination: destination.to_string(),
+            wanted: format!("{} for {}", destination.arch, destination.platform()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        arch,
+        platform,
+        simulator,
+        expected,
+        case("arm64", Some(Platform::Ios), false, true),
+        case("arm64", Some(Platform::IosSimulator), false, false),
+        case("arm64", Some(Platform::IosSimulator), true, true),
+        case("x86_64", Some(Platform::IosSimulator), true, false),
+        case("arm64", None, true, true),
+        case("arm64", Some(Platform::MacOs), false, false)
+    )]
+    fn test_destination_accepts(
+        arch: &str,
+        platform: Option<Platform>,
+        simulator: bool,
+        expected: bool,
+    ) {
+        let destination = Destination {
+            name: "somewhere",
+            arch: "arm64",
+            simulator,
+        };
+        let slice = Slice {
+            arch: arch.to_owned(),
+            platform,
+        };
+        assert_eq!(destination.accepts(&slice), expected);
+    }
+}
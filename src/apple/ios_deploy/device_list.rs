@@ -25,6 +25,15 @@ impl Reportable for DeviceListError {
             }
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DetectionFailed(..) => {
+                "apple.ios-deploy.device-list.device-list.detection-failed"
+            }
+            Self::ArchInvalid(..) => "apple.ios-deploy.device-list.device-list.arch-invalid",
+        }
+    }
 }
 
 fn parse_device_list<'a>(output: &bossy::Output) -> Result<BTreeSet<Device<'a>>, DeviceListError> {
@@ -1,30 +1,59 @@
 use crate::{
     apple::config::Config,
     env::{Env, ExplicitEnv as _},
-    opts,
-    util::cli::{Report, Reportable},
+    opts, ui,
+    util::{
+        cli::{Report, Reportable},
+        progress::{self, Phase},
+    },
 };
+use std::path::Path;
 
 #[derive(Debug)]
 pub enum RunAndDebugError {
     DeployFailed(bossy::Error),
+    InstallFailed(bossy::Error),
 }
 
 impl Reportable for RunAndDebugError {
     fn report(&self) -> Report {
         match self {
             Self::DeployFailed(err) => Report::error("Failed to deploy app to device", err),
+            Self::InstallFailed(err) => Report::error("Failed to install app on device", err),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DeployFailed(..) => "apple.ios-deploy.run.run-and-debug.deploy-failed",
+            Self::InstallFailed(..) => "apple.ios-deploy.run.run-and-debug.install-failed",
         }
     }
 }
 
+/// Installs the app bundle at `app_path` without launching it, which is all
+/// `ios-deploy` does without `--debug`.
+pub fn install(env: &Env, id: &str, app_path: &Path) -> Result<(), RunAndDebugError> {
+    ui::status("Installing app on device...");
+    let install = bossy::Command::pure("ios-deploy")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["--id", id])
+        .with_arg("--bundle")
+        .with_arg(app_path)
+        .with_arg("--no-wifi");
+    progress::phase(Phase::Install, None, || install.run_and_wait())
+        .map(|_| ())
+        .map_err(RunAndDebugError::InstallFailed)
+}
+
 pub fn run_and_debug(
     config: &Config,
     env: &Env,
     non_interactive: opts::NonInteractive,
     id: &str,
+    skip_log: opts::SkipLog,
 ) -> Result<(), RunAndDebugError> {
-    println!("Deploying app to device...");
+    ui::status("Deploying app to device...");
     bossy::Command::pure("ios-deploy")
         .with_env_vars(env.explicit_env())
         .with_arg("--debug")
@@ -36,6 +65,13 @@ pub fn run_and_debug(
         } else {
             None
         })
+        // `ios-deploy` streams logs via `lldb`, which we skip by detaching
+        // as soon as the app launches.
+        .with_args(if skip_log.yes() {
+            Some("--justlaunch")
+        } else {
+            None
+        })
         .with_arg("--no-wifi")
         .run_and_wait()
         .map(|_| ())
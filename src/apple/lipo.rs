@@ -0,0 +1,240 @@
+use super::{config::Config, target::Target};
+use crate::{
+    fingerprint::{Fingerprint, Store},
+    opts,
+    util::cli::{Report, Reportable},
+};
+use std::{fs, io, path::PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    DeviceAndSimulatorMixed {
+        device: Vec<String>,
+        simulator: Vec<String>,
+    },
+    LibMissing {
+        path: PathBuf,
+    },
+    ArchsFailed {
+        path: PathBuf,
+        cause: bossy::Error,
+    },
+    ArchsMismatched {
+        path: PathBuf,
+        expected: Vec<String>,
+        found: Vec<String>,
+    },
+    DirCreationFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    RemoveFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    CreateFailed(bossy::Error),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        let msg = "Failed to create universal simulator lib";
+        match self {
+            Self::DeviceAndSimulatorMixed { device, simulator } => Report::error(
+                msg,
+                format!(
+                    "Device slices ({}) and simulator slices ({}) can't be combined with `lipo`, since they're for different platforms. That requires an XCFramework instead.",
+                    device.join(", "),
+                    simulator.join(", "),
+                ),
+            ),
+            Self::LibMissing { path } => {
+                Report::error(msg, format!("Nothing was built at {:?}", path))
+            }
+            Self::ArchsFailed { path, cause } => Report::error(
+                msg,
+                format!("Failed to check archs of {:?}: {}", path, cause),
+            ),
+            Self::ArchsMismatched {
+                path,
+                expected,
+                found,
+            } => Report::error(
+                msg,
+                format!(
+                    "{:?} should contain {}, but `lipo -archs` found {}",
+                    path,
+                    expected.join(", "),
+                    if found.is_empty() {
+                        "nothing".to_owned()
+                    } else {
+                        found.join(", ")
+                    },
+                ),
+            ),
+            Self::DirCreationFailed { path, cause } => Report::error(
+                msg,
+                format!("Failed to create directory {:?}: {}", path, cause),
+            ),
+            Self::RemoveFailed { path, cause } => Report::error(
+                msg,
+                format!("Failed to remove stale lib {:?}: {}", path, cause),
+            ),
+            Self::CreateFailed(err) => Report::error(msg, err),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DeviceAndSimulatorMixed { .. } => "apple.lipo.device-and-simulator-mixed",
+            Self::LibMissing { .. } => "apple.lipo.lib-missing",
+            Self::ArchsFailed { .. } => "apple.lipo.archs-failed",
+            Self::ArchsMismatched { .. } => "apple.lipo.archs-mismatched",
+            Self::DirCreationFailed { .. } => "apple.lipo.dir-creation-failed",
+            Self::RemoveFailed { .. } => "apple.lipo.remove-failed",
+            Self::CreateFailed(..) => "apple.lipo.create-failed",
+        }
+    }
+}
+
+fn parse_archs(output: &str) -> Vec<String> {
+    output.split_whitespace().map(ToOwned::to_owned).collect()
+}
+
+pub fn archs(path: PathBuf) -> Result<Vec<String>, Error> {
+    if !path.is_file() {
+        return Err(Error::LibMissing { path });
+    }
+    bossy::Command::impure("lipo")
+        .with_arg("-archs")
+        .with_arg(&path)
+        .run_and_wait_for_str(parse_archs)
+        .map_err(|cause| Error::ArchsFailed { path, cause })
+}
+
+fn check_archs(path: PathBuf, expected: Vec<String>) -> Result<PathBuf, Error> {
+    let mut found = archs(path.clone())?;
+    found.sort();
+    let mut sorted = expected.clone();
+    sorted.sort();
+    if found == sorted {
+        Ok(path)
+    } else {
+        Err(Error::ArchsMismatched {
+            path,
+            expected,
+            found,
+        })
+    }
+}
+
+fn check_not_mixed(targets: &[Target<'_>]) -> Result<(), Error> {
+    let (simulator, device): (Vec<_>, Vec<_>) =
+        targets.iter().partition(|target| target.is_simulator());
+    if simulator.is_empty() || device.is_empty() {
+        Ok(())
+    } else {
+        let triples = |targets: Vec<&Target<'_>>| {
+            targets
+                .into_iter()
+                .map(|target| target.triple.to_owned())
+                .collect()
+        };
+        Err(Error::DeviceAndSimulatorMixed {
+            device: triples(device),
+            simulator: triples(simulator),
+        })
+    }
+}
+
+/// Combines the per-triple simulator libs into a fat archive at
+/// `Config::universal_simulator_lib_path`, which the Xcode project searches
+/// before the per-arch dirs. When only one simulator arch was built, any
+/// universal lib left over from a previous build is removed instead, so it
+/// can't shadow the fresh one. Nothing is done if neither the slices nor the
+/// universal lib have changed since it was last created, unless `force` is set.
+pub fn create_universal_simulator_lib(
+    config: &Config,
+    targets: &[Target<'_>],
+    profile: opts::Profile,
+    force: opts::Force,
+) -> Result<(), Error> {
+    check_not_mixed(targets)?;
+    let dest = config.universal_simulator_lib_path(profile);
+    if targets.len() < 2 {
+        if dest.is_file() {
+            log::info!("removing stale universal simulator lib {:?}", dest);
+            fs::remove_file(&dest).map_err(|cause| Error::RemoveFailed { path: dest, cause })?;
+        }
+        return Ok(());
+    }
+    let fingerprint = || {
+        targets
+            .iter()
+            .fold(Fingerprint::new(), |fingerprint, target| {
+                fingerprint.with_file(config.lib_path(target.triple, profile))
+            })
+            .with_file(&dest)
+    };
+    let store = Store::new(config.project_dir());
+    let step = format!("universal-simulator-lib-{}", profile.as_str());
+    if force.no() && store.fresh(&step, &fingerprint()) {
+        log::info!("universal simulator lib {:?} is up to date", dest);
+        return Ok(());
+    }
+    store.forget(&step);
+    let srcs = targets
+        .iter()
+        .map(|target| {
+            check_archs(
+                config.lib_path(target.triple, profile),
+                vec![target.arch.to_owned()],
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).map_err(|cause| Error::DirCreationFailed {
+            path: parent.to_owned(),
+            cause,
+        })?;
+    }
+    log::info!("combining {:?} into {:?}", srcs, dest);
+    bossy::Command::impure("lipo")
+        .with_arg("-create")
+        .with_args(&srcs)
+        .with_arg("-output")
+        .with_arg(&dest)
+        .run_and_wait()
+        .map_err(Error::CreateFailed)?;
+    check_archs(
+        dest.clone(),
+        targets
+            .iter()
+            .map(|target| target.arch.to_owned())
+            .collect(),
+    )?;
+    store.record(&step, &fingerprint());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_archs() {
+        assert_eq!(parse_archs("x86_64 arm64\n"), vec!["x86_64", "arm64"]);
+        assert!(parse_archs("\n").is_empty());
+    }
+
+    #[test]
+    fn test_check_not_mixed() {
+        let device = *Target::for_arch("arm64").unwrap();
+        let x86_64_sim = Target::for_simulator_arch("x86_64").unwrap();
+        let aarch64_sim = Target::for_simulator_arch("arm64").unwrap();
+        assert!(check_not_mixed(&[x86_64_sim, aarch64_sim]).is_ok());
+        assert!(matches!(
+            check_not_mixed(&[device, x86_64_sim]),
+            Err(Error::DeviceAndSimulatorMixed { .. })
+        ));
+    }
+}
@@ -0,0 +1,378 @@
+//! Just enough of the Mach-O format to tell what an executable was built for,
+//! which is what decides whether it can be installed somewhere, and how it was
+//! signed.
+
+use std::fmt::{self, Display};
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Error {
+    Truncated,
+    MagicInvalid(u32),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "The executable is truncated"),
+            Self::MagicInvalid(magic) => write!(
+                f,
+                "The executable doesn't start with a Mach-O magic number (found {:#010x})",
+                magic
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Platform {
+    MacOs,
+    Ios,
+    MacCatalyst,
+    IosSimulator,
+    Other(u32),
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MacOs => write!(f, "macOS"),
+            Self::Ios => write!(f, "iOS"),
+            Self::MacCatalyst => write!(f, "Mac Catalyst"),
+            Self::IosSimulator => write!(f, "the iOS Simulator"),
+            Self::Other(platform) => write!(f, "platform {}", platform),
+        }
+    }
+}
+
+impl Platform {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => Self::MacOs,
+            2 => Self::Ios,
+            6 => Self::MacCatalyst,
+            7 => Self::IosSimulator,
+            _ => Self::Other(raw),
+        }
+    }
+}
+
+/// One architecture's worth of a (possibly universal) binary. The platform's
+/// only known for binaries with an `LC_BUILD_VERSION` load command, which is
+/// everything built by Xcode 10 or later.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Slice {
+    pub arch: String,
+    pub platform: Option<Platform>,
+}
+
+impl Display for Slice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.arch)?;
+        if let Some(platform) = self.platform {
+            write!(f, " for {}", platform)?;
+        }
+        Ok(())
+    }
+}
+
+const MH_MAGIC: u32 = 0xfeed_face;
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const FAT_MAGIC: u32 = 0xcafe_babe;
+const FAT_MAGIC_64: u32 = 0xcafe_babf;
+const LC_BUILD_VERSION: u32 = 0x32;
+const LC_CODE_SIGNATURE: u32 = 0x1d;
+
+fn read_u32(bytes: &[u8], at: usize, big_endian: bool) -> Result<u32, Error> {
+    let bytes = bytes.get(at..at + 4).ok_or(Error::Truncated)?;
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    Ok(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Result<u64, Error> {
+    Ok((read_u32(bytes, at, true)? as u64) << 32 | read_u32(bytes, at + 4, true)? as u64)
+}
+
+fn arch_name(cpu_type: u32) -> String {
+    match cpu_type {
+        0x0100_000c => "arm64".to_owned(),
+        0x0200_000c => "arm64_32".to_owned(),
+        0x0100_0007 => "x86_64".to_owned(),
+        0x0000_000c => "armv7".to_owned(),
+        0x0000_0007 => "i386".to_owned(),
+        _ => format!("cpu type {:#x}", cpu_type),
+    }
+}
+
+// Everything Apple ships on is little-endian, so thin binaries are too.
+fn thin(bytes: &[u8]) -> Result<Slice, Error> {
+    let header_len = match read_u32(bytes, 0, false)? {
+        MH_MAGIC => 28,
+        MH_MAGIC_64 => 32,
+        magic => return Err(Error::MagicInvalid(magic)),
+    };
+    let arch = arch_name(read_u32(bytes, 4, false)?);
+    let command_count = read_u32(bytes, 16, false)?;
+    let mut at = header_len;
+    let mut platform = None;
+    for _ in 0..command_count {
+        let command = read_u32(bytes, at, false)?;
+        let command_len = read_u32(bytes, at + 4, false)? as usize;
+        if command == LC_BUILD_VERSION {
+            platform = Some(Platform::from_raw(read_u32(bytes, at + 8, false)?));
+            break;
+        }
+        if command_len == 0 {
+            return Err(Error::Truncated);
+        }
+        at += command_len;
+    }
+    Ok(Slice { arch, platform })
+}
+
+// Each thin binary in a (possibly universal) binary.
+fn thin_binaries(bytes: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    // Universal headers are big-endian, and say where each slice starts.
+    let magic = read_u32(bytes, 0, true)?;
+    let (entry_len, wide) = match magic {
+        FAT_MAGIC => (20, false),
+        FAT_MAGIC_64 => (32, true),
+        _ => return Ok(vec![bytes]),
+    };
+    let count = read_u32(bytes, 4, true)? as usize;
+    (0..count)
+        .map(|index| {
+            let at = 8 + index * entry_len;
+            let offset = if wide {
+                read_u64(bytes, at + 8)? as usize
+            } else {
+                read_u32(bytes, at + 8, true)? as usize
+            };
+            bytes.get(offset..).ok_or(Error::Truncated)
+        })
+        .collect()
+}
+
+/// The architectures (and platforms) a Mach-O binary was built for, which is
+/// one for a thin binary, or one for each slice of a universal binary.
+pub fn slices(bytes: &[u8]) -> Result<Vec<Slice>, Error> {
+    thin_binaries(bytes)?.into_iter().map(thin).collect()
+}
+
+/// What the code signature says about who signed a binary, and what with.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Signature {
+    pub identifier: Option<String>,
+    /// Only there for signatures made with a certificate from a team.
+    pub team_id: Option<String>,
+    pub adhoc: bool,
+    /// The entitlements plist, as XML.
+    pub entitlements: Option<Vec<u8>>,
+}
+
+const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade_0cc0;
+const CSMAGIC_CODEDIRECTORY: u32 = 0xfade_0c02;
+const CSMAGIC_EMBEDDED_ENTITLEMENTS: u32 = 0xfade_7171;
+const CSSLOT_CODEDIRECTORY: u32 = 0;
+const CSSLOT_ENTITLEMENTS: u32 = 5;
+const CS_ADHOC: u32 = 0x2;
+// The first code directory version with a team ID.
+const CS_SUPPORTSTEAMID: u32 = 0x20200;
+
+fn c_string(bytes: &[u8], at: usize) -> Option<String> {
+    let bytes = bytes.get(at..)?;
+    let len = bytes.iter().position(|b| *b == 0)?;
+    Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
+
+// The code signature is a blob of blobs, which unlike the rest of the file is
+// big-endian.
+fn signature_in(bytes: &[u8]) -> Result<Option<Signature>, Error> {
+    let header_len = match read_u32(bytes, 0, false)? {
+        MH_MAGIC => 28,
+        MH_MAGIC_64 => 32,
+        magic => return Err(Error::MagicInvalid(magic)),
+    };
+    let command_count = read_u32(bytes, 16, false)?;
+    let mut at = header_len;
+    let mut blob = None;
+    for _ in 0..command_count {
+        let command = read_u32(bytes, at, false)?;
+        let command_len = read_u32(bytes, at + 4, false)? as usize;
+        if command == LC_CODE_SIGNATURE {
+            blob = Some(read_u32(bytes, at + 8, false)? as usize);
+            break;
+        }
+        if command_len == 0 {
+            return Err(Error::Truncated);
+        }
+        at += command_len;
+    }
+    let blob = match blob {
+        Some(blob) => bytes.get(blob..).ok_or(Error::Truncated)?,
+        None => return Ok(None),
+    };
+    if read_u32(blob, 0, true)? != CSMAGIC_EMBEDDED_SIGNATURE {
+        return Ok(None);
+    }
+    let mut signature = Signature::default();
+    for index in 0..read_u32(blob, 8, true)? as usize {
+        let slot = read_u32(blob, 12 + index * 8, true)?;
+        let offset = read_u32(blob, 16 + index * 8, true)? as usize;
+        let entry = blob.get(offset..).ok_or(Error::Truncated)?;
+        match (slot, read_u32(entry, 0, true)?) {
+            (CSSLOT_CODEDIRECTORY, CSMAGIC_CODEDIRECTORY) => {
+                let version = read_u32(entry, 8, true)?;
+                signature.adhoc = read_u32(entry, 12, true)? & CS_ADHOC != 0;
+                signature.identifier = c_string(entry, read_u32(entry, 20, true)? as usize);
+                if version >= CS_SUPPORTSTEAMID {
+                    signature.team_id = match read_u32(entry, 48, true)? as usize {
+                        0 => None,
+                        at => c_string(entry, at),
+                    };
+                }
+            }
+            (CSSLOT_ENTITLEMENTS, CSMAGIC_EMBEDDED_ENTITLEMENTS) => {
+                let len = read_u32(entry, 4, true)? as usize;
+                signature.entitlements = Some(entry.get(8..len).ok_or(Error::Truncated)?.to_vec());
+            }
+            _ => (),
+        }
+    }
+    Ok(Some(signature))
+}
+
+/// The code signature of a binary, which is `None` if it isn't signed. Every
+/// slice of a universal binary is signed the same way, so we only look at the
+/// first.
+pub fn signature(bytes: &[u8]) -> Result<Option<Signature>, Error> {
+    match thin_binaries(bytes)?.first() {
+        Some(bytes) => signature_in(bytes),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn thin_binary(cpu_type: u32, platform: Option<u32>) -> Vec<u8> {
+        let command_count = if platform.is_some() { 2 } else { 1 };
+        let mut bytes = Vec::new();
+        for word in &[MH_MAGIC_64, cpu_type, 0, 2, command_count, 0, 0, 0] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        // `LC_UUID`, which is skipped over
+        bytes.extend_from_slice(&0x1bu32.to_le_bytes());
+        bytes.extend_from_slice(&24u32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 16]);
+        if let Some(platform) = platform {
+            for word in &[LC_BUILD_VERSION, 24, platform, 0, 0, 0] {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_thin_slices() {
+        assert_eq!(
+            slices(&thin_binary(0x0100_000c, Some(2))),
+            Ok(vec![Slice {
+                arch: "arm64".to_owned(),
+                platform: Some(Platform::Ios),
+            }])
+        );
+        assert_eq!(
+            slices(b"#!/bin/sh\n"),
+            Err(Error::MagicInvalid(u32::from_le_bytes(*b"#!/b")))
+        );
+    }
+
+    #[test]
+    fn test_signature() {
+        let mut bytes = Vec::new();
+        for word in &[MH_MAGIC_64, 0x0100_000c, 0, 2, 1, 0, 0, 0] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let blob_at = bytes.len() as u32 + 16;
+        for word in &[LC_CODE_SIGNATURE, 16, blob_at, 0] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut directory = Vec::new();
+        for word in &[CSMAGIC_CODEDIRECTORY, 0, CS_SUPPORTSTEAMID, 0, 0, 52] {
+            directory.extend_from_slice(&word.to_be_bytes());
+        }
+        directory.resize(48, 0);
+        directory.extend_from_slice(&68u32.to_be_bytes());
+        directory.extend_from_slice(b"com.example.app\0ABCDE12345\0");
+        let entitlements = b"<plist><dict/></plist>";
+        let mut blob = Vec::new();
+        for word in &[CSMAGIC_EMBEDDED_SIGNATURE, 0, 2] {
+            blob.extend_from_slice(&word.to_be_bytes());
+        }
+        let directory_at = 12 + 2 * 8;
+        let entitlements_at = directory_at + directory.len();
+        for word in &[
+            CSSLOT_CODEDIRECTORY,
+            directory_at as u32,
+            CSSLOT_ENTITLEMENTS,
+            entitlements_at as u32,
+        ] {
+            blob.extend_from_slice(&word.to_be_bytes());
+        }
+        blob.extend_from_slice(&directory);
+        blob.extend_from_slice(&CSMAGIC_EMBEDDED_ENTITLEMENTS.to_be_bytes());
+        blob.extend_from_slice(&(8 + entitlements.len() as u32).to_be_bytes());
+        blob.extend_from_slice(entitlements);
+        bytes.extend_from_slice(&blob);
+        assert_eq!(
+            signature(&bytes),
+            Ok(Some(Signature {
+                identifier: Some("com.example.app".to_owned()),
+                team_id: Some("ABCDE12345".to_owned()),
+                adhoc: false,
+                entitlements: Some(entitlements.to_vec()),
+            }))
+        );
+        assert_eq!(signature(&thin_binary(0x0100_000c, None)), Ok(None));
+    }
+
+    #[test]
+    fn test_universal_slices() {
+        let arm64 = thin_binary(0x0100_000c, Some(7));
+        let x86_64 = thin_binary(0x0100_0007, None);
+        let mut bytes = Vec::new();
+        for word in &[FAT_MAGIC, 2] {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        let first = 8 + 2 * 20;
+        let second = first + arm64.len();
+        for (cpu_type, offset, len) in &[
+            (0x0100_000cu32, first, arm64.len()),
+            (0x0100_0007, second, x86_64.len()),
+        ] {
+            for word in &[*cpu_type, 0, *offset as u32, *len as u32, 0] {
+                bytes.extend_from_slice(&word.to_be_bytes());
+            }
+        }
+        bytes.extend_from_slice(&arm64);
+        bytes.extend_from_slice(&x86_64);
+        assert_eq!(
+            slices(&bytes),
+            Ok(vec![
+                Slice {
+                    arch: "arm64".to_owned(),
+                    platform: Some(Platform::IosSimulator),
+                },
+                Slice {
+                    arch: "x86_64".to_owned(),
+                    platform: None,
+                },
+            ])
+        );
+    }
+}
@@ -1,12 +1,41 @@
 pub mod cli;
+mod capture;
+mod clean;
 pub(crate) mod config;
+mod console;
 mod deps;
-mod device;
+mod desktop;
+pub(crate) mod device;
+pub mod doctor;
+mod dsym;
+mod devicectl;
+mod entitlements;
+mod export;
+mod icons;
+mod identities;
+mod info_plist;
+mod inspect;
+mod install;
 mod ios_deploy;
+mod lipo;
+mod macho;
+pub(crate) mod packages;
+mod pods;
+pub(crate) mod profiles;
 pub(crate) mod project;
+mod schemes;
+mod signing;
+pub(crate) mod simctl;
+mod sources;
+mod symbolicate;
 mod system_profile;
-mod target;
+pub(crate) mod target;
 mod teams;
+mod transfer;
+mod upload;
+mod xcframework;
+pub(crate) mod xcode;
+mod xcodebuild;
 
 use crate::util::{
     self,
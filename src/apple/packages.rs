@@ -0,0 +1,319 @@
+use super::config::Config;
+use crate::{
+    env::{Env, ExplicitEnv as _},
+    ui,
+    util::cli::{Report, Reportable},
+};
+use once_cell_regex::regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+use std::fmt::{self, Display};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Raw {
+    pub name: String,
+    pub url: String,
+    pub exact: Option<String>,
+    pub up_to_next_major: Option<String>,
+    pub branch: Option<String>,
+    /// Defaults to a single product with the same name as the package.
+    pub products: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    RequirementMissing { name: String },
+    RequirementAmbiguous { name: String },
+    VersionInvalid { name: String, version: String },
+    BranchEmpty { name: String },
+    ProductsEmpty { name: String },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RequirementMissing { name } => write!(
+                f,
+                "Package {:?} needs one of `exact`, `up-to-next-major`, or `branch`",
+                name
+            ),
+            Self::RequirementAmbiguous { name } => write!(
+                f,
+                "Package {:?} can only have one of `exact`, `up-to-next-major`, or `branch`",
+                name
+            ),
+            Self::VersionInvalid { name, version } => write!(
+                f,
+                "Package {:?} has version {:?}, which should look like \"1.2.3\"",
+                name, version
+            ),
+            Self::BranchEmpty { name } => write!(f, "Package {:?} has an empty branch", name),
+            Self::ProductsEmpty { name } => write!(
+                f,
+                "Package {:?} has no products; omit `products` to use the package name",
+                name
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Requirement {
+    Exact(String),
+    UpToNextMajor(String),
+    Branch(String),
+}
+
+impl Requirement {
+    fn from_raw(raw: &Raw) -> Result<Self, Error> {
+        let name = || raw.name.clone();
+        let version = |version: &String| {
+            if regex!(r"^\d+\.\d+\.\d+(?:-[0-9A-Za-z.-]+)?$").is_match(version) {
+                Ok(version.clone())
+            } else {
+                Err(Error::VersionInvalid {
+                    name: name(),
+                    version: version.clone(),
+                })
+            }
+        };
+        match (&raw.exact, &raw.up_to_next_major, &raw.branch) {
+            (Some(exact), None, None) => version(exact).map(Self::Exact),
+            (None, Some(major), None) => version(major).map(Self::UpToNextMajor),
+            (None, None, Some(branch)) if branch.trim().is_empty() => {
+                Err(Error::BranchEmpty { name: name() })
+            }
+            (None, None, Some(branch)) => Ok(Self::Branch(branch.clone())),
+            (None, None, None) => Err(Error::RequirementMissing { name: name() }),
+            _ => Err(Error::RequirementAmbiguous { name: name() }),
+        }
+    }
+
+    // The keys `xcodegen` uses for each kind of requirement.
+    fn xcodegen_key(&self) -> &'static str {
+        match self {
+            Self::Exact(_) => "exactVersion",
+            Self::UpToNextMajor(_) => "majorVersion",
+            Self::Branch(_) => "branch",
+        }
+    }
+
+    fn value(&self) -> &str {
+        match self {
+            Self::Exact(value) | Self::UpToNextMajor(value) | Self::Branch(value) => value,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Package {
+    name: String,
+    url: String,
+    requirement: Requirement,
+    products: Vec<String>,
+}
+
+impl Package {
+    pub fn from_raw(raw: Raw) -> Result<Self, Error> {
+        let requirement = Requirement::from_raw(&raw)?;
+        let products = match raw.products {
+            Some(products) if products.is_empty() => {
+                return Err(Error::ProductsEmpty { name: raw.name })
+            }
+            Some(products) => products,
+            None => vec![raw.name.clone()],
+        };
+        Ok(Self {
+            name: raw.name,
+            url: raw.url,
+            requirement,
+            products,
+        })
+    }
+}
+
+/// The top-level `packages` section, rendered as JSON for `xcodegen`.
+pub fn spec(packages: &[Package]) -> String {
+    JsonValue::Object(
+        packages
+            .iter()
+            .map(|package| {
+                let mut fields = Map::new();
+                fields.insert("url".to_owned(), json!(package.url));
+                fields.insert(
+                    package.requirement.xcodegen_key().to_owned(),
+                    json!(package.requirement.value()),
+                );
+                (package.name.clone(), JsonValue::Object(fields))
+            })
+            .collect(),
+    )
+    .to_string()
+}
+
+/// The target dependencies, each rendered as JSON for `xcodegen`.
+pub fn dependencies(packages: &[Package]) -> Vec<String> {
+    packages
+        .iter()
+        .flat_map(|package| {
+            package
+                .products
+                .iter()
+                .map(move |product| json!({ "package": package.name, "product": product }))
+        })
+        .map(|dependency| dependency.to_string())
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum ResolveError {
+    ResolutionFailed {
+        packages: Vec<String>,
+        details: Vec<String>,
+    },
+    CommandFailed(bossy::Error),
+}
+
+impl Reportable for ResolveError {
+    fn report(&self) -> Report {
+        match self {
+            Self::ResolutionFailed { packages, details } => Report::error(
+                if packages.is_empty() {
+                    "Failed to resolve Swift packages".to_owned()
+                } else {
+                    format!("Failed to resolve Swift package(s) {}", packages.join(", "))
+                },
+                if details.is_empty() {
+                    "Check each package's `url` and version requirement in `apple.packages`."
+                        .to_owned()
+                } else {
+                    details.join("\n")
+                },
+            ),
+            Self::CommandFailed(err) => Report::error("Failed to resolve Swift packages", err),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ResolutionFailed { .. } => "apple.packages.resolve.resolution-failed",
+            Self::CommandFailed(..) => "apple.packages.resolve.command-failed",
+        }
+    }
+}
+
+// Returns the names of the packages mentioned by the error, along with the
+// error lines themselves.
+fn find_failure(packages: &[Package], output: &str) -> (Vec<String>, Vec<String>) {
+    let details = output
+        .lines()
+        .skip_while(|line| !line.contains("Could not resolve package dependencies"))
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    let names = packages
+        .iter()
+        .filter(|package| {
+            let url = package.url.trim_end_matches(".git");
+            details.iter().any(|line| {
+                line.contains(url)
+                    || regex!(r"'([^']+)'")
+                        .captures_iter(line)
+                        .any(|caps| caps[1] == package.name)
+            })
+        })
+        .map(|package| package.name.clone())
+        .collect();
+    (names, details)
+}
+
+/// Resolves the packages up front, since `xcodebuild` otherwise buries
+/// resolution failures in the middle of the build output.
+pub fn resolve(config: &Config, env: &Env) -> Result<(), ResolveError> {
+    if config.packages().is_empty() {
+        return Ok(());
+    }
+    ui::status("Resolving Swift packages...");
+    bossy::Command::pure("xcodebuild")
+        .with_env_vars(env.explicit_env())
+        .with_arg("-resolvePackageDependencies")
+        .with_arg("-workspace")
+        .with_arg(&config.workspace_path())
+        .with_args(&["-scheme", &config.scheme()])
+        .run_and_wait_for_output()
+        .map(|_| ())
+        .map_err(|err| {
+            let output = err
+                .stderr_str()
+                .and_then(Result::ok)
+                .map(ToOwned::to_owned)
+                .unwrap_or_default();
+            if output.contains("Could not resolve package dependencies") {
+                let (packages, details) = find_failure(config.packages(), &output);
+                ResolveError::ResolutionFailed { packages, details }
+            } else {
+                ResolveError::CommandFailed(err)
+            }
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn raw(exact: Option<&str>, major: Option<&str>, branch: Option<&str>) -> Raw {
+        Raw {
+            name: "Alamofire".to_owned(),
+            url: "https://github.com/Alamofire/Alamofire".to_owned(),
+            exact: exact.map(ToOwned::to_owned),
+            up_to_next_major: major.map(ToOwned::to_owned),
+            branch: branch.map(ToOwned::to_owned),
+            products: None,
+        }
+    }
+
+    #[rstest(
+        exact,
+        major,
+        branch,
+        expected,
+        case(Some("5.4.1"), None, None, Some(Requirement::Exact("5.4.1".to_owned()))),
+        case(None, Some("5.0.0"), None, Some(Requirement::UpToNextMajor("5.0.0".to_owned()))),
+        case(None, None, Some("main"), Some(Requirement::Branch("main".to_owned()))),
+        case(None, Some("5"), None, None),
+        case(None, None, Some(" "), None),
+        case(Some("5.4.1"), None, Some("main"), None),
+        case(None, None, None, None)
+    )]
+    fn test_requirement(
+        exact: Option<&str>,
+        major: Option<&str>,
+        branch: Option<&str>,
+        expected: Option<Requirement>,
+    ) {
+        assert_eq!(
+            Requirement::from_raw(&raw(exact, major, branch)).ok(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_find_failure() {
+        let package = Package::from_raw(raw(None, Some("5.0.0"), None)).unwrap();
+        let output = "xcodebuild: error: Could not resolve package dependencies:\n  Failed to clone repository https://github.com/Alamofire/Alamofire:\n    fatal: repository not found\n";
+        assert_eq!(
+            find_failure(&[package], output),
+            (
+                vec!["Alamofire".to_owned()],
+                vec![
+                    "Failed to clone repository https://github.com/Alamofire/Alamofire:".to_owned(),
+                    "fatal: repository not found".to_owned(),
+                ]
+            )
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use super::config::Config;
+use crate::{
+    ui,
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+static PODFILE_HEADER: &str =
+    "# Generated by cargo-mobile from `apple.pods`; any changes will be overwritten!";
+
+// Added to the app target by `pod install`, so its absence means the
+// integration was lost when `xcodegen` regenerated the project.
+static INTEGRATION_MARKER: &str = "[CP] Check Pods Manifest.lock";
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Pod {
+    pub name: String,
+    /// Any requirement CocoaPods understands, e.g. `"~> 8.0"`.
+    pub version: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    PresenceCheckFailed(bossy::Error),
+    PodMissing,
+    ReadFailed { path: PathBuf, cause: io::Error },
+    WriteFailed { path: PathBuf, cause: io::Error },
+    RemoveFailed { path: PathBuf, cause: io::Error },
+    InstallFailed(bossy::Error),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::PresenceCheckFailed(err) => Report::error("Failed to check for `pod`", err),
+            Self::PodMissing => Report::action_request(
+                "CocoaPods isn't installed, but `apple.pods` isn't empty",
+                "Install it with `brew install cocoapods` (or `sudo gem install cocoapods`), then run `cargo mobile init` again.",
+            ),
+            Self::ReadFailed { path, cause } => Report::error(
+                "Failed to read Podfile",
+                format!("Failed to read {:?}: {}", path, cause),
+            ),
+            Self::WriteFailed { path, cause } => Report::error(
+                "Failed to write Podfile",
+                format!("Failed to write {:?}: {}", path, cause),
+            ),
+            Self::RemoveFailed { path, cause } => Report::error(
+                "Failed to remove Podfile",
+                format!("Failed to remove {:?}: {}", path, cause),
+            ),
+            Self::InstallFailed(err) => Report::error("Failed to run `pod install`", err),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::PresenceCheckFailed(..) => "apple.pods.presence-check-failed",
+            Self::PodMissing => "apple.pods.pod-missing",
+            Self::ReadFailed { .. } => "apple.pods.read-failed",
+            Self::WriteFailed { .. } => "apple.pods.write-failed",
+            Self::RemoveFailed { .. } => "apple.pods.remove-failed",
+            Self::InstallFailed(..) => "apple.pods.install-failed",
+        }
+    }
+}
+
+// The Podfile is Ruby, and single-quoted strings there only have these two
+// escapes, so nothing from the config can end the string early.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn podfile(app_name: &str, deployment_target: &str, pods: &[Pod]) -> String {
+    let mut contents = format!(
+        "{}\nplatform :ios, {}\n\ntarget {} do\n",
+        PODFILE_HEADER,
+        quote(deployment_target),
+        quote(&format!("{}_iOS", app_name))
+    );
+    for pod in pods {
+        match &pod.version {
+            Some(version) => {
+                contents.push_str(&format!("  pod {}, {}\n", quote(&pod.name), quote(version)))
+            }
+            None => contents.push_str(&format!("  pod {}\n", quote(&pod.name))),
+        }
+    }
+    contents.push_str("end\n");
+    contents
+}
+
+fn read_if_exists(path: &Path) -> Result<Option<String>, Error> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(cause) if cause.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(cause) => Err(Error::ReadFailed {
+            path: path.to_owned(),
+            cause,
+        }),
+    }
+}
+
+/// Keeps the Podfile in sync with `apple.pods`, and runs `pod install` if
+/// the Podfile changed or the project lost its CocoaPods integration. This
+/// has to run after `xcodegen`, since that regenerates the project from
+/// scratch.
+pub fn sync(config: &Config) -> Result<(), Error> {
+    let path = config.podfile_path();
+    let old = read_if_exists(&path)?;
+    if !config.uses_pods() {
+        // We only clean up Podfiles that we wrote ourselves.
+        if old
+            .map(|old| old.starts_with(PODFILE_HEADER))
+            .unwrap_or_default()
+        {
+            log::info!("removing Podfile {:?}, since `apple.pods` is empty", path);
+            fs::remove_file(&path).map_err(|cause| Error::RemoveFailed { path, cause })?;
+        }
+        return Ok(());
+    }
+    if !util::command_present("pod").map_err(Error::PresenceCheckFailed)? {
+        return Err(Error::PodMissing);
+    }
+    let new = podfile(
+        config.product_name(),
+        config.ios_deployment_target(),
+        config.pods(),
+    );
+    let changed = old.as_deref() != Some(new.as_str());
+    if changed {
+        fs::write(&path, &new).map_err(|cause| Error::WriteFailed {
+            path: path.clone(),
+            cause,
+        })?;
+    }
+    let integrated = fs::read_to_string(config.pbxproj_path())
+        .map(|pbxproj| pbxproj.contains(INTEGRATION_MARKER))
+        .unwrap_or_default();
+    if changed || !integrated {
+        ui::status("Installing pods...");
+        // CLAide only takes option values after an `=`.
+        let mut project_directory = std::ffi::OsString::from("--project-directory=");
+        project_directory.push(config.project_dir());
+        bossy::Command::impure_parse("pod install")
+            .with_arg(project_directory)
+            .run_and_wait()
+            .map_err(Error::InstallFailed)?;
+    } else {
+        log::info!("Podfile unchanged and pods already integrated; skipping `pod install`");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_podfile() {
+        let pods = vec![
+            Pod {
+                name: "Sentry".to_owned(),
+                version: Some("~> 8.0".to_owned()),
+            },
+            Pod {
+                name: "Firebase/Crashlytics".to_owned(),
+                version: None,
+            },
+        ];
+        assert_eq!(
+            podfile("foo", "13.0", &pods),
+            format!(
+                "{}\nplatform :ios, '13.0'\n\ntarget 'foo_iOS' do\n  pod 'Sentry', '~> 8.0'\n  pod 'Firebase/Crashlytics'\nend\n",
+                PODFILE_HEADER
+            ),
+        );
+    }
+
+    #[rstest(
+        s,
+        expected,
+        case("Sentry", "'Sentry'"),
+        case("it's", r"'it\'s'"),
+        case(r"a\'; system('x')", r"'a\\\'; system(\'x\')'")
+    )]
+    fn test_quote(s: &str, expected: &str) {
+        assert_eq!(quote(s), expected);
+    }
+}
@@ -0,0 +1,388 @@
+use super::config::{Config, SigningStyle};
+use crate::{
+    ui,
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use serde::Deserialize;
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+static PROFILES_HINT: &str = "You can download profiles from the Apple Developer portal, or in Xcode under Preferences > Accounts > Download Manual Profiles. Run `cargo apple profiles` to see what's installed.";
+
+const EXPIRY_WARNING_DAYS: i64 = 7;
+
+const DAY: u64 = 60 * 60 * 24;
+
+// Xcode 16 moved these, but older versions still put them in the old place.
+fn profile_dirs() -> Result<Vec<PathBuf>, util::NoHomeDir> {
+    util::home_dir().map(|home| {
+        vec![
+            home.join("Library/MobileDevice/Provisioning Profiles"),
+            home.join("Library/Developer/Xcode/UserData/Provisioning Profiles"),
+        ]
+    })
+}
+
+// Negative once it's expired; a profile that expires later today still has 0
+// days left.
+fn days_left(expires: SystemTime, now: SystemTime) -> i64 {
+    match expires.duration_since(now) {
+        Ok(left) => (left.as_secs() / DAY) as i64,
+        Err(err) => -((err.duration().as_secs() / DAY) as i64) - 1,
+    }
+}
+
+// App IDs are prefixed with the team ID, and can end with a wildcard.
+fn app_id_matches(app_id: &str, team_id: &str, bundle_id: &str) -> bool {
+    app_id
+        .strip_prefix(team_id)
+        .and_then(|app_id| app_id.strip_prefix('.'))
+        .map(|app_id| match app_id.strip_suffix('*') {
+            Some(prefix) => bundle_id.starts_with(prefix),
+            None => app_id == bundle_id,
+        })
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Deserialize)]
+struct Entitlements {
+    #[serde(rename = "application-identifier")]
+    application_identifier: String,
+    #[serde(rename = "get-task-allow", default)]
+    get_task_allow: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Raw {
+    name: String,
+    #[serde(rename = "UUID")]
+    uuid: String,
+    team_identifier: Vec<String>,
+    expiration_date: plist::Date,
+    #[serde(default)]
+    provisioned_devices: Vec<String>,
+    #[serde(default)]
+    provisions_all_devices: bool,
+    entitlements: Entitlements,
+}
+
+#[derive(Debug)]
+pub struct Profile {
+    pub name: String,
+    pub uuid: String,
+    pub app_id: String,
+    pub team_ids: Vec<String>,
+    pub expires: SystemTime,
+    pub devices: Vec<String>,
+    pub all_devices: bool,
+    /// Only development profiles let debuggers attach.
+    pub get_task_allow: bool,
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}], app ID {}", self.name, self.uuid, self.app_id)?;
+        match self.days_left() {
+            days if days < 0 => write!(f, ", expired")?,
+            days => write!(f, ", expires in {} days", days)?,
+        }
+        if self.all_devices {
+            write!(f, ", all devices")
+        } else {
+            write!(f, ", {} devices", self.devices.len())
+        }
+    }
+}
+
+impl From<Raw> for Profile {
+    fn from(raw: Raw) -> Self {
+        Self {
+            name: raw.name,
+            uuid: raw.uuid,
+            app_id: raw.entitlements.application_identifier,
+            team_ids: raw.team_identifier,
+            expires: raw.expiration_date.into(),
+            devices: raw.provisioned_devices,
+            all_devices: raw.provisions_all_devices,
+            get_task_allow: raw.entitlements.get_task_allow,
+        }
+    }
+}
+
+impl Profile {
+    pub fn days_left(&self) -> i64 {
+        days_left(self.expires, SystemTime::now())
+    }
+
+    fn matches(&self, config: &Config) -> bool {
+        let team_id = config.development_team();
+        self.team_ids.iter().any(|id| id == team_id)
+            && app_id_matches(&self.app_id, team_id, &config.bundle_id())
+    }
+
+    fn includes_device(&self, udid: &str) -> bool {
+        self.all_devices || self.devices.iter().any(|device| device == udid)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    NoHomeDir(util::NoHomeDir),
+    DirReadFailed { dir: PathBuf, cause: io::Error },
+    ReadFailed { path: PathBuf, cause: io::Error },
+    DecodeFailed { path: PathBuf, cause: bossy::Error },
+    ParseFailed { path: PathBuf, cause: plist::Error },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoHomeDir(err) => write!(f, "{}", err),
+            Self::DirReadFailed { dir, cause } => {
+                write!(f, "Failed to read profiles directory {:?}: {}", dir, cause)
+            }
+            Self::ReadFailed { path, cause } => {
+                write!(f, "Failed to read profile {:?}: {}", path, cause)
+            }
+            Self::DecodeFailed { path, cause } => {
+                write!(f, "Failed to decode profile {:?}: {}", path, cause)
+            }
+            Self::ParseFailed { path, cause } => {
+                write!(f, "Failed to parse profile {:?}: {}", path, cause)
+            }
+        }
+    }
+}
+
+// Profiles are signed CMS blobs wrapping a plist, so `security` needs to peel
+// off the signature first.
+fn decode(path: &Path) -> Result<Profile, Error> {
+    let output = bossy::Command::impure("security")
+        .with_args(&["cms", "-D", "-i"])
+        .with_arg(path)
+        .run_and_wait_for_output()
+        .map_err(|cause| Error::DecodeFailed {
+            path: path.to_owned(),
+            cause,
+        })?;
+    plist::from_bytes::<Raw>(output.stdout())
+        .map(Profile::from)
+        .map_err(|cause| Error::ParseFailed {
+            path: path.to_owned(),
+            cause,
+        })
+}
+
+// The plist inside a profile is stored as-is, so we can usually find it
+// without `security`.
+fn embedded_plist(bytes: &[u8]) -> Option<&[u8]> {
+    let find = |needle: &[u8]| bytes.windows(needle.len()).position(|w| w == needle);
+    let start = find(b"<?xml")?;
+    let end = find(b"</plist>")? + b"</plist>".len();
+    bytes.get(start..end)
+}
+
+/// Reads the profile an app was signed with, i.e. its `embedded.mobileprovision`.
+pub fn read_embedded(path: &Path) -> Result<Profile, Error> {
+    let bytes = fs::read(path).map_err(|cause| Error::ReadFailed {
+        path: path.to_owned(),
+        cause,
+    })?;
+    match embedded_plist(&bytes) {
+        Some(plist) => plist::from_bytes::<Raw>(plist)
+            .map(Profile::from)
+            .map_err(|cause| Error::ParseFailed {
+                path: path.to_owned(),
+                cause,
+            }),
+        None => decode(path),
+    }
+}
+
+pub fn find_profiles() -> Result<Vec<Profile>, Error> {
+    let mut profiles = Vec::new();
+    for dir in profile_dirs().map_err(Error::NoHomeDir)? {
+        if !dir.is_dir() {
+            continue;
+        }
+        let entries = fs::read_dir(&dir).map_err(|cause| Error::DirReadFailed {
+            dir: dir.clone(),
+            cause,
+        })?;
+        for entry in entries {
+            let path = entry
+                .map_err(|cause| Error::DirReadFailed {
+                    dir: dir.clone(),
+                    cause,
+                })?
+                .path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("mobileprovision") {
+                profiles.push(decode(&path)?);
+            }
+        }
+    }
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(profiles)
+}
+
+#[derive(Debug)]
+pub enum CheckError {
+    Expired { profile: Profile },
+    DeviceMissing { udid: String, profiles: Vec<String> },
+}
+
+impl Reportable for CheckError {
+    fn report(&self) -> Report {
+        match self {
+            Self::Expired { profile } => Report::action_request(
+                format!("Provisioning profile {:?} has expired", profile.name),
+                PROFILES_HINT,
+            ),
+            Self::DeviceMissing { udid, profiles } => Report::action_request(
+                format!(
+                    "Device {:?} isn't included in any provisioning profile for this app",
+                    udid
+                ),
+                format!(
+                    "Checked {}. Add the device in the Apple Developer portal, then download the updated profile. {}",
+                    profiles.join(", "),
+                    PROFILES_HINT
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Expired { .. } => "apple.profiles.check.expired",
+            Self::DeviceMissing { .. } => "apple.profiles.check.device-missing",
+        }
+    }
+}
+
+/// With manual signing, `xcodebuild` only reports profile problems once it's
+/// done building; automatic signing fixes them on its own, so this does
+/// nothing there. Pass `udid` when running on a device, so that we can make
+/// sure it's provisioned.
+pub fn check(config: &Config, udid: Option<&str>) -> Result<(), CheckError> {
+    if config.signing_style() != SigningStyle::Manual {
+        return Ok(());
+    }
+    let profiles = match find_profiles() {
+        Ok(profiles) => profiles,
+        Err(err) => {
+            // This is just a heads-up, so it's not worth stopping the build.
+            ui::report(&Report::action_request(
+                "Failed to check provisioning profiles",
+                err,
+            ));
+            return Ok(());
+        }
+    };
+    let mut candidates = profiles
+        .into_iter()
+        .filter(|profile| profile.matches(config))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        ui::report(&Report::action_request(
+            format!(
+                "No provisioning profile for {:?} was found",
+                config.bundle_id()
+            ),
+            PROFILES_HINT,
+        ));
+        return Ok(());
+    }
+    if let Some(udid) = udid {
+        let names = candidates
+            .iter()
+            .map(|profile| format!("{:?}", profile.name))
+            .collect();
+        candidates.retain(|profile| profile.includes_device(udid));
+        if candidates.is_empty() {
+            return Err(CheckError::DeviceMissing {
+                udid: udid.to_owned(),
+                profiles: names,
+            });
+        }
+    }
+    // If there's more than one, the one that lasts longest is the best bet.
+    let profile = candidates
+        .into_iter()
+        .max_by_key(|profile| profile.expires)
+        .expect("developer error: no candidate profiles left");
+    match profile.days_left() {
+        days if days < 0 => Err(CheckError::Expired { profile }),
+        days if days < EXPIRY_WARNING_DAYS => {
+            ui::report(&Report::action_request(
+                format!(
+                    "Provisioning profile {:?} expires in {} days",
+                    profile.name, days
+                ),
+                PROFILES_HINT,
+            ));
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+    use std::time::Duration;
+
+    #[rstest(
+        app_id,
+        expected,
+        case("ABCDE12345.com.example.foo", true),
+        case("ABCDE12345.com.example.*", true),
+        case("ABCDE12345.*", true),
+        case("ABCDE12345.com.example.bar", false),
+        case("ZYXWV54321.com.example.foo", false),
+        case("ABCDE12345com.example.foo", false)
+    )]
+    fn test_app_id_matches(app_id: &str, expected: bool) {
+        assert_eq!(
+            app_id_matches(app_id, "ABCDE12345", "com.example.foo"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_embedded_plist() {
+        let plist = b"<?xml version=\"1.0\"?><plist><dict/></plist>";
+        let mut bytes = b"0\x80\x06\x09*\x86H".to_vec();
+        bytes.extend_from_slice(plist);
+        bytes.extend_from_slice(b"\xa0\x82\x0b");
+        assert_eq!(embedded_plist(&bytes), Some(&plist[..]));
+        assert_eq!(embedded_plist(b"\x30\x80"), None);
+    }
+
+    #[rstest(
+        offset_secs,
+        expected,
+        case(10 * DAY as i64 + 60, 10),
+        case(60, 0),
+        case(-60, -1),
+        case(-(2 * DAY as i64) - 60, -3)
+    )]
+    fn test_days_left(offset_secs: i64, expected: i64) {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100 * DAY);
+        let expires = if offset_secs < 0 {
+            now - Duration::from_secs(-offset_secs as u64)
+        } else {
+            now + Duration::from_secs(offset_secs as u64)
+        };
+        assert_eq!(days_left(expires, now), expected);
+    }
+}
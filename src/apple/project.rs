@@ -1,19 +1,27 @@
 use super::{
     config::{Config, Metadata},
-    deps, rust_version_check,
+    deps, icons, packages, pods, rust_version_check,
+    sources::{self, Sources},
     target::Target,
 };
 use crate::{
-    opts,
+    assets, opts,
+    plan::Writer,
     target::TargetTrait as _,
     templating::{self, Pack},
+    ui,
     util::{
         self,
         cli::{Report, Reportable, TextWrapper},
         ln,
+        progress::{self, Phase},
     },
+    version::{self, Version},
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
 };
-use std::path::{Path, PathBuf};
 
 pub static TEMPLATE_PACK: &str = "xcode";
 
@@ -22,10 +30,15 @@ pub enum Error {
     RustupFailed(bossy::Error),
     RustVersionCheckFailed(util::RustVersionError),
     DepsInstallFailed(deps::Error),
+    SourcesInvalid(sources::Error),
     MissingPack(templating::LookupError),
-    TemplateProcessingFailed(bicycle::ProcessingError),
+    TemplateProcessingFailed(templating::ProcessingError),
     AssetDirSymlinkFailed(ln::Error),
+    AssetIgnoreFailed(assets::Error),
+    IconsFailed(icons::Error),
+    VersionFailed(version::Error),
     XcodegenFailed(bossy::Error),
+    PodsFailed(pods::Error),
 }
 
 impl Reportable for Error {
@@ -36,6 +49,7 @@ impl Reportable for Error {
             Self::DepsInstallFailed(err) => {
                 Report::error("Failed to install Apple dependencies", err)
             }
+            Self::SourcesInvalid(err) => err.report(),
             Self::MissingPack(err) => Report::error("Failed to locate Xcode template pack", err),
             Self::TemplateProcessingFailed(err) => {
                 Report::error("Xcode template processing failed", err)
@@ -43,64 +57,212 @@ impl Reportable for Error {
             Self::AssetDirSymlinkFailed(err) => {
                 Report::error("Asset dir couldn't be symlinked into Xcode project", err)
             }
+            Self::AssetIgnoreFailed(err) => err.report(),
+            Self::IconsFailed(err) => err.report(),
+            Self::VersionFailed(err) => err.report(),
             Self::XcodegenFailed(err) => Report::error("Failed to run `xcodegen`", err),
+            Self::PodsFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::RustupFailed(..) => "apple.project.rustup-failed",
+            Self::RustVersionCheckFailed(err) => err.code(),
+            Self::DepsInstallFailed(..) => "apple.project.deps-install-failed",
+            Self::SourcesInvalid(err) => err.code(),
+            Self::MissingPack(..) => "apple.project.missing-pack",
+            Self::TemplateProcessingFailed(..) => "apple.project.template-processing-failed",
+            Self::AssetDirSymlinkFailed(..) => "apple.project.asset-dir-symlink-failed",
+            Self::AssetIgnoreFailed(err) => err.code(),
+            Self::IconsFailed(err) => err.code(),
+            Self::VersionFailed(err) => err.code(),
+            Self::XcodegenFailed(..) => "apple.project.xcodegen-failed",
+            Self::PodsFailed(err) => err.code(),
         }
     }
 }
 
-// unprefixed app_root seems pretty dangerous!!
-// TODO: figure out what I meant by that
-pub fn gen(
+// Escapes everything in `glob` that the build phase's shell would otherwise
+// take as something other than part of a glob.
+fn shell_glob(glob: &str) -> String {
+    glob.chars().fold(String::new(), |mut escaped, c| {
+        if !(c.is_ascii_alphanumeric() || "*?[]/._-".contains(c)) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+/// Renders the project's templates into `out_dir` as though it were the
+/// project dir, without any of the rest of what generating the project
+/// involves.
+pub fn render(
     config: &Config,
     metadata: &Metadata,
+    version: &Version,
     submodule_path: Option<&Path>,
     bike: &bicycle::Bicycle,
-    wrapper: &TextWrapper,
-    non_interactive: opts::NonInteractive,
-    skip_dev_tools: opts::SkipDevTools,
-    reinstall_deps: opts::ReinstallDeps,
     filter: &templating::Filter,
+    out_dir: &Path,
+    writer: Writer,
 ) -> Result<(), Error> {
-    println!("Installing iOS toolchains...");
-    Target::install_all().map_err(Error::RustupFailed)?;
-    rust_version_check(wrapper).map_err(Error::RustVersionCheckFailed)?;
-
-    deps::install(wrapper, non_interactive, skip_dev_tools, reinstall_deps)
-        .map_err(Error::DepsInstallFailed)?;
-
     let dest = config.project_dir();
     let rel_prefix = util::relativize_path(config.app().root_dir(), &dest);
     let source_dirs = std::iter::once("src".as_ref())
         .chain(submodule_path)
         .map(|path| rel_prefix.join(path))
         .collect::<Vec<PathBuf>>();
+    let extra_sources = Sources::from_config(config).map_err(Error::SourcesInvalid)?;
+    let ignore = assets::Ignore::load(config.app()).map_err(Error::AssetIgnoreFailed)?;
 
     let src = Pack::lookup_platform(TEMPLATE_PACK)
         .map_err(Error::MissingPack)?
         .expect_local();
 
-    bike.filter_and_process(
-        src,
-        &dest,
+    templating::process(
+        bike,
+        &src,
+        out_dir,
         |map| {
             map.insert("file-groups", &source_dirs);
             map.insert("ios-frameworks", metadata.ios().frameworks());
             map.insert("macos-frameworks", metadata.macos().frameworks());
+            map.insert(
+                "ios-info-plist",
+                config
+                    .info_plist()
+                    .ios(version, config.app().build_config_keys()),
+            );
+            map.insert("version", version);
+            map.insert("ios-extra-sources", extra_sources.spec(&dest));
+            map.insert(
+                "ignored-assets",
+                ignore
+                    .packaged_globs()
+                    .iter()
+                    .map(|glob| shell_glob(glob))
+                    .collect::<Vec<_>>(),
+            );
+            map.insert(
+                "xcode-configurations",
+                config
+                    .configurations()
+                    .iter()
+                    .map(|(configuration, profile)| (configuration.as_str(), profile.as_str()))
+                    .collect::<BTreeMap<_, _>>(),
+            );
+            if let Some(bridging_header) = extra_sources.bridging_header(&dest) {
+                map.insert("ios-bridging-header", bridging_header);
+            }
+            if !config.packages().is_empty() {
+                map.insert("swift-packages", packages::spec(config.packages()));
+                map.insert(
+                    "swift-package-dependencies",
+                    packages::dependencies(config.packages()),
+                );
+            }
+            if !config.entitlements().is_empty() {
+                map.insert("ios-entitlements", config.entitlements().properties());
+                map.insert(
+                    "ios-system-capabilities",
+                    config.entitlements().system_capabilities(),
+                );
+            }
         },
-        filter.fun(),
+        filter,
+        writer,
     )
-    .map_err(Error::TemplateProcessingFailed)?;
-
-    ln::force_symlink_relative(config.app().asset_dir(), &dest, ln::TargetStyle::Directory)
-        .map_err(Error::AssetDirSymlinkFailed)?;
+    .map_err(Error::TemplateProcessingFailed)
+}
 
+/// Generates the Xcode project from the `project.yml` that rendering left in
+/// the project dir.
+pub fn xcodegen(config: &Config) -> Result<(), Error> {
     // Note that Xcode doesn't always reload the project nicely; reopening is
     // often necessary.
-    println!("Generating Xcode project...");
+    ui::status("Generating Xcode project...");
     bossy::Command::impure("xcodegen")
         .with_args(&["generate", "--spec"])
-        .with_arg(dest.join("project.yml"))
+        .with_arg(config.project_dir().join("project.yml"))
         .run_and_wait()
-        .map_err(Error::XcodegenFailed)?;
+        .map_err(Error::XcodegenFailed)
+}
+
+// unprefixed app_root seems pretty dangerous!!
+// TODO: figure out what I meant by that
+pub fn gen(
+    config: &Config,
+    metadata: &Metadata,
+    version: &Version,
+    submodule_path: Option<&Path>,
+    bike: &bicycle::Bicycle,
+    wrapper: &TextWrapper,
+    non_interactive: opts::NonInteractive,
+    skip_dev_tools: opts::SkipDevTools,
+    reinstall_deps: opts::ReinstallDeps,
+    filter: &templating::Filter,
+    writer: Writer,
+) -> Result<(), Error> {
+    ui::status("Installing iOS toolchains...");
+    writer
+        .run("install iOS toolchains with `rustup`", || {
+            progress::phase(Phase::RustupTargets, None, || {
+                for target in Target::all().values() {
+                    target.install_for(config)?;
+                }
+                if let Some(target) = Target::desktop(config.desktop()) {
+                    target.install_for(config)?;
+                }
+                Ok(())
+            })
+        })
+        .map_err(Error::RustupFailed)?;
+    rust_version_check(wrapper).map_err(Error::RustVersionCheckFailed)?;
+
+    writer
+        .run("install Apple dependencies", || {
+            progress::phase(Phase::AppleDeps, None, || {
+                deps::install(wrapper, non_interactive, skip_dev_tools, reinstall_deps)
+            })
+        })
+        .map_err(Error::DepsInstallFailed)?;
+
+    let dest = config.project_dir();
+    version
+        .check_and_record(&dest, writer)
+        .map_err(Error::VersionFailed)?;
+    render(
+        config,
+        metadata,
+        version,
+        submodule_path,
+        bike,
+        filter,
+        &dest,
+        writer,
+    )?;
+
+    let asset_dir = config.app().asset_dir();
+    writer
+        .link(
+            &asset_dir,
+            &dest.join(asset_dir.file_name().unwrap_or_default()),
+            || ln::force_symlink_relative(&asset_dir, &dest, ln::TargetStyle::Directory),
+        )
+        .map_err(Error::AssetDirSymlinkFailed)?;
+
+    writer
+        .run("generate iOS app icons", || icons::gen(config, false))
+        .map_err(Error::IconsFailed)?;
+    writer.claim(&config.asset_catalog_dir());
+    config.entitlements().warn_about_provisioning(wrapper);
+
+    writer.run("run `xcodegen generate`", || xcodegen(config))?;
+    writer.claim(&config.xcodeproj_path());
+    writer
+        .run("sync CocoaPods", || pods::sync(config))
+        .map_err(Error::PodsFailed)?;
     Ok(())
 }
@@ -0,0 +1,158 @@
+use super::config::Config;
+use crate::{
+    env::{Env, ExplicitEnv as _},
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use serde::Deserialize;
+
+#[derive(Debug)]
+pub enum Error {
+    ListFailed(bossy::Error),
+    ListInvalid(serde_json::Error),
+    SchemeMissing {
+        scheme: String,
+        suggestion: Option<String>,
+        schemes: Vec<String>,
+    },
+    ConfigurationMissing {
+        configuration: String,
+        suggestion: Option<String>,
+        configurations: Vec<String>,
+    },
+}
+
+fn missing(kind: &str, name: &str, suggestion: &Option<String>, available: &[String]) -> Report {
+    Report::error(
+        format!("The Xcode project has no {} named {:?}", kind, name),
+        match suggestion {
+            Some(suggestion) => format!("Did you mean {:?}?", suggestion),
+            None => format!("Available {}s: {}", kind, available.join(", ")),
+        },
+    )
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ListFailed(err) => Report::error("Failed to list Xcode schemes", err),
+            Self::ListInvalid(err) => {
+                Report::error("`xcodebuild -list` output couldn't be parsed", err)
+            }
+            Self::SchemeMissing {
+                scheme,
+                suggestion,
+                schemes,
+            } => missing("scheme", scheme, suggestion, schemes),
+            Self::ConfigurationMissing {
+                configuration,
+                suggestion,
+                configurations,
+            } => missing("configuration", configuration, suggestion, configurations),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ListFailed(..) => "apple.schemes.list-failed",
+            Self::ListInvalid(..) => "apple.schemes.list-invalid",
+            Self::SchemeMissing { .. } => "apple.schemes.scheme-missing",
+            Self::ConfigurationMissing { .. } => "apple.schemes.configuration-missing",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Project {
+    #[serde(default)]
+    configurations: Vec<String>,
+    #[serde(default)]
+    schemes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct List {
+    project: Project,
+}
+
+fn suggest(name: &str, available: &[String]) -> Option<String> {
+    util::suggest(name, available.iter().map(String::as_str)).map(ToOwned::to_owned)
+}
+
+fn check_name(
+    name: Option<&str>,
+    available: &[String],
+    err: impl FnOnce(String, Option<String>) -> Error,
+) -> Result<(), Error> {
+    match name {
+        Some(name) if !available.iter().any(|candidate| candidate == name) => {
+            Err(err(name.to_owned(), suggest(name, available)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Makes sure a scheme and configuration picked on the command line exist,
+/// since `xcodebuild` only notices after resolving everything else.
+pub fn check(
+    config: &Config,
+    env: &Env,
+    scheme: Option<&str>,
+    configuration: Option<&str>,
+) -> Result<(), Error> {
+    if scheme.is_none() && configuration.is_none() {
+        return Ok(());
+    }
+    let output = bossy::Command::pure("xcodebuild")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["-list", "-json", "-project"])
+        .with_arg(config.xcodeproj_path())
+        .run_and_wait_for_str(|output| output.to_owned())
+        .map_err(Error::ListFailed)?;
+    let Project {
+        configurations,
+        schemes,
+    } = serde_json::from_str::<List>(&output)
+        .map_err(Error::ListInvalid)?
+        .project;
+    check_name(scheme, &schemes, |scheme, suggestion| {
+        Error::SchemeMissing {
+            scheme,
+            suggestion,
+            schemes: schemes.clone(),
+        }
+    })?;
+    check_name(
+        configuration,
+        &configurations,
+        |configuration, suggestion| Error::ConfigurationMissing {
+            configuration,
+            suggestion,
+            configurations: configurations.clone(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        name,
+        expected,
+        case("Stagign", Some("Staging")),
+        case("staging", Some("Staging")),
+        case("relase", Some("release")),
+        case("Production", None)
+    )]
+    fn test_suggest(name: &str, expected: Option<&str>) {
+        let available = ["debug", "release", "Staging"]
+            .iter()
+            .map(|name| (*name).to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(suggest(name, &available).as_deref(), expected);
+    }
+}
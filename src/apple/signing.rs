@@ -0,0 +1,79 @@
+use crate::util::cli::Report;
+use once_cell_regex::regex;
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Failure {
+    msg: String,
+    identity: Option<String>,
+    profile: Option<String>,
+}
+
+impl Failure {
+    /// Picks the first signing error out of `xcodebuild` output, along with
+    /// whatever identity/profile we can find mentioned anywhere in the output.
+    pub fn find(output: &str) -> Option<Self> {
+        let msg = output
+            .lines()
+            .map(str::trim)
+            .filter_map(|line| line.strip_prefix("error: "))
+            .map(|line| line.strip_prefix("exportArchive: ").unwrap_or(line))
+            .find(|line| {
+                regex!(r"(?i)sign|provisioning|profile|certificate|development team").is_match(line)
+            })?
+            .to_owned();
+        let identity = regex!(r#"[Ss]igning certificate (?:matching )?"([^"]+)""#)
+            .captures(output)
+            .map(|caps| caps[1].to_owned());
+        let profile = regex!(r#"(?:[Pp]rovisioning profile|[Nn]o profiles for) ["']([^"']+)["']"#)
+            .captures(output)
+            .map(|caps| caps[1].to_owned());
+        Some(Self {
+            msg,
+            identity,
+            profile,
+        })
+    }
+
+    pub fn report(&self, development_team: &str) -> Report {
+        let mut details = format!("{}\nDevelopment team: {}", self.msg, development_team);
+        if let Some(identity) = &self.identity {
+            details.push_str(&format!("\nIdentity: {}", identity));
+        }
+        if let Some(profile) = &self.profile {
+            details.push_str(&format!("\nProvisioning profile: {}", profile));
+        }
+        details.push_str("\nRun `cargo apple teams` to see which teams you have certificates for.");
+        Report::error("Code signing failed", details)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        output,
+        expected,
+        case(
+            r#"error: No signing certificate "iOS Development" found: No "iOS Development" signing certificate matching team ID "ABCDE12345" with a private key was found. (in target 'foo_iOS' from project 'foo')"#,
+            Some(Failure {
+                msg: r#"No signing certificate "iOS Development" found: No "iOS Development" signing certificate matching team ID "ABCDE12345" with a private key was found. (in target 'foo_iOS' from project 'foo')"#.to_owned(),
+                identity: Some("iOS Development".to_owned()),
+                profile: None,
+            })
+        ),
+        case(
+            "error: exportArchive: No profiles for 'com.example.foo' were found",
+            Some(Failure {
+                msg: "No profiles for 'com.example.foo' were found".to_owned(),
+                identity: None,
+                profile: Some("com.example.foo".to_owned()),
+            })
+        ),
+        case("error: linker command failed with exit code 1", None)
+    )]
+    fn test_find(output: &str, expected: Option<Failure>) {
+        assert_eq!(Failure::find(output), expected);
+    }
+}
@@ -0,0 +1,252 @@
+use super::{Runtime, Simulator, State};
+use crate::{
+    device::{self, ChooseError, Found},
+    env::{Env, ExplicitEnv as _},
+    opts::NonInteractive,
+    ui,
+    util::{
+        cli::{Report, Reportable},
+        prompt,
+    },
+};
+use serde::Deserialize;
+use std::{collections::BTreeMap, io};
+
+#[derive(Debug)]
+pub enum DeviceListError {
+    DetectionFailed(bossy::Error),
+    ParseFailed(serde_json::Error),
+}
+
+impl Reportable for DeviceListError {
+    fn report(&self) -> Report {
+        let msg = "Failed to detect iOS simulators";
+        match self {
+            Self::DetectionFailed(err) => Report::error(
+                msg,
+                format!("Failed to request simulator list from `simctl`: {}", err),
+            ),
+            Self::ParseFailed(err) => Report::error(
+                msg,
+                format!("Failed to parse simulator list from `simctl`: {}", err),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DetectionFailed(..) => "apple.simctl.device-list.device-list.detection-failed",
+            Self::ParseFailed(..) => "apple.simctl.device-list.device-list.parse-failed",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SimctlDevice {
+    udid: String,
+    name: String,
+    state: State,
+    #[serde(default)]
+    is_available: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SimctlDeviceList {
+    devices: BTreeMap<String, Vec<SimctlDevice>>,
+}
+
+fn parse_device_list(raw: &str) -> Result<Vec<Simulator>, serde_json::Error> {
+    let list = serde_json::from_str::<SimctlDeviceList>(raw)?;
+    let mut simulators = list
+        .devices
+        .into_iter()
+        .filter_map(|(identifier, devices)| {
+            // This is `None` for other platforms (e.g. watchOS)
+            Runtime::from_identifier(&identifier).map(|runtime| (runtime, devices))
+        })
+        .flat_map(|(runtime, devices)| {
+            devices
+                .into_iter()
+                .filter(|device| {
+                    if !device.is_available {
+                        log::info!("skipping unavailable simulator {:?}", device);
+                    }
+                    device.is_available
+                })
+                .map(move |device| Simulator {
+                    udid: device.udid,
+                    name: device.name,
+                    state: device.state,
+                    runtime,
+                })
+        })
+        .collect::<Vec<_>>();
+    simulators.sort();
+    Ok(simulators)
+}
+
+/// Lists available iOS simulators, in order of preference.
+pub fn device_list(env: &Env) -> Result<Vec<Simulator>, DeviceListError> {
+    bossy::Command::pure_parse("xcrun simctl list devices --json")
+        .with_env_vars(env.explicit_env())
+        .run_and_wait_for_str(parse_device_list)
+        .map_err(DeviceListError::DetectionFailed)?
+        .map_err(DeviceListError::ParseFailed)
+}
+
+#[derive(Debug)]
+pub enum SelectError {
+    ListFailed(DeviceListError),
+    NoneAvailable,
+    NoneBooted,
+    NotBooted {
+        simulator: Simulator,
+    },
+    NotFound {
+        query: String,
+        available: Vec<Simulator>,
+    },
+    PromptFailed(io::Error),
+    ChooseFailed(ChooseError),
+}
+
+impl Reportable for SelectError {
+    fn report(&self) -> Report {
+        let msg = "Failed to select iOS simulator";
+        match self {
+            Self::ListFailed(err) => err.report(),
+            Self::NoneAvailable => Report::action_request(
+                msg,
+                "No iOS simulators are available; you can create one in Xcode under Window > Devices and Simulators.",
+            ),
+            Self::NoneBooted => Report::action_request(
+                msg,
+                "No iOS simulator is booted; start your app with `cargo apple run --simulator` first.",
+            ),
+            Self::NotBooted { simulator } => Report::action_request(
+                msg,
+                format!(
+                    "{} isn't booted; start your app on it with `cargo apple run --simulator {:?}` first.",
+                    simulator, simulator.name
+                ),
+            ),
+            Self::NotFound { query, available } => Report::error(
+                msg,
+                format!(
+                    "No simulator has the name or UDID {:?}; available simulators are:\n{}",
+                    query,
+                    available
+                        .iter()
+                        .map(|simulator| format!("{} [{}]", simulator, simulator.udid))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                ),
+            ),
+            Self::PromptFailed(err) => Report::error(msg, err),
+            Self::ChooseFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ListFailed(..) => "apple.simctl.device-list.select.list-failed",
+            Self::NoneAvailable => "apple.simctl.device-list.select.none-available",
+            Self::NoneBooted => "apple.simctl.device-list.select.none-booted",
+            Self::NotBooted { .. } => "apple.simctl.device-list.select.not-booted",
+            Self::NotFound { .. } => "apple.simctl.device-list.select.not-found",
+            Self::PromptFailed(..) => "apple.simctl.device-list.select.prompt-failed",
+            Self::ChooseFailed(err) => err.code(),
+        }
+    }
+}
+
+// Simulators with the same name usually only differ by runtime, so which one
+// was meant is asked rather than guessed.
+fn find(
+    simulators: &[Simulator],
+    query: &str,
+    non_interactive: NonInteractive,
+) -> Result<Simulator, SelectError> {
+    match device::find(
+        simulators.iter().cloned(),
+        query,
+        Simulator::udid,
+        Simulator::name,
+    ) {
+        Found::One(simulator) => Ok(simulator),
+        Found::Several(matches) => device::choose(matches, query, non_interactive, Simulator::udid)
+            .map_err(SelectError::ChooseFailed),
+        Found::Nothing => Err(SelectError::NotFound {
+            query: query.to_owned(),
+            available: simulators.to_vec(),
+        }),
+    }
+}
+
+/// Picks a simulator by UDID (or the start of one) or name. Without a query, a booted simulator is
+/// used if there is one; otherwise, we prompt.
+pub fn select(
+    env: &Env,
+    query: Option<&str>,
+    non_interactive: NonInteractive,
+) -> Result<Simulator, SelectError> {
+    let simulators = device_list(env).map_err(SelectError::ListFailed)?;
+    if simulators.is_empty() {
+        return Err(SelectError::NoneAvailable);
+    }
+    let simulator = if let Some(query) = query {
+        find(&simulators, query, non_interactive)?
+    } else if simulators[0].booted() || non_interactive.yes() || simulators.len() == 1 {
+        simulators[0].clone()
+    } else {
+        let index = prompt::list(
+            "Available iOS simulators",
+            simulators.iter(),
+            "simulator",
+            None,
+            "Simulator",
+        )
+        .map_err(SelectError::PromptFailed)?;
+        simulators[index].clone()
+    };
+    ui::status(format!("Using simulator: {}", simulator));
+    Ok(simulator)
+}
+
+/// Like `select`, but for things that only make sense on a simulator that's
+/// already running, so only booted simulators are offered when several share
+/// a name, and nothing is prompted for without a query.
+pub fn select_booted(
+    env: &Env,
+    query: Option<&str>,
+    non_interactive: NonInteractive,
+) -> Result<Simulator, SelectError> {
+    let simulators = device_list(env).map_err(SelectError::ListFailed)?;
+    let simulator = if let Some(query) = query {
+        let booted = simulators
+            .iter()
+            .filter(|simulator| simulator.booted())
+            .cloned()
+            .collect::<Vec<_>>();
+        match device::find(booted, query, Simulator::udid, Simulator::name) {
+            Found::One(simulator) => simulator,
+            Found::Several(matches) => {
+                device::choose(matches, query, non_interactive, Simulator::udid)
+                    .map_err(SelectError::ChooseFailed)?
+            }
+            Found::Nothing => find(&simulators, query, non_interactive)?,
+        }
+    } else {
+        simulators
+            .first()
+            .filter(|simulator| simulator.booted())
+            .cloned()
+            .ok_or(SelectError::NoneBooted)?
+    };
+    if !simulator.booted() {
+        return Err(SelectError::NotBooted { simulator });
+    }
+    ui::status(format!("Using simulator: {}", simulator));
+    Ok(simulator)
+}
@@ -0,0 +1,137 @@
+mod device_list;
+mod run;
+
+pub use self::{device_list::*, run::*};
+
+use super::target::Target;
+use crate::device::{self, Kind, Listing, State as ListingState};
+use serde::Deserialize;
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+enum State {
+    Booted,
+    Shutdown,
+    #[serde(other)]
+    Other,
+}
+
+/// An iOS runtime version, parsed from identifiers like
+/// `com.apple.CoreSimulator.SimRuntime.iOS-14-2`.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct Runtime {
+    major: u32,
+    minor: u32,
+}
+
+impl Display for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "iOS {}.{}", self.major, self.minor)
+    }
+}
+
+impl Runtime {
+    fn from_identifier(identifier: &str) -> Option<Self> {
+        let mut components = identifier
+            .rsplit('.')
+            .next()?
+            .strip_prefix("iOS-")?
+            .split('-')
+            .map(|component| component.parse::<u32>().ok());
+        let major = components.next()??;
+        let minor = components.next().unwrap_or(Some(0))?;
+        Some(Self { major, minor })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Simulator {
+    udid: String,
+    name: String,
+    state: State,
+    runtime: Runtime,
+}
+
+impl Display for Simulator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.name, self.runtime)?;
+        if self.booted() {
+            write!(f, ", booted")?;
+        }
+        write!(f, ", {})", device::short_id(&self.udid))
+    }
+}
+
+// Simulators sort by preference: booted ones first, then the newest runtimes.
+impl Ord for Simulator {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .booted()
+            .cmp(&self.booted())
+            .then_with(|| other.runtime.cmp(&self.runtime))
+            .then_with(|| self.name.cmp(&other.name))
+            .then_with(|| self.udid.cmp(&other.udid))
+    }
+}
+
+impl PartialOrd for Simulator {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Simulator {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn udid(&self) -> &str {
+        &self.udid
+    }
+
+    pub fn booted(&self) -> bool {
+        self.state == State::Booted
+    }
+
+    /// Simulators run the host's arch.
+    pub fn listing(&self) -> Listing {
+        Listing {
+            name: self.name.clone(),
+            id: self.udid.clone(),
+            platform: "ios",
+            os_version: Some(format!("{}.{}", self.runtime.major, self.runtime.minor)),
+            arch: Target::simulator().arch.to_owned(),
+            kind: Kind::Virtual,
+            transport: None,
+            state: match self.state {
+                State::Booted => ListingState::Online,
+                State::Shutdown => ListingState::Shutdown,
+                State::Other => ListingState::Offline,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        identifier,
+        expected,
+        case("com.apple.CoreSimulator.SimRuntime.iOS-14-2", Some((14, 2))),
+        case("com.apple.CoreSimulator.SimRuntime.iOS-13", Some((13, 0))),
+        case("com.apple.CoreSimulator.SimRuntime.watchOS-7-1", None),
+        case("com.apple.CoreSimulator.SimRuntime.iOS-beta", None)
+    )]
+    fn test_runtime_from_identifier(identifier: &str, expected: Option<(u32, u32)>) {
+        assert_eq!(
+            Runtime::from_identifier(identifier),
+            expected.map(|(major, minor)| Runtime { major, minor })
+        );
+    }
+}
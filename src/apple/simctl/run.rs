@@ -0,0 +1,558 @@
+use super::{device_list, Simulator};
+use crate::{
+    apple::{
+        config::Config,
+        console,
+        target::{BuildError, HookError, Target},
+    },
+    crash::{self, Crashed},
+    env::{Env, ExplicitEnv as _},
+    hooks::Stage,
+    opts,
+    target::TargetTrait as _,
+    ui,
+    util::{
+        self,
+        cli::{Report, Reportable},
+        interrupt,
+        progress::{self, Phase},
+    },
+    vms,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+const BOOTSTATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum RunError {
+    RustupFailed(bossy::Error),
+    BootFailed(bossy::Error),
+    OpenFailed(bossy::Error),
+    BuildFailed(BuildError),
+    AppMissing { path: PathBuf },
+    InstallFailed(bossy::Error),
+    LaunchFailed(bossy::Error),
+    LogStreamFailed(bossy::Error),
+    TerminateFailed(bossy::Error),
+    HookFailed(HookError),
+    Crashed(Crashed),
+}
+
+impl Reportable for RunError {
+    fn report(&self) -> Report {
+        match self {
+            Self::RustupFailed(err) => {
+                Report::error("Failed to `rustup` iOS Simulator toolchain", err)
+            }
+            Self::BootFailed(err) => Report::error("Failed to boot simulator", err),
+            Self::OpenFailed(err) => Report::error("Failed to open Simulator app", err),
+            Self::BuildFailed(err) => err.report(),
+            Self::AppMissing { path } => Report::error(
+                "App bundle appears to be missing",
+                format!("Not found at {:?}", path),
+            ),
+            Self::InstallFailed(err) => Report::error("Failed to install app on simulator", err),
+            Self::LaunchFailed(err) => Report::error("Failed to launch app on simulator", err),
+            Self::LogStreamFailed(err) => Report::error("Failed to stream simulator logs", err),
+            Self::TerminateFailed(err) => {
+                Report::error("Failed to terminate app on simulator", err)
+            }
+            Self::HookFailed(err) => err.report(),
+            Self::Crashed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::RustupFailed(..) => "apple.simctl.run.run.rustup-failed",
+            Self::BootFailed(..) => "apple.simctl.run.run.boot-failed",
+            Self::OpenFailed(..) => "apple.simctl.run.run.open-failed",
+            Self::BuildFailed(err) => err.code(),
+            Self::AppMissing { .. } => "apple.simctl.run.run.app-missing",
+            Self::InstallFailed(..) => "apple.simctl.run.run.install-failed",
+            Self::LaunchFailed(..) => "apple.simctl.run.run.launch-failed",
+            Self::LogStreamFailed(..) => "apple.simctl.run.run.log-stream-failed",
+            Self::TerminateFailed(..) => "apple.simctl.run.run.terminate-failed",
+            Self::HookFailed(err) => err.code(),
+            Self::Crashed(err) => err.code(),
+        }
+    }
+}
+
+fn simctl(env: &Env) -> bossy::Command {
+    bossy::Command::pure("xcrun")
+        .with_env_vars(env.explicit_env())
+        .with_arg("simctl")
+}
+
+/// Makes sure the simulator toolchain is installed, and then boots the
+/// simulator.
+pub fn prepare(env: &Env, simulator: &Simulator) -> Result<(), RunError> {
+    Target::simulator()
+        .install()
+        .map_err(RunError::RustupFailed)?;
+    boot(env, simulator)
+}
+
+/// Boots the simulator (if it isn't already) and brings up the Simulator app.
+/// Simulators we boot are tracked, so they can be shut down again with
+/// `cargo mobile vms stop`, or with `--ephemeral`.
+pub fn boot(env: &Env, simulator: &Simulator) -> Result<(), RunError> {
+    if !simulator.booted() {
+        ui::status("Booting simulator...");
+        simctl(env)
+            .with_args(&["boot", simulator.udid()])
+            .run_and_wait()
+            .map_err(RunError::BootFailed)?;
+        vms::register(vms::Instance::new(
+            vms::Kind::Simulator,
+            simulator.udid(),
+            simulator.name(),
+        ));
+    }
+    bossy::Command::pure("open")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["-a", "Simulator"])
+        .run_and_wait()
+        .map_err(RunError::OpenFailed)
+}
+
+/// Whether the simulator's booted and answering. `bootstatus` waits for one
+/// that's still booting, so we only wait so long, in case it never finishes.
+pub fn responsive(env: &Env, udid: &str) -> bool {
+    let booted = device_list(env)
+        .map(|simulators| {
+            simulators
+                .iter()
+                .any(|simulator| simulator.udid() == udid && simulator.booted())
+        })
+        .unwrap_or(false);
+    if !booted {
+        return false;
+    }
+    let command = simctl(env).with_args(&["bootstatus", udid]);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // Nobody's listening anymore if we gave up on it.
+        let _ = tx.send(command.run_and_wait_for_output().is_ok());
+    });
+    rx.recv_timeout(BOOTSTATUS_TIMEOUT).unwrap_or(false)
+}
+
+pub fn shutdown(env: &Env, udid: &str) -> bossy::Result<()> {
+    simctl(env).with_args(&["shutdown", udid]).run_and_wait()?;
+    Ok(())
+}
+
+// Apps launched by SpringBoard are labeled `UIKitApplication:<bundle ID>[...]`,
+// and `launchctl list` shows a PID of `-` once they've exited.
+fn launchctl_running(output: &str, bundle_id: &str) -> bool {
+    let label = format!("UIKitApplication:{}[", bundle_id);
+    output.lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        let pid = fields.next().unwrap_or_default();
+        let label_matches = fields
+            .nth(1)
+            .map(|field| field.starts_with(&label))
+            .unwrap_or_default();
+        label_matches && pid.parse::<u32>().is_ok()
+    })
+}
+
+// The newest crash report for the app written since it was launched. The
+// simulator's crash reports end up alongside the Mac's own.
+fn crash_report(config: &Config, launched_at: SystemTime) -> Option<PathBuf> {
+    let dir = util::home_dir()
+        .ok()?
+        .join("Library/Logs/DiagnosticReports");
+    fs::read_dir(&dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with(config.product_name()) && name.ends_with(".ips")
+        })
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|meta| meta.modified()).ok()?;
+            if modified >= launched_at {
+                Some((modified, entry.path()))
+            } else {
+                None
+            }
+        })
+        .max()
+        .map(|(_, path)| path)
+}
+
+fn check_crash(
+    config: &Config,
+    env: &Env,
+    simulator: &Simulator,
+    skip_log: opts::SkipLog,
+    launched_at: SystemTime,
+) -> Result<(), Crashed> {
+    let bundle_id = config.bundle_id();
+    let died = crash::died(|| {
+        let output = simctl(env)
+            .with_args(&["spawn", simulator.udid(), "launchctl", "list"])
+            .run_and_wait_for_str(|output| launchctl_running(output, &bundle_id));
+        match output {
+            Ok(running) => Some(running),
+            Err(err) => {
+                log::warn!("failed to list running processes on simulator: {}", err);
+                None
+            }
+        }
+    });
+    if !died {
+        return Ok(());
+    }
+    let excerpt = if skip_log.yes() {
+        None
+    } else {
+        fs::read_to_string(config.simulator_console_path())
+            .ok()
+            .and_then(|log| crash::tail(&log))
+    };
+    let hint = crash_report(config, launched_at).map(|path| {
+        format!(
+            "Its crash report was saved to {:?}; if the frames aren't symbolicated, `cargo apple symbolicate` can fill them in.",
+            path
+        )
+    });
+    Err(Crashed::new(simulator.name())
+        .with_excerpt(excerpt)
+        .with_hint(hint))
+}
+
+/// Builds, installs, and launches the app on a simulator that's already been
+/// prepared. Unless `skip_log` is set, the app's output is redirected to the
+/// console log so that it can be streamed. Unless `no_crash_check` is set,
+/// the app has to stay up for [`crash::WINDOW`] after launching.
+pub fn deploy(
+    config: &Config,
+    env: &Env,
+    noise_level: opts::NoiseLevel,
+    profile: opts::Profile,
+    simulator: &Simulator,
+    skip_log: opts::SkipLog,
+    no_hooks: opts::NoHooks,
+    no_crash_check: opts::NoCrashCheck,
+) -> Result<(), RunError> {
+    let target = Target::simulator();
+    let run_hooks = |stage: Stage, artifact: Option<&Path>| {
+        target
+            .run_hooks(config, env, stage, profile, no_hooks, artifact)
+            .map_err(RunError::HookFailed)
+    };
+    run_hooks(Stage::PreBuild, None)?;
+    ui::status("Building app...");
+    target
+        .build_for_simulator(config, env, noise_level, profile, simulator.udid())
+        .map_err(RunError::BuildFailed)?;
+    let app_path = config.simulator_app_path(profile);
+    if !app_path.is_dir() {
+        return Err(RunError::AppMissing { path: app_path });
+    }
+    run_hooks(Stage::PostBuild, Some(&app_path))?;
+    run_hooks(Stage::PreRun, None)?;
+    install(env, simulator, &app_path)?;
+    let launched_at = SystemTime::now();
+    launch(config, env, simulator, &config.bundle_id(), skip_log)?;
+    if !no_crash_check.yes() {
+        check_crash(config, env, simulator, skip_log, launched_at).map_err(RunError::Crashed)?;
+    }
+    run_hooks(Stage::PostRun, Some(&app_path))
+}
+
+/// Installs the app bundle at `app_path` on a simulator that's already been
+/// booted.
+pub fn install(env: &Env, simulator: &Simulator, app_path: &Path) -> Result<(), RunError> {
+    // `boot` returns before the simulator is actually ready for installs
+    simctl(env)
+        .with_args(&["bootstatus", simulator.udid()])
+        .run_and_wait()
+        .map_err(RunError::BootFailed)?;
+    ui::status("Installing app on simulator...");
+    let install = simctl(env)
+        .with_args(&["install", simulator.udid()])
+        .with_arg(app_path);
+    progress::phase(Phase::Install, None, || install.run_and_wait())
+        .map_err(RunError::InstallFailed)?;
+    Ok(())
+}
+
+/// Launches the installed app with the bundle ID `bundle_id`. Unless
+/// `skip_log` is set, the app's output is redirected to the console log so
+/// that it can be streamed.
+pub fn launch(
+    config: &Config,
+    env: &Env,
+    simulator: &Simulator,
+    bundle_id: &str,
+    skip_log: opts::SkipLog,
+) -> Result<(), RunError> {
+    ui::status("Launching app...");
+    let console_path = config.simulator_console_path();
+    let mut launch = simctl(env).with_args(&["launch", "--terminate-running-process"]);
+    // The app's stdout and stderr go to a file rather than our terminal, so
+    // that it keeps running once we stop streaming.
+    if !skip_log.yes() {
+        if let Some(parent) = console_path.parent() {
+            if let Err(err) = fs::create_dir_all(parent) {
+                log::warn!("failed to create console log dir {:?}: {}", parent, err);
+            }
+        }
+        if let Err(err) = fs::write(&console_path, "") {
+            log::warn!("failed to clear console log {:?}: {}", console_path, err);
+        }
+        launch.add_arg(format!("--stdout={}", console_path.display()));
+        launch.add_arg(format!("--stderr={}", console_path.display()));
+    }
+    let launch = launch.with_arg(simulator.udid()).with_arg(bundle_id);
+    progress::phase(Phase::Launch, None, || launch.run_and_wait())
+        .map_err(RunError::LaunchFailed)?;
+    Ok(())
+}
+
+pub fn run(
+    config: &Config,
+    env: &Env,
+    noise_level: opts::NoiseLevel,
+    profile: opts::Profile,
+    simulator: &Simulator,
+    skip_log: opts::SkipLog,
+    kill_on_exit: opts::KillOnExit,
+    no_hooks: opts::NoHooks,
+    no_crash_check: opts::NoCrashCheck,
+) -> Result<(), RunError> {
+    prepare(env, simulator)?;
+    deploy(
+        config,
+        env,
+        noise_level,
+        profile,
+        simulator,
+        skip_log,
+        no_hooks,
+        no_crash_check,
+    )?;
+    if skip_log.yes() {
+        return Ok(());
+    }
+    follow(config, env, simulator, &config.bundle_id(), kill_on_exit)
+}
+
+// `log stream` wants the process name as a quoted string.
+fn process_predicate(process: &str) -> String {
+    format!(
+        "process == \"{}\"",
+        process.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+// What the app logs through `os_log` (which is where `oslog` and friends send
+// Rust's `log` records) doesn't go to its stdout, so that's streamed from the
+// simulator's unified log alongside it, filtered to the app's process to keep
+// the system's own chatter out.
+fn log_stream(env: &Env, simulator: &Simulator, process: &str) -> bossy::Command {
+    simctl(env)
+        .with_args(&["spawn", simulator.udid(), "log", "stream"])
+        .with_args(&["--style", "compact", "--level", "debug", "--predicate"])
+        .with_arg(process_predicate(process))
+}
+
+// Ctrl-C already reached it if that's why we're stopping, and otherwise it'd
+// outlive us.
+fn stop(handle: bossy::Handle) {
+    if !interrupt::interrupted() {
+        if let Err(err) = bossy::Command::impure("kill")
+            .with_arg(handle.id().to_string())
+            .run_and_wait()
+        {
+            log::warn!("failed to stop simulator log stream: {}", err);
+        }
+    }
+    let _ = handle.wait_for_output();
+}
+
+/// Streams the console log of an app launched by [`launch`], along with what
+/// it sends to the unified log, until the user stops us, and then terminates
+/// the app if `kill_on_exit` is set.
+pub fn follow(
+    config: &Config,
+    env: &Env,
+    simulator: &Simulator,
+    bundle_id: &str,
+    kill_on_exit: opts::KillOnExit,
+) -> Result<(), RunError> {
+    // Held until the log stream's stopped, so that it can tell whether Ctrl-C
+    // already got to it.
+    let _survive = interrupt::survive();
+    let console_path = config.simulator_console_path();
+    log::info!("streaming simulator console output from {:?}", console_path);
+    log::info!(
+        "streaming simulator logs using `log stream --predicate '{}'`",
+        process_predicate(config.product_name())
+    );
+    let log_stream = log_stream(env, simulator, config.product_name())
+        .run()
+        .map_err(|err| log::warn!("failed to stream simulator logs: {}", err))
+        .ok();
+    let streamed = console::stream(
+        bossy::Command::pure("tail")
+            .with_env_vars(env.explicit_env())
+            .with_args(&["-n", "+1", "-F"])
+            .with_arg(&console_path),
+    );
+    if let Some(handle) = log_stream {
+        stop(handle);
+    }
+    streamed.map_err(RunError::LogStreamFailed)?;
+    if kill_on_exit.yes() {
+        ui::status("Terminating app...");
+        simctl(env)
+            .with_args(&["terminate", simulator.udid()])
+            .with_arg(bundle_id)
+            .run_and_wait()
+            .map_err(RunError::TerminateFailed)?;
+    }
+    Ok(())
+}
+
+// The `simctl` subcommands that take a device as their first argument.
+static DEVICE_SUBCOMMANDS: &[&str] = &[
+    "addmedia",
+    "appinfo",
+    "boot",
+    "bootstatus",
+    "clone",
+    "delete",
+    "erase",
+    "get_app_container",
+    "getenv",
+    "icloud_sync",
+    "install",
+    "io",
+    "keychain",
+    "launch",
+    "listapps",
+    "location",
+    "logverbose",
+    "openurl",
+    "pbcopy",
+    "pbpaste",
+    "privacy",
+    "push",
+    "rename",
+    "shutdown",
+    "spawn",
+    "status_bar",
+    "terminate",
+    "ui",
+    "uninstall",
+    "upgrade",
+];
+
+// What `simctl` takes in place of a UDID.
+static DEVICE_KEYWORDS: &[&str] = &["booted", "all", "unavailable"];
+
+// `8A3C7E2B-5F1D-4C9A-B0E6-2D7F9A1C3B4E`
+fn is_udid(arg: &str) -> bool {
+    arg.len() == 36
+        && arg.char_indices().all(|(index, c)| match index {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+// Puts `udid` where the subcommand expects a device, unless a device was
+// already given.
+fn passthrough_args(args: &[String], udid: &str) -> Vec<String> {
+    let mut args = args.to_vec();
+    let takes_device = args.first().map_or(false, |subcommand| {
+        DEVICE_SUBCOMMANDS.contains(&subcommand.as_str())
+    });
+    let has_device = args.get(1).map_or(false, |arg| {
+        DEVICE_KEYWORDS.contains(&arg.as_str()) || is_udid(arg)
+    });
+    if takes_device && !has_device {
+        args.insert(1, udid.to_owned());
+    }
+    args
+}
+
+/// `xcrun simctl` for `cargo apple simctl`, with the simulator filled in.
+/// Unlike ours, this keeps the user's env, since that's how `SIMCTL_CHILD_*`
+/// vars get to the apps it launches.
+pub fn passthrough(env: &Env, simulator: &Simulator, args: &[String]) -> bossy::Command {
+    bossy::Command::impure("xcrun")
+        .with_env_vars(env.explicit_env())
+        .with_arg("simctl")
+        .with_args(passthrough_args(args, simulator.udid()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    const UDID: &str = "8A3C7E2B-5F1D-4C9A-B0E6-2D7F9A1C3B4E";
+
+    #[rstest(
+        args,
+        expected,
+        case(&["launch", "com.example.app"], &["launch", UDID, "com.example.app"]),
+        case(&["io", "screenshot", "shot.png"], &["io", UDID, "screenshot", "shot.png"]),
+        case(&["launch", "booted", "com.example.app"], &["launch", "booted", "com.example.app"]),
+        case(
+            &["terminate", "1F0E2D3C-4B5A-6978-8A9B-0C1D2E3F4A5B", "com.example.app"],
+            &["terminate", "1F0E2D3C-4B5A-6978-8A9B-0C1D2E3F4A5B", "com.example.app"]
+        ),
+        case(&["list", "devices"], &["list", "devices"]),
+        case(&[], &[])
+    )]
+    fn test_passthrough_args(args: &[&str], expected: &[&str]) {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(passthrough_args(&args, UDID), expected);
+    }
+
+    #[rstest(
+        process,
+        expected,
+        case("my-game", r#"process == "my-game""#),
+        case(r#"say "hi""#, r#"process == "say \"hi\"""#),
+        case(r"back\slash", r#"process == "back\\slash""#)
+    )]
+    fn test_process_predicate(process: &str, expected: &str) {
+        assert_eq!(process_predicate(process), expected);
+    }
+
+    const LAUNCHCTL_LIST: &str = "PID\tStatus\tLabel
+-\t0\tcom.apple.backboardd
+4127\t0\tUIKitApplication:com.example.other[7f3a][rb-legacy]
+4130\t0\tUIKitApplication:com.example.app.widget[2b1c][rb-legacy]
+-\t-9\tUIKitApplication:com.example.crashy[91d0][rb-legacy]
+4133\t0\tUIKitApplication:com.example.app[c4e2][rb-legacy]
+";
+
+    #[rstest(
+        bundle_id,
+        expected,
+        case("com.example.app", true),
+        case("com.example.other", true),
+        case("com.example.crashy", false),
+        case("com.example", false),
+        case("com.example.missing", false)
+    )]
+    fn test_launchctl_running(bundle_id: &str, expected: bool) {
+        assert_eq!(launchctl_running(LAUNCHCTL_LIST, bundle_id), expected);
+    }
+}
@@ -0,0 +1,215 @@
+use super::config::Config;
+use crate::util::{
+    self,
+    cli::{Report, Reportable},
+};
+use ignore::overrides::OverrideBuilder;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    PathMissing {
+        key: &'static str,
+        path: String,
+    },
+    GlobInvalid {
+        key: &'static str,
+        glob: String,
+        cause: ignore::Error,
+    },
+    GlobEmpty {
+        key: &'static str,
+        glob: String,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        let msg = "Failed to add extra sources to Xcode project";
+        match self {
+            Self::PathMissing { key, path } => Report::error(
+                msg,
+                format!(
+                    "`{}.{}` contains {:?}, but nothing exists there",
+                    super::NAME,
+                    key,
+                    path
+                ),
+            ),
+            Self::GlobInvalid { key, glob, cause } => Report::error(
+                msg,
+                format!(
+                    "`{}.{}` contains {:?}, which isn't a valid glob: {}",
+                    super::NAME,
+                    key,
+                    glob,
+                    cause
+                ),
+            ),
+            Self::GlobEmpty { key, glob } => Report::error(
+                msg,
+                format!(
+                    "`{}.{}` contains {:?}, but it doesn't match any files",
+                    super::NAME,
+                    key,
+                    glob
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::PathMissing { .. } => "apple.sources.path-missing",
+            Self::GlobInvalid { .. } => "apple.sources.glob-invalid",
+            Self::GlobEmpty { .. } => "apple.sources.glob-empty",
+        }
+    }
+}
+
+fn is_glob(path: &str) -> bool {
+    path.contains(|c| matches!(c, '*' | '?' | '[' | '{'))
+}
+
+// The part of a glob before any wildcards, which is where we start walking.
+fn glob_base(glob: &str) -> PathBuf {
+    Path::new(glob)
+        .components()
+        .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+        .collect()
+}
+
+fn expand(root: &Path, key: &'static str, paths: &[String]) -> Result<Vec<PathBuf>, Error> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        if !is_glob(path) {
+            let abs = root.join(path);
+            if !abs.exists() {
+                return Err(Error::PathMissing {
+                    key,
+                    path: path.clone(),
+                });
+            }
+            expanded.push(abs);
+            continue;
+        }
+        let glob_invalid = |cause| Error::GlobInvalid {
+            key,
+            glob: path.clone(),
+            cause,
+        };
+        let matcher = OverrideBuilder::new(root)
+            .add(path)
+            .and_then(|builder| builder.build())
+            .map_err(glob_invalid)?;
+        let mut matches = ignore::WalkBuilder::new(root.join(glob_base(path)))
+            .build()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry
+                    .file_type()
+                    .map(|file_type| file_type.is_file())
+                    .unwrap_or_default()
+                    && matcher.matched(entry.path(), false).is_whitelist()
+            })
+            .map(|entry| entry.into_path())
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            return Err(Error::GlobEmpty {
+                key,
+                glob: path.clone(),
+            });
+        }
+        // Walk order isn't guaranteed, and we don't want the project to churn.
+        matches.sort();
+        expanded.append(&mut matches);
+    }
+    Ok(expanded)
+}
+
+/// Native sources and resources from the config, with globs expanded.
+#[derive(Debug, Default)]
+pub struct Sources {
+    sources: Vec<PathBuf>,
+    resources: Vec<PathBuf>,
+    bridging_header: Option<PathBuf>,
+}
+
+impl Sources {
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let root = config.app().root_dir();
+        let sources = expand(root, "sources", config.extra_sources())?;
+        let resources = expand(root, "resources", config.extra_resources())?;
+        let bridging_header = config
+            .bridging_header()
+            .map(|header| {
+                let path = root.join(header);
+                if path.is_file() {
+                    Ok(path)
+                } else {
+                    Err(Error::PathMissing {
+                        key: "bridging-header",
+                        path: header.to_owned(),
+                    })
+                }
+            })
+            .transpose()?;
+        Ok(Self {
+            sources,
+            resources,
+            bridging_header,
+        })
+    }
+
+    /// Everything that ends up in the Xcode target, for `--watch` to watch.
+    pub fn watched(&self) -> Vec<PathBuf> {
+        self.sources
+            .iter()
+            .chain(&self.resources)
+            .chain(&self.bridging_header)
+            .cloned()
+            .collect()
+    }
+
+    /// Target source entries, each rendered as JSON for `xcodegen`. Paths are
+    /// relative to `project_dir`, since that's where the spec lives.
+    pub fn spec(&self, project_dir: &Path) -> Vec<String> {
+        let rel = |path: &Path| util::relativize_path(path, project_dir);
+        self.sources
+            .iter()
+            .map(|path| json!({ "path": rel(path) }))
+            .chain(self.resources.iter().map(|path| {
+                json!({
+                    "path": rel(path),
+                    "buildPhase": "resources",
+                })
+            }))
+            .map(|source| source.to_string())
+            .collect()
+    }
+
+    pub fn bridging_header(&self, project_dir: &Path) -> Option<PathBuf> {
+        self.bridging_header
+            .as_deref()
+            .map(|header| util::relativize_path(header, project_dir))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        glob,
+        base,
+        case("ios/Sources/**/*.swift", "ios/Sources"),
+        case("ios/*.m", "ios"),
+        case("*.metal", ""),
+        case("ios/Shaders/main.metal", "ios/Shaders/main.metal")
+    )]
+    fn test_glob_base(glob: &str, base: &str) {
+        assert_eq!(glob_base(glob), PathBuf::from(base));
+    }
+}
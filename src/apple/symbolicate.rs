@@ -0,0 +1,351 @@
+use super::dsym::{self, Slice};
+use crate::util::cli::{Report, Reportable};
+use once_cell_regex::regex;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    ReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    IpsInvalid {
+        path: PathBuf,
+        cause: serde_json::Error,
+    },
+    NoImages {
+        path: PathBuf,
+    },
+    DsymsFailed(dsym::Error),
+    NoMatchingDsym {
+        uuids: Vec<String>,
+        dsym_dir: PathBuf,
+    },
+    AtosFailed(bossy::Error),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        let msg = "Failed to symbolicate crash log";
+        match self {
+            Self::ReadFailed { path, cause } => {
+                Report::error(msg, format!("Failed to read {:?}: {}", path, cause))
+            }
+            Self::IpsInvalid { path, cause } => Report::error(
+                msg,
+                format!("{:?} isn't a valid `.ips` crash report: {}", path, cause),
+            ),
+            Self::NoImages { path } => Report::error(
+                msg,
+                format!("No binary images were found in {:?}; is it a crash log?", path),
+            ),
+            Self::DsymsFailed(err) => err.report(),
+            Self::NoMatchingDsym { uuids, dsym_dir } => Report::action_request(
+                "No dSYM matches this crash log",
+                format!(
+                    "None of the dSYMs in {:?} match the crash log's images ({}). dSYMs are saved there by `cargo apple archive --release`; use `--dsyms` to look somewhere else.",
+                    dsym_dir,
+                    uuids.join(", ")
+                ),
+            ),
+            Self::AtosFailed(err) => Report::error(msg, err),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadFailed { .. } => "apple.symbolicate.read-failed",
+            Self::IpsInvalid { .. } => "apple.symbolicate.ips-invalid",
+            Self::NoImages { .. } => "apple.symbolicate.no-images",
+            Self::DsymsFailed(..) => "apple.symbolicate.dsyms-failed",
+            Self::NoMatchingDsym { .. } => "apple.symbolicate.no-matching-dsym",
+            Self::AtosFailed(..) => "apple.symbolicate.atos-failed",
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Image {
+    uuid: String,
+    name: String,
+    load_address: u64,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Frame {
+    thread: usize,
+    index: usize,
+    image: usize,
+    address: u64,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+struct Crash {
+    images: Vec<Image>,
+    frames: Vec<Frame>,
+}
+
+// Crash logs use bare lowercase hex, while `dwarfdump` uses the usual
+// uppercase hyphenated form.
+fn normalize_uuid(uuid: &str) -> String {
+    let hex = uuid.replace('-', "").to_uppercase();
+    if hex.len() == 32 {
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    } else {
+        hex
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+impl Crash {
+    // The classic text format, used by `.crash` files and older `.ips` files.
+    fn parse_text(log: &str) -> Self {
+        let mut crash = Self::default();
+        for caps in
+            regex!(r"(?m)^\s*(0x[0-9a-f]+)\s+-\s+0x[0-9a-f]+\s+\+?(\S+)\s+\S+\s+<([0-9a-fA-F-]+)>")
+                .captures_iter(log)
+        {
+            if let Some(load_address) = parse_hex(&caps[1]) {
+                crash.images.push(Image {
+                    uuid: normalize_uuid(&caps[3]),
+                    name: caps[2].to_owned(),
+                    load_address,
+                });
+            }
+        }
+        let mut thread = 0;
+        for line in log.lines() {
+            if let Some(caps) = regex!(r"^Thread (\d+)").captures(line) {
+                thread = caps[1].parse().unwrap_or_default();
+            } else if let Some(caps) = regex!(r"^(\d+)\s+(\S+)\s+(0x[0-9a-f]+)\s").captures(line) {
+                let image = crash.images.iter().position(|image| image.name == caps[2]);
+                if let (Some(image), Some(address)) = (image, parse_hex(&caps[3])) {
+                    crash.frames.push(Frame {
+                        thread,
+                        index: caps[1].parse().unwrap_or_default(),
+                        image,
+                        address,
+                    });
+                }
+            }
+        }
+        crash
+    }
+
+    // The JSON format introduced in iOS 15, which is a one-line header
+    // followed by the report itself.
+    fn parse_ips(log: &str) -> Result<Self, serde_json::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct UsedImage {
+            uuid: String,
+            base: u64,
+            #[serde(default)]
+            name: Option<String>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct IpsFrame {
+            image_offset: u64,
+            image_index: usize,
+        }
+
+        #[derive(Deserialize)]
+        struct Thread {
+            #[serde(default)]
+            frames: Vec<IpsFrame>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Body {
+            used_images: Vec<UsedImage>,
+            #[serde(default)]
+            threads: Vec<Thread>,
+        }
+
+        let body = log.splitn(2, '\n').nth(1).unwrap_or_default();
+        let body = serde_json::from_str::<Body>(body)?;
+        let images = body
+            .used_images
+            .into_iter()
+            .map(|image| Image {
+                uuid: normalize_uuid(&image.uuid),
+                name: image.name.unwrap_or_default(),
+                load_address: image.base,
+            })
+            .collect::<Vec<_>>();
+        let frames = body
+            .threads
+            .into_iter()
+            .enumerate()
+            .flat_map(|(thread, Thread { frames })| {
+                frames
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(index, frame)| (thread, index, frame))
+            })
+            .filter_map(|(thread, index, frame)| {
+                images.get(frame.image_index).map(|image| Frame {
+                    thread,
+                    index,
+                    image: frame.image_index,
+                    address: image.load_address + frame.image_offset,
+                })
+            })
+            .collect();
+        Ok(Self { images, frames })
+    }
+
+    fn parse(log: &str) -> Result<Self, serde_json::Error> {
+        if log.trim_start().starts_with('{') {
+            Self::parse_ips(log)
+        } else {
+            Ok(Self::parse_text(log))
+        }
+    }
+}
+
+fn atos(slice: &Slice, load_address: u64, addresses: &[u64]) -> Result<Vec<String>, Error> {
+    bossy::Command::impure("atos")
+        .with_args(&["-arch", &slice.arch])
+        .with_arg("-o")
+        .with_arg(&slice.dwarf_path)
+        .with_arg("-l")
+        .with_arg(format!("{:#x}", load_address))
+        .with_args(addresses.iter().map(|address| format!("{:#x}", address)))
+        .run_and_wait_for_str(|output| output.lines().map(ToOwned::to_owned).collect())
+        .map_err(Error::AtosFailed)
+}
+
+/// Symbolicates every frame in the crash log that belongs to an image we have
+/// a dSYM for, and prints them by thread.
+pub fn symbolicate(crash_path: &Path, dsym_dir: &Path) -> Result<(), Error> {
+    let log = fs::read_to_string(crash_path).map_err(|cause| Error::ReadFailed {
+        path: crash_path.to_owned(),
+        cause,
+    })?;
+    let crash = Crash::parse(&log).map_err(|cause| Error::IpsInvalid {
+        path: crash_path.to_owned(),
+        cause,
+    })?;
+    if crash.images.is_empty() {
+        return Err(Error::NoImages {
+            path: crash_path.to_owned(),
+        });
+    }
+    let slices = dsym::slices(dsym_dir).map_err(Error::DsymsFailed)?;
+    let mut symbols = BTreeMap::new();
+    for (index, image) in crash.images.iter().enumerate() {
+        if let Some(slice) = slices.iter().find(|slice| slice.uuid == image.uuid) {
+            let frames = crash
+                .frames
+                .iter()
+                .filter(|frame| frame.image == index)
+                .collect::<Vec<_>>();
+            if frames.is_empty() {
+                continue;
+            }
+            let addresses = frames.iter().map(|frame| frame.address).collect::<Vec<_>>();
+            for (frame, symbol) in
+                frames
+                    .into_iter()
+                    .zip(atos(slice, image.load_address, &addresses)?)
+            {
+                symbols.insert((frame.thread, frame.index), (&image.name, symbol));
+            }
+        }
+    }
+    if symbols.is_empty() {
+        return Err(Error::NoMatchingDsym {
+            uuids: crash
+                .images
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| crash.frames.iter().any(|frame| frame.image == *index))
+                .map(|(_, image)| format!("{} {}", image.name, image.uuid))
+                .collect(),
+            dsym_dir: dsym_dir.to_owned(),
+        });
+    }
+    let mut current_thread = None;
+    for ((thread, index), (image, symbol)) in symbols {
+        if current_thread != Some(thread) {
+            println!("Thread {}:", thread);
+            current_thread = Some(thread);
+        }
+        println!("  {:<3} {:<20} {}", index, image, symbol);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_text() {
+        let log = "\
+Thread 0 Crashed:
+0   foo                           	0x0000000100f42a2c 0x100f3c000 + 27180
+1   UIKitCore                     	0x00000001a1b2c3d4 0x1a1000000 + 11715540
+
+Binary Images:
+0x100f3c000 - 0x10104ffff foo arm64  <1b2a3c4d5e6f708192a3b4c5d6e7f809> /private/var/containers/Bundle/Application/X/foo.app/foo
+";
+        assert_eq!(
+            Crash::parse(log).unwrap(),
+            Crash {
+                images: vec![Image {
+                    uuid: "1B2A3C4D-5E6F-7081-92A3-B4C5D6E7F809".to_owned(),
+                    name: "foo".to_owned(),
+                    load_address: 0x100f3c000,
+                }],
+                frames: vec![Frame {
+                    thread: 0,
+                    index: 0,
+                    image: 0,
+                    address: 0x100f42a2c,
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_ips() {
+        let log = r#"{"app_name":"foo","bug_type":"309"}
+{"usedImages":[{"uuid":"1b2a3c4d-5e6f-7081-92a3-b4c5d6e7f809","base":4310941696,"name":"foo","arch":"arm64"}],"threads":[{"frames":[{"imageOffset":27180,"imageIndex":0}]}]}"#;
+        assert_eq!(
+            Crash::parse(log).unwrap(),
+            Crash {
+                images: vec![Image {
+                    uuid: "1B2A3C4D-5E6F-7081-92A3-B4C5D6E7F809".to_owned(),
+                    name: "foo".to_owned(),
+                    load_address: 0x100f3c000,
+                }],
+                frames: vec![Frame {
+                    thread: 0,
+                    index: 0,
+                    image: 0,
+                    address: 0x100f42a2c,
+                }],
+            }
+        );
+    }
+}
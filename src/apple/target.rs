@@ -1,30 +1,35 @@
 use super::{
-    config::{Config, Metadata},
+    config::{Config, Desktop, Metadata, SigningStyle},
+    export,
+    sources::Sources,
     system_profile::{self, DeveloperTools},
+    xcodebuild,
 };
 use crate::{
-    env::{Env, ExplicitEnv as _},
-    opts::{self, ForceColor, NoiseLevel, Profile},
-    target::TargetTrait,
+    config::{build_config, rustflags},
+    device::Kind,
+    dot_cargo, entry_point,
+    env::{BuildEnv, Env, ExplicitEnv as _},
+    fingerprint::{Fingerprint, Store},
+    hooks::{self, Stage},
+    opts::{self, ForceColor, NoHooks, NoiseLevel, Profile},
+    resources,
+    sizes::{self, Sizes},
+    target::{self, TargetTrait},
+    ui,
     util::{
+        self,
         cli::{Report, Reportable},
+        progress::{self, Phase},
         CargoCommand,
     },
 };
 use once_cell_regex::exports::once_cell::sync::OnceCell;
 use std::{
-    collections::{BTreeMap, HashMap},
-    ffi::OsStr,
+    collections::BTreeMap,
+    path::{Path, PathBuf},
 };
 
-fn verbosity(noise_level: opts::NoiseLevel) -> Option<&'static str> {
-    if noise_level.pedantic() {
-        None
-    } else {
-        Some("-quiet")
-    }
-}
-
 #[derive(Debug)]
 pub enum VersionCheckError {
     LookupFailed(system_profile::Error),
@@ -38,6 +43,9 @@ pub enum VersionCheckError {
 impl Reportable for VersionCheckError {
     fn report(&self) -> Report {
         match self {
+            Self::LookupFailed(system_profile::Error::SystemProfilerFailed(err)) => {
+                err.report("Failed to lookup Xcode version")
+            }
             Self::LookupFailed(err) => Report::error("Failed to lookup Xcode version", err),
             Self::TooLow {
                 msg,
@@ -52,11 +60,145 @@ impl Reportable for VersionCheckError {
             ),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::LookupFailed(..) => "apple.target.version-check.lookup-failed",
+            Self::TooLow { .. } => "apple.target.version-check.too-low",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BuildEnvError {
+    SdkRootInvalid { sdk_root: PathBuf },
+    IncludeDirInvalid { include_dir: PathBuf },
+    MacosSdkRootInvalid { macos_sdk_root: PathBuf },
+}
+
+impl Reportable for BuildEnvError {
+    fn report(&self) -> Report {
+        match self {
+            Self::SdkRootInvalid { sdk_root } => Report::error(
+                "SDK root was invalid",
+                format!("{:?} doesn't exist or isn't a directory", sdk_root),
+            ),
+            Self::IncludeDirInvalid { include_dir } => Report::error(
+                "Include dir was invalid",
+                format!("{:?} doesn't exist or isn't a directory", include_dir),
+            ),
+            Self::MacosSdkRootInvalid { macos_sdk_root } => Report::error(
+                "macOS SDK root was invalid",
+                format!("{:?} doesn't exist or isn't a directory", macos_sdk_root),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::SdkRootInvalid { .. } => "apple.target.build-env.sdk-root-invalid",
+            Self::IncludeDirInvalid { .. } => "apple.target.build-env.include-dir-invalid",
+            Self::MacosSdkRootInvalid { .. } => "apple.target.build-env.macos-sdk-root-invalid",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HookError {
+    SdkRootFailed(bossy::Error),
+    BuildEnvFailed(BuildEnvError),
+    HookFailed(hooks::Error),
+}
+
+impl Reportable for HookError {
+    fn report(&self) -> Report {
+        match self {
+            Self::SdkRootFailed(err) => Report::error("Failed to locate SDK", err),
+            Self::BuildEnvFailed(err) => err.report(),
+            Self::HookFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::SdkRootFailed(..) => "apple.target.hook.sdk-root-failed",
+            Self::BuildEnvFailed(err) => err.code(),
+            Self::HookFailed(err) => err.code(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BuildStdError {
+    ToolchainCheckFailed(bossy::Error),
+    NotNightly { triples: Vec<String> },
+    RustSrcMissing { triples: Vec<String> },
+}
+
+impl Reportable for BuildStdError {
+    fn report(&self) -> Report {
+        match self {
+            Self::ToolchainCheckFailed(err) => {
+                Report::error("Failed to check toolchain for `build-std`", err)
+            }
+            Self::NotNightly { triples } => Report::action_request(
+                "Building std from source needs a nightly toolchain",
+                format!(
+                    "`{}.build-std` is set for {}, and only nightly cargo can do that. Run `rustup toolchain install nightly --component rust-src`, then `rustup override set nightly` in your project (or pin nightly in a `rust-toolchain.toml`).",
+                    super::NAME,
+                    util::list_display(&triples[..])
+                ),
+            ),
+            Self::RustSrcMissing { triples } => Report::action_request(
+                "Building std from source needs the `rust-src` component",
+                format!(
+                    "`{}.build-std` is set for {}, which builds std from its source. Run `rustup component add rust-src` in your project to install it.",
+                    super::NAME,
+                    util::list_display(&triples[..])
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ToolchainCheckFailed(..) => "apple.target.build-std.toolchain-check-failed",
+            Self::NotNightly { .. } => "apple.target.build-std.not-nightly",
+            Self::RustSrcMissing { .. } => "apple.target.build-std.rust-src-missing",
+        }
+    }
+}
+
+fn check_toolchain(triples: Vec<String>) -> Result<(), BuildStdError> {
+    if !util::toolchain_is_nightly().map_err(BuildStdError::ToolchainCheckFailed)? {
+        return Err(BuildStdError::NotNightly { triples });
+    }
+    let components = util::installed_components().map_err(BuildStdError::ToolchainCheckFailed)?;
+    if components.iter().any(|component| component == "rust-src") {
+        Ok(())
+    } else {
+        Err(BuildStdError::RustSrcMissing { triples })
+    }
+}
+
+/// Makes sure std can be built for every target that needs it, since cargo
+/// otherwise only complains once it's in the middle of the build.
+pub fn check_build_std(config: &Config) -> Result<(), BuildStdError> {
+    let triples = config
+        .build_std_triples()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    if triples.is_empty() {
+        Ok(())
+    } else {
+        check_toolchain(triples)
+    }
 }
 
 #[derive(Debug)]
 pub enum CheckError {
     VersionCheckFailed(VersionCheckError),
+    BuildStdFailed(BuildStdError),
     CargoCheckFailed(bossy::Error),
 }
 
@@ -64,51 +206,198 @@ impl Reportable for CheckError {
     fn report(&self) -> Report {
         match self {
             Self::VersionCheckFailed(err) => err.report(),
+            Self::BuildStdFailed(err) => err.report(),
             Self::CargoCheckFailed(err) => Report::error("Failed to run `cargo check`", err),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::VersionCheckFailed(err) => err.code(),
+            Self::BuildStdFailed(err) => err.code(),
+            Self::CargoCheckFailed(..) => "apple.target.check.cargo-check-failed",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum CompileLibError {
     VersionCheckFailed(VersionCheckError),
+    BuildStdFailed(BuildStdError),
     CargoBuildFailed(bossy::Error),
+    LibMissing { path: PathBuf, lib_name: String },
+    EntryPointMissing(entry_point::Error),
 }
 
 impl Reportable for CompileLibError {
     fn report(&self) -> Report {
         match self {
             Self::VersionCheckFailed(err) => err.report(),
+            Self::BuildStdFailed(err) => err.report(),
             Self::CargoBuildFailed(err) => Report::error("Failed to run `cargo build`", err),
+            Self::LibMissing { path, lib_name } => Report::action_request(
+                "The built lib wasn't where it was expected",
+                format!(
+                    "Nothing was found at {:?}, which is where cargo puts a lib named \"{}\". If that's not what your lib is called, check `[lib] name` in `Cargo.toml`; otherwise, make sure `crate-type` under `[lib]` includes `\"staticlib\"`.",
+                    path, lib_name
+                ),
+            ),
+            Self::EntryPointMissing(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::VersionCheckFailed(err) => err.code(),
+            Self::BuildStdFailed(err) => err.code(),
+            Self::CargoBuildFailed(..) => "apple.target.compile-lib.cargo-build-failed",
+            Self::LibMissing { .. } => "apple.target.compile-lib.lib-missing",
+            Self::EntryPointMissing(err) => err.code(),
         }
     }
 }
 
 #[derive(Debug)]
-pub struct BuildError(bossy::Error);
+pub enum BuildError {
+    BuildConfigFailed(build_config::ResolveError),
+    XcodebuildFailed(xcodebuild::RunError),
+}
 
 impl Reportable for BuildError {
     fn report(&self) -> Report {
-        Report::error("Failed to build via `xcodebuild`", &self.0)
+        match self {
+            Self::BuildConfigFailed(err) => err.report(),
+            Self::XcodebuildFailed(err) => err.report("Failed to build via `xcodebuild`"),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::BuildConfigFailed(err) => err.code(),
+            Self::XcodebuildFailed(err) => err.code("apple.target.build.xcodebuild-failed"),
+        }
     }
 }
 
 #[derive(Debug)]
-pub struct ArchiveError(bossy::Error);
+pub enum ArchiveError {
+    BuildConfigFailed(build_config::ResolveError),
+    XcodebuildFailed(xcodebuild::RunError),
+}
 
 impl Reportable for ArchiveError {
     fn report(&self) -> Report {
-        Report::error("Failed to archive via `xcodebuild`", &self.0)
+        match self {
+            Self::BuildConfigFailed(err) => err.report(),
+            Self::XcodebuildFailed(err) => err.report("Failed to archive via `xcodebuild`"),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::BuildConfigFailed(err) => err.code(),
+            Self::XcodebuildFailed(err) => err.code("apple.target.archive.xcodebuild-failed"),
+        }
     }
 }
 
 #[derive(Debug)]
-pub struct ExportError(bossy::Error);
+pub enum ExportError {
+    OptionsWriteFailed(export::OptionsWriteError),
+    ExportFailed(xcodebuild::RunError),
+}
 
 impl Reportable for ExportError {
     fn report(&self) -> Report {
-        Report::error("Failed to export archive via `xcodebuild`", &self.0)
+        match self {
+            Self::OptionsWriteFailed(err) => {
+                Report::error("Failed to generate export options", err)
+            }
+            Self::ExportFailed(err) => err.report("Failed to export archive via `xcodebuild`"),
+        }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::OptionsWriteFailed(..) => "apple.target.export.options-write-failed",
+            Self::ExportFailed(err) => err.code("apple.target.export.export-failed"),
+        }
+    }
+}
+
+// Passed as a build setting so that it overrides whatever's in the project.
+// Automatic signing picks its own identity, and `xcodebuild` fails with
+// conflicting provisioning settings if we pick one too.
+fn code_sign_identity(config: &Config) -> Option<String> {
+    config
+        .identity()
+        .filter(|_| config.signing_style() == SigningStyle::Manual)
+        .map(|identity| format!("CODE_SIGN_IDENTITY={}", identity))
+}
+
+// Projects generated before we set this for release would otherwise leave us
+// without dSYMs to symbolicate crashes with.
+static DWARF_WITH_DSYM: &str = "DEBUG_INFORMATION_FORMAT=dwarf-with-dsym";
+
+// The project's build phase calls `xcode-script`, which gets `--features` from
+// `CARGO_MOBILE_FEATURES` the same way it gets `--force-color`, and likewise
+// for `--variant`. The config's env is set too, so that the project's other
+// build phases see it, along with the build config, which the Info.plist
+// picks up as build settings. `CARGO_BUILD_JOBS` reaches `xcode-script` the
+// same way, so that cargo gets as many jobs as `xcodebuild` does.
+fn xcodebuild_command(
+    config: &Config,
+    env: &Env,
+    profile: Profile,
+) -> Result<bossy::Command, build_config::ResolveError> {
+    let build_config = config.build_config().resolve(profile)?;
+    let features = Some(config.cli_features())
+        .filter(|features| !features.is_empty())
+        .map(|features| {
+            (
+                "CARGO_MOBILE_FEATURES",
+                format!("--features={}", features.join(",")),
+            )
+        });
+    let variant = config.variant().map(|variant| {
+        (
+            "CARGO_MOBILE_VARIANT",
+            format!("--variant={}", variant.name()),
+        )
+    });
+    let plan = resources::current();
+    Ok(bossy::Command::pure("xcodebuild")
+        .with_env_vars(env.explicit_env())
+        .with_env_vars(config.env())
+        .with_env_vars(build_config.env_vars())
+        .with_env_vars(features)
+        .with_env_vars(variant)
+        .with_env_vars(plan.env_vars())
+        .with_args(
+            plan.jobs()
+                .map(|jobs| vec!["-jobs".to_owned(), jobs.to_string()])
+                .unwrap_or_default(),
+        )
+        .with_args(variant_settings(config)))
+}
+
+// Build settings passed on the command line win over the project's, which is
+// what lets a variant change these without regenerating the project.
+fn variant_settings(config: &Config) -> Vec<String> {
+    let mut settings = Vec::new();
+    if let Some(variant) = config.variant() {
+        settings.push(format!("PRODUCT_BUNDLE_IDENTIFIER={}", config.bundle_id()));
+        if let Some(display_name) = variant.display_name() {
+            settings.push(format!("CARGO_MOBILE_DISPLAY_NAME={}", display_name));
+        }
+        if variant.icon().is_some() {
+            settings.push(format!(
+                "ASSETCATALOG_COMPILER_APPICON_NAME={}",
+                Config::variant_icon_set(variant)
+            ));
+        }
+    }
+    settings
 }
 
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
@@ -116,12 +405,67 @@ pub struct Target<'a> {
     pub triple: &'a str,
     pub arch: &'a str,
     alias: Option<&'a str>,
+    simulator: bool,
     min_xcode_version: Option<((u32, u32), &'static str)>,
 }
 
+// Device `arm64` and simulator `arm64` use different SDKs, so rustc treats them
+// as different targets.
+const AARCH64_SIMULATOR: Target<'static> = Target {
+    triple: "aarch64-apple-ios-sim",
+    arch: "arm64",
+    alias: None,
+    simulator: true,
+    min_xcode_version: Some(((12, 0), "iOS Simulator doesn't run on Apple Silicon until")),
+};
+
+const MACOS_X86_64: Target<'static> = Target {
+    triple: "x86_64-apple-darwin",
+    arch: "x86_64",
+    alias: None,
+    simulator: false,
+    min_xcode_version: None,
+};
+
+const MACOS_AARCH64: Target<'static> = Target {
+    triple: "aarch64-apple-darwin",
+    arch: "arm64",
+    alias: None,
+    simulator: false,
+    min_xcode_version: Some(((12, 2), "macOS doesn't run on Apple Silicon until")),
+};
+
+const CATALYST_X86_64: Target<'static> = Target {
+    triple: "x86_64-apple-ios-macabi",
+    arch: "x86_64",
+    alias: None,
+    simulator: false,
+    min_xcode_version: Some(((11, 0), "Mac Catalyst isn't supported until")),
+};
+
+const CATALYST_AARCH64: Target<'static> = Target {
+    triple: "aarch64-apple-ios-macabi",
+    arch: "arm64",
+    alias: None,
+    simulator: false,
+    min_xcode_version: Some(((12, 2), "Mac Catalyst doesn't run on Apple Silicon until")),
+};
+
 impl<'a> TargetTrait<'a> for Target<'a> {
     const DEFAULT_KEY: &'static str = "aarch64";
 
+    // Arches as Xcode names them, with `-sim` for the simulator.
+    const ALIASES: &'static [(&'static str, &'static str)] = &[
+        ("arm64", "aarch64"),
+        ("arm64-sim", "aarch64-sim"),
+        ("x86_64-sim", "x86_64"),
+    ];
+
+    fn possible_values() -> &'static [&'static str] {
+        static VALUES: OnceCell<Vec<&'static str>> = OnceCell::new();
+        VALUES.get_or_init(target::possible_values::<Target<'static>>)
+    }
+
     fn all() -> &'a BTreeMap<&'a str, Self> {
         static TARGETS: OnceCell<BTreeMap<&'static str, Target<'static>>> = OnceCell::new();
         TARGETS.get_or_init(|| {
@@ -132,6 +476,7 @@ impl<'a> TargetTrait<'a> for Target<'a> {
                     triple: "aarch64-apple-ios",
                     arch: "arm64",
                     alias: Some("arm64e"),
+                    simulator: false,
                     min_xcode_version: None,
                 },
             );
@@ -141,6 +486,7 @@ impl<'a> TargetTrait<'a> for Target<'a> {
                     triple: "x86_64-apple-ios",
                     arch: "x86_64",
                     alias: None,
+                    simulator: true,
                     // Simulator only supports Metal as of Xcode 11.0:
                     // https://developer.apple.com/documentation/metal/developing_metal_apps_that_run_in_simulator?language=objc
                     // While this doesn't matter if you aren't using Metal,
@@ -149,6 +495,11 @@ impl<'a> TargetTrait<'a> for Target<'a> {
                     min_xcode_version: Some(((11, 0), "iOS Simulator doesn't support Metal until")),
                 },
             );
+            // Intel hosts can only run the x86_64 simulator, so there's no
+            // point in installing this there.
+            if cfg!(target_arch = "aarch64") {
+                targets.insert("aarch64-sim", AARCH64_SIMULATOR);
+            }
             targets
         })
     }
@@ -160,27 +511,100 @@ impl<'a> TargetTrait<'a> for Target<'a> {
     fn arch(&'a self) -> &'a str {
         self.arch
     }
+
+    fn kind(&'a self) -> Kind {
+        if self.simulator {
+            Kind::Virtual
+        } else {
+            Kind::Physical
+        }
+    }
 }
 
 impl<'a> Target<'a> {
-    // TODO: Make this cleaner
+    /// The macOS target matching the host's architecture.
     pub fn macos() -> Self {
-        Self {
-            triple: "x86_64-apple-darwin",
-            arch: "x86_64",
-            alias: None,
-            min_xcode_version: None,
+        if cfg!(target_arch = "aarch64") {
+            MACOS_AARCH64
+        } else {
+            MACOS_X86_64
+        }
+    }
+
+    pub fn for_macos_arch(arch: &str) -> Option<Self> {
+        match arch {
+            "arm64" => Some(MACOS_AARCH64),
+            "x86_64" => Some(MACOS_X86_64),
+            _ => None,
         }
     }
 
     pub fn is_macos(&self) -> bool {
-        *self == Self::macos()
+        self.triple.ends_with("-apple-darwin")
+    }
+
+    /// The Mac Catalyst target matching the host's architecture.
+    pub fn catalyst() -> Self {
+        if cfg!(target_arch = "aarch64") {
+            CATALYST_AARCH64
+        } else {
+            CATALYST_X86_64
+        }
+    }
+
+    pub fn for_catalyst_arch(arch: &str) -> Option<Self> {
+        match arch {
+            "arm64" => Some(CATALYST_AARCH64),
+            "x86_64" => Some(CATALYST_X86_64),
+            _ => None,
+        }
+    }
+
+    pub fn is_catalyst(&self) -> bool {
+        self.triple.ends_with("-macabi")
+    }
+
+    /// The target for `apple.desktop`, if it isn't disabled.
+    pub fn desktop(desktop: Desktop) -> Option<Self> {
+        match desktop {
+            Desktop::MacOs => Some(Self::macos()),
+            Desktop::Catalyst => Some(Self::catalyst()),
+            Desktop::None => None,
+        }
+    }
+
+    pub fn is_simulator(&self) -> bool {
+        self.simulator
+    }
+
+    /// The simulator target matching the host's architecture.
+    pub fn simulator() -> Self {
+        if cfg!(target_arch = "aarch64") {
+            AARCH64_SIMULATOR
+        } else {
+            *Self::all()
+                .get("x86_64")
+                .expect("developer error: no x86_64 target")
+        }
     }
 
     pub fn for_arch(arch: &str) -> Option<&'a Self> {
+        // Device targets win, since `x86_64` is only ever a simulator arch
+        // whereas `arm64` is ambiguous.
         Self::all()
             .values()
-            .find(|target| target.arch == arch || target.alias == Some(arch))
+            .filter(|target| target.arch == arch || target.alias == Some(arch))
+            .min_by_key(|target| target.simulator)
+    }
+
+    pub fn for_simulator_arch(arch: &str) -> Option<Self> {
+        match arch {
+            // Xcode still builds this for release on Intel hosts, so it's
+            // handled even though it isn't in `all` there.
+            "arm64" => Some(AARCH64_SIMULATOR),
+            "x86_64" => Self::all().get("x86_64").copied(),
+            _ => None,
+        }
     }
 
     fn min_xcode_version_satisfied(&self) -> Result<(), VersionCheckError> {
@@ -201,6 +625,31 @@ impl<'a> Target<'a> {
             .unwrap_or_else(|| Ok(()))
     }
 
+    /// Installs the target with `rustup`, unless it builds std from source,
+    /// in which case there's nothing to install (and likely nothing that
+    /// could be).
+    pub fn install_for(&'a self, config: &Config) -> bossy::Result<()> {
+        if config.build_std(self.triple).is_some() {
+            log::info!(
+                "not installing {}, since it builds std from source",
+                self.triple
+            );
+            Ok(())
+        } else {
+            self.install()
+        }
+    }
+
+    // Checked again here, since Xcode can call us without going through our
+    // CLI first.
+    fn build_std<'c>(&self, config: &'c Config) -> Result<Option<&'c [String]>, BuildStdError> {
+        let build_std = config.build_std(self.triple);
+        if build_std.is_some() {
+            check_toolchain(vec![self.triple.to_owned()])?;
+        }
+        Ok(build_std)
+    }
+
     fn cargo(
         &'a self,
         config: &'a Config,
@@ -219,9 +668,22 @@ impl<'a> Target<'a> {
                 .with_target(Some(&self.triple))
                 .with_no_default_features(metadata.no_default_features())
                 .with_features(metadata.features())
+                .with_jobs(resources::current().jobs())
         })
     }
 
+    // macOS builds are desktop builds, which the config's flags aren't for.
+    fn encoded_rustflags(&self, config: &Config) -> Option<String> {
+        if self.is_macos() {
+            return None;
+        }
+        let dir = std::env::current_dir().unwrap_or_else(|_| config.app().root_dir().to_owned());
+        let base = dot_cargo::configured_target(&dir, self.triple)
+            .rustflags
+            .map(|(flags, path)| (format!("cargo config at {:?}", path), flags));
+        config.rustflags().encoded(self.triple, base, config.env())
+    }
+
     pub fn check(
         &self,
         config: &Config,
@@ -229,15 +691,90 @@ impl<'a> Target<'a> {
         env: &Env,
         noise_level: NoiseLevel,
     ) -> Result<(), CheckError> {
+        let build_std = self.build_std(config).map_err(CheckError::BuildStdFailed)?;
+        let rustflags = self
+            .encoded_rustflags(config)
+            .map(|encoded| (rustflags::VAR, encoded));
         self.cargo(config, metadata, "check")
             .map_err(CheckError::VersionCheckFailed)?
+            .with_build_std(build_std)
             .with_verbose(noise_level.pedantic())
             .into_command_pure(env)
+            .with_env_vars(config.env())
+            .with_env_vars(rustflags)
             .run_and_wait()
             .map_err(CheckError::CargoCheckFailed)?;
         Ok(())
     }
 
+    /// Where the SDK Xcode builds this target against lives.
+    pub fn sdk_root(&self, env: &Env) -> bossy::Result<PathBuf> {
+        let sdk = if self.is_macos() || self.is_catalyst() {
+            "macosx"
+        } else if self.simulator {
+            "iphonesimulator"
+        } else {
+            "iphoneos"
+        };
+        bossy::Command::pure("xcrun")
+            .with_env_vars(env.explicit_env())
+            .with_args(&["--sdk", sdk, "--show-sdk-path"])
+            .run_and_wait_for_str(|path| PathBuf::from(path.trim()))
+    }
+
+    /// Everything cargo is run with when Xcode's build phase builds for this
+    /// target against the SDK at `sdk_root`, which includes the config's `env`.
+    pub fn build_env(
+        &self,
+        config: &Config,
+        env: &Env,
+        sdk_root: &Path,
+    ) -> Result<BuildEnv, BuildEnvError> {
+        if !sdk_root.is_dir() {
+            return Err(BuildEnvError::SdkRootInvalid {
+                sdk_root: sdk_root.to_owned(),
+            });
+        }
+        let include_dir = sdk_root.join("usr/include");
+        if !include_dir.is_dir() {
+            return Err(BuildEnvError::IncludeDirInvalid { include_dir });
+        }
+
+        // Host flags that are used by build scripts
+        let macos_isysroot = {
+            let macos_sdk_root =
+                sdk_root.join("../../../../MacOSX.platform/Developer/SDKs/MacOSX.sdk");
+            if !macos_sdk_root.is_dir() {
+                return Err(BuildEnvError::MacosSdkRootInvalid { macos_sdk_root });
+            }
+            format!("-isysroot {}", macos_sdk_root.display())
+        };
+        let mut build_env = BuildEnv::new(env)
+            .with_var("MAC_FLAGS", &macos_isysroot)
+            .with_var("CFLAGS_x86_64_apple_darwin", &macos_isysroot)
+            .with_var("CXXFLAGS_x86_64_apple_darwin", &macos_isysroot)
+            .with_var("CFLAGS_aarch64_apple_darwin", &macos_isysroot)
+            .with_var("CXXFLAGS_aarch64_apple_darwin", &macos_isysroot)
+            .with_var("OBJC_INCLUDE_PATH_x86_64_apple_darwin", &include_dir)
+            .with_var("OBJC_INCLUDE_PATH_aarch64_apple_darwin", &include_dir)
+            .with_var("RUST_BACKTRACE", "1");
+
+        // Set target-specific flags
+        let isysroot = format!("-isysroot {}", sdk_root.display());
+        let triple = self.triple.replace('-', "_");
+        build_env.insert(format!("CFLAGS_{}", triple), &isysroot);
+        build_env.insert(format!("CXXFLAGS_{}", triple), &isysroot);
+        build_env.insert(format!("OBJC_INCLUDE_PATH_{}", triple), &include_dir);
+        for (key, value) in config.env() {
+            build_env.insert(key.as_str(), value);
+        }
+        // This overrides any rustflags set above, so they're merged into it.
+        if let Some(encoded) = self.encoded_rustflags(config) {
+            build_env.insert(rustflags::VAR, encoded);
+        }
+        Ok(build_env)
+    }
+
     // NOTE: it's up to Xcode to pass the verbose flag here, so even when
     // using our build/run commands it won't get passed.
     // TODO: do something about that?
@@ -248,72 +785,337 @@ impl<'a> Target<'a> {
         noise_level: NoiseLevel,
         force_color: ForceColor,
         profile: Profile,
-        env: &Env,
-        cc_env: HashMap<&str, &OsStr>,
+        build_env: &BuildEnv,
     ) -> Result<(), CompileLibError> {
         // Force color when running from CLI
         let color = if force_color.yes() { "always" } else { "auto" };
+        let build_std = self
+            .build_std(config)
+            .map_err(CompileLibError::BuildStdFailed)?;
         self.cargo(config, metadata, "build")
             .map_err(CompileLibError::VersionCheckFailed)?
+            .with_build_std(build_std)
             .with_verbose(noise_level.pedantic())
             .with_release(profile.release())
-            .into_command_pure(env)
-            .with_env_vars(cc_env)
+            .into_command_pure(build_env)
             .with_args(&["--color", color])
             .run_and_wait()
             .map_err(CompileLibError::CargoBuildFailed)?;
-        Ok(())
+        // Xcode would otherwise fail at link time with just "file not found".
+        let lib_path = config.lib_path(self.triple, profile);
+        if !lib_path.is_file() {
+            return Err(CompileLibError::LibMissing {
+                path: lib_path,
+                lib_name: config.app().names().lib_name().to_owned(),
+            });
+        }
+        // ...and with "Undefined symbols" if the entry point's missing.
+        entry_point::check(
+            bossy::Command::pure("xcrun")
+                .with_env_vars(build_env.explicit_env())
+                .with_args(&["nm", "-g", "--defined-only"]),
+            lib_path,
+            config.entry_symbols(),
+            true,
+            "apple.entry-symbols",
+        )
+        .map_err(CompileLibError::EntryPointMissing)
+    }
+
+    /// Runs the configured hooks for `stage` with this target's build env.
+    /// Post-build hooks get the lib as their artifact unless told otherwise.
+    pub fn run_hooks(
+        &self,
+        config: &Config,
+        env: &Env,
+        stage: Stage,
+        profile: Profile,
+        no_hooks: NoHooks,
+        artifact: Option<&Path>,
+    ) -> Result<(), HookError> {
+        let hooks = config.hooks().unless(no_hooks);
+        if !hooks.has(stage) {
+            return Ok(());
+        }
+        let sdk_root = self.sdk_root(env).map_err(HookError::SdkRootFailed)?;
+        let build_env = self
+            .build_env(config, env, &sdk_root)
+            .map_err(HookError::BuildEnvFailed)?;
+        let lib_path = config.lib_path(self.triple, profile);
+        let artifact = artifact.or_else(|| {
+            if stage == Stage::PostBuild {
+                Some(lib_path.as_path())
+            } else {
+                None
+            }
+        });
+        hooks
+            .run(
+                stage,
+                config.app().root_dir(),
+                &build_env,
+                self.triple,
+                profile,
+                artifact,
+            )
+            .map_err(HookError::HookFailed)
+    }
+
+    // Keeping builds out of Xcode's global DerivedData means we know where the
+    // app ends up.
+    fn derived_data_dir(&self, config: &Config) -> PathBuf {
+        if self.simulator {
+            config.simulator_derived_data_dir()
+        } else {
+            config.device_derived_data_dir()
+        }
+    }
+
+    /// Where [`Target::build`] puts the app.
+    pub fn built_app_path(&self, config: &Config, profile: opts::Profile) -> PathBuf {
+        if self.simulator {
+            config.simulator_app_path(profile)
+        } else {
+            config.device_app_path(profile)
+        }
+    }
+
+    /// Measures the static lib and the app it was linked into, as left by
+    /// [`Target::build`].
+    pub fn measure(
+        &self,
+        config: &Config,
+        profile: opts::Profile,
+        sizes: &mut Sizes,
+    ) -> Result<(), sizes::Error> {
+        let lib_path = config.lib_path(self.triple, profile);
+        let lib_name = lib_path
+            .file_name()
+            .expect("developer error: lib path had no file name")
+            .to_string_lossy()
+            .into_owned();
+        sizes.measure(format!("{} ({})", lib_name, self.triple), &lib_path, None)?;
+        let app_path = self.built_app_path(config, profile);
+        let app_name = app_path
+            .file_name()
+            .expect("developer error: app path had no file name")
+            .to_string_lossy()
+            .into_owned();
+        let app_name = if self.simulator {
+            format!("{} (simulator)", app_name)
+        } else {
+            app_name
+        };
+        sizes.measure_package(app_name, &app_path)
     }
 
+    /// Everything that goes into [`Target::build`]. The Rust lib is only
+    /// built from within Xcode, so the app's sources stand in for it.
+    fn fingerprint(
+        &self,
+        config: &Config,
+        profile: opts::Profile,
+        build_config: &build_config::Resolved,
+    ) -> Fingerprint {
+        let root = config.app().root_dir();
+        let project_dir = config.project_dir();
+        let fingerprint = Fingerprint::new()
+            .with_value("cargo-mobile", env!("CARGO_PKG_VERSION"))
+            .with_value("profile", profile.as_str())
+            .with_value("build-config", build_config.digest())
+            .with_value("features", config.cli_features().join(","))
+            .with_value(
+                "variant",
+                config
+                    .variant()
+                    .map(|variant| variant.name())
+                    .unwrap_or_default(),
+            )
+            .with_value("team", config.development_team())
+            .with_value("identity", code_sign_identity(config).unwrap_or_default())
+            .with_sources(root, &[project_dir.clone(), root.join("target")])
+            .with_file(project_dir.join("project.yml"))
+            .with_file(config.pbxproj_path())
+            .with_file(config.lib_path(self.triple, profile))
+            .with_dir(self.built_app_path(config, profile));
+        match Sources::from_config(config) {
+            Ok(sources) => sources
+                .watched()
+                .into_iter()
+                .fold(fingerprint, |fingerprint, path| {
+                    if path.is_dir() {
+                        fingerprint.with_dir(path)
+                    } else {
+                        fingerprint.with_file(path)
+                    }
+                }),
+            Err(err) => fingerprint.with_value("sources", err.code()),
+        }
+    }
+
+    /// Builds the app with `xcodebuild`, which is skipped entirely if nothing
+    /// that goes into it has changed since the last build (unless `force` is
+    /// set). Forcing also clears the fingerprints of the steps Xcode runs
+    /// through `xcode-script`, so that those are redone as well.
     pub fn build(
         &self,
         config: &Config,
         env: &Env,
         noise_level: opts::NoiseLevel,
         profile: opts::Profile,
+        force: opts::Force,
     ) -> Result<(), BuildError> {
-        let configuration = profile.as_str();
-        bossy::Command::pure("xcodebuild")
-            .with_env_vars(env.explicit_env())
+        let build_config = config
+            .build_config()
+            .resolve(profile)
+            .map_err(BuildError::BuildConfigFailed)?;
+        let store = Store::new(config.project_dir());
+        let step = format!("build-{}-{}", self.triple, profile.as_str());
+        if force.yes() {
+            store.clear();
+        } else if store.fresh(&step, &self.fingerprint(config, profile, &build_config)) {
+            ui::status(format!(
+                "The app for {} is up to date, so xcodebuild was skipped",
+                self.triple
+            ));
+            return Ok(());
+        }
+        store.forget(&step);
+        let configuration = config.configuration(profile);
+        let command = xcodebuild_command(config, env, profile)
+            .map_err(BuildError::BuildConfigFailed)?
             .with_env_var("FORCE_COLOR", "--force-color")
-            .with_args(verbosity(noise_level))
             .with_args(&["-scheme", &config.scheme()])
             .with_arg("-workspace")
             .with_arg(&config.workspace_path())
-            .with_args(&["-configuration", configuration])
+            .with_args(if self.simulator {
+                &["-sdk", "iphonesimulator"][..]
+            } else {
+                &[][..]
+            })
+            .with_args(&["-configuration", configuration.as_str()])
             .with_args(&["-arch", self.arch])
+            .with_args(code_sign_identity(config))
+            .with_args(Some(DWARF_WITH_DSYM).filter(|_| profile.release()))
+            .with_arg("-derivedDataPath")
+            .with_arg(self.derived_data_dir(config))
             .with_arg("-allowProvisioningUpdates")
-            .with_arg("build")
-            .run_and_wait()
-            .map_err(BuildError)?;
+            .with_arg("build");
+        progress::phase(Phase::Xcodebuild, None, || {
+            xcodebuild::run(
+                command,
+                noise_level,
+                &config.log_path("build"),
+                config.development_team(),
+            )
+        })
+        .map_err(BuildError::XcodebuildFailed)?;
+        store.record(&step, &self.fingerprint(config, profile, &build_config));
         Ok(())
     }
 
+    pub fn build_for_simulator(
+        &self,
+        config: &Config,
+        env: &Env,
+        noise_level: opts::NoiseLevel,
+        profile: opts::Profile,
+        udid: &str,
+    ) -> Result<(), BuildError> {
+        let configuration = config.configuration(profile);
+        let command = xcodebuild_command(config, env, profile)
+            .map_err(BuildError::BuildConfigFailed)?
+            .with_env_var("FORCE_COLOR", "--force-color")
+            .with_args(&["-scheme", &config.scheme()])
+            .with_arg("-workspace")
+            .with_arg(&config.workspace_path())
+            .with_args(&["-sdk", "iphonesimulator"])
+            .with_arg("-destination")
+            .with_arg(format!("platform=iOS Simulator,id={}", udid))
+            .with_args(&["-configuration", configuration.as_str()])
+            .with_args(&["-arch", self.arch])
+            .with_arg("-derivedDataPath")
+            .with_arg(&config.simulator_derived_data_dir())
+            .with_arg("build");
+        progress::phase(Phase::Xcodebuild, None, || {
+            xcodebuild::run(
+                command,
+                noise_level,
+                &config.log_path("build-simulator"),
+                config.development_team(),
+            )
+        })
+        .map_err(BuildError::XcodebuildFailed)
+    }
+
+    pub fn build_for_desktop(
+        &self,
+        config: &Config,
+        env: &Env,
+        noise_level: opts::NoiseLevel,
+        profile: opts::Profile,
+    ) -> Result<(), BuildError> {
+        let configuration = config.configuration(profile);
+        let destination = if self.is_catalyst() {
+            "platform=macOS,variant=Mac Catalyst"
+        } else {
+            "platform=macOS"
+        };
+        let command = xcodebuild_command(config, env, profile)
+            .map_err(BuildError::BuildConfigFailed)?
+            .with_env_var("FORCE_COLOR", "--force-color")
+            .with_args(&["-scheme", &config.desktop_scheme()])
+            .with_arg("-workspace")
+            .with_arg(&config.workspace_path())
+            .with_args(&["-destination", destination])
+            .with_args(&["-configuration", configuration.as_str()])
+            .with_args(&["-arch", self.arch])
+            .with_arg("-derivedDataPath")
+            .with_arg(&config.desktop_derived_data_dir())
+            .with_arg("build");
+        progress::phase(Phase::Xcodebuild, None, || {
+            xcodebuild::run(
+                command,
+                noise_level,
+                &config.log_path("build-desktop"),
+                config.development_team(),
+            )
+        })
+        .map_err(BuildError::XcodebuildFailed)
+    }
+
     pub fn archive(
         &self,
         config: &Config,
         env: &Env,
         noise_level: opts::NoiseLevel,
         profile: opts::Profile,
+        archive_path: &Path,
     ) -> Result<(), ArchiveError> {
-        let configuration = profile.as_str();
-        let archive_path = config.archive_dir().join(&config.scheme());
-        bossy::Command::pure("xcodebuild")
-            .with_env_vars(env.explicit_env())
-            .with_args(verbosity(noise_level))
+        let configuration = config.configuration(profile);
+        let command = xcodebuild_command(config, env, profile)
+            .map_err(ArchiveError::BuildConfigFailed)?
             .with_args(&["-scheme", &config.scheme()])
             .with_arg("-workspace")
             .with_arg(&config.workspace_path())
             .with_args(&["-sdk", "iphoneos"])
-            .with_args(&["-configuration", configuration])
+            .with_args(&["-configuration", configuration.as_str()])
             .with_args(&["-arch", self.arch])
+            .with_args(code_sign_identity(config))
+            .with_arg(DWARF_WITH_DSYM)
             .with_arg("-allowProvisioningUpdates")
             .with_arg("archive")
             .with_arg("-archivePath")
-            .with_arg(&archive_path)
-            .run_and_wait()
-            .map_err(ArchiveError)?;
-        Ok(())
+            .with_arg(archive_path);
+        progress::phase(Phase::XcodebuildArchive, None, || {
+            xcodebuild::run(
+                command,
+                noise_level,
+                &config.log_path("archive"),
+                config.development_team(),
+            )
+        })
+        .map_err(ArchiveError::XcodebuildFailed)
     }
 
     pub fn export(
@@ -321,23 +1123,30 @@ impl<'a> Target<'a> {
         config: &Config,
         env: &Env,
         noise_level: opts::NoiseLevel,
+        archive_path: &Path,
+        method: export::Method,
+        export_dir: &Path,
     ) -> Result<(), ExportError> {
-        // Super fun discrepancy in expectation of `-archivePath` value
-        let archive_path = config
-            .archive_dir()
-            .join(&format!("{}.xcarchive", config.scheme()));
-        bossy::Command::pure("xcodebuild")
+        let options_path =
+            export::write_options(config, method).map_err(ExportError::OptionsWriteFailed)?;
+        let command = bossy::Command::pure("xcodebuild")
             .with_env_vars(env.explicit_env())
-            .with_args(verbosity(noise_level))
             .with_arg("-exportArchive")
             .with_arg("-archivePath")
-            .with_arg(&archive_path)
+            .with_arg(archive_path)
             .with_arg("-exportOptionsPlist")
-            .with_arg(&config.export_plist_path())
+            .with_arg(&options_path)
             .with_arg("-exportPath")
-            .with_arg(&config.export_dir())
-            .run_and_wait()
-            .map_err(ExportError)?;
-        Ok(())
+            .with_arg(export_dir)
+            .with_arg("-allowProvisioningUpdates");
+        progress::phase(Phase::XcodebuildExport, None, || {
+            xcodebuild::run(
+                command,
+                noise_level,
+                &config.log_path("export"),
+                config.development_team(),
+            )
+        })
+        .map_err(ExportError::ExportFailed)
     }
 }
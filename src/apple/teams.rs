@@ -1,3 +1,4 @@
+use once_cell_regex::regex;
 use openssl::{
     error::ErrorStack as OpenSslError,
     nid::Nid,
@@ -22,6 +23,18 @@ pub fn get_pem_list_new_name_scheme() -> bossy::Result<bossy::Output> {
     get_pem_list("Development:")
 }
 
+pub fn get_codesigning_identities() -> bossy::Result<Vec<String>> {
+    // `-v` filters out expired and revoked identities
+    bossy::Command::impure("security")
+        .with_args(&["find-identity", "-v", "-p", "codesigning"])
+        .run_and_wait_for_str(|output| {
+            regex!(r#"(?m)^\s*\d+\) [0-9A-F]+ "(.+)"$"#)
+                .captures_iter(output)
+                .map(|caps| caps[1].to_owned())
+                .collect()
+        })
+}
+
 #[derive(Debug)]
 pub enum Error {
     SecurityCommandFailed(bossy::Error),
@@ -59,6 +72,12 @@ pub struct Team {
     pub id: String,
 }
 
+impl Display for Team {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.id)
+    }
+}
+
 impl Team {
     pub fn from_x509(cert: X509) -> Result<Self, Error> {
         let subj = cert.subject_name();
@@ -68,17 +87,35 @@ impl Team {
     }
 }
 
+fn find_development_certs() -> Result<Vec<X509>, Error> {
+    let new = get_pem_list_new_name_scheme().map_err(Error::SecurityCommandFailed)?;
+    let mut certs = X509::stack_from_pem(new.stdout()).map_err(Error::X509ParseFailed)?;
+    let old = get_pem_list_old_name_scheme().map_err(Error::SecurityCommandFailed)?;
+    certs.append(&mut X509::stack_from_pem(old.stdout()).map_err(Error::X509ParseFailed)?);
+    Ok(certs)
+}
+
 pub fn find_development_teams() -> Result<Vec<Team>, Error> {
-    let certs = {
-        let new = get_pem_list_new_name_scheme().map_err(Error::SecurityCommandFailed)?;
-        let mut certs = X509::stack_from_pem(new.stdout()).map_err(Error::X509ParseFailed)?;
-        let old = get_pem_list_old_name_scheme().map_err(Error::SecurityCommandFailed)?;
-        certs.append(&mut X509::stack_from_pem(old.stdout()).map_err(Error::X509ParseFailed)?);
-        certs
-    };
+    let certs = find_development_certs()?;
     let mut teams = BTreeSet::new();
     for cert in certs {
         teams.insert(Team::from_x509(cert)?);
     }
     Ok(teams.into_iter().collect())
 }
+
+/// Checks if this machine has a valid codesigning identity for the team. Teams
+/// are often shared between machines, so a `false` here doesn't mean the team
+/// ID is wrong, just that we can't sign for it locally.
+pub fn has_codesigning_identity(team_id: &str) -> Result<bool, Error> {
+    let identities = get_codesigning_identities().map_err(Error::SecurityCommandFailed)?;
+    for cert in find_development_certs()? {
+        let subj = cert.subject_name();
+        if get_x509_field(subj, Nid::ORGANIZATIONALUNITNAME)? == team_id
+            && identities.contains(&get_x509_field(subj, Nid::COMMONNAME)?)
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,224 @@
+use super::{device::Device, devicectl, simctl::Simulator};
+use crate::{
+    env::{Env, ExplicitEnv as _},
+    transfer,
+    util::cli::{Report, Reportable},
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    ContainerFailed {
+        bundle_id: String,
+        simulator: String,
+        cause: bossy::Error,
+    },
+    Match(transfer::Error),
+    CopyFailed {
+        from: PathBuf,
+        to: PathBuf,
+        cause: io::Error,
+    },
+    DevicectlMissing,
+    DeviceWildcardUnsupported {
+        pattern: String,
+    },
+    DeviceCopyFailed(bossy::Error),
+    OutputMissing {
+        path: PathBuf,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ContainerFailed {
+                bundle_id,
+                simulator,
+                cause,
+            } => Report::error(
+                format!("Failed to find the data of `{}` on {}", bundle_id, simulator),
+                format!(
+                    "{}\nThe app has to be installed on the simulator; `cargo apple run` will do that.",
+                    cause
+                ),
+            ),
+            Self::Match(err) => err.report(),
+            Self::CopyFailed { from, to, cause } => Report::error(
+                format!("Failed to copy {:?} to {:?}", from, to),
+                cause,
+            ),
+            Self::DevicectlMissing => Report::action_request(
+                "Copying files on devices requires `devicectl`",
+                "`devicectl` comes with Xcode 15 and later; there's no other command-line tool that can get at an app's data on a device, so update Xcode and try again.",
+            ),
+            Self::DeviceWildcardUnsupported { pattern } => Report::action_request(
+                format!("{:?} can't be matched on a device", pattern),
+                "Wildcards can only be matched on simulators, since `devicectl` can't list an app's files in a way we can rely on. Give the file's exact path instead.",
+            ),
+            Self::DeviceCopyFailed(err) => Report::error(
+                "Failed to copy file",
+                format!(
+                    "{}\nThe device needs to be unlocked and trust this computer, and the app has to be installed on it.",
+                    err
+                ),
+            ),
+            Self::OutputMissing { path } => Report::error(
+                "Copied file appears to be missing",
+                format!("Nothing was written to {:?}", path),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ContainerFailed { .. } => "apple.transfer.container-failed",
+            Self::Match(err) => err.code(),
+            Self::CopyFailed { .. } => "apple.transfer.copy-failed",
+            Self::DevicectlMissing => "apple.transfer.devicectl-missing",
+            Self::DeviceWildcardUnsupported { .. } => "apple.transfer.device-wildcard-unsupported",
+            Self::DeviceCopyFailed(..) => "apple.transfer.device-copy-failed",
+            Self::OutputMissing { .. } => "apple.transfer.output-missing",
+        }
+    }
+}
+
+/// Where the app's data is.
+#[derive(Clone, Copy, Debug)]
+pub enum Endpoint<'a> {
+    Simulator(&'a Simulator),
+    Device(&'a Device<'a>),
+}
+
+// A simulator's app data is just a dir on this machine, so we can copy to and
+// from it ourselves.
+fn container(env: &Env, simulator: &Simulator, bundle_id: &str) -> Result<PathBuf, Error> {
+    bossy::Command::pure("xcrun")
+        .with_env_vars(env.explicit_env())
+        .with_args(&[
+            "simctl",
+            "get_app_container",
+            simulator.udid(),
+            bundle_id,
+            "data",
+        ])
+        .run_and_wait_for_str(|output| PathBuf::from(output.trim()))
+        .map_err(|cause| Error::ContainerFailed {
+            bundle_id: bundle_id.to_owned(),
+            simulator: simulator.to_string(),
+            cause,
+        })
+}
+
+fn copy(from: &Path, to: &Path) -> Result<u64, Error> {
+    fs::copy(from, to).map_err(|cause| Error::CopyFailed {
+        from: from.to_owned(),
+        to: to.to_owned(),
+        cause,
+    })
+}
+
+fn devicectl_copy(
+    env: &Env,
+    device: &Device<'_>,
+    bundle_id: &str,
+    direction: &str,
+    source: &str,
+    destination: &str,
+) -> Result<(), Error> {
+    if !devicectl::available(env) {
+        return Err(Error::DevicectlMissing);
+    }
+    bossy::Command::pure("xcrun")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["devicectl", "device", "copy", direction])
+        .with_args(&["--device", device.id()])
+        .with_args(&["--domain-type", "appDataContainer"])
+        .with_args(&["--domain-identifier", bundle_id])
+        .with_args(&["--source", source, "--destination", destination])
+        .run_and_wait_for_output()
+        .map_err(Error::DeviceCopyFailed)?;
+    Ok(())
+}
+
+/// Copies `remote`, relative to the app's data dir, to `local`.
+pub fn pull(
+    env: &Env,
+    endpoint: Endpoint<'_>,
+    bundle_id: &str,
+    remote: &str,
+    local: Option<&Path>,
+) -> Result<(), Error> {
+    match endpoint {
+        Endpoint::Simulator(simulator) => {
+            let container = container(env, simulator, bundle_id)?;
+            let remote = if transfer::has_wildcard(remote) {
+                transfer::only_match(remote, transfer::glob(&container, remote))
+                    .map_err(Error::Match)?
+            } else {
+                remote.to_owned()
+            };
+            let path = transfer::local_path(&remote, local);
+            let len = copy(&container.join(remote.trim_start_matches('/')), &path)?;
+            transfer::report_pulled(&remote, simulator, &path, len);
+        }
+        Endpoint::Device(device) => {
+            if transfer::has_wildcard(remote) {
+                return Err(Error::DeviceWildcardUnsupported {
+                    pattern: remote.to_owned(),
+                });
+            }
+            let path = transfer::local_path(remote, local);
+            devicectl_copy(
+                env,
+                device,
+                bundle_id,
+                "from",
+                remote,
+                &path.to_string_lossy(),
+            )?;
+            let len = fs::metadata(&path)
+                .map(|metadata| metadata.len())
+                .map_err(|_| Error::OutputMissing { path: path.clone() })?;
+            transfer::report_pulled(remote, device, &path, len);
+        }
+    }
+    Ok(())
+}
+
+/// Copies `local` to `remote`, relative to the app's data dir, or into it if
+/// it ends with a `/`.
+pub fn push(
+    env: &Env,
+    endpoint: Endpoint<'_>,
+    bundle_id: &str,
+    local: &Path,
+    remote: &str,
+) -> Result<(), Error> {
+    let remote = transfer::remote_path(local, remote);
+    match endpoint {
+        Endpoint::Simulator(simulator) => {
+            let container = container(env, simulator, bundle_id)?;
+            let len = copy(local, &container.join(remote.trim_start_matches('/')))?;
+            transfer::report_pushed(local, simulator, &remote, len);
+        }
+        Endpoint::Device(device) => {
+            devicectl_copy(
+                env,
+                device,
+                bundle_id,
+                "to",
+                &local.to_string_lossy(),
+                &remote,
+            )?;
+            let len = fs::metadata(local)
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+            transfer::report_pushed(local, device, &remote, len);
+        }
+    }
+    Ok(())
+}
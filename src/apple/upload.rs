@@ -0,0 +1,205 @@
+use crate::{
+    env::{Env, ExplicitEnv as _},
+    ui,
+    util::cli::{Report, Reportable},
+};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// App Store Connect API key credentials. These are only ever handed to
+/// `altool`, and never logged or included in error messages.
+#[derive(Debug)]
+pub struct ApiKey {
+    pub key_path: PathBuf,
+    pub key_id: String,
+    pub issuer_id: String,
+}
+
+impl ApiKey {
+    fn expected_file_name(&self) -> String {
+        format!("AuthKey_{}.p8", self.key_id)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct UserInfo {
+    #[serde(rename = "NSLocalizedFailureReason")]
+    failure_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProductError {
+    message: String,
+    code: Option<i64>,
+    #[serde(rename = "userInfo", default)]
+    user_info: UserInfo,
+}
+
+impl ProductError {
+    fn hint(&self) -> Option<&'static str> {
+        let reason = self.user_info.failure_reason.as_deref().unwrap_or_default();
+        let matches = |needle: &str| {
+            self.message.to_lowercase().contains(needle) || reason.to_lowercase().contains(needle)
+        };
+        if matches("redundant binary upload") {
+            Some("A build with this build number was already uploaded; bump `CFBundleVersion` and archive again.")
+        } else if matches("compliance") {
+            Some("Set `ITSAppUsesNonExemptEncryption` in your Info.plist (or answer the export compliance questions in App Store Connect).")
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AltoolOutput {
+    success_message: Option<String>,
+    #[serde(default)]
+    product_errors: Vec<ProductError>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IpaMissing { path: PathBuf },
+    IpaInvalid { path: PathBuf },
+    KeyMissing { path: PathBuf },
+    KeyFileNameInvalid { path: PathBuf, expected: String },
+    AltoolFailed { exit_code: Option<i32> },
+    OutputParseFailed(serde_json::Error),
+    Rejected(Vec<ProductError>),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::IpaMissing { path } => Report::action_request(
+                "IPA appears to be missing",
+                format!("Nothing was found at {:?}; run `cargo apple archive --export-method app-store` first.", path),
+            ),
+            Self::IpaInvalid { path } => Report::error(
+                "Only IPAs can be uploaded",
+                format!("{:?} doesn't have an `.ipa` extension", path),
+            ),
+            Self::KeyMissing { path } => Report::error(
+                "App Store Connect API key is missing",
+                format!("No file was found at {:?}", path),
+            ),
+            Self::KeyFileNameInvalid { path, expected } => Report::action_request(
+                "App Store Connect API key has an unexpected file name",
+                format!("`altool` only finds keys named like they were when downloaded from App Store Connect, so please rename {:?} to {:?}.", path, expected),
+            ),
+            Self::AltoolFailed { exit_code } => Report::error(
+                "Failed to run `xcrun altool`",
+                match exit_code {
+                    Some(exit_code) => format!("`altool` exited with code {}", exit_code),
+                    None => "`altool` couldn't be run".to_owned(),
+                },
+            ),
+            Self::OutputParseFailed(err) => {
+                Report::error("Failed to parse `xcrun altool` output", err)
+            }
+            Self::Rejected(errors) => {
+                let details = errors
+                    .iter()
+                    .map(|error| {
+                        let mut details = error.message.clone();
+                        if let Some(code) = error.code {
+                            details.push_str(&format!(" (error {})", code));
+                        }
+                        if let Some(reason) = &error.user_info.failure_reason {
+                            details.push_str(&format!("\n{}", reason));
+                        }
+                        if let Some(hint) = error.hint() {
+                            details.push_str(&format!("\n{}", hint));
+                        }
+                        details
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                Report::error("App Store Connect rejected the upload", details)
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::IpaMissing { .. } => "apple.upload.ipa-missing",
+            Self::IpaInvalid { .. } => "apple.upload.ipa-invalid",
+            Self::KeyMissing { .. } => "apple.upload.key-missing",
+            Self::KeyFileNameInvalid { .. } => "apple.upload.key-file-name-invalid",
+            Self::AltoolFailed { .. } => "apple.upload.altool-failed",
+            Self::OutputParseFailed(..) => "apple.upload.output-parse-failed",
+            Self::Rejected(..) => "apple.upload.rejected",
+        }
+    }
+}
+
+// We'd rather find out about these problems now than after a long upload.
+fn check_inputs(ipa_path: &Path, api_key: &ApiKey) -> Result<(), Error> {
+    if !ipa_path.is_file() {
+        return Err(Error::IpaMissing {
+            path: ipa_path.to_owned(),
+        });
+    }
+    if ipa_path.extension().and_then(|ext| ext.to_str()) != Some("ipa") {
+        return Err(Error::IpaInvalid {
+            path: ipa_path.to_owned(),
+        });
+    }
+    if !api_key.key_path.is_file() {
+        return Err(Error::KeyMissing {
+            path: api_key.key_path.clone(),
+        });
+    }
+    let expected = api_key.expected_file_name();
+    if api_key.key_path.file_name().and_then(|name| name.to_str()) != Some(&expected) {
+        return Err(Error::KeyFileNameInvalid {
+            path: api_key.key_path.clone(),
+            expected,
+        });
+    }
+    Ok(())
+}
+
+pub fn upload(env: &Env, ipa_path: &Path, api_key: &ApiKey) -> Result<(), Error> {
+    check_inputs(ipa_path, api_key)?;
+    let keys_dir = api_key
+        .key_path
+        .parent()
+        .expect("developer error: key path has a file name but no parent");
+    ui::status(format!("Uploading {:?}...", ipa_path));
+    // Progress goes to stderr, and the JSON result goes to stdout.
+    let result = bossy::Command::pure("xcrun")
+        .with_env_vars(env.explicit_env())
+        .with_env_var("API_PRIVATE_KEYS_DIR", keys_dir)
+        .with_stderr(bossy::Stdio::inherit())
+        .with_args(&["altool", "--upload-app", "--type", "ios", "--file"])
+        .with_arg(ipa_path)
+        .with_args(&["--apiKey", &api_key.key_id])
+        .with_args(&["--apiIssuer", &api_key.issuer_id])
+        .with_args(&["--output-format", "json"])
+        .run_and_wait_for_output();
+    let (stdout, exit_code) = match &result {
+        Ok(output) => (Some(output.stdout()), None),
+        // The error would display the command, which contains credentials.
+        Err(err) => (err.output().map(|output| output.stdout()), err.code()),
+    };
+    let output = stdout
+        .filter(|stdout| !stdout.is_empty())
+        .map(serde_json::from_slice::<AltoolOutput>)
+        .transpose();
+    match (result.is_ok(), output) {
+        (_, Ok(Some(output))) if !output.product_errors.is_empty() => {
+            Err(Error::Rejected(output.product_errors))
+        }
+        (true, Ok(output)) => {
+            if let Some(msg) = output.and_then(|output| output.success_message) {
+                println!("{}", msg);
+            }
+            Ok(())
+        }
+        (true, Err(err)) => Err(Error::OutputParseFailed(err)),
+        (false, _) => Err(Error::AltoolFailed { exit_code }),
+    }
+}
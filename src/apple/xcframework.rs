@@ -0,0 +1,391 @@
+use super::{
+    config::{Config, Metadata},
+    lipo,
+    target::{CompileLibError, Target},
+};
+use crate::{
+    env::{BuildEnv, Env, ExplicitEnv as _},
+    opts,
+    target::TargetTrait as _,
+    ui,
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use serde::Deserialize;
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    ManifestReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    ManifestParseFailed {
+        path: PathBuf,
+        cause: toml::de::Error,
+    },
+    NotStaticlib {
+        path: PathBuf,
+        crate_types: Vec<String>,
+    },
+    RustupFailed(bossy::Error),
+    CompileLibFailed(CompileLibError),
+    LipoFailed(lipo::Error),
+    CbindgenPresenceCheckFailed(bossy::Error),
+    CbindgenMissing,
+    CbindgenFailed(bossy::Error),
+    HeadersDirMissing {
+        path: PathBuf,
+    },
+    RemoveFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    CreateFailed(bossy::Error),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        let msg = "Failed to create XCFramework";
+        match self {
+            Self::ManifestReadFailed { path, cause } => {
+                Report::error(msg, format!("Failed to read {:?}: {}", path, cause))
+            }
+            Self::ManifestParseFailed { path, cause } => Report::error(
+                msg,
+                format!("Failed to parse contents of {:?}: {}", path, cause),
+            ),
+            Self::NotStaticlib { path, crate_types } => Report::action_request(
+                "Your crate doesn't produce a static library",
+                format!(
+                    "XCFrameworks are built from static libraries, but {:?} only specifies {}. Add `\"staticlib\"` to `crate-type` under `[lib]` and try again.",
+                    path,
+                    if crate_types.is_empty() {
+                        "the default crate type".to_owned()
+                    } else {
+                        format!("{:?}", crate_types)
+                    },
+                ),
+            ),
+            Self::RustupFailed(err) => Report::error("Failed to `rustup` iOS toolchains", err),
+            Self::CompileLibFailed(err) => err.report(),
+            Self::LipoFailed(err) => err.report(),
+            Self::CbindgenPresenceCheckFailed(err) => {
+                Report::error("Failed to check for `cbindgen`", err)
+            }
+            Self::CbindgenMissing => Report::action_request(
+                "`cbindgen` wasn't found, but `apple.cbindgen` is enabled",
+                "Run `cargo install cbindgen` and try again, or disable `apple.cbindgen` and provide headers via `apple.xcframework-headers`.",
+            ),
+            Self::CbindgenFailed(err) => {
+                Report::error("Failed to generate headers via `cbindgen`", err)
+            }
+            Self::HeadersDirMissing { path } => Report::action_request(
+                "Headers directory doesn't exist",
+                format!(
+                    "Nothing was found at {:?}. Set `apple.xcframework-headers` to a directory containing your C headers, or enable `apple.cbindgen` to generate them.",
+                    path
+                ),
+            ),
+            Self::RemoveFailed { path, cause } => Report::error(
+                msg,
+                format!("Failed to remove previous XCFramework {:?}: {}", path, cause),
+            ),
+            Self::CreateFailed(err) => Report::error(msg, err),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ManifestReadFailed { .. } => "apple.xcframework.manifest-read-failed",
+            Self::ManifestParseFailed { .. } => "apple.xcframework.manifest-parse-failed",
+            Self::NotStaticlib { .. } => "apple.xcframework.not-staticlib",
+            Self::RustupFailed(..) => "apple.xcframework.rustup-failed",
+            Self::CompileLibFailed(..) => "apple.xcframework.compile-lib-failed",
+            Self::LipoFailed(..) => "apple.xcframework.lipo-failed",
+            Self::CbindgenPresenceCheckFailed(..) => {
+                "apple.xcframework.cbindgen-presence-check-failed"
+            }
+            Self::CbindgenMissing => "apple.xcframework.cbindgen-missing",
+            Self::CbindgenFailed(..) => "apple.xcframework.cbindgen-failed",
+            Self::HeadersDirMissing { .. } => "apple.xcframework.headers-dir-missing",
+            Self::RemoveFailed { .. } => "apple.xcframework.remove-failed",
+            Self::CreateFailed(..) => "apple.xcframework.create-failed",
+        }
+    }
+}
+
+fn check_staticlib(manifest_path: PathBuf) -> Result<(), Error> {
+    #[derive(Debug, Default, Deserialize)]
+    struct Lib {
+        #[serde(default, rename = "crate-type")]
+        crate_type: Vec<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct CargoToml {
+        #[serde(default)]
+        lib: Lib,
+    }
+
+    let bytes = fs::read(&manifest_path).map_err(|cause| Error::ManifestReadFailed {
+        path: manifest_path.clone(),
+        cause,
+    })?;
+    let crate_types = toml::from_slice::<CargoToml>(&bytes)
+        .map_err(|cause| Error::ManifestParseFailed {
+            path: manifest_path.clone(),
+            cause,
+        })?
+        .lib
+        .crate_type;
+    if crate_types
+        .iter()
+        .any(|crate_type| crate_type == "staticlib")
+    {
+        Ok(())
+    } else {
+        Err(Error::NotStaticlib {
+            path: manifest_path,
+            crate_types,
+        })
+    }
+}
+
+fn gen_headers(config: &Config, env: &Env) -> Result<PathBuf, Error> {
+    let headers_dir = config.xcframework_headers_dir();
+    if config.cbindgen() {
+        if !util::command_present("cbindgen").map_err(Error::CbindgenPresenceCheckFailed)? {
+            return Err(Error::CbindgenMissing);
+        }
+        ui::status("Generating headers...");
+        bossy::Command::pure("cbindgen")
+            .with_env_vars(env.explicit_env())
+            .with_arg("--crate")
+            .with_arg(config.app().name())
+            .with_arg("--lang")
+            .with_arg("c")
+            .with_arg("--output")
+            .with_arg(headers_dir.join(format!("{}.h", config.app().names().lib_name())))
+            .with_arg(config.app().root_dir())
+            .run_and_wait()
+            .map_err(Error::CbindgenFailed)?;
+    }
+    if headers_dir.is_dir() {
+        Ok(headers_dir)
+    } else {
+        Err(Error::HeadersDirMissing { path: headers_dir })
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn newest_in(dir: &Path) -> Option<SystemTime> {
+    ignore::WalkBuilder::new(dir)
+        .standard_filters(false)
+        .build()
+        .filter_map(Result::ok)
+        .filter_map(|entry| modified(entry.path()))
+        .max()
+}
+
+// `-create-xcframework` always copies everything, so we skip it when none of
+// its inputs changed since the last run.
+fn up_to_date(out: &Path, libs: &[PathBuf], headers_dir: &Path) -> bool {
+    modified(out)
+        .map(|out_modified| {
+            libs.iter()
+                .map(|lib| modified(lib))
+                .chain(std::iter::once(newest_in(headers_dir)))
+                .all(|input| input.map(|input| input <= out_modified).unwrap_or_default())
+        })
+        .unwrap_or_default()
+}
+
+// Each device target gets a slice of its own, but the simulator ones have to
+// share one, so there's a universal lib for them when there's more than one.
+fn slice_libs(
+    device: Vec<PathBuf>,
+    mut simulator: Vec<PathBuf>,
+    universal: PathBuf,
+) -> Vec<PathBuf> {
+    let simulator = if simulator.len() > 1 {
+        universal
+    } else {
+        simulator.remove(0)
+    };
+    device
+        .into_iter()
+        .chain(std::iter::once(simulator))
+        .collect()
+}
+
+/// A library in an XCFramework, as listed in its `Info.plist`.
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+struct Slice {
+    library_identifier: String,
+    supported_platform: String,
+    supported_platform_variant: Option<String>,
+    supported_architectures: Vec<String>,
+}
+
+impl Display for Slice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}",
+            self.library_identifier, self.supported_platform
+        )?;
+        if let Some(variant) = &self.supported_platform_variant {
+            write!(f, " {}", variant)?;
+        }
+        write!(f, ", {})", self.supported_architectures.join(" and "))
+    }
+}
+
+fn read_slices(out: &Path) -> Result<Vec<Slice>, plist::Error> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Info {
+        available_libraries: Vec<Slice>,
+    }
+
+    plist::from_file::<_, Info>(out.join("Info.plist")).map(|info| info.available_libraries)
+}
+
+/// Builds all iOS targets and bundles them into an XCFramework, returning the
+/// path it ended up at. Unless `force` is set, the bundling is skipped when
+/// nothing changed since the last time.
+pub fn create(
+    config: &Config,
+    metadata: &Metadata,
+    env: &Env,
+    noise_level: opts::NoiseLevel,
+    profile: opts::Profile,
+    force: opts::Force,
+    out: Option<PathBuf>,
+) -> Result<PathBuf, Error> {
+    check_staticlib(config.app().manifest_path())?;
+    let out = out.unwrap_or_else(|| config.xcframework_path());
+
+    // cargo's own fingerprinting makes these no-ops when nothing changed.
+    let (simulator, device): (Vec<Target<'_>>, Vec<Target<'_>>) = Target::all()
+        .values()
+        .copied()
+        .partition(Target::is_simulator);
+    for target in device.iter().chain(simulator.iter()) {
+        target.install_for(config).map_err(Error::RustupFailed)?;
+        target
+            .compile_lib(
+                config,
+                metadata,
+                noise_level,
+                opts::ForceColor::Yes,
+                profile,
+                &BuildEnv::new(env),
+            )
+            .map_err(Error::CompileLibFailed)?;
+    }
+    lipo::create_universal_simulator_lib(config, &simulator, profile, force)
+        .map_err(Error::LipoFailed)?;
+    let lib_paths = |targets: &[Target<'_>]| {
+        targets
+            .iter()
+            .map(|target| config.lib_path(target.triple, profile))
+            .collect::<Vec<_>>()
+    };
+    let libs = slice_libs(
+        lib_paths(&device),
+        lib_paths(&simulator),
+        config.universal_simulator_lib_path(profile),
+    );
+
+    let headers_dir = gen_headers(config, env)?;
+    if force.no() && up_to_date(&out, &libs, &headers_dir) {
+        log::info!("XCFramework at {:?} is up to date", out);
+        return Ok(out);
+    }
+    if out.exists() {
+        fs::remove_dir_all(&out).map_err(|cause| Error::RemoveFailed {
+            path: out.clone(),
+            cause,
+        })?;
+    }
+    ui::status("Creating XCFramework...");
+    let mut command = bossy::Command::pure("xcodebuild")
+        .with_env_vars(env.explicit_env())
+        .with_arg("-create-xcframework");
+    for lib in &libs {
+        command.add_arg("-library").add_arg(lib);
+        command.add_arg("-headers").add_arg(&headers_dir);
+    }
+    command
+        .with_arg("-output")
+        .with_arg(&out)
+        .run_and_wait()
+        .map_err(Error::CreateFailed)?;
+    match read_slices(&out) {
+        Ok(slices) => {
+            for slice in slices {
+                log::info!("XCFramework has slice {}", slice);
+            }
+        }
+        Err(err) => log::warn!("failed to read slices of XCFramework at {:?}: {}", out, err),
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        simulator,
+        expected,
+        case(&["sim-arm64.a"], &["arm64.a", "sim-arm64.a"]),
+        case(&["sim-arm64.a", "sim-x86_64.a"], &["arm64.a", "universal.a"])
+    )]
+    fn test_slice_libs(simulator: &[&str], expected: &[&str]) {
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(
+            slice_libs(
+                paths(&["arm64.a"]),
+                paths(simulator),
+                PathBuf::from("universal.a")
+            ),
+            paths(expected)
+        );
+    }
+
+    #[test]
+    fn test_read_slices() {
+        let temp_dir = util::TempDir::new("xcframework-test").unwrap();
+        fs::write(
+            temp_dir.path().join("Info.plist"),
+            include_str!("transcripts/xcframework-info.plist"),
+        )
+        .unwrap();
+        let slices = read_slices(temp_dir.path()).unwrap();
+        assert_eq!(
+            slices
+                .iter()
+                .map(|slice| slice.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "ios-arm64 (ios, arm64)",
+                "ios-arm64_x86_64-simulator (ios simulator, arm64 and x86_64)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{
+    env::{Env, ExplicitEnv as _},
+    util::cli::{Report, Reportable},
+};
+use once_cell_regex::regex;
+use std::path::{Path, PathBuf};
+
+static COMMAND_LINE_TOOLS_DIR: &str = "/Library/Developer/CommandLineTools";
+
+/// Parses versions like `12.4` or `14`, ignoring any patch version.
+pub fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let mut components = version.trim().split('.');
+    let major = components.next()?.parse().ok()?;
+    let minor = components.next().map(str::parse).unwrap_or(Ok(0)).ok()?;
+    Some((major, minor))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    SelectFailed(bossy::Error),
+    CommandLineToolsOnly {
+        developer_dir: PathBuf,
+    },
+    VersionFailed(bossy::Error),
+    VersionInvalid {
+        output: String,
+    },
+    TooLow {
+        you_have: (u32, u32),
+        you_need: (u32, u32),
+        developer_dir: PathBuf,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::SelectFailed(err) => Report::error("Failed to find Xcode", err),
+            Self::CommandLineToolsOnly { developer_dir } => Report::action_request(
+                "Full Xcode is required, but only the Command Line Tools are selected",
+                format!(
+                    "The active developer dir is {:?}. If Xcode isn't installed yet, get it from the App Store; then run `sudo xcode-select --switch /Applications/Xcode.app` once and try again.",
+                    developer_dir
+                ),
+            ),
+            Self::VersionFailed(err) => Report::error("Failed to check Xcode version", err),
+            Self::VersionInvalid { output } => Report::error(
+                "Failed to check Xcode version",
+                format!("Couldn't find a version in `xcodebuild -version` output: {:?}", output),
+            ),
+            Self::TooLow {
+                you_have,
+                you_need,
+                developer_dir,
+            } => Report::action_request(
+                "Installed Xcode version too low; please upgrade and try again",
+                format!(
+                    "This project requires Xcode {}.{}, but the Xcode at {:?} is {}.{}. If you have a newer Xcode installed, select it with `xcode-select --switch` or `DEVELOPER_DIR`; you can also change `apple.min-xcode-version` in your `mobile.toml`.",
+                    you_need.0, you_need.1, developer_dir, you_have.0, you_have.1
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::SelectFailed(..) => "apple.xcode.select-failed",
+            Self::CommandLineToolsOnly { .. } => "apple.xcode.command-line-tools-only",
+            Self::VersionFailed(..) => "apple.xcode.version-failed",
+            Self::VersionInvalid { .. } => "apple.xcode.version-invalid",
+            Self::TooLow { .. } => "apple.xcode.too-low",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Xcode {
+    developer_dir: PathBuf,
+    version: (u32, u32),
+}
+
+impl Xcode {
+    /// Finds the selected Xcode, which is the one `DEVELOPER_DIR` points to if
+    /// it's set, and otherwise the one `xcode-select` points to.
+    pub fn detect(env: &Env) -> Result<Self, Error> {
+        let developer_dir = bossy::Command::pure_parse("xcode-select --print-path")
+            .with_env_vars(env.explicit_env())
+            .run_and_wait_for_str(|s| PathBuf::from(s.trim()))
+            .map_err(Error::SelectFailed)?;
+        if developer_dir.starts_with(COMMAND_LINE_TOOLS_DIR) {
+            return Err(Error::CommandLineToolsOnly { developer_dir });
+        }
+        let output = bossy::Command::pure_parse("xcodebuild -version")
+            .with_env_vars(env.explicit_env())
+            .run_and_wait_for_str(|s| s.to_owned())
+            .map_err(|err| {
+                // `xcode-select` can point to a stale path, which `xcodebuild`
+                // treats the same as having just the Command Line Tools.
+                if err
+                    .stderr_str()
+                    .and_then(Result::ok)
+                    .map(|stderr| stderr.contains("requires Xcode"))
+                    .unwrap_or_default()
+                {
+                    Error::CommandLineToolsOnly {
+                        developer_dir: developer_dir.clone(),
+                    }
+                } else {
+                    Error::VersionFailed(err)
+                }
+            })?;
+        let version = regex!(r"(?m)^Xcode (\d+(?:\.\d+)*)")
+            .captures(&output)
+            .and_then(|caps| parse_version(&caps[1]))
+            .ok_or_else(|| Error::VersionInvalid {
+                output: output.clone(),
+            })?;
+        log::info!(
+            "using Xcode {}.{} at {:?}{}",
+            version.0,
+            version.1,
+            developer_dir,
+            if env.developer_dir().is_some() {
+                " (from `DEVELOPER_DIR`)"
+            } else {
+                ""
+            }
+        );
+        Ok(Self {
+            developer_dir,
+            version,
+        })
+    }
+
+    pub fn developer_dir(&self) -> &Path {
+        &self.developer_dir
+    }
+
+    pub fn version(&self) -> (u32, u32) {
+        self.version
+    }
+}
+
+pub fn check(env: &Env, min_version: (u32, u32)) -> Result<Xcode, Error> {
+    let xcode = Xcode::detect(env)?;
+    if xcode.version >= min_version {
+        Ok(xcode)
+    } else {
+        Err(Error::TooLow {
+            you_have: xcode.version,
+            you_need: min_version,
+            developer_dir: xcode.developer_dir,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        version,
+        expected,
+        case("12.4", Some((12, 4))),
+        case("15.0.1", Some((15, 0))),
+        case("14", Some((14, 0))),
+        case("beta", None),
+        case("12.x", None)
+    )]
+    fn test_parse_version(version: &str, expected: Option<(u32, u32)>) {
+        assert_eq!(parse_version(version), expected);
+    }
+}
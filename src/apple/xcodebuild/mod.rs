@@ -0,0 +1,362 @@
+use super::signing;
+use crate::{
+    opts::NoiseLevel,
+    ui,
+    util::{
+        self,
+        cli::{Report, Reportable as _},
+        watchdog::{self, Limits},
+    },
+};
+use once_cell_regex::regex;
+use std::{
+    fmt::{self, Display},
+    fs::{self, File},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+// xcodebuild escapes spaces in paths, so a path is everything up to the first
+// unescaped space.
+fn unescape(path: &str) -> String {
+    path.replace("\\ ", " ")
+}
+
+fn file_name(path: &str) -> String {
+    let path = unescape(path);
+    Path::new(&path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or(path)
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Step {
+    verb: &'static str,
+    subject: String,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.verb, self.subject)
+    }
+}
+
+impl Step {
+    fn parse(line: &str) -> Option<Self> {
+        let step = |verb, subject| Some(Self { verb, subject });
+        if let Some(caps) = regex!(r"^CompileSwift normal \S+ (.+?\.swift)").captures(line) {
+            step("Compiling", file_name(&caps[1]))
+        } else if let Some(caps) =
+            regex!(r"^Compile(?:C|Storyboard|XIB) (?:(?:\\ |\S)+ )?((?:\\ |\S)+\.(?:c|cc|cpp|m|mm|storyboard|xib)) ")
+                .captures(line)
+        {
+            step("Compiling", file_name(&caps[1]))
+        } else if let Some(caps) = regex!(r"^Ld ((?:\\ |\S)+) ").captures(line) {
+            step("Linking", file_name(&caps[1]))
+        } else if let Some(caps) = regex!(r"^CodeSign ((?:\\ |\S)+)").captures(line) {
+            step("Signing", file_name(&caps[1]))
+        } else if let Some(caps) = regex!(r"^PhaseScriptExecution ((?:\\ |\S)+) ").captures(line) {
+            step("Running", unescape(&caps[1]))
+        } else if let Some(caps) = regex!(r"^ExternalBuildToolExecution (\S+)").captures(line) {
+            // This is how our Rust lib targets show up.
+            step("Building", caps[1].to_owned())
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    location: Option<String>,
+    msg: String,
+    context: Vec<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}: ", location)?;
+        }
+        write!(f, "{}", self.msg)?;
+        for line in &self.context {
+            write!(f, "\n{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Diagnostic {
+    fn new(location: Option<String>, msg: impl Into<String>) -> Self {
+        Self {
+            location,
+            msg: msg.into(),
+            context: Vec::new(),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        if let Some(caps) = regex!(r"^(.+?:\d+(?::\d+)?): (?:fatal )?error: (.+)$").captures(line) {
+            Some(Self::new(Some(unescape(&caps[1])), &caps[2]))
+        } else if let Some(caps) =
+            regex!(r"^(?:ld|clang|xcodebuild): (?:error: )?(.+)$").captures(line)
+        {
+            // The linker likes to warn about missing search paths, which is
+            // both harmless and a surefire way to distract from the real error.
+            Some(Self::new(None, &caps[1]))
+                .filter(|_| !caps[1].to_lowercase().starts_with("warning"))
+        } else if let Some(caps) = regex!(r"^error(?:\[\w+\])?: (.+)$").captures(line) {
+            Some(Self::new(None, &caps[1]))
+        } else if line.starts_with("Undefined symbols for architecture") {
+            Some(Self::new(None, line.trim_end_matches(':')))
+        } else {
+            None
+        }
+    }
+}
+
+/// Compresses `xcodebuild` output into progress steps, while holding on to any
+/// errors so they can be reported once the build fails.
+#[derive(Debug, Default)]
+pub struct Filter {
+    diagnostics: Vec<Diagnostic>,
+    collecting: bool,
+}
+
+impl Filter {
+    pub fn feed(&mut self, line: &str) -> Option<Step> {
+        // cargo output gets colored when `FORCE_COLOR` is set, which would
+        // otherwise get in the way of matching.
+        let line = util::strip_ansi(line.trim_end());
+        let line = line.as_ref();
+        if let Some(diagnostic) = Diagnostic::parse(line) {
+            self.collecting = !self.diagnostics.contains(&diagnostic);
+            if self.collecting {
+                self.diagnostics.push(diagnostic);
+            }
+            None
+        } else if let Some(step) = Step::parse(line) {
+            self.collecting = false;
+            Some(step)
+        } else {
+            if self.collecting {
+                let diagnostic = self
+                    .diagnostics
+                    .last_mut()
+                    .expect("developer error: collecting context without a diagnostic");
+                // Swift and clang follow errors with the offending source line
+                // and a caret, while rustc snippets and undefined symbol lists
+                // are indented (save for rustc's line numbers).
+                let context = if diagnostic.location.is_some() {
+                    !line.is_empty() && !line.starts_with("** ") && diagnostic.context.len() < 2
+                } else {
+                    line.starts_with(char::is_whitespace) || regex!(r"^\d+ +\|").is_match(line)
+                };
+                if context {
+                    diagnostic.context.push(line.to_owned());
+                } else {
+                    self.collecting = false;
+                }
+            }
+            None
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+}
+
+#[derive(Debug)]
+pub enum RunError {
+    LogCreationFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    OutputFailed(io::Error),
+    SigningFailed {
+        failure: signing::Failure,
+        development_team: String,
+    },
+    BuildFailed {
+        diagnostics: Vec<Diagnostic>,
+        log_path: PathBuf,
+        cause: bossy::Error,
+    },
+    CommandFailed(bossy::Error),
+    Stalled(watchdog::Stalled),
+}
+
+impl RunError {
+    pub fn report(&self, msg: &str) -> Report {
+        match self {
+            Self::LogCreationFailed { path, cause } => Report::error(
+                format!("Failed to create `xcodebuild` log file at {:?}", path),
+                cause,
+            ),
+            Self::OutputFailed(err) => Report::error("Failed to save `xcodebuild` output", err),
+            Self::SigningFailed {
+                failure,
+                development_team,
+            } => failure.report(development_team),
+            Self::BuildFailed {
+                diagnostics,
+                log_path,
+                cause,
+            } => {
+                let details = if diagnostics.is_empty() {
+                    cause.to_string()
+                } else {
+                    diagnostics
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("\n\n")
+                };
+                Report::error(
+                    msg,
+                    format!(
+                        "{}\n\nThe full output is in {:?}; run with `-v` to see it as it happens.",
+                        details, log_path
+                    ),
+                )
+            }
+            Self::CommandFailed(err) => Report::error(msg, err),
+            Self::Stalled(err) => err.report(),
+        }
+    }
+
+    /// A stall has its own code, and everything else gets `code`.
+    pub fn code(&self, code: &'static str) -> &'static str {
+        match self {
+            Self::Stalled(err) => err.code(),
+            _ => code,
+        }
+    }
+}
+
+fn run_filtered(
+    command: bossy::Command,
+    log_path: &Path,
+) -> Result<(Filter, String, Result<(), watchdog::Error>), RunError> {
+    let mut log = log_path
+        .parent()
+        .map(fs::create_dir_all)
+        .transpose()
+        .and_then(|_| File::create(log_path))
+        .map_err(|cause| RunError::LogCreationFailed {
+            path: log_path.to_owned(),
+            cause,
+        })?;
+    // stderr only gets a line or two (e.g. `xcodebuild: error: ...`), so it's
+    // passed through as-is.
+    let mut filter = Filter::default();
+    let mut transcript = String::new();
+    let mut log_failed = None;
+    let result = watchdog::run_with(command, Limits::PACKAGE, |line| {
+        if log_failed.is_none() {
+            log_failed = log
+                .write_all(line)
+                .and_then(|()| log.write_all(b"\n"))
+                .err();
+        }
+        let line = String::from_utf8_lossy(line);
+        if let Some(step) = filter.feed(&line) {
+            ui::status(format!("  {}", step));
+        }
+        transcript.push_str(&line);
+        transcript.push('\n');
+    });
+    if let Some(err) = log_failed {
+        return Err(RunError::OutputFailed(err));
+    }
+    Ok((filter, transcript, result))
+}
+
+/// Runs an `xcodebuild` command. By default, output is filtered down to
+/// progress steps and written in full to `log_path`; at `-v` and above, it's
+/// passed straight through.
+pub fn run(
+    command: bossy::Command,
+    noise_level: NoiseLevel,
+    log_path: &Path,
+    development_team: &str,
+) -> Result<(), RunError> {
+    if !noise_level.polite() {
+        return watchdog::run_and_wait(command, Limits::PACKAGE)
+            .map_err(|err| err.split(RunError::CommandFailed, RunError::Stalled));
+    }
+    let (filter, transcript, result) = run_filtered(command, log_path)?;
+    result.map_err(|err| {
+        err.split(
+            |cause| {
+                if let Some(failure) = signing::Failure::find(&transcript) {
+                    RunError::SigningFailed {
+                        failure,
+                        development_team: development_team.to_owned(),
+                    }
+                } else {
+                    RunError::BuildFailed {
+                        diagnostics: filter.diagnostics,
+                        log_path: log_path.to_owned(),
+                        cause,
+                    }
+                }
+            },
+            RunError::Stalled,
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn filter(transcript: &str) -> (Vec<String>, Filter) {
+        let mut filter = Filter::default();
+        let steps = transcript
+            .lines()
+            .filter_map(|line| filter.feed(line))
+            .map(|step| step.to_string())
+            .collect();
+        (steps, filter)
+    }
+
+    #[rstest(
+        transcript,
+        steps,
+        diagnostics,
+        case(
+            include_str!("transcripts/signing.log"),
+            vec!["Building lib_foo_iOS"],
+            vec![r#"Signing for "foo_iOS" requires a development team. Select a development team in the Signing & Capabilities editor. (in target 'foo_iOS' from project 'foo')"#]
+        ),
+        case(
+            include_str!("transcripts/swift-syntax.log"),
+            vec!["Building lib_foo_iOS", "Compiling main.swift"],
+            vec!["/Users/me/foo/gen/apple/Sources/foo/main.swift:3:15: expected expression in list of expressions\nlet x = [1, 2,, 3]\n              ^"]
+        ),
+        case(
+            include_str!("transcripts/missing-library.log"),
+            vec!["Building lib_foo_iOS", "Compiling main.mm", "Linking foo"],
+            vec![
+                "library not found for -lfoo",
+                "linker command failed with exit code 1 (use -v to see invocation)",
+            ]
+        )
+    )]
+    fn test_filter(transcript: &str, steps: Vec<&str>, diagnostics: Vec<&str>) {
+        let (actual_steps, filter) = filter(transcript);
+        assert_eq!(actual_steps, steps);
+        assert_eq!(
+            filter
+                .diagnostics()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            diagnostics
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! What from the asset dir gets packaged. Everything does, except what's
+//! matched by `app.asset-ignore`, a few defaults for OS and editor litter, and
+//! `.gitignore` rules: those in `.gitignore`s in the asset dir, plus those from
+//! the app root down to it that apply at any depth (so that ignoring the asset
+//! dir itself, like when its contents are downloaded, doesn't empty it).
+//!
+//! The asset dir is linked into the generated projects rather than copied, so
+//! Gradle and Xcode are what actually leave files out. They're given globs
+//! translated from the same rules used to list what's included, so that the two
+//! can't disagree.
+
+use crate::{
+    config::app::App,
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Ignored no matter what, since nobody means to ship these.
+pub static DEFAULT_IGNORE: &[&str] = &[".DS_Store", "Thumbs.db", ".gitignore", "*.swp", "*~"];
+
+#[derive(Debug)]
+pub enum Error {
+    ReadFailed { path: PathBuf, cause: io::Error },
+    PatternInvalid { glob: String, cause: ignore::Error },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ReadFailed { path, cause } => {
+                Report::error(format!("Failed to read {:?}", path), cause)
+            }
+            Self::PatternInvalid { glob, cause } => Report::action_request(
+                format!("Asset ignore pattern {:?} is invalid", glob),
+                format!(
+                    "{}; check `app.asset-ignore` and the `.gitignore`s in the asset dir.",
+                    cause
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadFailed { .. } => "assets.read-failed",
+            Self::PatternInvalid { .. } => "assets.pattern-invalid",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Asset {
+    /// Relative to the asset dir.
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+// Turns a line of a `.gitignore` into globs relative to the asset dir. `base`
+// is where the `.gitignore` is relative to the asset dir, or `None` if it's
+// above it. The globs are anchored, and only use `*`, `?`, `[...]`, and `**`,
+// which Gradle and bash understand the same way `ignore` does.
+fn translate(line: &str, base: Option<&str>) -> Vec<String> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return Vec::new();
+    }
+    if line.starts_with('!') {
+        log::warn!(
+            "asset ignore pattern {:?} can't be honored, since negated patterns can't be turned into exclude patterns",
+            line
+        );
+        return Vec::new();
+    }
+    let line = line.strip_prefix('\\').unwrap_or(line);
+    let dir_only = line.ends_with('/');
+    let pattern = line.trim_end_matches('/');
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+    let glob = match (anchored, base) {
+        (false, None) | (false, Some("")) => format!("**/{}", pattern),
+        (false, Some(base)) => format!("{}/**/{}", base, pattern),
+        (true, None) => {
+            log::info!(
+                "skipping asset ignore pattern {:?}, since it's anchored above the asset dir",
+                line
+            );
+            return Vec::new();
+        }
+        (true, Some("")) => pattern.to_owned(),
+        (true, Some(base)) => format!("{}/{}", base, pattern),
+    };
+    if dir_only || glob.ends_with("**") {
+        vec![format!("{}/**", glob.trim_end_matches("/**"))]
+    } else {
+        let contents = format!("{}/**", glob);
+        vec![glob, contents]
+    }
+}
+
+fn read_gitignore(dir: &Path) -> Result<Option<String>, Error> {
+    let path = dir.join(".gitignore");
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(cause) => Err(Error::ReadFailed { path, cause }),
+    }
+}
+
+// The entries of `dir`, sorted, or none if it doesn't exist.
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    match fs::read_dir(dir) {
+        Ok(entries) => {
+            let mut paths = entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|cause| Error::ReadFailed {
+                    path: dir.to_owned(),
+                    cause,
+                })?;
+            paths.sort();
+            Ok(paths)
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(cause) => Err(Error::ReadFailed {
+            path: dir.to_owned(),
+            cause,
+        }),
+    }
+}
+
+// `.gitignore` syntax always uses `/`, whatever the OS.
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn nested_gitignores(dir: &Path, rel: &Path, globs: &mut Vec<String>) -> Result<(), Error> {
+    if let Some(contents) = read_gitignore(dir)? {
+        let base = slash_path(rel);
+        globs.extend(
+            contents
+                .lines()
+                .flat_map(|line| translate(line, Some(&base))),
+        );
+    }
+    for path in read_dir(dir)? {
+        if path.is_dir() {
+            nested_gitignores(&path, &rel.join(path.file_name().unwrap()), globs)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct Ignore {
+    dir: PathBuf,
+    globs: Vec<String>,
+    matcher: Gitignore,
+}
+
+impl Ignore {
+    pub fn load(app: &App) -> Result<Self, Error> {
+        let dir = app.asset_dir();
+        let mut globs = DEFAULT_IGNORE
+            .iter()
+            .flat_map(|pattern| translate(pattern, Some("")))
+            .collect::<Vec<_>>();
+        for ancestor in dir
+            .ancestors()
+            .skip(1)
+            .take_while(|ancestor| ancestor.starts_with(app.root_dir()))
+        {
+            if let Some(contents) = read_gitignore(ancestor)? {
+                globs.extend(contents.lines().flat_map(|line| translate(line, None)));
+            }
+        }
+        nested_gitignores(&dir, Path::new(""), &mut globs)?;
+        globs.extend(
+            app.asset_ignore()
+                .iter()
+                .flat_map(|pattern| translate(pattern, Some(""))),
+        );
+        let mut seen = std::collections::HashSet::new();
+        globs.retain(|glob| seen.insert(glob.clone()));
+        Self::from_globs(dir, globs)
+    }
+
+    fn from_globs(dir: PathBuf, globs: Vec<String>) -> Result<Self, Error> {
+        let mut builder = GitignoreBuilder::new(&dir);
+        for glob in &globs {
+            builder
+                .add_line(None, &format!("/{}", glob))
+                .map_err(|cause| Error::PatternInvalid {
+                    glob: glob.clone(),
+                    cause,
+                })?;
+        }
+        let matcher = builder.build().map_err(|cause| Error::PatternInvalid {
+            glob: globs.join(", "),
+            cause,
+        })?;
+        Ok(Self {
+            dir,
+            globs,
+            matcher,
+        })
+    }
+
+    /// The globs, relative to where the asset dir is linked into the
+    /// generated projects, i.e. prefixed with the asset dir's name.
+    pub fn packaged_globs(&self) -> Vec<String> {
+        let name = self
+            .dir
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        self.globs
+            .iter()
+            .map(|glob| format!("{}/{}", name, glob))
+            .collect()
+    }
+
+    /// Whether `path`, relative to the asset dir, is left out.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.matcher
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    }
+
+    /// Checks that nothing in `packaged` should've been left out, returning a
+    /// warning if anything should have. The paths are relative to where the
+    /// asset dir was linked, like the globs from `packaged_globs`; anything
+    /// that isn't from the asset dir is skipped.
+    pub fn check_packaged<'a>(&self, packaged: impl Iterator<Item = &'a str>) -> Option<String> {
+        let name = self.dir.file_name().unwrap_or_default();
+        let stowaways = packaged
+            .filter(|path| {
+                Path::new(path)
+                    .strip_prefix(name)
+                    .map_or(false, |rel| self.is_ignored(rel, false))
+            })
+            .collect::<Vec<_>>();
+        let regen = "so the project was probably generated before they were ignored; run `cargo mobile regen`";
+        match stowaways.as_slice() {
+            [] => None,
+            [only] => Some(format!(
+                "{} is ignored, but was packaged anyway, {}",
+                only, regen
+            )),
+            [first, rest @ ..] => Some(format!(
+                "{} and {} other ignored assets were packaged anyway, {}",
+                first,
+                rest.len(),
+                regen
+            )),
+        }
+    }
+
+    fn collect(&self, dir: &Path, rel: &Path, assets: &mut Vec<Asset>) -> Result<(), Error> {
+        for path in read_dir(dir)? {
+            let rel = rel.join(path.file_name().unwrap());
+            // Following symlinks, since the build tools do too.
+            let metadata = fs::metadata(&path).map_err(|cause| Error::ReadFailed {
+                path: path.clone(),
+                cause,
+            })?;
+            if self.is_ignored(&rel, metadata.is_dir()) {
+                log::info!("leaving out ignored asset {:?}", rel);
+            } else if metadata.is_dir() {
+                self.collect(&path, &rel, assets)?;
+            } else {
+                assets.push(Asset {
+                    path: rel,
+                    size: metadata.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Everything in the asset dir that'll be packaged.
+    pub fn included(&self) -> Result<Vec<Asset>, Error> {
+        let mut assets = Vec::new();
+        self.collect(&self.dir, Path::new(""), &mut assets)?;
+        Ok(assets)
+    }
+}
+
+/// Lists what from the asset dir will be packaged, for `--show-assets`.
+pub fn show(app: &App) -> Result<(), Error> {
+    let assets = Ignore::load(app)?.included()?;
+    for asset in &assets {
+        println!(
+            "{:>10}  {}",
+            util::display_size(asset.size),
+            asset.path.display()
+        );
+    }
+    println!(
+        "{} assets, {} in total",
+        assets.len(),
+        util::display_size(assets.iter().map(|asset| asset.size).sum())
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        line,
+        base,
+        expected,
+        case("", Some(""), &[]),
+        case("# comment", Some(""), &[]),
+        case("!keep.psd", Some(""), &[]),
+        case("*.psd", Some(""), &["**/*.psd", "**/*.psd/**"]),
+        case("*.psd  ", None, &["**/*.psd", "**/*.psd/**"]),
+        case("raw/**", Some(""), &["raw/**"]),
+        case("/raw/", Some(""), &["raw/**"]),
+        case("build/", Some(""), &["**/build/**"]),
+        case("/big.bin", Some("sounds"), &["sounds/big.bin", "sounds/big.bin/**"]),
+        case("*.wav", Some("sounds"), &["sounds/**/*.wav", "sounds/**/*.wav/**"]),
+        case("/assets/raw", None, &[]),
+        case("\\#notes.txt", Some(""), &["**/#notes.txt", "**/#notes.txt/**"])
+    )]
+    fn test_translate(line: &str, base: Option<&str>, expected: &[&str]) {
+        assert_eq!(translate(line, base), expected);
+    }
+
+    #[test]
+    fn test_check_packaged() {
+        let ignore = Ignore::from_globs(
+            "/app/assets".into(),
+            translate("*.psd", Some(""))
+                .into_iter()
+                .chain(translate("raw/**", Some("")))
+                .collect(),
+        )
+        .unwrap();
+        assert!(ignore.is_ignored(Path::new("art/logo.psd"), false));
+        assert!(ignore.is_ignored(Path::new("raw/music/theme.wav"), false));
+        assert!(!ignore.is_ignored(Path::new("art/logo.png"), false));
+        assert_eq!(
+            ignore
+                .check_packaged(vec!["assets/art/logo.png", "lib/arm64-v8a/libapp.so"].into_iter()),
+            None
+        );
+        assert!(ignore
+            .check_packaged(vec!["assets/art/logo.psd"].into_iter())
+            .unwrap()
+            .starts_with("assets/art/logo.psd is ignored, but was packaged anyway"));
+    }
+}
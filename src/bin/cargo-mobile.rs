@@ -1,16 +1,24 @@
 #![forbid(unsafe_code)]
 
 use cargo_mobile::{
-    init, opts, update,
+    config::{self, app::Platform, Config},
+    device::{self, LastDevice, Listing},
+    doctor, errors, generated, init, new, opts,
+    plan::Writer,
+    plugin, regen, setup,
+    templating::{self, PostInitPolicy},
+    update, upgrade,
     util::{
         self,
         cli::{
             self, Exec, GlobalFlags, Report, Reportable, TextWrapper, VERSION_LONG, VERSION_SHORT,
         },
     },
-    NAME,
+    version::{self, Version},
+    vms, workspace, NAME,
 };
-use structopt::StructOpt;
+use std::path::PathBuf;
+use structopt::{clap::AppSettings, StructOpt};
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -18,13 +26,31 @@ use structopt::StructOpt;
     version = VERSION_SHORT,
     long_version = VERSION_LONG.as_str(),
     global_settings = cli::GLOBAL_SETTINGS,
-    settings = cli::SETTINGS,
+    // `--list` doesn't need a subcommand.
+    settings = &[AppSettings::ArgRequiredElseHelp],
 )]
 pub struct Input {
     #[structopt(flatten)]
     flags: GlobalFlags,
+    #[structopt(flatten)]
+    package: cli::Package,
+    #[structopt(
+        long = "list",
+        help = "Lists built-in subcommands and the plugins on your `PATH`"
+    )]
+    list: bool,
     #[structopt(subcommand)]
-    command: Command,
+    command: Option<Command>,
+}
+
+#[cfg(target_os = "macos")]
+fn apple_devices() -> Result<Vec<Listing>, Error> {
+    cargo_mobile::apple::cli::device_listings().map_err(Error::AppleFailed)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn apple_devices() -> Result<Vec<Listing>, Error> {
+    Ok(Vec::new())
 }
 
 #[derive(Clone, Debug, StructOpt)]
@@ -44,8 +70,80 @@ pub enum Command {
             parse(from_flag = opts::OpenInEditor::from_bool),
         )]
         open_in_editor: opts::OpenInEditor,
+        #[structopt(
+            long = "force",
+            help = "Overwrite existing files that differ from what would be generated, instead of asking",
+            parse(from_flag = opts::Force::from_bool),
+        )]
+        force: opts::Force,
         #[structopt(long = "submodule-commit", help = "Template pack commit to checkout")]
         submodule_commit: Option<String>,
+        #[structopt(
+            long = "team",
+            help = "Apple development team ID to use, instead of prompting for one"
+        )]
+        development_team: Option<String>,
+        #[structopt(
+            long = "template-pack",
+            help = "Template pack to generate from, as a name, a path, or a git URL with an optional `#ref`"
+        )]
+        template_pack: Option<String>,
+        #[structopt(
+            long = "platform",
+            help = "Platform to generate a project for, instead of every platform; can be repeated",
+            number_of_values = 1,
+            possible_values = &["android", "apple"],
+        )]
+        platforms: Vec<Platform>,
+        #[structopt(flatten)]
+        template_vars: cli::TemplateVars,
+        #[structopt(flatten)]
+        post_init_hooks: cli::PostInitHooks,
+        #[structopt(flatten)]
+        dry_run: cli::DryRun,
+    },
+    #[structopt(
+        name = "new",
+        about = "Creates a new project in a new directory, crate and all"
+    )]
+    New {
+        #[structopt(
+            name = "name",
+            help = "Directory to create the project in, which the app's named after unless `--name` is given"
+        )]
+        dir: PathBuf,
+        #[structopt(long = "name", help = "App name to use, instead of the directory's")]
+        name: Option<String>,
+        #[structopt(long = "domain", help = "Domain to use, instead of prompting for one")]
+        domain: Option<String>,
+        #[structopt(
+            long = "template-pack",
+            help = "Template pack to generate from, as a name, a path, or a git URL with an optional `#ref`"
+        )]
+        template_pack: Option<String>,
+        #[structopt(
+            long = "platform",
+            help = "Platform to generate a project for, instead of every platform; can be repeated",
+            number_of_values = 1,
+            possible_values = &["android", "apple"],
+        )]
+        platforms: Vec<Platform>,
+        #[structopt(flatten)]
+        template_vars: cli::TemplateVars,
+        #[structopt(flatten)]
+        post_init_hooks: cli::PostInitHooks,
+        #[structopt(
+            long = "no-git",
+            help = "Don't initialize a git repo",
+            parse(from_flag = opts::NoGit::from_bool),
+        )]
+        no_git: opts::NoGit,
+        #[structopt(
+            long = "force",
+            help = "Generate into the directory even if it isn't empty",
+            parse(from_flag = opts::Force::from_bool),
+        )]
+        force: opts::Force,
     },
     #[structopt(name = "open", about = "Open project in default code editor")]
     Open,
@@ -54,6 +152,110 @@ pub enum Command {
         #[structopt(long = "init", help = "Regenerate project if update succeeds")]
         init: bool,
     },
+    #[structopt(
+        name = "doctor",
+        about = "Checks that your environment is ready for building"
+    )]
+    Doctor {
+        #[structopt(long = "json", help = "Print results as JSON")]
+        json: bool,
+        #[structopt(
+            long = "section",
+            help = "Section to check, instead of every section; can be repeated",
+            number_of_values = 1,
+            possible_values = &["general", "android", "apple"],
+        )]
+        sections: Vec<doctor::SectionName>,
+    },
+    #[structopt(
+        name = "setup",
+        about = "Walks through fixing whatever `doctor` finds, then checks again"
+    )]
+    Setup,
+    #[structopt(
+        name = "build",
+        about = "Builds the app for every platform it selects, or every app in the workspace"
+    )]
+    Build {
+        #[structopt(
+            long = "all",
+            help = "Build every app in the workspace, instead of just the current one"
+        )]
+        all: bool,
+        #[structopt(flatten)]
+        profile: cli::Profile,
+    },
+    #[structopt(
+        name = "check-config",
+        about = "Checks your config for unknown keys and invalid values"
+    )]
+    CheckConfig,
+    #[structopt(
+        name = "regen",
+        about = "Regenerates your projects from your config, without the rest of what `init` does"
+    )]
+    Regen {
+        #[structopt(flatten)]
+        template_vars: cli::TemplateVars,
+        #[structopt(flatten)]
+        dry_run: cli::DryRun,
+    },
+    #[structopt(
+        name = "upgrade",
+        about = "Brings your projects up to date with the current templates, keeping your changes"
+    )]
+    Upgrade {
+        #[structopt(flatten)]
+        dry_run: cli::DryRun,
+        #[structopt(flatten)]
+        template_vars: cli::TemplateVars,
+    },
+    #[structopt(
+        name = "gc",
+        about = "Lists what's in `gen/` that isn't generated anymore, and offers to remove it"
+    )]
+    Gc,
+    #[structopt(name = "templates", about = "Template pack commands")]
+    Templates(TemplatesCommand),
+    #[structopt(
+        name = "version",
+        about = "Prints the app's version and build number, or bumps the version"
+    )]
+    Version {
+        #[structopt(
+            long = "bump",
+            help = "Bump the crate version in Cargo.toml and regenerate the project",
+            possible_values = version::Bump::name_list()
+        )]
+        bump: Option<version::Bump>,
+    },
+    #[structopt(
+        name = "devices",
+        about = "Lists connected devices, simulators, and emulators for every platform"
+    )]
+    Devices {
+        #[structopt(long = "json", help = "Print devices as JSON")]
+        json: bool,
+        #[structopt(
+            long = "forget",
+            help = "Forget the devices `run` last used, so that it prompts again instead of defaulting to them",
+            conflicts_with = "json"
+        )]
+        forget: bool,
+    },
+    #[structopt(
+        name = "vms",
+        about = "Lists and shuts down the simulators and emulators cargo-mobile booted"
+    )]
+    Vms(VmsCommand),
+    #[structopt(
+        name = "completions",
+        about = "Prints a completion script for your shell"
+    )]
+    Completions {
+        #[structopt(flatten)]
+        completions: cli::Completions,
+    },
     #[cfg_attr(
         target_os = "macos",
         structopt(
@@ -68,29 +270,144 @@ pub enum Command {
         about = "Android commands (tip: type less by running `cargo android` instead!)"
     )]
     Android(cargo_mobile::android::cli::Command),
+    #[structopt(
+        name = "errors",
+        about = "Lists the codes errors are reported with",
+        setting = AppSettings::Hidden
+    )]
+    Errors {
+        #[structopt(long = "list", required = true, help = "Lists every error code")]
+        list: bool,
+    },
+    #[structopt(external_subcommand)]
+    External(Vec<String>),
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum TemplatesCommand {
+    #[structopt(
+        name = "list",
+        about = "Lists built-in template packs and the ones registered in `~/.cargo-mobile/template-packs.toml`"
+    )]
+    List,
+    #[structopt(
+        name = "lint",
+        about = "Renders a template pack with sample apps and reports anything that'd go wrong"
+    )]
+    Lint {
+        #[structopt(
+            name = "PACK",
+            help = "The pack to lint, as a name, path, or git URL like `app.template-pack` takes"
+        )]
+        pack: String,
+        #[structopt(long = "json", help = "Print findings as JSON")]
+        json: bool,
+        #[structopt(flatten)]
+        template_vars: cli::TemplateVars,
+    },
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum VmsCommand {
+    #[structopt(
+        name = "list",
+        about = "Lists the simulators and emulators cargo-mobile booted, and whether they're responsive"
+    )]
+    List,
+    #[structopt(
+        name = "stop",
+        about = "Shuts down a simulator or emulator cargo-mobile booted, or all of them"
+    )]
+    Stop {
+        #[structopt(
+            long = "all",
+            help = "Shut down everything cargo-mobile booted",
+            conflicts_with = "name",
+            required_unless = "name"
+        )]
+        all: bool,
+        #[structopt(name = "name", help = "The name or ID of the one to shut down")]
+        name: Option<String>,
+    },
 }
 
 #[derive(Debug)]
 pub enum Error {
     InitFailed(init::Error),
+    NewFailed(new::Error),
     OpenFailed(util::OpenInEditorError),
     UpdateFailed(update::Error),
+    DoctorFailed(doctor::Failed),
+    SetupFailed(setup::Error),
+    BuildFailed(workspace::Error),
+    CheckConfigFailed(config::CheckError),
+    RegenFailed(regen::Error),
+    UpgradeFailed(upgrade::Error),
+    GcFailed(generated::Error),
+    TemplatesFailed(templating::ListError),
+    LintFailed(templating::lint::Error),
+    VersionFailed(version::Error),
+    BumpFailed(version::BumpError),
     #[cfg(target_os = "macos")]
     AppleFailed(cargo_mobile::apple::cli::Error),
     AndroidFailed(cargo_mobile::android::cli::Error),
+    PluginFailed(plugin::Error),
+    ForgetFailed(device::ForgetError),
+    VmsFailed(vms::Error),
 }
 
 impl Reportable for Error {
     fn report(&self) -> Report {
         match self {
             Self::InitFailed(err) => err.report(),
+            Self::NewFailed(err) => err.report(),
             Self::OpenFailed(err) => {
                 Report::error("Failed to open project in default code editor", err)
             }
             Self::UpdateFailed(err) => Report::error("Failed to update `cargo-mobile`", err),
+            Self::DoctorFailed(err) => err.report(),
+            Self::SetupFailed(err) => err.report(),
+            Self::BuildFailed(err) => err.report(),
+            Self::CheckConfigFailed(err) => err.report(),
+            Self::RegenFailed(err) => err.report(),
+            Self::UpgradeFailed(err) => err.report(),
+            Self::GcFailed(err) => err.report(),
+            Self::TemplatesFailed(err) => Report::error("Failed to list template packs", err),
+            Self::LintFailed(err) => err.report(),
+            Self::VersionFailed(err) => err.report(),
+            Self::BumpFailed(err) => err.report(),
             #[cfg(target_os = "macos")]
             Self::AppleFailed(err) => err.report(),
             Self::AndroidFailed(err) => err.report(),
+            Self::PluginFailed(err) => err.report(),
+            Self::ForgetFailed(err) => err.report(),
+            Self::VmsFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::InitFailed(err) => err.code(),
+            Self::NewFailed(err) => err.code(),
+            Self::OpenFailed(..) => "cli.open-failed",
+            Self::UpdateFailed(..) => "cli.update-failed",
+            Self::DoctorFailed(err) => err.code(),
+            Self::SetupFailed(err) => err.code(),
+            Self::BuildFailed(err) => err.code(),
+            Self::CheckConfigFailed(err) => err.code(),
+            Self::RegenFailed(err) => err.code(),
+            Self::UpgradeFailed(err) => err.code(),
+            Self::GcFailed(err) => err.code(),
+            Self::TemplatesFailed(..) => "cli.templates-failed",
+            Self::LintFailed(err) => err.code(),
+            Self::VersionFailed(err) => err.code(),
+            Self::BumpFailed(err) => err.code(),
+            #[cfg(target_os = "macos")]
+            Self::AppleFailed(err) => err.code(),
+            Self::AndroidFailed(err) => err.code(),
+            Self::PluginFailed(err) => err.code(),
+            Self::ForgetFailed(err) => err.code(),
+            Self::VmsFailed(err) => err.code(),
         }
     }
 }
@@ -102,28 +419,98 @@ impl Exec for Input {
         self.flags
     }
 
+    fn package(&self) -> &cli::Package {
+        &self.package
+    }
+
     fn exec(self, wrapper: &TextWrapper) -> Result<(), Self::Report> {
-        let Self { flags, command } = self;
+        let Self {
+            flags,
+            list,
+            command,
+            ..
+        } = self;
         let GlobalFlags {
-            non_interactive, ..
+            noise_level,
+            non_interactive,
+            format,
+            ..
         } = flags;
+        let format = format.unwrap_or_default();
+        if list {
+            print_subcommands();
+            return Ok(());
+        }
+        let command = match command {
+            Some(command) => command,
+            None => {
+                // There were args, just none that do anything on their own.
+                let _ = Input::clap().print_long_help();
+                println!();
+                return Ok(());
+            }
+        };
         match command {
             Command::Init {
                 skip_dev_tools: cli::SkipDevTools { skip_dev_tools },
                 reinstall_deps: cli::ReinstallDeps { reinstall_deps },
                 open_in_editor,
+                force,
                 submodule_commit,
-            } => init::exec(
+                development_team,
+                template_pack,
+                platforms,
+                template_vars: cli::TemplateVars { vars },
+                post_init_hooks: cli::PostInitHooks { yes, no_hooks },
+                dry_run,
+            } => dry_run
+                .exec(|writer| {
+                    init::exec(
+                        wrapper,
+                        non_interactive,
+                        skip_dev_tools,
+                        reinstall_deps,
+                        open_in_editor,
+                        opts::NoGit::No,
+                        force,
+                        submodule_commit,
+                        None,
+                        None,
+                        development_team,
+                        template_pack,
+                        &platforms,
+                        &vars,
+                        PostInitPolicy::from_flags(yes, no_hooks),
+                        writer,
+                        ".",
+                    )
+                    .map(|_| ())
+                })
+                .map_err(Error::InitFailed),
+            Command::New {
+                dir,
+                name,
+                domain,
+                template_pack,
+                platforms,
+                template_vars: cli::TemplateVars { vars },
+                post_init_hooks: cli::PostInitHooks { yes, no_hooks },
+                no_git,
+                force,
+            } => new::exec(
                 wrapper,
                 non_interactive,
-                skip_dev_tools,
-                reinstall_deps,
-                open_in_editor,
-                submodule_commit,
-                ".",
+                &dir,
+                name,
+                domain,
+                template_pack,
+                &platforms,
+                &vars,
+                PostInitPolicy::from_flags(yes, no_hooks),
+                no_git,
+                force,
             )
-            .map(|_| ())
-            .map_err(Error::InitFailed),
+            .map_err(Error::NewFailed),
             Command::Open => util::open_in_editor(".").map_err(Error::OpenFailed),
             Command::Update { init } => {
                 update::update(wrapper).map_err(Error::UpdateFailed)?;
@@ -135,12 +522,177 @@ impl Exec for Input {
                         Default::default(),
                         Default::default(),
                         Default::default(),
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                        &[],
+                        &[],
+                        PostInitPolicy::Skip,
+                        Writer::Disk,
                         ".",
                     )
                     .map_err(Error::InitFailed)?;
                 }
                 Ok(())
             }
+            Command::Doctor { json, sections } => {
+                let sections = doctor::check(&sections);
+                doctor::exec(wrapper, &sections, json || format.json()).map_err(Error::DoctorFailed)
+            }
+            Command::Setup => setup::exec(wrapper, non_interactive).map_err(Error::SetupFailed),
+            Command::Build {
+                all,
+                profile: cli::Profile { profile },
+            } => {
+                let apps = if all {
+                    workspace::apps()
+                } else {
+                    workspace::current().map(|app| vec![app])
+                }
+                .map_err(Error::BuildFailed)?;
+                workspace::build(&apps, profile, &flags).map_err(Error::BuildFailed)
+            }
+            Command::CheckConfig => {
+                let config = Config::check(".").map_err(Error::CheckConfigFailed)?;
+                println!("Config in {} is valid", config.source());
+                Ok(())
+            }
+            Command::Regen {
+                template_vars: cli::TemplateVars { vars },
+                dry_run,
+            } => dry_run
+                .exec(|writer| regen::exec(wrapper, non_interactive, &vars, writer))
+                .map_err(Error::RegenFailed),
+            Command::Upgrade {
+                dry_run: cli::DryRun { dry_run, diff },
+                template_vars: cli::TemplateVars { vars },
+            } => upgrade::exec(wrapper, non_interactive, dry_run, diff, &vars)
+                .map_err(Error::UpgradeFailed),
+            Command::Gc => {
+                let config = Config::check(".").map_err(Error::CheckConfigFailed)?;
+                generated::gc(&config, non_interactive).map_err(Error::GcFailed)
+            }
+            Command::Templates(TemplatesCommand::List) => {
+                let dir = templating::app_pack_dir()
+                    .map_err(templating::ListError::ExtractFailed)
+                    .map_err(Error::TemplatesFailed)?;
+                println!(
+                    "Built-in template packs, version {}, extracted to {:?}:",
+                    templating::embedded::VERSION,
+                    dir
+                );
+                for name in templating::list_app_packs().map_err(Error::TemplatesFailed)? {
+                    println!("    {} ({})", name, templating::Origin::Embedded);
+                }
+                let registry = templating::registry_path()
+                    .map_err(templating::ListError::NoHomeDir)
+                    .map_err(Error::TemplatesFailed)?;
+                let registered =
+                    templating::list_registered_packs().map_err(Error::TemplatesFailed)?;
+                if registered.is_empty() {
+                    println!("No template packs are registered in {:?}", registry);
+                } else {
+                    println!("Registered template packs, from {:?}:", registry);
+                    for (name, source, shadowed) in registered {
+                        let origin = source
+                            .origin()
+                            .map_err(templating::ListError::NoHomeDir)
+                            .map_err(Error::TemplatesFailed)?;
+                        if shadowed {
+                            println!(
+                                "    {}: {} ({}, shadowed by the built-in pack)",
+                                name, source, origin
+                            );
+                        } else {
+                            println!("    {}: {} ({})", name, source, origin);
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Command::Templates(TemplatesCommand::Lint {
+                pack,
+                json,
+                template_vars: cli::TemplateVars { vars },
+            }) => {
+                let lint = templating::lint::lint(&pack, &vars).map_err(Error::LintFailed)?;
+                lint.print(json || format.json());
+                match lint.errors() {
+                    0 => Ok(()),
+                    errors => Err(Error::LintFailed(templating::lint::Error::ErrorsFound {
+                        errors,
+                    })),
+                }
+            }
+            Command::Version { bump } => {
+                // Printing the version shouldn't be what generates a config.
+                let config = Config::check(".").map_err(Error::CheckConfigFailed)?;
+                let old = Version::resolve(config.app()).map_err(Error::VersionFailed)?;
+                if let Some(bump) = bump {
+                    version::bump(&config.app().manifest_path(), bump)
+                        .map_err(Error::BumpFailed)?;
+                    let new = Version::resolve(config.app()).map_err(Error::VersionFailed)?;
+                    regen::exec(wrapper, non_interactive, &[], Writer::Disk)
+                        .map_err(Error::RegenFailed)?;
+                    println!("Version: {} -> {}", old.name, new.name);
+                    println!("Build number: {} -> {}", old.code, new.code);
+                } else {
+                    println!("Version: {}", old.name);
+                    println!("Build number: {}", old.code);
+                }
+                Ok(())
+            }
+            Command::Devices { forget: true, .. } => {
+                let config = Config::check(".").map_err(Error::CheckConfigFailed)?;
+                for platform in Platform::ALL {
+                    let last = LastDevice::new(config.app().root_dir(), *platform);
+                    if last.forget().map_err(Error::ForgetFailed)? {
+                        println!("Forgot the previously selected {} device", platform);
+                    }
+                }
+                Ok(())
+            }
+            Command::Devices { json, .. } => {
+                let mut listings = Vec::new();
+                // Missing tools for one platform shouldn't hide the other
+                // platform's devices.
+                let results = vec![
+                    apple_devices(),
+                    cargo_mobile::android::cli::device_listings().map_err(Error::AndroidFailed),
+                ];
+                for result in results {
+                    match result {
+                        Ok(more) => listings.extend(more),
+                        Err(err) => err.report().print(wrapper),
+                    }
+                }
+                device::print_listings(&listings, json || format.json());
+                Ok(())
+            }
+            Command::Vms(VmsCommand::List) => {
+                vms::print_list(&vms::list().map_err(Error::VmsFailed)?);
+                Ok(())
+            }
+            // `--all` is required without a name, so no name means everything.
+            Command::Vms(VmsCommand::Stop { name, .. }) => {
+                let stopped = vms::stop(name.as_deref()).map_err(Error::VmsFailed)?;
+                if stopped.is_empty() {
+                    println!("cargo-mobile hasn't booted anything that's still tracked");
+                }
+                for instance in stopped {
+                    println!("Shut down {}", instance);
+                }
+                Ok(())
+            }
+            Command::Completions {
+                completions: cli::Completions { shell },
+            } => {
+                cli::print_completions::<Input>(NAME, shell, false);
+                Ok(())
+            }
             #[cfg(target_os = "macos")]
             Command::Apple(command) => cargo_mobile::apple::cli::Input::new(flags, command)
                 .exec(wrapper)
@@ -148,6 +700,54 @@ impl Exec for Input {
             Command::Android(command) => cargo_mobile::android::cli::Input::new(flags, command)
                 .exec(wrapper)
                 .map_err(Error::AndroidFailed),
+            Command::Errors { .. } => {
+                for code in errors::ALL {
+                    println!("{}", code);
+                }
+                Ok(())
+            }
+            Command::External(args) => {
+                let (name, args) = args
+                    .split_first()
+                    .expect("developer error: external subcommand had no name");
+                let built_in = cli::subcommands::<Input>();
+                plugin::exec(
+                    plugin::Context {
+                        noise_level,
+                        non_interactive,
+                        format,
+                    },
+                    name,
+                    args,
+                    &built_in
+                        .iter()
+                        .map(|(name, _)| name.as_str())
+                        .collect::<Vec<_>>(),
+                )
+                .map_err(Error::PluginFailed)
+            }
+        }
+    }
+}
+
+fn print_subcommands() {
+    println!("Built-in subcommands:");
+    for (name, about) in cli::subcommands::<Input>() {
+        match about {
+            Some(about) => println!("    {:16} {}", name, about),
+            None => println!("    {}", name),
+        }
+    }
+    let plugins = plugin::discover();
+    if plugins.is_empty() {
+        println!(
+            "No plugins were found on your `PATH` (plugins are executables named `{}<name>`)",
+            plugin::PREFIX
+        );
+    } else {
+        println!("Plugins, from your `PATH`:");
+        for (name, path) in plugins {
+            println!("    {:16} {}", name, path.display());
         }
     }
 }
@@ -1,28 +1,43 @@
 mod common_email_providers;
 pub mod domain;
 pub mod name;
+mod platform;
 mod raw;
 
-pub use self::raw::*;
+pub use self::{platform::*, raw::*};
 
+use super::{
+    build_config::{self, BuildConfig},
+    variant::{self, Variant},
+};
 use crate::{
+    resources,
     templating::{self, Pack},
     util::{self, cli::Report},
+    version::BuildNumber,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
 };
-use serde::Serialize;
-use std::path::{Path, PathBuf};
 
 pub static KEY: &str = "app";
 
 pub static DEFAULT_ASSET_DIR: &str = "assets";
+
 #[cfg(feature = "brainium")]
 pub static DEFAULT_TEMPLATE_PACK: &str = "brainstorm";
 #[cfg(not(feature = "brainium"))]
 pub static DEFAULT_TEMPLATE_PACK: &str = "bevy";
 
+pub const DEFAULT_MIN_FREE_SPACE_GIB: u64 = 2;
+
 #[derive(Debug)]
 pub enum Error {
     NameInvalid(name::Invalid),
+    NamesUnusable(name::DeriveError),
     DomainInvalid {
         domain: String,
         cause: domain::DomainError,
@@ -36,6 +51,9 @@ pub enum Error {
         root_dir: PathBuf,
     },
     TemplatePackNotFound(templating::LookupError),
+    PlatformsEmpty,
+    VariantInvalid(variant::Error),
+    BuildConfigInvalid(build_config::Error),
 }
 
 impl Error {
@@ -44,6 +62,13 @@ impl Error {
             Self::NameInvalid(err) => {
                 Report::error(msg, format!("`{}.name` invalid: {}", KEY, err))
             }
+            Self::NamesUnusable(err) => Report::error(
+                msg,
+                format!(
+                    "`{}.name`, `{}.stylized-name`, or `[lib] name` in `Cargo.toml` can't be used: {}",
+                    KEY, KEY, err
+                ),
+            ),
             Self::DomainInvalid { domain, cause } => Report::error(
                 msg,
                 format!("`{}.domain` {:?} isn't valid: {}", KEY, domain, cause),
@@ -66,6 +91,45 @@ impl Error {
                 ),
             ),
             Self::TemplatePackNotFound(err) => Report::error(msg, err),
+            Self::PlatformsEmpty => Report::error(
+                msg,
+                format!(
+                    "`{}.platforms` is empty, so there'd be nothing to generate; remove it to select every platform",
+                    KEY
+                ),
+            ),
+            Self::VariantInvalid(err) => err.report(msg),
+            Self::BuildConfigInvalid(err) => err.report(msg),
+        }
+    }
+}
+
+// Cargo names the lib after the package unless `[lib] name` says otherwise.
+// There's no `Cargo.toml` yet during init, but the one it generates doesn't
+// rename the lib, and one that can't be parsed is left for cargo to complain
+// about.
+fn manifest_lib_name(manifest_path: &Path) -> Option<String> {
+    #[derive(Debug, Default, Deserialize)]
+    struct Lib {
+        name: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct CargoToml {
+        #[serde(default)]
+        lib: Lib,
+    }
+
+    let bytes = fs::read(manifest_path).ok()?;
+    match toml::from_slice::<CargoToml>(&bytes) {
+        Ok(cargo_toml) => cargo_toml.lib.name,
+        Err(err) => {
+            log::warn!(
+                "failed to parse {:?}, so assuming the lib isn't renamed: {}",
+                manifest_path,
+                err
+            );
+            None
         }
     }
 }
@@ -76,20 +140,48 @@ pub struct App {
     root_dir: PathBuf,
     name: String,
     stylized_name: String,
+    #[serde(flatten)]
+    names: name::Names,
     domain: String,
     asset_dir: PathBuf,
     #[serde(skip)]
+    asset_ignore: Vec<String>,
+    #[serde(skip)]
     template_pack: Pack,
+    #[serde(skip)]
+    platforms: Vec<Platform>,
+    #[serde(skip)]
+    build_number: BuildNumber,
+    #[serde(skip)]
+    variants: BTreeMap<String, Variant>,
+    #[serde(skip)]
+    build_config: BuildConfig,
+    #[serde(skip)]
+    min_free_space: u64,
+    #[serde(skip)]
+    jobs: resources::Setting,
 }
 
 impl App {
-    pub fn from_raw(root_dir: PathBuf, raw: Raw) -> Result<Self, Error> {
+    pub fn from_raw(
+        root_dir: PathBuf,
+        raw: Raw,
+        variants: Option<BTreeMap<String, variant::Raw>>,
+        build_config: Option<build_config::Raw>,
+    ) -> Result<Self, Error> {
         assert!(root_dir.is_absolute(), "root must be absolute");
 
         let name = name::validate(raw.name).map_err(Error::NameInvalid)?;
 
         let stylized_name = raw.stylized_name.unwrap_or_else(|| name.clone());
 
+        let lib_name = manifest_lib_name(&root_dir.join("Cargo.toml"));
+        if let Some(lib_name) = &lib_name {
+            log::info!("`[lib] name` is set, so the lib is named {:?}", lib_name);
+        }
+        let names = name::Names::derive(&name, lib_name.as_deref(), &stylized_name)
+            .map_err(Error::NamesUnusable)?;
+
         let domain = {
             let domain = raw.domain;
             domain::check_domain_syntax(&domain)
@@ -146,15 +238,63 @@ impl App {
         #[cfg(not(feature = "brainium"))]
         let template_pack = &raw.template_pack;
 
-        let template_pack = Pack::lookup_app(template_pack).map_err(Error::TemplatePackNotFound)?;
+        let template_pack = Pack::lookup_app_source(template_pack, &root_dir)
+            .map_err(Error::TemplatePackNotFound)?;
+
+        let platforms = match raw.platforms {
+            Some(mut platforms) => {
+                if platforms.is_empty() {
+                    return Err(Error::PlatformsEmpty);
+                }
+                platforms.sort();
+                platforms.dedup();
+                platforms
+            }
+            None => {
+                log::info!("`{}.platforms` not set; defaulting to every platform", KEY);
+                Platform::ALL.to_vec()
+            }
+        };
+
+        let build_number = raw.build_number.unwrap_or_else(|| {
+            let build_number = BuildNumber::default();
+            log::info!(
+                "`{}.build-number` not set; defaulting to {}",
+                KEY,
+                build_number
+            );
+            build_number
+        });
+
+        let min_free_space = raw.min_free_space_gib.unwrap_or_else(|| {
+            log::info!(
+                "`{}.min-free-space-gib` not set; defaulting to {}",
+                KEY,
+                DEFAULT_MIN_FREE_SPACE_GIB
+            );
+            DEFAULT_MIN_FREE_SPACE_GIB
+        });
+
+        let variants = Variant::from_raw_map(variants).map_err(Error::VariantInvalid)?;
+
+        let build_config = BuildConfig::from_raw(build_config::KEY, build_config)
+            .map_err(Error::BuildConfigInvalid)?;
 
         Ok(Self {
             root_dir,
             name,
             stylized_name,
+            names,
             domain,
             asset_dir,
+            asset_ignore: raw.asset_ignore.unwrap_or_default(),
             template_pack,
+            platforms,
+            build_number,
+            variants,
+            build_config,
+            min_free_space: min_free_space.saturating_mul(1 << 30),
+            jobs: raw.jobs.unwrap_or_default(),
         })
     }
 
@@ -174,15 +314,16 @@ impl App {
         &self.name
     }
 
-    pub fn name_snake(&self) -> String {
-        use heck::SnekCase as _;
-        self.name().to_snek_case()
-    }
-
     pub fn stylized_name(&self) -> &str {
         &self.stylized_name
     }
 
+    /// The forms of the name that go in file names, identifiers, and the
+    /// like.
+    pub fn names(&self) -> &name::Names {
+        &self.names
+    }
+
     pub fn reverse_domain(&self) -> String {
         self.domain
             .clone()
@@ -200,7 +341,119 @@ impl App {
         self.root_dir().join(&self.asset_dir)
     }
 
+    /// Patterns for what in the asset dir not to package, in `.gitignore`
+    /// syntax, relative to the asset dir.
+    pub fn asset_ignore(&self) -> &[String] {
+        &self.asset_ignore
+    }
+
     pub fn template_pack(&self) -> &Pack {
         &self.template_pack
     }
+
+    pub fn platforms(&self) -> &[Platform] {
+        &self.platforms
+    }
+
+    /// Whether `platform` is one projects get generated for.
+    pub fn selects(&self, platform: Platform) -> bool {
+        self.platforms.contains(&platform)
+    }
+
+    pub fn build_number(&self) -> BuildNumber {
+        self.build_number
+    }
+
+    pub fn variants(&self) -> impl Iterator<Item = &Variant> {
+        self.variants.values()
+    }
+
+    /// The variant passed with `--variant`, if there was one. Naming one that
+    /// isn't in the config is an error.
+    pub fn variant(&self, name: Option<&str>) -> Result<Option<&Variant>, variant::Unknown> {
+        variant::select(&self.variants, name)
+    }
+
+    /// Without any variant's values overlaid.
+    pub fn build_config(&self) -> &BuildConfig {
+        &self.build_config
+    }
+
+    /// How much free space builds should have to work with, in bytes. Having
+    /// less only gets a warning, since this is a rough guess.
+    pub fn min_free_space(&self) -> u64 {
+        self.min_free_space
+    }
+
+    /// `--jobs` and `CARGO_BUILD_JOBS` both win over this.
+    pub fn jobs(&self) -> resources::Setting {
+        self.jobs
+    }
+
+    /// Every build config key the app or any of its variants has, for the
+    /// generated projects to pick up whichever are set at build time.
+    pub fn build_config_keys(&self) -> BTreeSet<&str> {
+        self.variants
+            .values()
+            .flat_map(|variant| variant.build_config().keys())
+            .chain(self.build_config.keys())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{templating, util};
+
+    static ANDROID_MANIFEST: &str = include_str!(
+        "../../../templates/platforms/android-studio/app/src/main/AndroidManifest.xml.hbs"
+    );
+    static PROJECT_YML: &str = include_str!("../../../templates/platforms/xcode/project.yml.hbs");
+
+    #[test]
+    fn test_renamed_lib_resolves_in_templates() {
+        let dir = util::TempDir::new("renamed-lib-test").unwrap();
+        // `from_raw` compares it against the asset dir, which is canonicalized.
+        let root_dir = dir.path().canonicalize().unwrap();
+        fs::write(
+            root_dir.join("Cargo.toml"),
+            "[package]\nname = \"my-app\"\nversion = \"0.1.0\"\n\n[lib]\nname = \"engine_core\"\ncrate-type = [\"staticlib\", \"cdylib\", \"rlib\"]\n",
+        )
+        .unwrap();
+        let app = App::from_raw(
+            root_dir.clone(),
+            Raw {
+                name: "my-app".to_owned(),
+                stylized_name: None,
+                domain: "example.com".to_owned(),
+                asset_dir: None,
+                asset_ignore: None,
+                #[cfg(feature = "brainium")]
+                template_pack: Some(".".to_owned()),
+                #[cfg(not(feature = "brainium"))]
+                template_pack: ".".to_owned(),
+                platforms: None,
+                build_number: None,
+                min_free_space_gib: None,
+                jobs: None,
+            },
+            None,
+            None,
+        );
+        let app = app.unwrap();
+        assert_eq!(app.names().crate_name(), "my-app");
+        assert_eq!(app.names().lib_name(), "engine_core");
+        let bike = templating::init(None, &[]);
+        let render = |template: &str| {
+            bike.render(template, |map| {
+                map.insert(KEY, &app);
+            })
+            .unwrap()
+        };
+        assert!(render(ANDROID_MANIFEST).contains("android:value=\"engine_core\""));
+        let project_yml = render(PROJECT_YML);
+        assert!(project_yml.contains("framework: libengine_core.a"));
+        assert!(!project_yml.contains("libmy-app.a"));
+    }
 }
@@ -1,6 +1,7 @@
 use crate::util;
 use heck::{KebabCase as _, SnekCase as _};
 use reserved_names::{is_reserved, Reservation};
+use serde::Serialize;
 use std::{
     fmt::{self, Display},
     ops::Deref,
@@ -158,8 +159,8 @@ fn validate_non_recursive<T: Deref<Target = str>>(app_name: T) -> Result<T, Inva
     // general requirements, but being conservative here is a super good idea.
     // Rust also forbids some reserved keywords. We're extra aggressive and
     // forbid uppercase entirely, since it's very unconventional to use. We do
-    // allow hyphens, so when hyphens are unacceptable `app_name_snake` must be
-    // used.
+    // allow hyphens, so when hyphens are unacceptable one of the other forms in
+    // `Names` must be used.
     if !app_name.is_empty() {
         if app_name.is_ascii() {
             if has_initial_number(&app_name.deref()) {
@@ -238,3 +239,358 @@ pub fn validate<T: Deref<Target = str>>(app_name: T) -> Result<T, Invalid> {
     }
     result
 }
+
+// The App Store cuts names off here, and anything longer doesn't fit under an
+// icon anyway.
+pub static DISPLAY_NAME_MAX_LEN: usize = 30;
+
+// Punctuation that reads fine both under an Android icon and as
+// `CFBundleDisplayName`. Templates escape the display name wherever it ends up,
+// so this is about what looks right on a home screen more than what's safe.
+static DISPLAY_NAME_PUNCTUATION: &[char] = &[
+    ' ', '-', '_', '.', ',', '&', '!', '+', '(', ')', '\'', '\u{2019}',
+];
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum DisplayNameInvalid {
+    Empty,
+    SurroundingWhitespace {
+        display_name: String,
+    },
+    TooLong {
+        display_name: String,
+        len: usize,
+    },
+    CharsNotAllowed {
+        display_name: String,
+        naughty_chars: Vec<char>,
+    },
+}
+
+impl Display for DisplayNameInvalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "The stylized name can't be empty."),
+            Self::SurroundingWhitespace { display_name } => write!(
+                f,
+                "\"{}\" starts or ends with whitespace, which gets lost or mangled on the home screen.",
+                display_name
+            ),
+            Self::TooLong { display_name, len } => write!(
+                f,
+                "\"{}\" is {} characters long, but the App Store only allows {}.",
+                display_name, len, DISPLAY_NAME_MAX_LEN
+            ),
+            Self::CharsNotAllowed {
+                display_name,
+                naughty_chars,
+            } => write!(
+                f,
+                "\"{}\" contains {}, but Android labels and iOS display names can only safely contain letters, numbers, spaces, and {}.",
+                display_name,
+                util::list_display(
+                    &naughty_chars.iter().map(|c| format!("'{}'", c)).collect::<Vec<_>>()
+                ),
+                util::list_display(
+                    &DISPLAY_NAME_PUNCTUATION[1..]
+                        .iter()
+                        .map(|c| format!("'{}'", c))
+                        .collect::<Vec<_>>()
+                ),
+            ),
+        }
+    }
+}
+
+/// Checks the stylized name against what Android's resource compiler, Xcode,
+/// and the App Store will all accept.
+pub fn validate_display_name(display_name: &str) -> Result<(), DisplayNameInvalid> {
+    if display_name.trim().is_empty() {
+        return Err(DisplayNameInvalid::Empty);
+    }
+    if display_name.trim() != display_name {
+        return Err(DisplayNameInvalid::SurroundingWhitespace {
+            display_name: display_name.to_owned(),
+        });
+    }
+    let len = display_name.chars().count();
+    if len > DISPLAY_NAME_MAX_LEN {
+        return Err(DisplayNameInvalid::TooLong {
+            display_name: display_name.to_owned(),
+            len,
+        });
+    }
+    let mut naughty_chars = Vec::new();
+    for c in display_name
+        .chars()
+        .filter(|c| !c.is_alphanumeric() && !DISPLAY_NAME_PUNCTUATION.contains(c))
+    {
+        if !naughty_chars.contains(&c) {
+            naughty_chars.push(c);
+        }
+    }
+    if naughty_chars.is_empty() {
+        Ok(())
+    } else {
+        Err(DisplayNameInvalid::CharsNotAllowed {
+            display_name: display_name.to_owned(),
+            naughty_chars,
+        })
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum DeriveError {
+    Empty {
+        form: &'static str,
+        from: String,
+    },
+    StartsWithDigit {
+        form: &'static str,
+        from: String,
+        derived: String,
+    },
+}
+
+impl Display for DeriveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty { form, from } => {
+                write!(f, "The {} derived from \"{}\" would be empty.", form, from)
+            }
+            Self::StartsWithDigit {
+                form,
+                from,
+                derived,
+            } => write!(
+                f,
+                "The {} derived from \"{}\" would be \"{}\", which starts with a digit.",
+                form, from, derived
+            ),
+        }
+    }
+}
+
+fn check_derived(form: &'static str, from: &str, derived: String) -> Result<String, DeriveError> {
+    match derived.chars().next() {
+        None => Err(DeriveError::Empty {
+            form,
+            from: from.to_owned(),
+        }),
+        Some(c) if c.is_ascii_digit() => Err(DeriveError::StartsWithDigit {
+            form,
+            from: from.to_owned(),
+            derived,
+        }),
+        Some(_) => Ok(derived),
+    }
+}
+
+/// Every form of the app's name that ends up somewhere, all derived here so
+/// that Rust, Gradle, and Xcode agree with each other. Templates get these
+/// under `app`, i.e. `{{app.lib-name}}`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Names {
+    /// The package name in `Cargo.toml`, which is just `app.name`.
+    crate_name: String,
+    /// What cargo calls the lib, which is `[lib] name` if `Cargo.toml` sets
+    /// it, and otherwise the crate name with hyphens replaced by underscores;
+    /// this has to match cargo exactly, since it's in the file names of the
+    /// built libs.
+    lib_name: String,
+    /// `app.stylized-name`, which is what's shown under the app icon.
+    display_name: String,
+    /// Safe to use as a segment of a Java package or an identifier in code:
+    /// lowercase ASCII letters, digits, and single underscores.
+    identifier: String,
+    /// The name of the Xcode project, its targets, and the built `.app`,
+    /// which sticks to ASCII letters, digits, hyphens, and underscores.
+    xcode_product_name: String,
+}
+
+impl Names {
+    /// `lib_name` is `[lib] name` from `Cargo.toml`, if it's set.
+    pub fn derive(
+        crate_name: &str,
+        lib_name: Option<&str>,
+        display_name: &str,
+    ) -> Result<Self, DeriveError> {
+        let crate_name = check_derived("crate name", crate_name, crate_name.to_owned())?;
+        let lib_name = match lib_name {
+            Some(lib_name) => check_derived("lib name", lib_name, lib_name.to_owned())?,
+            None => check_derived("lib name", &crate_name, crate_name.replace('-', "_"))?,
+        };
+        let display_name =
+            check_derived("display name", display_name, display_name.trim().to_owned())?;
+        let identifier = check_derived(
+            "identifier",
+            &crate_name,
+            deunicode::deunicode(&crate_name)
+                .to_ascii_lowercase()
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>()
+                .join("_"),
+        )?;
+        let xcode_product_name = check_derived(
+            "Xcode product name",
+            &crate_name,
+            deunicode::deunicode(&crate_name)
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+                .collect(),
+        )?;
+        Ok(Self {
+            crate_name,
+            lib_name,
+            display_name,
+            identifier,
+            xcode_product_name,
+        })
+    }
+
+    pub fn crate_name(&self) -> &str {
+        &self.crate_name
+    }
+
+    pub fn lib_name(&self) -> &str {
+        &self.lib_name
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn identifier(&self) -> &str {
+        &self.identifier
+    }
+
+    pub fn xcode_product_name(&self) -> &str {
+        &self.xcode_product_name
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        crate_name,
+        display_name,
+        lib_name,
+        identifier,
+        xcode_product_name,
+        case(
+            "my-cool-app",
+            "My Cool App",
+            "my_cool_app",
+            "my_cool_app",
+            "my-cool-app"
+        ),
+        case(
+            "my--cool--app",
+            " My Cool App ",
+            "my__cool__app",
+            "my_cool_app",
+            "my--cool--app"
+        ),
+        case(
+            "snake_case_app",
+            "Snake Case",
+            "snake_case_app",
+            "snake_case_app",
+            "snake_case_app"
+        ),
+        case("-app-", "App", "_app_", "app", "-app-"),
+        case("café-app", "Café", "café_app", "cafe_app", "cafe-app"),
+        case("app 2", "App 2", "app 2", "app_2", "app2"),
+        case(
+            "cafe-tracker",
+            "Café Tracker",
+            "cafe_tracker",
+            "cafe_tracker",
+            "cafe-tracker"
+        ),
+        case("joes-app", "Joe's App", "joes_app", "joes_app", "joes-app")
+    )]
+    fn test_derive(
+        crate_name: &str,
+        display_name: &str,
+        lib_name: &str,
+        identifier: &str,
+        xcode_product_name: &str,
+    ) {
+        let names = Names::derive(crate_name, None, display_name).unwrap();
+        assert_eq!(names.crate_name(), crate_name);
+        assert_eq!(names.lib_name(), lib_name);
+        assert_eq!(names.display_name(), display_name.trim());
+        assert_eq!(names.identifier(), identifier);
+        assert_eq!(names.xcode_product_name(), xcode_product_name);
+        // Xcode's schemes and targets are named after this, so the display
+        // name never gets anywhere near them.
+        assert!(names
+            .xcode_product_name()
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    }
+
+    #[rstest(
+        crate_name,
+        display_name,
+        expected,
+        case("", "App", DeriveError::Empty { form: "crate name", from: "".to_owned() }),
+        case("app", "  ", DeriveError::Empty { form: "display name", from: "  ".to_owned() }),
+        case(
+            "9lives",
+            "9 Lives",
+            DeriveError::StartsWithDigit { form: "crate name", from: "9lives".to_owned(), derived: "9lives".to_owned() }
+        ),
+        case(
+            "-9lives",
+            "9 Lives",
+            DeriveError::StartsWithDigit { form: "identifier", from: "-9lives".to_owned(), derived: "9lives".to_owned() }
+        ),
+        case("---", "Dashes", DeriveError::Empty { form: "identifier", from: "---".to_owned() })
+    )]
+    fn test_derive_fails(crate_name: &str, display_name: &str, expected: DeriveError) {
+        assert_eq!(Names::derive(crate_name, None, display_name), Err(expected));
+    }
+
+    #[rstest(
+        crate_name,
+        lib_name,
+        expected,
+        case("my-app", "engine_core", "engine_core"),
+        case("my-app", "my_app", "my_app"),
+        case("engine", "my_engine", "my_engine")
+    )]
+    fn test_derive_renamed_lib(crate_name: &str, lib_name: &str, expected: &str) {
+        let names = Names::derive(crate_name, Some(lib_name), "App").unwrap();
+        assert_eq!(names.crate_name(), crate_name);
+        assert_eq!(names.lib_name(), expected);
+    }
+
+    #[rstest(
+        display_name,
+        expected,
+        case("My Cool App", Ok(())),
+        case("Café Ōkami (Beta)", Ok(())),
+        case("Joe's Café Tracker", Ok(())),
+        case("Ça Va’s Tracker", Ok(())),
+        case("", Err(DisplayNameInvalid::Empty)),
+        case(" App", Err(DisplayNameInvalid::SurroundingWhitespace { display_name: " App".to_owned() })),
+        case(
+            "An Extraordinarily Long App Name",
+            Err(DisplayNameInvalid::TooLong { display_name: "An Extraordinarily Long App Name".to_owned(), len: 32 })
+        ),
+        case(
+            "AC/DC 🎸",
+            Err(DisplayNameInvalid::CharsNotAllowed { display_name: "AC/DC 🎸".to_owned(), naughty_chars: vec!['/', '🎸'] })
+        )
+    )]
+    fn test_validate_display_name(display_name: &str, expected: Result<(), DisplayNameInvalid>) {
+        assert_eq!(validate_display_name(display_name), expected);
+    }
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub struct UnknownPlatform(String);
+
+impl Display for UnknownPlatform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} isn't a platform; the platforms are {}",
+            self.0,
+            Platform::ALL
+                .iter()
+                .map(|platform| format!("`{}`", platform))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+/// A platform a project can be generated for, as selected by `app.platforms`
+/// or `--platform`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Platform {
+    Android,
+    Apple,
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = UnknownPlatform;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|platform| platform.name() == s)
+            .ok_or_else(|| UnknownPlatform(s.to_owned()))
+    }
+}
+
+impl Platform {
+    pub const ALL: &'static [Self] = &[Self::Android, Self::Apple];
+
+    /// Also the name of the platform's config section, template data, and
+    /// directory in app template packs. This can't use `crate::apple::NAME`,
+    /// since Apple is still a platform when the host isn't macOS.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Android => "android",
+            Self::Apple => "apple",
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use super::{common_email_providers::COMMON_EMAIL_PROVIDERS, domain, name};
 use crate::{
-    templating,
+    resources, templating,
     util::{cli::TextWrapper, prompt, Git},
+    version::BuildNumber,
 };
 use colored::{Color, Colorize as _};
 use heck::{KebabCase as _, TitleCase as _};
@@ -94,6 +95,9 @@ impl Defaults {
 pub enum DetectError {
     DefaultsFailed(DefaultsError),
     NameNotDetected,
+    NameInvalid(name::Invalid),
+    StylizedNameInvalid(name::DisplayNameInvalid),
+    DomainInvalid(domain::DomainError),
 }
 
 impl Display for DetectError {
@@ -101,6 +105,9 @@ impl Display for DetectError {
         match self {
             Self::DefaultsFailed(err) => write!(f, "Failed to detect default values: {}", err),
             Self::NameNotDetected => write!(f, "No app name was detected."),
+            Self::NameInvalid(err) => write!(f, "{}", err),
+            Self::StylizedNameInvalid(err) => write!(f, "{}", err),
+            Self::DomainInvalid(err) => write!(f, "{}", err),
         }
     }
 }
@@ -108,6 +115,8 @@ impl Display for DetectError {
 #[derive(Debug)]
 pub enum PromptError {
     DefaultsFailed(DefaultsError),
+    NameInvalid(name::Invalid),
+    DomainInvalid(domain::DomainError),
     NamePromptFailed(io::Error),
     StylizedNamePromptFailed(io::Error),
     DomainPromptFailed(io::Error),
@@ -119,6 +128,8 @@ impl Display for PromptError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::DefaultsFailed(err) => write!(f, "Failed to detect default values: {}", err),
+            Self::NameInvalid(err) => write!(f, "{}", err),
+            Self::DomainInvalid(err) => write!(f, "{}", err),
             Self::NamePromptFailed(err) => write!(f, "Failed to prompt for name: {}", err),
             Self::StylizedNamePromptFailed(err) => {
                 write!(f, "Failed to prompt for stylized name: {}", err)
@@ -139,33 +150,99 @@ pub struct Raw {
     pub stylized_name: Option<String>,
     pub domain: String,
     pub asset_dir: Option<String>,
+    pub asset_ignore: Option<Vec<String>>,
     #[cfg(feature = "brainium")]
     pub template_pack: Option<String>,
     #[cfg(not(feature = "brainium"))]
     pub template_pack: String,
+    pub platforms: Option<Vec<super::Platform>>,
+    pub build_number: Option<BuildNumber>,
+    pub min_free_space_gib: Option<u64>,
+    pub jobs: Option<resources::Setting>,
 }
 
 impl Raw {
-    pub fn detect() -> Result<Self, DetectError> {
+    /// `name`, `domain`, and `template_pack` are used instead of the
+    /// defaults, if given. Since nobody's around to pick something else, a
+    /// value that breaks the rules for it is an error.
+    pub fn detect(
+        name: Option<&str>,
+        domain: Option<&str>,
+        template_pack: Option<&str>,
+    ) -> Result<Self, DetectError> {
         let defaults = Defaults::new().map_err(DetectError::DefaultsFailed)?;
+        let template_pack = template_pack.unwrap_or(super::DEFAULT_TEMPLATE_PACK);
+        #[cfg(feature = "brainium")]
+        let template_pack = Some(template_pack)
+            .filter(|pack| *pack != super::DEFAULT_TEMPLATE_PACK)
+            .map(str::to_owned);
+        #[cfg(not(feature = "brainium"))]
+        let template_pack = template_pack.to_owned();
+        let (name, stylized_name) = match name {
+            Some(name) => (
+                name::validate(name)
+                    .map_err(DetectError::NameInvalid)?
+                    .to_owned(),
+                name.to_title_case(),
+            ),
+            None => (
+                defaults.name.ok_or_else(|| DetectError::NameNotDetected)?,
+                defaults.stylized_name,
+            ),
+        };
+        name::validate_display_name(&stylized_name).map_err(DetectError::StylizedNameInvalid)?;
+        let domain = match domain {
+            Some(domain) => {
+                domain::check_domain_syntax(domain).map_err(DetectError::DomainInvalid)?;
+                domain.to_owned()
+            }
+            None => defaults.domain,
+        };
         Ok(Self {
-            name: defaults.name.ok_or_else(|| DetectError::NameNotDetected)?,
-            stylized_name: Some(defaults.stylized_name),
-            domain: defaults.domain,
+            name,
+            stylized_name: Some(stylized_name),
+            domain,
             asset_dir: None,
-            #[cfg(feature = "brainium")]
-            template_pack: None,
-            #[cfg(not(feature = "brainium"))]
-            template_pack: super::DEFAULT_TEMPLATE_PACK.to_owned(),
+            asset_ignore: None,
+            template_pack,
+            platforms: None,
+            build_number: None,
+            min_free_space_gib: None,
+            jobs: None,
         })
     }
 
-    pub fn prompt(wrapper: &TextWrapper) -> Result<Self, PromptError> {
+    /// `name`, `domain`, and `template_pack` are used instead of prompting
+    /// for them, if given, as long as they're valid; otherwise, this fails
+    /// with the same message the prompt would've shown.
+    pub fn prompt(
+        wrapper: &TextWrapper,
+        name: Option<&str>,
+        domain: Option<&str>,
+        template_pack: Option<&str>,
+    ) -> Result<Self, PromptError> {
         let defaults = Defaults::new().map_err(PromptError::DefaultsFailed)?;
-        let (name, default_stylized) = Self::prompt_name(wrapper, &defaults)?;
-        let stylized_name = Self::prompt_stylized_name(&name, default_stylized)?;
-        let domain = Self::prompt_domain(wrapper, &defaults)?;
-        let template_pack = Self::prompt_template_pack(wrapper)?;
+        let (name, default_stylized) = match name {
+            Some(name) => (
+                name::validate(name)
+                    .map_err(PromptError::NameInvalid)?
+                    .to_owned(),
+                None,
+            ),
+            None => Self::prompt_name(wrapper, &defaults)?,
+        };
+        let stylized_name = Self::prompt_stylized_name(wrapper, &name, default_stylized)?;
+        let domain = match domain {
+            Some(domain) => {
+                domain::check_domain_syntax(domain).map_err(PromptError::DomainInvalid)?;
+                domain.to_owned()
+            }
+            None => Self::prompt_domain(wrapper, &defaults)?,
+        };
+        let template_pack = match template_pack {
+            Some(template_pack) => template_pack.to_owned(),
+            None => Self::prompt_template_pack(wrapper)?,
+        };
         #[cfg(feature = "brainium")]
         let template_pack = Some(template_pack).filter(|pack| pack != super::DEFAULT_TEMPLATE_PACK);
         Ok(Self {
@@ -173,9 +250,90 @@ impl Raw {
             stylized_name: Some(stylized_name),
             domain,
             asset_dir: None,
+            asset_ignore: None,
             template_pack,
+            platforms: None,
+            build_number: None,
+            min_free_space_gib: None,
+            jobs: None,
         })
     }
+
+    /// For configs that only exist to render templates with, so nothing's
+    /// detected or validated until it gets to `App::from_raw`.
+    pub fn sample(
+        name: &str,
+        stylized_name: &str,
+        template_pack: &str,
+        platforms: &[super::Platform],
+    ) -> Self {
+        Self {
+            name: name.to_owned(),
+            stylized_name: Some(stylized_name.to_owned()),
+            domain: "example.com".to_owned(),
+            asset_dir: None,
+            asset_ignore: None,
+            #[cfg(feature = "brainium")]
+            template_pack: Some(template_pack.to_owned()),
+            #[cfg(not(feature = "brainium"))]
+            template_pack: template_pack.to_owned(),
+            platforms: Some(platforms.to_vec()),
+            build_number: None,
+            min_free_space_gib: None,
+            jobs: None,
+        }
+    }
+
+    /// Returns `true` if this changed anything, in which case the config needs
+    /// to be written back out for the change to stick.
+    pub fn set_template_pack(&mut self, template_pack: Option<&str>) -> bool {
+        #[cfg(feature = "brainium")]
+        let current = self
+            .template_pack
+            .as_deref()
+            .unwrap_or(super::DEFAULT_TEMPLATE_PACK);
+        #[cfg(not(feature = "brainium"))]
+        let current = self.template_pack.as_str();
+        match template_pack {
+            Some(template_pack) if template_pack != current => {
+                log::info!(
+                    "changing `{}.template-pack` from {:?} to {:?}",
+                    super::KEY,
+                    current,
+                    template_pack
+                );
+                #[cfg(feature = "brainium")]
+                {
+                    self.template_pack = Some(template_pack.to_owned())
+                        .filter(|pack| pack != super::DEFAULT_TEMPLATE_PACK);
+                }
+                #[cfg(not(feature = "brainium"))]
+                {
+                    self.template_pack = template_pack.to_owned();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if this changed anything, like
+    /// [`Self::set_template_pack`].
+    pub fn set_platforms(&mut self, platforms: Option<&[super::Platform]>) -> bool {
+        match platforms {
+            Some(platforms) if self.platforms.as_deref() != Some(platforms) => {
+                log::info!(
+                    "changing `{}.platforms` from {:?} to {:?}",
+                    super::KEY,
+                    self.platforms,
+                    platforms
+                );
+                self.platforms = Some(platforms.to_vec());
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 impl Raw {
@@ -222,33 +380,41 @@ impl Raw {
     }
 
     fn prompt_stylized_name(
+        wrapper: &TextWrapper,
         name: &str,
         default_stylized: Option<String>,
     ) -> Result<String, PromptError> {
         let stylized = default_stylized
             .unwrap_or_else(|| name.replace("-", " ").replace("_", " ").to_title_case());
-        prompt::default("Stylized name", Some(&stylized), None)
-            .map_err(PromptError::StylizedNamePromptFailed)
+        prompt::validated(
+            wrapper,
+            "Stylized name",
+            Some(&stylized),
+            name::validate_display_name,
+        )
+        .map_err(PromptError::StylizedNamePromptFailed)
     }
 
     fn prompt_domain(wrapper: &TextWrapper, defaults: &Defaults) -> Result<String, PromptError> {
-        Ok(loop {
-            let response = prompt::default("Domain", Some(&defaults.domain), None)
-                .map_err(PromptError::DomainPromptFailed)?;
-            match domain::check_domain_syntax(response.as_str()) {
-                Ok(_) => break response,
-                Err(err) => {
-                    println!(
-                        "{}",
-                        wrapper.fill(&format!("Sorry! {}", err)).bright_magenta()
-                    )
-                }
-            }
-        })
+        prompt::validated(
+            wrapper,
+            "Domain",
+            Some(&defaults.domain),
+            domain::check_domain_syntax,
+        )
+        .map_err(PromptError::DomainPromptFailed)
     }
 
     pub fn prompt_template_pack(wrapper: &TextWrapper) -> Result<String, PromptError> {
-        let packs = templating::list_app_packs().map_err(PromptError::ListTemplatePacksFailed)?;
+        let mut packs =
+            templating::list_app_packs().map_err(PromptError::ListTemplatePacksFailed)?;
+        packs.extend(
+            templating::list_registered_packs()
+                .map_err(PromptError::ListTemplatePacksFailed)?
+                .into_iter()
+                .filter(|(_, _, shadowed)| !shadowed)
+                .map(|(name, _, _)| name),
+        );
         let mut default_pack = None;
         println!("Detected template packs:");
         for (index, pack) in packs.iter().enumerate() {
@@ -269,7 +435,10 @@ impl Raw {
             println!("  -- none --");
         }
         loop {
-            println!("  Enter an {} for a template pack above.", "index".green(),);
+            println!(
+                "  Enter an {} for a template pack above, or a path or git URL for another one.",
+                "index".green(),
+            );
             let pack_input = prompt::default(
                 "Template pack",
                 default_pack.as_deref(),
@@ -280,7 +449,11 @@ impl Raw {
                 .parse::<usize>()
                 .ok()
                 .and_then(|index| packs.get(index))
-                .map(|pack| pack.clone());
+                .map(|pack| pack.clone())
+                .or_else(|| match templating::Source::parse(&pack_input) {
+                    templating::Source::Named(_) => None,
+                    _ => Some(pack_input.clone()),
+                });
             if let Some(pack_name) = pack_name {
                 break Ok(pack_name);
             } else {
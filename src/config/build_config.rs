@@ -0,0 +1,391 @@
+//! Values baked into the app at build time, like an API endpoint or a feature
+//! toggle, which are defined under `[build-config]` (and per variant under
+//! `[variants.<name>.build-config]`).
+//!
+//! Each value reaches the Rust build as a `CARGO_MOBILE_CFG_<KEY>` env var, so
+//! it can be read with `env!` or `option_env!`, and reaches the platform
+//! projects through the same env vars. A value of `env:VAR_NAME` is read from
+//! `VAR_NAME` when building instead, which keeps secrets out of the config;
+//! those are never written anywhere, and are redacted wherever env vars are
+//! printed.
+
+use super::variant::Variant;
+use crate::{
+    env::BuildEnv,
+    opts::Profile,
+    util::cli::{Report, Reportable},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
+    fmt::{self, Debug},
+    hash::{Hash, Hasher},
+};
+
+pub static KEY: &str = "build-config";
+
+static VAR_PREFIX: &str = "CARGO_MOBILE_CFG_";
+
+static ENV_PREFIX: &str = "env:";
+
+#[derive(Debug)]
+pub enum Error {
+    KeyInvalid { table: String, key: String },
+    VarNameInvalid { table: String, key: String },
+}
+
+impl Error {
+    pub fn report(&self, msg: &str) -> Report {
+        match self {
+            Self::KeyInvalid { table, key } => Report::error(
+                msg,
+                format!(
+                    "`{}.{}` isn't a valid key; keys have to start with an uppercase letter and contain only uppercase letters, digits, and underscores, since they're used as env var and field names",
+                    table, key
+                ),
+            ),
+            Self::VarNameInvalid { table, key } => Report::error(
+                msg,
+                format!(
+                    "`{}.{}` starts with `{}`, but isn't followed by an env var name",
+                    table, key, ENV_PREFIX
+                ),
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ResolveError {
+    key: String,
+    var: String,
+}
+
+impl Reportable for ResolveError {
+    fn report(&self) -> Report {
+        Report::action_request(
+            format!("Build config value `{}` isn't set", self.key),
+            format!(
+                "It's read from the `{}` env var at build time, which isn't set",
+                self.var
+            ),
+        )
+    }
+
+    fn code(&self) -> &'static str {
+        "config.build-config.unset"
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Raw {
+    values: Option<BTreeMap<String, String>>,
+    debug: Option<BTreeMap<String, String>>,
+    release: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Value {
+    Literal(String),
+    Env(String),
+}
+
+fn key_valid(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_uppercase())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn parse_table(
+    table: &str,
+    raw: Option<BTreeMap<String, String>>,
+) -> Result<BTreeMap<String, Value>, Error> {
+    raw.unwrap_or_default()
+        .into_iter()
+        .map(|(key, value)| {
+            if !key_valid(&key) {
+                return Err(Error::KeyInvalid {
+                    table: table.to_owned(),
+                    key,
+                });
+            }
+            let value = match value.strip_prefix(ENV_PREFIX) {
+                Some("") => {
+                    return Err(Error::VarNameInvalid {
+                        table: table.to_owned(),
+                        key,
+                    })
+                }
+                Some(var) => Value::Env(var.to_owned()),
+                None => Value::Literal(value),
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// The name of the env var that `key`'s value is passed along in.
+pub fn var_name(key: &str) -> String {
+    format!("{}{}", VAR_PREFIX, key)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BuildConfig {
+    values: BTreeMap<String, Value>,
+    debug: BTreeMap<String, Value>,
+    release: BTreeMap<String, Value>,
+}
+
+impl BuildConfig {
+    /// `table` is where `raw` came from in the config, for error messages.
+    pub fn from_raw(table: &str, raw: Option<Raw>) -> Result<Self, Error> {
+        let raw = raw.unwrap_or_default();
+        Ok(Self {
+            values: parse_table(&format!("{}.values", table), raw.values)?,
+            debug: parse_table(&format!("{}.debug", table), raw.debug)?,
+            release: parse_table(&format!("{}.release", table), raw.release)?,
+        })
+    }
+
+    /// Overlays the variant's values, which win over the ones they share a
+    /// key with, regardless of profile.
+    pub fn with_variant(&self, variant: Option<&Variant>) -> Self {
+        let mut config = self.clone();
+        if let Some(variant) = variant {
+            for (key, value) in &variant.build_config().values {
+                config.debug.remove(key);
+                config.release.remove(key);
+                config.values.insert(key.clone(), value.clone());
+            }
+            config.debug.extend(variant.build_config().debug.clone());
+            config
+                .release
+                .extend(variant.build_config().release.clone());
+        }
+        config
+    }
+
+    /// Every key, whichever profile it's for.
+    pub fn keys(&self) -> BTreeSet<&str> {
+        self.values
+            .keys()
+            .chain(self.debug.keys())
+            .chain(self.release.keys())
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn for_profile(&self, profile: Profile) -> BTreeMap<&str, &Value> {
+        let overrides = match profile {
+            Profile::Debug => &self.debug,
+            Profile::Release => &self.release,
+        };
+        self.values
+            .iter()
+            .chain(overrides)
+            .map(|(key, value)| (key.as_str(), value))
+            .collect()
+    }
+
+    // When we're run from inside a build we started (i.e. by Gradle or Xcode),
+    // the value was already resolved and passed along, so that's used if the
+    // original env var didn't make it.
+    fn resolve_with(
+        &self,
+        profile: Profile,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Resolved, ResolveError> {
+        let vars = self
+            .for_profile(profile)
+            .into_iter()
+            .map(|(key, value)| {
+                let name = var_name(key);
+                let (value, secret) = match value {
+                    Value::Literal(value) => (value.clone(), false),
+                    Value::Env(var) => (
+                        lookup(var)
+                            .or_else(|| lookup(&name))
+                            .ok_or_else(|| ResolveError {
+                                key: key.to_owned(),
+                                var: var.clone(),
+                            })?,
+                        true,
+                    ),
+                };
+                Ok(Var {
+                    name,
+                    value,
+                    secret,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Resolved { vars })
+    }
+
+    /// The values to build with for `profile`, with `env:` values read from
+    /// the environment.
+    pub fn resolve(&self, profile: Profile) -> Result<Resolved, ResolveError> {
+        self.resolve_with(profile, |var| std::env::var(var).ok())
+    }
+}
+
+#[derive(Clone)]
+struct Var {
+    name: String,
+    value: String,
+    secret: bool,
+}
+
+/// Build config values, ready to be passed along as env vars.
+#[derive(Clone, Default)]
+pub struct Resolved {
+    vars: Vec<Var>,
+}
+
+// Secrets would otherwise end up in the logs along with anything that has
+// this in it.
+impl Debug for Resolved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.vars.iter().map(|var| {
+                let value = if var.secret {
+                    crate::env::REDACTED
+                } else {
+                    var.value.as_str()
+                };
+                (&var.name, value)
+            }))
+            .finish()
+    }
+}
+
+impl Resolved {
+    pub fn env_vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars
+            .iter()
+            .map(|var| (var.name.as_str(), var.value.as_str()))
+    }
+
+    pub fn apply(&self, build_env: &mut BuildEnv) {
+        for var in &self.vars {
+            if var.secret {
+                build_env.insert_secret(var.name.as_str(), &var.value);
+            } else {
+                build_env.insert(var.name.as_str(), &var.value);
+            }
+        }
+    }
+
+    /// Changes along with the values, without giving them away, so that it
+    /// can go in a fingerprint.
+    pub fn digest(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        for var in &self.vars {
+            var.name.hash(&mut hasher);
+            var.value.hash(&mut hasher);
+        }
+        format!("{:016x}", hasher.finish())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn table(entries: &[(&str, &str)]) -> Option<BTreeMap<String, String>> {
+        Some(
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[rstest(
+        key,
+        expected,
+        case("API_URL", true),
+        case("FEATURE_2", true),
+        case("api_url", false),
+        case("2FA", false),
+        case("API-URL", false),
+        case("", false)
+    )]
+    fn test_key_valid(key: &str, expected: bool) {
+        assert_eq!(key_valid(key), expected);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let config = BuildConfig::from_raw(
+            KEY,
+            Some(Raw {
+                values: table(&[("API_URL", "https://staging.example.com"), ("BETA", "true")]),
+                release: table(&[
+                    ("API_URL", "https://example.com"),
+                    ("API_KEY", "env:EXAMPLE_API_KEY"),
+                ]),
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+        let lookup = |var: &str| Some(format!("<{}>", var)).filter(|_| var == "EXAMPLE_API_KEY");
+        let debug = config.resolve_with(Profile::Debug, lookup).unwrap();
+        assert_eq!(
+            debug.env_vars().collect::<Vec<_>>(),
+            vec![
+                ("CARGO_MOBILE_CFG_API_URL", "https://staging.example.com"),
+                ("CARGO_MOBILE_CFG_BETA", "true"),
+            ]
+        );
+        let release = config.resolve_with(Profile::Release, lookup).unwrap();
+        assert_eq!(
+            release.env_vars().collect::<Vec<_>>(),
+            vec![
+                ("CARGO_MOBILE_CFG_API_KEY", "<EXAMPLE_API_KEY>"),
+                ("CARGO_MOBILE_CFG_API_URL", "https://example.com"),
+                ("CARGO_MOBILE_CFG_BETA", "true"),
+            ]
+        );
+        assert!(!format!("{:?}", release).contains("<EXAMPLE_API_KEY>"));
+        let err = config.resolve_with(Profile::Release, |_| None).unwrap_err();
+        assert_eq!(err.var, "EXAMPLE_API_KEY");
+        // Already resolved by whatever started this build
+        let nested = config
+            .resolve_with(Profile::Release, |var| {
+                Some("passed along".to_owned()).filter(|_| var == "CARGO_MOBILE_CFG_API_KEY")
+            })
+            .unwrap();
+        assert_eq!(
+            nested.env_vars().next(),
+            Some(("CARGO_MOBILE_CFG_API_KEY", "passed along"))
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(matches!(
+            BuildConfig::from_raw(
+                KEY,
+                Some(Raw {
+                    debug: table(&[("API_KEY", "env:")]),
+                    ..Default::default()
+                }),
+            ),
+            Err(Error::VarNameInvalid { .. })
+        ));
+        assert!(matches!(
+            BuildConfig::from_raw(
+                KEY,
+                Some(Raw {
+                    values: table(&[("api_key", "hunter2")]),
+                    ..Default::default()
+                }),
+            ),
+            Err(Error::KeyInvalid { .. })
+        ));
+    }
+}
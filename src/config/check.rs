@@ -0,0 +1,352 @@
+//! Parsing the config with enough context to say where it's wrong. Plain
+//! deserialization drops unknown keys on the floor, which makes a typo like
+//! `min-sdk-verison` look exactly like the key not being set at all.
+
+use super::{app, build_config, raw::Raw, template, variant, Source};
+#[cfg(target_os = "macos")]
+use crate::apple;
+use crate::{android, util};
+use once_cell_regex::regex;
+use serde::{
+    de::{self, DeserializeOwned, Deserializer, Visitor},
+    Deserialize,
+};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Problem {
+    line: Option<usize>,
+    msg: String,
+}
+
+impl Problem {
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+}
+
+// Deserializing into this only gets as far as asking for the struct's fields,
+// which is all we want out of it.
+struct FieldsProbe;
+
+#[derive(Debug)]
+struct Fields(Option<&'static [&'static str]>);
+
+impl Display for Fields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not a struct")
+    }
+}
+
+impl std::error::Error for Fields {}
+
+impl de::Error for Fields {
+    fn custom<T: Display>(_msg: T) -> Self {
+        Self(None)
+    }
+}
+
+impl<'de> Deserializer<'de> for FieldsProbe {
+    type Error = Fields;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(Fields(None))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        Err(Fields(Some(fields)))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum
+        identifier ignored_any
+    }
+}
+
+/// The keys a config table deserialized into `T` can have.
+pub fn known_fields<T: DeserializeOwned>() -> &'static [&'static str] {
+    match T::deserialize(FieldsProbe) {
+        Err(Fields(Some(fields))) => fields,
+        _ => &[],
+    }
+}
+
+// Only used for suggestions, so tables we don't know about (i.e. ones that
+// are passed along as-is) just don't get any.
+fn known_keys(table: &[&str]) -> &'static [&'static str] {
+    match table {
+        [] => known_fields::<Raw>(),
+        ["app"] => known_fields::<app::Raw>(),
+        #[cfg(target_os = "macos")]
+        ["apple", rest @ ..] => apple::config::known_keys(rest),
+        ["android", rest @ ..] => android::config::known_keys(rest),
+        ["variants", _] => known_fields::<variant::Raw>(),
+        ["build-config"] | ["variants", _, "build-config"] => known_fields::<build_config::Raw>(),
+        ["template"] => known_fields::<template::Raw>(),
+        _ => &[],
+    }
+}
+
+fn segments(path: &serde_ignored::Path<'_>) -> Vec<String> {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => Vec::new(),
+        Path::Seq { parent, index } => {
+            let mut segments = segments(parent);
+            segments.push(index.to_string());
+            segments
+        }
+        Path::Map { parent, key } => {
+            let mut segments = segments(parent);
+            segments.push(key.clone());
+            segments
+        }
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => segments(parent),
+    }
+}
+
+// The `apple` section is only understood on macOS, but it's perfectly valid
+// for it to be there everywhere else. `key` is relative to the config.
+fn skipped(key: &[String]) -> bool {
+    cfg!(not(target_os = "macos")) && key.first().map(String::as_str) == Some("apple")
+}
+
+fn split_key(key: &str) -> Vec<String> {
+    key.split('.')
+        .map(|part| {
+            part.trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .to_owned()
+        })
+        .collect()
+}
+
+// The line a key is defined on, or failing that, the line its closest
+// ancestor is (which is what happens for keys in inline tables). This only
+// understands as much TOML as config files tend to use, so anything exotic
+// just goes without a line.
+fn locate(source: &str, key: &[String]) -> Option<usize> {
+    let mut table = Vec::new();
+    let mut array_lens = HashMap::<Vec<String>, usize>::new();
+    let mut closest = None;
+    for (index, line) in source.lines().enumerate() {
+        let path = if let Some(caps) =
+            regex!(r#"^\s*(?P<open>\[\[?)\s*(?P<key>[\w\-.\s"']+?)\s*\]\]?\s*(#.*)?$"#)
+                .captures(line)
+        {
+            let mut path = split_key(&caps["key"]);
+            if &caps["open"] == "[[" {
+                let len = array_lens.entry(path.clone()).or_default();
+                path.push(len.to_string());
+                *len += 1;
+            }
+            table = path.clone();
+            path
+        } else if let Some(caps) = regex!(r#"^\s*(?P<key>[\w\-.\s"']+?)\s*="#).captures(line) {
+            table
+                .iter()
+                .cloned()
+                .chain(split_key(&caps["key"]))
+                .collect()
+        } else {
+            continue;
+        };
+        if path == key {
+            return Some(index + 1);
+        }
+        if key.starts_with(&path) && closest.map_or(true, |(len, _)| path.len() > len) {
+            closest = Some((path.len(), index + 1));
+        }
+    }
+    closest.map(|(_, line)| line)
+}
+
+fn unknown_key(contents: &str, prefix: usize, key: Vec<String>) -> Problem {
+    let (name, table) = key[prefix..]
+        .split_last()
+        .expect("the root can't be an unknown key");
+    let table = table.iter().map(String::as_str).collect::<Vec<_>>();
+    let mut msg = format!("unknown key `{}`", key.join("."));
+    if let Some(suggestion) = util::suggest(name, known_keys(&table).iter().copied()) {
+        msg.push_str(&format!(", did you mean `{}`?", suggestion));
+    }
+    Problem {
+        line: locate(contents, &key),
+        msg,
+    }
+}
+
+fn invalid(err: toml::de::Error) -> Problem {
+    let msg = err.to_string();
+    Problem {
+        line: err.line_col().map(|(line, _)| line + 1),
+        // The line's reported separately, and the column isn't much use.
+        msg: regex!(r" at line \d+ column \d+$")
+            .replace(&msg, "")
+            .into_owned(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CargoTable {
+    metadata: Option<CargoMetadata>,
+}
+
+#[derive(Deserialize)]
+struct CargoMetadata {
+    #[serde(rename = "cargo-mobile")]
+    config: Option<Raw>,
+}
+
+// Only as much of `Cargo.toml` as it takes to get to our table; everything
+// else in it is cargo's business.
+#[derive(Deserialize)]
+struct CargoToml {
+    package: Option<CargoTable>,
+    workspace: Option<CargoTable>,
+}
+
+impl CargoToml {
+    fn config(self, table: &str) -> Option<Raw> {
+        match table {
+            "package" => self.package,
+            _ => self.workspace,
+        }
+        .and_then(|table| table.metadata)
+        .and_then(|metadata| metadata.config)
+    }
+}
+
+// Deserializes the whole file, collecting the unknown keys under `prefix`.
+fn deserialize<T: DeserializeOwned>(
+    contents: &str,
+    prefix: &[&str],
+) -> (Result<T, toml::de::Error>, Vec<Vec<String>>) {
+    let mut unknown = Vec::new();
+    let mut deserializer = toml::Deserializer::new(contents);
+    let result = serde_ignored::deserialize(&mut deserializer, |path| {
+        let key = segments(&path);
+        if key.len() > prefix.len() && key.iter().zip(prefix).all(|(a, b)| a == b) {
+            unknown.push(key);
+        }
+    })
+    .and_then(|value| deserializer.end().map(|()| value));
+    (result, unknown)
+}
+
+/// Parses the config out of `contents`, reporting every unknown key along
+/// with whatever made parsing fail. Deserialization stops at the first invalid
+/// value, so there's never more than one of those, and unknown keys after it
+/// go unnoticed until it's fixed.
+pub fn parse(source: &Source, contents: &str) -> Result<Raw, Vec<Problem>> {
+    let prefix = source.prefix();
+    let (result, unknown) = match source {
+        Source::File(_) => {
+            let (result, unknown) = deserialize::<Raw>(contents, &prefix);
+            (result.map(Some), unknown)
+        }
+        Source::CargoMetadata { table, .. } => {
+            let (result, unknown) = deserialize::<CargoToml>(contents, &prefix);
+            (result.map(|cargo_toml| cargo_toml.config(table)), unknown)
+        }
+    };
+    let mut problems = unknown
+        .into_iter()
+        .filter(|key| !skipped(&key[prefix.len()..]))
+        .map(|key| unknown_key(contents, prefix.len(), key))
+        .collect::<Vec<_>>();
+    match result {
+        Ok(Some(raw)) if problems.is_empty() => return Ok(raw),
+        Ok(Some(_)) => (),
+        // Only possible if the file changed since we found the config in it.
+        Ok(None) => problems.push(Problem {
+            line: None,
+            msg: format!("`{}` is missing", prefix.join(".")),
+        }),
+        Err(err) => problems.push(invalid(err)),
+    }
+    problems.sort_by_key(|problem| problem.line);
+    Err(problems)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const APP: &str =
+        "[app]\nname = \"fish\"\ndomain = \"example.com\"\ntemplate-pack = \"bevy\"\n";
+
+    fn file() -> Source {
+        Source::File("mobile.toml".into())
+    }
+
+    #[test]
+    fn test_unknown_keys_are_located_and_suggested() {
+        let contents = format!(
+            "{}\n[android]\nmin-sdk-verison = 24\nhooks = {{ pre-biuld = [] }}\n",
+            APP
+        );
+        assert_eq!(
+            parse(&file(), &contents).unwrap_err(),
+            vec![
+                Problem {
+                    line: Some(7),
+                    msg: "unknown key `android.min-sdk-verison`, did you mean `min-sdk-version`?"
+                        .to_owned(),
+                },
+                Problem {
+                    line: Some(8),
+                    msg: "unknown key `android.hooks.pre-biuld`, did you mean `pre-build`?"
+                        .to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_values_are_located() {
+        let contents = format!("{}\n[android]\nmin-sdk-version = \"24\"\n", APP);
+        let problems = parse(&file(), &contents).unwrap_err();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line(), Some(7));
+        assert!(problems[0].msg().contains("min-sdk-version"));
+    }
+
+    #[test]
+    fn test_cargo_metadata_is_checked_the_same_way() {
+        let source = Source::CargoMetadata {
+            path: "Cargo.toml".into(),
+            table: "package",
+        };
+        let contents = format!(
+            "[package]\nname = \"fish\"\n\n{}\n[android]\nmin-sdk-verison = 24\n\n[dependencies]\nlog = \"0.4\"\n",
+            APP.replace("[app]", "[package.metadata.cargo-mobile.app]")
+        )
+        .replace("[android]", "[package.metadata.cargo-mobile.android]");
+        assert_eq!(
+            parse(&source, &contents).unwrap_err(),
+            vec![Problem {
+                line: Some(10),
+                msg: "unknown key `package.metadata.cargo-mobile.android.min-sdk-verison`, did you mean `min-sdk-version`?"
+                    .to_owned(),
+            }]
+        );
+        let contents = contents.replace("min-sdk-verison", "min-sdk-version");
+        assert!(parse(&source, &contents).is_ok());
+    }
+}
@@ -30,6 +30,13 @@ impl Reportable for Error {
             ),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadFailed { .. } => "config.metadata.read-failed",
+            Self::ParseFailed { .. } => "config.metadata.parse-failed",
+        }
+    }
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -43,6 +50,17 @@ pub struct Metadata {
 
 impl Metadata {
     pub fn load(project_root: &Path) -> Result<Self, Error> {
+        let path = project_root.join("Cargo.toml");
+        let bytes = fs::read(&path).map_err(|cause| Error::ReadFailed {
+            path: path.clone(),
+            cause,
+        })?;
+        Self::from_slice(path, &bytes)
+    }
+
+    /// Reads metadata from the contents of the `Cargo.toml` at `path`, for
+    /// when it hasn't been written yet.
+    pub fn from_slice(path: PathBuf, bytes: &[u8]) -> Result<Self, Error> {
         #[derive(Debug, Deserialize)]
         struct Package {
             #[serde(default)]
@@ -54,11 +72,6 @@ impl Metadata {
             package: Package,
         }
 
-        let path = project_root.join("Cargo.toml");
-        let bytes = fs::read(&path).map_err(|cause| Error::ReadFailed {
-            path: path.clone(),
-            cause,
-        })?;
         let cargo_toml = toml::from_slice::<CargoToml>(&bytes)
             .map_err(|cause| Error::ParseFailed { path, cause })?;
         Ok(cargo_toml.package.metadata.unwrap_or_default())
@@ -1,22 +1,34 @@
 pub mod app;
+pub mod build_config;
+mod check;
 pub mod metadata;
 mod raw;
+pub mod rustflags;
+mod source;
+pub mod template;
+pub mod variant;
 
-use self::{app::App, raw::*};
+use self::{
+    app::{App, Platform},
+    raw::*,
+    template::Template,
+};
+pub use self::{
+    check::{known_fields, Problem},
+    raw::LoadError,
+    source::{Source, CARGO_METADATA_KEY},
+};
 #[cfg(target_os = "macos")]
 use crate::apple;
 use crate::{
     android,
     opts::NonInteractive,
+    plan::Writer,
     templating,
     util::cli::{Report, Reportable, TextWrapper},
 };
 use serde::Serialize;
-use std::{
-    fmt::Debug,
-    io,
-    path::{Path, PathBuf},
-};
+use std::{fmt::Debug, io, path::Path};
 
 pub fn file_name() -> String {
     format!("{}.toml", crate::NAME)
@@ -28,6 +40,7 @@ pub enum FromRawError {
     #[cfg(target_os = "macos")]
     AppleConfigInvalid(apple::config::Error),
     AndroidConfigInvalid(android::config::Error),
+    TemplateConfigInvalid(template::Error),
 }
 
 impl FromRawError {
@@ -37,6 +50,7 @@ impl FromRawError {
             #[cfg(target_os = "macos")]
             Self::AppleConfigInvalid(err) => err.report(msg),
             Self::AndroidConfigInvalid(err) => err.report(msg),
+            Self::TemplateConfigInvalid(err) => err.report(msg),
         }
     }
 }
@@ -63,24 +77,82 @@ impl Reportable for GenError {
             Self::WriteFailed(err) => err.report(),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::PromptFailed(..) => "config.gen.prompt-failed",
+            Self::DetectFailed(..) => "config.gen.detect-failed",
+            Self::CanonicalizeFailed(..) => "config.gen.canonicalize-failed",
+            Self::FromRawFailed(..) => "config.gen.from-raw-failed",
+            Self::WriteFailed(..) => "config.gen.write-failed",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum LoadOrGenError {
     LoadFailed(LoadError),
-    FromRawFailed { path: PathBuf, cause: FromRawError },
+    FromRawFailed { source: Source, cause: FromRawError },
     GenFailed(GenError),
+    WriteFailed(WriteError),
+}
+
+fn load_failed(err: &LoadError) -> Report {
+    Report::error("Failed to load config", err)
+}
+
+fn from_raw_failed(source: &Source, cause: &FromRawError) -> Report {
+    cause.report(&format!("Config in {} invalid", source))
 }
 
 impl Reportable for LoadOrGenError {
     fn report(&self) -> Report {
         match self {
-            Self::LoadFailed(err) => Report::error("Failed to load config", err),
-            Self::FromRawFailed { path, cause } => {
-                let msg = format!("Config file at {:?} invalid", path);
-                cause.report(&msg)
-            }
+            Self::LoadFailed(err) => load_failed(err),
+            Self::FromRawFailed { source, cause } => from_raw_failed(source, cause),
             Self::GenFailed(err) => err.report(),
+            Self::WriteFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::LoadFailed(..) => "config.load-or-gen.load-failed",
+            Self::FromRawFailed { .. } => "config.load-or-gen.from-raw-failed",
+            Self::GenFailed(err) => err.code(),
+            Self::WriteFailed(err) => err.code(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CheckError {
+    LoadFailed(LoadError),
+    Missing,
+    FromRawFailed { source: Source, cause: FromRawError },
+}
+
+impl Reportable for CheckError {
+    fn report(&self) -> Report {
+        match self {
+            Self::LoadFailed(err) => load_failed(err),
+            Self::Missing => Report::error(
+                "Failed to load config",
+                format!(
+                    "No `{}` or `[package.metadata.{}]` was found in the current dir or any of its parents",
+                    file_name(),
+                    CARGO_METADATA_KEY,
+                ),
+            ),
+            Self::FromRawFailed { source, cause } => from_raw_failed(source, cause),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::LoadFailed(..) => "config.check.load-failed",
+            Self::Missing => "config.check.missing",
+            Self::FromRawFailed { .. } => "config.check.from-raw-failed",
         }
     }
 }
@@ -100,71 +172,155 @@ impl Origin {
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
+    #[serde(skip_serializing)]
+    source: Source,
+    #[serde(skip_serializing)]
+    raw: Raw,
     app: App,
     #[cfg(target_os = "macos")]
     apple: apple::config::Config,
     android: android::config::Config,
+    template: Template,
 }
 
 impl Config {
-    fn from_raw(root_dir: PathBuf, raw: Raw) -> Result<Self, FromRawError> {
-        let app = App::from_raw(root_dir, raw.app).map_err(FromRawError::AppConfigInvalid)?;
+    fn from_raw(source: Source, raw: Raw) -> Result<Self, FromRawError> {
+        let original = raw.clone();
+        let app = App::from_raw(
+            source.root_dir().to_owned(),
+            raw.app,
+            raw.variants,
+            raw.build_config,
+        )
+        .map_err(FromRawError::AppConfigInvalid)?;
         #[cfg(target_os = "macos")]
         let apple = apple::config::Config::from_raw(app.clone(), raw.apple)
             .map_err(FromRawError::AppleConfigInvalid)?;
         let android = android::config::Config::from_raw(app.clone(), raw.android)
             .map_err(FromRawError::AndroidConfigInvalid)?;
+        let template =
+            Template::from_raw(raw.template).map_err(FromRawError::TemplateConfigInvalid)?;
         Ok(Self {
+            source,
+            raw: original,
             app,
             #[cfg(target_os = "macos")]
             apple,
             android,
+            template,
         })
     }
 
     fn gen(
         cwd: impl AsRef<Path>,
         non_interactive: NonInteractive,
+        name: Option<&str>,
+        domain: Option<&str>,
+        development_team: Option<&str>,
+        template_pack: Option<&str>,
+        platforms: Option<&[Platform]>,
+        writer: Writer,
         wrapper: &TextWrapper,
     ) -> Result<Self, GenError> {
-        let raw = if non_interactive.no() {
-            Raw::prompt(wrapper).map_err(GenError::PromptFailed)
+        let mut raw = if non_interactive.no() {
+            Raw::prompt(wrapper, name, domain, development_team, template_pack)
+                .map_err(GenError::PromptFailed)
         } else {
-            Raw::detect().map_err(GenError::DetectFailed)
+            Raw::detect(wrapper, name, domain, development_team, template_pack)
+                .map_err(GenError::DetectFailed)
         }?;
+        raw.app.set_platforms(platforms);
         let root_dir = cwd
             .as_ref()
             .canonicalize()
             .map_err(GenError::CanonicalizeFailed)?;
-        let config =
-            Self::from_raw(root_dir.clone(), raw.clone()).map_err(GenError::FromRawFailed)?;
+        let source = Source::File(root_dir.join(file_name()));
+        let config = Self::from_raw(source, raw.clone()).map_err(GenError::FromRawFailed)?;
         log::info!("generated config: {:#?}", config);
-        raw.write(&root_dir).map_err(GenError::WriteFailed)?;
+        raw.write(&root_dir, writer)
+            .map_err(GenError::WriteFailed)?;
         Ok(config)
     }
 
+    /// `name` and `domain` are only used when generating a new config, instead
+    /// of prompting for them or detecting them. `development_team` overrides
+    /// the Apple development team in the config, `template_pack` overrides the
+    /// app template pack, and `platforms` overrides the selected platforms.
+    /// Any of them get persisted to the config if it's in its own file. During
+    /// a dry run, `writer` only plans writing the config.
     pub fn load_or_gen(
         cwd: impl AsRef<Path>,
         non_interactive: NonInteractive,
+        name: Option<&str>,
+        domain: Option<&str>,
+        development_team: Option<&str>,
+        template_pack: Option<&str>,
+        platforms: Option<&[Platform]>,
+        writer: Writer,
         wrapper: &TextWrapper,
     ) -> Result<(Self, Origin), LoadOrGenError> {
         let cwd = cwd.as_ref();
-        if let Some((root_dir, raw)) = Raw::load(cwd).map_err(LoadOrGenError::LoadFailed)? {
-            Self::from_raw(root_dir.clone(), raw)
+        if let Some((source, mut raw)) = Raw::load(cwd).map_err(LoadOrGenError::LoadFailed)? {
+            // Not short-circuiting, since they all need to be applied.
+            let changed = raw.set_development_team(wrapper, development_team)
+                | raw.app.set_template_pack(template_pack)
+                | raw.app.set_platforms(platforms);
+            if changed {
+                match &source {
+                    Source::File(path) => raw
+                        .write_overrides(path, writer)
+                        .map_err(LoadOrGenError::WriteFailed)?,
+                    // We'd rather not rewrite somebody's `Cargo.toml`.
+                    Source::CargoMetadata { .. } => log::warn!(
+                        "overrides from the command line aren't saved to {}, so they'll need to be passed again next time",
+                        source
+                    ),
+                }
+            }
+            Self::from_raw(source.clone(), raw)
                 .map(|config| (config, Origin::Loaded))
-                .map_err(|cause| LoadOrGenError::FromRawFailed {
-                    path: root_dir,
-                    cause,
-                })
+                .map_err(|cause| LoadOrGenError::FromRawFailed { source, cause })
         } else {
-            Self::gen(cwd, non_interactive, wrapper)
-                .map(|config| (config, Origin::FreshlyMinted))
-                .map_err(LoadOrGenError::GenFailed)
+            Self::gen(
+                cwd,
+                non_interactive,
+                name,
+                domain,
+                development_team,
+                template_pack,
+                platforms,
+                writer,
+                wrapper,
+            )
+            .map(|config| (config, Origin::FreshlyMinted))
+            .map_err(LoadOrGenError::GenFailed)
         }
     }
 
-    pub fn path(&self) -> PathBuf {
-        self.app().root_dir().join(file_name())
+    /// Loads the config the same way [`Self::load_or_gen`] does, without
+    /// generating or changing anything, so that problems are reported exactly
+    /// like they'd be when building.
+    pub fn check(cwd: impl AsRef<Path>) -> Result<Self, CheckError> {
+        let (source, raw) = Raw::load(cwd)
+            .map_err(CheckError::LoadFailed)?
+            .ok_or(CheckError::Missing)?;
+        Self::from_raw(source.clone(), raw)
+            .map_err(|cause| CheckError::FromRawFailed { source, cause })
+    }
+
+    /// A config that's never loaded from or written to a file, for rendering
+    /// templates with sample data. `root_dir` has to be absolute.
+    pub fn sample(root_dir: &Path, app: app::Raw) -> Result<Self, FromRawError> {
+        Self::from_raw(Source::File(root_dir.join(file_name())), Raw::sample(app))
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    /// The config as it was written, before any defaults were filled in.
+    pub fn raw_json(&self) -> serde_json::Value {
+        serde_json::to_value(&self.raw).expect("developer error: config couldn't be serialized")
     }
 
     pub fn app(&self) -> &App {
@@ -180,7 +336,12 @@ impl Config {
         &self.android
     }
 
-    pub fn build_a_bike(&self) -> bicycle::Bicycle {
-        templating::init(Some(self))
+    pub fn template(&self) -> &Template {
+        &self.template
+    }
+
+    /// `cli_vars` are the ones passed with `--var`, which override the config.
+    pub fn build_a_bike(&self, cli_vars: &[(String, String)]) -> bicycle::Bicycle {
+        templating::init(Some(self), cli_vars)
     }
 }
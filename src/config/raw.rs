@@ -1,17 +1,19 @@
-use super::app;
+use super::{app, build_config, check, template, variant, Problem, Source};
 #[cfg(target_os = "macos")]
 use crate::apple;
 use crate::{
     android,
+    plan::Writer,
     util::cli::{Report, Reportable, TextWrapper},
 };
 use serde::{Deserialize, Serialize};
-
 use std::{
+    collections::BTreeMap,
     fmt::{self, Display},
     fs, io,
     path::{Path, PathBuf},
 };
+use toml_edit::{Document, Item};
 
 #[derive(Debug)]
 pub enum PromptError {
@@ -33,6 +35,14 @@ impl Reportable for PromptError {
             ),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::AppFailed(..) => "config.raw.prompt.app-failed",
+            #[cfg(target_os = "macos")]
+            Self::AppleFailed(..) => "config.raw.prompt.apple-failed",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -54,6 +64,14 @@ impl Reportable for DetectError {
             }
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::AppFailed(..) => "config.raw.detect.app-failed",
+            #[cfg(target_os = "macos")]
+            Self::AppleFailed(..) => "config.raw.detect.apple-failed",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -63,10 +81,14 @@ pub enum LoadError {
         path: PathBuf,
         cause: io::Error,
     },
-    ParseFailed {
+    CargoTomlInvalid {
         path: PathBuf,
         cause: toml::de::Error,
     },
+    Invalid {
+        source: Source,
+        problems: Vec<Problem>,
+    },
 }
 
 impl Display for LoadError {
@@ -78,10 +100,21 @@ impl Display for LoadError {
                 err
             ),
             Self::ReadFailed { path, cause } => {
-                write!(f, "Failed to read config file at {:?}: {}", path, cause)
+                write!(f, "Failed to read {:?}: {}", path, cause)
+            }
+            Self::CargoTomlInvalid { path, cause } => {
+                write!(f, "Failed to parse {:?}: {}", path, cause)
             }
-            Self::ParseFailed { path, cause } => {
-                write!(f, "Failed to parse config file at {:?}: {}", path, cause)
+            Self::Invalid { source, problems } => {
+                write!(f, "Config in {} is invalid:", source)?;
+                let path = source.path();
+                for problem in problems {
+                    match problem.line() {
+                        Some(line) => write!(f, "\n{}:{}: {}", path.display(), line, problem.msg()),
+                        None => write!(f, "\n{}: {}", path.display(), problem.msg()),
+                    }?;
+                }
+                Ok(())
             }
         }
     }
@@ -89,6 +122,8 @@ impl Display for LoadError {
 
 #[derive(Debug)]
 pub enum WriteError {
+    ReadFailed(io::Error),
+    ParseFailed(toml_edit::TomlError),
     SerializeFailed(toml::ser::Error),
     WriteFailed(io::Error),
 }
@@ -96,10 +131,21 @@ pub enum WriteError {
 impl Reportable for WriteError {
     fn report(&self) -> Report {
         match self {
+            Self::ReadFailed(err) => Report::error("Failed to read config", err),
+            Self::ParseFailed(err) => Report::error("Failed to parse config", err),
             Self::SerializeFailed(err) => Report::error("Failed to serialize config", err),
             Self::WriteFailed(err) => Report::error("Failed to write config", err),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadFailed(..) => "config.raw.write.read-failed",
+            Self::ParseFailed(..) => "config.raw.write.parse-failed",
+            Self::SerializeFailed(..) => "config.raw.write.serialize-failed",
+            Self::WriteFailed(..) => "config.raw.write.write-failed",
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -109,74 +155,216 @@ pub struct Raw {
     #[cfg(target_os = "macos")]
     pub apple: Option<apple::config::Raw>,
     pub android: Option<android::config::Raw>,
+    pub variants: Option<BTreeMap<String, variant::Raw>>,
+    pub build_config: Option<build_config::Raw>,
+    pub template: Option<template::Raw>,
 }
 
 impl Raw {
-    pub fn prompt(wrapper: &TextWrapper) -> Result<Self, PromptError> {
-        let app = app::Raw::prompt(wrapper).map_err(PromptError::AppFailed)?;
+    // `development_team` is only used on macOS
+    #[cfg_attr(not(target_os = "macos"), allow(unused_variables))]
+    pub fn prompt(
+        wrapper: &TextWrapper,
+        name: Option<&str>,
+        domain: Option<&str>,
+        development_team: Option<&str>,
+        template_pack: Option<&str>,
+    ) -> Result<Self, PromptError> {
+        let app = app::Raw::prompt(wrapper, name, domain, template_pack)
+            .map_err(PromptError::AppFailed)?;
         #[cfg(target_os = "macos")]
-        let apple = apple::config::Raw::prompt(wrapper).map_err(PromptError::AppleFailed)?;
+        let apple = if let Some(development_team) = development_team {
+            apple::config::Raw::from_development_team(wrapper, development_team.to_owned())
+        } else {
+            apple::config::Raw::prompt(wrapper).map_err(PromptError::AppleFailed)?
+        };
         Ok(Self {
             app,
             #[cfg(target_os = "macos")]
             apple: Some(apple),
             android: None,
+            variants: None,
+            build_config: None,
+            template: None,
         })
     }
 
-    pub fn detect() -> Result<Self, DetectError> {
-        let app = app::Raw::detect().map_err(DetectError::AppFailed)?;
+    /// Just enough to render templates with.
+    pub fn sample(app: app::Raw) -> Self {
+        Self {
+            app,
+            #[cfg(target_os = "macos")]
+            apple: Some(apple::config::Raw::sample()),
+            android: None,
+            variants: None,
+            build_config: None,
+            template: None,
+        }
+    }
+
+    #[cfg_attr(not(target_os = "macos"), allow(unused_variables))]
+    pub fn detect(
+        wrapper: &TextWrapper,
+        name: Option<&str>,
+        domain: Option<&str>,
+        development_team: Option<&str>,
+        template_pack: Option<&str>,
+    ) -> Result<Self, DetectError> {
+        let app = app::Raw::detect(name, domain, template_pack).map_err(DetectError::AppFailed)?;
         #[cfg(target_os = "macos")]
-        let apple = apple::config::Raw::detect().map_err(DetectError::AppleFailed)?;
+        let apple = if let Some(development_team) = development_team {
+            apple::config::Raw::from_development_team(wrapper, development_team.to_owned())
+        } else {
+            apple::config::Raw::detect().map_err(DetectError::AppleFailed)?
+        };
         Ok(Self {
             app,
             #[cfg(target_os = "macos")]
             apple: Some(apple),
             android: None,
+            variants: None,
+            build_config: None,
+            template: None,
         })
     }
 
-    pub fn discover_root(cwd: impl AsRef<Path>) -> io::Result<Option<PathBuf>> {
-        let file_name = super::file_name();
-        let mut path = cwd.as_ref().canonicalize()?.join(&file_name);
-        log::info!("looking for config file at {:?}", path);
-        while !path.exists() {
-            if let Some(parent) = path.parent().and_then(Path::parent) {
-                path = parent.join(&file_name);
-                log::info!("looking for config file at {:?}", path);
-            } else {
-                log::info!("no config file was ever found");
-                return Ok(None);
+    /// Returns `true` if this changed anything, in which case the config needs
+    /// to be written back out for the change to stick.
+    #[cfg_attr(not(target_os = "macos"), allow(unused_variables))]
+    pub fn set_development_team(
+        &mut self,
+        wrapper: &TextWrapper,
+        development_team: Option<&str>,
+    ) -> bool {
+        #[cfg(target_os = "macos")]
+        if let Some(development_team) = development_team {
+            match &mut self.apple {
+                Some(raw) if raw.development_team == development_team => (),
+                Some(raw) => {
+                    log::info!(
+                        "changing `{}.development-team` from {:?} to {:?}",
+                        apple::NAME,
+                        raw.development_team,
+                        development_team
+                    );
+                    apple::config::check_development_team(wrapper, development_team);
+                    raw.development_team = development_team.to_owned();
+                    return true;
+                }
+                None => {
+                    self.apple = Some(apple::config::Raw::from_development_team(
+                        wrapper,
+                        development_team.to_owned(),
+                    ));
+                    return true;
+                }
             }
         }
-        log::info!("found config file at {:?}", path);
-        path.pop();
-        Ok(Some(path))
+        false
     }
 
-    pub fn load(cwd: impl AsRef<Path>) -> Result<Option<(PathBuf, Self)>, LoadError> {
-        Self::discover_root(cwd)
-            .map_err(LoadError::DiscoverFailed)?
-            .map(|root_dir| {
-                let path = root_dir.join(super::file_name());
-                let bytes = fs::read(&path).map_err(|cause| LoadError::ReadFailed {
-                    path: path.clone(),
-                    cause,
-                })?;
-                toml::from_slice::<Self>(&bytes)
-                    .map(|raw| (root_dir, raw))
-                    .map_err(|cause| LoadError::ParseFailed {
-                        path: path.clone(),
+    pub fn load(cwd: impl AsRef<Path>) -> Result<Option<(Source, Self)>, LoadError> {
+        Source::discover(cwd)?
+            .map(|source| {
+                let contents =
+                    fs::read_to_string(source.path()).map_err(|cause| LoadError::ReadFailed {
+                        path: source.path().to_owned(),
                         cause,
-                    })
+                    })?;
+                match check::parse(&source, &contents) {
+                    Ok(raw) => Ok((source, raw)),
+                    Err(problems) => Err(LoadError::Invalid { source, problems }),
+                }
             })
             .transpose()
     }
 
-    pub fn write(&self, root_dir: &Path) -> Result<(), WriteError> {
+    pub fn write(&self, root_dir: &Path, writer: Writer) -> Result<(), WriteError> {
         let bytes = toml::to_vec(self).map_err(WriteError::SerializeFailed)?;
         let path = root_dir.join(super::file_name());
         log::info!("writing config to {:?}", path);
-        fs::write(path, bytes).map_err(WriteError::WriteFailed)
+        writer.write(&path, bytes).map_err(WriteError::WriteFailed)
+    }
+
+    /// Writes the keys that can be overridden from the command line back to
+    /// the config at `path`, leaving the rest of it (comments and all) alone.
+    pub fn write_overrides(&self, path: &Path, writer: Writer) -> Result<(), WriteError> {
+        let contents = fs::read_to_string(path).map_err(WriteError::ReadFailed)?;
+        let mut document = contents
+            .parse::<Document>()
+            .map_err(WriteError::ParseFailed)?;
+        let fresh = toml::to_string(self)
+            .map_err(WriteError::SerializeFailed)?
+            .parse::<Document>()
+            .expect("developer error: serialized config couldn't be parsed");
+        #[cfg_attr(not(target_os = "macos"), allow(unused_mut))]
+        let mut overrides = vec![(app::KEY, "template-pack"), (app::KEY, "platforms")];
+        // Elsewhere, the Apple config isn't loaded, so we'd only be removing
+        // what's there.
+        #[cfg(target_os = "macos")]
+        overrides.push((apple::NAME, "development-team"));
+        for (table, key) in overrides {
+            let wanted = fresh.get(table).and_then(|table| table.get(key));
+            match document.get_mut(table).and_then(Item::as_table_like_mut) {
+                Some(existing) => match wanted {
+                    Some(wanted) => match existing.get_mut(key) {
+                        Some(item) => {
+                            // Keeps any comment after the value.
+                            let decor = item.as_value().map(|value| value.decor().clone());
+                            *item = wanted.clone();
+                            if let (Some(decor), Some(value)) = (decor, item.as_value_mut()) {
+                                *value.decor_mut() = decor;
+                            }
+                        }
+                        None => {
+                            existing.insert(key, wanted.clone());
+                        }
+                    },
+                    None => {
+                        existing.remove(key);
+                    }
+                },
+                None => {
+                    if let Some(wanted) = fresh.get(table) {
+                        document.insert(table, wanted.clone());
+                    }
+                }
+            }
+        }
+        log::info!("writing overrides to config at {:?}", path);
+        writer
+            .write(path, document.to_string())
+            .map_err(WriteError::WriteFailed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{config::app::Platform, util::TempDir};
+
+    #[test]
+    fn test_write_overrides_keeps_the_rest() {
+        let contents = r#"# Hand-written, please keep this comment!
+[app]
+name = "fish"
+domain = "example.com" # ours
+template-pack = "bevy"
+platforms = ["apple"] # just the one for now
+
+[android]
+min-sdk-version = 24
+"#;
+        let temp_dir = TempDir::new("write-overrides-test").unwrap();
+        let path = temp_dir.path().join("mobile.toml");
+        fs::write(&path, contents).unwrap();
+        let mut raw = toml::from_str::<Raw>(contents).unwrap();
+        let platforms = [Platform::Android, Platform::Apple];
+        assert!(raw.app.set_platforms(Some(&platforms)));
+        raw.write_overrides(&path, Writer::Disk).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            contents.replace(r#"["apple"]"#, r#"["android", "apple"]"#)
+        );
     }
 }
@@ -0,0 +1,362 @@
+//! Extra flags for rustc, for when a target needs something that cargo-mobile
+//! doesn't pass on its own, like the max page size Android 15 requires. Each
+//! platform's table takes `rustflags` and `link-args`, and so does
+//! `target.<triple>` under it, whose flags come after the platform's.
+//!
+//! They reach rustc through `CARGO_ENCODED_RUSTFLAGS`, and only for the cargo
+//! invocations we make, so builds for the host are left alone. That var wins
+//! over every other source of flags, so what cargo would otherwise have used
+//! is merged in first, and any `RUSTFLAGS` you've set goes last.
+
+use crate::util::cli::Report;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub static VAR: &str = "CARGO_ENCODED_RUSTFLAGS";
+
+static USER_VARS: &[&str] = &[VAR, "RUSTFLAGS"];
+
+// What `CARGO_ENCODED_RUSTFLAGS` separates flags with.
+const SEPARATOR: char = '\u{1f}';
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Raw {
+    pub rustflags: Option<Vec<String>>,
+    pub link_args: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    FlagEmpty { key: String },
+    FlagUnsplit { key: String, flag: String },
+    QuotesUnbalanced { key: String, flag: String },
+    TargetInvalid { table: String, triple: String },
+}
+
+impl Error {
+    pub fn report(&self, msg: &str) -> Report {
+        match self {
+            Self::FlagEmpty { key } => {
+                Report::error(msg, format!("`{}` contains an empty flag", key))
+            }
+            Self::FlagUnsplit { key, flag } => Report::error(
+                msg,
+                format!(
+                    "`{}` contains {:?}, which rustc would get as a single arg; give the option and its value as separate entries, i.e. [\"-C\", \"target-cpu=native\"]",
+                    key, flag
+                ),
+            ),
+            Self::QuotesUnbalanced { key, flag } => Report::error(
+                msg,
+                format!(
+                    "`{}` contains {:?}, which has unbalanced quotes; each entry is passed along as-is, so it doesn't need any quoting",
+                    key, flag
+                ),
+            ),
+            Self::TargetInvalid { table, triple } => Report::error(
+                msg,
+                format!(
+                    "`{}.target` has {:?}, which isn't a target triple for this platform",
+                    table, triple
+                ),
+            ),
+        }
+    }
+}
+
+// Backslash-escaped quotes are fine, since they're probably meant literally.
+fn quotes_balanced(flag: &str) -> bool {
+    let (mut double, mut single, mut escaped) = (0, 0, false);
+    for c in flag.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => double += 1,
+            '\'' => single += 1,
+            _ => (),
+        }
+    }
+    double % 2 == 0 && single % 2 == 0
+}
+
+// i.e. "-C target-cpu=native", which rustc rejects as an unknown codegen
+// option ` target-cpu`.
+fn unsplit(flag: &str) -> bool {
+    let mut chars = flag.chars();
+    chars.next() == Some('-')
+        && chars.next().map(|c| c.is_ascii_alphabetic()) == Some(true)
+        && chars.next().map(char::is_whitespace) == Some(true)
+}
+
+fn check(key: &str, flags: &[String]) -> Result<(), Error> {
+    for flag in flags {
+        if flag.trim().is_empty() {
+            return Err(Error::FlagEmpty {
+                key: key.to_owned(),
+            });
+        }
+        if unsplit(flag) {
+            return Err(Error::FlagUnsplit {
+                key: key.to_owned(),
+                flag: flag.clone(),
+            });
+        }
+        if !quotes_balanced(flag) {
+            return Err(Error::QuotesUnbalanced {
+                key: key.to_owned(),
+                flag: flag.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Link args are passed as `-Clink-arg`s, after the table's own rustflags.
+fn flags_from_raw(table: &str, raw: Raw) -> Result<Vec<String>, Error> {
+    let rustflags = raw.rustflags.unwrap_or_default();
+    check(&format!("{}.rustflags", table), &rustflags)?;
+    let link_args = raw.link_args.unwrap_or_default();
+    check(&format!("{}.link-args", table), &link_args)?;
+    Ok(rustflags
+        .into_iter()
+        .chain(
+            link_args
+                .into_iter()
+                .map(|arg| format!("-Clink-arg={}", arg)),
+        )
+        .collect())
+}
+
+/// Flags that came from somewhere, and a description of where, for logging.
+pub type Source = (String, Vec<String>);
+
+fn user_flags(lookup: impl Fn(&str) -> Option<String>) -> Option<Source> {
+    USER_VARS.iter().find_map(|var| {
+        let value = lookup(var)?;
+        let flags = if *var == VAR {
+            value
+                .split(SEPARATOR)
+                .filter(|flag| !flag.is_empty())
+                .map(str::to_owned)
+                .collect()
+        } else {
+            value.split_whitespace().map(str::to_owned).collect()
+        };
+        Some((format!("`{}`", var), flags))
+    })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RustFlags {
+    table: String,
+    platform: Vec<String>,
+    targets: BTreeMap<String, Vec<String>>,
+}
+
+impl RustFlags {
+    /// `table` is where these came from in the config, and `triple_valid`
+    /// says whether a `target.<triple>` table is for this platform.
+    pub fn from_raw(
+        table: &str,
+        platform: Raw,
+        targets: Option<BTreeMap<String, Raw>>,
+        triple_valid: impl Fn(&str) -> bool,
+    ) -> Result<Self, Error> {
+        let targets = targets
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(triple, raw)| {
+                if !triple_valid(&triple) {
+                    return Err(Error::TargetInvalid {
+                        table: table.to_owned(),
+                        triple,
+                    });
+                }
+                let flags = flags_from_raw(&format!("{}.target.{}", table, triple), raw)?;
+                Ok((triple, flags))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            table: table.to_owned(),
+            platform: flags_from_raw(table, platform)?,
+            targets,
+        })
+    }
+
+    fn sources(&self, triple: &str) -> Vec<Source> {
+        let mut sources = Vec::new();
+        if !self.platform.is_empty() {
+            sources.push((format!("`{}`", self.table), self.platform.clone()));
+        }
+        if let Some(flags) = self.targets.get(triple).filter(|flags| !flags.is_empty()) {
+            sources.push((format!("`{}.target.{}`", self.table, triple), flags.clone()));
+        }
+        sources
+    }
+
+    fn encoded_with(
+        &self,
+        triple: &str,
+        base: Option<Source>,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Option<String> {
+        let ours = self.sources(triple);
+        if ours.is_empty() {
+            return None;
+        }
+        let sources = base
+            .into_iter()
+            .chain(ours)
+            .chain(user_flags(lookup))
+            .collect::<Vec<_>>();
+        log::info!(
+            "rustflags for {}, in the order they're passed: {}",
+            triple,
+            sources
+                .iter()
+                .map(|(source, flags)| format!("{} {:?}", source, flags))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Some(
+            sources
+                .into_iter()
+                .flat_map(|(_, flags)| flags)
+                .collect::<Vec<_>>()
+                .join(&SEPARATOR.to_string()),
+        )
+    }
+
+    /// What to set [`VAR`] to when building for `triple`, or `None` if the
+    /// config doesn't add any flags for it, in which case cargo's left to
+    /// work them out like usual. `base` is what cargo would've used instead,
+    /// and `env` is the config's, which is checked for `RUSTFLAGS` before
+    /// our own environment.
+    pub fn encoded(
+        &self,
+        triple: &str,
+        base: Option<Source>,
+        env: &BTreeMap<String, String>,
+    ) -> Option<String> {
+        self.encoded_with(triple, base, |var| {
+            env.get(var).cloned().or_else(|| std::env::var(var).ok())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn raw(rustflags: &[&str], link_args: &[&str]) -> Raw {
+        let strings = |flags: &[&str]| Some(flags.iter().map(|flag| flag.to_string()).collect());
+        Raw {
+            rustflags: strings(rustflags),
+            link_args: strings(link_args),
+        }
+    }
+
+    fn rustflags() -> RustFlags {
+        let mut targets = BTreeMap::new();
+        targets.insert(
+            "aarch64-linux-android".to_owned(),
+            raw(&["-C", "target-feature=+neon"], &[]),
+        );
+        RustFlags::from_raw(
+            "android",
+            raw(&[], &["-Wl,-z,max-page-size=16384"]),
+            Some(targets),
+            |triple| triple.ends_with("-android"),
+        )
+        .unwrap()
+    }
+
+    #[rstest(
+        flag,
+        expected,
+        case("-Ctarget-cpu=native", true),
+        case(r#"-Clink-arg=-Wl,--version-script="exports.map""#, true),
+        case(r#"--cfg=feature="simd"#, false),
+        case("-Clink-arg=-L'/opt/my libs", false),
+        case(r#"-Clink-arg=\"#, true)
+    )]
+    fn test_quotes_balanced(flag: &str, expected: bool) {
+        assert_eq!(quotes_balanced(flag), expected);
+    }
+
+    #[rstest(
+        rustflags,
+        link_args,
+        ok,
+        case(&["-C", "target-cpu=native"], &["-Wl,-z,max-page-size=16384"], true),
+        case(&["-C target-cpu=native"], &[], false),
+        case(&["--cfg", "feature=\"simd"], &[], false),
+        case(&[], &[" "], false)
+    )]
+    fn test_from_raw(rustflags: &[&str], link_args: &[&str], ok: bool) {
+        let result = RustFlags::from_raw("android", raw(rustflags, link_args), None, |_| true);
+        assert_eq!(result.is_ok(), ok, "{:?}", result);
+    }
+
+    #[test]
+    fn test_target_invalid() {
+        let mut targets = BTreeMap::new();
+        targets.insert("aarch64-apple-ios".to_owned(), Raw::default());
+        assert!(matches!(
+            RustFlags::from_raw("android", Raw::default(), Some(targets), |triple| triple
+                .ends_with("-android")),
+            Err(Error::TargetInvalid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_encoded() {
+        let rustflags = rustflags();
+        let base = || {
+            Some((
+                "defaults".to_owned(),
+                vec!["-Clink-arg=-landroid".to_owned()],
+            ))
+        };
+        assert_eq!(
+            rustflags
+                .encoded_with("aarch64-linux-android", base(), |var| {
+                    Some("-C  debuginfo=1 ".to_owned()).filter(|_| var == "RUSTFLAGS")
+                })
+                .unwrap()
+                .split(SEPARATOR)
+                .collect::<Vec<_>>(),
+            vec![
+                "-Clink-arg=-landroid",
+                "-Clink-arg=-Wl,-z,max-page-size=16384",
+                "-C",
+                "target-feature=+neon",
+                "-C",
+                "debuginfo=1",
+            ]
+        );
+        // Encoded flags can contain spaces, so they're taken over `RUSTFLAGS`.
+        assert_eq!(
+            rustflags
+                .encoded_with("x86_64-linux-android", None, |var| match var {
+                    "CARGO_ENCODED_RUSTFLAGS" => Some("-L\u{1f}/opt/my libs".to_owned()),
+                    _ => Some("-Cdebuginfo=1".to_owned()),
+                })
+                .unwrap()
+                .split(SEPARATOR)
+                .collect::<Vec<_>>(),
+            vec![
+                "-Clink-arg=-Wl,-z,max-page-size=16384",
+                "-L",
+                "/opt/my libs"
+            ]
+        );
+        // Nothing configured means nothing changes.
+        assert_eq!(
+            RustFlags::default().encoded_with("aarch64-linux-android", base(), |_| None),
+            None
+        );
+    }
+}
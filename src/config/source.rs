@@ -0,0 +1,237 @@
+use super::raw::LoadError;
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The key our table goes under in `[package.metadata]` or
+/// `[workspace.metadata]`.
+pub static CARGO_METADATA_KEY: &str = "cargo-mobile";
+
+/// Where the config lives: either in its own file, or under
+/// `[package.metadata.cargo-mobile]` or `[workspace.metadata.cargo-mobile]` in
+/// a `Cargo.toml`, for projects that would rather not have another file at the
+/// top of their repo.
+#[derive(Clone, Debug)]
+pub enum Source {
+    File(PathBuf),
+    CargoMetadata {
+        path: PathBuf,
+        /// Either `package` or `workspace`.
+        table: &'static str,
+    },
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{:?}", path),
+            Self::CargoMetadata { path, .. } => {
+                write!(f, "`[{}]` of {:?}", self.prefix().join("."), path)
+            }
+        }
+    }
+}
+
+impl Source {
+    /// Looks for the config in `cwd` and then each of its parents, stopping at
+    /// the first dir that has either a config file or a `Cargo.toml` with our
+    /// table in it. The config file wins if a dir has both.
+    ///
+    /// Discovery is anchored to the package `cwd` is in: past that package's
+    /// dir, only a config for the whole workspace counts, since any other
+    /// package's config is for a different app.
+    pub fn discover(cwd: impl AsRef<Path>) -> Result<Option<Self>, LoadError> {
+        let mut dir = cwd
+            .as_ref()
+            .canonicalize()
+            .map_err(LoadError::DiscoverFailed)?;
+        let mut package_dir = None;
+        loop {
+            log::info!("looking for config in {:?}", dir);
+            let file = dir.join(super::file_name());
+            let cargo_toml = dir.join("Cargo.toml");
+            let manifest = if cargo_toml.is_file() {
+                Manifest::read(&cargo_toml)?
+            } else {
+                Manifest::default()
+            };
+            let mut table = manifest.table;
+            let mut file_counts = true;
+            if let Some(package_dir) = &package_dir {
+                if manifest.package {
+                    log::info!(
+                        "ignoring any config for the package in {:?}, since we're in the package in {:?}",
+                        dir,
+                        package_dir
+                    );
+                    table = table.filter(|table| *table == "workspace");
+                    file_counts = false;
+                }
+            }
+            if file_counts && file.is_file() {
+                if let Some(table) = table {
+                    log::warn!(
+                        "both {:?} and `[{}.metadata.{}]` in {:?} exist; only {:?} is used",
+                        file,
+                        table,
+                        CARGO_METADATA_KEY,
+                        cargo_toml,
+                        file
+                    );
+                }
+                log::info!("found config file at {:?}", file);
+                return Ok(Some(Self::File(file)));
+            }
+            if let Some(table) = table {
+                let source = Self::CargoMetadata {
+                    path: cargo_toml,
+                    table,
+                };
+                log::info!("found config in {}", source);
+                return Ok(Some(source));
+            }
+            if manifest.package && package_dir.is_none() {
+                package_dir = Some(dir.clone());
+            }
+            if !dir.pop() {
+                log::info!("no config was ever found");
+                return Ok(None);
+            }
+        }
+    }
+
+    /// The file the config was read from.
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::File(path) => path,
+            Self::CargoMetadata { path, .. } => path,
+        }
+    }
+
+    /// The dir the config's relative paths are relative to.
+    pub fn root_dir(&self) -> &Path {
+        self.path()
+            .parent()
+            .expect("developer error: config path has no parent")
+    }
+
+    /// The keys the config is nested under in its file.
+    pub fn prefix(&self) -> Vec<&str> {
+        match self {
+            Self::File(_) => Vec::new(),
+            Self::CargoMetadata { table, .. } => vec![table, "metadata", CARGO_METADATA_KEY],
+        }
+    }
+}
+
+// What discovery needs to know about a `Cargo.toml`.
+#[derive(Debug, Default)]
+struct Manifest {
+    package: bool,
+    /// Which of `package` and `workspace` has our table, if either does.
+    table: Option<&'static str>,
+}
+
+impl Manifest {
+    fn read(path: &Path) -> Result<Self, LoadError> {
+        let contents = fs::read_to_string(path).map_err(|cause| LoadError::ReadFailed {
+            path: path.to_owned(),
+            cause,
+        })?;
+        let cargo_toml = toml::from_str::<toml::Value>(&contents).map_err(|cause| {
+            LoadError::CargoTomlInvalid {
+                path: path.to_owned(),
+                cause,
+            }
+        })?;
+        Ok(Self {
+            package: cargo_toml.get("package").is_some(),
+            table: ["package", "workspace"].iter().copied().find(|table| {
+                cargo_toml
+                    .get(table)
+                    .and_then(|table| table.get("metadata"))
+                    .and_then(|metadata| metadata.get(CARGO_METADATA_KEY))
+                    .is_some()
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+    use rstest::rstest;
+
+    #[rstest(
+        cwd,
+        expected,
+        case("consumer/src", Some("consumer/Cargo.toml")),
+        case("tool", Some("tool/FILE")),
+        case("tool/nested", Some("tool/nested/Cargo.toml")),
+        case("libs/shared", Some("Cargo.toml")),
+        case(".", Some("Cargo.toml"))
+    )]
+    fn test_discover(cwd: &str, expected: Option<&str>) {
+        let temp_dir = TempDir::new(&format!("discover-test-{}", cwd.replace('/', "-"))).unwrap();
+        let root = temp_dir.path();
+        for (path, contents) in &[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"*\"]\n[workspace.metadata.cargo-mobile.app]\nname = \"shared\"\n",
+            ),
+            (
+                "consumer/Cargo.toml",
+                "[package]\nname = \"consumer\"\n[package.metadata.cargo-mobile.app]\nname = \"consumer\"\n",
+            ),
+            ("consumer/src/lib.rs", ""),
+            ("tool/Cargo.toml", "[package]\nname = \"tool\"\n"),
+            ("tool/FILE", "[app]\nname = \"tool\"\n"),
+            (
+                "tool/nested/Cargo.toml",
+                "[package]\nname = \"nested\"\n[package.metadata.cargo-mobile.app]\nname = \"nested\"\n",
+            ),
+            ("libs/shared/Cargo.toml", "[package]\nname = \"shared\"\n"),
+        ] {
+            let path = root.join(path.replace("FILE", &crate::config::file_name()));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let source = Source::discover(root.join(cwd)).map(|source| {
+            source.map(|source| {
+                source
+                    .path()
+                    .strip_prefix(root.canonicalize().unwrap())
+                    .unwrap()
+                    .to_owned()
+            })
+        });
+        let expected =
+            expected.map(|path| PathBuf::from(path.replace("FILE", &crate::config::file_name())));
+        assert_eq!(source.unwrap(), expected);
+    }
+
+    #[test]
+    fn test_discover_skips_other_packages() {
+        let temp_dir = TempDir::new("discover-skip-test").unwrap();
+        let root = temp_dir.path();
+        // The root package is an app of its own, so its config isn't the
+        // member's.
+        for (path, contents) in &[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"consumer\"\n[workspace]\n[package.metadata.cargo-mobile.app]\nname = \"consumer\"\n",
+            ),
+            ("FILE", "[app]\nname = \"consumer\"\n"),
+            ("tool/Cargo.toml", "[package]\nname = \"tool\"\n"),
+        ] {
+            let path = root.join(path.replace("FILE", &crate::config::file_name()));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let source = Source::discover(root.join("tool"));
+        assert!(matches!(source, Ok(None)));
+    }
+}
@@ -0,0 +1,114 @@
+//! Values for placeholders in template packs beyond the built-in ones, which
+//! are defined under `[template.vars]` and can be overridden with `--var`.
+
+use crate::{templating::RESERVED_VARS, util::cli::Report};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub static KEY: &str = "template";
+
+#[derive(Debug)]
+pub enum Error {
+    VarsReserved { vars: Vec<String> },
+}
+
+impl Error {
+    pub fn report(&self, msg: &str) -> Report {
+        match self {
+            Self::VarsReserved { vars } => Report::error(
+                msg,
+                format!(
+                    "`{}.vars` can't set {}, since built-in template data and helpers use those names; the reserved names are {}",
+                    KEY,
+                    vars.iter()
+                        .map(|var| format!("`{}`", var))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    RESERVED_VARS.join(", ")
+                ),
+            ),
+        }
+    }
+}
+
+/// Strings, booleans, and numbers are all templates have any use for.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Var {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Raw {
+    vars: Option<BTreeMap<String, Var>>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Template {
+    vars: BTreeMap<String, Var>,
+}
+
+impl Template {
+    pub fn from_raw(raw: Option<Raw>) -> Result<Self, Error> {
+        let vars = raw.and_then(|raw| raw.vars).unwrap_or_default();
+        let reserved = vars
+            .keys()
+            .filter(|var| RESERVED_VARS.contains(&var.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        if reserved.is_empty() {
+            Ok(Self { vars })
+        } else {
+            Err(Error::VarsReserved { vars: reserved })
+        }
+    }
+
+    pub fn vars(&self) -> &BTreeMap<String, Var> {
+        &self.vars
+    }
+
+    /// The vars from the config, with the ones from `--var` on top.
+    pub fn merged_vars(&self, cli_vars: &[(String, String)]) -> BTreeMap<String, Var> {
+        let mut vars = self.vars.clone();
+        for (key, value) in cli_vars {
+            if let Some(old) = vars.insert(key.clone(), Var::String(value.clone())) {
+                log::info!(
+                    "`--var {}={}` overrides {:?} from `{}.vars`",
+                    key,
+                    value,
+                    old,
+                    KEY
+                );
+            }
+        }
+        vars
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cli_vars_override_config_and_reserved_vars_are_rejected() {
+        let raw = toml::from_str::<Raw>(
+            "vars = { company = \"Acme\", api-url = \"https://example.com\", telemetry = true, retries = 3 }",
+        )
+        .unwrap();
+        let template = Template::from_raw(Some(raw)).unwrap();
+        let vars = template.merged_vars(&[("company".to_owned(), "Acme Corp".to_owned())]);
+        assert_eq!(vars["company"], Var::String("Acme Corp".to_owned()));
+        assert_eq!(vars["telemetry"], Var::Bool(true));
+        assert_eq!(vars["retries"], Var::Integer(3));
+        let raw = toml::from_str::<Raw>("vars = { app = \"nope\", author = \"me\" }").unwrap();
+        assert!(matches!(
+            Template::from_raw(Some(raw)),
+            Err(Error::VarsReserved { vars }) if vars == vec!["app".to_owned(), "author".to_owned()]
+        ));
+    }
+}
@@ -0,0 +1,283 @@
+//! Variants of the app, like `dev`, `beta`, and `prod`, which are defined under
+//! `[variants.<name>]` and picked with `--variant`. A variant overlays the
+//! rest of the config with its own identifier, name, icon, env, and features.
+
+use super::build_config::{self, BuildConfig};
+use crate::util::{
+    self,
+    cli::{Report, Reportable},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub static KEY: &str = "variants";
+
+#[derive(Debug)]
+pub enum Error {
+    IdentifierSuffixInvalid {
+        variant: String,
+        suffix: String,
+    },
+    IdentifierSuffixTaken {
+        variant: String,
+        other: String,
+        suffix: String,
+    },
+    BuildConfigInvalid(build_config::Error),
+}
+
+impl Error {
+    pub fn report(&self, msg: &str) -> Report {
+        match self {
+            Self::IdentifierSuffixInvalid { variant, suffix } => Report::error(
+                msg,
+                format!(
+                    "`{}.{}.identifier-suffix` {:?} isn't valid; it has to be empty, or made of `.`-separated parts that each start with a letter and contain only letters and digits (the default is `.` followed by the variant's name)",
+                    KEY, variant, suffix
+                ),
+            ),
+            Self::IdentifierSuffixTaken {
+                variant,
+                other,
+                suffix,
+            } => Report::error(
+                msg,
+                format!(
+                    "`{}.{}` and `{}.{}` both have the identifier suffix {:?}, so they couldn't be installed side by side",
+                    KEY, other, KEY, variant, suffix
+                ),
+            ),
+            Self::BuildConfigInvalid(err) => err.report(msg),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Unknown {
+    name: String,
+    defined: Vec<String>,
+}
+
+impl Reportable for Unknown {
+    fn report(&self) -> Report {
+        let msg = format!("There's no variant named {:?}", self.name);
+        if self.defined.is_empty() {
+            Report::action_request(
+                msg,
+                format!(
+                    "No variants are defined; add them to your config as `[{}.<name>]` tables.",
+                    KEY
+                ),
+            )
+        } else {
+            let mut details = format!("The defined variants are: {}", self.defined.join(", "));
+            if let Some(suggestion) =
+                util::suggest(&self.name, self.defined.iter().map(String::as_str))
+            {
+                details.push_str(&format!(" (did you mean {:?}?)", suggestion));
+            }
+            Report::action_request(msg, details)
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        "config.variant.unknown"
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Raw {
+    identifier_suffix: Option<String>,
+    display_name: Option<String>,
+    icon: Option<String>,
+    env: Option<BTreeMap<String, String>>,
+    features: Option<Vec<String>>,
+    build_config: Option<build_config::Raw>,
+}
+
+// Android package names and Apple bundle IDs don't agree on much, so this is
+// what's left of both.
+fn suffix_valid(suffix: &str) -> bool {
+    suffix.is_empty()
+        || suffix.strip_prefix('.').map_or(false, |suffix| {
+            suffix.split('.').all(|part| {
+                part.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && part.chars().all(|c| c.is_ascii_alphanumeric())
+            })
+        })
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Variant {
+    name: String,
+    identifier_suffix: String,
+    display_name: Option<String>,
+    icon: Option<String>,
+    env: BTreeMap<String, String>,
+    features: Vec<String>,
+    #[serde(skip)]
+    build_config: BuildConfig,
+}
+
+impl Variant {
+    fn from_raw(name: String, raw: Raw) -> Result<Self, Error> {
+        let identifier_suffix = raw.identifier_suffix.unwrap_or_else(|| {
+            let suffix = format!(".{}", name);
+            log::info!(
+                "`{}.{}.identifier-suffix` not set; defaulting to {:?}",
+                KEY,
+                name,
+                suffix
+            );
+            suffix
+        });
+        if !suffix_valid(&identifier_suffix) {
+            return Err(Error::IdentifierSuffixInvalid {
+                variant: name,
+                suffix: identifier_suffix,
+            });
+        }
+        let build_config = BuildConfig::from_raw(
+            &format!("{}.{}.{}", KEY, name, build_config::KEY),
+            raw.build_config,
+        )
+        .map_err(Error::BuildConfigInvalid)?;
+        Ok(Self {
+            name,
+            identifier_suffix,
+            display_name: raw.display_name,
+            icon: raw.icon,
+            env: raw.env.unwrap_or_default(),
+            features: raw.features.unwrap_or_default(),
+            build_config,
+        })
+    }
+
+    /// Every variant has its own identifier, so that they can all be
+    /// installed on the same device.
+    pub fn from_raw_map(
+        raw: Option<BTreeMap<String, Raw>>,
+    ) -> Result<BTreeMap<String, Self>, Error> {
+        let mut variants = BTreeMap::<String, Self>::new();
+        for (name, raw) in raw.unwrap_or_default() {
+            let variant = Self::from_raw(name.clone(), raw)?;
+            if let Some(other) = variants
+                .values()
+                .find(|other| other.identifier_suffix == variant.identifier_suffix)
+            {
+                return Err(Error::IdentifierSuffixTaken {
+                    variant: name,
+                    other: other.name.clone(),
+                    suffix: variant.identifier_suffix,
+                });
+            }
+            variants.insert(name, variant);
+        }
+        Ok(variants)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Appended to the Android application ID and the Apple bundle ID.
+    pub fn identifier_suffix(&self) -> &str {
+        &self.identifier_suffix
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    /// Relative to the app root.
+    pub fn icon(&self) -> Option<&str> {
+        self.icon.as_deref()
+    }
+
+    /// Set on top of the platform's `env`.
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    /// Added to whatever features the platform would be built with otherwise.
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    /// Overlaid on the app's with [`BuildConfig::with_variant`].
+    pub fn build_config(&self) -> &BuildConfig {
+        &self.build_config
+    }
+}
+
+/// Looks up the variant passed with `--variant`, if there was one.
+pub fn select<'a>(
+    variants: &'a BTreeMap<String, Variant>,
+    name: Option<&str>,
+) -> Result<Option<&'a Variant>, Unknown> {
+    name.map(|name| {
+        variants.get(name).ok_or_else(|| Unknown {
+            name: name.to_owned(),
+            defined: variants.keys().cloned().collect(),
+        })
+    })
+    .transpose()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        suffix,
+        expected,
+        case("", true),
+        case(".dev", true),
+        case(".beta.internal2", true),
+        case("dev", false),
+        case(".2beta", false),
+        case(".dev_build", false),
+        case(".dev-build", false),
+        case(".", false)
+    )]
+    fn test_suffix_valid(suffix: &str, expected: bool) {
+        assert_eq!(suffix_valid(suffix), expected);
+    }
+
+    #[test]
+    fn test_variants_need_distinct_identifiers() {
+        let raw = |suffix: Option<&str>| Raw {
+            identifier_suffix: suffix.map(str::to_owned),
+            ..Default::default()
+        };
+        let variants = Variant::from_raw_map(Some(
+            vec![
+                ("dev".to_owned(), raw(None)),
+                ("prod".to_owned(), raw(Some(""))),
+            ]
+            .into_iter()
+            .collect(),
+        ))
+        .unwrap();
+        assert_eq!(variants["dev"].identifier_suffix(), ".dev");
+        assert!(select(&variants, Some("beta")).is_err());
+        assert_eq!(
+            select(&variants, Some("prod")).unwrap().map(Variant::name),
+            Some("prod")
+        );
+        assert!(matches!(
+            Variant::from_raw_map(Some(
+                vec![
+                    ("beta".to_owned(), raw(Some(".dev"))),
+                    ("dev".to_owned(), raw(None)),
+                ]
+                .into_iter()
+                .collect(),
+            )),
+            Err(Error::IdentifierSuffixTaken { .. })
+        ));
+    }
+}
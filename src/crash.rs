@@ -0,0 +1,177 @@
+//! Noticing when an app dies right after `run` launches it. Launch commands
+//! return as soon as the app's been started, so a crash on startup would
+//! otherwise look just like success, apart from the device showing nothing.
+//!
+//! Each platform knows how to tell whether its app is still running and what
+//! it logged on the way down; this just decides how long to keep asking.
+
+use crate::{
+    ui,
+    util::cli::{Report, Reportable},
+};
+use std::{thread, time::Duration};
+
+/// How long the app has to stay up to count as launched.
+pub const WINDOW: Duration = Duration::from_secs(5);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Crash excerpts are cut down to this many lines, which is plenty to see
+/// what went wrong without burying the error under the log.
+pub const EXCERPT_LINES: usize = 40;
+
+#[derive(Debug)]
+pub struct Crashed {
+    device: String,
+    excerpt: Option<String>,
+    hint: Option<String>,
+}
+
+impl Crashed {
+    pub fn new(device: impl Into<String>) -> Self {
+        Self {
+            device: device.into(),
+            excerpt: None,
+            hint: None,
+        }
+    }
+
+    /// What the app logged before it died, if anything was captured.
+    pub fn with_excerpt(mut self, excerpt: Option<String>) -> Self {
+        self.excerpt = excerpt;
+        self
+    }
+
+    /// Where to look for more, like a crash report that was saved somewhere.
+    pub fn with_hint(mut self, hint: Option<String>) -> Self {
+        self.hint = hint;
+        self
+    }
+}
+
+impl Reportable for Crashed {
+    fn report(&self) -> Report {
+        let mut details = match &self.excerpt {
+            Some(excerpt) => format!("This was logged on the way down:\n\n{}\n\n", excerpt),
+            None => String::new(),
+        };
+        if let Some(hint) = &self.hint {
+            details.push_str(hint);
+            details.push(' ');
+        }
+        details.push_str(
+            "If the app is meant to exit on its own, pass `--no-crash-check` to skip this check.",
+        );
+        Report::error(
+            format!(
+                "The app stopped within {} seconds of launching on {}",
+                WINDOW.as_secs(),
+                self.device
+            ),
+            details,
+        )
+    }
+
+    fn code(&self) -> &'static str {
+        "crash.crashed"
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Outcome {
+    Running,
+    Died,
+    Unknown,
+}
+
+// An app that's never seen running either died before the first check or
+// never got going at all, which are both worth knowing about.
+fn judge(samples: impl IntoIterator<Item = Option<bool>>) -> Outcome {
+    let mut seen = false;
+    for sample in samples {
+        match sample {
+            Some(true) => seen = true,
+            Some(false) if seen => return Outcome::Died,
+            Some(false) => (),
+            None => return Outcome::Unknown,
+        }
+    }
+    if seen {
+        Outcome::Running
+    } else {
+        Outcome::Died
+    }
+}
+
+/// Asks `running` whether the app is running every so often until
+/// [`WINDOW`] is up, and returns whether it died in the meantime. `running`
+/// returns `None` if it can't tell, in which case the check is given up on,
+/// since that's no reason to fail a launch that probably worked.
+pub fn died(mut running: impl FnMut() -> Option<bool>) -> bool {
+    ui::status(format!(
+        "Making sure the app is still running after {} seconds...",
+        WINDOW.as_secs()
+    ));
+    let polls = WINDOW.as_millis() / POLL_INTERVAL.as_millis();
+    let samples = (0..=polls).map(|i| {
+        if i > 0 {
+            thread::sleep(POLL_INTERVAL);
+        }
+        running()
+    });
+    match judge(samples) {
+        Outcome::Running => false,
+        Outcome::Died => true,
+        Outcome::Unknown => {
+            log::warn!(
+                "couldn't tell whether the app is still running, so skipping the crash check"
+            );
+            false
+        }
+    }
+}
+
+/// The last [`EXCERPT_LINES`] lines of `log` that aren't blank, or `None` if
+/// there aren't any.
+pub fn tail(log: &str) -> Option<String> {
+    let lines = log
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines[lines.len().saturating_sub(EXCERPT_LINES)..].join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        samples,
+        expected,
+        case(&[Some(true), Some(true), Some(true)], Outcome::Running),
+        case(&[Some(false), Some(true), Some(true)], Outcome::Running),
+        case(&[Some(true), Some(false), Some(true)], Outcome::Died),
+        case(&[Some(false), Some(false)], Outcome::Died),
+        case(&[Some(true), None, Some(false)], Outcome::Unknown)
+    )]
+    fn test_judge(samples: &[Option<bool>], expected: Outcome) {
+        assert_eq!(judge(samples.iter().copied()), expected);
+    }
+
+    #[test]
+    fn test_tail() {
+        assert_eq!(tail("\n  \n"), None);
+        let log = (0..EXCERPT_LINES + 5)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        let tail = tail(&log).unwrap();
+        assert_eq!(tail.lines().count(), EXCERPT_LINES);
+        assert!(tail.starts_with("line 5\n"));
+        assert!(tail.ends_with(&format!("line {}", EXCERPT_LINES + 4)));
+    }
+}
@@ -1,5 +1,22 @@
-use crate::util::cli::{Report, Reportable};
-use std::{fmt::Debug, io};
+use crate::{
+    config::app::Platform,
+    generated,
+    opts::NonInteractive,
+    ui,
+    util::{
+        self,
+        cli::{Report, Reportable},
+        prompt,
+    },
+};
+use serde::Serialize;
+use std::{
+    fmt::{self, Debug, Display},
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug)]
 pub enum PromptErrorCause<T: Reportable> {
@@ -27,6 +44,14 @@ impl<T: Reportable> Reportable for PromptError<T> {
             ),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match &self.cause {
+            PromptErrorCause::DetectionFailed(err) => err.code(),
+            PromptErrorCause::PromptFailed(_) => "device.prompt.prompt-failed",
+            PromptErrorCause::NoneDetected => "device.prompt.none-detected",
+        }
+    }
 }
 
 impl<T: Reportable> PromptError<T> {
@@ -47,6 +72,465 @@ impl<T: Reportable> PromptError<T> {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    Physical,
+    Virtual,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Physical => "physical",
+            Self::Virtual => "virtual",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum State {
+    /// Ready to run on.
+    Online,
+    /// Connected, but the device hasn't agreed to trust this computer yet.
+    Unauthorized,
+    /// A simulator or emulator that isn't running.
+    Shutdown,
+    /// Connected, but not responding.
+    Offline,
+}
+
+impl State {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Online => "online",
+            Self::Unauthorized => "unauthorized",
+            Self::Shutdown => "shutdown",
+            Self::Offline => "offline",
+        }
+    }
+}
+
+/// How a physical device is connected, which is one of the ways of telling
+/// apart devices that are otherwise the same.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    Usb,
+    Wifi,
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usb => write!(f, "USB"),
+            Self::Wifi => write!(f, "Wi-Fi"),
+        }
+    }
+}
+
+impl Transport {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Usb => "usb",
+            Self::Wifi => "wifi",
+        }
+    }
+}
+
+/// The end of a serial number or UDID, which is enough to tell apart devices
+/// with the same name in a list, i.e. `…3F2A`.
+pub fn short_id(id: &str) -> String {
+    let start = id.char_indices().rev().nth(3).map_or(0, |(index, _)| index);
+    if start == 0 {
+        id.to_owned()
+    } else {
+        format!("…{}", &id[start..])
+    }
+}
+
+/// A device as shown by the `devices` commands. The field names are part of
+/// the `--json` output, so they shouldn't change. `id` is what `--device`
+/// takes.
+#[derive(Debug, Serialize)]
+pub struct Listing {
+    pub name: String,
+    pub id: String,
+    pub platform: &'static str,
+    pub os_version: Option<String>,
+    pub arch: String,
+    pub kind: Kind,
+    /// `None` for emulators and simulators.
+    pub transport: Option<Transport>,
+    pub state: State,
+}
+
+/// Prints the name of each device that's ready to run on, one per line, for
+/// shell completion scripts to offer as `--device` values.
+pub fn print_names<'a>(listings: impl IntoIterator<Item = &'a Listing>) {
+    for listing in listings {
+        if listing.state == State::Online {
+            println!("{}", listing.name);
+        }
+    }
+}
+
+/// Prints devices as a table, or as a JSON array. An empty list isn't an
+/// error, since having nothing connected is a perfectly normal state.
+pub fn print_listings(listings: &[Listing], json: bool) {
+    if json {
+        println!("{}", serde_json::json!(listings));
+        return;
+    }
+    if listings.is_empty() {
+        println!("No devices were found.");
+        return;
+    }
+    let rows = listings
+        .iter()
+        .map(|listing| {
+            vec![
+                listing.name.clone(),
+                listing.id.clone(),
+                listing.platform.to_owned(),
+                listing.os_version.as_deref().unwrap_or("-").to_owned(),
+                listing.arch.clone(),
+                listing.kind.as_str().to_owned(),
+                listing
+                    .transport
+                    .map_or("-", |transport| transport.as_str())
+                    .to_owned(),
+                listing.state.as_str().to_owned(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    util::print_table(
+        &[
+            "NAME",
+            "ID",
+            "PLATFORM",
+            "VERSION",
+            "ARCH",
+            "KIND",
+            "TRANSPORT",
+            "STATE",
+        ],
+        &rows,
+    );
+}
+
+/// What a `--device` or `--simulator` query picked out.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Found<T> {
+    One(T),
+    Several(Vec<T>),
+    Nothing,
+}
+
+/// Finds the devices `query` could mean: the one with that ID, or else the
+/// ones with that name, or else the ones with IDs starting with it. IDs are
+/// unique, so a serial number or UDID (or enough of its start) always picks
+/// out one device, however many share its name. Case doesn't matter.
+pub fn find<T>(
+    devices: impl IntoIterator<Item = T>,
+    query: &str,
+    id: impl Fn(&T) -> &str,
+    name: impl Fn(&T) -> &str,
+) -> Found<T> {
+    let devices = devices.into_iter().collect::<Vec<_>>();
+    let prefix = query.to_lowercase();
+    let by_id = |device: &T| id(device).eq_ignore_ascii_case(query);
+    let by_name = |device: &T| name(device).eq_ignore_ascii_case(query);
+    let by_prefix = |device: &T| id(device).to_lowercase().starts_with(&prefix);
+    let matches: &dyn Fn(&T) -> bool = if devices.iter().any(by_id) {
+        &by_id
+    } else if devices.iter().any(by_name) {
+        &by_name
+    } else {
+        &by_prefix
+    };
+    let mut found = devices
+        .into_iter()
+        .filter(|device| matches(device))
+        .collect::<Vec<_>>();
+    match found.len() {
+        0 => Found::Nothing,
+        1 => Found::One(found.remove(0)),
+        _ => Found::Several(found),
+    }
+}
+
+#[derive(Debug)]
+pub enum ChooseError {
+    PromptFailed { query: String, cause: io::Error },
+    Ambiguous { query: String, matches: Vec<String> },
+}
+
+impl Reportable for ChooseError {
+    fn report(&self) -> Report {
+        match self {
+            Self::PromptFailed { query, cause } => Report::error(
+                format!("Failed to prompt for which device {:?} means", query),
+                cause,
+            ),
+            Self::Ambiguous { query, matches } => Report::action_request(
+                format!("More than one device matches {:?}", query),
+                format!(
+                    "Pass the ID of the one you want instead, or enough of its start to tell them apart:\n{}",
+                    matches.join("\n")
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::PromptFailed { .. } => "device.choose.prompt-failed",
+            Self::Ambiguous { .. } => "device.choose.ambiguous",
+        }
+    }
+}
+
+/// Asks which of the devices `query` matched to use, since picking one for
+/// the user would just as likely pick the wrong one. When nobody's around to
+/// ask, it's an error listing them instead.
+pub fn choose<T: Display>(
+    mut matches: Vec<T>,
+    query: &str,
+    non_interactive: NonInteractive,
+    id: impl Fn(&T) -> &str,
+) -> Result<T, ChooseError> {
+    if non_interactive.yes() {
+        return Err(ChooseError::Ambiguous {
+            query: query.to_owned(),
+            matches: matches
+                .iter()
+                .map(|device| format!("{} [{}]", device, id(device)))
+                .collect(),
+        });
+    }
+    let index = prompt::list(
+        format!("More than one device matches {:?}", query),
+        matches.iter(),
+        "device",
+        None,
+        "Device",
+    )
+    .map_err(|cause| ChooseError::PromptFailed {
+        query: query.to_owned(),
+        cause,
+    })?;
+    Ok(matches.swap_remove(index))
+}
+
+/// A device that's connected, but can't be used until something's done about
+/// it on the device itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Blocked {
+    pub name: String,
+    /// What's wrong, i.e. "is locked".
+    pub problem: &'static str,
+    /// What to do about it.
+    pub guidance: &'static str,
+}
+
+impl Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.problem)
+    }
+}
+
+#[derive(Debug)]
+pub enum WaitError<T: Reportable> {
+    CheckFailed(T),
+    NotReady {
+        blocked: Vec<Blocked>,
+        waited: Option<Duration>,
+    },
+}
+
+impl<T: Reportable> Reportable for WaitError<T> {
+    fn report(&self) -> Report {
+        match self {
+            Self::CheckFailed(err) => err.report(),
+            Self::NotReady { blocked, waited } => {
+                let problems = blocked
+                    .iter()
+                    .map(Blocked::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", and ");
+                Report::action_request(
+                    match waited {
+                        Some(waited) => format!(
+                            "Gave up after {} seconds, since {}",
+                            waited.as_secs(),
+                            problems
+                        ),
+                        None => format!("Can't use devices, since {}", problems),
+                    },
+                    blocked
+                        .iter()
+                        .map(|blocked| format!("{}: {}", blocked.name, blocked.guidance))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                )
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::CheckFailed(err) => err.code(),
+            Self::NotReady { .. } => "device.wait.not-ready",
+        }
+    }
+}
+
+static POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+fn wait_with_interval<T: Reportable>(
+    non_interactive: NonInteractive,
+    timeout: Duration,
+    interval: Duration,
+    mut check: impl FnMut() -> Result<Vec<Blocked>, T>,
+) -> Result<(), WaitError<T>> {
+    let mut blocked = check().map_err(WaitError::CheckFailed)?;
+    if blocked.is_empty() {
+        return Ok(());
+    }
+    // There's nobody around to do anything about it.
+    if non_interactive.yes() {
+        return Err(WaitError::NotReady {
+            blocked,
+            waited: None,
+        });
+    }
+    let mut told = Vec::new();
+    let mut tell = |blocked: &[Blocked]| {
+        for blocked in blocked {
+            if !told.contains(blocked) {
+                ui::status(format!("{}. {}", blocked, blocked.guidance));
+                told.push(blocked.clone());
+            }
+        }
+    };
+    tell(&blocked);
+    ui::status(format!(
+        "Waiting for up to {} seconds...",
+        timeout.as_secs()
+    ));
+    let start = Instant::now();
+    loop {
+        thread::sleep(interval);
+        blocked = check().map_err(WaitError::CheckFailed)?;
+        if blocked.is_empty() {
+            ui::status(format!("Ready to go!"));
+            return Ok(());
+        }
+        // Fixing one thing can reveal another, i.e. unlocking a device that
+        // then asks whether to trust this computer.
+        tell(&blocked);
+        if start.elapsed() >= timeout {
+            return Err(WaitError::NotReady {
+                blocked,
+                waited: Some(timeout),
+            });
+        }
+    }
+}
+
+/// Waits until `check` stops finding blocked devices, telling the user what
+/// to do on each one in the meantime. `check` is polled every couple of
+/// seconds, for up to `timeout`. In non-interactive mode, this fails right
+/// away instead, since there's nobody to do anything about it.
+pub fn wait_until_ready<T: Reportable>(
+    non_interactive: NonInteractive,
+    timeout: Duration,
+    check: impl FnMut() -> Result<Vec<Blocked>, T>,
+) -> Result<(), WaitError<T>> {
+    wait_with_interval(non_interactive, timeout, POLL_INTERVAL, check)
+}
+
+/// What `--device` takes to prompt for a device even when there's a
+/// previously selected one to default to.
+pub static ASK: &str = "ask";
+
+#[derive(Debug)]
+pub struct ForgetError {
+    path: PathBuf,
+    cause: io::Error,
+}
+
+impl Reportable for ForgetError {
+    fn report(&self) -> Report {
+        Report::error(
+            format!(
+                "Failed to forget previously selected device in {:?}",
+                self.path
+            ),
+            &self.cause,
+        )
+    }
+
+    fn code(&self) -> &'static str {
+        "device.forget-failed"
+    }
+}
+
+/// The device a platform's `run` last succeeded on, by serial number or UDID,
+/// which `run` defaults to as long as it's still connected. This lives in
+/// `gen/` next to the generated projects rather than in them, so regenerating
+/// them doesn't forget it.
+#[derive(Debug)]
+pub struct LastDevice {
+    path: PathBuf,
+}
+
+impl LastDevice {
+    pub fn new(root_dir: impl AsRef<Path>, platform: Platform) -> Self {
+        Self {
+            path: generated::gen_dir_in(root_dir.as_ref())
+                .join(format!(".last-device-{}", platform.name())),
+        }
+    }
+
+    pub fn id(&self) -> Option<String> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .map(|id| id.trim().to_owned())
+            .filter(|id| !id.is_empty())
+    }
+
+    /// Failing to remember isn't worth failing the run over.
+    pub fn record(&self, id: &str) {
+        let result = self
+            .path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&self.path, id));
+        if let Err(err) = result {
+            log::warn!(
+                "failed to record selected device in {:?}: {}",
+                self.path,
+                err
+            );
+        }
+    }
+
+    /// Returns whether there was anything to forget.
+    pub fn forget(&self) -> Result<bool, ForgetError> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(cause) => Err(ForgetError {
+                path: self.path.clone(),
+                cause,
+            }),
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! define_device_prompt {
     ($func:path, $e:ty, $name:ident) => {
@@ -70,11 +554,11 @@ macro_rules! define_device_prompt {
                     0
                 };
                 let device = device_list.into_iter().nth(index).unwrap();
-                println!(
+                $crate::ui::status(format!(
                     "Detected connected device: {} with target {:?}",
                     device,
                     device.target().triple,
-                );
+                ));
                 Ok(device)
             } else {
                 Err($crate::device::PromptError::none_detected(stringify!(
@@ -84,3 +568,134 @@ macro_rules! define_device_prompt {
         }
     };
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[derive(Debug)]
+    struct Never;
+
+    impl Reportable for Never {
+        fn report(&self) -> Report {
+            unreachable!()
+        }
+
+        fn code(&self) -> &'static str {
+            unreachable!()
+        }
+    }
+
+    fn locked() -> Blocked {
+        Blocked {
+            name: "Phone".to_owned(),
+            problem: "is locked",
+            guidance: "Unlock it.",
+        }
+    }
+
+    #[test]
+    fn test_last_device() {
+        let temp_dir = TempDir::new("last-device-test").unwrap();
+        let android = LastDevice::new(temp_dir.path(), Platform::Android);
+        let apple = LastDevice::new(temp_dir.path(), Platform::Apple);
+        assert_eq!(android.id(), None);
+        android.record("emulator-5554");
+        apple.record("00008030-001A");
+        assert_eq!(android.id().as_deref(), Some("emulator-5554"));
+        assert!(android.forget().unwrap());
+        assert!(!android.forget().unwrap());
+        assert_eq!(android.id(), None);
+        assert_eq!(apple.id().as_deref(), Some("00008030-001A"));
+    }
+
+    #[test]
+    fn test_wait_until_unblocked() {
+        let mut polls = 0;
+        let result = wait_with_interval(
+            NonInteractive::No,
+            Duration::from_secs(60),
+            Duration::from_millis(1),
+            || {
+                polls += 1;
+                Ok::<_, Never>(if polls < 3 { vec![locked()] } else { vec![] })
+            },
+        );
+        assert!(result.is_ok());
+        assert_eq!(polls, 3);
+    }
+
+    #[test]
+    fn test_wait_gives_up() {
+        let result = wait_with_interval(
+            NonInteractive::No,
+            Duration::from_millis(5),
+            Duration::from_millis(1),
+            || Ok::<_, Never>(vec![locked()]),
+        );
+        assert!(matches!(
+            result,
+            Err(WaitError::NotReady {
+                waited: Some(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_wait_fails_right_away_when_non_interactive() {
+        let mut polls = 0;
+        let result = wait_with_interval(
+            NonInteractive::Yes,
+            Duration::from_secs(60),
+            Duration::from_millis(1),
+            || {
+                polls += 1;
+                Ok::<_, Never>(vec![locked()])
+            },
+        );
+        assert!(matches!(
+            result,
+            Err(WaitError::NotReady { waited: None, .. })
+        ));
+        assert_eq!(polls, 1);
+    }
+
+    const PHONES: [(&str, &str); 3] = [
+        ("R58M1234ABC", "Pixel 7"),
+        ("R58M9876XYZ", "Pixel 7"),
+        ("emulator-5554", "Pixel 7 Pro"),
+    ];
+
+    fn find_phone(query: &str) -> Found<&'static str> {
+        match find(PHONES.iter(), query, |phone| phone.0, |phone| phone.1) {
+            Found::One(phone) => Found::One(phone.0),
+            Found::Several(phones) => Found::Several(phones.iter().map(|phone| phone.0).collect()),
+            Found::Nothing => Found::Nothing,
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find_phone("r58m1234abc"), Found::One("R58M1234ABC"));
+        assert_eq!(find_phone("Pixel 7 Pro"), Found::One("emulator-5554"));
+        assert_eq!(
+            find_phone("pixel 7"),
+            Found::Several(vec!["R58M1234ABC", "R58M9876XYZ"])
+        );
+        assert_eq!(find_phone("R58M9"), Found::One("R58M9876XYZ"));
+        assert_eq!(
+            find_phone("R58M"),
+            Found::Several(vec!["R58M1234ABC", "R58M9876XYZ"])
+        );
+        assert_eq!(find_phone("Galaxy"), Found::Nothing);
+    }
+
+    #[test]
+    fn test_short_id() {
+        assert_eq!(short_id("00008110-001A2C3E0E38801E"), "…801E");
+        assert_eq!(short_id("ab12"), "ab12");
+        assert_eq!(short_id(""), "");
+    }
+}
@@ -0,0 +1,520 @@
+use crate::util::{
+    self,
+    cli::{Report, Reportable, TextWrapper},
+    proxy,
+};
+use colored::Colorize as _;
+use serde::Serialize;
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+// More failures than this and `init` suggests `setup`.
+const SETUP_THRESHOLD: usize = 2;
+
+#[derive(Debug)]
+pub struct UnknownSection(String);
+
+impl Display for UnknownSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} isn't a doctor section; the sections are {}",
+            self.0,
+            SectionName::ALL
+                .iter()
+                .map(|name| format!("`{}`", name.as_str()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+/// Which group of checks to run, as selected by `--section`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SectionName {
+    General,
+    Android,
+    Apple,
+}
+
+impl FromStr for SectionName {
+    type Err = UnknownSection;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|name| name.as_str() == s)
+            .ok_or_else(|| UnknownSection(s.to_owned()))
+    }
+}
+
+impl SectionName {
+    pub const ALL: &'static [Self] = &[Self::General, Self::Android, Self::Apple];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::General => "general",
+            Self::Android => "android",
+            Self::Apple => "apple",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+    NotApplicable,
+}
+
+impl Status {
+    fn color(&self) -> colored::Color {
+        match self {
+            Self::Pass => colored::Color::BrightGreen,
+            Self::Warn => colored::Color::BrightYellow,
+            Self::Fail => colored::Color::BrightRed,
+            Self::NotApplicable => colored::Color::BrightBlack,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Warn => "warn",
+            Self::Fail => "fail",
+            Self::NotApplicable => "n/a",
+        }
+    }
+}
+
+/// Where a fix falls in `cargo mobile setup`, which goes through fixes in this
+/// order, since later ones tend to need earlier ones (i.e. `sdkmanager` won't
+/// install anything until the licenses are accepted, and an AVD needs a
+/// system image).
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Stage {
+    Rust,
+    Android,
+    Xcode,
+    Devices,
+}
+
+/// How `cargo mobile setup` can fix a check: by running commands, or if there
+/// aren't any, by pointing out the hint for the user to follow.
+#[derive(Clone, Debug)]
+pub struct Fix {
+    stage: Stage,
+    commands: Vec<Vec<String>>,
+}
+
+impl Fix {
+    pub fn manual(stage: Stage) -> Self {
+        Self {
+            stage,
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn run(stage: Stage, command: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        Self::manual(stage).then(command)
+    }
+
+    /// Adds a command to run after the previous ones succeed.
+    pub fn then(mut self, command: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        self.commands.push(
+            command
+                .into_iter()
+                .map(|arg| arg.as_ref().to_owned())
+                .collect(),
+        );
+        self
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn commands(&self) -> &[Vec<String>] {
+        &self.commands
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Item {
+    status: Status,
+    msg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    docs: Option<String>,
+    #[serde(skip)]
+    fix: Option<Fix>,
+}
+
+impl Item {
+    fn new(status: Status, msg: impl Display) -> Self {
+        Self {
+            status,
+            msg: msg.to_string(),
+            hint: None,
+            docs: None,
+            fix: None,
+        }
+    }
+
+    pub fn pass(msg: impl Display) -> Self {
+        Self::new(Status::Pass, msg)
+    }
+
+    pub fn warn(msg: impl Display) -> Self {
+        Self::new(Status::Warn, msg)
+    }
+
+    pub fn fail(msg: impl Display) -> Self {
+        Self::new(Status::Fail, msg)
+    }
+
+    pub fn not_applicable(msg: impl Display) -> Self {
+        Self::new(Status::NotApplicable, msg)
+    }
+
+    /// Hints should contain the exact command needed to fix the problem,
+    /// whenever one exists.
+    pub fn with_hint(mut self, hint: impl Display) -> Self {
+        self.hint = Some(hint.to_string());
+        self
+    }
+
+    /// For when the fix is more involved than a hint can cover.
+    pub fn with_docs(mut self, url: impl Display) -> Self {
+        self.docs = Some(url.to_string());
+        self
+    }
+
+    /// For checks that `cargo mobile setup` can help with.
+    pub fn with_fix(mut self, fix: Fix) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn hint(&self) -> Option<&str> {
+        self.hint.as_deref()
+    }
+
+    pub fn docs(&self) -> Option<&str> {
+        self.docs.as_deref()
+    }
+
+    pub fn fix(&self) -> Option<&Fix> {
+        self.fix.as_ref()
+    }
+
+    fn print(&self, wrapper: &TextWrapper) {
+        static INDENT: &str = "    ";
+        let label = format!("[{}]", self.status.as_str());
+        let head = if colored::control::SHOULD_COLORIZE.should_colorize() {
+            format!("{} {}", label.color(self.status.color()).bold(), self.msg)
+        } else {
+            format!("{} {}", label, self.msg)
+        };
+        println!(
+            "{}",
+            wrapper
+                .clone()
+                .initial_indent("  ")
+                .subsequent_indent(INDENT)
+                .fill(&head)
+        );
+        if let Some(hint) = &self.hint {
+            println!(
+                "{}",
+                wrapper
+                    .clone()
+                    .initial_indent(INDENT)
+                    .subsequent_indent(INDENT)
+                    .fill(&format!("hint: {}", hint))
+            );
+        }
+        if let Some(docs) = &self.docs {
+            println!("{}docs: {}", INDENT, docs);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Section {
+    name: SectionName,
+    title: &'static str,
+    items: Vec<Item>,
+}
+
+impl Section {
+    pub fn new(name: SectionName, title: &'static str) -> Self {
+        Self {
+            name,
+            title,
+            items: Vec::new(),
+        }
+    }
+
+    /// A section for a platform that can't be checked on this host.
+    pub fn not_applicable(name: SectionName, title: &'static str, reason: impl Display) -> Self {
+        let mut section = Self::new(name, title);
+        section.push(Item::not_applicable(reason));
+        section
+    }
+
+    pub fn push(&mut self, item: Item) {
+        self.items.push(item);
+    }
+
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    fn failures(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.status == Status::Fail)
+            .count()
+    }
+
+    fn print(&self, wrapper: &TextWrapper) {
+        println!("{}", self.title.bold());
+        for item in &self.items {
+            item.print(wrapper);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Failed {
+    failures: usize,
+}
+
+impl Reportable for Failed {
+    fn report(&self) -> Report {
+        Report::error(
+            "Your environment isn't ready yet",
+            format!(
+                "{} check{} failed; see the hints above for how to fix {}.",
+                self.failures,
+                if self.failures == 1 { "" } else { "s" },
+                if self.failures == 1 { "it" } else { "them" },
+            ),
+        )
+    }
+
+    fn code(&self) -> &'static str {
+        "doctor.failed"
+    }
+}
+
+fn check_install(section: &mut Section) {
+    match util::installed_commit_msg() {
+        Ok(Some(msg)) => section.push(Item::pass(format!(
+            "cargo-mobile {} is installed from {}",
+            env!("CARGO_PKG_VERSION"),
+            msg.lines().next().unwrap_or_default().trim()
+        ))),
+        Ok(None) => section.push(
+            Item::warn(format!(
+                "cargo-mobile {} is installed, but wasn't installed with its install script, so its commit is unknown",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .with_hint("`cargo mobile update` reinstalls it the usual way"),
+        ),
+        Err(err) => section.push(Item::warn(err)),
+    }
+}
+
+fn check_rust(section: &mut Section) {
+    match util::command_present("rustup") {
+        Ok(true) => section.push(Item::pass("`rustup` is installed")),
+        Ok(false) => section.push(
+            Item::fail("`rustup` wasn't found on your `PATH`, so Rust targets can't be installed")
+                .with_hint("Install `rustup`")
+                .with_docs("https://rustup.rs")
+                .with_fix(Fix::manual(Stage::Rust)),
+        ),
+        Err(err) => section.push(Item::fail(format!("Failed to look for `rustup`: {}", err))),
+    }
+    // This is the same check that building for Apple platforms does.
+    match util::RustVersion::check() {
+        Ok(version) if version.valid() => {
+            section.push(Item::pass(format!("rustc {} is installed", version)))
+        }
+        Ok(version) => section.push(
+            Item::fail(format!(
+                "rustc {} has a bug that breaks building for iOS",
+                version
+            ))
+            .with_hint("`rustup update stable && rustup default stable`")
+            .with_fix(
+                Fix::run(Stage::Rust, &["rustup", "update", "stable"])
+                    .then(&["rustup", "default", "stable"]),
+            ),
+        ),
+        Err(err) => section.push(
+            Item::fail(err)
+                .with_hint("Install Rust with `rustup`")
+                .with_docs("https://rustup.rs"),
+        ),
+    }
+    match util::host_target_triple() {
+        Ok(triple) => section.push(Item::pass(format!("Host target is `{}`", triple))),
+        Err(util::HostTargetTripleError::CommandFailed(err)) => section.push(Item::fail(format!(
+            "Failed to detect host target triple: {}",
+            err
+        ))),
+    }
+    match util::installed_targets() {
+        Ok(installed) => section.push(Item::pass(format!(
+            "Installed Rust targets: {}",
+            util::list_display(&installed[..])
+        ))),
+        Err(err) => section.push(Item::warn(format!(
+            "Failed to list installed Rust targets: {}",
+            err
+        ))),
+    }
+}
+
+// Nothing to pass or fail, but it's the first thing to know when downloads
+// fail behind a corporate network.
+fn check_proxy(section: &mut Section) {
+    match proxy::Proxy::load() {
+        Ok(proxy) => {
+            let settings = proxy.describe();
+            if settings.is_empty() {
+                section.push(Item::pass("No proxy is set"));
+            }
+            for setting in settings {
+                section.push(Item::pass(setting));
+            }
+        }
+        Err(err) => section.push(Item::fail(err)),
+    }
+}
+
+/// Checks what every platform needs: cargo-mobile itself, Rust, and the
+/// network settings downloads go through.
+pub fn check_general() -> Section {
+    let mut section = Section::new(SectionName::General, "General");
+    check_install(&mut section);
+    check_rust(&mut section);
+    check_proxy(&mut section);
+    section
+}
+
+#[cfg(target_os = "macos")]
+fn check_apple() -> Section {
+    crate::apple::doctor::check()
+}
+
+#[cfg(not(target_os = "macos"))]
+fn check_apple() -> Section {
+    Section::not_applicable(
+        SectionName::Apple,
+        "Apple",
+        "Building for Apple platforms requires macOS",
+    )
+}
+
+/// Runs every check in the sections named in `names`, or in all sections if
+/// `names` is empty. Checks never stop at the first failure, since knowing
+/// everything that's wrong at once saves a lot of back and forth.
+pub fn check(names: &[SectionName]) -> Vec<Section> {
+    SectionName::ALL
+        .iter()
+        .filter(|name| names.is_empty() || names.contains(name))
+        .map(|name| match name {
+            SectionName::General => check_general(),
+            SectionName::Android => crate::android::doctor::check(),
+            SectionName::Apple => check_apple(),
+        })
+        .collect()
+}
+
+/// How many checks failed across all of `sections`.
+pub fn failures(sections: &[Section]) -> usize {
+    sections.iter().map(Section::failures).sum()
+}
+
+/// Runs the checks in `names`, and suggests `cargo mobile setup` if more than
+/// a couple of them fail, since fixing them as they come up one at a time is
+/// a slog. The results themselves aren't printed.
+pub fn suggest_setup(wrapper: &TextWrapper, names: &[SectionName]) {
+    let failures = failures(&check(names));
+    if failures > SETUP_THRESHOLD {
+        Report::action_request(
+            format!("{} environment checks failed", failures),
+            "Run `cargo mobile setup` to walk through fixing them all at once; otherwise, you'll run into them one at a time.",
+        )
+        .print(wrapper);
+    }
+}
+
+/// Prints the results of all checks, either for humans or as JSON. Any failing
+/// check makes this return an error, so CI can rely on the exit code alone;
+/// warnings don't.
+pub fn exec(wrapper: &TextWrapper, sections: &[Section], json: bool) -> Result<(), Failed> {
+    if json {
+        println!("{}", serde_json::json!({ "sections": sections }));
+    } else {
+        for (i, section) in sections.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            section.print(wrapper);
+        }
+    }
+    let failures = failures(sections);
+    if failures == 0 {
+        Ok(())
+    } else {
+        Err(Failed { failures })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_only_failures_fail() {
+        let wrapper = TextWrapper::with_splitter(80, textwrap::NoHyphenation);
+        let mut section = Section::new(SectionName::General, "General");
+        section.push(Item::pass("fine"));
+        section.push(Item::warn("meh").with_docs("https://example.com"));
+        section.push(Item::not_applicable("n/a"));
+        assert!(exec(&wrapper, std::slice::from_ref(&section), true).is_ok());
+        section.push(Item::fail("broken").with_hint("`fix it`"));
+        assert_eq!(
+            exec(&wrapper, &[section], true).map_err(|err| err.failures),
+            Err(1)
+        );
+    }
+
+    #[test]
+    fn test_section_names_round_trip() {
+        for name in SectionName::ALL {
+            assert_eq!(name.as_str().parse::<SectionName>().unwrap(), *name);
+        }
+        assert!("ios".parse::<SectionName>().is_err());
+    }
+}
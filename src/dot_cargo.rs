@@ -1,9 +1,20 @@
+//! The app's `.cargo/config.toml`, which we only ever add to. Whatever's
+//! already there is kept as-is (comments and all), since it might have been
+//! written by hand, and it's what a plain `cargo build` goes by.
+
 use crate::{
     config::app::App,
+    guard::Guard,
+    plan::Writer,
     util::cli::{Report, Reportable},
 };
-use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, fs, io, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+};
+use toml_edit::{Array, Decor, Document, Item, Key, Table, TableLike, Value};
 
 #[derive(Debug)]
 pub enum LoadError {
@@ -22,7 +33,7 @@ pub enum LoadError {
     },
     DeserializeFailed {
         path: PathBuf,
-        cause: toml::de::Error,
+        cause: toml_edit::TomlError,
     },
 }
 
@@ -50,11 +61,19 @@ impl Reportable for LoadError {
             ),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DirCreationFailed { .. } => "dot-cargo.load.dir-creation-failed",
+            Self::MigrateFailed { .. } => "dot-cargo.load.migrate-failed",
+            Self::ReadFailed { .. } => "dot-cargo.load.read-failed",
+            Self::DeserializeFailed { .. } => "dot-cargo.load.deserialize-failed",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum WriteError {
-    SerializeFailed(toml::ser::Error),
     DirCreationFailed { path: PathBuf, cause: io::Error },
     WriteFailed { path: PathBuf, cause: io::Error },
 }
@@ -62,7 +81,6 @@ pub enum WriteError {
 impl Reportable for WriteError {
     fn report(&self) -> Report {
         match self {
-            Self::SerializeFailed(err) => Report::error("Failed to serialize cargo config", err),
             Self::DirCreationFailed { path, cause } => Report::error(
                 format!("Failed to create \".cargo\" directory at {:?}", path),
                 cause,
@@ -72,22 +90,16 @@ impl Reportable for WriteError {
             }
         }
     }
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct DotCargoBuild {
-    target: String,
-}
 
-impl DotCargoBuild {
-    pub fn new(target: impl Into<String>) -> Self {
-        Self {
-            target: target.into(),
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DirCreationFailed { .. } => "dot-cargo.write.dir-creation-failed",
+            Self::WriteFailed { .. } => "dot-cargo.write.write-failed",
         }
     }
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default)]
 pub struct DotCargoTarget {
     pub ar: Option<String>,
     pub linker: Option<String>,
@@ -98,63 +110,465 @@ impl DotCargoTarget {
     pub fn is_empty(&self) -> bool {
         self.ar.is_none() && self.linker.is_none() && self.rustflags.is_empty()
     }
+
+    fn entries(&self) -> Vec<(&'static str, Value)> {
+        let mut entries = Vec::new();
+        if let Some(ar) = &self.ar {
+            entries.push(("ar", ar.as_str().into()));
+        }
+        if let Some(linker) = &self.linker {
+            entries.push(("linker", linker.as_str().into()));
+        }
+        if !self.rustflags.is_empty() {
+            let rustflags = self.rustflags.iter().map(String::as_str);
+            entries.push(("rustflags", rustflags.collect::<Array>().into()));
+        }
+        entries
+    }
+}
+
+// Compares what values are, rather than how they're written.
+fn same(wanted: &Value, existing: &Value) -> bool {
+    match (wanted, existing) {
+        (Value::String(wanted), Value::String(existing)) => wanted.value() == existing.value(),
+        (Value::Array(wanted), Value::Array(existing)) => {
+            wanted.len() == existing.len()
+                && wanted
+                    .iter()
+                    .zip(existing.iter())
+                    .all(|(wanted, existing)| same(wanted, existing))
+        }
+        _ => false,
+    }
+}
+
+// A value as it'd be written, without the whitespace or comments around it.
+fn display_value(value: &Value) -> String {
+    let mut value = value.clone();
+    *value.decor_mut() = Decor::default();
+    value.to_string()
+}
+
+/// A key that's already set to something other than what we'd set it to,
+/// which is left alone.
+#[derive(Debug)]
+pub struct Conflict {
+    pub key: String,
+    pub existing: String,
+    pub wanted: String,
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is {}, rather than {}",
+            self.key, self.existing, self.wanted
+        )
+    }
+}
+
+fn lookup<'a>(table: &'a dyn TableLike, path: &[String]) -> Option<&'a Item> {
+    let (first, rest) = path.split_first()?;
+    let item = table.get(first)?;
+    if rest.is_empty() {
+        Some(item)
+    } else {
+        lookup(item.as_table_like()?, rest)
+    }
+}
+
+fn display_key(path: &[String]) -> String {
+    path.iter()
+        .map(|part| Key::new(part.as_str()).to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// The table at `path`, which is created if it doesn't exist yet. A table
+// that only exists because of a dotted key (or that's inline) can't be added
+// to without rewriting it, so `None` is returned for those.
+fn table_mut<'a>(table: &'a mut Table, path: &[String], prefix: &str) -> Option<&'a mut Table> {
+    let (first, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Some(table),
+    };
+    let item = table.entry(first).or_insert_with(|| {
+        let mut table = Table::new();
+        // Only the innermost table gets a header.
+        table.set_implicit(true);
+        table.decor_mut().set_prefix(prefix);
+        Item::Table(table)
+    });
+    let table = item.as_table_mut().filter(|table| !table.is_dotted())?;
+    if rest.is_empty() {
+        table.set_implicit(false);
+    }
+    table_mut(table, rest, prefix)
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// Adds the keys in `additions` that aren't set yet to `document`, without
+/// touching anything that's already there.
+fn merge(
+    document: &mut Document,
+    additions: &[(Vec<String>, Vec<(&'static str, Value)>)],
+) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    for (table, entries) in additions {
+        let mut missing = Vec::new();
+        for (key, value) in entries {
+            let mut path = table.clone();
+            path.push((*key).to_owned());
+            match lookup(document.as_table(), &path) {
+                Some(existing) if existing.as_value().map_or(false, |e| same(value, e)) => (),
+                Some(existing) => conflicts.push(Conflict {
+                    key: display_key(&path),
+                    existing: existing
+                        .as_value()
+                        .map_or_else(|| "a table".to_owned(), display_value),
+                    wanted: display_value(value),
+                }),
+                None => missing.push((*key, value.clone())),
+            }
+        }
+        if missing.is_empty() {
+            continue;
+        }
+        // New tables are separated by a blank line from whatever's before.
+        let prefix = if document.to_string().trim().is_empty() {
+            ""
+        } else {
+            "\n"
+        };
+        match table_mut(document.as_table_mut(), table, prefix) {
+            Some(table) => {
+                for (key, value) in missing {
+                    table.insert(key, Item::Value(value));
+                }
+            }
+            None => log::warn!(
+                "not adding to `{}` in cargo config, since it isn't defined by a `[{}]` header",
+                display_key(table),
+                display_key(table)
+            ),
+        }
+    }
+    conflicts
+}
+
+#[derive(Debug, Default)]
 pub struct DotCargo {
-    build: Option<DotCargoBuild>,
+    contents: String,
+    document: Document,
+    build_target: Option<String>,
     target: BTreeMap<String, DotCargoTarget>,
 }
 
 impl DotCargo {
-    fn create_dir_and_get_path(app: &App) -> Result<PathBuf, (PathBuf, io::Error)> {
+    fn create_dir_and_get_path(app: &App, writer: Writer) -> Result<PathBuf, (PathBuf, io::Error)> {
         let dir = app.prefix_path(".cargo");
-        fs::create_dir_all(&dir)
+        writer
+            .create_dir_all(&dir)
             .map(|()| dir.join("config.toml"))
             .map_err(|cause| (dir, cause))
     }
 
-    pub fn load(app: &App) -> Result<Self, LoadError> {
-        let path = Self::create_dir_and_get_path(app)
+    pub fn load(app: &App, writer: Writer) -> Result<Self, LoadError> {
+        let mut path = Self::create_dir_and_get_path(app, writer)
             .map_err(|(path, cause)| LoadError::DirCreationFailed { path, cause })?;
         let old_style = path
             .parent()
             .expect("developer error: cargo config path had no parent")
             .join("config");
         if old_style.is_file() {
-            // Migrate from old-style cargo config
-            std::fs::rename(&old_style, &path).map_err(|cause| LoadError::MigrateFailed {
-                from: old_style,
-                to: path.clone(),
-                cause,
-            })?;
+            if writer.dry_run() {
+                // Migrating is only a rename, so reading the old one is
+                // equivalent.
+                path = old_style;
+            } else {
+                // Migrate from old-style cargo config
+                std::fs::rename(&old_style, &path).map_err(|cause| LoadError::MigrateFailed {
+                    from: old_style,
+                    to: path.clone(),
+                    cause,
+                })?;
+            }
         }
         if path.is_file() {
-            let bytes = fs::read(&path).map_err(|cause| LoadError::ReadFailed {
+            let contents = fs::read_to_string(&path).map_err(|cause| LoadError::ReadFailed {
                 path: path.clone(),
                 cause,
             })?;
-            toml::from_slice(&bytes).map_err(|cause| LoadError::DeserializeFailed { path, cause })
+            let document = contents
+                .parse()
+                .map_err(|cause| LoadError::DeserializeFailed { path, cause })?;
+            Ok(Self {
+                contents,
+                document,
+                ..Default::default()
+            })
         } else {
             Ok(Self::default())
         }
     }
 
     pub fn set_default_target(&mut self, target: impl Into<String>) {
-        self.build = Some(DotCargoBuild::new(target));
+        self.build_target = Some(target.into());
     }
 
     pub fn insert_target(&mut self, name: impl Into<String>, target: DotCargoTarget) {
         if !target.is_empty() {
-            // merging could be nice, but is also very painful...
             self.target.insert(name.into(), target);
         }
     }
 
-    pub fn write(self, app: &App) -> Result<(), WriteError> {
-        let path = Self::create_dir_and_get_path(app)
+    /// Adds whatever isn't already in the config, and returns the keys that
+    /// were left alone because they're set to something else. If there's a
+    /// `guard`, it gets the final say on replacing an existing config.
+    pub fn write(
+        mut self,
+        app: &App,
+        guard: Option<&Guard>,
+        writer: Writer,
+    ) -> Result<Vec<Conflict>, WriteError> {
+        let mut additions = Vec::new();
+        if let Some(target) = &self.build_target {
+            additions.push((
+                vec!["build".to_owned()],
+                vec![("target", target.as_str().into())],
+            ));
+        }
+        for (name, target) in &self.target {
+            additions.push((vec!["target".to_owned(), name.clone()], target.entries()));
+        }
+        let conflicts = merge(&mut self.document, &additions);
+        let merged = self.document.to_string();
+        let path = Self::create_dir_and_get_path(app, writer)
             .map_err(|(path, cause)| WriteError::DirCreationFailed { path, cause })?;
-        let ser = toml::to_string_pretty(&self).map_err(WriteError::SerializeFailed)?;
-        fs::write(&path, ser).map_err(|cause| WriteError::WriteFailed { path, cause })
+        if merged != self.contents || !path.is_file() {
+            match guard {
+                Some(guard) => guard.write(writer, &path, merged),
+                None => writer.write(&path, merged),
+            }
+            .map_err(|cause| WriteError::WriteFailed { path, cause })?;
+        }
+        Ok(conflicts)
+    }
+}
+
+/// What the cargo config that applies in a directory says about a target.
+#[derive(Debug, Default)]
+pub struct ConfiguredTarget {
+    /// The linker, and the config file it's set in.
+    pub linker: Option<(String, PathBuf)>,
+    /// The rustflags, and the config file they're set in.
+    pub rustflags: Option<(Vec<String>, PathBuf)>,
+}
+
+// Like cargo, a string is split on whitespace.
+fn rustflags(value: &Value) -> Option<Vec<String>> {
+    match value {
+        Value::String(flags) => Some(
+            flags
+                .value()
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect(),
+        ),
+        Value::Array(flags) => flags
+            .iter()
+            .map(|flag| flag.as_str().map(str::to_owned))
+            .collect(),
+        _ => None,
+    }
+}
+
+// Cargo reads every `.cargo/config.toml` (or old-style `.cargo/config`) from
+// `dir` up to the root, and then the one in `CARGO_HOME`; closer ones win.
+fn config_paths(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = dir
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .collect::<Vec<_>>();
+    if let Ok(cargo_home) = home::cargo_home() {
+        if !dirs.contains(&cargo_home) {
+            dirs.push(cargo_home);
+        }
+    }
+    dirs.into_iter()
+        .filter_map(|dir| {
+            let path = dir.join("config.toml");
+            if path.is_file() {
+                Some(path)
+            } else {
+                Some(dir.join("config")).filter(|path| path.is_file())
+            }
+        })
+        .collect()
+}
+
+/// What cargo would use for `triple` when building in `dir`, going by its
+/// config files. Files that can't be read are skipped, since cargo will
+/// complain about them itself.
+pub fn configured_target(dir: &Path, triple: &str) -> ConfiguredTarget {
+    let mut configured = ConfiguredTarget::default();
+    for path in config_paths(dir) {
+        let document = match fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| contents.parse::<Document>().map_err(|err| err.to_string()))
+        {
+            Ok(document) => document,
+            Err(err) => {
+                log::warn!("failed to read cargo config at {:?}: {}", path, err);
+                continue;
+            }
+        };
+        let target = ["target".to_owned(), triple.to_owned()];
+        if let Some(target) = lookup(document.as_table(), &target).and_then(Item::as_table_like) {
+            if configured.linker.is_none() {
+                configured.linker = target
+                    .get("linker")
+                    .and_then(Item::as_str)
+                    .map(|linker| (linker.to_owned(), path.clone()));
+            }
+            if configured.rustflags.is_none() {
+                configured.rustflags = target
+                    .get("rustflags")
+                    .and_then(Item::as_value)
+                    .and_then(rustflags)
+                    .map(|flags| (flags, path.clone()));
+            }
+        }
+    }
+    configured
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn additions() -> Vec<(Vec<String>, Vec<(&'static str, Value)>)> {
+        vec![
+            (
+                vec!["build".to_owned()],
+                vec![("target", "x86_64-unknown-linux-gnu".into())],
+            ),
+            (
+                vec!["target".to_owned(), "aarch64-linux-android".to_owned()],
+                DotCargoTarget {
+                    ar: Some("/ndk/bin/aarch64-linux-android-ar".to_owned()),
+                    linker: Some("/ndk/bin/aarch64-linux-android21-clang".to_owned()),
+                    rustflags: vec!["-Clink-arg=-landroid".to_owned()],
+                }
+                .entries(),
+            ),
+        ]
+    }
+
+    fn merged(contents: &str) -> (String, Vec<Conflict>) {
+        let mut document = contents.parse().unwrap();
+        let conflicts = merge(&mut document, &additions());
+        (document.to_string(), conflicts)
+    }
+    #[test]
+    fn test_merge_into_empty() {
+        let (merged, conflicts) = merged("");
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            r#"[build]
+target = "x86_64-unknown-linux-gnu"
+
+[target.aarch64-linux-android]
+ar = "/ndk/bin/aarch64-linux-android-ar"
+linker = "/ndk/bin/aarch64-linux-android21-clang"
+rustflags = ["-Clink-arg=-landroid"]
+"#
+        );
+        // Merging again doesn't change anything.
+        assert_eq!(self::merged(&merged).0, merged);
+    }
+
+    #[test]
+    fn test_merge_keeps_existing() {
+        let contents = r#"# Hand-written, please keep this comment!
+[build]
+target = "aarch64-apple-darwin"
+
+[target."aarch64-linux-android"]
+linker = "/somewhere/else/clang" # custom
+
+[alias]
+b = "build"
+"#;
+        let (merged, conflicts) = merged(contents);
+        assert_eq!(
+            merged,
+            r#"# Hand-written, please keep this comment!
+[build]
+target = "aarch64-apple-darwin"
+
+[target."aarch64-linux-android"]
+linker = "/somewhere/else/clang" # custom
+ar = "/ndk/bin/aarch64-linux-android-ar"
+rustflags = ["-Clink-arg=-landroid"]
+
+[alias]
+b = "build"
+"#
+        );
+        assert_eq!(
+            conflicts
+                .iter()
+                .map(|conflict| conflict.key.as_str())
+                .collect::<Vec<_>>(),
+            vec!["build.target", "target.aarch64-linux-android.linker"]
+        );
+    }
+
+    #[test]
+    fn test_merge_skips_tables_without_headers() {
+        let contents = "target.aarch64-linux-android.runner = \"adb-run\"\n";
+        let (merged, _) = merged(contents);
+        assert_eq!(
+            merged,
+            r#"target.aarch64-linux-android.runner = "adb-run"
+
+[build]
+target = "x86_64-unknown-linux-gnu"
+"#
+        );
+    }
+
+    #[rstest(
+        value,
+        expected,
+        case(
+            Value::from(" -Ctarget-cpu=native  -Clink-arg=-s"),
+            Some(vec!["-Ctarget-cpu=native", "-Clink-arg=-s"])
+        ),
+        case(
+            Value::from(["-L", "/opt/my libs"].iter().copied().collect::<Array>()),
+            Some(vec!["-L", "/opt/my libs"])
+        ),
+        case(Value::from([1i64].iter().copied().collect::<Array>()), None),
+        case(Value::from(true), None)
+    )]
+    fn test_rustflags(value: Value, expected: Option<Vec<&str>>) {
+        assert_eq!(
+            rustflags(&value),
+            expected.map(|flags| flags.into_iter().map(str::to_owned).collect())
+        );
+    }
+
+    #[test]
+    fn test_conflict_display() {
+        let contents = "[build]\ntarget = 'C:\\ndk' # custom\n";
+        let (_, conflicts) = merged(contents);
+        assert_eq!(
+            conflicts[0].to_string(),
+            r#"`build.target` is 'C:\ndk', rather than "x86_64-unknown-linux-gnu""#
+        );
     }
 }
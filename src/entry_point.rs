@@ -0,0 +1,160 @@
+//! Checking that the built lib exports what the platform code calls to start
+//! the app. Without this, a missing `#[mobile_entry_point]` only shows up as
+//! `System.loadLibrary` failing at launch on Android, or as an undefined symbol
+//! when Xcode links, neither of which says much about what went wrong.
+//!
+//! The symbols expected by default are the ones the templates' platform code
+//! uses; `android.entry-symbols` and `apple.entry-symbols` override them, and
+//! an empty list turns the check off.
+
+use crate::util::cli::{Report, Reportable};
+use std::{collections::BTreeSet, path::PathBuf};
+
+/// What the Android template's `NativeActivity` looks up once the lib's loaded.
+pub static ANDROID_DEFAULT: &str = "ANativeActivity_onCreate";
+/// What the Apple template's `main.mm` calls.
+pub static APPLE_DEFAULT: &str = "start_app";
+
+#[derive(Debug)]
+pub struct Error {
+    artifact: PathBuf,
+    missing: Vec<String>,
+    config_key: &'static str,
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        let symbols = self
+            .missing
+            .iter()
+            .map(|symbol| format!("`{}`", symbol))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let snippets = self
+            .missing
+            .iter()
+            .map(|symbol| snippet(symbol))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Report::action_request(
+            format!("The built lib doesn't export {}", symbols),
+            format!(
+                "{:?} doesn't define {}, which the generated project calls to start your app. That usually means `#[mobile_entry_point]` is missing from your lib's `main`, or that it's in a module that isn't compiled for this target. The lib needs something like this:\n\n{}\n\nIf your project starts the app some other way, set `{}` to the symbols it calls, or to `[]` to skip this check.",
+                self.artifact, symbols, snippets, self.config_key,
+            ),
+        )
+    }
+
+    fn code(&self) -> &'static str {
+        "entry-point.missing"
+    }
+}
+
+fn snippet(symbol: &str) -> String {
+    if symbol == ANDROID_DEFAULT || symbol == APPLE_DEFAULT {
+        "#[mobile_entry_point]\nfn main() {\n    // ...\n}".to_owned()
+    } else {
+        format!(
+            "#[no_mangle]\npub extern \"C\" fn {}() {{\n    // ...\n}}",
+            symbol
+        )
+    }
+}
+
+/// The defined symbols in `nm` output. Mach-O symbols get a leading `_`, which
+/// `strip_underscore` takes off so they match the names in Rust.
+fn parse_nm(output: &str, strip_underscore: bool) -> BTreeSet<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            // `<address> <type> <name>`; undefined symbols have no address,
+            // and archives have a `lib.a(member.o):` line before each member.
+            let mut fields = line.split_whitespace();
+            let (_address, kind, name) = (fields.next()?, fields.next()?, fields.next()?);
+            if kind.eq_ignore_ascii_case("u") {
+                return None;
+            }
+            // Versioned ELF symbols look like `name@@VERSION`.
+            let name = name.split('@').next().unwrap_or(name);
+            let name = if strip_underscore {
+                name.strip_prefix('_').unwrap_or(name)
+            } else {
+                name
+            };
+            Some(name.to_owned())
+        })
+        .collect()
+}
+
+/// Runs `nm` and makes sure everything in `expected` is defined in `artifact`.
+/// If `nm` itself fails, that's logged and the check is skipped, since the
+/// build tools will still catch a missing symbol, just less helpfully.
+pub fn check(
+    nm: bossy::Command,
+    artifact: PathBuf,
+    expected: &[String],
+    strip_underscore: bool,
+    config_key: &'static str,
+) -> Result<(), Error> {
+    if expected.is_empty() {
+        return Ok(());
+    }
+    let defined = match nm
+        .with_arg(&artifact)
+        .run_and_wait_for_str(|output| parse_nm(output, strip_underscore))
+    {
+        Ok(defined) => defined,
+        Err(err) => {
+            log::warn!(
+                "failed to list the symbols in {:?}, so not checking for {:?}: {}",
+                artifact,
+                expected,
+                err
+            );
+            return Ok(());
+        }
+    };
+    let missing = expected
+        .iter()
+        .filter(|symbol| !defined.contains(symbol.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        log::info!("{:?} exports {:?}", artifact, expected);
+        Ok(())
+    } else {
+        Err(Error {
+            artifact,
+            missing,
+            config_key,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_nm_elf() {
+        let defined = parse_nm(
+            "0000000000052f10 T ANativeActivity_onCreate\n                 U __cxa_finalize@LIBC\n0000000000053000 T JNI_OnLoad@@VERS_1.0\n",
+            false,
+        );
+        assert!(defined.contains("ANativeActivity_onCreate"));
+        assert!(defined.contains("JNI_OnLoad"));
+        assert!(!defined.contains("__cxa_finalize"));
+    }
+
+    #[test]
+    fn test_parse_nm_archive() {
+        let defined = parse_nm(
+            "\nlibapp.a(app-1a2b.app.o):\n0000000000000000 T _start_app\n                 U _objc_msgSend\n\nlibapp.a(std-3c4d.o):\n",
+            true,
+        );
+        assert_eq!(
+            defined.into_iter().collect::<Vec<_>>(),
+            vec!["start_app".to_owned()]
+        );
+    }
+}
@@ -1,6 +1,10 @@
-use crate::util::cli::{Report, Reportable};
+use crate::util::{
+    cli::{Report, Reportable},
+    proxy::{self, Proxy},
+};
 use std::{
-    ffi::OsStr,
+    collections::{BTreeMap, BTreeSet},
+    ffi::{OsStr, OsString},
     fmt::{self, Debug, Display},
     path::Path,
 };
@@ -36,6 +40,13 @@ impl Reportable for Error {
     fn report(&self) -> Report {
         Report::error("Failed to initialize base environment", self)
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::HomeNotSet(..) => "env.home-not-set",
+            Self::PathNotSet(..) => "env.path-not-set",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -44,6 +55,8 @@ pub struct Env {
     path: String,
     term: Option<String>,
     ssh_auth_sock: Option<String>,
+    developer_dir: Option<String>,
+    proxy: &'static Proxy,
 }
 
 impl Env {
@@ -52,11 +65,15 @@ impl Env {
         let path = std::env::var("PATH").map_err(Error::PathNotSet)?;
         let term = std::env::var("TERM").ok();
         let ssh_auth_sock = std::env::var("SSH_AUTH_SOCK").ok();
+        // This lets CI pin a specific Xcode when several are installed.
+        let developer_dir = std::env::var("DEVELOPER_DIR").ok();
         Ok(Self {
             home,
             path,
             term,
             ssh_auth_sock,
+            developer_dir,
+            proxy: proxy::get(),
         })
     }
 
@@ -64,6 +81,10 @@ impl Env {
         &self.path
     }
 
+    pub fn developer_dir(&self) -> Option<&str> {
+        self.developer_dir.as_deref()
+    }
+
     pub fn prepend_to_path(mut self, path: impl AsRef<Path>) -> Self {
         self.path = format!("{}:{}", path.as_ref().display(), self.path);
         self
@@ -79,6 +100,243 @@ impl ExplicitEnv for Env {
         if let Some(ssh_auth_sock) = self.ssh_auth_sock.as_ref() {
             env.push(("SSH_AUTH_SOCK", ssh_auth_sock.as_ref()));
         }
+        if let Some(developer_dir) = self.developer_dir.as_ref() {
+            env.push(("DEVELOPER_DIR", developer_dir.as_ref()));
+        }
+        env.extend(
+            self.proxy
+                .env_vars()
+                .into_iter()
+                .map(|(var, value)| (var, value.as_ref())),
+        );
         env
     }
 }
+
+// Words that mark a variable as holding something that shouldn't end up in a
+// bug report, i.e. `KEYSTORE_PASSWORD` or `API_TOKEN`.
+static SECRET_WORDS: &[&str] = &[
+    "CREDENTIAL",
+    "CREDENTIALS",
+    "PASS",
+    "PASSWD",
+    "PASSWORD",
+    "SECRET",
+    "TOKEN",
+];
+
+pub(crate) static REDACTED: &str = "<redacted>";
+
+pub fn is_secret(key: &str) -> bool {
+    key.to_uppercase()
+        .split('_')
+        .any(|word| SECRET_WORDS.contains(&word))
+}
+
+/// How to print a [`BuildEnv`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Table,
+    Shell(Shell),
+    Json,
+}
+
+impl Format {
+    pub fn from_flags(shell: bool, json: bool) -> Self {
+        if json {
+            Self::Json
+        } else if shell {
+            Self::Shell(Shell::detect())
+        } else {
+            Self::Table
+        }
+    }
+}
+
+/// The syntax `--shell` output uses.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shell {
+    Posix,
+    Fish,
+    PowerShell,
+}
+
+impl Shell {
+    /// Goes by `SHELL`, falling back to POSIX syntax, which most shells take.
+    pub fn detect() -> Self {
+        let shell = std::env::var("SHELL").unwrap_or_default();
+        match Path::new(&shell).file_name().and_then(OsStr::to_str) {
+            Some("fish") => Self::Fish,
+            Some("pwsh") | Some("powershell") => Self::PowerShell,
+            _ => Self::Posix,
+        }
+    }
+
+    pub fn export(self, key: &str, value: &str) -> String {
+        match self {
+            Self::Posix => format!("export {}='{}'", key, value.replace('\'', "'\\''")),
+            Self::Fish => format!(
+                "set -gx {} '{}'",
+                key,
+                value.replace('\\', "\\\\").replace('\'', "\\'")
+            ),
+            Self::PowerShell => format!("$env:{} = '{}'", key, value.replace('\'', "''")),
+        }
+    }
+}
+
+/// Every variable a build runs with. This is what commands are actually run
+/// with, so printing it shows exactly what cargo-mobile sets.
+#[derive(Clone, Default)]
+pub struct BuildEnv {
+    vars: BTreeMap<String, OsString>,
+    // Ones that don't look secret, but are anyway.
+    secrets: BTreeSet<String>,
+}
+
+impl Debug for BuildEnv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.displayable()).finish()
+    }
+}
+
+impl BuildEnv {
+    pub fn new(base: &impl ExplicitEnv) -> Self {
+        Self {
+            vars: base
+                .explicit_env()
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+            secrets: Default::default(),
+        }
+    }
+
+    pub fn with_var(mut self, key: impl Into<String>, value: impl AsRef<OsStr>) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: impl AsRef<OsStr>) {
+        self.vars.insert(key.into(), value.as_ref().to_owned());
+    }
+
+    /// Like [`BuildEnv::insert`], but the value's always redacted when
+    /// printed.
+    pub fn insert_secret(&mut self, key: impl Into<String>, value: impl AsRef<OsStr>) {
+        let key = key.into();
+        self.secrets.insert(key.clone());
+        self.insert(key, value);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&OsStr> {
+        self.vars.get(key).map(OsString::as_os_str)
+    }
+
+    // Secrets are swapped out here, so that no format can forget to.
+    fn displayable(&self) -> impl Iterator<Item = (&str, String)> {
+        self.vars.iter().map(|(key, value)| {
+            let value = if is_secret(key) || self.secrets.contains(key) {
+                REDACTED.to_owned()
+            } else if proxy::is_var(key) {
+                proxy::redact(&value.to_string_lossy())
+            } else {
+                value.to_string_lossy().into_owned()
+            };
+            (key.as_str(), value)
+        })
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Table => {
+                let width = self.vars.keys().map(String::len).max().unwrap_or_default();
+                // `CARGO_ENCODED_RUSTFLAGS` separates flags with an invisible
+                // control char, which would otherwise run them together.
+                self.displayable()
+                    .map(|(key, value)| {
+                        format!(
+                            "{:width$}  {}\n",
+                            key,
+                            value.replace('\u{1f}', " "),
+                            width = width
+                        )
+                    })
+                    .collect()
+            }
+            Format::Shell(shell) => self
+                .displayable()
+                .map(|(key, value)| format!("{}\n", shell.export(key, &value)))
+                .collect(),
+            Format::Json => {
+                let map = self
+                    .displayable()
+                    .map(|(key, value)| (key.to_owned(), serde_json::Value::String(value)))
+                    .collect::<serde_json::Map<_, _>>();
+                format!(
+                    "{}\n",
+                    serde_json::to_string_pretty(&map)
+                        .expect("developer error: env couldn't be serialized")
+                )
+            }
+        }
+    }
+}
+
+impl ExplicitEnv for BuildEnv {
+    fn explicit_env(&self) -> Vec<(&str, &OsStr)> {
+        self.vars
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_os_str()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        key,
+        expected,
+        case("KEYSTORE_PASSWORD", true),
+        case("api_token", true),
+        case("MY_SECRET", true),
+        case("PATH", false),
+        case("PASSTHROUGH", false),
+        case("SSH_AUTH_SOCK", false)
+    )]
+    fn test_is_secret(key: &str, expected: bool) {
+        assert_eq!(is_secret(key), expected);
+    }
+
+    #[rstest(
+        shell,
+        expected,
+        case(Shell::Posix, "export KEY='it'\\''s'"),
+        case(Shell::Fish, "set -gx KEY 'it\\'s'"),
+        case(Shell::PowerShell, "$env:KEY = 'it''s'")
+    )]
+    fn test_export_quoting(shell: Shell, expected: &str) {
+        assert_eq!(shell.export("KEY", "it's"), expected);
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let mut env = BuildEnv::default()
+            .with_var("KEYSTORE_PASSWORD", "hunter2")
+            .with_var("TARGET_CC", "clang");
+        env.insert_secret("CARGO_MOBILE_CFG_API", "correct horse");
+        assert!(!format!("{:?}", env).contains("correct horse"));
+        for format in &[Format::Table, Format::Shell(Shell::Posix), Format::Json] {
+            let rendered = env.render(*format);
+            assert!(
+                !rendered.contains("hunter2") && !rendered.contains("correct horse"),
+                "{:?} leaked a secret",
+                format
+            );
+            assert!(rendered.contains("clang"));
+        }
+    }
+}
@@ -0,0 +1,525 @@
+//! Every error code, so that they can be listed with the hidden
+//! `cargo mobile errors --list`, and so that tests notice when one goes
+//! missing. Codes are what tools match on, so once one's been released, it
+//! can't be renamed or removed; add new ones here along with their
+//! `Reportable::code`.
+
+/// Sorted, and includes codes for errors that only happen on macOS.
+pub static ALL: &[&str] = &[
+    "android.adb.device-list.abi-failed",
+    "android.adb.device-list.abi-invalid",
+    "android.adb.device-list.devices-failed",
+    "android.adb.device-list.model-failed",
+    "android.adb.device-list.name-failed",
+    "android.adb.device-list.select.not-found",
+    "android.adb.device-list.version-failed",
+    "android.adb.device-name.dumpsys-failed",
+    "android.adb.device-name.not-matched",
+    "android.adb.get-prop.lookup-failed",
+    "android.apk.abis.not-an-apk",
+    "android.apk.abis.read-failed",
+    "android.apk.apk-build.assemble-failed",
+    "android.apk.apk-build.bundle-failed",
+    "android.apk.apk-build.connected-test-failed",
+    "android.apk.copy.ambiguous",
+    "android.apk.copy.copy-failed",
+    "android.apk.copy.dir-creation-failed",
+    "android.apk.outputs.metadata-missing",
+    "android.apk.outputs.no-bundle",
+    "android.apk.outputs.no-outputs",
+    "android.apk.outputs.parse-failed",
+    "android.apk.outputs.read-failed",
+    "android.cli.build-env-failed",
+    "android.cli.dot-cargo-gen-failed",
+    "android.cli.open-failed",
+    "android.cli.project-dir-absent",
+    "android.cli.target-invalid",
+    "android.cli.test-kind-missing",
+    "android.cli.unsupported",
+    "android.device.apk-install.abi-mismatch",
+    "android.device.apk-install.install-failed",
+    "android.device.forward.listen-failed",
+    "android.device.forward.reverse-failed",
+    "android.device.forward.unreached",
+    "android.device.run.logcat-failed",
+    "android.device.run.start-failed",
+    "android.device.run.wake-screen-failed",
+    "android.device.transfer.not-debuggable",
+    "android.device.transfer.not-installed",
+    "android.device.transfer.pull-failed",
+    "android.device.transfer.push-failed",
+    "android.device.transfer.read-failed",
+    "android.device.transfer.remote-missing",
+    "android.device.transfer.run-as-failed",
+    "android.device.transfer.write-failed",
+    "android.env.sdk-root-not-a-dir",
+    "android.env.sdk-root-not-set",
+    "android.inspect.no-artifact",
+    "android.inspect.not-an-archive",
+    "android.inspect.read-failed",
+    "android.instrumented.no-results",
+    "android.instrumented.results-clear-failed",
+    "android.instrumented.results-read-failed",
+    "android.instrumented.tests-failed",
+    "android.jdk.read-failed",
+    "android.jdk.write-failed",
+    "android.jnilibs.remove-broken-links.entry-failed",
+    "android.jnilibs.remove-broken-links.read-dir-failed",
+    "android.jnilibs.remove-broken-links.remove-failed",
+    "android.jnilibs.strip-lib.copy-failed",
+    "android.jnilibs.strip-lib.metadata-failed",
+    "android.jnilibs.strip-lib.missing-tool",
+    "android.jnilibs.strip-lib.remove-failed",
+    "android.jnilibs.strip-lib.source-missing",
+    "android.jnilibs.strip-lib.strip-failed",
+    "android.jnilibs.symlink-lib.source-missing",
+    "android.jnilibs.symlink-lib.symlink-failed",
+    "android.ndk.host-tag-not-found",
+    "android.ndk.ndk-home-not-a-dir",
+    "android.ndk.ndk-home-not-set",
+    "android.ndk.prebuilt-dir-missing",
+    "android.ndk.required-libs.invalid-utf8",
+    "android.ndk.required-libs.missing-tool",
+    "android.ndk.required-libs.read-elf-failed",
+    "android.ndk.version-lookup-failed",
+    "android.ndk.version-too-low",
+    "android.parallel.failed",
+    "android.project.asset-dir-symlink-failed",
+    "android.project.directory-creation-failed",
+    "android.project.dot-cargo-gen-failed",
+    "android.project.missing-pack",
+    "android.project.rustup-failed",
+    "android.project.template-processing-failed",
+    "android.project.variant-icon-copy-failed",
+    "android.target.compile-lib.cargo-failed",
+    "android.target.compile-lib.missing-tool",
+    "android.target.hook.build-env-failed",
+    "android.target.symlink-libs.jni-libs-creation-failed",
+    "android.target.symlink-libs.lib-missing",
+    "android.target.symlink-libs.libcxx-shared-path-failed",
+    "apple.capture.device-recording-unsupported",
+    "apple.capture.device-screenshot-failed",
+    "apple.capture.dir-creation-failed",
+    "apple.capture.idevicescreenshot-missing",
+    "apple.capture.output-missing",
+    "apple.capture.presence-check-failed",
+    "apple.capture.record-failed",
+    "apple.capture.screenshot-failed",
+    "apple.clean.cargo-clean-failed",
+    "apple.clean.derived-data-read-failed",
+    "apple.clean.no-home-dir",
+    "apple.clean.remove-failed",
+    "apple.cli.arch-invalid",
+    "apple.cli.cargo-env-failed",
+    "apple.cli.identities-failed",
+    "apple.cli.ipa-missing",
+    "apple.cli.no-home-dir",
+    "apple.cli.open-failed",
+    "apple.cli.profiles-failed",
+    "apple.cli.project-dir-absent",
+    "apple.cli.sdk-root-failed",
+    "apple.cli.target-invalid",
+    "apple.cli.teams-failed",
+    "apple.cli.unsupported",
+    "apple.desktop.run.app-failed",
+    "apple.desktop.run.app-missing",
+    "apple.desktop.run.disabled",
+    "apple.desktop.run.rustup-failed",
+    "apple.device.device-list.command-present-failed",
+    "apple.device.device-list.no-backend",
+    "apple.device.run.ipa-missing",
+    "apple.device.run.kill-on-exit-unsupported",
+    "apple.device.run.launch-unsupported",
+    "apple.device.run.unpaired",
+    "apple.device.run.unzip-failed",
+    "apple.device.select.not-found",
+    "apple.devicectl.device-list.device-list.arch-invalid",
+    "apple.devicectl.device-list.device-list.detection-failed",
+    "apple.devicectl.run.run.command-present-failed",
+    "apple.devicectl.run.run.install-failed",
+    "apple.devicectl.run.run.launch-failed",
+    "apple.devicectl.run.run.locked",
+    "apple.devicectl.run.run.log-stream-failed",
+    "apple.devicectl.run.terminate.list-failed",
+    "apple.devicectl.run.terminate.signal-failed",
+    "apple.dsym.copy-failed",
+    "apple.dsym.dir-creation-failed",
+    "apple.dsym.hook-failed",
+    "apple.dsym.read-dir-failed",
+    "apple.dsym.remove-failed",
+    "apple.dsym.uuid-lookup-failed",
+    "apple.icons.icon-not-configured",
+    "apple.icons.resize-failed",
+    "apple.icons.source-has-alpha",
+    "apple.icons.source-open-failed",
+    "apple.icons.source-size-invalid",
+    "apple.icons.write-failed",
+    "apple.identities.check.invalid",
+    "apple.identities.check.lookup-failed",
+    "apple.identities.check.missing",
+    "apple.inspect.no-artifact",
+    "apple.install.dir-creation-failed",
+    "apple.install.executable-missing",
+    "apple.install.extension-invalid",
+    "apple.install.info-plist-invalid",
+    "apple.install.mach-o-invalid",
+    "apple.install.mismatch",
+    "apple.install.payload-missing",
+    "apple.install.read-failed",
+    "apple.install.unzip-failed",
+    "apple.ios-deploy.device-list.device-list.arch-invalid",
+    "apple.ios-deploy.device-list.device-list.detection-failed",
+    "apple.ios-deploy.run.run-and-debug.deploy-failed",
+    "apple.ios-deploy.run.run-and-debug.install-failed",
+    "apple.lipo.archs-failed",
+    "apple.lipo.archs-mismatched",
+    "apple.lipo.create-failed",
+    "apple.lipo.device-and-simulator-mixed",
+    "apple.lipo.dir-creation-failed",
+    "apple.lipo.lib-missing",
+    "apple.lipo.remove-failed",
+    "apple.packages.resolve.command-failed",
+    "apple.packages.resolve.resolution-failed",
+    "apple.pods.install-failed",
+    "apple.pods.pod-missing",
+    "apple.pods.presence-check-failed",
+    "apple.pods.read-failed",
+    "apple.pods.remove-failed",
+    "apple.pods.write-failed",
+    "apple.profiles.check.device-missing",
+    "apple.profiles.check.expired",
+    "apple.project.asset-dir-symlink-failed",
+    "apple.project.deps-install-failed",
+    "apple.project.missing-pack",
+    "apple.project.rustup-failed",
+    "apple.project.template-processing-failed",
+    "apple.project.xcodegen-failed",
+    "apple.schemes.configuration-missing",
+    "apple.schemes.list-failed",
+    "apple.schemes.list-invalid",
+    "apple.schemes.scheme-missing",
+    "apple.simctl.device-list.device-list.detection-failed",
+    "apple.simctl.device-list.device-list.parse-failed",
+    "apple.simctl.device-list.select.list-failed",
+    "apple.simctl.device-list.select.none-available",
+    "apple.simctl.device-list.select.none-booted",
+    "apple.simctl.device-list.select.not-booted",
+    "apple.simctl.device-list.select.not-found",
+    "apple.simctl.device-list.select.prompt-failed",
+    "apple.simctl.run.run.app-missing",
+    "apple.simctl.run.run.boot-failed",
+    "apple.simctl.run.run.install-failed",
+    "apple.simctl.run.run.launch-failed",
+    "apple.simctl.run.run.log-stream-failed",
+    "apple.simctl.run.run.open-failed",
+    "apple.simctl.run.run.rustup-failed",
+    "apple.simctl.run.run.terminate-failed",
+    "apple.sources.glob-empty",
+    "apple.sources.glob-invalid",
+    "apple.sources.path-missing",
+    "apple.symbolicate.atos-failed",
+    "apple.symbolicate.dsyms-failed",
+    "apple.symbolicate.ips-invalid",
+    "apple.symbolicate.no-images",
+    "apple.symbolicate.no-matching-dsym",
+    "apple.symbolicate.read-failed",
+    "apple.target.archive.xcodebuild-failed",
+    "apple.target.build-env.include-dir-invalid",
+    "apple.target.build-env.macos-sdk-root-invalid",
+    "apple.target.build-env.sdk-root-invalid",
+    "apple.target.build-std.not-nightly",
+    "apple.target.build-std.rust-src-missing",
+    "apple.target.build-std.toolchain-check-failed",
+    "apple.target.build.xcodebuild-failed",
+    "apple.target.check.cargo-check-failed",
+    "apple.target.compile-lib.cargo-build-failed",
+    "apple.target.compile-lib.lib-missing",
+    "apple.target.export.export-failed",
+    "apple.target.export.options-write-failed",
+    "apple.target.hook.sdk-root-failed",
+    "apple.target.version-check.lookup-failed",
+    "apple.target.version-check.too-low",
+    "apple.transfer.container-failed",
+    "apple.transfer.copy-failed",
+    "apple.transfer.device-copy-failed",
+    "apple.transfer.device-wildcard-unsupported",
+    "apple.transfer.devicectl-missing",
+    "apple.transfer.output-missing",
+    "apple.upload.altool-failed",
+    "apple.upload.ipa-invalid",
+    "apple.upload.ipa-missing",
+    "apple.upload.key-file-name-invalid",
+    "apple.upload.key-missing",
+    "apple.upload.output-parse-failed",
+    "apple.upload.rejected",
+    "apple.xcframework.cbindgen-failed",
+    "apple.xcframework.cbindgen-missing",
+    "apple.xcframework.cbindgen-presence-check-failed",
+    "apple.xcframework.compile-lib-failed",
+    "apple.xcframework.create-failed",
+    "apple.xcframework.headers-dir-missing",
+    "apple.xcframework.lipo-failed",
+    "apple.xcframework.manifest-parse-failed",
+    "apple.xcframework.manifest-read-failed",
+    "apple.xcframework.not-staticlib",
+    "apple.xcframework.remove-failed",
+    "apple.xcframework.rustup-failed",
+    "apple.xcode.command-line-tools-only",
+    "apple.xcode.select-failed",
+    "apple.xcode.too-low",
+    "apple.xcode.version-failed",
+    "apple.xcode.version-invalid",
+    "assets.pattern-invalid",
+    "assets.read-failed",
+    "cli.open-failed",
+    "cli.templates-failed",
+    "cli.update-failed",
+    "config.build-config.unset",
+    "config.check.from-raw-failed",
+    "config.check.load-failed",
+    "config.check.missing",
+    "config.gen.canonicalize-failed",
+    "config.gen.detect-failed",
+    "config.gen.from-raw-failed",
+    "config.gen.prompt-failed",
+    "config.gen.write-failed",
+    "config.load-or-gen.from-raw-failed",
+    "config.load-or-gen.load-failed",
+    "config.metadata.parse-failed",
+    "config.metadata.read-failed",
+    "config.raw.detect.app-failed",
+    "config.raw.detect.apple-failed",
+    "config.raw.prompt.app-failed",
+    "config.raw.prompt.apple-failed",
+    "config.raw.write.parse-failed",
+    "config.raw.write.read-failed",
+    "config.raw.write.serialize-failed",
+    "config.raw.write.write-failed",
+    "config.variant.unknown",
+    "crash.crashed",
+    "device.choose.ambiguous",
+    "device.choose.prompt-failed",
+    "device.forget-failed",
+    "device.prompt.none-detected",
+    "device.prompt.prompt-failed",
+    "device.wait.not-ready",
+    "doctor.failed",
+    "dot-cargo.load.deserialize-failed",
+    "dot-cargo.load.dir-creation-failed",
+    "dot-cargo.load.migrate-failed",
+    "dot-cargo.load.read-failed",
+    "dot-cargo.write.dir-creation-failed",
+    "dot-cargo.write.write-failed",
+    "entry-point.missing",
+    "env.home-not-set",
+    "env.path-not-set",
+    "generated.parse-failed",
+    "generated.prompt-failed",
+    "generated.read-failed",
+    "generated.write-failed",
+    "hooks.failed",
+    "init.asset-dir-creation-failed",
+    "init.code-command-present-failed",
+    "init.dot-first-init-delete-failed",
+    "init.dot-first-init-write-failed",
+    "init.filter-configure-failed",
+    "init.lldb-extension-install-failed",
+    "init.open-in-editor-failed",
+    "init.post-init-hooks-failed",
+    "net.free-port-failed",
+    "net.port-in-use",
+    "new.dir-creation-failed",
+    "new.dir-name-invalid",
+    "new.dir-not-empty",
+    "new.dir-read-failed",
+    "new.domain-invalid",
+    "new.inside-project",
+    "new.name-invalid",
+    "plugin.failed",
+    "plugin.no-such-command",
+    "preflight.not-writable",
+    "preflight.out-of-space",
+    "project.git-init-failed",
+    "project.manifest-failed",
+    "project.processing-failed",
+    "project.template-pack-resolve-failed",
+    "regen.prompt-failed",
+    "regen.regen-declined",
+    "setup.prompt-failed",
+    "sizes.grew",
+    "sizes.measure-failed",
+    "stamp.mismatch",
+    "templating.embedded.dir-creation-failed",
+    "templating.embedded.no-home-dir",
+    "templating.embedded.remove-failed",
+    "templating.embedded.rename-failed",
+    "templating.embedded.temp-dir-failed",
+    "templating.embedded.write-failed",
+    "templating.lint.current-dir-failed",
+    "templating.lint.errors-found",
+    "templating.lint.lookup-failed",
+    "templating.lint.read-dir-failed",
+    "templating.lint.resolve-failed",
+    "templating.lint.sample-config-failed",
+    "templating.lint.temp-write-failed",
+    "transfer.ambiguous",
+    "transfer.no-match",
+    "upgrade.manifest-invalid",
+    "upgrade.read-failed",
+    "upgrade.remove-failed",
+    "upgrade.temp-dir-failed",
+    "upgrade.write-failed",
+    "util.host-target-triple.command-failed",
+    "util.host-target-triple.launch-failed",
+    "util.host-target-triple.search-failed",
+    "util.passthrough.exited",
+    "util.passthrough.failed",
+    "util.pipe.pipe-failed",
+    "util.pipe.rx-command-failed",
+    "util.pipe.tx-command-failed",
+    "util.pipe.wait-failed",
+    "util.progress.fd-open-failed",
+    "util.rust-version.command-failed",
+    "util.rust-version.day-invalid",
+    "util.rust-version.major-invalid",
+    "util.rust-version.minor-invalid",
+    "util.rust-version.month-invalid",
+    "util.rust-version.patch-invalid",
+    "util.rust-version.year-invalid",
+    "util.watchdog.stalled",
+    "version.build-number-decreased",
+    "version.bump.read-failed",
+    "version.bump.version-inherited",
+    "version.bump.version-invalid",
+    "version.bump.version-missing",
+    "version.bump.write-failed",
+    "version.commit-count-failed",
+    "version.commit-count-invalid",
+    "version.last-invalid",
+    "version.last-read-failed",
+    "version.last-write-failed",
+    "version.manifest-invalid",
+    "version.metadata-failed",
+    "version.metadata-invalid",
+    "version.package-missing",
+    "vms.lock-failed",
+    "vms.no-home-dir",
+    "vms.not-found",
+    "vms.parse-failed",
+    "vms.read-failed",
+    "vms.stop-failed",
+    "vms.write-failed",
+    "watch.metadata-failed",
+    "watch.metadata-invalid",
+    "watch.watch-failed",
+    "watch.watcher-failed",
+    "workspace.build-failed",
+    "workspace.change-dir-failed",
+    "workspace.config-invalid",
+    "workspace.current-exe-failed",
+    "workspace.discover-failed",
+    "workspace.manifest-missing",
+    "workspace.metadata-failed",
+    "workspace.metadata-invalid",
+    "workspace.names-collide",
+    "workspace.no-apps",
+    "workspace.package-not-found",
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use once_cell_regex::regex;
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        fs,
+        path::Path,
+    };
+
+    // The codes returned by every `Reportable::code` in the source, which
+    // relies on them being formatted by rustfmt.
+    fn codes_in(dir: &Path, codes: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                codes_in(&path, codes);
+                continue;
+            }
+            if path.extension().map_or(true, |ext| ext != "rs") {
+                continue;
+            }
+            let src = fs::read_to_string(&path).unwrap();
+            let mut lines = src.lines();
+            while let Some(line) = lines.next() {
+                if line.trim() != "fn code(&self) -> &'static str {" {
+                    continue;
+                }
+                let end = format!("{}}", &line[..line.len() - line.trim_start().len()]);
+                for line in lines.by_ref().take_while(|line| *line != end) {
+                    assert!(
+                        !line.trim_start().starts_with("_ =>"),
+                        "{:?} has a catch-all arm in `Reportable::code`, but every variant needs its own code",
+                        path
+                    );
+                    if line.trim_start().starts_with("#[") {
+                        continue;
+                    }
+                    for caps in regex!(r#""([^"]*)""#).captures_iter(line) {
+                        codes.push(caps[1].to_owned());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_codes_unique_and_well_formed() {
+        let mut sorted = ALL.to_vec();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(sorted, ALL, "codes must be sorted and unique");
+        for code in ALL {
+            assert!(
+                regex!(r"^[a-z0-9]+(-[a-z0-9]+)*(\.[a-z0-9]+(-[a-z0-9]+)*)+$").is_match(code),
+                "{:?} isn't a well-formed code",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_codes_match_source() {
+        let mut codes = Vec::new();
+        codes_in(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+            &mut codes,
+        );
+        let mut counts = BTreeMap::<_, usize>::new();
+        for code in &codes {
+            *counts.entry(code.as_str()).or_default() += 1;
+        }
+        assert_eq!(
+            counts
+                .into_iter()
+                .filter(|(_, count)| *count > 1)
+                .collect::<Vec<_>>(),
+            vec![],
+            "codes returned for more than one error"
+        );
+        let codes = codes.into_iter().collect::<BTreeSet<_>>();
+        let all = ALL
+            .iter()
+            .map(|code| code.to_string())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            codes.difference(&all).collect::<Vec<_>>(),
+            Vec::<&String>::new(),
+            "codes missing from `ALL`"
+        );
+        assert_eq!(
+            all.difference(&codes).collect::<Vec<_>>(),
+            Vec::<&String>::new(),
+            "codes in `ALL` that nothing returns anymore"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Which cargo features each platform gets built with. They can come from
+//! `Cargo.toml` metadata, the config, or `--features`, in increasing order of
+//! precedence.
+
+use crate::{env::ExplicitEnv, util};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path};
+
+/// Works out `--no-default-features` and `--features` for one platform.
+/// Listing features in `Cargo.toml` metadata has always implied
+/// `--no-default-features`, so the config's features replace those outright
+/// and leave default features on unless `no-default-features` says otherwise.
+/// Features from the command line are added to whatever that comes to.
+pub fn merge(
+    platform: &str,
+    metadata_features: Option<&[String]>,
+    config_features: Option<&[String]>,
+    config_no_default_features: Option<bool>,
+    cli_features: &[String],
+) -> (bool, Option<Vec<String>>) {
+    let (no_default_features, mut features) = match config_features {
+        Some(features) => (
+            config_no_default_features.unwrap_or_default(),
+            Some(features.to_vec()),
+        ),
+        None => (
+            config_no_default_features.unwrap_or_else(|| metadata_features.is_some()),
+            metadata_features.map(<[String]>::to_vec),
+        ),
+    };
+    if !cli_features.is_empty() {
+        let configured = features.clone().unwrap_or_default();
+        let merged = features.get_or_insert_with(Vec::new);
+        for feature in cli_features {
+            if !merged.contains(feature) {
+                merged.push(feature.clone());
+            }
+        }
+        log::info!(
+            "building {} with features {:?} (adding {:?} from the command line to {:?})",
+            platform,
+            merged,
+            cli_features,
+            configured
+        );
+    }
+    (no_default_features, features)
+}
+
+#[derive(Debug, Deserialize)]
+struct Dependency {
+    name: String,
+    rename: Option<String>,
+    #[serde(default)]
+    optional: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    name: String,
+    features: BTreeMap<String, Vec<String>>,
+    dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+}
+
+// Optional dependencies are features too, even though older versions of cargo
+// leave them out of `features`.
+fn known(package: &Package) -> Vec<&str> {
+    package
+        .features
+        .keys()
+        .map(String::as_str)
+        .chain(
+            package
+                .dependencies
+                .iter()
+                .filter(|dep| dep.optional)
+                .map(|dep| dep.rename.as_deref().unwrap_or(&dep.name)),
+        )
+        .collect()
+}
+
+fn unknown<'a>(features: &'a [String], known: &[&str]) -> Vec<(&'a str, Option<String>)> {
+    features
+        .iter()
+        .map(String::as_str)
+        // Features of dependencies are cargo's to check.
+        .filter(|feature| !feature.contains('/') && !feature.starts_with("dep:"))
+        .filter(|feature| !known.contains(feature))
+        .map(|feature| {
+            let suggestion = util::suggest(feature, known.iter().copied()).map(str::to_owned);
+            (feature, suggestion)
+        })
+        .collect()
+}
+
+/// Warns about any of `features` that `package` doesn't have, which cargo
+/// would otherwise only complain about once the build gets going. This is
+/// best-effort, so failing to ask cargo just skips the check.
+pub fn warn_unknown(
+    manifest_path: &Path,
+    package: &str,
+    env: &impl ExplicitEnv,
+    features: &[String],
+) {
+    if features.is_empty() {
+        return;
+    }
+    let output = bossy::Command::pure("cargo")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["metadata", "--format-version", "1", "--no-deps"])
+        .with_arg("--manifest-path")
+        .with_arg(manifest_path)
+        .run_and_wait_for_str(|output| output.to_owned());
+    let metadata = match output {
+        Ok(output) => serde_json::from_str::<Metadata>(&output),
+        Err(err) => {
+            log::info!(
+                "skipping feature check, since `cargo metadata` failed: {}",
+                err
+            );
+            return;
+        }
+    };
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            log::info!(
+                "skipping feature check, since `cargo metadata` output couldn't be parsed: {}",
+                err
+            );
+            return;
+        }
+    };
+    if let Some(package) = metadata.packages.iter().find(|p| p.name == package) {
+        for (feature, suggestion) in unknown(features, &known(package)) {
+            match suggestion {
+                Some(suggestion) => log::warn!(
+                    "`{}` has no feature `{}`; did you mean `{}`?",
+                    package.name,
+                    feature,
+                    suggestion
+                ),
+                None => log::warn!("`{}` has no feature `{}`", package.name, feature),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn strings(strs: &[&str]) -> Vec<String> {
+        strs.iter().map(|s| (*s).to_owned()).collect()
+    }
+
+    #[test]
+    fn test_config_replaces_metadata_and_cli_adds_to_it() {
+        let metadata = strings(&["vulkan"]);
+        let config = strings(&["gl", "audio"]);
+        assert_eq!(
+            merge("android", Some(&metadata), None, None, &[]),
+            (true, Some(metadata.clone()))
+        );
+        assert_eq!(
+            merge("android", Some(&metadata), Some(&config), None, &[]),
+            (false, Some(config.clone()))
+        );
+        assert_eq!(
+            merge(
+                "android",
+                Some(&metadata),
+                Some(&config),
+                Some(true),
+                &strings(&["audio", "profiling"])
+            ),
+            (true, Some(strings(&["gl", "audio", "profiling"])))
+        );
+        assert_eq!(
+            merge("android", None, None, None, &strings(&["profiling"])),
+            (false, Some(strings(&["profiling"])))
+        );
+    }
+
+    #[test]
+    fn test_unknown_features_get_suggestions() {
+        let known = vec!["android-gl", "serde"];
+        let features = strings(&["andriod-gl", "serde", "bevy/trace", "wgpu"]);
+        assert_eq!(
+            unknown(&features, &known),
+            vec![
+                ("andriod-gl", Some("android-gl".to_owned())),
+                ("wgpu", None)
+            ]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// A record of everything that goes into a build step, so that the step can be
+/// skipped when none of it has changed since the step last ran. Files are
+/// compared by size and modification time rather than by content, which is
+/// plenty for build products and keeps checking cheap.
+#[derive(Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Fingerprint {
+    entries: BTreeMap<String, String>,
+}
+
+fn stamp(path: &Path) -> String {
+    fs::metadata(path)
+        .map(|metadata| {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_nanos())
+                .unwrap_or_default();
+            format!("{} bytes, modified {}", metadata.len(), modified)
+        })
+        .unwrap_or_else(|_| "missing".to_owned())
+}
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_value(mut self, key: &str, value: impl Display) -> Self {
+        self.entries.insert(key.to_owned(), value.to_string());
+        self
+    }
+
+    /// A missing file is recorded as such, so that it showing up (or going
+    /// away) also counts as a change.
+    pub fn with_file(mut self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        self.entries.insert(path.display().to_string(), stamp(path));
+        self
+    }
+
+    /// Records every file under `dir`, following symlinks.
+    pub fn with_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        match fs::read_dir(dir) {
+            Ok(entries) => {
+                for entry in entries.filter_map(Result::ok) {
+                    let path = entry.path();
+                    self = if path.is_dir() {
+                        self.with_dir(path)
+                    } else {
+                        self.with_file(path)
+                    };
+                }
+            }
+            Err(_) => {
+                self.entries
+                    .insert(dir.display().to_string(), "missing".to_owned());
+            }
+        }
+        self
+    }
+
+    /// Records every file under `dir` that git wouldn't ignore, besides those
+    /// under `skip`, which is for dirs that builds write into.
+    pub fn with_sources(mut self, dir: impl AsRef<Path>, skip: &[PathBuf]) -> Self {
+        let skip = skip.to_vec();
+        let walk = WalkBuilder::new(dir)
+            .hidden(false)
+            .require_git(false)
+            .filter_entry(move |entry| {
+                entry.file_name() != ".git" && !skip.iter().any(|skip| entry.path() == skip)
+            })
+            .build();
+        for entry in walk.filter_map(Result::ok) {
+            if entry.file_type().map_or(false, |ty| ty.is_file()) {
+                self = self.with_file(entry.path());
+            }
+        }
+        self
+    }
+}
+
+/// Where fingerprints are kept between runs, one file per build step.
+#[derive(Debug)]
+pub struct Store {
+    dir: PathBuf,
+}
+
+impl Store {
+    pub fn new(project_dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: project_dir.as_ref().join(".fingerprints"),
+        }
+    }
+
+    fn path(&self, step: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", step))
+    }
+
+    /// Whether `step` last ran with exactly this fingerprint. Anything going
+    /// wrong just means the step runs again.
+    pub fn fresh(&self, step: &str, fingerprint: &Fingerprint) -> bool {
+        fs::read(self.path(step))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Fingerprint>(&bytes).ok())
+            .map(|recorded| &recorded == fingerprint)
+            .unwrap_or_default()
+    }
+
+    /// Forgets what `step` last ran with. This should be done before running
+    /// it, so that a step that fails halfway isn't mistaken for up to date.
+    pub fn forget(&self, step: &str) {
+        let path = self.path(step);
+        if path.is_file() {
+            if let Err(err) = fs::remove_file(&path) {
+                log::warn!("failed to remove fingerprint {:?}: {}", path, err);
+            }
+        }
+    }
+
+    /// Forgets every step, so that they all run again.
+    pub fn clear(&self) {
+        if self.dir.is_dir() {
+            if let Err(err) = fs::remove_dir_all(&self.dir) {
+                log::warn!("failed to remove fingerprints {:?}: {}", self.dir, err);
+            }
+        }
+    }
+
+    pub fn record(&self, step: &str, fingerprint: &Fingerprint) {
+        let path = self.path(step);
+        let result = fs::create_dir_all(&self.dir)
+            .and_then(|()| {
+                serde_json::to_vec_pretty(fingerprint)
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+            })
+            .and_then(|bytes| fs::write(&path, bytes));
+        if let Err(err) = result {
+            log::warn!("failed to write fingerprint {:?}: {}", path, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_values() {
+        let debug = Fingerprint::new().with_value("profile", "debug");
+        assert_eq!(debug, Fingerprint::new().with_value("profile", "debug"));
+        assert_ne!(debug, Fingerprint::new().with_value("profile", "release"));
+        assert_ne!(
+            debug,
+            Fingerprint::new()
+                .with_value("profile", "debug")
+                .with_value("features", "")
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_missing_file() {
+        let path = Path::new("this/file/does/not/exist");
+        let fingerprint = Fingerprint::new().with_file(path);
+        assert_eq!(
+            fingerprint.entries.get(&path.display().to_string()),
+            Some(&"missing".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_store_survives_reload() {
+        let dir = crate::util::TempDir::new("fingerprint-store").unwrap();
+        let file = dir.path().join("input");
+        fs::write(&file, "hi").unwrap();
+        let fingerprint = || {
+            Fingerprint::new()
+                .with_value("profile", "debug")
+                .with_file(&file)
+        };
+        Store::new(dir.path()).record("step", &fingerprint());
+        let store = Store::new(dir.path());
+        assert!(store.fresh("step", &fingerprint()));
+        assert!(!store.fresh("other-step", &fingerprint()));
+        fs::write(&file, "hello").unwrap();
+        assert!(!store.fresh("step", &fingerprint()));
+        store.record("step", &fingerprint());
+        store.clear();
+        assert!(!Store::new(dir.path()).fresh("step", &fingerprint()));
+    }
+
+    #[test]
+    fn test_fingerprint_sources() {
+        let dir = crate::util::TempDir::new("fingerprint-sources").unwrap();
+        let build = dir.path().join("build");
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::create_dir_all(&build).unwrap();
+        fs::write(dir.path().join(".gitignore"), "/target\n").unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+        fs::create_dir_all(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join("target/lib.a"), "").unwrap();
+        fs::write(build.join("app"), "").unwrap();
+        let fingerprint = Fingerprint::new().with_sources(dir.path(), &[build]);
+        let keys = fingerprint.entries.keys().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                dir.path().join(".gitignore").display().to_string(),
+                dir.path().join("src/lib.rs").display().to_string(),
+            ]
+        );
+    }
+}
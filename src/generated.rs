@@ -0,0 +1,471 @@
+//! Keeping track of everything generation puts in `gen/`, so that whatever it
+//! stops generating (i.e. after renaming the app or switching template packs)
+//! can be cleaned up without touching anything else that's in there.
+//!
+//! Each generation records what it wrote in `gen/.generated`, along with the
+//! hash of the config it was generated from. Anything in a project that was
+//! just regenerated that was generated last time but not this time is removed
+//! right away; whatever can't be is kept track of as an orphan, which
+//! `cargo mobile gc` offers to remove later.
+
+use crate::{
+    config::{app::App, Config},
+    opts, regen, ui,
+    util::{
+        cli::{Report, Reportable},
+        prompt,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+static FILE_NAME: &str = ".generated";
+
+// Build outputs and what CocoaPods adds, which aren't generated, but aren't
+// the user's either, so there's no use in listing them. Hidden files are our
+// (and Gradle's) bookkeeping.
+static TOOL_OWNED: &[&str] = &["build", "jniLibs", "Pods", "Podfile", "Podfile.lock"];
+
+fn tool_owned(name: &str) -> bool {
+    name.starts_with('.') || name.ends_with(".xcworkspace") || TOOL_OWNED.contains(&name)
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    File,
+    Dir,
+    Link,
+    /// A dir that's generated along with everything in it.
+    Tree,
+}
+
+/// Everything written during a generation, which [`crate::plan::Writer`]
+/// keeps when it's recording.
+#[derive(Debug, Default)]
+pub struct Record {
+    entries: RefCell<BTreeMap<PathBuf, Kind>>,
+    covered: RefCell<Vec<PathBuf>>,
+}
+
+impl Record {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, path: &Path, kind: Kind) {
+        self.entries.borrow_mut().insert(path.to_owned(), kind);
+    }
+
+    /// Marks `dir` as having been entirely regenerated, so that whatever was
+    /// generated in it before but wasn't this time can be cleaned up. Projects
+    /// that weren't regenerated (i.e. Apple on Linux) are left alone.
+    pub fn cover(&self, dir: &Path) {
+        self.covered.borrow_mut().push(dir.to_owned());
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    ParseFailed {
+        path: PathBuf,
+        cause: serde_json::Error,
+    },
+    WriteFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    PromptFailed(io::Error),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ReadFailed { path, cause } => {
+                Report::error(format!("Failed to read {:?}", path), cause)
+            }
+            Self::ParseFailed { path, cause } => Report::error(
+                format!("Failed to parse {:?}", path),
+                format!("{}; run `cargo mobile regen` to record it again", cause),
+            ),
+            Self::WriteFailed { path, cause } => {
+                Report::error(format!("Failed to write {:?}", path), cause)
+            }
+            Self::PromptFailed(err) => Report::error("Failed to prompt for removal", err),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadFailed { .. } => "generated.read-failed",
+            Self::ParseFailed { .. } => "generated.parse-failed",
+            Self::WriteFailed { .. } => "generated.write-failed",
+            Self::PromptFailed(..) => "generated.prompt-failed",
+        }
+    }
+}
+
+/// Where the generated projects live, along with anything else that's ours to
+/// overwrite and what we keep track of them with.
+pub fn gen_dir(app: &App) -> PathBuf {
+    gen_dir_in(app.root_dir())
+}
+
+/// Like [`gen_dir`], for when all there is to go on is the app's root dir.
+pub fn gen_dir_in(root_dir: &Path) -> PathBuf {
+    root_dir.join("gen")
+}
+
+/// What was generated last, with paths relative to `gen/`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Manifest {
+    config_hash: String,
+    entries: BTreeMap<PathBuf, Kind>,
+    /// Generated at some point, but not anymore, and not removed yet.
+    #[serde(default)]
+    orphans: BTreeMap<PathBuf, Kind>,
+}
+
+// Whether `path` is one of `entries`, contains one of them, or is in a tree.
+fn owned_by(entries: &BTreeMap<PathBuf, Kind>, path: &Path) -> Option<Kind> {
+    entries.get(path).copied().or_else(|| {
+        entries.iter().find_map(|(entry, kind)| {
+            if entry.starts_with(path) {
+                Some(Kind::Dir)
+            } else if *kind == Kind::Tree && path.starts_with(entry) {
+                Some(Kind::Tree)
+            } else {
+                None
+            }
+        })
+    })
+}
+
+impl Manifest {
+    // `Ok(None)` if nothing's been recorded yet.
+    fn load(gen_dir: &Path) -> Result<Option<Self>, Error> {
+        let path = gen_dir.join(FILE_NAME);
+        match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|cause| Error::ParseFailed { path, cause }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(cause) => Err(Error::ReadFailed { path, cause }),
+        }
+    }
+
+    fn save(&self, gen_dir: &Path) -> Result<(), Error> {
+        let path = gen_dir.join(FILE_NAME);
+        let json =
+            serde_json::to_vec_pretty(self).expect("developer error: manifest not serializable");
+        fs::create_dir_all(gen_dir)
+            .and_then(|()| fs::write(&path, json))
+            .map_err(|cause| Error::WriteFailed { path, cause })
+    }
+
+    // Comes up with the new manifest, along with what's gone stale. Anything
+    // that wasn't regenerated just carries over.
+    fn update(
+        self,
+        gen_dir: &Path,
+        config_hash: String,
+        record: &Record,
+    ) -> (Self, Vec<(PathBuf, Kind)>) {
+        let relative = |path: &PathBuf| path.strip_prefix(gen_dir).ok().map(Path::to_owned);
+        let mut entries = record
+            .entries
+            .borrow()
+            .iter()
+            .filter_map(|(path, kind)| relative(path).map(|path| (path, *kind)))
+            .filter(|(path, _)| !path.as_os_str().is_empty())
+            .collect::<BTreeMap<_, _>>();
+        let covered = record
+            .covered
+            .borrow()
+            .iter()
+            .filter_map(relative)
+            .collect::<Vec<_>>();
+        let is_covered = |path: &Path| covered.iter().any(|dir| path.starts_with(dir));
+        let mut orphans = BTreeMap::new();
+        let mut stale = Vec::new();
+        for (path, kind, orphaned) in self
+            .entries
+            .into_iter()
+            .map(|(path, kind)| (path, kind, false))
+            .chain(
+                self.orphans
+                    .into_iter()
+                    .map(|(path, kind)| (path, kind, true)),
+            )
+        {
+            if owned_by(&entries, &path).is_some() {
+                continue;
+            }
+            if is_covered(&path) {
+                stale.push((path, kind));
+            } else if orphaned {
+                orphans.insert(path, kind);
+            } else {
+                entries.insert(path, kind);
+            }
+        }
+        (
+            Self {
+                config_hash,
+                entries,
+                orphans,
+            },
+            stale,
+        )
+    }
+}
+
+// Files and links go, trees go along with everything in them, and dirs only go
+// if they're empty, since anything left in them isn't ours. Returns whether
+// `path` is gone.
+fn remove(path: &Path, kind: Kind) -> io::Result<bool> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(err) => return Err(err),
+    };
+    if !metadata.is_dir() {
+        fs::remove_file(path)?;
+    } else if kind == Kind::Tree {
+        fs::remove_dir_all(path)?;
+    } else if fs::read_dir(path)?.next().is_none() {
+        fs::remove_dir(path)?;
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Deepest first, so that dirs are emptied before they're removed. Whatever
+// can't be removed is returned.
+fn remove_all(gen_dir: &Path, mut paths: Vec<(PathBuf, Kind)>) -> Vec<(PathBuf, Kind)> {
+    paths.sort_by(|(a, _), (b, _)| b.cmp(a));
+    paths
+        .into_iter()
+        .filter(|(path, kind)| {
+            let full = gen_dir.join(path);
+            match remove(&full, *kind) {
+                Ok(removed) => !removed,
+                Err(err) => {
+                    log::warn!("failed to remove {:?}: {}", full, err);
+                    true
+                }
+            }
+        })
+        .collect()
+}
+
+/// Records what was just generated, and removes what was generated last time
+/// in the same projects but wasn't this time. Failing to do either only gets
+/// a warning, since the projects were generated just fine.
+pub fn record(config: &Config, written: &Record) {
+    let gen_dir = gen_dir(config.app());
+    let previous = match Manifest::load(&gen_dir) {
+        Ok(previous) => previous.unwrap_or_default(),
+        Err(err) => {
+            log::warn!("{}", err.report().details());
+            Manifest::default()
+        }
+    };
+    let (mut manifest, stale) = previous.update(&gen_dir, regen::hash(config), written);
+    if !stale.is_empty() {
+        let count = stale.len();
+        let left = remove_all(&gen_dir, stale);
+        ui::status(format!(
+            "Removed {} generated file{} that aren't generated anymore",
+            count - left.len(),
+            if count - left.len() == 1 { "" } else { "s" }
+        ));
+        manifest.orphans.extend(left);
+    }
+    if let Err(err) = manifest.save(&gen_dir) {
+        log::warn!(
+            "failed to record generated files: {}",
+            err.report().details()
+        );
+    }
+}
+
+#[derive(Debug, Default)]
+struct Scan {
+    orphans: Vec<(PathBuf, Kind)>,
+    unmanaged: Vec<PathBuf>,
+}
+
+fn scan_dir(manifest: &Manifest, gen_dir: &Path, dir: &Path, scan: &mut Scan) -> io::Result<()> {
+    let mut entries = fs::read_dir(gen_dir.join(dir))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for name in entries {
+        if tool_owned(&name.to_string_lossy()) {
+            continue;
+        }
+        let path = dir.join(&name);
+        let is_dir = fs::symlink_metadata(gen_dir.join(&path))?.is_dir();
+        match owned_by(&manifest.entries, &path) {
+            Some(Kind::Dir) if is_dir => scan_dir(manifest, gen_dir, &path, scan)?,
+            Some(_) => (),
+            None => match owned_by(&manifest.orphans, &path) {
+                Some(kind) => {
+                    // There might be things the user added inside.
+                    if kind == Kind::Dir && is_dir {
+                        scan_dir(manifest, gen_dir, &path, scan)?;
+                    }
+                    scan.orphans.push((path, kind));
+                }
+                None => scan.unmanaged.push(path),
+            },
+        }
+    }
+    Ok(())
+}
+
+fn print_paths(gen_dir: &Path, paths: impl Iterator<Item = PathBuf>) {
+    for path in paths {
+        println!("    {}", gen_dir.join(path).display());
+    }
+}
+
+/// Lists what's in `gen/` that isn't generated anymore, and removes it if
+/// the user says so. Anything that was never generated is listed too, but is
+/// never removed.
+pub fn gc(config: &Config, non_interactive: opts::NonInteractive) -> Result<(), Error> {
+    let gen_dir = gen_dir(config.app());
+    let mut manifest = match Manifest::load(&gen_dir)? {
+        Some(manifest) => manifest,
+        None => {
+            println!("Nothing's been recorded as generated yet; run `cargo mobile regen` first.");
+            return Ok(());
+        }
+    };
+    if manifest.config_hash != regen::hash(config) {
+        println!("Your config changed since your projects were last generated, so what's listed might be out of date; run `cargo mobile regen` first to be sure.");
+    }
+    let mut scan = Scan::default();
+    scan_dir(&manifest, &gen_dir, Path::new(""), &mut scan).map_err(|cause| Error::ReadFailed {
+        path: gen_dir.clone(),
+        cause,
+    })?;
+    if !scan.unmanaged.is_empty() {
+        println!("These weren't generated, so they're yours, and will never be removed; since they're in `gen/`, you're living dangerously:");
+        print_paths(&gen_dir, scan.unmanaged.into_iter());
+    }
+    if scan.orphans.is_empty() {
+        println!("There's nothing that isn't generated anymore.");
+        return Ok(());
+    }
+    println!("These were generated before, but aren't anymore:");
+    print_paths(&gen_dir, scan.orphans.iter().map(|(path, _)| path.clone()));
+    if non_interactive.yes() {
+        println!("Run `cargo mobile gc` interactively to remove them.");
+        return Ok(());
+    }
+    let answer = loop {
+        if let Some(answer) = prompt::yes_no("Remove them?", Some(prompt::YesOrNo::No))
+            .map_err(Error::PromptFailed)?
+        {
+            break answer;
+        }
+    };
+    if answer.no() {
+        return Ok(());
+    }
+    let left = remove_all(&gen_dir, scan.orphans);
+    for (path, _) in &left {
+        println!(
+            "Left {:?}, since there's something else in it",
+            gen_dir.join(path)
+        );
+    }
+    // Orphans that are already gone don't need tracking anymore.
+    manifest.orphans = left.into_iter().collect();
+    manifest.save(&gen_dir)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entries(entries: &[(&str, Kind)]) -> BTreeMap<PathBuf, Kind> {
+        entries
+            .iter()
+            .map(|(path, kind)| (PathBuf::from(path), *kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_owned_by() {
+        let entries = entries(&[
+            ("apple/project.yml", Kind::File),
+            ("apple/Foo.xcodeproj", Kind::Tree),
+        ]);
+        assert_eq!(owned_by(&entries, Path::new("apple")), Some(Kind::Dir));
+        assert_eq!(
+            owned_by(&entries, Path::new("apple/project.yml")),
+            Some(Kind::File)
+        );
+        assert_eq!(
+            owned_by(&entries, Path::new("apple/Foo.xcodeproj/project.pbxproj")),
+            Some(Kind::Tree)
+        );
+        assert_eq!(owned_by(&entries, Path::new("apple/notes.txt")), None);
+    }
+
+    #[test]
+    fn test_update() {
+        let gen_dir = Path::new("/project/gen");
+        let previous = Manifest {
+            config_hash: "old".to_owned(),
+            entries: entries(&[
+                ("apple/project.yml", Kind::File),
+                ("apple/Old_iOS", Kind::Dir),
+                ("apple/Old_iOS/main.mm", Kind::File),
+                ("android/app/build.gradle", Kind::File),
+            ]),
+            orphans: entries(&[("android/old.gradle", Kind::File)]),
+        };
+        let record = Record::new();
+        record.cover(&gen_dir.join("apple"));
+        record.insert(&gen_dir.join("apple/project.yml"), Kind::File);
+        record.insert(&gen_dir.join("apple/New_iOS/main.mm"), Kind::File);
+        record.insert(Path::new("/project/Cargo.toml"), Kind::File);
+        let (manifest, stale) = previous.update(gen_dir, "new".to_owned(), &record);
+        assert_eq!(
+            stale,
+            vec![
+                (PathBuf::from("apple/Old_iOS"), Kind::Dir),
+                (PathBuf::from("apple/Old_iOS/main.mm"), Kind::File),
+            ]
+        );
+        // Android wasn't regenerated, so it's left as it was.
+        assert_eq!(
+            manifest.entries,
+            entries(&[
+                ("android/app/build.gradle", Kind::File),
+                ("apple/New_iOS/main.mm", Kind::File),
+                ("apple/project.yml", Kind::File),
+            ])
+        );
+        assert_eq!(
+            manifest.orphans,
+            entries(&[("android/old.gradle", Kind::File)])
+        );
+        assert_eq!(manifest.config_hash, "new");
+    }
+}
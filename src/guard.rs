@@ -0,0 +1,240 @@
+//! Keeping `init` from clobbering files it didn't make. Everything in `gen`
+//! belongs to us, but the rest of the app (its `Cargo.toml`, `src`, cargo
+//! config, and so on) might've been written or changed by hand since, so a
+//! file that's already there and differs from what we'd write is only
+//! replaced if you say so.
+//!
+//! Dry runs go straight through, since the plan already shows the diff and
+//! nothing gets written anyway.
+
+use crate::{
+    generated, opts,
+    plan::{self, Writer},
+    ui,
+    util::prompt,
+};
+use colored::Color;
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+static ALONGSIDE_EXT: &str = ".cargo-mobile.new";
+
+/// What to do about a file that differs from what would be written.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    Ask,
+    Keep,
+    Overwrite,
+}
+
+impl Policy {
+    /// `--force` always overwrites, and otherwise files are kept unless
+    /// there's someone to ask.
+    pub fn new(non_interactive: opts::NonInteractive, force: opts::Force) -> Self {
+        if force.yes() {
+            Self::Overwrite
+        } else if non_interactive.yes() {
+            Self::Keep
+        } else {
+            Self::Ask
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Action {
+    Write,
+    Keep,
+    Alongside,
+}
+
+// Writing something that's identical is harmless, and leaves it recorded as
+// generated.
+fn decide(
+    current: Option<&[u8]>,
+    planned: &[u8],
+    policy: Policy,
+    ask: impl FnOnce() -> io::Result<Action>,
+) -> io::Result<Action> {
+    match current {
+        Some(current) if current != planned => match policy {
+            Policy::Ask => ask(),
+            Policy::Keep => Ok(Action::Keep),
+            Policy::Overwrite => Ok(Action::Write),
+        },
+        _ => Ok(Action::Write),
+    }
+}
+
+fn alongside(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(ALONGSIDE_EXT);
+    path.with_file_name(name)
+}
+
+#[derive(Debug)]
+pub struct Guard {
+    root_dir: PathBuf,
+    gen_dir: PathBuf,
+    policy: Policy,
+}
+
+impl Guard {
+    pub fn new(root_dir: &Path, policy: Policy) -> Self {
+        Self {
+            root_dir: root_dir.to_owned(),
+            gen_dir: generated::gen_dir_in(root_dir),
+            policy,
+        }
+    }
+
+    fn display<'a>(&self, path: &'a Path) -> std::path::Display<'a> {
+        path.strip_prefix(&self.root_dir).unwrap_or(path).display()
+    }
+
+    fn ask(&self, path: &Path, current: &[u8], planned: &[u8]) -> io::Result<Action> {
+        let name = self.display(path).to_string();
+        println!(
+            "{} already exists, and differs from what would be written:",
+            name
+        );
+        plan::print_diff(&name, current, planned);
+        loop {
+            let response = prompt::default(
+                format!(
+                    "[k]eep it, [o]verwrite it, or write [n]ext to it as {}{}?",
+                    name, ALONGSIDE_EXT
+                ),
+                Some("k"),
+                Some(Color::Green),
+            )?;
+            match response.to_ascii_lowercase().as_str() {
+                "k" | "keep" => break Ok(Action::Keep),
+                "o" | "overwrite" => break Ok(Action::Write),
+                "n" | "next" => break Ok(Action::Alongside),
+                _ => println!("That wasn't a K, an O, or an N!"),
+            }
+        }
+    }
+
+    // Where to write instead of `path`, or `None` if it's being kept.
+    fn resolve(&self, writer: Writer, path: &Path, planned: &[u8]) -> io::Result<Option<PathBuf>> {
+        if writer.dry_run() || path.starts_with(&self.gen_dir) || !path.is_file() {
+            return Ok(Some(path.to_owned()));
+        }
+        let current = fs::read(path)?;
+        let action = decide(Some(&current), planned, self.policy, || {
+            self.ask(path, &current, planned)
+        })?;
+        Ok(match action {
+            Action::Write => Some(path.to_owned()),
+            Action::Keep => {
+                if self.policy == Policy::Keep {
+                    ui::status(format!(
+                        "Left {} alone, since it differs from what would be written; pass `--force` to overwrite it",
+                        self.display(path)
+                    ));
+                }
+                None
+            }
+            Action::Alongside => Some(alongside(path)),
+        })
+    }
+
+    /// Like [`Writer::write`], but checks with the policy first if there's
+    /// already something different at `path`.
+    pub fn write(&self, writer: Writer, path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        match self.resolve(writer, path, contents.as_ref())? {
+            Some(dest) => writer.write(&dest, contents),
+            None => Ok(()),
+        }
+    }
+
+    /// Like [`Writer::copy`], but checks with the policy first if there's
+    /// already something different at `dest`.
+    pub fn copy(&self, writer: Writer, src: &Path, dest: &Path) -> io::Result<()> {
+        // Only worth reading if there's something to compare it to.
+        let planned = if writer.dry_run() || !dest.is_file() {
+            Vec::new()
+        } else {
+            fs::read(src)?
+        };
+        match self.resolve(writer, dest, &planned)? {
+            Some(dest) => writer.copy(src, &dest),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+    use rstest::rstest;
+
+    #[rstest(
+        current,
+        policy,
+        expected,
+        case(None, Policy::Keep, Action::Write),
+        case(Some("same"), Policy::Ask, Action::Write),
+        case(Some("same"), Policy::Keep, Action::Write),
+        case(Some("mine"), Policy::Keep, Action::Keep),
+        case(Some("mine"), Policy::Overwrite, Action::Write)
+    )]
+    fn test_decide_without_asking(current: Option<&str>, policy: Policy, expected: Action) {
+        let action = decide(current.map(str::as_bytes), b"same", policy, || {
+            panic!("asked about {:?} under {:?}", current, policy)
+        })
+        .unwrap();
+        assert_eq!(action, expected);
+    }
+
+    #[rstest(
+        answer,
+        case(Action::Keep),
+        case(Action::Write),
+        case(Action::Alongside)
+    )]
+    fn test_decide_asks_when_different(answer: Action) {
+        let action = decide(Some(b"mine"), b"ours", Policy::Ask, || Ok(answer)).unwrap();
+        assert_eq!(action, answer);
+    }
+
+    #[test]
+    fn test_write_keeps_what_differs() {
+        let temp_dir = TempDir::new("guard-test").unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("gen")).unwrap();
+        for (path, contents) in &[
+            ("Cargo.toml", "mine"),
+            ("same.rs", "ours"),
+            ("gen/build.gradle", "stale"),
+        ] {
+            fs::write(root.join(path), contents).unwrap();
+        }
+        let guard = Guard::new(root, Policy::Keep);
+        for path in &["Cargo.toml", "same.rs", "new.rs", "gen/build.gradle"] {
+            guard.write(Writer::Disk, &root.join(path), "ours").unwrap();
+        }
+        let read = |path: &str| fs::read_to_string(root.join(path)).unwrap();
+        let contents = ["Cargo.toml", "same.rs", "new.rs", "gen/build.gradle"]
+            .iter()
+            .map(|path| read(path))
+            .collect::<Vec<_>>();
+        let alongside = root.join("Cargo.toml.cargo-mobile.new").exists();
+        assert_eq!(contents, vec!["mine", "ours", "ours", "ours"]);
+        assert!(!alongside);
+    }
+
+    #[test]
+    fn test_alongside() {
+        assert_eq!(
+            alongside(Path::new("/app/Cargo.toml")),
+            Path::new("/app/Cargo.toml.cargo-mobile.new")
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! User-configured commands that run around builds and runs, i.e. for
+//! compiling shaders beforehand or reporting binary sizes afterwards.
+
+use crate::{
+    env::{BuildEnv, ExplicitEnv as _},
+    opts::{NoHooks, Profile},
+    ui,
+    util::cli::{Report, Reportable},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    io::{self, Write as _},
+    path::Path,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stage {
+    PreBuild,
+    PostBuild,
+    PreRun,
+    PostRun,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PreBuild => "pre-build",
+            Self::PostBuild => "post-build",
+            Self::PreRun => "pre-run",
+            Self::PostRun => "post-run",
+        }
+    }
+
+    pub fn post(self) -> bool {
+        matches!(self, Self::PostBuild | Self::PostRun)
+    }
+}
+
+/// What to do when a post hook fails. Pre hooks always abort, since whatever
+/// they were meant to produce is missing.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PostFailure {
+    Warn,
+    Error,
+}
+
+impl Default for PostFailure {
+    fn default() -> Self {
+        Self::Warn
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Raw {
+    pre_build: Option<Vec<String>>,
+    post_build: Option<Vec<String>>,
+    pre_run: Option<Vec<String>>,
+    post_run: Option<Vec<String>>,
+    post_failure: Option<PostFailure>,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    stage: Stage,
+    command: String,
+    cause: bossy::Error,
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        let msg = format!("`{}` hook `{}` failed", self.stage, self.command);
+        match self.cause.output() {
+            Some(output) => Report::error(
+                msg,
+                format!(
+                    "{}\n{}{}",
+                    self.cause,
+                    String::from_utf8_lossy(output.stdout()),
+                    String::from_utf8_lossy(output.stderr())
+                )
+                .trim_end(),
+            ),
+            None => Report::error(msg, &self.cause),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        "hooks.failed"
+    }
+}
+
+static NONE: Hooks = Hooks {
+    pre_build: Vec::new(),
+    post_build: Vec::new(),
+    pre_run: Vec::new(),
+    post_run: Vec::new(),
+    post_failure: PostFailure::Warn,
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct Hooks {
+    pre_build: Vec<String>,
+    post_build: Vec<String>,
+    pre_run: Vec<String>,
+    post_run: Vec<String>,
+    post_failure: PostFailure,
+}
+
+impl Hooks {
+    pub fn from_raw(raw: Option<Raw>) -> Self {
+        let raw = raw.unwrap_or_default();
+        Self {
+            pre_build: raw.pre_build.unwrap_or_default(),
+            post_build: raw.post_build.unwrap_or_default(),
+            pre_run: raw.pre_run.unwrap_or_default(),
+            post_run: raw.post_run.unwrap_or_default(),
+            post_failure: raw.post_failure.unwrap_or_default(),
+        }
+    }
+
+    /// These hooks, or none at all if the user passed `--no-hooks`.
+    pub fn unless(&self, no_hooks: NoHooks) -> &Self {
+        if no_hooks.yes() {
+            &NONE
+        } else {
+            self
+        }
+    }
+
+    pub fn commands(&self, stage: Stage) -> &[String] {
+        match stage {
+            Stage::PreBuild => &self.pre_build,
+            Stage::PostBuild => &self.post_build,
+            Stage::PreRun => &self.pre_run,
+            Stage::PostRun => &self.post_run,
+        }
+    }
+
+    pub fn has(&self, stage: Stage) -> bool {
+        !self.commands(stage).is_empty()
+    }
+
+    /// Runs each hook for `stage` through `sh` from `root`, stopping at the
+    /// first failure. Hooks see the build env along with `CARGO_MOBILE_TARGET`
+    /// and `CARGO_MOBILE_PROFILE`, plus `CARGO_MOBILE_ARTIFACT` if there's an
+    /// artifact to speak of. Post hook failures only get warned about unless
+    /// the config says otherwise.
+    pub fn run(
+        &self,
+        stage: Stage,
+        root: &Path,
+        env: &BuildEnv,
+        target: &str,
+        profile: Profile,
+        artifact: Option<&Path>,
+    ) -> Result<(), Error> {
+        let commands = self.commands(stage);
+        if commands.is_empty() {
+            return Ok(());
+        }
+        let mut env = env
+            .clone()
+            .with_var("CARGO_MOBILE_TARGET", target)
+            .with_var("CARGO_MOBILE_PROFILE", profile.as_str());
+        if let Some(artifact) = artifact {
+            env.insert("CARGO_MOBILE_ARTIFACT", artifact);
+        }
+        for command in commands {
+            ui::status(format!("Running {} hook `{}`...", stage, command));
+            // The user's env is passed through, since hooks are their own
+            // scripts. Output is captured so that it can go in the report if
+            // the hook fails, and passed along as-is otherwise.
+            let result = bossy::Command::impure("sh")
+                .with_env_vars(env.explicit_env())
+                .with_current_dir(root)
+                .with_args(&["-c", command])
+                .run_and_wait_for_output();
+            match result {
+                Ok(output) => {
+                    let _ = io::stdout().write_all(output.stdout());
+                    let _ = io::stderr().write_all(output.stderr());
+                }
+                Err(cause) => {
+                    let err = Error {
+                        stage,
+                        command: command.clone(),
+                        cause,
+                    };
+                    if stage.post() && self.post_failure == PostFailure::Warn {
+                        let report = err.report();
+                        log::warn!("{}: {}", report.msg(), report.details());
+                    } else {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_no_hooks_skips_everything() {
+        let hooks = Hooks::from_raw(Some(Raw {
+            pre_build: Some(vec!["false".to_owned()]),
+            ..Default::default()
+        }));
+        assert!(hooks.has(Stage::PreBuild));
+        assert!(hooks.unless(NoHooks::No).has(Stage::PreBuild));
+        assert!(!hooks.unless(NoHooks::Yes).has(Stage::PreBuild));
+    }
+}
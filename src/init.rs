@@ -4,14 +4,23 @@ use crate::apple;
 use crate::{
     config::{
         self,
+        app::Platform,
         metadata::{self, Metadata},
         Config,
     },
-    dot_cargo, opts, project, templating,
+    doctor::{self, SectionName},
+    dot_cargo,
+    generated::{self, Record},
+    guard::{self, Guard},
+    opts,
+    plan::Writer,
+    project, regen, templating, ui, upgrade,
     util::{
         self,
         cli::{Report, Reportable, TextWrapper},
+        progress::{self, Phase},
     },
+    version::{self, Version},
 };
 use std::{
     fs, io,
@@ -20,7 +29,7 @@ use std::{
 
 pub static DOT_FIRST_INIT_FILE_NAME: &str = ".first-init";
 static DOT_FIRST_INIT_CONTENTS: &str = // newline
-r#"The presence of this file indicates `cargo mobile init` has been called for
+    r#"The presence of this file indicates `cargo mobile init` has been called for
 the first time on a new project, but hasn't yet completed successfully once. As
 long as this file is here, `cargo mobile init` will use a more aggressive
 template generation strategy that allows it to place files that it wouldn't
@@ -53,6 +62,7 @@ pub enum Error {
     DotCargoLoadFailed(dot_cargo::LoadError),
     HostTargetTripleDetectionFailed(util::HostTargetTripleError),
     MetadataFailed(metadata::Error),
+    VersionFailed(version::Error),
     #[cfg(target_os = "macos")]
     AppleInitFailed(apple::project::Error),
     AndroidEnvFailed(android::env::Error),
@@ -62,6 +72,7 @@ pub enum Error {
         path: PathBuf,
         cause: io::Error,
     },
+    PostInitHooksFailed(templating::PostInitError),
     OpenInEditorFailed(util::OpenInEditorError),
 }
 
@@ -78,15 +89,41 @@ impl Reportable for Error {
             Self::DotCargoLoadFailed(err) => err.report(),
             Self::HostTargetTripleDetectionFailed(err) => err.report(),
             Self::MetadataFailed(err) => err.report(),
+            Self::VersionFailed(err) => err.report(),
             Self::AndroidEnvFailed(err) => err.report(),
             Self::AndroidInitFailed(err) => err.report(),
             #[cfg(target_os = "macos")]
             Self::AppleInitFailed(err) => err.report(),
             Self::DotCargoWriteFailed(err) => err.report(),
             Self::DotFirstInitDeleteFailed { path, cause } => Report::action_request(format!("Failed to delete first init dot file {:?}; the project generated successfully, but `cargo mobile init` will have unexpected results unless you manually delete this file!", path), cause),
+            Self::PostInitHooksFailed(err) => Report::error("Failed to run post-init hooks", err),
             Self::OpenInEditorFailed(err) => Report::error("Failed to open project in editor (your project generated successfully though, so no worries!)", err),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ConfigLoadOrGenFailed(err) => err.code(),
+            Self::DotFirstInitWriteFailed { .. } => "init.dot-first-init-write-failed",
+            Self::FilterConfigureFailed(..) => "init.filter-configure-failed",
+            Self::ProjectInitFailed(err) => err.code(),
+            Self::AssetDirCreationFailed { .. } => "init.asset-dir-creation-failed",
+            Self::CodeCommandPresentFailed(..) => "init.code-command-present-failed",
+            Self::LldbExtensionInstallFailed(..) => "init.lldb-extension-install-failed",
+            Self::DotCargoLoadFailed(err) => err.code(),
+            Self::HostTargetTripleDetectionFailed(err) => err.code(),
+            Self::MetadataFailed(err) => err.code(),
+            Self::VersionFailed(err) => err.code(),
+            Self::AndroidEnvFailed(err) => err.code(),
+            Self::AndroidInitFailed(err) => err.code(),
+            #[cfg(target_os = "macos")]
+            Self::AppleInitFailed(err) => err.code(),
+            Self::DotCargoWriteFailed(err) => err.code(),
+            Self::DotFirstInitDeleteFailed { .. } => "init.dot-first-init-delete-failed",
+            Self::PostInitHooksFailed(..) => "init.post-init-hooks-failed",
+            Self::OpenInEditorFailed(..) => "init.open-in-editor-failed",
+        }
+    }
 }
 
 pub fn exec(
@@ -95,40 +132,102 @@ pub fn exec(
     skip_dev_tools: opts::SkipDevTools,
     reinstall_deps: opts::ReinstallDeps,
     open_in_editor: opts::OpenInEditor,
+    no_git: opts::NoGit,
+    force: opts::Force,
     submodule_commit: Option<String>,
+    name: Option<String>,
+    domain: Option<String>,
+    development_team: Option<String>,
+    template_pack: Option<String>,
+    platforms: &[Platform],
+    template_vars: &[(String, String)],
+    post_init: templating::PostInitPolicy,
+    writer: Writer,
     cwd: impl AsRef<Path>,
 ) -> Result<Config, Error> {
     let cwd = cwd.as_ref();
-    let (config, config_origin) =
-        Config::load_or_gen(cwd, non_interactive, wrapper).map_err(Error::ConfigLoadOrGenFailed)?;
+    let record = Record::new();
+    let writer = writer.recording(&record);
+    let (config, config_origin) = progress::phase(Phase::Config, None, || {
+        Config::load_or_gen(
+            cwd,
+            non_interactive,
+            name.as_deref(),
+            domain.as_deref(),
+            development_team.as_deref(),
+            template_pack.as_deref(),
+            Some(platforms).filter(|platforms| !platforms.is_empty()),
+            writer,
+            wrapper,
+        )
+    })
+    .map_err(Error::ConfigLoadOrGenFailed)?;
     let dot_first_init_path = config.app().root_dir().join(DOT_FIRST_INIT_FILE_NAME);
     let dot_first_init_exists = {
         let dot_first_init_exists = dot_first_init_path.exists();
         if config_origin.freshly_minted() && !dot_first_init_exists {
             // indicate first init is ongoing, so that if we error out and exit
-            // the next init will know to still use `WildWest` filtering
-            log::info!("creating first init dot file at {:?}", dot_first_init_path);
-            fs::write(&dot_first_init_path, DOT_FIRST_INIT_CONTENTS).map_err(|cause| {
-                Error::DotFirstInitWriteFailed {
-                    path: dot_first_init_path.clone(),
-                    cause,
-                }
-            })?;
+            // the next init will know to still use `WildWest` filtering; a
+            // dry run doesn't need it, since it'd be deleted by the end anyway
+            if !writer.dry_run() {
+                log::info!("creating first init dot file at {:?}", dot_first_init_path);
+                fs::write(&dot_first_init_path, DOT_FIRST_INIT_CONTENTS).map_err(|cause| {
+                    Error::DotFirstInitWriteFailed {
+                        path: dot_first_init_path.clone(),
+                        cause,
+                    }
+                })?;
+            }
             true
         } else {
             dot_first_init_exists
         }
     };
-    let bike = config.build_a_bike();
+    // A first init is usually on a machine that's never built anything, so
+    // everything that's missing is pointed out up front.
+    if dot_first_init_exists && !writer.dry_run() {
+        let sections = std::iter::once(SectionName::General)
+            .chain(
+                config
+                    .app()
+                    .platforms()
+                    .iter()
+                    .map(|platform| match platform {
+                        Platform::Android => SectionName::Android,
+                        Platform::Apple => SectionName::Apple,
+                    }),
+            )
+            .collect::<Vec<_>>();
+        doctor::suggest_setup(wrapper, &sections);
+    }
+    let bike = config.build_a_bike(template_vars);
     let filter = templating::Filter::new(&config, config_origin, dot_first_init_exists)
         .map_err(Error::FilterConfigureFailed)?;
+    // The filter lets a first init write anywhere, so this is what keeps it
+    // from replacing files that were there before it.
+    let guard = Guard::new(
+        config.app().root_dir(),
+        guard::Policy::new(non_interactive, force),
+    );
 
     // Generate the base project
-    project::gen(&config, &bike, &filter, submodule_commit).map_err(Error::ProjectInitFailed)?;
+    let hooks = progress::phase(Phase::Project, None, || {
+        project::gen(
+            &config,
+            &bike,
+            &filter,
+            &guard,
+            no_git,
+            submodule_commit,
+            writer,
+        )
+    })
+    .map_err(Error::ProjectInitFailed)?;
 
     let asset_dir = config.app().asset_dir();
     if !asset_dir.is_dir() {
-        fs::create_dir_all(&asset_dir)
+        writer
+            .create_dir_all(&asset_dir)
             .map_err(|cause| Error::AssetDirCreationFailed { asset_dir, cause })?;
     }
     if skip_dev_tools.no()
@@ -139,12 +238,14 @@ pub fn exec(
         if non_interactive.yes() {
             command.add_arg("--force");
         }
-        command
-            .run_and_wait()
+        writer
+            .run("install the CodeLLDB extension for VS Code", || {
+                progress::phase(Phase::DevTools, None, || progress::run_and_wait(command))
+            })
             .map_err(Error::LldbExtensionInstallFailed)?;
     }
     let mut dot_cargo =
-        dot_cargo::DotCargo::load(config.app()).map_err(Error::DotCargoLoadFailed)?;
+        dot_cargo::DotCargo::load(config.app(), writer).map_err(Error::DotCargoLoadFailed)?;
     // Mysteriously, builds that don't specify `--target` seem to fight over
     // the build cache with builds that use `--target`! This means that
     // alternating between i.e. `cargo run` and `cargo apple run` would
@@ -159,55 +260,111 @@ pub fn exec(
         util::host_target_triple().map_err(Error::HostTargetTripleDetectionFailed)?,
     );
 
-    let metadata = Metadata::load(&config.app().root_dir()).map_err(Error::MetadataFailed)?;
+    // This has to wait until the app's `Cargo.toml` has been generated, which
+    // during a dry run might've only been planned.
+    let manifest_path = config.app().manifest_path();
+    let planned_manifest = match writer {
+        Writer::DryRun(plan) => plan
+            .contents(&manifest_path)
+            .filter(|planned| fs::read(&manifest_path).ok().as_ref() != Some(planned)),
+        Writer::Disk | Writer::Recording(_) => None,
+    };
+    let metadata = match &planned_manifest {
+        Some(manifest) => Metadata::from_slice(manifest_path, manifest),
+        None => Metadata::load(&config.app().root_dir()),
+    }
+    .map_err(Error::MetadataFailed)?;
+    let version = match &planned_manifest {
+        Some(manifest) => Version::resolve_planned(config.app(), manifest),
+        None => Version::resolve(config.app()),
+    }
+    .map_err(Error::VersionFailed)?;
 
     // Generate Xcode project
     #[cfg(target_os = "macos")]
-    if metadata.apple().supported() {
-        apple::project::gen(
-            config.apple(),
-            metadata.apple(),
-            config.app().template_pack().submodule_path(),
-            &bike,
-            wrapper,
-            non_interactive,
-            skip_dev_tools,
-            reinstall_deps,
-            &filter,
-        )
+    if !config.app().selects(Platform::Apple) {
+        ui::status("Skipping iOS init, since Apple isn't one of the selected platforms");
+    } else if metadata.apple().supported() {
+        progress::phase(Phase::AppleProject, None, || {
+            apple::project::gen(
+                config.apple(),
+                metadata.apple(),
+                &version,
+                config.app().template_pack().submodule_path(),
+                &bike,
+                wrapper,
+                non_interactive,
+                skip_dev_tools,
+                reinstall_deps,
+                &filter,
+                writer,
+            )
+        })
         .map_err(Error::AppleInitFailed)?;
+        record.cover(&config.apple().project_dir());
     } else {
-        println!("Skipping iOS init, since it's marked as unsupported in your Cargo.toml metadata");
+        ui::status(
+            "Skipping iOS init, since it's marked as unsupported in your Cargo.toml metadata",
+        );
     }
 
     // Generate Android Studio project
-    if metadata.android().supported() {
+    if !config.app().selects(Platform::Android) {
+        ui::status("Skipping Android init, since Android isn't one of the selected platforms");
+    } else if metadata.android().supported() {
         match android::env::Env::new() {
-            Ok(env) => {
-                android::project::gen(config.android(), &env, &bike, &filter, &mut dot_cargo)
-                    .map_err(Error::AndroidInitFailed)?
-            }
+            Ok(env) => progress::phase(Phase::AndroidProject, None, || {
+                android::project::gen(
+                    config.android(),
+                    &env,
+                    &version,
+                    &bike,
+                    &filter,
+                    &mut dot_cargo,
+                    writer,
+                )
+            })
+            .map_err(Error::AndroidInitFailed)
+            .map(|()| record.cover(&config.android().project_dir()))?,
             Err(err) => {
                 if err.sdk_or_ndk_issue() {
-                    Report::action_request(
+                    ui::report(&Report::action_request(
                         "Failed to initialize Android environment; Android support won't be usable until you fix the issue below and re-run `cargo mobile init`!",
                         err,
-                    )
-                    .print(wrapper);
+                    ));
                 } else {
                     Err(Error::AndroidEnvFailed(err))?;
                 }
             }
         }
     } else {
-        println!(
-            "Skipping Android init, since it's marked as unsupported in your Cargo.toml metadata"
+        ui::status(
+            "Skipping Android init, since it's marked as unsupported in your Cargo.toml metadata",
         );
     }
 
-    dot_cargo
-        .write(config.app())
-        .map_err(Error::DotCargoWriteFailed)?;
+    for conflict in dot_cargo
+        .write(config.app(), Some(&guard), writer)
+        .map_err(Error::DotCargoWriteFailed)?
+    {
+        log::warn!("left your `.cargo/config.toml` alone: {}", conflict);
+    }
+    progress::phase(Phase::PostInitHooks, None, || {
+        templating::run_post_init_hooks(
+            wrapper,
+            &hooks,
+            &config.app().root_dir(),
+            config.app().platforms(),
+            post_init,
+            non_interactive,
+            writer,
+        )
+    })
+    .map_err(Error::PostInitHooksFailed)?;
+    // The rest is bookkeeping for the next run, which a dry run doesn't need.
+    if writer.dry_run() {
+        return Ok(config);
+    }
     if dot_first_init_exists {
         log::info!("deleting first init dot file at {:?}", dot_first_init_path);
         fs::remove_file(&dot_first_init_path).map_err(|cause| Error::DotFirstInitDeleteFailed {
@@ -215,11 +372,13 @@ pub fn exec(
             cause,
         })?;
     }
-    Report::victory(
+    regen::record(&config);
+    generated::record(&config, &record);
+    upgrade::record(&config, &metadata, &version, &bike);
+    ui::report(&Report::victory(
         "Project generated successfully!",
         "Make cool apps! 🌻 🐕 🎉",
-    )
-    .print(wrapper);
+    ));
     if open_in_editor.yes() {
         util::open_in_editor(cwd).map_err(Error::OpenInEditorFailed)?;
     }
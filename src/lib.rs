@@ -1,19 +1,46 @@
 #![deny(unsafe_code)]
 
 pub mod android;
+pub mod api;
+pub mod assets;
 #[cfg(target_os = "macos")]
 pub mod apple;
 pub mod config;
+pub mod crash;
 pub mod device;
+pub mod doctor;
 mod dot_cargo;
+pub mod entry_point;
 pub mod env;
+pub mod errors;
+pub mod features;
+mod fingerprint;
+pub mod generated;
+pub mod guard;
+pub mod hooks;
 pub mod init;
+pub mod new;
 pub mod opts;
 pub mod os;
+pub mod plan;
+pub mod plugin;
+pub mod preflight;
 mod project;
+pub mod regen;
+pub mod resources;
+pub mod setup;
+pub mod sizes;
+pub mod stamp;
 pub mod target;
-mod templating;
+pub mod templating;
+pub mod transfer;
+pub mod ui;
 pub mod update;
+pub mod upgrade;
 pub mod util;
+pub mod version;
+pub mod vms;
+pub mod watch;
+pub mod workspace;
 
 pub static NAME: &str = "mobile";
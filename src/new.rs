@@ -0,0 +1,237 @@
+//! `cargo mobile new` makes a directory for a new app and runs `init` in it,
+//! so that the crate itself comes from the template pack along with
+//! everything else.
+
+use crate::{
+    config::{
+        app::{domain, name, Platform},
+        Source,
+    },
+    init, opts,
+    plan::Writer,
+    templating::PostInitPolicy,
+    ui,
+    util::cli::{Report, Reportable, TextWrapper},
+};
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// These are left out when listing what was created, since there's nothing
+// interesting in them.
+static UNLISTED_DIRS: &[&str] = &[".git", "target"];
+
+#[derive(Debug)]
+pub enum Error {
+    DirNameInvalid(PathBuf),
+    NameInvalid(name::Invalid),
+    DomainInvalid {
+        domain: String,
+        cause: domain::DomainError,
+    },
+    DirNotEmpty(PathBuf),
+    DirCreationFailed {
+        dir: PathBuf,
+        cause: io::Error,
+    },
+    InsideProject {
+        dir: PathBuf,
+        source: Source,
+    },
+    DirReadFailed {
+        dir: PathBuf,
+        cause: io::Error,
+    },
+    InitFailed(init::Error),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::DirNameInvalid(dir) => Report::error(
+                format!("Can't derive an app name from {:?}", dir),
+                "Pass `--name` to choose one",
+            ),
+            Self::NameInvalid(err) => Report::error("App name invalid", err),
+            Self::DomainInvalid { domain, cause } => {
+                Report::error(format!("Domain {:?} invalid", domain), cause)
+            }
+            Self::DirNotEmpty(dir) => Report::error(
+                format!("{:?} isn't empty", dir),
+                "Pick another name, or pass `--force` to generate into it anyway (generated files will replace any existing ones)",
+            ),
+            Self::DirCreationFailed { dir, cause } => {
+                Report::error(format!("Failed to create directory {:?}", dir), cause)
+            }
+            Self::InsideProject { dir, source } => Report::error(
+                format!("{:?} is inside of the project configured in {}", dir, source),
+                "`init` would pick up that config and generate into that project instead, so the new app has to go somewhere else",
+            ),
+            Self::DirReadFailed { dir, cause } => {
+                Report::error(format!("Failed to read directory {:?}", dir), cause)
+            }
+            Self::InitFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::DirNameInvalid(..) => "new.dir-name-invalid",
+            Self::NameInvalid(..) => "new.name-invalid",
+            Self::DomainInvalid { .. } => "new.domain-invalid",
+            Self::DirNotEmpty(..) => "new.dir-not-empty",
+            Self::DirCreationFailed { .. } => "new.dir-creation-failed",
+            Self::InsideProject { .. } => "new.inside-project",
+            Self::DirReadFailed { .. } => "new.dir-read-failed",
+            Self::InitFailed(err) => err.code(),
+        }
+    }
+}
+
+fn list_files(dir: &Path, root: &Path, files: &mut BTreeSet<PathBuf>) -> Result<(), Error> {
+    let read_failed = |cause| Error::DirReadFailed {
+        dir: dir.to_owned(),
+        cause,
+    };
+    for entry in fs::read_dir(dir).map_err(read_failed)? {
+        let path = entry.map_err(read_failed)?.path();
+        let rel = path
+            .strip_prefix(root)
+            .expect("developer error: `read_dir` yielded a path outside of the dir")
+            .to_owned();
+        let unlisted = UNLISTED_DIRS
+            .iter()
+            .any(|unlisted| rel.as_os_str() == *unlisted);
+        if path.is_dir() && !unlisted {
+            list_files(&path, root, files)?;
+        } else {
+            files.insert(rel);
+        }
+    }
+    Ok(())
+}
+
+fn existing_files(dir: &Path) -> Result<BTreeSet<PathBuf>, Error> {
+    let mut files = BTreeSet::new();
+    if dir.is_dir() {
+        list_files(dir, dir, &mut files)?;
+    }
+    Ok(files)
+}
+
+/// Creates a new app in `dir`, which has to be empty or missing unless `force`
+/// is set. The app's named after `dir` unless `name` is given. Git is
+/// initialized unless `no_git` is set, and the rest is up to `init`, which
+/// only prompts for what wasn't given.
+pub fn exec(
+    wrapper: &TextWrapper,
+    non_interactive: opts::NonInteractive,
+    dir: &Path,
+    name: Option<String>,
+    domain: Option<String>,
+    template_pack: Option<String>,
+    platforms: &[Platform],
+    template_vars: &[(String, String)],
+    post_init: PostInitPolicy,
+    no_git: opts::NoGit,
+    force: opts::Force,
+) -> Result<(), Error> {
+    let name = match name {
+        Some(name) => name,
+        None => dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(str::to_owned)
+            .ok_or_else(|| Error::DirNameInvalid(dir.to_owned()))?,
+    };
+    let name = name::validate(name).map_err(Error::NameInvalid)?;
+    if let Some(domain) = &domain {
+        domain::check_domain_syntax(domain).map_err(|cause| Error::DomainInvalid {
+            domain: domain.clone(),
+            cause,
+        })?;
+    }
+    let existing = existing_files(dir)?;
+    if !existing.is_empty() && force.no() {
+        return Err(Error::DirNotEmpty(dir.to_owned()));
+    }
+    fs::create_dir_all(dir).map_err(|cause| Error::DirCreationFailed {
+        dir: dir.to_owned(),
+        cause,
+    })?;
+    // A discovery failure here would just happen again during `init`, which
+    // reports it properly.
+    if let Some(source) = Source::discover(dir).ok().flatten() {
+        let inside = dir
+            .canonicalize()
+            .map_or(false, |dir| source.root_dir() != dir);
+        if inside {
+            return Err(Error::InsideProject {
+                dir: dir.to_owned(),
+                source,
+            });
+        }
+    }
+    ui::status(format!("Creating app {:?} in {:?}...", name, dir));
+    init::exec(
+        wrapper,
+        non_interactive,
+        opts::SkipDevTools::No,
+        opts::ReinstallDeps::No,
+        opts::OpenInEditor::No,
+        no_git,
+        // `force` only lets this generate into a directory that isn't empty;
+        // what's already there still isn't replaced without asking.
+        opts::Force::No,
+        None,
+        Some(name),
+        domain,
+        None,
+        template_pack,
+        platforms,
+        template_vars,
+        post_init,
+        Writer::Disk,
+        dir,
+    )
+    .map_err(Error::InitFailed)?;
+    let created = existing_files(dir)?
+        .into_iter()
+        .filter(|file| !existing.contains(file))
+        .collect::<Vec<_>>();
+    println!("Created in {:?}:", dir);
+    for file in created {
+        println!("  {}", file.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn test_existing_files_skips_git_and_target() {
+        let temp_dir = TempDir::new("new-test").unwrap();
+        let dir = temp_dir.path();
+        for path in &[".git/HEAD", "target/debug/app", "src/lib.rs", "Cargo.toml"] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let files = existing_files(dir).unwrap();
+        assert_eq!(
+            files.into_iter().collect::<Vec<_>>(),
+            vec![
+                PathBuf::from(".git"),
+                PathBuf::from("Cargo.toml"),
+                PathBuf::from("src/lib.rs"),
+                PathBuf::from("target"),
+            ]
+        );
+        assert!(existing_files(&dir).unwrap().is_empty());
+    }
+}
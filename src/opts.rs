@@ -46,6 +46,36 @@ yes_or_no!(ReinstallDeps);
 
 yes_or_no!(OpenInEditor);
 
+yes_or_no!(NoGit);
+
+yes_or_no!(SkipStrip);
+
+yes_or_no!(SkipLog);
+
+yes_or_no!(KillOnExit);
+
+yes_or_no!(FullClean);
+
+yes_or_no!(CleanDerivedData);
+
+yes_or_no!(Watch);
+
+yes_or_no!(Force);
+
+yes_or_no!(NoHooks);
+
+yes_or_no!(NoDaemonManagement);
+
+yes_or_no!(NoCrashCheck);
+
+yes_or_no!(Timings);
+
+yes_or_no!(Quiet);
+
+yes_or_no!(IgnoreVersionMismatch);
+
+yes_or_no!(Ephemeral);
+
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum Profile {
     Debug,
@@ -76,3 +106,40 @@ impl Profile {
         }
     }
 }
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::Text
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("{:?} isn't an output format", s)),
+        }
+    }
+}
+
+impl Format {
+    pub fn json(self) -> bool {
+        matches!(self, Self::Json)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! How much memory and CPU we're allowed, which in a container is whatever
+//! its cgroup says rather than what the host has.
+
+use std::{fs, path::Path};
+
+static CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// cgroup v1 reports "no limit" as a huge number instead of `max`.
+const V1_UNLIMITED: u64 = 1 << 62;
+
+fn read(path: impl AsRef<Path>) -> Option<String> {
+    fs::read_to_string(path).ok()
+}
+
+// `MemTotal:       16314480 kB`
+fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|rest| rest.trim().strip_suffix("kB"))
+        .and_then(|kib| kib.trim().parse::<u64>().ok())
+        .map(|kib| kib.saturating_mul(1024))
+}
+
+fn parse_memory_limit(limit: &str) -> Option<u64> {
+    limit
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|limit| *limit < V1_UNLIMITED)
+}
+
+// cgroup v2's `cpu.max` is `<quota> <period>`, or `max <period>` without one.
+fn parse_cpu_max(cpu_max: &str) -> Option<usize> {
+    let mut fields = cpu_max.split_whitespace();
+    let quota = fields.next()?.parse::<u64>().ok()?;
+    let period = fields.next()?.parse::<u64>().ok()?;
+    cpus_for_quota(quota, period)
+}
+
+// A quota of 1.5 CPUs still lets 2 jobs make progress.
+fn cpus_for_quota(quota: u64, period: u64) -> Option<usize> {
+    if period == 0 {
+        None
+    } else {
+        Some(((quota + period - 1) / period).max(1) as usize)
+    }
+}
+
+fn cgroup_memory_limit() -> Option<u64> {
+    read(Path::new(CGROUP_ROOT).join("memory.max"))
+        .or_else(|| read(Path::new(CGROUP_ROOT).join("memory/memory.limit_in_bytes")))
+        .and_then(|limit| parse_memory_limit(&limit))
+}
+
+fn lower_memory_limit(total: Option<u64>, limit: Option<u64>) -> Option<(u64, bool)> {
+    match (total, limit) {
+        (Some(total), Some(limit)) if limit < total => Some((limit, true)),
+        (None, Some(limit)) => Some((limit, true)),
+        (total, _) => total.map(|total| (total, false)),
+    }
+}
+
+/// The memory we can use: the machine's, or our cgroup's limit if that's
+/// lower. The flag is whether it was the cgroup's.
+pub fn memory_limit() -> Option<(u64, bool)> {
+    let total = read("/proc/meminfo").and_then(|meminfo| parse_meminfo(&meminfo));
+    lower_memory_limit(total, cgroup_memory_limit())
+}
+
+/// How many CPUs our cgroup's quota amounts to, if it has one.
+pub fn cpu_quota() -> Option<usize> {
+    read(Path::new(CGROUP_ROOT).join("cpu.max"))
+        .and_then(|cpu_max| parse_cpu_max(&cpu_max))
+        .or_else(|| {
+            let quota = read(Path::new(CGROUP_ROOT).join("cpu/cpu.cfs_quota_us"))?
+                .trim()
+                .parse::<i64>()
+                .ok()
+                // -1 means there's no quota.
+                .filter(|quota| *quota > 0)?;
+            let period = read(Path::new(CGROUP_ROOT).join("cpu/cpu.cfs_period_us"))?
+                .trim()
+                .parse::<u64>()
+                .ok()?;
+            cpus_for_quota(quota as u64, period)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_parse_meminfo() {
+        assert_eq!(
+            parse_meminfo("MemTotal:        7110868 kB\nMemFree:          302096 kB\n"),
+            Some(7110868 * 1024)
+        );
+        assert_eq!(parse_meminfo("MemFree:          302096 kB\n"), None);
+    }
+
+    #[rstest(
+        limit,
+        expected,
+        case("max\n", None),
+        case("4294967296\n", Some(4294967296)),
+        case("9223372036854771712\n", None)
+    )]
+    fn test_parse_memory_limit(limit: &str, expected: Option<u64>) {
+        assert_eq!(parse_memory_limit(limit), expected);
+    }
+
+    #[rstest(
+        total,
+        limit,
+        expected,
+        case(Some(16 << 30), None, Some((16 << 30, false))),
+        case(Some(16 << 30), Some(32 << 30), Some((16 << 30, false))),
+        case(Some(16 << 30), Some(4 << 30), Some((4 << 30, true))),
+        case(None, Some(4 << 30), Some((4 << 30, true))),
+        case(None, None, None)
+    )]
+    fn test_lower_memory_limit(
+        total: Option<u64>,
+        limit: Option<u64>,
+        expected: Option<(u64, bool)>,
+    ) {
+        assert_eq!(lower_memory_limit(total, limit), expected);
+    }
+
+    #[rstest(
+        cpu_max,
+        expected,
+        case("max 100000\n", None),
+        case("200000 100000\n", Some(2)),
+        case("150000 100000\n", Some(2)),
+        case("50000 100000\n", Some(1))
+    )]
+    fn test_parse_cpu_max(cpu_max: &str, expected: Option<usize>) {
+        assert_eq!(parse_cpu_max(cpu_max), expected);
+    }
+}
@@ -1,5 +1,8 @@
+mod limits;
 mod xdg;
 
+pub use self::limits::*;
+
 use std::{
     ffi::{OsStr, OsString},
     fmt::{self, Display},
@@ -109,3 +109,18 @@ pub fn command_path(name: &str) -> bossy::Result<bossy::Output> {
         .with_args(&["-v", name])
         .run_and_wait_for_output()
 }
+
+/// The machine's memory. There are no cgroups here, so it's never limited.
+pub fn memory_limit() -> Option<(u64, bool)> {
+    bossy::Command::impure("sysctl")
+        .with_args(&["-n", "hw.memsize"])
+        .run_and_wait_for_str(|memsize| memsize.trim().parse::<u64>().ok())
+        .map_err(|err| log::warn!("failed to check how much memory there is: {}", err))
+        .ok()
+        .flatten()
+        .map(|memsize| (memsize, false))
+}
+
+pub fn cpu_quota() -> Option<usize> {
+    None
+}
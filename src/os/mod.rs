@@ -1,5 +1,9 @@
 #![allow(unsafe_code)]
 
+mod port_owner;
+
+pub use self::port_owner::*;
+
 #[cfg(target_os = "macos")]
 mod macos;
 
@@ -0,0 +1,117 @@
+use std::fmt::{self, Display};
+
+/// A process that's listening on a TCP port.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PortOwner {
+    pub pid: u32,
+    pub name: String,
+}
+
+impl Display for PortOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` (pid {})", self.name, self.pid)
+    }
+}
+
+// `lsof -F pc` prints a line per field, each starting with the field's letter,
+// so a process is a `p<pid>` line followed by a `c<command>` line.
+fn parse_lsof(output: &str) -> Option<PortOwner> {
+    let mut pid = None;
+    for line in output.lines() {
+        if let Some(value) = line.strip_prefix('p') {
+            pid = value.parse().ok();
+        } else if let (Some(pid), Some(name)) = (pid, line.strip_prefix('c')) {
+            return Some(PortOwner {
+                pid,
+                name: name.to_owned(),
+            });
+        }
+    }
+    None
+}
+
+// i.e. `LISTEN 0 511 0.0.0.0:8080 0.0.0.0:* users:(("node",pid=1234,fd=20))`;
+// the process is only shown if it's ours, or if we're root.
+#[cfg(target_os = "linux")]
+fn parse_ss(output: &str) -> Option<PortOwner> {
+    let caps = once_cell_regex::regex!(r#"users:\(\("([^"]+)",pid=(\d+)"#).captures(output)?;
+    Some(PortOwner {
+        pid: caps[2].parse().ok()?,
+        name: caps[1].to_owned(),
+    })
+}
+
+fn lsof(port: u16) -> Option<PortOwner> {
+    bossy::Command::impure("lsof")
+        .with_args(&[
+            "-nP",
+            &format!("-iTCP:{}", port),
+            "-sTCP:LISTEN",
+            "-F",
+            "pc",
+        ])
+        .run_and_wait_for_str(parse_lsof)
+        .ok()
+        .flatten()
+}
+
+/// The process listening on `port`, if `lsof` can tell.
+#[cfg(target_os = "macos")]
+pub fn port_owner(port: u16) -> Option<PortOwner> {
+    lsof(port)
+}
+
+/// The process listening on `port`, if it can be found out. Not every distro
+/// has `lsof`, so this falls back to `ss`.
+#[cfg(target_os = "linux")]
+pub fn port_owner(port: u16) -> Option<PortOwner> {
+    lsof(port).or_else(|| {
+        bossy::Command::impure("ss")
+            .with_args(&["-Hltnp", "sport", "=", &format!(":{}", port)])
+            .run_and_wait_for_str(parse_ss)
+            .ok()
+            .flatten()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        output,
+        expected,
+        case("p1234\ncnode\nf20\n", Some((1234, "node"))),
+        case("p1234\ncnode\np5678\ncpython3\n", Some((1234, "node"))),
+        case("cnode\n", None),
+        case("", None)
+    )]
+    fn test_parse_lsof(output: &str, expected: Option<(u32, &str)>) {
+        assert_eq!(
+            parse_lsof(output),
+            expected.map(|(pid, name)| PortOwner {
+                pid,
+                name: name.to_owned()
+            })
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_ss() {
+        assert_eq!(
+            parse_ss(
+                "LISTEN 0      511      0.0.0.0:8080      0.0.0.0:*    users:((\"node\",pid=1234,fd=20))\n"
+            ),
+            Some(PortOwner {
+                pid: 1234,
+                name: "node".to_owned()
+            })
+        );
+        assert_eq!(
+            parse_ss("LISTEN 0      511      0.0.0.0:8080      0.0.0.0:*\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Dry runs go through the same code as real ones, except that everything that
+//! would be written to the project gets collected into a [`Plan`] instead, and
+//! commands that would change things outside of the project aren't run.
+
+use crate::generated::{Kind, Record};
+use colored::Colorize as _;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+enum Change {
+    Dir,
+    File(Vec<u8>),
+    Link(PathBuf),
+}
+
+#[derive(Debug, Default)]
+pub struct Plan {
+    changes: RefCell<BTreeMap<PathBuf, Change>>,
+    skipped: RefCell<Vec<String>>,
+}
+
+impl Plan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What's planned to be in the file at `path`, if anything is.
+    pub fn contents(&self, path: &Path) -> Option<Vec<u8>> {
+        match self.changes.borrow().get(path) {
+            Some(Change::File(contents)) => Some(contents.clone()),
+            _ => None,
+        }
+    }
+
+    /// Lists everything that would've been done, relative to `root_dir`.
+    /// Files that already have the planned contents are left out, and files
+    /// that would be overwritten get a unified diff if `diff` is set.
+    pub fn print(&self, root_dir: &Path, diff: bool) {
+        let relative = |path: &Path| {
+            path.strip_prefix(root_dir)
+                .unwrap_or(path)
+                .display()
+                .to_string()
+        };
+        let mut unchanged = 0;
+        for (path, change) in self.changes.borrow().iter() {
+            match change {
+                Change::Dir => println!("{:>10} {}/", "create".green(), relative(path)),
+                Change::File(contents) => match fs::read(path) {
+                    Ok(current) if &current == contents => unchanged += 1,
+                    Ok(current) => {
+                        println!(
+                            "{:>10} {} ({} bytes)",
+                            "overwrite".yellow(),
+                            relative(path),
+                            contents.len()
+                        );
+                        if diff {
+                            print_diff(&relative(path), &current, contents);
+                        }
+                    }
+                    Err(_) => println!(
+                        "{:>10} {} ({} bytes)",
+                        "create".green(),
+                        relative(path),
+                        contents.len()
+                    ),
+                },
+                Change::Link(target) => println!(
+                    "{:>10} {} -> {}",
+                    "link".cyan(),
+                    relative(path),
+                    relative(target)
+                ),
+            }
+        }
+        if unchanged > 0 {
+            println!("({} generated files are already up to date)", unchanged);
+        }
+        for skipped in self.skipped.borrow().iter() {
+            println!("{:>10} {}", "skip".dimmed(), skipped);
+        }
+    }
+}
+
+/// Prints a unified diff from `current` to `planned`, unless either is binary.
+pub fn print_diff(name: &str, current: &[u8], planned: &[u8]) {
+    match (std::str::from_utf8(current), std::str::from_utf8(planned)) {
+        (Ok(current), Ok(planned)) => print!(
+            "{}",
+            similar::TextDiff::from_lines(current, planned)
+                .unified_diff()
+                .header(&format!("a/{}", name), &format!("b/{}", name))
+        ),
+        _ => println!("Binary files a/{0} and b/{0} differ", name),
+    }
+}
+
+/// Where generated files go: straight to disk, into a [`Plan`] during a dry
+/// run, or to disk with a [`Record`] kept of what was generated.
+#[derive(Clone, Copy, Debug)]
+pub enum Writer<'a> {
+    Disk,
+    DryRun(&'a Plan),
+    Recording(&'a Record),
+}
+
+impl<'a> Writer<'a> {
+    pub fn dry_run(self) -> bool {
+        matches!(self, Self::DryRun(_))
+    }
+
+    /// Records what's written in `record`, unless this is a dry run.
+    pub fn recording(self, record: &'a Record) -> Self {
+        match self {
+            Self::Disk | Self::Recording(_) => Self::Recording(record),
+            Self::DryRun(_) => self,
+        }
+    }
+
+    /// Records that everything in `dir` was generated, for when something
+    /// other than us generates it (i.e. `xcodegen`).
+    pub fn claim(self, dir: &Path) {
+        if let Self::Recording(record) = self {
+            record.insert(dir, Kind::Tree);
+        }
+    }
+
+    pub fn create_dir_all(self, path: &Path) -> io::Result<()> {
+        match self {
+            Self::Disk => fs::create_dir_all(path),
+            Self::Recording(record) => {
+                record.insert(path, Kind::Dir);
+                fs::create_dir_all(path)
+            }
+            Self::DryRun(plan) => {
+                if !path.is_dir() {
+                    let mut changes = plan.changes.borrow_mut();
+                    // Creating the file's parent directories goes without saying.
+                    if !changes.keys().any(|planned| planned.starts_with(path)) {
+                        changes.insert(path.to_owned(), Change::Dir);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn write(self, path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        match self {
+            Self::Disk => fs::write(path, contents),
+            Self::Recording(record) => {
+                record.insert(path, Kind::File);
+                fs::write(path, contents)
+            }
+            Self::DryRun(plan) => {
+                let mut changes = plan.changes.borrow_mut();
+                changes.retain(|planned, change| {
+                    !matches!(change, Change::Dir) || !path.starts_with(planned)
+                });
+                changes.insert(path.to_owned(), Change::File(contents.as_ref().to_vec()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Copying keeps permissions, which matters for things like `gradlew`.
+    pub fn copy(self, src: &Path, dest: &Path) -> io::Result<()> {
+        match self {
+            Self::Disk => fs::copy(src, dest).map(|_| ()),
+            Self::Recording(record) => {
+                record.insert(dest, Kind::File);
+                fs::copy(src, dest).map(|_| ())
+            }
+            Self::DryRun(_) => self.write(dest, fs::read(src)?),
+        }
+    }
+
+    /// Reads what's planned to be at `path` if there is anything, and what's
+    /// on disk otherwise.
+    pub fn read(self, path: &Path) -> io::Result<Vec<u8>> {
+        match self {
+            Self::DryRun(plan) => plan
+                .contents(path)
+                .map(Ok)
+                .unwrap_or_else(|| fs::read(path)),
+            Self::Disk | Self::Recording(_) => fs::read(path),
+        }
+    }
+
+    /// Records that a symlink to `target` would be made at `path` during a dry
+    /// run, and makes it with `link` otherwise.
+    pub fn link<E>(
+        self,
+        target: &Path,
+        path: &Path,
+        link: impl FnOnce() -> Result<(), E>,
+    ) -> Result<(), E> {
+        match self {
+            Self::Disk => link(),
+            Self::Recording(record) => {
+                record.insert(path, Kind::Link);
+                link()
+            }
+            Self::DryRun(plan) => {
+                plan.changes
+                    .borrow_mut()
+                    .insert(path.to_owned(), Change::Link(target.to_owned()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs `run`, unless this is a dry run, in which case `what` is listed as
+    /// skipped instead.
+    pub fn run<E>(self, what: &str, run: impl FnOnce() -> Result<(), E>) -> Result<(), E> {
+        match self {
+            Self::Disk | Self::Recording(_) => run(),
+            Self::DryRun(plan) => {
+                plan.skipped.borrow_mut().push(what.to_owned());
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn test_dry_run_plans_instead_of_writing() {
+        let temp_dir = TempDir::new("plan-test").unwrap();
+        let root = temp_dir.path().join("app");
+        let plan = Plan::new();
+        let writer = Writer::DryRun(&plan);
+        writer.create_dir_all(&root.join("gen/android")).unwrap();
+        writer
+            .write(&root.join("gen/android/build.gradle"), "plugins {}")
+            .unwrap();
+        writer.run("run `xcodegen`", || Err(())).unwrap();
+        assert!(!root.exists());
+        assert_eq!(
+            writer.read(&root.join("gen/android/build.gradle")).unwrap(),
+            b"plugins {}"
+        );
+        assert!(plan.contents(&root.join("gen/android")).is_none());
+        assert_eq!(plan.changes.borrow().len(), 1);
+        assert_eq!(*plan.skipped.borrow(), vec!["run `xcodegen`".to_owned()]);
+    }
+}
@@ -0,0 +1,307 @@
+//! Subcommands we don't know about are run as plugins, the same way cargo runs
+//! `cargo-<name>`: `cargo mobile foo` runs `cargo-mobile-foo` from the `PATH`,
+//! with the rest of the args, and env vars describing the project it was run
+//! in, so that plugins don't have to find and parse the config themselves.
+
+use crate::{
+    config::{app::Platform, Config, Source},
+    opts,
+    target::TargetTrait as _,
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub static PREFIX: &str = "cargo-mobile-";
+
+#[derive(Debug)]
+pub enum Error {
+    NoSuchCommand {
+        name: String,
+        suggestion: Option<String>,
+    },
+    Failed {
+        name: String,
+        cause: bossy::Error,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::NoSuchCommand { name, suggestion } => {
+                let mut details = format!(
+                    "There's no built-in subcommand named `{}`, and no `{}{}` on your `PATH` to run instead. Plugins are executables named `{}<name>`; run `cargo mobile --list` to see every subcommand.",
+                    name, PREFIX, name, PREFIX
+                );
+                if let Some(suggestion) = suggestion {
+                    details = format!("Did you mean `{}`? {}", suggestion, details);
+                }
+                Report::error(format!("No such subcommand `{}`", name), details)
+            }
+            Self::Failed { name, cause } => {
+                Report::error(format!("`cargo mobile {}` failed", name), cause)
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NoSuchCommand { .. } => "plugin.no-such-command",
+            Self::Failed { .. } => "plugin.failed",
+        }
+    }
+}
+
+#[cfg(unix)]
+fn executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt as _;
+    fs::metadata(path).map_or(false, |metadata| {
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    })
+}
+
+#[cfg(not(unix))]
+fn executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+fn plugin_name(file_name: &str) -> Option<&str> {
+    let name = file_name.strip_prefix(PREFIX)?;
+    let name = if cfg!(windows) {
+        name.strip_suffix(".exe")?
+    } else {
+        name
+    };
+    Some(name).filter(|name| !name.is_empty())
+}
+
+// Earlier dirs win, just like they would if the plugin was run by name.
+fn discover_in(dirs: impl IntoIterator<Item = PathBuf>) -> BTreeMap<String, PathBuf> {
+    let mut plugins = BTreeMap::new();
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(plugin_name)
+                .map(str::to_owned);
+            if let Some(name) = name {
+                if !plugins.contains_key(&name) && executable(&path) {
+                    plugins.insert(name, path);
+                }
+            }
+        }
+    }
+    plugins
+}
+
+/// Every plugin on the `PATH`, by subcommand name.
+pub fn discover() -> BTreeMap<String, PathBuf> {
+    std::env::var_os("PATH")
+        .map(|path| discover_in(std::env::split_paths(&path)))
+        .unwrap_or_default()
+}
+
+/// What plugins are told about how they were run, and about the project
+/// they were run in.
+#[derive(Clone, Copy, Debug)]
+pub struct Context {
+    pub noise_level: opts::NoiseLevel,
+    pub non_interactive: opts::NonInteractive,
+    pub format: opts::Format,
+}
+
+// The targets `rustup` has installed are the ones the project is set up to
+// build for, so those are the ones that are selected.
+fn selected_targets<T: crate::target::TargetTrait<'static> + 'static>(
+    installed: &[String],
+) -> String {
+    T::all()
+        .iter()
+        .filter(|(_, target)| installed.iter().any(|triple| triple == target.triple()))
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Context {
+    fn env_vars(&self) -> Vec<(&'static str, OsString)> {
+        let mut vars = vec![
+            ("CARGO_MOBILE_FORMAT", self.format.as_str().into()),
+            (
+                "CARGO_MOBILE_NON_INTERACTIVE",
+                if self.non_interactive.yes() { "1" } else { "0" }.into(),
+            ),
+            (
+                "CARGO_MOBILE_VERBOSE",
+                match self.noise_level {
+                    opts::NoiseLevel::Polite => "0",
+                    opts::NoiseLevel::LoudAndProud => "1",
+                    opts::NoiseLevel::FranklyQuitePedantic => "2",
+                }
+                .into(),
+            ),
+        ];
+        // Plugins can be useful outside of a project too, so a missing or
+        // broken config just means there's less to tell them.
+        let source = match Source::discover(".") {
+            Ok(Some(source)) => source,
+            Ok(None) => return vars,
+            Err(err) => {
+                log::warn!("failed to find config to pass along to plugin: {}", err);
+                return vars;
+            }
+        };
+        vars.push(("CARGO_MOBILE_PROJECT_ROOT", source.root_dir().into()));
+        vars.push(("CARGO_MOBILE_CONFIG_PATH", source.path().into()));
+        match Config::check(".") {
+            Ok(config) => {
+                let platforms = config.app().platforms();
+                vars.push((
+                    "CARGO_MOBILE_PLATFORMS",
+                    platforms
+                        .iter()
+                        .map(|platform| platform.name())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                        .into(),
+                ));
+                let installed = util::installed_targets().unwrap_or_else(|err| {
+                    log::warn!(
+                        "failed to list installed targets to pass along to plugin: {}",
+                        err
+                    );
+                    Vec::new()
+                });
+                if platforms.contains(&Platform::Android) {
+                    vars.push((
+                        "CARGO_MOBILE_ANDROID_TARGETS",
+                        selected_targets::<crate::android::target::Target<'static>>(&installed)
+                            .into(),
+                    ));
+                }
+                #[cfg(target_os = "macos")]
+                if platforms.contains(&Platform::Apple) {
+                    vars.push((
+                        "CARGO_MOBILE_APPLE_TARGETS",
+                        selected_targets::<crate::apple::target::Target<'static>>(&installed)
+                            .into(),
+                    ));
+                }
+            }
+            Err(err) => log::warn!(
+                "failed to load config to pass along to plugin: {}",
+                err.report().details()
+            ),
+        }
+        vars
+    }
+}
+
+/// Runs the plugin for the subcommand `name` with `args`, or explains that
+/// there's no such subcommand. `built_in` is every subcommand we have, which
+/// typos are checked against along with the plugins.
+pub fn exec(context: Context, name: &str, args: &[String], built_in: &[&str]) -> Result<(), Error> {
+    let plugins = discover();
+    let path = plugins.get(name).ok_or_else(|| Error::NoSuchCommand {
+        name: name.to_owned(),
+        suggestion: util::suggest(
+            name,
+            built_in
+                .iter()
+                .copied()
+                .chain(plugins.keys().map(String::as_str)),
+        )
+        .map(str::to_owned),
+    })?;
+    log::info!("running plugin {:?}", path);
+    bossy::Command::impure(path)
+        .with_env_vars(context.env_vars())
+        .with_args(args)
+        .run_and_wait()
+        .map_err(|cause| Error::Failed {
+            name: name.to_owned(),
+            cause,
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        file_name,
+        expected,
+        case("cargo-mobile-lint", Some("lint")),
+        case("cargo-mobile-", None),
+        case("cargo-android", None)
+    )]
+    #[cfg(unix)]
+    fn test_plugin_name(file_name: &str, expected: Option<&str>) {
+        assert_eq!(plugin_name(file_name), expected);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_discover_in() {
+        use std::os::unix::fs::PermissionsExt as _;
+        let root = util::TempDir::new("plugin-test").unwrap();
+        let (first, second) = (root.path().join("first"), root.path().join("second"));
+        for (dir, name, mode) in &[
+            (&first, "cargo-mobile-lint", 0o755),
+            (&first, "cargo-mobile-notes", 0o644),
+            (&second, "cargo-mobile-lint", 0o755),
+            (&second, "cargo-mobile-ship", 0o755),
+            (&second, "cargo-android", 0o755),
+        ] {
+            fs::create_dir_all(dir).unwrap();
+            let path = dir.join(name);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(*mode)).unwrap();
+        }
+        let plugins = discover_in(vec![
+            first.clone(),
+            root.path().join("missing"),
+            second.clone(),
+        ]);
+        assert_eq!(
+            plugins.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("lint".to_owned(), first.join("cargo-mobile-lint")),
+                ("ship".to_owned(), second.join("cargo-mobile-ship")),
+            ]
+        );
+    }
+
+    #[rstest(
+        installed,
+        expected,
+        case(&[], ""),
+        case(&["aarch64-linux-android", "x86_64-apple-darwin"], "aarch64"),
+        case(&["i686-linux-android", "aarch64-linux-android"], "aarch64,i686")
+    )]
+    fn test_selected_targets(installed: &[&str], expected: &str) {
+        let installed = installed
+            .iter()
+            .map(|triple| triple.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            selected_targets::<crate::android::target::Target<'static>>(&installed),
+            expected
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! Checks run before builds, so that a full disk or a read-only output
+//! directory is reported up front, instead of after ten minutes of compiling.
+//!
+//! Only a nearly full volume is an error; anything less than
+//! `app.min-free-space-gib` just gets a warning, since how much a build needs
+//! varies too much to refuse one that might have fit.
+
+use crate::{
+    config::app::App,
+    generated,
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// Even a build that's entirely up to date writes fingerprints and logs, so
+// there's no point in starting with less than this.
+const HARD_MIN_FREE_SPACE: u64 = 100 << 20;
+
+#[derive(Debug)]
+pub enum Error {
+    NotWritable { path: PathBuf, cause: io::Error },
+    OutOfSpace { path: PathBuf, available: u64 },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::NotWritable { path, cause } => Report::action_request(
+                format!("Output directory {:?} isn't writable", path),
+                format!(
+                    "Failed to create a file in it: {}. Make sure it's not on a read-only volume, and that you have permission to write to it.",
+                    cause
+                ),
+            ),
+            Self::OutOfSpace { path, available } => Report::action_request(
+                format!("Not enough free space to build in {:?}", path),
+                format!(
+                    "Only {} is free on its volume, which isn't enough for even a build that's already up to date; free up some space and try again.",
+                    util::display_size(*available)
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NotWritable { .. } => "preflight.not-writable",
+            Self::OutOfSpace { .. } => "preflight.out-of-space",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    target_directory: PathBuf,
+}
+
+/// The dir cargo builds in, as `cargo metadata` reports it, so that
+/// `CARGO_TARGET_DIR`, `build.target-dir`, and workspaces are all accounted
+/// for. If cargo can't tell us, this falls back to `CARGO_TARGET_DIR` or
+/// `target`, the way cargo would without any config.
+pub fn target_dir(app: &App) -> PathBuf {
+    let output = bossy::Command::impure("cargo")
+        .with_args(&["metadata", "--format-version", "1", "--no-deps"])
+        .with_arg("--manifest-path")
+        .with_arg(app.manifest_path())
+        .run_and_wait_for_str(|output| serde_json::from_str::<Metadata>(output));
+    match output {
+        Ok(Ok(metadata)) => return metadata.target_directory,
+        Ok(Err(err)) => log::warn!("`cargo metadata` output couldn't be parsed: {}", err),
+        Err(err) => log::warn!("failed to ask `cargo metadata` for the target dir: {}", err),
+    }
+    std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| app.prefix_path("target"))
+}
+
+// The dir itself might not exist yet, in which case whatever it'll be created
+// in is what has to be writable (and is what `df` can tell us about).
+fn existing_ancestor(dir: &Path) -> &Path {
+    dir.ancestors()
+        .find(|ancestor| ancestor.is_dir())
+        .unwrap_or(dir)
+}
+
+fn check_writable(dir: &Path) -> Result<(), Error> {
+    let existing = existing_ancestor(dir);
+    let path = existing.join(format!(".cargo-mobile-preflight-{}", std::process::id()));
+    fs::write(&path, "").map_err(|cause| Error::NotWritable {
+        path: dir.to_owned(),
+        cause,
+    })?;
+    if let Err(err) = fs::remove_file(&path) {
+        log::warn!("failed to remove {:?}: {}", path, err);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Volume {
+    mount_point: String,
+    available: u64,
+}
+
+// `df -P` always prints a header and then one line per path, with sizes in
+// 1024-byte blocks and the mount point last (which can contain spaces).
+fn parse_df(output: &str) -> Option<Volume> {
+    let line = output.lines().nth(1)?;
+    let mut fields = line.split_whitespace();
+    let available = fields.nth(3)?.parse::<u64>().ok()?;
+    let _capacity = fields.next()?;
+    let mount_point = fields.collect::<Vec<_>>().join(" ");
+    if mount_point.is_empty() {
+        None
+    } else {
+        Some(Volume {
+            mount_point,
+            available: available.saturating_mul(1024),
+        })
+    }
+}
+
+fn volume(dir: &Path) -> Option<Volume> {
+    let output = bossy::Command::impure("df")
+        .with_args(&["-P", "-k"])
+        .with_arg(dir)
+        .run_and_wait_for_str(parse_df);
+    match output {
+        Ok(Some(volume)) => Some(volume),
+        Ok(None) => {
+            log::warn!("failed to understand `df` output for {:?}", dir);
+            None
+        }
+        Err(err) => {
+            log::warn!("failed to check free space for {:?}: {}", dir, err);
+            None
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Space {
+    Plenty,
+    Low,
+    Exhausted,
+}
+
+fn classify(available: u64, min_free_space: u64) -> Space {
+    if available < HARD_MIN_FREE_SPACE {
+        Space::Exhausted
+    } else if available < min_free_space {
+        Space::Low
+    } else {
+        Space::Plenty
+    }
+}
+
+/// Makes sure the cargo target dir and `gen/` can be written to, and that
+/// the volumes they're on aren't (nearly) full. Dirs that don't exist yet
+/// aren't created; the closest dir that does is checked instead. Failing to find out how much space is free is only
+/// logged, since that's no reason not to build.
+pub fn check(app: &App) -> Result<(), Error> {
+    let dirs = vec![target_dir(app), generated::gen_dir(app)];
+    for dir in &dirs {
+        check_writable(dir)?;
+    }
+    // Both dirs are usually on the same volume, which is only checked once.
+    let volumes = dirs
+        .into_iter()
+        .filter_map(|dir| {
+            volume(existing_ancestor(&dir))
+                .map(|volume| (volume.mount_point, (dir, volume.available)))
+        })
+        .collect::<BTreeMap<_, _>>();
+    for (_, (dir, available)) in volumes {
+        log::info!(
+            "{} free on the volume holding {:?}",
+            util::display_size(available),
+            dir
+        );
+        match classify(available, app.min_free_space()) {
+            Space::Plenty => (),
+            Space::Low => log::warn!(
+                "only {} is free on the volume holding {:?}, and builds can take up to {}; if this build fails, try freeing up some space (or set `app.min-free-space-gib` to change when you're warned about this)",
+                util::display_size(available),
+                dir,
+                util::display_size(app.min_free_space())
+            ),
+            Space::Exhausted => {
+                return Err(Error::OutOfSpace {
+                    path: dir,
+                    available,
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        output,
+        expected,
+        case(
+            "Filesystem     1024-blocks      Used Available Capacity Mounted on\n/dev/nvme0n1p2   479079112 301234000 153445912      67% /\n",
+            Some(Volume { mount_point: "/".to_owned(), available: 153445912 * 1024 })
+        ),
+        case(
+            "Filesystem   1024-blocks      Used Available Capacity  Mounted on\n/dev/disk3s5   971350180 512004332 421988112    55%    /Volumes/Build Drive\n",
+            Some(Volume { mount_point: "/Volumes/Build Drive".to_owned(), available: 421988112 * 1024 })
+        ),
+        case("Filesystem 1024-blocks Used Available Capacity Mounted on\n", None),
+        case("", None)
+    )]
+    fn test_parse_df(output: &str, expected: Option<Volume>) {
+        assert_eq!(parse_df(output), expected);
+    }
+
+    #[rstest(
+        available,
+        expected,
+        case(0, Space::Exhausted),
+        case(HARD_MIN_FREE_SPACE - 1, Space::Exhausted),
+        case(HARD_MIN_FREE_SPACE, Space::Low),
+        case(2 << 30, Space::Plenty)
+    )]
+    fn test_classify(available: u64, expected: Space) {
+        assert_eq!(classify(available, 2 << 30), expected);
+    }
+
+    #[test]
+    fn test_existing_ancestor() {
+        let dir = std::env::temp_dir();
+        assert_eq!(existing_ancestor(&dir.join("not/there/yet")), dir);
+        assert_eq!(existing_ancestor(&dir), dir);
+    }
+}
@@ -1,6 +1,10 @@
 use crate::{
     config::Config,
-    templating::{self, FancyPackResolveError},
+    guard::Guard,
+    opts::NoGit,
+    plan::Writer,
+    templating::{self, FancyPackResolveError, PostInitHook},
+    ui,
     util::{
         cli::{Report, Reportable},
         Git,
@@ -12,10 +16,11 @@ use std::path::PathBuf;
 pub enum Error {
     GitInitFailed(bossy::Error),
     TemplatePackResolveFailed(FancyPackResolveError),
+    ManifestFailed(templating::ManifestError),
     ProcessingFailed {
         src: PathBuf,
         dest: PathBuf,
-        cause: bicycle::ProcessingError,
+        cause: templating::ProcessingError,
     },
 }
 
@@ -26,6 +31,9 @@ impl Reportable for Error {
             Self::TemplatePackResolveFailed(err) => {
                 Report::error("Failed to resolve template pack", err)
             }
+            Self::ManifestFailed(err) => {
+                Report::error("Failed to load template pack manifest", err)
+            }
             Self::ProcessingFailed { src, dest, cause } => Report::error(
                 format!(
                     "Base project template processing from src {:?} to dest {:?} failed",
@@ -35,32 +43,61 @@ impl Reportable for Error {
             ),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::GitInitFailed(..) => "project.git-init-failed",
+            Self::TemplatePackResolveFailed(..) => "project.template-pack-resolve-failed",
+            Self::ManifestFailed(..) => "project.manifest-failed",
+            Self::ProcessingFailed { .. } => "project.processing-failed",
+        }
+    }
 }
 
+/// A git repo gets initialized unless there's already one or `no_git` is set.
+/// Returns the post-init hooks declared by every pack in the chain, in order.
 pub fn gen(
     config: &Config,
     bike: &bicycle::Bicycle,
     filter: &templating::Filter,
+    guard: &Guard,
+    no_git: NoGit,
     submodule_commit: Option<String>,
-) -> Result<(), Error> {
-    println!("Generating base project...");
+    writer: Writer,
+) -> Result<Vec<PostInitHook>, Error> {
+    ui::status("Generating base project...");
     let root = config.app().root_dir();
     let git = Git::new(&root);
-    git.init().map_err(Error::GitInitFailed)?;
+    if no_git.no() && !root.join(".git").exists() {
+        writer
+            .run("run `git init`", || git.init())
+            .map_err(Error::GitInitFailed)?;
+    }
     let pack_chain = config
         .app()
         .template_pack()
         .resolve(git, submodule_commit.as_deref())
         .map_err(Error::TemplatePackResolveFailed)?;
     log::info!("template pack chain: {:#?}", pack_chain);
+    let mut hooks = Vec::new();
     for pack in pack_chain {
         log::info!("traversing template pack {:#?}", pack);
-        bike.filter_and_process(&pack, &root, |_| (), filter.fun())
-            .map_err(|cause| Error::ProcessingFailed {
-                src: pack.to_owned(),
-                dest: root.to_owned(),
-                cause,
-            })?;
+        let manifest = templating::Manifest::load(pack).map_err(Error::ManifestFailed)?;
+        hooks.extend_from_slice(manifest.post_init());
+        templating::process_app(
+            bike,
+            &pack,
+            &root,
+            config.app().platforms(),
+            filter,
+            guard,
+            writer,
+        )
+        .map_err(|cause| Error::ProcessingFailed {
+            src: pack.to_owned(),
+            dest: root.to_owned(),
+            cause,
+        })?;
     }
-    Ok(())
+    Ok(hooks)
 }
@@ -0,0 +1,299 @@
+//! Keeping the generated projects in sync with the config. Generating them
+//! records a hash of the config in `gen/`, which builds compare against, so
+//! that a config change doesn't go unnoticed until somebody remembers to run
+//! `cargo mobile init` again. It's also stamped with the cargo-mobile that
+//! generated it, so that one with a different layout is caught up front.
+
+use crate::{
+    config::{self, Config},
+    generated::gen_dir,
+    init, opts,
+    plan::Writer,
+    stamp, templating, ui,
+    util::{
+        cli::{Report, Reportable, TextWrapper},
+        prompt,
+    },
+};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+static HASH_FILE_NAME: &str = ".config-hash";
+static MANUAL_FILE_NAME: &str = ".manual-regen";
+
+#[derive(Debug)]
+pub enum Error {
+    ConfigFailed(config::LoadOrGenError),
+    RegenFailed(init::Error),
+    VersionMismatched(stamp::Mismatch),
+    PromptFailed(io::Error),
+    RegenDeclined,
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ConfigFailed(err) => err.report(),
+            Self::RegenFailed(err) => err.report(),
+            Self::VersionMismatched(err) => err.report(),
+            Self::PromptFailed(err) => Report::error("Failed to prompt for regenerating", err),
+            Self::RegenDeclined => Report::action_request(
+                "Left your projects as they are",
+                "Run `cargo mobile regen` once you're ready, or pass `--ignore-version-mismatch` to build from them anyway.",
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ConfigFailed(err) => err.code(),
+            Self::RegenFailed(err) => err.code(),
+            Self::VersionMismatched(err) => err.code(),
+            Self::PromptFailed(..) => "regen.prompt-failed",
+            Self::RegenDeclined => "regen.regen-declined",
+        }
+    }
+}
+
+// FNV-1a, which is plenty for telling configs and generated files apart, and
+// unlike `std`'s hasher is guaranteed to stay the same between builds of
+// cargo-mobile.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// JSON with every object's keys sorted, however the map kept them, so that
+// the same config always comes out the same.
+fn canonical(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let entries = map
+                .iter()
+                .collect::<BTreeMap<_, _>>()
+                .into_iter()
+                .map(|(key, value)| format!("{}:{}", Value::from(key.as_str()), canonical(value)))
+                .collect::<Vec<_>>();
+            format!("{{{}}}", entries.join(","))
+        }
+        Value::Array(values) => {
+            let values = values.iter().map(canonical).collect::<Vec<_>>();
+            format!("[{}]", values.join(","))
+        }
+        value => value.to_string(),
+    }
+}
+
+/// A hash of the config as it was written, along with where the app is, so
+/// that moving it counts as a change too. Tables are hashed with their keys
+/// sorted, so reordering or reformatting the config doesn't count. This covers
+/// more than generation strictly depends on, since regenerating too often
+/// beats not regenerating often enough.
+pub fn hash(config: &Config) -> String {
+    let value = json!({
+        "root-dir": config.app().root_dir(),
+        "config": config.raw_json(),
+    });
+    format!("{:016x}", fnv1a(canonical(&value).as_bytes()))
+}
+
+/// Records the config the projects were just generated from.
+pub fn record(config: &Config) {
+    let dir = gen_dir(config.app());
+    let path = dir.join(HASH_FILE_NAME);
+    log::info!("recording config hash in {:?}", path);
+    if let Err(err) = fs::create_dir_all(&dir).and_then(|()| fs::write(&path, hash(config))) {
+        log::warn!("failed to record config hash in {:?}: {}", path, err);
+    }
+    stamp::record(&dir);
+}
+
+// Regenerating is the fix for a mismatch, so we offer to do it on the spot.
+fn ask_to_regen(mismatch: stamp::Mismatch) -> Result<(), Error> {
+    ui::report(&mismatch.report());
+    let answer = loop {
+        if let Some(answer) = prompt::yes_no(
+            "Regenerate them with this version now?",
+            Some(prompt::YesOrNo::Yes),
+        )
+        .map_err(Error::PromptFailed)?
+        {
+            break answer;
+        }
+    };
+    if answer.yes() {
+        Ok(())
+    } else {
+        Err(Error::RegenDeclined)
+    }
+}
+
+fn load(wrapper: &TextWrapper, non_interactive: opts::NonInteractive) -> Result<Config, Error> {
+    Config::load_or_gen(
+        ".",
+        non_interactive,
+        None,
+        None,
+        None,
+        None,
+        None,
+        Writer::Disk,
+        wrapper,
+    )
+    .map(|(config, _origin)| config)
+    .map_err(Error::ConfigFailed)
+}
+
+// Returns whether the projects were regenerated.
+fn check_version(
+    dir: &Path,
+    wrapper: &TextWrapper,
+    non_interactive: opts::NonInteractive,
+    ignore_version_mismatch: opts::IgnoreVersionMismatch,
+) -> Result<bool, Error> {
+    if !dir.is_dir() {
+        return Ok(false);
+    }
+    match stamp::check(dir) {
+        Ok(()) => Ok(false),
+        Err(mismatch) if ignore_version_mismatch.yes() => {
+            log::warn!(
+                "{}; carrying on anyway, since `--ignore-version-mismatch` was passed",
+                mismatch
+            );
+            Ok(false)
+        }
+        Err(mismatch) if non_interactive.yes() => Err(Error::VersionMismatched(mismatch)),
+        Err(mismatch) => {
+            ask_to_regen(mismatch)?;
+            exec(wrapper, non_interactive, &[], Writer::Disk).map(|()| true)
+        }
+    }
+}
+
+/// Checks the projects against the cargo-mobile that generated them, for
+/// commands that use what's in `gen/` without building it. On a mismatch,
+/// you're asked whether to regenerate, unless `ignore_version_mismatch` is
+/// set, in which case it's only a warning.
+pub fn if_version_mismatched(
+    wrapper: &TextWrapper,
+    non_interactive: opts::NonInteractive,
+    ignore_version_mismatch: opts::IgnoreVersionMismatch,
+) -> Result<(), Error> {
+    let config = load(wrapper, non_interactive)?;
+    let dir = gen_dir(config.app());
+    check_version(&dir, wrapper, non_interactive, ignore_version_mismatch).map(|_| ())
+}
+
+/// Regenerates the projects if the config changed since they were generated.
+/// If `gen/.manual-regen` exists, that's left up to the user, who just gets a
+/// warning instead. Projects that were generated before hashes were recorded
+/// are assumed to be up to date.
+///
+/// Before that, the projects are checked against the cargo-mobile that
+/// generated them, the same as with [`if_version_mismatched`].
+pub fn if_stale(
+    wrapper: &TextWrapper,
+    non_interactive: opts::NonInteractive,
+    ignore_version_mismatch: opts::IgnoreVersionMismatch,
+) -> Result<(), Error> {
+    let config = load(wrapper, non_interactive)?;
+    let dir = gen_dir(config.app());
+    if check_version(&dir, wrapper, non_interactive, ignore_version_mismatch)? {
+        return Ok(());
+    }
+    let recorded = match fs::read_to_string(dir.join(HASH_FILE_NAME)) {
+        Ok(recorded) => recorded,
+        Err(err) => {
+            log::info!(
+                "no config hash to compare against, so projects are assumed to be up to date: {}",
+                err
+            );
+            return Ok(());
+        }
+    };
+    if recorded.trim() == hash(&config) {
+        log::info!("config hasn't changed since projects were generated");
+        return Ok(());
+    }
+    let manual = dir.join(MANUAL_FILE_NAME);
+    if manual.is_file() {
+        Report::action_request(
+            "Your config has changed since your projects were generated!",
+            format!(
+                "Run `cargo mobile regen` to bring them up to date. This isn't done automatically, since {:?} exists.",
+                manual
+            ),
+        )
+        .print(wrapper);
+        Ok(())
+    } else {
+        ui::status("Your config has changed since your projects were generated, so they're being regenerated...");
+        exec(wrapper, non_interactive, &[], Writer::Disk)
+    }
+}
+
+/// Regenerates the projects from the config, without any of the prompts or
+/// extra setup that `cargo mobile init` comes with. `template_vars` are the
+/// ones passed with `--var`. Everything in `gen` is overwritten, but files
+/// outside of it that were changed by hand are only replaced if you say so,
+/// and are kept when there's nobody to ask, the same as `init` without
+/// `--force`.
+pub fn exec(
+    wrapper: &TextWrapper,
+    non_interactive: opts::NonInteractive,
+    template_vars: &[(String, String)],
+    writer: Writer,
+) -> Result<(), Error> {
+    init::exec(
+        wrapper,
+        non_interactive,
+        opts::SkipDevTools::Yes,
+        opts::ReinstallDeps::No,
+        opts::OpenInEditor::No,
+        opts::NoGit::No,
+        opts::Force::No,
+        None,
+        None,
+        None,
+        None,
+        None,
+        &[],
+        template_vars,
+        templating::PostInitPolicy::Skip,
+        writer,
+        ".",
+    )
+    .map(|_| ())
+    .map_err(Error::RegenFailed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        input,
+        expected,
+        case("", 0xcbf2_9ce4_8422_2325),
+        case("a", 0xaf63_dc4c_8601_ec8c),
+        case("foobar", 0x8594_4171_f739_67e8)
+    )]
+    fn test_fnv1a(input: &str, expected: u64) {
+        assert_eq!(fnv1a(input.as_bytes()), expected);
+    }
+
+    #[test]
+    fn test_canonical() {
+        let a = json!({ "b": [2, 1], "a": { "d": null, "c": "x" } });
+        let mut map = serde_json::Map::new();
+        map.insert("a".to_owned(), json!({ "c": "x", "d": null }));
+        map.insert("b".to_owned(), json!([2, 1]));
+        let b = Value::Object(map);
+        assert_eq!(canonical(&a), r#"{"a":{"c":"x","d":null},"b":[2,1]}"#);
+        assert_eq!(canonical(&a), canonical(&b));
+        assert_ne!(canonical(&a), canonical(&json!({ "b": [1, 2] })));
+    }
+}
@@ -0,0 +1,362 @@
+//! How much of the machine builds get to use. By default, cargo, Gradle, and
+//! `xcodebuild` each pick their own concurrency and memory, which is fine on
+//! a workstation, but together they can run a small CI runner out of memory.
+//!
+//! `--jobs` (or `CARGO_BUILD_JOBS`, or `app.jobs`) caps cargo's jobs, Gradle's
+//! workers, and `xcodebuild -jobs` all at once, and `android.gradle-heap-mib`
+//! sets the Gradle daemon's heap. Either can be `auto`, which is also the
+//! default: nothing's changed unless the machine (or the cgroup we're in) is
+//! short on cores or memory, in which case conservative values are used.
+
+use crate::{os, ui, util};
+use once_cell_regex::exports::once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display},
+    str::FromStr,
+    sync::Mutex,
+};
+
+/// The env var cargo reads its job count from, which is also how the cargo
+/// we run from Gradle and Xcode find out how many jobs they get.
+pub static JOBS_VAR: &str = "CARGO_BUILD_JOBS";
+
+const MIB: u64 = 1 << 20;
+const GIB: u64 = 1 << 30;
+
+// Below either of these, the tools' defaults are likely to be too much.
+const CONSTRAINED_CORES: usize = 2;
+const CONSTRAINED_MEMORY: u64 = 8 * GIB;
+
+// What a rustc job can take on a big crate, which is what bounds how many can
+// run at once when memory's tight.
+const MEMORY_PER_JOB: u64 = 2 * GIB;
+
+const MIN_GRADLE_HEAP_MIB: u64 = 512;
+// The generated `gradle.properties` asks for this much anyway.
+const MAX_GRADLE_HEAP_MIB: u64 = 1536;
+
+static PLAN: Lazy<Mutex<Option<Plan>>> = Lazy::new(Default::default);
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum SettingRaw {
+    Fixed(u32),
+    Keyword(String),
+}
+
+/// A number, or `auto`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "SettingRaw", into = "SettingRaw")]
+pub enum Setting {
+    Auto,
+    Fixed(u32),
+}
+
+impl Default for Setting {
+    fn default() -> Self {
+        Self::Auto
+    }
+}
+
+impl Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Fixed(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl FromStr for Setting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            _ if s == "auto" => Ok(Self::Auto),
+            Ok(0) => Err("has to be at least 1".to_owned()),
+            Ok(value) => Ok(Self::Fixed(value)),
+            Err(_) => Err(format!("expected a number or `auto`, but got {:?}", s)),
+        }
+    }
+}
+
+impl TryFrom<SettingRaw> for Setting {
+    type Error = String;
+
+    fn try_from(raw: SettingRaw) -> Result<Self, Self::Error> {
+        match raw {
+            SettingRaw::Fixed(value) => value.to_string().parse(),
+            SettingRaw::Keyword(keyword) => keyword.parse(),
+        }
+    }
+}
+
+impl From<Setting> for SettingRaw {
+    fn from(setting: Setting) -> Self {
+        match setting {
+            Setting::Auto => Self::Keyword("auto".to_owned()),
+            Setting::Fixed(value) => Self::Fixed(value),
+        }
+    }
+}
+
+/// What we found out about the machine.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Machine {
+    cores: usize,
+    memory: Option<u64>,
+    /// Whether either of those is our cgroup's limit, rather than the
+    /// machine's.
+    cgroup: bool,
+}
+
+impl Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} core{}",
+            self.cores,
+            if self.cores == 1 { "" } else { "s" }
+        )?;
+        if let Some(memory) = self.memory {
+            write!(f, ", {} of memory", util::display_size(memory))?;
+        }
+        if self.cgroup {
+            write!(f, " (cgroup limits)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Machine {
+    pub fn detect() -> Self {
+        let available = std::thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(1);
+        let quota = os::cpu_quota().filter(|quota| *quota < available);
+        let memory = os::memory_limit();
+        Self {
+            cores: quota.unwrap_or(available),
+            memory: memory.map(|(memory, _)| memory),
+            cgroup: quota.is_some() || memory.map_or(false, |(_, cgroup)| cgroup),
+        }
+    }
+
+    pub fn constrained(&self) -> bool {
+        self.cores <= CONSTRAINED_CORES
+            || self
+                .memory
+                .map_or(false, |memory| memory < CONSTRAINED_MEMORY)
+    }
+}
+
+/// What builds get to use. `None` leaves it up to the tool.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Plan {
+    jobs: Option<u32>,
+    gradle_heap_mib: Option<u64>,
+    constrained: bool,
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.jobs {
+            Some(jobs) => write!(f, "{} job{}", jobs, if jobs == 1 { "" } else { "s" })?,
+            None => write!(f, "default jobs")?,
+        }
+        if let Some(heap) = self.gradle_heap_mib {
+            write!(f, ", {} MiB Gradle heap", heap)?;
+        }
+        Ok(())
+    }
+}
+
+impl Plan {
+    fn new(machine: Option<&Machine>, jobs: Setting, gradle_heap: Option<Setting>) -> Self {
+        let constrained = machine.filter(|machine| machine.constrained());
+        let gradle_heap_mib = match (gradle_heap, constrained) {
+            (Some(Setting::Fixed(heap)), _) => Some(u64::from(heap)),
+            (Some(Setting::Auto), Some(machine)) => Some(
+                machine
+                    .memory
+                    // A sixth of the memory, to the nearest 256 MiB below.
+                    .map_or(MAX_GRADLE_HEAP_MIB, |memory| memory / 6 / MIB / 256 * 256)
+                    .max(MIN_GRADLE_HEAP_MIB)
+                    .min(MAX_GRADLE_HEAP_MIB),
+            ),
+            _ => None,
+        };
+        let jobs = match (jobs, constrained) {
+            (Setting::Fixed(jobs), _) => Some(jobs),
+            (Setting::Auto, Some(machine)) => {
+                let cores = machine.cores as u64;
+                let by_memory = machine.memory.map_or(cores, |memory| {
+                    memory.saturating_sub(gradle_heap_mib.unwrap_or(0) * MIB) / MEMORY_PER_JOB
+                });
+                Some(by_memory.min(cores).max(1) as u32)
+            }
+            (Setting::Auto, None) => None,
+        };
+        Self {
+            jobs,
+            gradle_heap_mib,
+            constrained: constrained.is_some(),
+        }
+    }
+
+    /// How many jobs cargo gets, which is also how many Gradle workers and
+    /// `xcodebuild` jobs there are.
+    pub fn jobs(&self) -> Option<u32> {
+        self.jobs
+    }
+
+    pub fn gradle_heap_mib(&self) -> Option<u64> {
+        self.gradle_heap_mib
+    }
+
+    /// Whether building more than one target at a time is asking for trouble.
+    pub fn constrained(&self) -> bool {
+        self.constrained
+    }
+
+    /// `CARGO_BUILD_JOBS`, for passing to whatever runs cargo for us.
+    pub fn env_vars(&self) -> Option<(&'static str, String)> {
+        self.jobs.map(|jobs| (JOBS_VAR, jobs.to_string()))
+    }
+}
+
+fn inherited_jobs() -> Option<Setting> {
+    std::env::var(JOBS_VAR)
+        .ok()
+        .and_then(|jobs| match jobs.parse() {
+            Ok(jobs) => Some(jobs),
+            Err(err) => {
+                log::warn!("ignoring `{}={}`: {}", JOBS_VAR, jobs, err);
+                None
+            }
+        })
+}
+
+/// Decides what this build gets to use and says so, unless we were run by a
+/// build that already did (in which case `CARGO_BUILD_JOBS` is set). `jobs`
+/// is from `--jobs`, which wins over `CARGO_BUILD_JOBS`, which wins over
+/// `config_jobs`. `gradle_heap` is `None` when Gradle isn't involved.
+pub fn configure(
+    jobs: Option<Setting>,
+    config_jobs: Setting,
+    gradle_heap: Option<Setting>,
+) -> Plan {
+    let inherited = inherited_jobs();
+    let nested = jobs.is_none() && inherited.is_some();
+    let jobs = jobs.or(inherited).unwrap_or(config_jobs);
+    // Nothing's auto, so there's no need to look.
+    let machine = if jobs == Setting::Auto || gradle_heap == Some(Setting::Auto) {
+        Some(Machine::detect())
+    } else {
+        None
+    };
+    let plan = Plan::new(machine.as_ref(), jobs, gradle_heap);
+    let msg = match machine {
+        Some(machine) => format!("Building with {} on {}", plan, machine),
+        None => format!("Building with {}", plan),
+    };
+    if nested {
+        log::info!("{}", msg);
+    } else {
+        ui::status(msg);
+    }
+    *PLAN
+        .lock()
+        .expect("developer error: resource plan lock poisoned") = Some(plan);
+    plan
+}
+
+/// The plan from `configure`, or if it hasn't been called, whatever
+/// `CARGO_BUILD_JOBS` says (which the build that ran us would've set).
+pub fn current() -> Plan {
+    PLAN.lock()
+        .expect("developer error: resource plan lock poisoned")
+        .unwrap_or_else(|| Plan {
+            jobs: match inherited_jobs() {
+                Some(Setting::Fixed(jobs)) => Some(jobs),
+                _ => None,
+            },
+            ..Default::default()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    const CI_RUNNER: Machine = Machine {
+        cores: 2,
+        memory: Some(7 * GIB),
+        cgroup: false,
+    };
+
+    const WORKSTATION: Machine = Machine {
+        cores: 16,
+        memory: Some(64 * GIB),
+        cgroup: false,
+    };
+
+    const CONTAINER: Machine = Machine {
+        cores: 8,
+        memory: Some(3 * GIB),
+        cgroup: true,
+    };
+
+    #[rstest(
+        s,
+        expected,
+        case("auto", Ok(Setting::Auto)),
+        case("4", Ok(Setting::Fixed(4))),
+        case("0", Err(())),
+        case("lots", Err(()))
+    )]
+    fn test_parse_setting(s: &str, expected: Result<Setting, ()>) {
+        assert_eq!(s.parse::<Setting>().map_err(|_| ()), expected);
+    }
+
+    #[rstest(
+        machine,
+        jobs,
+        gradle_heap,
+        expected_jobs,
+        expected_heap,
+        case(WORKSTATION, Setting::Auto, Some(Setting::Auto), None, None),
+        case(
+            WORKSTATION,
+            Setting::Fixed(3),
+            Some(Setting::Fixed(4096)),
+            Some(3),
+            Some(4096)
+        ),
+        case(CI_RUNNER, Setting::Auto, Some(Setting::Auto), Some(2), Some(1024)),
+        case(CI_RUNNER, Setting::Auto, None, Some(2), None),
+        case(CI_RUNNER, Setting::Fixed(4), Some(Setting::Auto), Some(4), Some(1024)),
+        case(CONTAINER, Setting::Auto, Some(Setting::Auto), Some(1), Some(512)),
+        case(
+            CONTAINER,
+            Setting::Auto,
+            Some(Setting::Fixed(2048)),
+            Some(1),
+            Some(2048)
+        )
+    )]
+    fn test_plan(
+        machine: Machine,
+        jobs: Setting,
+        gradle_heap: Option<Setting>,
+        expected_jobs: Option<u32>,
+        expected_heap: Option<u64>,
+    ) {
+        let plan = Plan::new(Some(&machine), jobs, gradle_heap);
+        assert_eq!(plan.jobs(), expected_jobs);
+        assert_eq!(plan.gradle_heap_mib(), expected_heap);
+    }
+}
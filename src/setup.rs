@@ -0,0 +1,210 @@
+//! `cargo mobile setup`, which runs the doctor checks and walks through
+//! fixing whatever failed, so that a fresh machine doesn't have to get ready
+//! one error at a time. Each fix is optional, and they're offered in
+//! dependency order; without a terminal to ask in, they're printed as a
+//! checklist instead.
+
+use crate::{
+    doctor::{self, Fix, Item, Status},
+    opts, ui,
+    util::{
+        cli::{Report, Reportable, TextWrapper},
+        prompt,
+    },
+};
+use colored::Colorize as _;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    PromptFailed(io::Error),
+    StillFailing(doctor::Failed),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::PromptFailed(err) => Report::error("Failed to prompt for fix", err),
+            Self::StillFailing(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::PromptFailed(..) => "setup.prompt-failed",
+            Self::StillFailing(err) => err.code(),
+        }
+    }
+}
+
+// Args are quoted if the shell would otherwise mangle them, so that the
+// checklist can be copied and pasted.
+fn display_command(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|arg| {
+            if !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:+@".contains(c))
+            {
+                arg.clone()
+            } else {
+                format!("{:?}", arg)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Warnings are included, since some of them (like having nowhere to run)
+// are worth fixing too.
+fn fixable(sections: &[doctor::Section]) -> Vec<(&Item, &Fix)> {
+    let mut fixable = sections
+        .iter()
+        .flat_map(|section| section.items())
+        .filter(|item| matches!(item.status(), Status::Fail | Status::Warn))
+        .filter_map(|item| item.fix().map(|fix| (item, fix)))
+        .collect::<Vec<_>>();
+    // Stable, so checks keep their order within a stage.
+    fixable.sort_by_key(|(_, fix)| fix.stage());
+    fixable
+}
+
+fn print_step(wrapper: &TextWrapper, step: usize, item: &Item) {
+    println!(
+        "{}",
+        wrapper.fill(&format!("{}. {}", step, item.msg())).bold()
+    );
+    if let Some(hint) = item.hint() {
+        println!("{}", wrapper.fill(&format!("   hint: {}", hint)));
+    }
+    if let Some(docs) = item.docs() {
+        println!("   docs: {}", docs);
+    }
+}
+
+fn print_checklist(wrapper: &TextWrapper, fixable: &[(&Item, &Fix)]) {
+    println!("To finish setting up, work through this checklist:");
+    for (i, (item, fix)) in fixable.iter().enumerate() {
+        println!();
+        print_step(wrapper, i + 1, item);
+        for command in fix.commands() {
+            println!("   $ {}", display_command(command));
+        }
+    }
+}
+
+fn ask(command: &str) -> Result<bool, Error> {
+    loop {
+        if let Some(answer) =
+            prompt::yes_no(format!("Run `{}`?", command), Some(prompt::YesOrNo::Yes))
+                .map_err(Error::PromptFailed)?
+        {
+            break Ok(answer.yes());
+        }
+    }
+}
+
+// Stops at the first command that fails, since the rest usually depend on it.
+fn apply(wrapper: &TextWrapper, fix: &Fix) -> Result<(), Error> {
+    if fix.commands().is_empty() {
+        println!("This one's up to you; setup will continue with the next step.");
+        return Ok(());
+    }
+    for command in fix.commands() {
+        let display = display_command(command);
+        if !ask(&display)? {
+            println!("Skipped.");
+            return Ok(());
+        }
+        let result = bossy::Command::impure(&command[0])
+            .with_args(&command[1..])
+            .run_and_wait();
+        if let Err(err) = result {
+            Report::action_request(format!("`{}` failed", display), err).print(wrapper);
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Runs every doctor check, fixes whatever failed that the user agrees to
+/// fix, and then checks again to show where things stand. It's an error if
+/// anything's still failing at the end.
+pub fn exec(wrapper: &TextWrapper, non_interactive: opts::NonInteractive) -> Result<(), Error> {
+    let sections = doctor::check(&[]);
+    let result = doctor::exec(wrapper, &sections, false).map_err(Error::StillFailing);
+    println!();
+    let fixable = fixable(&sections);
+    if fixable.is_empty() {
+        println!("There's nothing setup can help with.");
+        return result;
+    }
+    if non_interactive.yes() {
+        print_checklist(wrapper, &fixable);
+        return result;
+    }
+    for (i, (item, fix)) in fixable.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_step(wrapper, i + 1, item);
+        apply(wrapper, fix)?;
+    }
+    ui::status("\nChecking again...\n");
+    doctor::exec(wrapper, &doctor::check(&[]), false).map_err(Error::StillFailing)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::doctor::{Section, SectionName, Stage};
+    use rstest::rstest;
+
+    #[rstest(
+        command,
+        expected,
+        case(
+            &["rustup", "target", "add", "aarch64-linux-android"],
+            "rustup target add aarch64-linux-android"
+        ),
+        case(
+            &["sdkmanager", "system-images;android-30;google_apis;x86_64"],
+            r#"sdkmanager "system-images;android-30;google_apis;x86_64""#
+        ),
+        case(
+            &["/Android Sdk/tools/bin/sdkmanager", "--licenses"],
+            r#""/Android Sdk/tools/bin/sdkmanager" --licenses"#
+        )
+    )]
+    fn test_display_command(command: &[&str], expected: &str) {
+        let command = command
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(display_command(&command), expected);
+    }
+
+    #[test]
+    fn test_fixable_order() {
+        let mut section = Section::new(SectionName::Android, "Android");
+        section.push(
+            Item::warn("no devices")
+                .with_fix(Fix::run(Stage::Devices, &["avdmanager", "create", "avd"])),
+        );
+        section.push(Item::fail("no licenses").with_fix(Fix::run(Stage::Android, &["sdkmanager"])));
+        section.push(Item::fail("no fix"));
+        section.push(Item::pass("fine").with_fix(Fix::manual(Stage::Android)));
+        section.push(Item::fail("no targets").with_fix(Fix::run(Stage::Rust, &["rustup"])));
+        section.push(Item::fail("no ndk").with_fix(Fix::run(Stage::Android, &["sdkmanager"])));
+        let sections = [section];
+        assert_eq!(
+            fixable(&sections)
+                .into_iter()
+                .map(|(item, _)| item.msg())
+                .collect::<Vec<_>>(),
+            vec!["no targets", "no licenses", "no ndk", "no devices"]
+        );
+    }
+}
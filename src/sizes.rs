@@ -0,0 +1,476 @@
+//! Sizes of what a build produced, compared to the last build with the same
+//! profile, so that growth gets noticed before it ships.
+//!
+//! Only what actually ships is measured, so libs are measured after they've
+//! been stripped, and apps and APKs after they've been packaged. The last sizes
+//! are kept in `gen/`, so that host projects' dirs are left alone.
+
+use crate::{
+    config::app::App,
+    generated,
+    opts::Profile,
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// How to print the summary, as selected by `--size-report`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("{:?} isn't a size report format", s)),
+        }
+    }
+}
+
+/// What to do when an artifact grows by more than `max-growth-percent`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnGrowth {
+    Warn,
+    Error,
+}
+
+impl Default for OnGrowth {
+    fn default() -> Self {
+        Self::Warn
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Raw {
+    max_growth_percent: Option<f64>,
+    on_growth: Option<OnGrowth>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Policy {
+    max_growth_percent: Option<f64>,
+    on_growth: OnGrowth,
+}
+
+impl Policy {
+    pub fn from_raw(raw: Option<Raw>) -> Self {
+        let raw = raw.unwrap_or_default();
+        Self {
+            max_growth_percent: raw.max_growth_percent,
+            on_growth: raw.on_growth.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    MeasureFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    Grew {
+        max_growth_percent: f64,
+        grown: Vec<(String, f64)>,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::MeasureFailed { path, cause } => {
+                Report::error(format!("Failed to measure size of {:?}", path), cause)
+            }
+            Self::Grew {
+                max_growth_percent,
+                grown,
+            } => Report::error(
+                format!(
+                    "Build artifacts grew by more than {}% since the last build",
+                    max_growth_percent
+                ),
+                describe_growth(grown),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::MeasureFailed { .. } => "sizes.measure-failed",
+            Self::Grew { .. } => "sizes.grew",
+        }
+    }
+}
+
+fn describe_growth(grown: &[(String, f64)]) -> String {
+    grown
+        .iter()
+        .map(|(name, percent)| format!("{} grew by {:.1}%", name, percent))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Symlinks are followed, since the `jniLibs` dir is full of them, and `.app`
+// bundles are directories.
+fn size_of(path: &Path) -> io::Result<u64> {
+    let metadata = fs::metadata(path)?;
+    if metadata.is_dir() {
+        let mut total = 0;
+        for entry in fs::read_dir(path)? {
+            total += size_of(&entry?.path())?;
+        }
+        Ok(total)
+    } else {
+        Ok(metadata.len())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Artifact {
+    name: String,
+    bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unstripped_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta_bytes: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta_percent: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    profile: &'static str,
+    artifacts: Vec<Artifact>,
+    total_bytes: u64,
+}
+
+fn format_delta(bytes: i64, percent: Option<f64>) -> String {
+    let sign = if bytes < 0 { "-" } else { "+" };
+    let mut formatted = format!("{}{}", sign, util::display_size(bytes.abs() as u64));
+    if let Some(percent) = percent {
+        let _ = write!(formatted, " ({}{:.1}%)", sign, percent.abs());
+    }
+    formatted
+}
+
+#[derive(Debug)]
+struct Measured {
+    name: String,
+    bytes: u64,
+    unstripped_bytes: Option<u64>,
+    /// Whether this is an app or APK, which the libs are inside of.
+    package: bool,
+}
+
+impl Summary {
+    fn new(profile: Profile, measured: Vec<Measured>, last: &BTreeMap<String, u64>) -> Self {
+        // Libs that were packaged are already counted in their packages.
+        let packaged = measured.iter().any(|measured| measured.package);
+        let total_bytes = measured
+            .iter()
+            .filter(|measured| measured.package == packaged)
+            .map(|measured| measured.bytes)
+            .sum();
+        Self {
+            profile: profile.as_str(),
+            artifacts: measured
+                .into_iter()
+                .map(|measured| {
+                    let Measured {
+                        name,
+                        bytes,
+                        unstripped_bytes,
+                        ..
+                    } = measured;
+                    let last = last.get(&name).copied();
+                    Artifact {
+                        delta_bytes: last.map(|last| bytes as i64 - last as i64),
+                        delta_percent: last
+                            .filter(|last| *last > 0)
+                            .map(|last| (bytes as f64 - last as f64) / last as f64 * 100.0),
+                        name,
+                        bytes,
+                        unstripped_bytes,
+                    }
+                })
+                .collect(),
+            total_bytes,
+        }
+    }
+
+    fn bytes_by_name(&self) -> BTreeMap<&str, u64> {
+        self.artifacts
+            .iter()
+            .map(|artifact| (artifact.name.as_str(), artifact.bytes))
+            .collect()
+    }
+
+    /// The artifacts that grew by more than `max_growth_percent`, and by how
+    /// much.
+    fn grown(&self, max_growth_percent: f64) -> Vec<(String, f64)> {
+        self.artifacts
+            .iter()
+            .filter_map(|artifact| {
+                artifact
+                    .delta_percent
+                    .filter(|percent| *percent > max_growth_percent)
+                    .map(|percent| (artifact.name.clone(), percent))
+            })
+            .collect()
+    }
+
+    pub fn print(&self, format: Format) {
+        match format {
+            Format::Text => print!("{}", self.render()),
+            Format::Json => println!("{}", serde_json::json!(self)),
+        }
+    }
+
+    /// Growth past the policy's threshold is warned about, or fails if the
+    /// policy says so. Either way, the sizes were already recorded, so the next
+    /// build compares against this one.
+    pub fn check(&self, policy: Policy) -> Result<(), Error> {
+        let max_growth_percent = match policy.max_growth_percent {
+            Some(max_growth_percent) => max_growth_percent,
+            None => return Ok(()),
+        };
+        let grown = self.grown(max_growth_percent);
+        if grown.is_empty() {
+            return Ok(());
+        }
+        match policy.on_growth {
+            OnGrowth::Warn => {
+                log::warn!(
+                    "build artifacts grew by more than {}% since the last build:\n{}",
+                    max_growth_percent,
+                    describe_growth(&grown)
+                );
+                Ok(())
+            }
+            OnGrowth::Error => Err(Error::Grew {
+                max_growth_percent,
+                grown,
+            }),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let width = self
+            .artifacts
+            .iter()
+            .map(|artifact| artifact.name.len())
+            .max()
+            .unwrap_or_default()
+            .max("total".len());
+        let mut rendered = format!("Sizes ({}):\n", self.profile);
+        for artifact in &self.artifacts {
+            let _ = write!(
+                rendered,
+                "  {:width$}  {:>10}",
+                artifact.name,
+                util::display_size(artifact.bytes),
+                width = width
+            );
+            if let Some(unstripped) = artifact.unstripped_bytes {
+                let _ = write!(
+                    rendered,
+                    "  ({} unstripped)",
+                    util::display_size(unstripped)
+                );
+            }
+            if let Some(delta) = artifact.delta_bytes {
+                let _ = write!(
+                    rendered,
+                    "  {} vs last build",
+                    format_delta(delta, artifact.delta_percent)
+                );
+            }
+            rendered.push('\n');
+        }
+        let _ = writeln!(
+            rendered,
+            "  {:width$}  {:>10}",
+            "total",
+            util::display_size(self.total_bytes),
+            width = width
+        );
+        rendered
+    }
+}
+
+/// Collects the sizes of a build's artifacts, in the order they're measured.
+#[derive(Debug, Default)]
+pub struct Sizes {
+    measured: Vec<Measured>,
+}
+
+impl Sizes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(
+        &mut self,
+        name: String,
+        path: &Path,
+        unstripped: Option<&Path>,
+        package: bool,
+    ) -> Result<(), Error> {
+        let measure = |path: &Path| {
+            size_of(path).map_err(|cause| Error::MeasureFailed {
+                path: path.to_owned(),
+                cause,
+            })
+        };
+        let bytes = measure(path)?;
+        let unstripped_bytes = unstripped
+            .filter(|unstripped| unstripped.exists())
+            .map(measure)
+            .transpose()?;
+        self.measured.push(Measured {
+            name,
+            bytes,
+            unstripped_bytes,
+            package,
+        });
+        Ok(())
+    }
+
+    /// Measures the lib at `path` as `name`. `unstripped` is where the
+    /// unstripped copy is, if there is one.
+    pub fn measure(
+        &mut self,
+        name: impl Into<String>,
+        path: &Path,
+        unstripped: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.push(name.into(), path, unstripped, false)
+    }
+
+    /// Measures the app or APK at `path` as `name`, which the libs measured
+    /// alongside it were packaged into.
+    pub fn measure_package(&mut self, name: impl Into<String>, path: &Path) -> Result<(), Error> {
+        self.push(name.into(), path, None, true)
+    }
+
+    /// Records these sizes in `gen/` for next time, and returns how they
+    /// compare to the last build for `platform` with the same profile.
+    /// There's nothing to summarize if nothing was measured.
+    pub fn summarize(self, app: &App, platform: &str, profile: Profile) -> Option<Summary> {
+        if self.measured.is_empty() {
+            return None;
+        }
+        let dir = generated::gen_dir(app);
+        let path = dir.join(format!(".sizes-{}-{}.json", platform, profile.as_str()));
+        let last = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        let summary = Summary::new(profile, self.measured, &last);
+        let result = serde_json::to_vec_pretty(&summary.bytes_by_name())
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+            .and_then(|bytes| {
+                fs::create_dir_all(&dir)?;
+                fs::write(&path, bytes)
+            });
+        if let Err(err) = result {
+            log::warn!("failed to record sizes in {:?}: {}", path, err);
+        }
+        Some(summary)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summary_compares_to_last_build() {
+        let last = vec![
+            ("libapp.so (arm64-v8a)".to_owned(), 1000),
+            ("app.apk".to_owned(), 4000),
+        ]
+        .into_iter()
+        .collect();
+        let measured = |name: &str, bytes, unstripped_bytes, package| Measured {
+            name: name.to_owned(),
+            bytes,
+            unstripped_bytes,
+            package,
+        };
+        let summary = Summary::new(
+            Profile::Release,
+            vec![
+                measured("libapp.so (arm64-v8a)", 1150, Some(5000), false),
+                measured("app.apk", 3900, None, true),
+                measured("libapp.so (x86_64)", 1200, Some(5200), false),
+            ],
+            &last,
+        );
+        let deltas = summary
+            .artifacts
+            .iter()
+            .map(|artifact| (artifact.delta_bytes, artifact.delta_percent))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            deltas,
+            vec![
+                (Some(150), Some(15.0)),
+                (Some(-100), Some(-2.5)),
+                (None, None)
+            ]
+        );
+        // The libs are inside the APK.
+        assert_eq!(summary.total_bytes, 3900);
+        assert_eq!(
+            summary.grown(10.0),
+            vec![("libapp.so (arm64-v8a)".to_owned(), 15.0)]
+        );
+        assert!(summary.grown(20.0).is_empty());
+    }
+
+    #[test]
+    fn test_total_without_packages_is_libs() {
+        let summary = Summary::new(
+            Profile::Debug,
+            vec![
+                Measured {
+                    name: "libapp.so (arm64-v8a)".to_owned(),
+                    bytes: 1000,
+                    unstripped_bytes: None,
+                    package: false,
+                },
+                Measured {
+                    name: "libapp.so (x86_64)".to_owned(),
+                    bytes: 1200,
+                    unstripped_bytes: None,
+                    package: false,
+                },
+            ],
+            &BTreeMap::new(),
+        );
+        assert_eq!(summary.total_bytes, 2200);
+    }
+
+    #[test]
+    fn test_format_delta() {
+        assert_eq!(format_delta(2048, Some(12.34)), "+2.0 KiB (+12.3%)");
+        assert_eq!(format_delta(-512, None), "-512 B");
+    }
+}
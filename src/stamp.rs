@@ -0,0 +1,235 @@
+//! Which cargo-mobile generated `gen/`. Switching branches, or working with
+//! someone on a different version, can leave `gen/` laid out differently
+//! than this cargo-mobile expects, which otherwise only shows up as a
+//! baffling failure somewhere deep in a build.
+//!
+//! What matters is the schema, which is only bumped when the layout changes
+//! in a way the other side can't cope with; different versions that agree on
+//! the schema get along fine.
+
+use crate::{
+    ui,
+    util::{
+        self,
+        cli::{Report, Reportable},
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::Path,
+};
+
+static FILE_NAME: &str = ".cargo-mobile-version";
+
+/// Bump this whenever `gen/` changes in a way older or newer versions of
+/// cargo-mobile can't build from.
+pub const SCHEMA: u32 = 1;
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Stamp {
+    version: Option<String>,
+    commit: Option<String>,
+    schema: Option<u32>,
+}
+
+impl Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "v{}", version)?,
+            None => write!(f, "an unknown version")?,
+        }
+        if let Some(commit) = &self.commit {
+            write!(f, " ({:?})", commit)?;
+        }
+        Ok(())
+    }
+}
+
+impl Stamp {
+    /// This cargo-mobile, with the commit it was installed from if it was
+    /// installed from a checkout.
+    pub fn current() -> Self {
+        let commit = util::installed_commit_msg()
+            .unwrap_or_else(|err| {
+                log::warn!("failed to get current commit msg: {}", err);
+                None
+            })
+            .and_then(|msg| msg.lines().next().map(|line| line.trim().to_owned()))
+            .filter(|line| !line.is_empty());
+        Self {
+            version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            commit,
+            schema: Some(SCHEMA),
+        }
+    }
+
+    // What's left behind once we've warned about a missing stamp, so that we
+    // only do it once.
+    fn unknown() -> Self {
+        Self {
+            version: None,
+            commit: None,
+            schema: None,
+        }
+    }
+
+    fn read(gen_dir: &Path) -> Option<Self> {
+        let path = gen_dir.join(FILE_NAME);
+        let contents = fs::read_to_string(&path).ok()?;
+        toml::from_str(&contents)
+            .map_err(|err| log::warn!("failed to parse {:?}: {}", path, err))
+            .ok()
+    }
+
+    fn write(&self, gen_dir: &Path) {
+        let path = gen_dir.join(FILE_NAME);
+        let contents = toml::to_string(self).expect("developer error: stamp not serializable");
+        if let Err(err) = fs::create_dir_all(gen_dir).and_then(|()| fs::write(&path, contents)) {
+            log::warn!("failed to write {:?}: {}", path, err);
+        }
+    }
+}
+
+/// Stamps `gen_dir` as generated by this cargo-mobile.
+pub fn record(gen_dir: &Path) {
+    log::info!("stamping {:?} with cargo-mobile's version", gen_dir);
+    Stamp::current().write(gen_dir);
+}
+
+#[derive(Debug)]
+pub struct Mismatch {
+    recorded: Stamp,
+    current: Stamp,
+}
+
+impl Mismatch {
+    fn newer(&self) -> bool {
+        self.recorded.schema > self.current.schema
+    }
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`gen` is from cargo-mobile {}, but this is cargo-mobile {}, and their layouts don't match",
+            self.recorded, self.current
+        )
+    }
+}
+
+impl Reportable for Mismatch {
+    fn report(&self) -> Report {
+        let (msg, fix) = if self.newer() {
+            (
+                "Your projects were generated by a newer cargo-mobile",
+                "Update cargo-mobile with `cargo mobile update`, or run `cargo mobile regen` to regenerate them with this version",
+            )
+        } else {
+            (
+                "Your projects were generated by an older cargo-mobile",
+                "Run `cargo mobile regen` to regenerate them with this version",
+            )
+        };
+        Report::action_request(
+            msg,
+            format!(
+                "{}, so building from it would likely fail in confusing ways. {}, or pass `--ignore-version-mismatch` to carry on anyway.",
+                self, fix
+            ),
+        )
+    }
+
+    fn code(&self) -> &'static str {
+        "stamp.mismatch"
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Comparison {
+    Compatible,
+    Unstamped,
+    Mismatched,
+}
+
+fn compare(recorded: Option<&Stamp>, current: &Stamp) -> Comparison {
+    match recorded {
+        None => Comparison::Unstamped,
+        // Warned about already.
+        Some(Stamp { schema: None, .. }) => Comparison::Compatible,
+        Some(recorded) if recorded.schema == current.schema => Comparison::Compatible,
+        Some(_) => Comparison::Mismatched,
+    }
+}
+
+/// Checks that `gen_dir` was generated by a cargo-mobile that agrees with us
+/// on its layout. Projects from before stamps are assumed to be fine, but get
+/// a warning the first time.
+pub fn check(gen_dir: &Path) -> Result<(), Mismatch> {
+    let recorded = Stamp::read(gen_dir);
+    let current = Stamp::current();
+    match compare(recorded.as_ref(), &current) {
+        Comparison::Compatible => {
+            log::info!(
+                "{:?} is from cargo-mobile {}, which is compatible",
+                gen_dir,
+                recorded.unwrap_or_else(Stamp::unknown)
+            );
+            Ok(())
+        }
+        Comparison::Unstamped => {
+            ui::report(&Report::action_request(
+                "Your projects don't say which cargo-mobile generated them",
+                "They're probably fine, so this is the only time you'll hear about it. Run `cargo mobile regen` to have them record it, so that you'll be told if they're ever generated by a version this one can't build from.",
+            ));
+            Stamp::unknown().write(gen_dir);
+            Ok(())
+        }
+        Comparison::Mismatched => Err(Mismatch {
+            recorded: recorded.expect("developer error: mismatched without a stamp"),
+            current,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+    use rstest::rstest;
+
+    fn stamp(schema: Option<u32>) -> Stamp {
+        Stamp {
+            version: Some("0.1.0".to_owned()),
+            commit: None,
+            schema,
+        }
+    }
+
+    #[rstest(
+        recorded,
+        expected,
+        case(None, Comparison::Unstamped),
+        case(Some(stamp(None)), Comparison::Compatible),
+        case(Some(stamp(Some(SCHEMA))), Comparison::Compatible),
+        case(Some(stamp(Some(SCHEMA + 1))), Comparison::Mismatched),
+        case(Some(stamp(Some(SCHEMA - 1))), Comparison::Mismatched)
+    )]
+    fn test_compare(recorded: Option<Stamp>, expected: Comparison) {
+        assert_eq!(compare(recorded.as_ref(), &Stamp::current()), expected);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let temp_dir = TempDir::new("stamp-test").unwrap();
+        let gen_dir = temp_dir.path();
+        Stamp::current().write(gen_dir);
+        let read = Stamp::read(gen_dir);
+        Stamp::unknown().write(gen_dir);
+        let unknown = Stamp::read(gen_dir);
+        assert_eq!(read, Some(Stamp::current()));
+        assert_eq!(unknown, Some(Stamp::unknown()));
+    }
+}
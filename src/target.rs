@@ -1,5 +1,6 @@
-use crate::util;
+use crate::{device::Kind, util};
 use once_cell_regex::exports::once_cell::sync::OnceCell;
+use serde::Serialize;
 use std::{
     collections::BTreeMap,
     fmt::{self, Debug, Display},
@@ -8,6 +9,11 @@ use std::{
 pub trait TargetTrait<'a>: Debug + Sized {
     const DEFAULT_KEY: &'static str;
 
+    /// Other names targets can be selected by, paired with the key of the
+    /// target they stand for. Triples are always accepted too, so they don't
+    /// need to be listed here.
+    const ALIASES: &'static [(&'static str, &'static str)] = &[];
+
     fn all() -> &'a BTreeMap<&'a str, Self>;
 
     fn name_list() -> &'static [&'a str]
@@ -18,6 +24,12 @@ pub trait TargetTrait<'a>: Debug + Sized {
         INSTANCE.get_or_init(|| Self::all().keys().map(|key| *key).collect::<Vec<_>>())
     }
 
+    /// Everything [`Self::for_name`] accepts, for clap to check args against
+    /// and for shell completion to offer. Each implementation keeps these in
+    /// its own static, since one in a default method would be shared by all
+    /// of them; [`possible_values`] does the rest.
+    fn possible_values() -> &'static [&'static str];
+
     fn default_ref() -> &'a Self {
         Self::all()
             .get(Self::DEFAULT_KEY)
@@ -25,7 +37,22 @@ pub trait TargetTrait<'a>: Debug + Sized {
     }
 
     fn for_name(name: &str) -> Option<&'a Self> {
-        Self::all().get(name)
+        let key = Self::ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map(|(_, key)| *key)
+            .unwrap_or(name);
+        Self::all()
+            .get(key)
+            .or_else(|| Self::all().values().find(|target| target.triple() == name))
+    }
+
+    fn aliases_for(key: &str) -> Vec<&'static str> {
+        Self::ALIASES
+            .iter()
+            .filter(|(_, aliased)| *aliased == key)
+            .map(|(alias, _)| *alias)
+            .collect()
     }
 
     fn for_arch(arch: &str) -> Option<&'a Self> {
@@ -36,7 +63,10 @@ pub trait TargetTrait<'a>: Debug + Sized {
 
     fn arch(&'a self) -> &'a str;
 
-    fn install(&'a self) -> bossy::Result<bossy::ExitStatus> {
+    /// Whether this target runs on devices or on simulators/emulators.
+    fn kind(&'a self) -> Kind;
+
+    fn install(&'a self) -> bossy::Result<()> {
         util::rustup_add(self.triple())
     }
 
@@ -54,19 +84,58 @@ pub trait TargetTrait<'a>: Debug + Sized {
 #[derive(Debug)]
 pub struct TargetInvalid {
     name: String,
+    suggestion: Option<String>,
     possible: Vec<String>,
 }
 
 impl Display for TargetInvalid {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Target {:?} is invalid; the possible targets are {:?}",
-            self.name, self.possible,
+        write!(f, "Target {:?} is invalid; ", self.name)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "did you mean {:?}? ", suggestion)?;
+        }
+        write!(f, "the possible targets are {:?}", self.possible)
+    }
+}
+
+impl TargetInvalid {
+    fn new<'a, T: TargetTrait<'a>>(name: &str) -> Self {
+        let possible = T::all().keys().map(|key| *key);
+        let suggestion = util::suggest(
+            name,
+            possible.clone().chain(
+                T::ALIASES
+                    .iter()
+                    .filter(|(_, key)| T::all().contains_key(key))
+                    .map(|(alias, _)| *alias),
+            ),
         )
+        .map(str::to_owned);
+        Self {
+            name: name.to_owned(),
+            suggestion,
+            possible: possible.map(str::to_owned).collect(),
+        }
     }
 }
 
+/// Keys, aliases, and triples, in that order for each target.
+pub fn possible_values<T: TargetTrait<'static>>() -> Vec<&'static str> {
+    T::all()
+        .iter()
+        .flat_map(|(key, target)| {
+            std::iter::once(*key)
+                .chain(T::aliases_for(key))
+                .chain(std::iter::once(target.triple()))
+        })
+        .collect()
+}
+
+/// Looks up a target by key, alias, or triple.
+pub fn parse_target<'a, T: TargetTrait<'a>>(name: &str) -> Result<&'a T, TargetInvalid> {
+    T::for_name(name).ok_or_else(|| TargetInvalid::new::<T>(name))
+}
+
 pub fn get_targets<'a, Iter, I, T, U>(
     targets: Iter,
     // we use `dyn` so the type doesn't need to be known when this is `None`
@@ -80,12 +149,7 @@ where
     let targets_empty = targets.len() == 0;
     Ok(if !targets_empty {
         targets
-            .map(|name| {
-                T::for_name(name.as_ref()).ok_or_else(|| TargetInvalid {
-                    name: name.as_ref().to_owned(),
-                    possible: T::all().keys().map(|key| key.to_string()).collect(),
-                })
-            })
+            .map(|name| parse_target(name.as_ref()))
             .collect::<Result<_, _>>()?
     } else {
         let target = fallback
@@ -135,3 +199,122 @@ where
         Ok(())
     })
 }
+
+/// A target as shown by the `targets` commands. The field names are part of
+/// the `--json` output, so they shouldn't change.
+#[derive(Debug, Serialize)]
+pub struct Listing {
+    pub name: &'static str,
+    pub aliases: Vec<&'static str>,
+    pub triple: String,
+    pub arch: String,
+    pub kind: Kind,
+    /// `None` if `rustup` couldn't tell us.
+    pub installed: Option<bool>,
+}
+
+/// Every target in the registry, along with whether `rustup` has it
+/// installed.
+pub fn listings<T: TargetTrait<'static> + 'static>() -> Vec<Listing> {
+    let installed = util::installed_targets()
+        .map_err(|err| log::warn!("failed to list installed targets: {}", err))
+        .ok();
+    T::all()
+        .iter()
+        .map(|(name, target)| Listing {
+            name: *name,
+            aliases: T::aliases_for(name),
+            triple: target.triple().to_owned(),
+            arch: target.arch().to_owned(),
+            kind: target.kind(),
+            installed: installed
+                .as_ref()
+                .map(|installed| installed.iter().any(|triple| triple == target.triple())),
+        })
+        .collect()
+}
+
+/// Prints targets as a table, or as a JSON array.
+pub fn print_listings(listings: &[Listing], json: bool) {
+    if json {
+        println!("{}", serde_json::json!(listings));
+        return;
+    }
+    let rows = listings
+        .iter()
+        .map(|listing| {
+            vec![
+                listing.name.to_owned(),
+                if listing.aliases.is_empty() {
+                    "-".to_owned()
+                } else {
+                    listing.aliases.join(", ")
+                },
+                listing.triple.clone(),
+                listing.arch.clone(),
+                listing.kind.as_str().to_owned(),
+                match listing.installed {
+                    Some(true) => "yes",
+                    Some(false) => "no",
+                    None => "?",
+                }
+                .to_owned(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    util::print_table(
+        &["NAME", "ALIASES", "TRIPLE", "ARCH", "KIND", "INSTALLED"],
+        &rows,
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::android::target::Target;
+    use rstest::rstest;
+
+    #[rstest(
+        name,
+        expected,
+        case("aarch64", "aarch64-linux-android"),
+        case("arm64", "aarch64-linux-android"),
+        case("armeabi-v7a", "armv7-linux-androideabi"),
+        case("x86", "i686-linux-android"),
+        case("x86_64-linux-android", "x86_64-linux-android")
+    )]
+    fn test_for_name_accepts_aliases_and_triples(name: &str, expected: &str) {
+        assert_eq!(Target::for_name(name).unwrap().triple, expected);
+    }
+
+    #[test]
+    fn test_possible_values_cover_everything_for_name_accepts() {
+        let values = Target::possible_values();
+        for value in values {
+            assert!(
+                Target::for_name(value).is_some(),
+                "{:?} isn't a target",
+                value
+            );
+        }
+        for (key, target) in Target::all() {
+            assert!(values.contains(key));
+            assert!(values.contains(&target.triple));
+            for alias in Target::aliases_for(key) {
+                assert!(values.contains(&alias));
+            }
+        }
+    }
+
+    #[rstest(
+        name,
+        suggestion,
+        case("aarch46", Some("aarch64")),
+        case("armv8", Some("armv7")),
+        case("mips", None)
+    )]
+    fn test_invalid_targets_get_suggestions(name: &str, suggestion: Option<&str>) {
+        let err = parse_target::<Target>(name).unwrap_err();
+        assert_eq!(err.suggestion.as_deref(), suggestion);
+    }
+}
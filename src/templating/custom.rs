@@ -0,0 +1,305 @@
+use super::FancyPackResolveError;
+use crate::{
+    ui,
+    util::{self, proxy, Git},
+};
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Where an app template pack comes from, as given by `app.template-pack` or
+/// `--template-pack`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Source {
+    /// A built-in pack, or one registered in `~/.cargo-mobile/template-packs.toml`.
+    Named(String),
+    /// Relative paths are relative to the app root.
+    Path(PathBuf),
+    /// Checked out under `~/.cargo-mobile/checkouts/template-packs`.
+    Git { url: String, rev: Option<String> },
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Named(name) => write!(f, "{}", name),
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Git { url, rev: None } => write!(f, "{}", url),
+            Self::Git {
+                url,
+                rev: Some(rev),
+            } => write!(f, "{}#{}", url, rev),
+        }
+    }
+}
+
+impl Source {
+    pub fn parse(source: &str) -> Self {
+        let (url, rev) = match source.rfind('#') {
+            Some(index) => (&source[..index], Some(&source[index + 1..])),
+            None => (source, None),
+        };
+        if url.contains("://") || url.starts_with("git@") || url.ends_with(".git") {
+            Self::Git {
+                url: url.to_owned(),
+                rev: rev.filter(|rev| !rev.is_empty()).map(str::to_owned),
+            }
+        } else if source.contains('/')
+            || source.contains('\\')
+            || source.starts_with('.')
+            || source.starts_with('~')
+        {
+            Self::Path(source.into())
+        } else {
+            Self::Named(source.to_owned())
+        }
+    }
+}
+
+/// Where a pack's files come from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Origin {
+    /// Built into the binary, and extracted on first use.
+    Embedded,
+    /// A directory on this machine.
+    Local,
+    /// A git checkout that's already been cloned, which is used as-is if
+    /// fetching fails.
+    Cached,
+    /// A git checkout that hasn't been cloned yet.
+    Remote,
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Embedded => write!(f, "embedded"),
+            Self::Local => write!(f, "local"),
+            Self::Cached => write!(f, "cached"),
+            Self::Remote => write!(f, "remote"),
+        }
+    }
+}
+
+impl Source {
+    pub fn origin(&self) -> Result<Origin, util::NoHomeDir> {
+        match self {
+            Self::Named(_) => Ok(Origin::Embedded),
+            Self::Path(_) => Ok(Origin::Local),
+            Self::Git { url, rev } => CustomPack::from_git(url.clone(), rev.clone()).map(|pack| {
+                if pack.cached() {
+                    Origin::Cached
+                } else {
+                    Origin::Remote
+                }
+            }),
+        }
+    }
+}
+
+// Every URL gets its own checkout, named so that it's recognizable when
+// poking around in there.
+fn checkout_name(url: &str) -> String {
+    url.trim_end_matches(".git")
+        .split("://")
+        .last()
+        .unwrap_or(url)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+/// What's wrong with a template pack at `path`, if anything. Packs only need
+/// what every generated app needs, since anything else is up to them.
+pub(super) fn problems(path: &Path) -> Vec<String> {
+    if !path.is_dir() {
+        return vec![format!("{:?} isn't a directory", path)];
+    }
+    let mut problems = Vec::new();
+    if !path.join("Cargo.toml.hbs").is_file() && !path.join("Cargo.toml").is_file() {
+        problems.push(
+            "there's no `Cargo.toml.hbs` (or `Cargo.toml`) to generate the app's manifest from"
+                .to_owned(),
+        );
+    }
+    if !path.join("src").is_dir() {
+        problems.push("there's no `src` directory to generate the app's code from".to_owned());
+    }
+    problems
+}
+
+/// A template pack from outside of cargo-mobile, which gets checked before
+/// anything is generated from it.
+#[derive(Clone, Debug)]
+pub struct CustomPack {
+    source: Source,
+    path: PathBuf,
+}
+
+impl CustomPack {
+    pub fn from_path(source: Source, path: PathBuf) -> Self {
+        Self { source, path }
+    }
+
+    pub fn from_git(url: String, rev: Option<String>) -> Result<Self, util::NoHomeDir> {
+        let path = util::checkouts_dir()?
+            .join("template-packs")
+            .join(checkout_name(&url));
+        Ok(Self {
+            source: Source::Git { url, rev },
+            path,
+        })
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn cached(&self) -> bool {
+        self.path.join(".git").is_dir()
+    }
+
+    // Fetching happens here rather than when the config's loaded, so that
+    // only generating the project needs network access.
+    fn fetch(&self, url: &str, rev: Option<&str>) -> Result<(), FancyPackResolveError> {
+        let fetch_failed = |cause| FancyPackResolveError::FetchFailed {
+            url: url.to_owned(),
+            cause,
+        };
+        let cached = self.cached();
+        if !cached {
+            ui::status(format!("Cloning template pack from {}...", url));
+            let parent = self
+                .path
+                .parent()
+                .expect("developer error: checkout path was at root");
+            fs::create_dir_all(parent).map_err(|cause| {
+                FancyPackResolveError::CheckoutDirCreationFailed {
+                    path: parent.to_owned(),
+                    cause,
+                }
+            })?;
+            proxy::run_and_wait(
+                Git::new(parent)
+                    .command_parse("clone --quiet")
+                    .with_arg(url)
+                    .with_arg(&self.path),
+            )
+            .map_err(fetch_failed)?;
+        } else {
+            ui::status(format!("Updating template pack from {}...", url));
+        }
+        let git = Git::new(&self.path);
+        let fetched = proxy::run_and_wait(
+            git.command_parse("fetch --quiet origin")
+                .with_arg(rev.unwrap_or("HEAD")),
+        );
+        // Being offline shouldn't stop us from using what we cloned before.
+        let checkout = match fetched {
+            Ok(_) => "FETCH_HEAD",
+            Err(err) if cached => {
+                log::warn!(
+                    "failed to update template pack from {}, so using the cached checkout: {}",
+                    url,
+                    err
+                );
+                rev.unwrap_or("HEAD")
+            }
+            Err(err) => return Err(fetch_failed(err)),
+        };
+        git.command_parse("checkout --quiet --force --detach")
+            .with_arg(checkout)
+            .run_and_wait()
+            .map_err(fetch_failed)?;
+        git.command_parse("clean --quiet -dfx")
+            .run_and_wait()
+            .map_err(fetch_failed)?;
+        Ok(())
+    }
+
+    /// Where the pack is, once it's been fetched if it's from git. Unlike
+    /// [`Self::resolve`], this doesn't check that there's a pack there.
+    pub fn checkout(&self) -> Result<&Path, FancyPackResolveError> {
+        if let Source::Git { url, rev } = &self.source {
+            self.fetch(url, rev.as_deref())?;
+        }
+        Ok(&self.path)
+    }
+
+    pub fn resolve(&self) -> Result<Vec<&Path>, FancyPackResolveError> {
+        self.checkout()?;
+        let problems = problems(&self.path);
+        if problems.is_empty() {
+            Ok(vec![&self.path])
+        } else {
+            Err(FancyPackResolveError::Malformed {
+                source: self.source.to_string(),
+                problems,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        input,
+        expected,
+        case("bevy", Source::Named("bevy".to_owned())),
+        case("../our-templates", Source::Path("../our-templates".into())),
+        case("~/templates/ours", Source::Path("~/templates/ours".into())),
+        case(
+            "https://github.com/acme/templates",
+            Source::Git { url: "https://github.com/acme/templates".to_owned(), rev: None }
+        ),
+        case(
+            "git@github.com:acme/templates.git#v2",
+            Source::Git { url: "git@github.com:acme/templates.git".to_owned(), rev: Some("v2".to_owned()) }
+        ),
+        case(
+            "https://github.com/acme/templates#",
+            Source::Git { url: "https://github.com/acme/templates".to_owned(), rev: None }
+        )
+    )]
+    fn test_parse(input: &str, expected: Source) {
+        let source = Source::parse(input);
+        assert_eq!(source, expected);
+        if input.ends_with('#') {
+            assert_eq!(source.to_string(), input.trim_end_matches('#'));
+        } else {
+            assert_eq!(source.to_string(), input);
+        }
+    }
+
+    #[rstest(
+        input,
+        expected,
+        case("bevy", Origin::Embedded),
+        case("../our-templates", Origin::Local),
+        case("~/templates/ours", Origin::Local)
+    )]
+    fn test_origin(input: &str, expected: Origin) {
+        assert_eq!(Source::parse(input).origin().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_checkout_name() {
+        assert_eq!(
+            checkout_name("https://github.com/acme/templates.git"),
+            "github-com-acme-templates"
+        );
+        assert_eq!(
+            checkout_name("git@github.com:acme/templates.git"),
+            "git-github-com-acme-templates"
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use crate::util::{
+    self,
+    cli::{Report, Reportable},
+};
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// A file from `templates/platforms` or `templates/apps`, baked into the
+/// binary so that the built-in packs never need network access.
+struct EmbeddedFile {
+    path: &'static str,
+    contents: &'static [u8],
+    #[cfg_attr(not(unix), allow(dead_code))]
+    executable: bool,
+}
+
+include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+
+/// The crate version, plus a hash of the templates themselves.
+pub static VERSION: &str = env!("CARGO_MOBILE_TEMPLATES_VERSION");
+
+// Written last, so a half-finished extraction is retried.
+static VERSION_FILE: &str = ".version";
+
+#[derive(Debug)]
+pub enum Error {
+    NoHomeDir(util::NoHomeDir),
+    RemoveFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    TempDirFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    DirCreationFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    WriteFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    RenameFailed {
+        from: PathBuf,
+        to: PathBuf,
+        cause: io::Error,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        let msg = "Failed to extract the built-in templates";
+        match self {
+            Self::NoHomeDir(err) => Report::error(msg, err),
+            Self::RemoveFailed { .. }
+            | Self::TempDirFailed { .. }
+            | Self::DirCreationFailed { .. }
+            | Self::WriteFailed { .. }
+            | Self::RenameFailed { .. } => Report::error(msg, self),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NoHomeDir(..) => "templating.embedded.no-home-dir",
+            Self::RemoveFailed { .. } => "templating.embedded.remove-failed",
+            Self::TempDirFailed { .. } => "templating.embedded.temp-dir-failed",
+            Self::DirCreationFailed { .. } => "templating.embedded.dir-creation-failed",
+            Self::WriteFailed { .. } => "templating.embedded.write-failed",
+            Self::RenameFailed { .. } => "templating.embedded.rename-failed",
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoHomeDir(err) => write!(f, "{}", err),
+            Self::RemoveFailed { path, cause } => write!(
+                f,
+                "Failed to remove old built-in templates at {:?}: {}",
+                path, cause
+            ),
+            Self::TempDirFailed { path, cause } => write!(
+                f,
+                "Failed to create {:?} to extract built-in templates into: {}",
+                path, cause
+            ),
+            Self::DirCreationFailed { path, cause } => write!(
+                f,
+                "Failed to create built-in templates directory {:?}: {}",
+                path, cause
+            ),
+            Self::WriteFailed { path, cause } => write!(
+                f,
+                "Failed to extract built-in template {:?}: {}",
+                path, cause
+            ),
+            Self::RenameFailed { from, to, cause } => write!(
+                f,
+                "Failed to move built-in templates from {:?} to {:?}: {}",
+                from, to, cause
+            ),
+        }
+    }
+}
+
+/// Where the built-in packs are extracted to.
+pub fn dir() -> Result<PathBuf, util::NoHomeDir> {
+    util::install_dir().map(|dir| dir.join("templates"))
+}
+
+/// The version of the built-in packs that were last extracted, if any were.
+pub fn extracted_version() -> Result<Option<String>, util::NoHomeDir> {
+    dir().map(|dir| version_in(&dir))
+}
+
+fn version_in(dir: &Path) -> Option<String> {
+    fs::read_to_string(dir.join(VERSION_FILE))
+        .ok()
+        .map(|version| version.trim().to_owned())
+}
+
+fn write(path: &Path, file: &EmbeddedFile) -> io::Result<()> {
+    fs::write(path, file.contents)?;
+    #[cfg(unix)]
+    if file.executable {
+        use std::os::unix::fs::PermissionsExt as _;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+// Everything's written next to `dir` and then renamed into place, so that
+// no one ever sees a half-extracted pack, or half of one version and half of
+// another. The old templates are moved out of the way first, and removed
+// along with the temp dir they were moved into.
+fn extract(dir: &Path) -> Result<(), Error> {
+    let parent = dir
+        .parent()
+        .expect("developer error: templates dir was at root");
+    let temp_dir_failed = |(path, cause)| Error::TempDirFailed { path, cause };
+    let staging = util::TempDir::new_in(parent, ".templates-new").map_err(temp_dir_failed)?;
+    let new = staging.path().join("templates");
+    for file in FILES {
+        let path = new.join(file.path);
+        let parent = path
+            .parent()
+            .expect("developer error: embedded template was at root");
+        fs::create_dir_all(parent).map_err(|cause| Error::DirCreationFailed {
+            path: parent.to_owned(),
+            cause,
+        })?;
+        write(&path, file).map_err(|cause| Error::WriteFailed { path, cause })?;
+    }
+    let path = new.join(VERSION_FILE);
+    fs::write(&path, VERSION).map_err(|cause| Error::WriteFailed { path, cause })?;
+    let old = util::TempDir::new_in(parent, ".templates-old").map_err(temp_dir_failed)?;
+    if dir.is_dir() {
+        let to = old.path().join("templates");
+        fs::rename(dir, &to).map_err(|cause| Error::RenameFailed {
+            from: dir.to_owned(),
+            to,
+            cause,
+        })?;
+    }
+    match fs::rename(&new, dir) {
+        Ok(()) => Ok(()),
+        // Another run extracted the same templates in the meantime.
+        Err(_) if version_in(dir).as_deref() == Some(VERSION) => Ok(()),
+        Err(cause) => Err(Error::RenameFailed {
+            from: new,
+            to: dir.to_owned(),
+            cause,
+        }),
+    }
+}
+
+/// Extracts the built-in packs, unless this version of them already was.
+pub fn ensure_extracted() -> Result<PathBuf, Error> {
+    let dir = dir().map_err(Error::NoHomeDir)?;
+    match extracted_version().map_err(Error::NoHomeDir)? {
+        Some(version) if version == VERSION => Ok(dir),
+        extracted => {
+            log::info!(
+                "extracting built-in templates {} to {:?} (found {:?})",
+                VERSION,
+                dir,
+                extracted
+            );
+            extract(&dir).map(|()| dir)
+        }
+    }
+}
+
+/// Makes the next use extract the built-in packs again, which is how an
+/// updated binary's templates replace the old ones.
+pub fn invalidate() -> Result<(), Error> {
+    let path = dir().map_err(Error::NoHomeDir)?.join(VERSION_FILE);
+    match fs::remove_file(&path) {
+        Err(cause) if cause.kind() != io::ErrorKind::NotFound => {
+            Err(Error::RemoveFailed { path, cause })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extract_replaces() {
+        let parent = util::TempDir::new("embedded-test").unwrap();
+        let dir = parent.path().join("templates");
+        fs::create_dir_all(dir.join("apps/stale")).unwrap();
+        for _ in 0..2 {
+            extract(&dir).unwrap();
+            assert_eq!(version_in(&dir).as_deref(), Some(VERSION));
+            assert!(!dir.join("apps/stale").exists());
+            assert!(FILES.iter().all(|file| dir.join(file.path).is_file()));
+            // The temp dirs are gone once they're done with.
+            assert_eq!(fs::read_dir(parent.path()).unwrap().count(), 1);
+        }
+    }
+}
@@ -48,6 +48,18 @@ impl Display for FancyPackParseError {
 pub enum FancyPackResolveError {
     SubmoduleFailed(submodule::Error),
     PackNotFound(PathBuf),
+    CheckoutDirCreationFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    FetchFailed {
+        url: String,
+        cause: bossy::Error,
+    },
+    Malformed {
+        source: String,
+        problems: Vec<String>,
+    },
 }
 
 impl Display for FancyPackResolveError {
@@ -55,6 +67,20 @@ impl Display for FancyPackResolveError {
         match self {
             Self::SubmoduleFailed(err) => write!(f, "Failed to initialize submodule: {}", err),
             Self::PackNotFound(path) => write!(f, "Template pack wasn't found at {:?}", path),
+            Self::CheckoutDirCreationFailed { path, cause } => write!(
+                f,
+                "Failed to create template pack checkout directory {:?}: {}",
+                path, cause
+            ),
+            Self::FetchFailed { url, cause } => {
+                write!(f, "Failed to fetch template pack from {}: {}", url, cause)
+            }
+            Self::Malformed { source, problems } => write!(
+                f,
+                "Template pack {} is malformed: {}",
+                source,
+                problems.join("; ")
+            ),
         }
     }
 }
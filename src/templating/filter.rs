@@ -1,10 +1,9 @@
 use crate::config::{Config, Origin};
-use bicycle::Action;
 use ignore::gitignore::Gitignore;
 use std::{
     fmt::{self, Display},
     io,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 #[derive(Debug)]
@@ -62,31 +61,26 @@ impl Filter {
         }
     }
 
-    pub fn fun(&self) -> impl FnMut(&Action) -> bool + '_ {
-        move |action| match self {
+    /// Whether something at `dest` should be written.
+    pub fn allows(&self, dest: &Path, is_dir: bool) -> bool {
+        match self {
             Self::WildWest => {
                 log::debug!(
-                    "filtering strategy is `WildWest`, so action will be processed: {:#?}",
-                    action
+                    "filtering strategy is `WildWest`, so {:?} will be written",
+                    dest
                 );
                 true
             }
             Self::Protected { unprotected } => {
                 // If we're protecting the user's files, then we only allow
-                // actions that apply to paths excluded from version control.
+                // writing to paths excluded from version control.
                 let ignored = unprotected
-                    .matched_path_or_any_parents(action.dest(), action.is_create_directory())
+                    .matched_path_or_any_parents(dest, is_dir)
                     .is_ignore();
                 if ignored {
-                    log::debug!(
-                        "action has unprotected src, so will be processed: {:#?}",
-                        action
-                    );
+                    log::debug!("{:?} is unprotected, so will be written", dest);
                 } else {
-                    log::debug!(
-                        "action has protected src, so won't be processed: {:#?}",
-                        action
-                    );
+                    log::debug!("{:?} is protected, so won't be written", dest);
                 }
                 ignored
             }
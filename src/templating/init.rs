@@ -1,14 +1,19 @@
 use crate::{
-    config::{app, Config},
+    config::{
+        app::{self, Platform},
+        Config,
+    },
+    target::TargetTrait as _,
     util::{self, Git},
 };
 use bicycle::{
     handlebars::{
         self, Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError,
+        Renderable as _,
     },
     Bicycle, EscapeFn, HelperDef, JsonMap,
 };
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 fn get_str<'a>(helper: &'a Helper) -> &'a str {
     helper
@@ -41,6 +46,49 @@ fn html_escape(
         .map_err(Into::into)
 }
 
+// Android string resources get XML-escaped, and then aapt has its own escapes
+// on top of that: quotes and backslashes have to be escaped, and a leading `@`
+// or `?` would otherwise make it a reference to another resource.
+fn android_string_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            '\\' | '\'' | '"' => escaped.push('\\'),
+            '@' | '?' if i == 0 => escaped.push('\\'),
+            _ => (),
+        }
+        escaped.push(c);
+    }
+    handlebars::html_escape(&escaped)
+}
+
+fn android_string(
+    helper: &Helper,
+    _: &Handlebars,
+    _ctx: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    out.write(&android_string_escape(get_str(helper)))
+        .map_err(Into::into)
+}
+
+// A JSON string is also a double-quoted YAML string, which is how values that
+// could contain spaces, quotes, or a leading `&` end up in `project.yml`.
+fn quote(
+    helper: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    out.write(
+        &serde_json::to_string(get_str(helper))
+            .expect("developer error: failed to serialize a string"),
+    )
+    .map_err(Into::into)
+}
+
 fn join(
     helper: &Helper,
     _: &Handlebars,
@@ -147,6 +195,56 @@ fn unprefix_path(
     .map_err(Into::into)
 }
 
+// Raw blocks hand us their contents without parsing them, so rendering them
+// just passes them through, braces and all.
+fn raw(
+    helper: &Helper,
+    registry: &Handlebars,
+    ctx: &Context,
+    rc: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    match helper.template() {
+        Some(template) => template.render(registry, ctx, rc, out),
+        None => Ok(()),
+    }
+}
+
+// Placeholders without a value would otherwise render as nothing, which is
+// never what anybody wants. The exception is a platform that isn't selected,
+// whose config is left out entirely: templates shared between platforms
+// can't be expected to guard every reference to it, so those are left empty.
+fn helper_missing(
+    helper: &Helper,
+    _: &Handlebars,
+    ctx: &Context,
+    _: &mut RenderContext,
+    _: &mut dyn Output,
+) -> HelperResult {
+    let name = helper.name();
+    let unselected = name
+        .split('.')
+        .next()
+        .filter(|prefix| {
+            Platform::ALL
+                .iter()
+                .any(|platform| platform.name() == *prefix)
+        })
+        .filter(|prefix| ctx.data().get(prefix).is_none());
+    if let Some(platform) = unselected {
+        log::warn!(
+            "placeholder `{}` was left empty, since {} isn't one of the selected platforms",
+            name,
+            platform
+        );
+        return Ok(());
+    }
+    Err(RenderError::new(format!(
+        "Placeholder `{}` has no value; set it in `[template.vars]` or with `--var`",
+        helper.name()
+    )))
+}
+
 fn detect_author() -> String {
     let git = Git::new(".".as_ref());
     let name_output = git.user_name().ok();
@@ -158,16 +256,93 @@ fn detect_author() -> String {
     format!("{} <{}>", name.trim(), email.trim())
 }
 
-pub fn init(config: Option<&Config>) -> Bicycle {
+/// Tells templates which platforms they're being generated for, so that they
+/// can leave out whatever doesn't apply.
+pub fn insert_platform_data(map: &mut JsonMap, platforms: &[Platform]) {
+    map.insert(
+        "platforms",
+        Platform::ALL
+            .iter()
+            .map(|platform| (platform.name(), platforms.contains(platform)))
+            .collect::<BTreeMap<_, _>>(),
+    );
+    map.insert("host-os", std::env::consts::OS);
+    let mut triples = Vec::new();
+    #[cfg(target_os = "macos")]
+    if platforms.contains(&Platform::Apple) {
+        triples.extend(
+            crate::apple::target::Target::all()
+                .values()
+                .map(|target| target.triple),
+        );
+    }
+    if platforms.contains(&Platform::Android) {
+        triples.extend(
+            crate::android::target::Target::all()
+                .values()
+                .map(|target| target.triple),
+        );
+    }
+    map.insert("target-triples", triples);
+}
+
+/// Names template vars can't use, since built-in template data and helpers
+/// already do.
+pub static RESERVED_VARS: &[&str] = &[
+    // Data every template gets
+    "app",
+    "author",
+    "apple",
+    "android",
+    "platforms",
+    "host-os",
+    "target-triples",
+    // Data only platform templates get
+    "root-dir-rel",
+    "version",
+    "targets",
+    "target-names",
+    "arches",
+    "file-groups",
+    "ios-frameworks",
+    "macos-frameworks",
+    "ios-info-plist",
+    "ios-extra-sources",
+    "xcode-configurations",
+    "ios-bridging-header",
+    "swift-packages",
+    "swift-package-dependencies",
+    "ios-entitlements",
+    "ios-system-capabilities",
+    // Helpers
+    "html-escape",
+    "android-string",
+    "quote",
+    "join",
+    "quote-and-join",
+    "snake-case",
+    "reverse-domain",
+    "prefix-path",
+    "unprefix-path",
+    "raw",
+    "helperMissing",
+    "this",
+];
+
+pub fn init(config: Option<&Config>, cli_vars: &[(String, String)]) -> Bicycle {
     Bicycle::new(
         EscapeFn::None,
         {
             let mut helpers = HashMap::<_, Box<dyn HelperDef + Send + Sync>>::new();
             helpers.insert("html-escape", Box::new(html_escape));
+            helpers.insert("android-string", Box::new(android_string));
+            helpers.insert("quote", Box::new(quote));
             helpers.insert("join", Box::new(join));
             helpers.insert("quote-and-join", Box::new(quote_and_join));
             helpers.insert("snake-case", Box::new(snake_case));
             helpers.insert("reverse-domain", Box::new(reverse_domain));
+            helpers.insert("raw", Box::new(raw));
+            helpers.insert("helperMissing", Box::new(helper_missing));
             if config.is_some() {
                 // don't mix these up or very bad things will happen to all of us
                 helpers.insert("prefix-path", Box::new(prefix_path));
@@ -180,11 +355,146 @@ pub fn init(config: Option<&Config>) -> Bicycle {
             if let Some(config) = config {
                 map.insert(app::KEY, config.app());
                 map.insert("author", detect_author());
+                // Leaving out the unselected platforms' config means
+                // `{{#if apple}}` and `{{#if android}}` work as you'd hope.
                 #[cfg(target_os = "macos")]
-                map.insert(crate::apple::NAME, config.apple());
-                map.insert(crate::android::NAME, config.android());
+                if config.app().selects(Platform::Apple) {
+                    map.insert(crate::apple::NAME, config.apple());
+                }
+                if config.app().selects(Platform::Android) {
+                    map.insert(crate::android::NAME, config.android());
+                }
+                insert_platform_data(&mut map, config.app().platforms());
+                for (key, value) in config.template().merged_vars(cli_vars) {
+                    map.insert(key.as_str(), value);
+                }
             }
             map
         },
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    static STRINGS_XML: &str = include_str!(
+        "../../templates/platforms/android-studio/app/src/main/res/values/strings.xml.hbs"
+    );
+    static PROJECT_YML: &str = include_str!("../../templates/platforms/xcode/project.yml.hbs");
+
+    fn render(template: &str, display_name: &str) -> String {
+        init(None, &[])
+            .render(template, |map| {
+                map.insert(
+                    app::KEY,
+                    serde_json::json!({ "display-name": display_name }),
+                );
+            })
+            .unwrap()
+    }
+
+    // Just enough of what aapt does to read back the app name: the XML is
+    // unescaped first, and then the string resource.
+    fn parse_app_name(xml: &str) -> String {
+        let start = r#"<string name="app_name">"#;
+        let text = &xml[xml.find(start).expect("app name missing") + start.len()..];
+        let text = &text[..text.find("</string>").expect("app name unterminated")];
+        assert!(!text.contains('<'), "markup in app name: {:?}", text);
+        let mut unescaped = String::new();
+        let mut rest = text;
+        while let Some(index) = rest.find('&') {
+            unescaped.push_str(&rest[..index]);
+            let end = rest[index..].find(';').expect("unterminated entity") + index;
+            unescaped.push(match &rest[index + 1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                entity if entity.starts_with("#x") => {
+                    std::char::from_u32(u32::from_str_radix(&entity[2..], 16).unwrap()).unwrap()
+                }
+                entity => panic!("unknown entity {:?}", entity),
+            });
+            rest = &rest[end + 1..];
+        }
+        unescaped.push_str(rest);
+        let mut parsed = String::new();
+        let mut chars = unescaped.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => parsed.push(chars.next().expect("trailing backslash")),
+                '\'' | '"' => panic!("unescaped quote in {:?}", unescaped),
+                c => parsed.push(c),
+            }
+        }
+        parsed
+    }
+
+    #[rstest(
+        display_name,
+        case("Café Tracker"),
+        case("Joe's Café Tracker"),
+        case("Ça Va’s Tracker"),
+        case("Rock & Roll <3"),
+        case(r#"The "Best" \ App"#),
+        case("@Home"),
+        case("?Quiz")
+    )]
+    fn test_display_name_in_strings_xml(display_name: &str) {
+        let xml = render(STRINGS_XML, display_name);
+        assert_eq!(parse_app_name(&xml), display_name);
+    }
+
+    #[rstest(
+        display_name,
+        case("Café Tracker"),
+        case("Joe's Café Tracker"),
+        case("& Friends"),
+        case(r#"The "Best" App: Part 2 # 1"#)
+    )]
+    fn test_display_name_in_project_yml(display_name: &str) {
+        let line = PROJECT_YML
+            .lines()
+            .find(|line| line.contains("CARGO_MOBILE_DISPLAY_NAME:"))
+            .expect("display name setting missing");
+        let rendered = render(line, display_name);
+        let value = rendered
+            .trim()
+            .strip_prefix("CARGO_MOBILE_DISPLAY_NAME:")
+            .unwrap()
+            .trim();
+        // It has to be quoted, or YAML would mangle a leading `&` or a `: `.
+        assert_eq!(serde_json::from_str::<String>(value).unwrap(), display_name);
+    }
+
+    fn render_android_only(template: &str) -> Result<String, String> {
+        init(None, &[])
+            .render(template, |map| {
+                map.insert(app::KEY, serde_json::json!({ "name": "flappy" }));
+                map.insert(
+                    crate::android::NAME,
+                    serde_json::json!({ "min-sdk-version": 24 }),
+                );
+                insert_platform_data(map, &[Platform::Android]);
+            })
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_unselected_platform_renders_empty() {
+        let template = "{{app.name}} {{android.min-sdk-version}} [{{apple.development-team}}]";
+        assert_eq!(render_android_only(template).unwrap(), "flappy 24 []");
+    }
+
+    #[rstest(
+        template,
+        case("{{company-name}}"),
+        case("{{android.development-team}}")
+    )]
+    fn test_missing_placeholder_fails(template: &str) {
+        assert!(render_android_only(template).is_err());
+    }
+}
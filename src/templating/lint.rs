@@ -0,0 +1,654 @@
+//! `cargo mobile templates lint`, for catching what would otherwise only come
+//! up partway through somebody's `cargo mobile init`. Every file in the pack
+//! is rendered into a temp dir, once for each combination of sample names and
+//! platforms, and then we look at what came out.
+
+use super::{
+    custom, is_binary, render_failed, FancyPackResolveError, LookupError, Manifest, ManifestError,
+    Pack, PostInitHook, ProcessingError, MANIFEST_FILE_NAME, RESERVED_VARS, TEMPLATE_EXT,
+};
+use crate::{
+    config::{
+        self,
+        app::{self, Platform},
+        Config,
+    },
+    util::{
+        self,
+        cli::{Report, Reportable},
+        Git,
+    },
+};
+use bicycle::Bicycle;
+use once_cell_regex::regex;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// Names that come up in packs meant for one platform or the other, but that
+// aren't what the platform directories are called.
+static PLATFORM_LOOKALIKES: &[(&str, Platform)] = &[
+    ("ios", Platform::Apple),
+    ("macos", Platform::Apple),
+    ("xcode", Platform::Apple),
+    ("android-studio", Platform::Android),
+];
+
+struct SampleNames {
+    label: &'static str,
+    name: &'static str,
+    stylized_name: &'static str,
+}
+
+static SAMPLE_NAMES: &[SampleNames] = &[
+    SampleNames {
+        label: "short name",
+        name: "app",
+        stylized_name: "App",
+    },
+    SampleNames {
+        label: "long name",
+        name: "extraordinarily-long-application-name",
+        stylized_name: "Extraordinarily Long App Name",
+    },
+    SampleNames {
+        label: "unicode display name",
+        name: "cafe",
+        stylized_name: "Ça Va’s Café",
+    },
+];
+
+// Each platform on its own, and then all of them.
+fn platform_selections() -> Vec<Vec<Platform>> {
+    Platform::ALL
+        .iter()
+        .map(|platform| vec![*platform])
+        .chain(std::iter::once(Platform::ALL.to_vec()))
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum Error {
+    CurrentDirFailed(io::Error),
+    LookupFailed(LookupError),
+    ResolveFailed(FancyPackResolveError),
+    SampleConfigFailed(config::FromRawError),
+    ReadDirFailed { path: PathBuf, cause: io::Error },
+    TempWriteFailed { path: PathBuf, cause: io::Error },
+    ErrorsFound { errors: usize },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::CurrentDirFailed(err) => {
+                Report::error("Failed to get current working directory", err)
+            }
+            Self::LookupFailed(err) => Report::error("Failed to find template pack", err),
+            Self::ResolveFailed(err) => Report::error("Failed to resolve template pack", err),
+            Self::SampleConfigFailed(err) => {
+                err.report("Failed to make a sample config to render templates with")
+            }
+            Self::ReadDirFailed { path, cause } => Report::error(
+                format!("Failed to read template directory {:?}", path),
+                cause,
+            ),
+            Self::TempWriteFailed { path, cause } => Report::error(
+                format!("Failed to write rendered template to {:?}", path),
+                cause,
+            ),
+            Self::ErrorsFound { errors } => Report::error(
+                "Template pack has problems",
+                format!(
+                    "{} error{} found; see above for where.",
+                    errors,
+                    if *errors == 1 { " was" } else { "s were" }
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::CurrentDirFailed(..) => "templating.lint.current-dir-failed",
+            Self::LookupFailed(..) => "templating.lint.lookup-failed",
+            Self::ResolveFailed(..) => "templating.lint.resolve-failed",
+            Self::SampleConfigFailed(..) => "templating.lint.sample-config-failed",
+            Self::ReadDirFailed { .. } => "templating.lint.read-dir-failed",
+            Self::TempWriteFailed { .. } => "templating.lint.temp-write-failed",
+            Self::ErrorsFound { .. } => "templating.lint.errors-found",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    severity: Severity,
+    path: PathBuf,
+    line: Option<usize>,
+    msg: String,
+    /// Which of the sample names and platforms it came up with, which is
+    /// empty if it doesn't depend on them.
+    samples: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Lint {
+    pack: String,
+    findings: Vec<Finding>,
+    #[serde(skip)]
+    sample_count: usize,
+}
+
+impl Lint {
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+
+    pub fn print(&self, json: bool) {
+        if json {
+            println!("{}", serde_json::json!(self));
+            return;
+        }
+        for finding in &self.findings {
+            let location = match finding.line {
+                Some(line) => format!("{}:{}", finding.path.display(), line),
+                None => finding.path.display().to_string(),
+            };
+            print!("{}: {}: {}", finding.severity, location, finding.msg);
+            // Something that comes up every time is a problem with the pack,
+            // not with a particular name or platform.
+            if !finding.samples.is_empty() && finding.samples.len() < self.sample_count {
+                print!(" (with {})", finding.samples.join("; "));
+            }
+            println!();
+        }
+        let (errors, warnings) = (self.errors(), self.count(Severity::Warning));
+        if errors == 0 && warnings == 0 {
+            println!("No problems found in template pack {}", self.pack);
+        } else {
+            println!(
+                "{} error{} and {} warning{} in template pack {}",
+                errors,
+                if errors == 1 { "" } else { "s" },
+                warnings,
+                if warnings == 1 { "" } else { "s" },
+                self.pack
+            );
+        }
+    }
+}
+
+// The same finding from several samples gets reported once, along with which
+// samples it came up with.
+#[derive(Default)]
+struct Findings(BTreeMap<(Severity, PathBuf, Option<usize>, String), Vec<String>>);
+
+impl Findings {
+    fn push(
+        &mut self,
+        severity: Severity,
+        path: &Path,
+        line: Option<usize>,
+        msg: impl Into<String>,
+        sample: Option<&str>,
+    ) {
+        let samples = self
+            .0
+            .entry((severity, path.to_owned(), line, msg.into()))
+            .or_default();
+        if let Some(sample) = sample {
+            if !samples.iter().any(|other| other == sample) {
+                samples.push(sample.to_owned());
+            }
+        }
+    }
+
+    fn into_vec(self) -> Vec<Finding> {
+        self.0
+            .into_iter()
+            .map(|((severity, path, line, msg), samples)| Finding {
+                severity,
+                path,
+                line,
+                msg,
+                samples,
+            })
+            .collect()
+    }
+}
+
+fn line_of(contents: &str, needle: &str) -> Option<usize> {
+    contents
+        .lines()
+        .position(|line| line.contains(needle))
+        .map(|index| index + 1)
+}
+
+/// Placeholders for data cargo-mobile provides (or for `vars`) that are still
+/// there, with the lines they're on.
+fn leftover_placeholders<'a>(contents: &'a str, vars: &[&str]) -> Vec<(usize, &'a str)> {
+    let mut found = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        for caps in
+            regex!(r"\{\{\{?\s*(?:[#/^]?[\w-]+\s+)?([A-Za-z][\w-]*)[^}]*\}\}").captures_iter(line)
+        {
+            let name = caps.get(1).map_or("", |name| name.as_str());
+            if RESERVED_VARS.contains(&name) || vars.contains(&name) {
+                found.push((index + 1, caps.get(0).map_or("", |all| all.as_str())));
+            }
+        }
+    }
+    found
+}
+
+fn check_structure(dir: &Path, custom: bool, findings: &mut Findings) -> Result<(), Error> {
+    if custom {
+        for problem in custom::problems(dir) {
+            findings.push(Severity::Error, dir, None, problem, None);
+        }
+    }
+    let entries = fs::read_dir(dir).map_err(|cause| Error::ReadDirFailed {
+        path: dir.to_owned(),
+        cause,
+    })?;
+    for entry in entries {
+        let path = entry
+            .map_err(|cause| Error::ReadDirFailed {
+                path: dir.to_owned(),
+                cause,
+            })?
+            .path();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let lowercase = name.to_ascii_lowercase();
+        if let Some(platform) = Platform::ALL
+            .iter()
+            .find(|platform| platform.name() == name)
+        {
+            if !path.is_dir() {
+                findings.push(
+                    Severity::Warning,
+                    &path,
+                    None,
+                    format!(
+                        "This is a file, so it's generated for every platform; only a `{}` directory is just for {}",
+                        platform.name(),
+                        platform
+                    ),
+                    None,
+                );
+            }
+        } else if let Some(platform) = Platform::ALL
+            .iter()
+            .find(|platform| platform.name() == lowercase)
+            .or_else(|| {
+                PLATFORM_LOOKALIKES
+                    .iter()
+                    .find(|(lookalike, _)| *lookalike == lowercase)
+                    .map(|(_, platform)| platform)
+            })
+        {
+            if path.is_dir() {
+                findings.push(
+                    Severity::Warning,
+                    &path,
+                    None,
+                    format!(
+                        "This isn't a platform directory, so it's generated for every platform; files just for {} go in `{}`",
+                        platform,
+                        platform.name()
+                    ),
+                    None,
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn load_manifest(dir: &Path, findings: &mut Findings) -> Vec<PostInitHook> {
+    match Manifest::load(dir) {
+        Ok(manifest) => manifest.post_init().to_vec(),
+        Err(err) => {
+            let line = match &err {
+                ManifestError::ParseFailed { cause, .. } => {
+                    cause.line_col().map(|(line, _)| line + 1)
+                }
+                _ => None,
+            };
+            findings.push(
+                Severity::Error,
+                &dir.join(MANIFEST_FILE_NAME),
+                line,
+                err.to_string(),
+                None,
+            );
+            Vec::new()
+        }
+    }
+}
+
+struct Render<'a> {
+    bike: &'a Bicycle,
+    vars: &'a [&'a str],
+    sample: &'a str,
+    findings: &'a mut Findings,
+}
+
+impl Render<'_> {
+    fn render_failed(&mut self, src: &Path, err: impl Display) {
+        match render_failed(src, err) {
+            ProcessingError::RenderFailed { path, line, msg } => {
+                self.findings
+                    .push(Severity::Error, &path, line, msg, Some(self.sample))
+            }
+            err => unreachable!("developer error: `render_failed` returned {:?}", err),
+        }
+    }
+
+    // Like `process_dir`, but it carries on after finding something wrong.
+    fn dir(&mut self, src_dir: &Path, dest_dir: &Path, skip: &[PathBuf]) -> Result<(), Error> {
+        let read_dir_failed = |cause| Error::ReadDirFailed {
+            path: src_dir.to_owned(),
+            cause,
+        };
+        for entry in fs::read_dir(src_dir).map_err(read_dir_failed)? {
+            let src = entry.map_err(read_dir_failed)?.path();
+            if skip.contains(&src) {
+                continue;
+            }
+            let name = src
+                .file_name()
+                .expect("developer error: `read_dir` yielded an unnamed entry")
+                .to_string_lossy()
+                .into_owned();
+            let name = if name.contains("{{") {
+                match self.bike.render(&name, |_| ()) {
+                    Ok(name) => name,
+                    Err(err) => {
+                        self.render_failed(&src, err);
+                        continue;
+                    }
+                }
+            } else {
+                name
+            };
+            if src.is_dir() {
+                self.dir(&src, &dest_dir.join(&name), &[])?;
+            } else {
+                self.file(&src, dest_dir, &name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn file(&mut self, src: &Path, dest_dir: &Path, name: &str) -> Result<(), Error> {
+        let contents = match fs::read(src) {
+            Ok(contents) => contents,
+            Err(err) => {
+                self.findings.push(
+                    Severity::Error,
+                    src,
+                    None,
+                    format!("Failed to read template: {}", err),
+                    None,
+                );
+                return Ok(());
+            }
+        };
+        let (name, template) = match name.strip_suffix(TEMPLATE_EXT) {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let binary = is_binary(name, &contents);
+        if template && binary {
+            self.findings.push(
+                Severity::Warning,
+                src,
+                None,
+                format!(
+                    "This looks binary, so it's copied as-is instead of being rendered; drop the `{}` to say so",
+                    TEMPLATE_EXT
+                ),
+                None,
+            );
+        }
+        let rendered = if template && !binary {
+            let template = String::from_utf8(contents)
+                .expect("developer error: `is_binary` let invalid UTF-8 through");
+            let rendered = match self.bike.render(&template, |_| ()) {
+                Ok(rendered) => rendered,
+                Err(err) => {
+                    self.render_failed(src, err);
+                    return Ok(());
+                }
+            };
+            // Raw blocks and escapes are how braces are kept on purpose.
+            if !template.contains("{{{{raw}}}}") && !template.contains("\\{{") {
+                for (line, placeholder) in leftover_placeholders(&rendered, self.vars) {
+                    self.findings.push(
+                        Severity::Error,
+                        src,
+                        Some(line),
+                        format!("`{}` is still there after rendering", placeholder),
+                        Some(self.sample),
+                    );
+                }
+            }
+            rendered.into_bytes()
+        } else {
+            if !binary {
+                let text = String::from_utf8_lossy(&contents);
+                for (line, placeholder) in leftover_placeholders(&text, self.vars) {
+                    self.findings.push(
+                        Severity::Warning,
+                        src,
+                        Some(line),
+                        format!(
+                            "`{}` won't be filled in, since only files ending in `{}` are rendered",
+                            placeholder, TEMPLATE_EXT
+                        ),
+                        None,
+                    );
+                }
+            }
+            contents
+        };
+        let dest = dest_dir.join(name);
+        let write_failed = |cause| Error::TempWriteFailed {
+            path: dest.clone(),
+            cause,
+        };
+        fs::create_dir_all(dest_dir).map_err(write_failed)?;
+        fs::write(&dest, rendered).map_err(write_failed)
+    }
+}
+
+// Only hooks with a `dir` can be checked, since the others run from the root.
+fn check_hooks(
+    hooks: &[(PathBuf, PostInitHook)],
+    root: &Path,
+    platforms: &[Platform],
+    sample: &str,
+    findings: &mut Findings,
+) {
+    for (manifest, hook) in hooks {
+        let dir = match hook.dir() {
+            Some(dir) => dir,
+            None => continue,
+        };
+        // `gen` is filled in by the platform packs, which aren't being linted.
+        if dir.starts_with("gen") {
+            continue;
+        }
+        if hook.applies_to(platforms) && !root.join(dir).is_dir() {
+            let line = fs::read_to_string(manifest).ok().and_then(|contents| {
+                line_of(&contents, &format!("{:?}", dir.display().to_string()))
+            });
+            findings.push(
+                Severity::Error,
+                manifest,
+                line,
+                format!(
+                    "Post-init hook {} runs in `{}`, which the pack doesn't generate",
+                    hook,
+                    dir.display()
+                ),
+                Some(sample),
+            );
+        }
+    }
+}
+
+/// Lints the pack `pack`, which is a name, path, or git URL, just like
+/// `app.template-pack`. `cli_vars` are the ones passed with `--var`, since a
+/// pack that uses its own vars can't be rendered without them.
+pub fn lint(pack: &str, cli_vars: &[(String, String)]) -> Result<Lint, Error> {
+    let cwd = std::env::current_dir().map_err(Error::CurrentDirFailed)?;
+    let resolved = Pack::lookup_app_source(pack, &cwd).map_err(Error::LookupFailed)?;
+    // Custom packs are checked here instead of when resolving, so that what's
+    // wrong with them comes out as findings along with everything else.
+    let (dirs, custom, template_pack) = match &resolved {
+        Pack::Custom(custom) => {
+            let path = custom.checkout().map_err(Error::ResolveFailed)?;
+            (vec![path], true, path.to_string_lossy().into_owned())
+        }
+        other => (
+            other
+                .resolve(Git::new(&cwd), None)
+                .map_err(Error::ResolveFailed)?,
+            false,
+            pack.to_owned(),
+        ),
+    };
+    log::info!("linting template pack {:?} at {:?}", pack, dirs);
+
+    let mut findings = Findings::default();
+    let mut hooks = Vec::new();
+    for dir in &dirs {
+        check_structure(dir, custom, &mut findings)?;
+        let manifest = dir.join(MANIFEST_FILE_NAME);
+        hooks.extend(
+            load_manifest(dir, &mut findings)
+                .into_iter()
+                .map(|hook| (manifest.clone(), hook)),
+        );
+    }
+
+    let vars = cli_vars
+        .iter()
+        .map(|(key, _)| key.as_str())
+        .collect::<Vec<_>>();
+    let temp = util::TempDir::new("lint")
+        .map_err(|(path, cause)| Error::TempWriteFailed { path, cause })?;
+    let selections = platform_selections();
+    let mut sample_count = 0;
+    for names in SAMPLE_NAMES {
+        for platforms in &selections {
+            sample_count += 1;
+            let sample = format!(
+                "{}, {}",
+                names.label,
+                platforms
+                    .iter()
+                    .map(|platform| platform.name())
+                    .collect::<Vec<_>>()
+                    .join(" and ")
+            );
+            let root = temp.path().join(sample_count.to_string());
+            fs::create_dir_all(&root).map_err(|cause| Error::TempWriteFailed {
+                path: root.clone(),
+                cause,
+            })?;
+            let config = Config::sample(
+                &root,
+                app::Raw::sample(names.name, names.stylized_name, &template_pack, platforms),
+            )
+            .map_err(Error::SampleConfigFailed)?;
+            let bike = config.build_a_bike(cli_vars);
+            let mut render = Render {
+                bike: &bike,
+                vars: &vars,
+                sample: &sample,
+                findings: &mut findings,
+            };
+            for dir in &dirs {
+                // The same as `process_app`, except that a checkout's
+                // `.git` is left alone.
+                let skip = Platform::ALL
+                    .iter()
+                    .map(|platform| dir.join(platform.name()))
+                    .chain(std::iter::once(dir.join(MANIFEST_FILE_NAME)))
+                    .chain(std::iter::once(dir.join(".git")))
+                    .collect::<Vec<_>>();
+                render.dir(dir, &root, &skip)?;
+                for platform in platforms {
+                    let platform_dir = dir.join(platform.name());
+                    if platform_dir.is_dir() {
+                        render.dir(&platform_dir, &root, &[])?;
+                    }
+                }
+            }
+            check_hooks(&hooks, &root, platforms, &sample, &mut findings);
+        }
+    }
+    Ok(Lint {
+        pack: pack.to_owned(),
+        findings: findings.into_vec(),
+        sample_count,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        contents,
+        vars,
+        expected,
+        case("name = \"{{app.name}}\"", &[], &[(1, "{{app.name}}")]),
+        case(
+            "a\n{{#if platforms.android}}b{{/if}}",
+            &[],
+            &[(2, "{{#if platforms.android}}")]
+        ),
+        case("api_key = \"{{api-key}}\"", &["api-key"], &[(1, "{{api-key}}")]),
+        case("run: echo ${{ github.sha }}", &[], &[]),
+        case("<p>{{ message }}</p>", &[], &[])
+    )]
+    fn test_leftover_placeholders(contents: &str, vars: &[&str], expected: &[(usize, &str)]) {
+        assert_eq!(leftover_placeholders(contents, vars), expected);
+    }
+}
@@ -0,0 +1,350 @@
+//! An app template pack can have a `pack.toml` at its root, which declares
+//! commands to run once a project's been generated from it, i.e. installing
+//! npm packages for a web view. It's never rendered into the project itself.
+
+use crate::{
+    config::app::Platform,
+    env::{self, Env, ExplicitEnv as _},
+    opts::NonInteractive,
+    plan::Writer,
+    ui,
+    util::{
+        cli::{Report, TextWrapper},
+        prompt,
+    },
+};
+use serde::Deserialize;
+use std::{
+    fmt::{self, Display},
+    fs,
+    io::{self, Write as _},
+    path::{Component, Path, PathBuf},
+};
+
+pub static MANIFEST_FILE_NAME: &str = "pack.toml";
+
+#[derive(Debug)]
+pub enum ManifestError {
+    ReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    ParseFailed {
+        path: PathBuf,
+        cause: toml::de::Error,
+    },
+    DirInvalid {
+        path: PathBuf,
+        dir: PathBuf,
+    },
+}
+
+impl Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadFailed { path, cause } => {
+                write!(f, "Failed to read template pack manifest {:?}: {}", path, cause)
+            }
+            Self::ParseFailed { path, cause } => {
+                write!(f, "Failed to parse template pack manifest {:?}: {}", path, cause)
+            }
+            Self::DirInvalid { path, dir } => write!(
+                f,
+                "Post-init hook in {:?} has `dir = {:?}`, but hooks can only run in directories inside of the generated project",
+                path, dir
+            ),
+        }
+    }
+}
+
+/// A command declared under `[[post-init]]`, which is run through `sh` from
+/// `dir` (relative to the generated project) once generation is done.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PostInitHook {
+    command: String,
+    platform: Option<Platform>,
+    dir: Option<PathBuf>,
+    #[serde(default)]
+    required: bool,
+}
+
+impl Display for PostInitHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`", self.command)?;
+        if let Some(dir) = &self.dir {
+            write!(f, " in `{}`", dir.display())?;
+        }
+        if let Some(platform) = self.platform {
+            write!(f, " (for {})", platform)?;
+        }
+        Ok(())
+    }
+}
+
+impl PostInitHook {
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// Relative to the generated project.
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Hooks for Apple only apply on macOS, since that's the only place the
+    /// Xcode project gets generated.
+    pub fn applies_to(&self, platforms: &[Platform]) -> bool {
+        match self.platform {
+            None => true,
+            Some(Platform::Apple) if !cfg!(target_os = "macos") => false,
+            Some(platform) => platforms.contains(&platform),
+        }
+    }
+
+    // Hooks see the same env as the tools cargo-mobile runs itself, rather
+    // than whatever happened to be set when `cargo mobile init` was run.
+    fn run(&self, root: &Path, env: &Env) -> bossy::Result<bossy::Output> {
+        let dir = match &self.dir {
+            Some(dir) => root.join(dir),
+            None => root.to_owned(),
+        };
+        bossy::Command::pure("sh")
+            .with_env_vars(env.explicit_env())
+            .with_current_dir(dir)
+            .with_args(&["-c", &self.command])
+            .run_and_wait_for_output()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Manifest {
+    #[serde(default)]
+    post_init: Vec<PostInitHook>,
+}
+
+impl Manifest {
+    /// Loads the manifest from the root of the template pack at `pack`, which
+    /// is empty if the pack doesn't have one.
+    pub fn load(pack: &Path) -> Result<Self, ManifestError> {
+        let path = pack.join(MANIFEST_FILE_NAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let bytes = fs::read(&path).map_err(|cause| ManifestError::ReadFailed {
+            path: path.clone(),
+            cause,
+        })?;
+        Self::from_slice(path, &bytes)
+    }
+
+    fn from_slice(path: PathBuf, bytes: &[u8]) -> Result<Self, ManifestError> {
+        let this = toml::from_slice::<Self>(bytes).map_err(|cause| ManifestError::ParseFailed {
+            path: path.clone(),
+            cause,
+        })?;
+        for hook in &this.post_init {
+            if let Some(dir) = &hook.dir {
+                if !dir
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+                {
+                    return Err(ManifestError::DirInvalid {
+                        path,
+                        dir: dir.clone(),
+                    });
+                }
+            }
+        }
+        Ok(this)
+    }
+
+    pub fn post_init(&self) -> &[PostInitHook] {
+        &self.post_init
+    }
+}
+
+/// Whether to run post-init hooks, as decided by `--yes` and `--no-hooks`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PostInitPolicy {
+    Ask,
+    Run,
+    Skip,
+}
+
+impl PostInitPolicy {
+    pub fn from_flags(yes: bool, no_hooks: bool) -> Self {
+        if no_hooks {
+            Self::Skip
+        } else if yes {
+            Self::Run
+        } else {
+            Self::Ask
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PostInitError {
+    EnvFailed(env::Error),
+    PromptFailed(io::Error),
+    RequiredFailed {
+        hook: PostInitHook,
+        cause: bossy::Error,
+    },
+}
+
+fn describe_failure(cause: &bossy::Error) -> String {
+    match cause.output() {
+        Some(output) => format!(
+            "{}\n{}{}",
+            cause,
+            String::from_utf8_lossy(output.stdout()),
+            String::from_utf8_lossy(output.stderr())
+        )
+        .trim_end()
+        .to_owned(),
+        None => cause.to_string(),
+    }
+}
+
+impl Display for PostInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EnvFailed(err) => write!(f, "{}", err),
+            Self::PromptFailed(err) => {
+                write!(f, "Failed to prompt for running post-init hooks: {}", err)
+            }
+            Self::RequiredFailed { hook, cause } => write!(
+                f,
+                "Required post-init hook {} failed: {}",
+                hook,
+                describe_failure(cause)
+            ),
+        }
+    }
+}
+
+/// Runs the hooks that apply to `platforms` from `root`, once they've been
+/// listed and confirmed. Each one's output is captured, and a failure is only
+/// reported unless the hook is `required`, in which case the rest are skipped.
+/// During a dry run, they're only listed as skipped.
+pub fn run_post_init_hooks(
+    wrapper: &TextWrapper,
+    hooks: &[PostInitHook],
+    root: &Path,
+    platforms: &[Platform],
+    policy: PostInitPolicy,
+    non_interactive: NonInteractive,
+    writer: Writer,
+) -> Result<(), PostInitError> {
+    let hooks = hooks
+        .iter()
+        .filter(|hook| hook.applies_to(platforms))
+        .collect::<Vec<_>>();
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    if policy == PostInitPolicy::Skip {
+        ui::status(format!(
+            "Skipping {} post-init hook(s) from the template pack, since `--no-hooks` was passed",
+            hooks.len()
+        ));
+        return Ok(());
+    }
+    if !writer.dry_run() {
+        println!("The template pack wants to run these commands in your project:");
+        for hook in &hooks {
+            println!("  {}", hook);
+        }
+        if policy == PostInitPolicy::Ask {
+            if non_interactive.yes() {
+                println!("Skipping them, since there's nobody to confirm them; pass `--yes` to run them anyway");
+                return Ok(());
+            }
+            let confirmed = loop {
+                if let Some(answer) = prompt::yes_no("Run them?", Some(prompt::YesOrNo::No))
+                    .map_err(PostInitError::PromptFailed)?
+                {
+                    break answer.yes();
+                }
+            };
+            if !confirmed {
+                println!("Skipping post-init hooks; you can run them yourself later");
+                return Ok(());
+            }
+        }
+    }
+    let env = Env::new().map_err(PostInitError::EnvFailed)?;
+    for hook in hooks {
+        let mut output = None;
+        let result = writer.run(&format!("run post-init hook {}", hook), || {
+            ui::status(format!("Running post-init hook {}...", hook));
+            output = Some(hook.run(root, &env)?);
+            Ok(())
+        });
+        match result {
+            Ok(()) => {
+                if let Some(output) = output {
+                    let _ = io::stdout().write_all(output.stdout());
+                    let _ = io::stderr().write_all(output.stderr());
+                }
+            }
+            Err(cause) if hook.required() => {
+                return Err(PostInitError::RequiredFailed {
+                    hook: hook.clone(),
+                    cause,
+                })
+            }
+            Err(cause) => Report::error(
+                format!("Post-init hook {} failed", hook),
+                describe_failure(&cause),
+            )
+            .print(wrapper),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_post_init_hooks() {
+        let manifest = Manifest::from_slice(
+            "pack.toml".into(),
+            br#"
+[[post-init]]
+command = "npm install"
+dir = "web"
+required = true
+
+[[post-init]]
+command = "pod install"
+platform = "apple"
+dir = "gen/apple"
+"#,
+        )
+        .unwrap();
+        let hooks = manifest.post_init();
+        assert_eq!(hooks.len(), 2);
+        assert_eq!(hooks[0].command(), "npm install");
+        assert!(hooks[0].required());
+        assert!(hooks[0].applies_to(&[Platform::Android]));
+        assert!(!hooks[1].required());
+        assert!(!hooks[1].applies_to(&[Platform::Android]));
+        assert!(matches!(
+            Manifest::from_slice(
+                "pack.toml".into(),
+                b"[[post-init]]\ncommand = \"rm -rf *\"\ndir = \"../..\"\n"
+            ),
+            Err(ManifestError::DirInvalid { .. })
+        ));
+    }
+}
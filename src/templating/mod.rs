@@ -1,8 +1,14 @@
+mod custom;
+pub mod embedded;
 mod fancy;
 mod filter;
 mod init;
+pub mod lint;
+mod manifest;
+mod process;
+mod registry;
 
-pub use self::{fancy::*, filter::*, init::*};
+pub use self::{custom::*, fancy::*, filter::*, init::*, manifest::*, process::*, registry::*};
 
 use crate::util::{self, Git};
 use std::{
@@ -15,29 +21,37 @@ use std::{
 // always be at the top of the list.
 static BRAINIUM: &[&str] = &["brainstorm", "brainstorm-demo"];
 
-fn platform_pack_dir() -> Result<PathBuf, util::NoHomeDir> {
-    util::install_dir().map(|dir| dir.join("templates/platforms"))
+fn platform_pack_dir() -> Result<PathBuf, embedded::Error> {
+    embedded::ensure_extracted().map(|dir| dir.join("platforms"))
 }
 
-fn app_pack_dir() -> Result<PathBuf, util::NoHomeDir> {
-    util::install_dir().map(|dir| dir.join("templates/apps"))
+pub fn app_pack_dir() -> Result<PathBuf, embedded::Error> {
+    embedded::ensure_extracted().map(|dir| dir.join("apps"))
 }
 
 #[derive(Debug)]
 pub enum LookupError {
     NoHomeDir(util::NoHomeDir),
+    ExtractFailed(embedded::Error),
     MissingPack {
         name: String,
         tried_toml: PathBuf,
         tried: PathBuf,
     },
     FancyPackParseFailed(FancyPackParseError),
+    RegistryFailed(RegistryError),
+    RegisteredPackInvalid {
+        name: String,
+        other: String,
+    },
+    PathNotFound(PathBuf),
 }
 
 impl Display for LookupError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::NoHomeDir(err) => write!(f, "{}", err),
+            Self::ExtractFailed(err) => write!(f, "{}", err),
             Self::MissingPack {
                 name,
                 tried_toml,
@@ -48,6 +62,15 @@ impl Display for LookupError {
                 name, tried_toml, tried
             ),
             Self::FancyPackParseFailed(err) => write!(f, "{}", err),
+            Self::RegistryFailed(err) => write!(f, "{}", err),
+            Self::RegisteredPackInvalid { name, other } => write!(
+                f,
+                "Template pack {:?} is registered as {:?}, but registered packs have to be paths or git URLs",
+                name, other
+            ),
+            Self::PathNotFound(path) => {
+                write!(f, "Didn't find a template pack at {:?}", path)
+            }
         }
     }
 }
@@ -56,6 +79,7 @@ impl Display for LookupError {
 pub enum Pack {
     Simple(PathBuf),
     Fancy(FancyPack),
+    Custom(CustomPack),
 }
 
 impl Pack {
@@ -96,16 +120,66 @@ impl Pack {
 
     pub fn lookup_platform(name: &str) -> Result<Self, LookupError> {
         platform_pack_dir()
-            .map_err(LookupError::NoHomeDir)
+            .map_err(LookupError::ExtractFailed)
             .and_then(|dir| Self::lookup(dir, name))
     }
 
     pub fn lookup_app(name: &str) -> Result<Self, LookupError> {
         app_pack_dir()
-            .map_err(LookupError::NoHomeDir)
+            .map_err(LookupError::ExtractFailed)
             .and_then(|dir| Self::lookup(dir, name))
     }
 
+    // Relative paths are relative to `base_dir`.
+    fn from_source(source: Source, base_dir: &Path) -> Result<Self, LookupError> {
+        match source {
+            Source::Named(name) => match Self::lookup_app(&name) {
+                Err(err @ LookupError::MissingPack { .. }) => {
+                    let registry = Registry::load().map_err(LookupError::RegistryFailed)?;
+                    match registry.get(&name) {
+                        None => Err(err),
+                        Some(Source::Named(other)) => {
+                            Err(LookupError::RegisteredPackInvalid { name, other })
+                        }
+                        Some(source) => {
+                            log::info!("template pack {:?} is registered as {}", name, source);
+                            let base_dir = registry_path()
+                                .map_err(LookupError::NoHomeDir)?
+                                .parent()
+                                .expect("developer error: registry path was at root")
+                                .to_owned();
+                            Self::from_source(source, &base_dir)
+                        }
+                    }
+                }
+                result => result,
+            },
+            Source::Path(ref path) => {
+                let path = base_dir.join(util::expand_home(path).map_err(LookupError::NoHomeDir)?);
+                if !path.exists() {
+                    Err(LookupError::PathNotFound(path))
+                } else if path.extension() == Some("toml".as_ref()) {
+                    FancyPack::parse(path)
+                        .map(Pack::Fancy)
+                        .map_err(LookupError::FancyPackParseFailed)
+                } else {
+                    Ok(Pack::Custom(CustomPack::from_path(source, path)))
+                }
+            }
+            Source::Git { url, rev } => CustomPack::from_git(url, rev)
+                .map(Pack::Custom)
+                .map_err(LookupError::NoHomeDir),
+        }
+    }
+
+    /// Looks up the app template pack given by `app.template-pack`, which can
+    /// be the name of a built-in or registered pack, a path relative to
+    /// `root_dir`, or a git URL. Built-in packs take precedence over
+    /// registered ones with the same name.
+    pub fn lookup_app_source(source: &str, root_dir: &Path) -> Result<Self, LookupError> {
+        Self::from_source(Source::parse(source), root_dir)
+    }
+
     pub fn expect_local(self) -> PathBuf {
         if let Self::Simple(path) = self {
             path
@@ -137,6 +211,14 @@ impl Pack {
                 Ok(vec![&path])
             }
             Self::Fancy(pack) => pack.resolve(git, submodule_commit),
+            Self::Custom(pack) => {
+                if submodule_commit.is_some() {
+                    log::warn!(
+                        "specified a submodule commit, but the template pack {} isn't submodule-based", pack.source()
+                    );
+                }
+                pack.resolve()
+            }
         }
     }
 }
@@ -144,26 +226,30 @@ impl Pack {
 #[derive(Debug)]
 pub enum ListError {
     NoHomeDir(util::NoHomeDir),
+    ExtractFailed(embedded::Error),
     DirReadFailed { dir: PathBuf, cause: io::Error },
     DirEntryReadFailed { dir: PathBuf, cause: io::Error },
+    RegistryFailed(RegistryError),
 }
 
 impl Display for ListError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::NoHomeDir(err) => write!(f, "{}", err),
+            Self::ExtractFailed(err) => write!(f, "{}", err),
             Self::DirReadFailed { dir, cause } => {
                 write!(f, "Failed to read directory {:?}: {}", dir, cause)
             }
             Self::DirEntryReadFailed { dir, cause } => {
                 write!(f, "Failed to read entry in directory {:?}: {}", dir, cause)
             }
+            Self::RegistryFailed(err) => write!(f, "{}", err),
         }
     }
 }
 
 pub fn list_app_packs() -> Result<Vec<String>, ListError> {
-    let dir = app_pack_dir().map_err(ListError::NoHomeDir)?;
+    let dir = app_pack_dir().map_err(ListError::ExtractFailed)?;
     let mut packs = Vec::new();
     for entry in fs::read_dir(&dir).map_err(|cause| ListError::DirReadFailed {
         dir: dir.clone(),
@@ -193,3 +279,17 @@ pub fn list_app_packs() -> Result<Vec<String>, ListError> {
         packs
     })
 }
+
+/// Registered packs are listed with their sources, and whether a built-in pack
+/// shadows them.
+pub fn list_registered_packs() -> Result<Vec<(String, Source, bool)>, ListError> {
+    let built_in = list_app_packs()?;
+    let registry = Registry::load().map_err(ListError::RegistryFailed)?;
+    Ok(registry
+        .iter()
+        .map(|(name, source)| {
+            let shadowed = built_in.iter().any(|built_in| built_in == name);
+            (name.to_owned(), source, shadowed)
+        })
+        .collect())
+}
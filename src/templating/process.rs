@@ -0,0 +1,394 @@
+use super::{Filter, MANIFEST_FILE_NAME};
+use crate::{config::app::Platform, guard::Guard, plan::Writer};
+use bicycle::{Bicycle, JsonMap};
+use once_cell_regex::regex;
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub(super) static TEMPLATE_EXT: &str = ".hbs";
+
+// Files with these extensions are never rendered, even if they end in `.hbs`,
+// since rendering would only mangle them.
+static BINARY_EXTS: &[&str] = &[
+    "a", "bin", "der", "dylib", "gif", "icns", "ico", "jar", "jks", "jpeg", "jpg", "keystore",
+    "otf", "p12", "png", "so", "spv", "ttf", "webp", "zip",
+];
+
+#[derive(Debug)]
+pub enum ProcessingError {
+    ReadDirFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    ReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    RenderFailed {
+        path: PathBuf,
+        line: Option<usize>,
+        msg: String,
+    },
+    DirCreationFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    WriteFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+}
+
+impl Display for ProcessingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadDirFailed { path, cause } => {
+                write!(f, "Failed to read template directory {:?}: {}", path, cause)
+            }
+            Self::ReadFailed { path, cause } => {
+                write!(f, "Failed to read template {:?}: {}", path, cause)
+            }
+            Self::RenderFailed {
+                path,
+                line: Some(line),
+                msg,
+            } => write!(f, "{}:{}: {}", path.display(), line, msg),
+            Self::RenderFailed {
+                path,
+                line: None,
+                msg,
+            } => write!(f, "{}: {}", path.display(), msg),
+            Self::DirCreationFailed { path, cause } => {
+                write!(f, "Failed to create directory {:?}: {}", path, cause)
+            }
+            Self::WriteFailed { path, cause } => write!(f, "Failed to write {:?}: {}", path, cause),
+        }
+    }
+}
+
+// The engine's errors only know about the string they were rendering, so the
+// line has to be dug out of the message for us to pair it with the path.
+pub(super) fn render_failed(path: &Path, err: impl Display) -> ProcessingError {
+    let msg = err.to_string();
+    let line = regex!(r"line (\d+)|:(\d+):\d+")
+        .captures(&msg)
+        .and_then(|caps| caps.get(1).or_else(|| caps.get(2)))
+        .and_then(|line| line.as_str().parse().ok());
+    ProcessingError::RenderFailed {
+        path: path.to_owned(),
+        line,
+        msg,
+    }
+}
+
+pub(super) fn is_binary(name: &str, contents: &[u8]) -> bool {
+    let ext = Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    ext.map_or(false, |ext| BINARY_EXTS.contains(&ext.as_str()))
+        || contents.contains(&0)
+        || std::str::from_utf8(contents).is_err()
+}
+
+// The parent might not exist yet if the filter didn't allow creating it.
+fn create_parent(path: &Path, writer: Writer) -> Result<(), ProcessingError> {
+    if let Some(parent) = path.parent() {
+        writer
+            .create_dir_all(parent)
+            .map_err(|cause| ProcessingError::DirCreationFailed {
+                path: parent.to_owned(),
+                cause,
+            })?;
+    }
+    Ok(())
+}
+
+fn process_dir(
+    bike: &Bicycle,
+    src_dir: &Path,
+    dest_dir: &Path,
+    insert_data: &dyn Fn(&mut JsonMap),
+    filter: &Filter,
+    skip: &dyn Fn(&Path) -> bool,
+    guard: Option<&Guard>,
+    writer: Writer,
+) -> Result<(), ProcessingError> {
+    let read_dir_failed = |cause| ProcessingError::ReadDirFailed {
+        path: src_dir.to_owned(),
+        cause,
+    };
+    for entry in fs::read_dir(src_dir).map_err(read_dir_failed)? {
+        let src = entry.map_err(read_dir_failed)?.path();
+        if skip(&src) {
+            continue;
+        }
+        let name = src
+            .file_name()
+            .expect("developer error: `read_dir` yielded an unnamed entry")
+            .to_string_lossy();
+        let name = if name.contains("{{") {
+            bike.render(&name, |map| insert_data(map))
+                .map_err(|err| render_failed(&src, err))?
+        } else {
+            name.into_owned()
+        };
+        if src.is_dir() {
+            let dest = dest_dir.join(&name);
+            // Files inside might still be allowed even if the directory
+            // itself isn't, so this only decides about empty directories.
+            if filter.allows(&dest, true) {
+                writer.create_dir_all(&dest).map_err(|cause| {
+                    ProcessingError::DirCreationFailed {
+                        path: dest.clone(),
+                        cause,
+                    }
+                })?;
+            }
+            process_dir(bike, &src, &dest, insert_data, filter, skip, guard, writer)?;
+            continue;
+        }
+        let (name, template) = match name.strip_suffix(TEMPLATE_EXT) {
+            Some(name) => (name, true),
+            None => (name.as_str(), false),
+        };
+        let dest = dest_dir.join(name);
+        if !filter.allows(&dest, false) {
+            continue;
+        }
+        let contents = fs::read(&src).map_err(|cause| ProcessingError::ReadFailed {
+            path: src.clone(),
+            cause,
+        })?;
+        create_parent(&dest, writer)?;
+        let write_failed = |cause| ProcessingError::WriteFailed {
+            path: dest.clone(),
+            cause,
+        };
+        if template && !is_binary(name, &contents) {
+            let template = String::from_utf8(contents)
+                .expect("developer error: `is_binary` let invalid UTF-8 through");
+            let rendered = bike
+                .render(&template, |map| insert_data(map))
+                .map_err(|err| render_failed(&src, err))?;
+            match guard {
+                Some(guard) => guard.write(writer, &dest, rendered),
+                None => writer.write(&dest, rendered),
+            }
+            .map_err(write_failed)?;
+        } else {
+            if template {
+                log::info!("{:?} looks binary, so it'll be copied as-is", src);
+            }
+            match guard {
+                Some(guard) => guard.copy(writer, &src, &dest),
+                None => writer.copy(&src, &dest),
+            }
+            .map_err(write_failed)?;
+        }
+    }
+    Ok(())
+}
+
+/// Renders the templates in `src` into `dest`, skipping anything `filter`
+/// doesn't allow. Only files ending in `.hbs` are rendered (with the extension
+/// stripped), unless they look binary, in which case they're copied as-is like
+/// everything else. Text that shouldn't be rendered can go in a
+/// `{{{{raw}}}}...{{{{/raw}}}}` block. Everything goes through `writer`, so a
+/// dry run only plans what would be written.
+pub fn process(
+    bike: &Bicycle,
+    src: &Path,
+    dest: &Path,
+    insert_data: impl Fn(&mut JsonMap),
+    filter: &Filter,
+    writer: Writer,
+) -> Result<(), ProcessingError> {
+    log::info!("processing templates from {:?} into {:?}", src, dest);
+    process_dir(
+        bike,
+        src,
+        dest,
+        &insert_data,
+        filter,
+        &|_| false,
+        None,
+        writer,
+    )
+}
+
+/// Like [`process`], but for app template packs, where a top-level `android`
+/// or `apple` directory holds files that are only rendered if that platform is
+/// selected. Those directories get rendered into the root of `dest`, so
+/// `android/src/android.rs.hbs` becomes `src/android.rs`. The pack's manifest
+/// is left out, since it's only meant for cargo-mobile. Files that are
+/// already there and differ are left to `guard`.
+pub fn process_app(
+    bike: &Bicycle,
+    src: &Path,
+    dest: &Path,
+    platforms: &[Platform],
+    filter: &Filter,
+    guard: &Guard,
+    writer: Writer,
+) -> Result<(), ProcessingError> {
+    log::info!(
+        "processing app templates from {:?} into {:?} for {:?}",
+        src,
+        dest,
+        platforms
+    );
+    let skipped = Platform::ALL
+        .iter()
+        .map(|platform| src.join(platform.name()))
+        .chain(std::iter::once(src.join(MANIFEST_FILE_NAME)))
+        .collect::<Vec<_>>();
+    process_dir(
+        bike,
+        src,
+        dest,
+        &|_| (),
+        filter,
+        &|path| skipped.iter().any(|skipped| skipped == path),
+        Some(guard),
+        writer,
+    )?;
+    for platform in platforms {
+        let platform_dir = src.join(platform.name());
+        if platform_dir.is_dir() {
+            process_dir(
+                bike,
+                &platform_dir,
+                dest,
+                &|_| (),
+                filter,
+                &|_| false,
+                Some(guard),
+                writer,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{guard::Policy, util::TempDir};
+    use rstest::rstest;
+
+    #[rstest(
+        name,
+        contents,
+        expected,
+        case("MainActivity.kt", b"val x = \"${y}\"" as &[u8], false),
+        case("ic_launcher.png", b"not really a png", true),
+        case("release.KEYSTORE", b"", true),
+        case("blob", b"a\0b", true),
+        case("latin1.txt", b"caf\xe9", true)
+    )]
+    fn test_is_binary(name: &str, contents: &[u8], expected: bool) {
+        assert_eq!(is_binary(name, contents), expected);
+    }
+
+    #[rstest(
+        msg,
+        expected,
+        case("Error rendering \"Unnamed template\" line 3, col 5: oops", Some(3)),
+        case("Template error: invalid handlebars syntax\n    --> Template error in \"Unnamed template\":12:1", Some(12)),
+        case("something else entirely", None)
+    )]
+    fn test_render_failed_finds_line(msg: &str, expected: Option<usize>) {
+        match render_failed(Path::new("a.hbs"), msg) {
+            ProcessingError::RenderFailed { line, .. } => assert_eq!(line, expected),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    fn files(dir: &Path, root: &Path, out: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files(&path, root, out);
+            } else {
+                let rel = path.strip_prefix(root).unwrap();
+                out.push(rel.to_str().unwrap().replace('\\', "/"));
+            }
+        }
+    }
+
+    #[rstest(
+        platforms,
+        expected_files,
+        expected_manifest,
+        case(&[], &["Cargo.toml", "src/lib.rs"], ""),
+        case(
+            &[Platform::Android],
+            &["Cargo.toml", "res/icon.png", "src/android.rs", "src/lib.rs"],
+            "[android]"
+        ),
+        case(&[Platform::Apple], &["Cargo.toml", "src/apple.rs", "src/lib.rs"], "[apple]"),
+        case(
+            &[Platform::Android, Platform::Apple],
+            &["Cargo.toml", "res/icon.png", "src/android.rs", "src/apple.rs", "src/lib.rs"],
+            "[android][apple]"
+        )
+    )]
+    fn test_process_app_only_renders_selected_platforms(
+        platforms: &[Platform],
+        expected_files: &[&str],
+        expected_manifest: &str,
+    ) {
+        let temp_dir = TempDir::new(&format!(
+            "process-test-{}",
+            platforms
+                .iter()
+                .map(|platform| platform.name())
+                .collect::<Vec<_>>()
+                .join("-")
+        ))
+        .unwrap();
+        let root = temp_dir.path();
+        let (src, dest) = (root.join("pack"), root.join("out"));
+        for (path, contents) in &[
+            (
+                "Cargo.toml.hbs",
+                "{{#if platforms.android}}[android]{{/if}}{{#if platforms.apple}}[apple]{{/if}}"
+                    .as_bytes(),
+            ),
+            ("src/lib.rs", b"" as &[u8]),
+            ("pack.toml", b"[[post-init]]\ncommand = \"true\"\n" as &[u8]),
+            ("android/src/android.rs.hbs", b"// {{host-os}}" as &[u8]),
+            ("android/res/icon.png", b"\x89PNG\0" as &[u8]),
+            ("apple/src/apple.rs.hbs", b"// {{host-os}}" as &[u8]),
+        ] {
+            let path = src.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let bike = Bicycle::new(bicycle::EscapeFn::None, Default::default(), {
+            let mut map = JsonMap::default();
+            crate::templating::insert_platform_data(&mut map, platforms);
+            map
+        });
+        process_app(
+            &bike,
+            &src,
+            &dest,
+            platforms,
+            &Filter::WildWest,
+            &Guard::new(&dest, Policy::Keep),
+            Writer::Disk,
+        )
+        .unwrap();
+        let mut actual_files = Vec::new();
+        files(&dest, &dest, &mut actual_files);
+        actual_files.sort();
+        let manifest = fs::read_to_string(dest.join("Cargo.toml")).unwrap();
+        assert_eq!(actual_files, expected_files);
+        assert_eq!(manifest, expected_manifest);
+    }
+}
@@ -0,0 +1,83 @@
+use super::Source;
+use crate::util;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs, io,
+    path::PathBuf,
+};
+
+#[derive(Debug)]
+pub enum RegistryError {
+    NoHomeDir(util::NoHomeDir),
+    ReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    ParseFailed {
+        path: PathBuf,
+        cause: toml::de::Error,
+    },
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoHomeDir(err) => write!(f, "{}", err),
+            Self::ReadFailed { path, cause } => write!(
+                f,
+                "Failed to read template pack registry {:?}: {}",
+                path, cause
+            ),
+            Self::ParseFailed { path, cause } => write!(
+                f,
+                "Failed to parse template pack registry {:?}: {}",
+                path, cause
+            ),
+        }
+    }
+}
+
+pub fn registry_path() -> Result<PathBuf, util::NoHomeDir> {
+    util::install_dir().map(|dir| dir.join("template-packs.toml"))
+}
+
+/// Template packs registered by name in `~/.cargo-mobile/template-packs.toml`,
+/// so that they can be used like built-in ones:
+///
+/// ```toml
+/// [packs]
+/// ours = "git@github.com:acme/templates.git#stable"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Registry {
+    #[serde(default)]
+    packs: BTreeMap<String, String>,
+}
+
+impl Registry {
+    /// Having no registry is the same as having an empty one.
+    pub fn load() -> Result<Self, RegistryError> {
+        let path = registry_path().map_err(RegistryError::NoHomeDir)?;
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|cause| RegistryError::ParseFailed { path, cause }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                log::info!("no template pack registry found at {:?}", path);
+                Ok(Self::default())
+            }
+            Err(cause) => Err(RegistryError::ReadFailed { path, cause }),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Source> {
+        self.packs.get(name).map(|source| Source::parse(source))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Source)> + '_ {
+        self.packs
+            .iter()
+            .map(|(name, source)| (name.as_str(), Source::parse(source)))
+    }
+}
@@ -0,0 +1,265 @@
+//! Copying files to and from an app's data on a device, like pulling its
+//! database to poke at, or pushing a fixture for it to load. Each platform
+//! gets at the data its own way; what's here is what they agree on: remote
+//! paths are relative to the app's data dir, where things land by default,
+//! what gets printed, and that a wildcard has to match exactly one file.
+
+use crate::util::{
+    self,
+    cli::{Report, Reportable},
+};
+use globset::{GlobBuilder, GlobMatcher};
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    NoMatch {
+        pattern: String,
+    },
+    Ambiguous {
+        pattern: String,
+        matches: Vec<String>,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::NoMatch { pattern } => Report::error(
+                format!("Nothing in the app's data matches {:?}", pattern),
+                "Paths are relative to the app's data dir, so `files/*.db` is a database in its `files` dir.",
+            ),
+            Self::Ambiguous { pattern, matches } => Report::action_request(
+                format!("{:?} matches {} files", pattern, matches.len()),
+                format!(
+                    "Only one file can be pulled at a time, and picking one would be a guess. Give one of these instead: {}",
+                    matches.join(", ")
+                ),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NoMatch { .. } => "transfer.no-match",
+            Self::Ambiguous { .. } => "transfer.ambiguous",
+        }
+    }
+}
+
+pub fn has_wildcard(path: &str) -> bool {
+    path.contains(|c| c == '*' || c == '?')
+}
+
+// Components are matched one at a time, so `*` never has a `/` to cross.
+fn matcher(pattern: &str) -> Option<GlobMatcher> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|err| log::warn!("invalid pattern {:?}: {}", pattern, err))
+        .ok()
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters,
+/// `?` matches any one, and `[...]` matches any one of those inside, like they
+/// do in a shell.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    matcher(pattern).map_or(false, |matcher| matcher.is_match(name))
+}
+
+/// The paths under `root` that `pattern` matches, relative to `root`. Only
+/// for when the files are on this machine, like a simulator's are.
+pub fn glob(root: &Path, pattern: &str) -> Vec<String> {
+    let mut found = vec![String::new()];
+    for component in pattern.split('/').filter(|component| !component.is_empty()) {
+        let join = |prefix: &str, name: &str| {
+            if prefix.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{}/{}", prefix, name)
+            }
+        };
+        found = found
+            .into_iter()
+            .flat_map(|prefix| {
+                let dir = root.join(&prefix);
+                if has_wildcard(component) {
+                    let matcher = match matcher(component) {
+                        Some(matcher) => matcher,
+                        None => return Vec::new(),
+                    };
+                    let mut names = fs::read_dir(&dir)
+                        .into_iter()
+                        .flatten()
+                        .flatten()
+                        .filter_map(|entry| entry.file_name().into_string().ok())
+                        .filter(|name| matcher.is_match(name))
+                        .map(|name| join(&prefix, &name))
+                        .collect::<Vec<_>>();
+                    names.sort();
+                    names
+                } else if dir.join(component).exists() {
+                    vec![join(&prefix, component)]
+                } else {
+                    Vec::new()
+                }
+            })
+            .collect();
+    }
+    found
+}
+
+/// The one file `pattern` matched. We'd rather refuse than pick one of
+/// several, since pulling the wrong file looks a lot like pulling the right
+/// one.
+pub fn only_match(pattern: &str, mut matches: Vec<String>) -> Result<String, Error> {
+    match matches.len() {
+        0 => Err(Error::NoMatch {
+            pattern: pattern.to_owned(),
+        }),
+        1 => Ok(matches.remove(0)),
+        _ => Err(Error::Ambiguous {
+            pattern: pattern.to_owned(),
+            matches,
+        }),
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(path)
+}
+
+/// Where a pulled file goes: `local` if it's given (or inside it, if it's a
+/// dir), and otherwise the current dir, under the remote file's name.
+pub fn local_path(remote: &str, local: Option<&Path>) -> PathBuf {
+    match local {
+        Some(local) if local.is_dir() => local.join(file_name(remote)),
+        Some(local) => local.to_owned(),
+        None => PathBuf::from(file_name(remote)),
+    }
+}
+
+/// Where a pushed file goes, which is inside `remote` under the local file's
+/// name if `remote` ends with a `/`.
+pub fn remote_path(local: &Path, remote: &str) -> String {
+    match local.file_name() {
+        Some(name) if remote.is_empty() || remote.ends_with('/') => {
+            format!("{}{}", remote, name.to_string_lossy())
+        }
+        _ => remote.to_owned(),
+    }
+}
+
+pub fn report_pulled(remote: &str, device: impl Display, local: &Path, len: u64) {
+    println!(
+        "Pulled {} ({}) from {} to {}",
+        remote,
+        util::display_size(len),
+        device,
+        local.display()
+    );
+}
+
+pub fn report_pushed(local: &Path, device: impl Display, remote: &str, len: u64) {
+    println!(
+        "Pushed {} ({}) to {} on {}",
+        local.display(),
+        util::display_size(len),
+        remote,
+        device
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+    use rstest::rstest;
+
+    #[rstest(
+        pattern,
+        name,
+        expected,
+        case("*.db", "app.db", true),
+        case("*.db", "app.db-journal", false),
+        case("app-?.log", "app-1.log", true),
+        case("app-?.log", "app-10.log", false),
+        case("*a*b", "xaxxab", true),
+        case("*", "", true),
+        case("prefs.xml", "prefs.xml", true),
+        case("app-[12].log", "app-2.log", true),
+        case("app-[12].log", "app-3.log", false),
+        case("app-[1.log", "app-[1.log", false)
+    )]
+    fn test_matches(pattern: &str, name: &str, expected: bool) {
+        assert_eq!(matches(pattern, name), expected);
+    }
+
+    #[test]
+    fn test_glob() {
+        let temp_dir = TempDir::new("transfer-test").unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("files/logs")).unwrap();
+        for name in &["files/app.db", "files/logs/1.log", "files/logs/2.log"] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        let db = glob(root, "files/*.db");
+        let logs = glob(root, "files/*/?.log");
+        let none = glob(root, "cache/*");
+        assert_eq!(db, vec!["files/app.db"]);
+        assert_eq!(logs, vec!["files/logs/1.log", "files/logs/2.log"]);
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn test_only_match() {
+        assert!(matches!(
+            only_match("*.db", Vec::new()),
+            Err(Error::NoMatch { .. })
+        ));
+        assert_eq!(
+            only_match("*.db", vec!["app.db".to_owned()]).unwrap(),
+            "app.db"
+        );
+        assert!(matches!(
+            only_match("*.db", vec!["app.db".to_owned(), "cache.db".to_owned()]),
+            Err(Error::Ambiguous { matches, .. }) if matches.len() == 2
+        ));
+    }
+
+    #[rstest(
+        local,
+        remote,
+        expected,
+        case("fixture.json", "files/", "files/fixture.json"),
+        case("fixture.json", "", "fixture.json"),
+        case("fixture.json", "files/data.json", "files/data.json")
+    )]
+    fn test_remote_path(local: &str, remote: &str, expected: &str) {
+        assert_eq!(remote_path(Path::new(local), remote), expected);
+    }
+
+    #[test]
+    fn test_local_path() {
+        assert_eq!(
+            local_path("databases/app.db", None),
+            PathBuf::from("app.db")
+        );
+        assert_eq!(
+            local_path("databases/app.db", Some(Path::new("backup.db"))),
+            PathBuf::from("backup.db")
+        );
+        assert_eq!(
+            local_path("databases/app.db", Some(&std::env::temp_dir())),
+            std::env::temp_dir().join("app.db")
+        );
+    }
+}
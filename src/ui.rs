@@ -0,0 +1,98 @@
+//! Where user-facing messages go, so that tools embedding us as a library
+//! can show them however they like (or not at all), instead of having them
+//! printed to a terminal that might not exist.
+//!
+//! Errors aren't messages; they're returned as `Reportable`s for the caller
+//! to deal with. This is for everything else, like which device is being used,
+//! or which steps were skipped.
+
+use crate::{
+    opts,
+    util::cli::{self, Report, TextWrapper},
+};
+use once_cell_regex::exports::once_cell::sync::Lazy;
+use std::{fmt::Display, sync::RwLock};
+
+pub trait Ui: Send + Sync {
+    /// A line of status, like which device is being used.
+    fn status(&self, msg: &str);
+
+    /// Something that went wrong, or that needs doing, that isn't an error.
+    fn report(&self, report: &Report);
+}
+
+/// Prints everything, which is what the CLI does, unless it's quiet, in which
+/// case status only goes to the log.
+#[derive(Debug)]
+pub struct Terminal {
+    wrapper: TextWrapper,
+    quiet: opts::Quiet,
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self::new(cli::wrapper(None))
+    }
+}
+
+impl Terminal {
+    pub fn new(wrapper: TextWrapper) -> Self {
+        Self {
+            wrapper,
+            quiet: opts::Quiet::No,
+        }
+    }
+
+    pub fn with_quiet(mut self, quiet: opts::Quiet) -> Self {
+        self.quiet = quiet;
+        self
+    }
+}
+
+impl Ui for Terminal {
+    fn status(&self, msg: &str) {
+        if self.quiet.yes() {
+            log::info!("{}", msg);
+        } else {
+            println!("{}", msg);
+        }
+    }
+
+    fn report(&self, report: &Report) {
+        report.print(&self.wrapper);
+    }
+}
+
+/// Sends everything to the log, for when there's no one to show it to.
+#[derive(Debug, Default)]
+pub struct Logged;
+
+impl Ui for Logged {
+    fn status(&self, msg: &str) {
+        log::info!("{}", msg);
+    }
+
+    fn report(&self, report: &Report) {
+        log::warn!("{}: {}", report.msg(), report.details());
+    }
+}
+
+static UI: Lazy<RwLock<Box<dyn Ui>>> = Lazy::new(|| RwLock::new(Box::new(Terminal::default())));
+
+fn with_ui<T>(f: impl FnOnce(&dyn Ui) -> T) -> T {
+    f(UI.read().unwrap_or_else(|err| err.into_inner()).as_ref())
+}
+
+/// Replaces where messages go from now on. Until this is called, they're
+/// printed.
+pub fn set(ui: impl Ui + 'static) {
+    *UI.write().unwrap_or_else(|err| err.into_inner()) = Box::new(ui);
+}
+
+pub fn status(msg: impl Display) {
+    with_ui(|ui| ui.status(&msg.to_string()))
+}
+
+pub fn report(report: &Report) {
+    with_ui(|ui| ui.report(report))
+}
@@ -1,7 +1,11 @@
-use crate::util::{
-    self,
-    cli::{Report, TextWrapper},
-    repo::{self, Repo},
+use crate::{
+    templating::embedded,
+    ui,
+    util::{
+        self,
+        cli::{Report, TextWrapper},
+        repo::{self, Repo},
+    },
 };
 use std::{
     fmt::{self, Display},
@@ -22,6 +26,7 @@ pub enum Error {
     MarkerCreateFailed { path: PathBuf, cause: io::Error },
     UpdateFailed(repo::Error),
     InstallFailed(bossy::Error),
+    TemplatesInvalidateFailed(embedded::Error),
     MarkerDeleteFailed { path: PathBuf, cause: io::Error },
 }
 
@@ -41,6 +46,11 @@ impl Display for Error {
                 "Failed to install new version of `cargo-mobile`: {}",
                 err
             ),
+            Self::TemplatesInvalidateFailed(err) => write!(
+                f,
+                "Failed to mark built-in templates for re-extraction: {}",
+                err
+            ),
             Self::MarkerDeleteFailed { path, cause } => {
                 write!(f, "Failed to delete marker file at {:?}: {}", path, cause)
             }
@@ -70,7 +80,7 @@ pub fn update(wrapper: &TextWrapper) -> Result<(), Error> {
         })?;
         repo.update("https://github.com/BrainiumLLC/cargo-mobile")
             .map_err(Error::UpdateFailed)?;
-        println!("Installing updated `cargo-mobile`...");
+        ui::status("Installing updated `cargo-mobile`...");
         bossy::Command::impure_parse("cargo install --force --path")
             .with_arg(repo.path())
             .with_parsed_args("--no-default-features --features")
@@ -80,6 +90,9 @@ pub fn update(wrapper: &TextWrapper) -> Result<(), Error> {
             .with_arg(ENABLED_FEATURES.join(" "))
             .run_and_wait()
             .map_err(Error::InstallFailed)?;
+        // The new binary has its own templates, so the next run should use
+        // them even if the version's the same.
+        embedded::invalidate().map_err(Error::TemplatesInvalidateFailed)?;
         fs::remove_file(&marker).map_err(|cause| Error::MarkerDeleteFailed {
             path: marker.to_owned(),
             cause,
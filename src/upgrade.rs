@@ -0,0 +1,519 @@
+//! Bringing generated projects up to date with the current templates, without
+//! clobbering changes made to them by hand. Generating the projects records a
+//! hash of every file the templates produced, so that upgrading can tell which
+//! files were edited since and leave those for the user to merge.
+
+use crate::android;
+#[cfg(target_os = "macos")]
+use crate::apple;
+use crate::{
+    config::{
+        self,
+        app::Platform,
+        metadata::{self, Metadata},
+        Config,
+    },
+    generated, opts,
+    plan::{self, Writer},
+    regen, templating,
+    util::{
+        self,
+        cli::{Report, Reportable, TextWrapper},
+    },
+    version::{self, Version},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+static MANIFEST_FILE_NAME: &str = ".generated-files.json";
+
+#[derive(Debug)]
+pub enum Error {
+    ConfigFailed(config::LoadOrGenError),
+    MetadataFailed(metadata::Error),
+    VersionFailed(version::Error),
+    TempDirFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    #[cfg(target_os = "macos")]
+    AppleRenderFailed(apple::project::Error),
+    AndroidRenderFailed(android::project::Error),
+    ReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    ManifestInvalid {
+        path: PathBuf,
+        cause: serde_json::Error,
+    },
+    WriteFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    RemoveFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    #[cfg(target_os = "macos")]
+    XcodegenFailed(apple::project::Error),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ConfigFailed(err) => err.report(),
+            Self::MetadataFailed(err) => err.report(),
+            Self::VersionFailed(err) => err.report(),
+            Self::TempDirFailed { path, cause } => Report::error(
+                format!("Failed to create temporary directory {:?}", path),
+                cause,
+            ),
+            #[cfg(target_os = "macos")]
+            Self::AppleRenderFailed(err) => err.report(),
+            Self::AndroidRenderFailed(err) => err.report(),
+            Self::ReadFailed { path, cause } => {
+                Report::error(format!("Failed to read {:?}", path), cause)
+            }
+            Self::ManifestInvalid { path, cause } => Report::error(
+                format!("Failed to parse {:?}", path),
+                format!(
+                    "{}; delete it and every generated file will be treated as modified",
+                    cause
+                ),
+            ),
+            Self::WriteFailed { path, cause } => {
+                Report::error(format!("Failed to write {:?}", path), cause)
+            }
+            Self::RemoveFailed { path, cause } => {
+                Report::error(format!("Failed to remove {:?}", path), cause)
+            }
+            #[cfg(target_os = "macos")]
+            Self::XcodegenFailed(err) => err.report(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ConfigFailed(err) => err.code(),
+            Self::MetadataFailed(err) => err.code(),
+            Self::VersionFailed(err) => err.code(),
+            Self::TempDirFailed { .. } => "upgrade.temp-dir-failed",
+            #[cfg(target_os = "macos")]
+            Self::AppleRenderFailed(err) => err.code(),
+            Self::AndroidRenderFailed(err) => err.code(),
+            Self::ReadFailed { .. } => "upgrade.read-failed",
+            Self::ManifestInvalid { .. } => "upgrade.manifest-invalid",
+            Self::WriteFailed { .. } => "upgrade.write-failed",
+            Self::RemoveFailed { .. } => "upgrade.remove-failed",
+            #[cfg(target_os = "macos")]
+            Self::XcodegenFailed(err) => err.code(),
+        }
+    }
+}
+
+// Maps where each rendered file would go to where it was rendered to.
+fn collect(
+    rendered_dir: &Path,
+    dest_dir: &Path,
+    files: &mut BTreeMap<PathBuf, PathBuf>,
+) -> Result<(), Error> {
+    let entries = fs::read_dir(rendered_dir).map_err(|cause| Error::ReadFailed {
+        path: rendered_dir.to_owned(),
+        cause,
+    })?;
+    for entry in entries {
+        let path = entry
+            .map_err(|cause| Error::ReadFailed {
+                path: rendered_dir.to_owned(),
+                cause,
+            })?
+            .path();
+        let dest = dest_dir.join(
+            path.file_name()
+                .expect("`read_dir` yielded an unnamed entry"),
+        );
+        if path.is_dir() {
+            collect(&path, &dest, files)?;
+        } else {
+            files.insert(dest, path);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Rendered {
+    files: BTreeMap<PathBuf, PathBuf>,
+    _temp_dir: util::TempDir,
+}
+
+// Everything is rendered, regardless of `.gitignore`, since which files the
+// user gets to keep is decided here instead.
+fn render(
+    config: &Config,
+    metadata: &Metadata,
+    version: &Version,
+    bike: &bicycle::Bicycle,
+) -> Result<Rendered, Error> {
+    let temp_dir = util::TempDir::new("upgrade")
+        .map_err(|(path, cause)| Error::TempDirFailed { path, cause })?;
+    let filter = templating::Filter::WildWest;
+    let mut files = BTreeMap::new();
+    #[cfg(target_os = "macos")]
+    if config.app().selects(Platform::Apple) && metadata.apple().supported() {
+        let out_dir = temp_dir.path().join("apple");
+        apple::project::render(
+            config.apple(),
+            metadata.apple(),
+            version,
+            config.app().template_pack().submodule_path(),
+            bike,
+            &filter,
+            &out_dir,
+            Writer::Disk,
+        )
+        .map_err(Error::AppleRenderFailed)?;
+        collect(&out_dir, &config.apple().project_dir(), &mut files)?;
+    }
+    if config.app().selects(Platform::Android) && metadata.android().supported() {
+        let out_dir = temp_dir.path().join("android");
+        android::project::render(
+            config.android(),
+            version,
+            bike,
+            &filter,
+            &out_dir,
+            Writer::Disk,
+        )
+        .map_err(Error::AndroidRenderFailed)?;
+        collect(
+            &out_dir,
+            &android::project::template_dest(config.android()),
+            &mut files,
+        )?;
+    }
+    Ok(Rendered {
+        files,
+        _temp_dir: temp_dir,
+    })
+}
+
+fn read_hash(path: &Path) -> Result<Option<String>, Error> {
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(format!("{:016x}", regen::fnv1a(&contents)))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(cause) => Err(Error::ReadFailed {
+            path: path.to_owned(),
+            cause,
+        }),
+    }
+}
+
+// Paths are kept relative to the app root where possible, so that moving the
+// project doesn't make everything look modified.
+fn relative(config: &Config, path: &Path) -> PathBuf {
+    config
+        .app()
+        .unprefix_path(path)
+        .unwrap_or_else(|_| path.to_owned())
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Manifest {
+    files: BTreeMap<PathBuf, String>,
+}
+
+impl Manifest {
+    fn path(config: &Config) -> PathBuf {
+        generated::gen_dir(config.app()).join(MANIFEST_FILE_NAME)
+    }
+
+    fn from_rendered(config: &Config, rendered: &Rendered) -> Result<Self, Error> {
+        let mut files = BTreeMap::new();
+        for (dest, rendered) in &rendered.files {
+            let hash = read_hash(rendered)?.expect("rendered file vanished");
+            files.insert(relative(config, dest), hash);
+        }
+        Ok(Self { files })
+    }
+
+    // Projects generated before hashes were recorded don't have a manifest,
+    // which makes every file that differs from the templates count as
+    // modified.
+    fn load(config: &Config) -> Result<Self, Error> {
+        let path = Self::path(config);
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|cause| Error::ManifestInvalid { path, cause }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                log::info!("no manifest found at {:?}", path);
+                Ok(Self::default())
+            }
+            Err(cause) => Err(Error::ReadFailed { path, cause }),
+        }
+    }
+
+    fn write(&self, config: &Config) -> Result<(), Error> {
+        let path = Self::path(config);
+        let contents = serde_json::to_string_pretty(self).expect("manifest failed to serialize");
+        path.parent()
+            .map(fs::create_dir_all)
+            .transpose()
+            .and_then(|_| fs::write(&path, contents))
+            .map_err(|cause| Error::WriteFailed { path, cause })
+    }
+
+    fn base(&self, config: &Config, path: &Path) -> Option<&str> {
+        self.files.get(&relative(config, path)).map(String::as_str)
+    }
+}
+
+/// Records what the templates produced, for `cargo mobile upgrade` to compare
+/// against later. Failing to just means upgrading will be more cautious.
+pub fn record(config: &Config, metadata: &Metadata, version: &Version, bike: &bicycle::Bicycle) {
+    let result = render(config, metadata, version, bike)
+        .and_then(|rendered| Manifest::from_rendered(config, &rendered))
+        .and_then(|manifest| manifest.write(config));
+    if let Err(err) = result {
+        let report = err.report();
+        log::warn!(
+            "failed to record hashes of generated files: {}: {}",
+            report.msg(),
+            report.details()
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Action {
+    // The templates have a new file.
+    Add,
+    // The templates changed a file the user didn't.
+    Update,
+    // The user changed a file the templates didn't.
+    Keep,
+    // The user and the templates both changed a file.
+    Conflict,
+    // The templates dropped a file the user didn't change.
+    Remove,
+    // The templates dropped a file the user changed.
+    Orphan,
+}
+
+impl Action {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Update => "update",
+            Self::Keep => "keep",
+            Self::Conflict => "conflict",
+            Self::Remove => "remove",
+            Self::Orphan => "orphan",
+        }
+    }
+}
+
+// Compares hashes of what's on disk, what was last generated, and what the
+// templates produce now. `None` means there's nothing to do.
+fn classify(current: Option<&str>, base: Option<&str>, new: Option<&str>) -> Option<Action> {
+    match (current, new) {
+        (None, None) => None,
+        (None, Some(_)) => Some(Action::Add),
+        (Some(current), Some(new)) if current == new => None,
+        (Some(current), Some(_)) if base == Some(current) => Some(Action::Update),
+        (Some(_), Some(new)) if base == Some(new) => Some(Action::Keep),
+        (Some(_), Some(_)) => Some(Action::Conflict),
+        (Some(current), None) if base == Some(current) => Some(Action::Remove),
+        (Some(_), None) => Some(Action::Orphan),
+    }
+}
+
+fn new_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".new");
+    path.with_file_name(file_name)
+}
+
+fn copy(src: &Path, dest: &Path) -> Result<(), Error> {
+    dest.parent()
+        .map(fs::create_dir_all)
+        .transpose()
+        .and_then(|_| fs::copy(src, dest))
+        .map(|_| ())
+        .map_err(|cause| Error::WriteFailed {
+            path: dest.to_owned(),
+            cause,
+        })
+}
+
+/// Renders the current templates and applies whatever changed to the generated
+/// projects. Files the user changed are left alone; if the templates changed
+/// them too, the new version gets written next to them with a `.new`
+/// extension instead. With `dry_run`, nothing is changed at all, and `diff`
+/// shows how the files that would be updated would change. `template_vars`
+/// are the ones passed with `--var`.
+pub fn exec(
+    wrapper: &TextWrapper,
+    non_interactive: opts::NonInteractive,
+    dry_run: bool,
+    diff: bool,
+    template_vars: &[(String, String)],
+) -> Result<(), Error> {
+    let (config, _origin) = Config::load_or_gen(
+        ".",
+        non_interactive,
+        None,
+        None,
+        None,
+        None,
+        None,
+        Writer::Disk,
+        wrapper,
+    )
+    .map_err(Error::ConfigFailed)?;
+    let metadata = Metadata::load(config.app().root_dir()).map_err(Error::MetadataFailed)?;
+    let version = Version::resolve(config.app()).map_err(Error::VersionFailed)?;
+    let bike = config.build_a_bike(template_vars);
+    let rendered = render(&config, &metadata, &version, &bike)?;
+    let manifest = Manifest::load(&config)?;
+
+    let mut changes = Vec::new();
+    for (dest, src) in &rendered.files {
+        let action = classify(
+            read_hash(dest)?.as_deref(),
+            manifest.base(&config, dest),
+            read_hash(src)?.as_deref(),
+        );
+        if let Some(action) = action {
+            changes.push((dest.clone(), Some(src), action));
+        }
+    }
+    for (path, base) in &manifest.files {
+        let dest = config.app().prefix_path(path);
+        if !rendered.files.contains_key(&dest) {
+            if let Some(action) = classify(read_hash(&dest)?.as_deref(), Some(base.as_str()), None)
+            {
+                changes.push((dest, None, action));
+            }
+        }
+    }
+
+    if changes.is_empty() {
+        println!("Your projects are already up to date with the current templates.");
+    }
+    for (dest, src, action) in &changes {
+        let relative = relative(&config, dest);
+        println!("{:>8} {}", action.label(), relative.display());
+        if let (true, Some(src), Action::Update | Action::Conflict) = (diff, src, action) {
+            let read = |path: &Path| {
+                fs::read(path).map_err(|cause| Error::ReadFailed {
+                    path: path.to_owned(),
+                    cause,
+                })
+            };
+            plan::print_diff(&relative.to_string_lossy(), &read(dest)?, &read(src)?);
+        }
+    }
+    if dry_run {
+        if !changes.is_empty() {
+            println!("This was a dry run, so nothing was changed.");
+        }
+        return Ok(());
+    }
+
+    let mut conflicts = Vec::new();
+    for (dest, src, action) in &changes {
+        match (action, src) {
+            (Action::Add, Some(src)) | (Action::Update, Some(src)) => copy(src, dest)?,
+            (Action::Conflict, Some(src)) => {
+                let new = new_path(dest);
+                copy(src, &new)?;
+                conflicts.push(relative(&config, &new));
+            }
+            (Action::Remove, _) => fs::remove_file(dest).map_err(|cause| Error::RemoveFailed {
+                path: dest.clone(),
+                cause,
+            })?,
+            _ => (),
+        }
+    }
+    // Conflicts count as generated now too, so that they don't come up again
+    // until the templates change them again.
+    Manifest::from_rendered(&config, &rendered)?.write(&config)?;
+
+    #[cfg(target_os = "macos")]
+    if changes.iter().any(|(dest, _, action)| {
+        matches!(action, Action::Add | Action::Update | Action::Remove)
+            && dest.starts_with(config.apple().project_dir())
+    }) {
+        apple::project::xcodegen(config.apple()).map_err(Error::XcodegenFailed)?;
+    }
+
+    if conflicts.is_empty() {
+        if !changes.is_empty() {
+            Report::victory(
+                "Projects upgraded successfully!",
+                "Everything you changed by hand was left as is.",
+            )
+            .print(wrapper);
+        }
+    } else {
+        Report::action_request(
+            "Some files were changed both by you and by the templates",
+            format!(
+                "Your versions were left in place, and the new versions were written next to them: {}. Merge in whatever you want to keep, and then delete them.",
+                conflicts
+                    .iter()
+                    .map(|path| format!("{:?}", path))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )
+        .print(wrapper);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        current,
+        base,
+        new,
+        expected,
+        case(None, None, Some("a"), Some(Action::Add)),
+        case(Some("a"), Some("a"), Some("a"), None),
+        case(Some("b"), None, Some("b"), None),
+        case(Some("a"), Some("a"), Some("b"), Some(Action::Update)),
+        case(Some("c"), Some("a"), Some("a"), Some(Action::Keep)),
+        case(Some("c"), Some("a"), Some("b"), Some(Action::Conflict)),
+        case(Some("c"), None, Some("b"), Some(Action::Conflict)),
+        case(Some("a"), Some("a"), None, Some(Action::Remove)),
+        case(Some("c"), Some("a"), None, Some(Action::Orphan)),
+        case(None, Some("a"), None, None)
+    )]
+    fn test_classify(
+        current: Option<&str>,
+        base: Option<&str>,
+        new: Option<&str>,
+        expected: Option<Action>,
+    ) {
+        assert_eq!(classify(current, base, new), expected);
+    }
+
+    #[test]
+    fn test_new_path() {
+        assert_eq!(
+            new_path(Path::new("gen/android/app/build.gradle.kts")),
+            Path::new("gen/android/app/build.gradle.kts.new")
+        );
+    }
+}
@@ -11,6 +11,8 @@ pub struct CargoCommand<'a> {
     no_default_features: bool,
     features: Option<&'a [String]>,
     release: bool,
+    build_std: Option<&'a [String]>,
+    jobs: Option<u32>,
 }
 
 impl<'a> CargoCommand<'a> {
@@ -24,6 +26,8 @@ impl<'a> CargoCommand<'a> {
             no_default_features: Default::default(),
             features: Default::default(),
             release: Default::default(),
+            build_std: Default::default(),
+            jobs: Default::default(),
         }
     }
 
@@ -62,6 +66,17 @@ impl<'a> CargoCommand<'a> {
         self
     }
 
+    /// Builds these crates from source for the target, which needs nightly.
+    pub fn with_build_std(mut self, build_std: Option<&'a [String]>) -> Self {
+        self.build_std = build_std;
+        self
+    }
+
+    pub fn with_jobs(mut self, jobs: Option<u32>) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
     fn into_command_inner(self, mut command: bossy::Command) -> bossy::Command {
         command.add_arg(self.subcommand);
         if self.verbose {
@@ -96,6 +111,12 @@ impl<'a> CargoCommand<'a> {
         if self.release {
             command.add_arg("--release");
         }
+        if let Some(build_std) = self.build_std {
+            command.add_args(&["-Z", &format!("build-std={}", build_std.join(","))]);
+        }
+        if let Some(jobs) = self.jobs {
+            command.add_args(&["--jobs", &jobs.to_string()]);
+        }
         command
     }
 
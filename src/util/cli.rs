@@ -1,7 +1,16 @@
-use crate::{opts, util};
+use crate::{
+    opts,
+    plan::{Plan, Writer},
+    resources, sizes, templating, ui,
+    util::{self, logging, progress, proxy, watchdog},
+    vms, workspace,
+};
 use colored::Colorize as _;
 use once_cell_regex::exports::once_cell::sync::Lazy;
-use std::fmt::{Debug, Display};
+use std::{
+    fmt::{Debug, Display},
+    path::PathBuf,
+};
 use structopt::{
     clap::{self, AppSettings},
     StructOpt,
@@ -41,6 +50,15 @@ pub struct GlobalFlags {
         parse(from_occurrences = opts::NoiseLevel::from_occurrences),
     )]
     pub noise_level: opts::NoiseLevel,
+    #[structopt(
+        short = "q",
+        long = "quiet",
+        help = "Only print errors, warnings, and results",
+        global = true,
+        conflicts_with = "noise-level",
+        parse(from_flag = opts::Quiet::from_bool),
+    )]
+    pub quiet: opts::Quiet,
     #[structopt(
         short = "y",
         long = "non-interactive",
@@ -49,6 +67,92 @@ pub struct GlobalFlags {
         parse(from_flag = opts::NonInteractive::from_bool),
     )]
     pub non_interactive: opts::NonInteractive,
+    #[structopt(
+        long = "format",
+        help = "How to print results; `json` also streams progress events to stderr",
+        global = true,
+        possible_values = &["text", "json"],
+    )]
+    pub format: Option<opts::Format>,
+    #[structopt(
+        long = "progress-fd",
+        help = "File descriptor to stream progress events to, instead of stderr",
+        global = true
+    )]
+    pub progress_fd: Option<u32>,
+    #[structopt(
+        long = "width",
+        help = "Column to wrap messages at, instead of the terminal's width",
+        global = true
+    )]
+    pub width: Option<usize>,
+    #[structopt(
+        long = "ignore-version-mismatch",
+        help = "Build from `gen` even if it was generated by a cargo-mobile whose layout doesn't match this one's",
+        global = true,
+        parse(from_flag = opts::IgnoreVersionMismatch::from_bool),
+    )]
+    pub ignore_version_mismatch: opts::IgnoreVersionMismatch,
+    #[structopt(
+        long = "ephemeral",
+        help = "Shut down any simulators or emulators this command boots once it's done",
+        global = true,
+        parse(from_flag = opts::Ephemeral::from_bool),
+    )]
+    pub ephemeral: opts::Ephemeral,
+    #[structopt(
+        short = "j",
+        long = "jobs",
+        help = "How many jobs cargo, Gradle, and xcodebuild get, or `auto` to pick for this machine",
+        global = true
+    )]
+    pub jobs: Option<resources::Setting>,
+    #[structopt(
+        long = "no-watchdog",
+        help = "Wait on Gradle, xcodebuild, and adb however long they go without printing anything",
+        global = true
+    )]
+    pub no_watchdog: bool,
+}
+
+impl GlobalFlags {
+    /// These flags as args, for passing on to another `cargo mobile` run.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        match self.noise_level {
+            opts::NoiseLevel::Polite => (),
+            opts::NoiseLevel::LoudAndProud => args.push("-v".to_owned()),
+            opts::NoiseLevel::FranklyQuitePedantic => args.push("-vv".to_owned()),
+        }
+        if self.quiet.yes() {
+            args.push("--quiet".to_owned());
+        }
+        if self.non_interactive.yes() {
+            args.push("--non-interactive".to_owned());
+        }
+        if let Some(format) = self.format {
+            args.push(format!("--format={}", format.as_str()));
+        }
+        if let Some(progress_fd) = self.progress_fd {
+            args.push(format!("--progress-fd={}", progress_fd));
+        }
+        if let Some(width) = self.width {
+            args.push(format!("--width={}", width));
+        }
+        if self.ignore_version_mismatch.yes() {
+            args.push("--ignore-version-mismatch".to_owned());
+        }
+        if self.ephemeral.yes() {
+            args.push("--ephemeral".to_owned());
+        }
+        if let Some(jobs) = self.jobs {
+            args.push(format!("--jobs={}", jobs));
+        }
+        if self.no_watchdog {
+            args.push("--no-watchdog".to_owned());
+        }
+        args
+    }
 }
 
 #[derive(Clone, Copy, Debug, StructOpt)]
@@ -71,6 +175,366 @@ pub struct ReinstallDeps {
     pub reinstall_deps: opts::ReinstallDeps,
 }
 
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct SkipStrip {
+    #[structopt(
+        long = "no-strip",
+        help = "Keep debug symbols in release libs",
+        parse(from_flag = opts::SkipStrip::from_bool),
+    )]
+    pub skip_strip: opts::SkipStrip,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct Force {
+    #[structopt(
+        long = "force",
+        help = "Redo every build step, even ones that look up to date",
+        parse(from_flag = opts::Force::from_bool),
+    )]
+    pub force: opts::Force,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct NoHooks {
+    #[structopt(
+        long = "no-hooks",
+        help = "Skip the pre/post hooks in your config",
+        parse(from_flag = opts::NoHooks::from_bool),
+    )]
+    pub no_hooks: opts::NoHooks,
+}
+
+/// Which app a command's for, in a workspace with more than one. Either picks
+/// a package, and the command then runs from its dir.
+#[derive(Clone, Debug, Default, StructOpt)]
+pub struct Package {
+    #[structopt(
+        long = "manifest-path",
+        help = "Path to the `Cargo.toml` of the app's package, instead of the one the current dir is in",
+        global = true,
+        parse(from_os_str)
+    )]
+    pub manifest_path: Option<PathBuf>,
+    #[structopt(
+        short = "p",
+        long = "package",
+        help = "Workspace package of the app, instead of the one the current dir is in",
+        global = true
+    )]
+    pub package: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct NoDaemonManagement {
+    #[structopt(
+        long = "no-daemon-management",
+        help = "Never stop Gradle daemons or restart the adb server, even if they look stale",
+        global = true,
+        parse(from_flag = opts::NoDaemonManagement::from_bool),
+    )]
+    pub no_daemon_management: opts::NoDaemonManagement,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct NoCrashCheck {
+    #[structopt(
+        long = "no-crash-check",
+        help = "Don't check whether the app crashed right after launching, for apps that are meant to exit",
+        parse(from_flag = opts::NoCrashCheck::from_bool),
+    )]
+    pub no_crash_check: opts::NoCrashCheck,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct Timings {
+    #[structopt(
+        long = "timings",
+        help = "Print how long each build step took (always on with `-v`)",
+        parse(from_flag = opts::Timings::from_bool),
+    )]
+    pub timings: opts::Timings,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct ShowAssets {
+    #[structopt(
+        long = "show-assets",
+        help = "List the assets that will be packaged, with their sizes"
+    )]
+    pub show_assets: bool,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct SizeReport {
+    #[structopt(
+        long = "size-report",
+        help = "How to print the sizes of what was built",
+        default_value = "text",
+        possible_values = &["text", "json"],
+    )]
+    pub size_report: sizes::Format,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct DeviceTimeout {
+    #[structopt(
+        long = "device-timeout",
+        help = "How many seconds to wait for a device that's locked or doesn't trust this computer yet",
+        default_value = "60"
+    )]
+    pub device_timeout: u64,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct Features {
+    #[structopt(
+        long = "features",
+        help = "Comma-separated cargo features to build with, on top of the ones in your config",
+        use_delimiter = true
+    )]
+    pub features: Vec<String>,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct Variant {
+    #[structopt(
+        long = "variant",
+        help = "Build the variant of the app with this name from your config"
+    )]
+    pub variant: Option<String>,
+}
+
+fn parse_var(var: &str) -> Result<(String, String), String> {
+    let index = var
+        .find('=')
+        .ok_or_else(|| format!("expected `key=value`, but got {:?}", var))?;
+    let (key, value) = (&var[..index], &var[index + 1..]);
+    if templating::RESERVED_VARS.contains(&key) {
+        Err(format!(
+            "`{}` is used by built-in template data or helpers; the reserved names are {}",
+            key,
+            templating::RESERVED_VARS.join(", ")
+        ))
+    } else {
+        Ok((key.to_owned(), value.to_owned()))
+    }
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct TemplateVars {
+    #[structopt(
+        long = "var",
+        help = "Template var to set as `key=value`, overriding `[template.vars]` in your config",
+        number_of_values = 1,
+        parse(try_from_str = parse_var)
+    )]
+    pub vars: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct PostInitHooks {
+    #[structopt(
+        long = "yes",
+        help = "Run the template pack's post-init hooks without asking first"
+    )]
+    pub yes: bool,
+    #[structopt(
+        long = "no-hooks",
+        conflicts_with = "yes",
+        help = "Skip the template pack's post-init hooks"
+    )]
+    pub no_hooks: bool,
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct DryRun {
+    #[structopt(
+        long = "dry-run",
+        help = "List what would be generated, without changing anything"
+    )]
+    pub dry_run: bool,
+    #[structopt(
+        long = "diff",
+        requires = "dry-run",
+        help = "Show how files that would be overwritten would change"
+    )]
+    pub diff: bool,
+}
+
+impl DryRun {
+    /// Gives `f` a writer that only plans what it'd write during a dry run,
+    /// and then lists everything that was planned.
+    pub fn exec<E>(self, f: impl FnOnce(Writer) -> Result<(), E>) -> Result<(), E> {
+        if !self.dry_run {
+            return f(Writer::Disk);
+        }
+        let plan = Plan::new();
+        f(Writer::DryRun(&plan))?;
+        plan.print(&std::env::current_dir().unwrap_or_default(), self.diff);
+        println!("This was a dry run, so nothing was changed.");
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, StructOpt)]
+pub struct Completions {
+    #[structopt(
+        name = "shell",
+        help = "Shell to generate completions for",
+        possible_values = &clap::Shell::variants(),
+        case_insensitive = true,
+    )]
+    pub shell: clap::Shell,
+}
+
+/// Prints a completion script for `cargo <name>`, generated from the same
+/// definition that parses our args, so it can't fall out of date. If
+/// `devices` is set, `--device` completes with whatever `cargo <name> devices
+/// --names` prints at the time, since clap can only generate static
+/// completions.
+pub fn print_completions<T: StructOpt>(name: &str, shell: clap::Shell, devices: bool) {
+    print!("{}", completions::<T>(name, shell, devices));
+}
+
+// clap only knows how to complete the binary it's given, which is
+// `cargo-<name>`, but nobody types that. For the shells that let us, we tack
+// on a completer for `cargo` that hands `cargo <name> ...` over to the
+// generated one as `cargo-<name> ...`, and hands everything else to whatever
+// completed `cargo` before us.
+//
+// Device names are completed by wrapping the generated completer in one that
+// takes over right after `--device`.
+fn completions<T: StructOpt>(name: &str, shell: clap::Shell, devices: bool) -> String {
+    let bin = format!("cargo-{}", name);
+    let mut script = Vec::new();
+    T::clap().gen_completions_to(&bin, shell, &mut script);
+    let mut script =
+        String::from_utf8(script).expect("developer error: completion script wasn't UTF-8");
+    let completer = if devices {
+        format!("__cargo_{}_devices", name)
+    } else {
+        format!("_{}", bin)
+    };
+    match shell {
+        clap::Shell::Bash => {
+            if devices {
+                script.push_str(&format!(
+                    r#"
+{completer}() {{
+    if [[ ${{COMP_WORDS[COMP_CWORD-1]}} == --device ]]; then
+        local IFS=$'\n'
+        COMPREPLY=($(compgen -W "$({bin} devices --names 2>/dev/null)" -- "${{COMP_WORDS[COMP_CWORD]}}"))
+        # Escapes the spaces in device names.
+        compopt -o filenames 2>/dev/null
+    else
+        _{bin}
+    fi
+}}
+complete -F {completer} -o bashdefault -o default {bin}
+"#,
+                    completer = completer,
+                    bin = bin,
+                ));
+            }
+            script.push_str(&format!(
+                r#"
+__cargo_{name}_fallback=$(complete -p cargo 2>/dev/null | sed -n 's/.* -F \([^ ]*\) .*/\1/p')
+__cargo_{name}() {{
+    if [[ ${{COMP_CWORD}} -ge 2 && ${{COMP_WORDS[1]}} == {name} ]]; then
+        COMP_WORDS=({bin} "${{COMP_WORDS[@]:2}}")
+        COMP_CWORD=$((COMP_CWORD - 1))
+        {completer}
+    elif [[ -n ${{__cargo_{name}_fallback}} ]]; then
+        "${{__cargo_{name}_fallback}}" "$@"
+    fi
+}}
+complete -F __cargo_{name} -o bashdefault -o default cargo
+"#,
+                name = name,
+                bin = bin,
+                completer = completer,
+            ));
+        }
+        clap::Shell::Zsh => {
+            // The script ends by calling its completer, which only works when
+            // it's autoloaded from `fpath`; sourcing it is what lets us
+            // register for `cargo` too, so that call has to go.
+            let call = format!("_{} \"$@\"", bin);
+            if let Some(index) = script.rfind(&call) {
+                script.truncate(index);
+            }
+            if devices {
+                script.push_str(&format!(
+                    r#"
+{completer}() {{
+    if [[ ${{words[CURRENT-1]}} == --device ]]; then
+        local -a devices
+        devices=("${{(@f)$({bin} devices --names 2>/dev/null)}}")
+        compadd -a devices
+    else
+        _{bin}
+    fi
+}}
+"#,
+                    completer = completer,
+                    bin = bin,
+                ));
+            }
+            script.push_str(&format!(
+                r#"
+__cargo_{name}_fallback=${{_comps[cargo]}}
+__cargo_{name}() {{
+    if (( CURRENT > 2 )) && [[ ${{words[2]}} == {name} ]]; then
+        shift words
+        words[1]={bin}
+        (( CURRENT-- ))
+        {completer}
+    elif [[ -n ${{__cargo_{name}_fallback}} ]]; then
+        ${{__cargo_{name}_fallback}} "$@"
+    fi
+}}
+compdef {completer} {bin}
+compdef __cargo_{name} cargo
+"#,
+                name = name,
+                bin = bin,
+                completer = completer,
+            ));
+        }
+        clap::Shell::Fish => {
+            if devices {
+                script.push_str(&format!(
+                    "\ncomplete -c {bin} -l device -x -a \"({bin} devices --names 2>/dev/null)\"\n",
+                    bin = bin,
+                ));
+            }
+            script.push_str(&format!(
+                r#"
+complete -c cargo -n "__fish_use_subcommand" -f -a "{name}"
+complete -c cargo -n "__fish_seen_subcommand_from {name}" -f -a "(complete -C (commandline -cp | string replace -r '^\s*cargo\s+{name}\b' {bin}))"
+"#,
+                name = name,
+                bin = bin,
+            ));
+        }
+        // PowerShell and Elvish only let one completer claim a command, so
+        // these still complete `cargo-<name>`.
+        clap::Shell::PowerShell | clap::Shell::Elvish => (),
+    }
+    script
+}
+
+/// The names of `T`'s subcommands, along with what they do.
+pub fn subcommands<T: StructOpt>() -> Vec<(String, Option<String>)> {
+    T::clap()
+        .p
+        .subcommands
+        .iter()
+        .map(|app| (app.p.meta.name.clone(), app.p.meta.about.map(str::to_owned)))
+        .collect()
+}
+
 #[derive(Clone, Copy, Debug, StructOpt)]
 pub struct Profile {
     #[structopt(
@@ -83,6 +547,68 @@ pub struct Profile {
 
 pub type TextWrapper = textwrap::Wrapper<'static, textwrap::NoHyphenation>;
 
+/// Wraps at `width` columns if it's given, and at the terminal's width
+/// otherwise, which is 80 if there's no terminal.
+pub fn wrapper(width: Option<usize>) -> TextWrapper {
+    TextWrapper::with_splitter(
+        width.unwrap_or_else(textwrap::termwidth),
+        textwrap::NoHyphenation,
+    )
+}
+
+// Splits on spaces, except inside a closed `backtick span`, so that commands
+// and paths stay in one piece for copying. Leading whitespace is kept, since
+// it's probably indentation.
+fn words(line: &str) -> Vec<String> {
+    let trimmed = line.trim_start();
+    let segments = trimmed.split('`').collect::<Vec<_>>();
+    let mut words = vec![line[..line.len() - trimmed.len()].to_owned()];
+    for (i, segment) in segments.iter().enumerate() {
+        let word = words
+            .last_mut()
+            .expect("developer error: no words to add to");
+        if i > 0 {
+            word.push('`');
+        }
+        if i % 2 == 1 && i + 1 < segments.len() {
+            word.push_str(segment);
+        } else {
+            let mut parts = segment.split(' ');
+            word.push_str(parts.next().unwrap_or_default());
+            words.extend(parts.map(str::to_owned));
+        }
+    }
+    words.retain(|word| !word.is_empty());
+    words
+}
+
+// Each line's wrapped on its own, starting with `first` for the very first
+// line and `rest` for every one after. Words longer than `width` are left to
+// overflow instead of being broken.
+fn wrap(text: &str, width: usize, first: &str, rest: &str) -> String {
+    let len = |s: &str| s.chars().count();
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let mut current: Option<String> = None;
+        for word in words(line) {
+            let fits = current
+                .as_ref()
+                .map_or(false, |current| len(current) + 1 + len(&word) <= width);
+            if fits {
+                let current = current.as_mut().expect("developer error: nothing fit");
+                current.push(' ');
+                current.push_str(&word);
+            } else {
+                lines.extend(current.take());
+                let indent = if lines.is_empty() { first } else { rest };
+                current = Some(format!("{}{}", indent, word));
+            }
+        }
+        lines.push(current.unwrap_or_default());
+    }
+    lines.join("\n")
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum Label {
     Error,
@@ -113,6 +639,15 @@ impl Label {
             Self::Victory => "victory",
         }
     }
+
+    /// The label's name in JSON output.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::ActionRequest => "action-request",
+            Self::Victory => "victory",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -120,6 +655,7 @@ pub struct Report {
     label: Label,
     msg: String,
     details: String,
+    code: Option<&'static str>,
 }
 
 impl Report {
@@ -128,9 +664,21 @@ impl Report {
             label,
             msg: format!("{}", msg),
             details: format!("{}", details),
+            code: None,
         }
     }
 
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// Adds a paragraph to the details, for what the error itself can't know.
+    pub fn with_note(mut self, note: impl Display) -> Self {
+        self.details = format!("{}\n\n{}", self.details, note);
+        self
+    }
+
     pub fn error(msg: impl Display, details: impl Display) -> Self {
         Self::new(Label::Error, msg, details)
     }
@@ -147,29 +695,71 @@ impl Report {
         self.label.exit_code()
     }
 
-    fn format(&self, wrapper: &TextWrapper) -> String {
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn details(&self) -> &str {
+        &self.details
+    }
+
+    pub fn label(&self) -> Label {
+        self.label
+    }
+
+    pub fn code(&self) -> Option<&'static str> {
+        self.code
+    }
+
+    // Colors go on after wrapping, since escape codes would otherwise count
+    // towards the width.
+    fn render(&self, wrapper: &TextWrapper, colorize: bool) -> String {
         static INDENT: &str = "    ";
-        let head = if colored::control::SHOULD_COLORIZE.should_colorize() {
-            wrapper.fill(&format!(
-                "{} {}",
-                format!("{}:", self.label.as_str())
-                    .color(self.label.color())
-                    .bold(),
-                self.msg.color(self.label.color())
-            ))
-        } else {
-            wrapper.fill(&format!("{}: {}", self.label.as_str(), &self.msg))
+        let label = format!("{}:", self.label.as_str());
+        let code = self.code.map(|code| format!("[{}]", code));
+        let head = wrap(
+            &format!(
+                "{} {}{}",
+                label,
+                self.msg,
+                code.as_ref()
+                    .map(|code| format!(" {}", code))
+                    .unwrap_or_default()
+            ),
+            wrapper.width,
+            "",
+            &" ".repeat(label.chars().count() + 1),
+        );
+        let details = wrap(&self.details, wrapper.width, INDENT, INDENT);
+        if !colorize {
+            return format!("{}\n{}\n", head, details);
+        }
+        let color = self.label.color();
+        let head = match head.strip_prefix(&label) {
+            Some(rest) => {
+                let (msg, code) = match &code {
+                    Some(code) => match rest.strip_suffix(code.as_str()) {
+                        Some(msg) => (msg, code.dimmed().to_string()),
+                        None => (rest, String::new()),
+                    },
+                    None => (rest, String::new()),
+                };
+                format!("{}{}{}", label.color(color).bold(), msg.color(color), code)
+            }
+            None => head.color(color).to_string(),
         };
-        let wrapper = wrapper
-            .clone()
-            .initial_indent(INDENT)
-            .subsequent_indent(INDENT);
-        format!("{}\n{}\n", head, wrapper.fill(&self.details))
+        format!("{}\n{}\n", head, details)
+    }
+
+    fn format(&self, wrapper: &TextWrapper) -> String {
+        self.render(wrapper, colored::control::SHOULD_COLORIZE.should_colorize())
     }
 
     pub fn print(&self, wrapper: &TextWrapper) {
         let s = self.format(wrapper);
-        if matches!(self.label, Label::Error) {
+        // Progress events on stderr are only parseable if nothing else is
+        // written there.
+        if matches!(self.label, Label::Error) && !progress::on_stderr() {
             eprint!("{}", s)
         } else {
             print!("{}", s)
@@ -179,6 +769,16 @@ impl Report {
 
 pub trait Reportable: Debug {
     fn report(&self) -> Report;
+
+    /// Which error this is, like `android.env.sdk-root-not-set`, for tools and
+    /// tests to match on instead of messages. These are listed by the hidden
+    /// `cargo mobile errors --list`, and can't change once released.
+    fn code(&self) -> &'static str;
+
+    /// How bad it is, which is the same as the label it's reported with.
+    fn severity(&self) -> Label {
+        self.report().label()
+    }
 }
 
 pub trait Exec: Debug + StructOpt {
@@ -186,6 +786,8 @@ pub trait Exec: Debug + StructOpt {
 
     fn global_flags(&self) -> GlobalFlags;
 
+    fn package(&self) -> &Package;
+
     fn exec(self, wrapper: &TextWrapper) -> Result<(), Self::Report>;
 }
 
@@ -199,21 +801,6 @@ fn get_args(name: &str) -> Vec<String> {
     args
 }
 
-fn init_logging(noise_level: opts::NoiseLevel) {
-    use env_logger::{Builder, Env};
-    let default_level = match noise_level {
-        opts::NoiseLevel::Polite => "warn",
-        opts::NoiseLevel::LoudAndProud => {
-            "cargo_mobile=info,cargo_android=info,cargo_apple=info,bossy=info,hit=info"
-        }
-        opts::NoiseLevel::FranklyQuitePedantic => {
-            "info,cargo_mobile=debug,cargo_android=debug,cargo_apple=debug,bossy=debug,hit=debug"
-        }
-    };
-    let env = Env::default().default_filter_or(default_level);
-    Builder::from_env(env).init();
-}
-
 #[derive(Debug)]
 pub enum Exit {
     Report(Report),
@@ -223,12 +810,17 @@ pub enum Exit {
 impl Exit {
     fn report(reportable: impl Reportable) -> Self {
         log::info!("exiting with {:#?}", reportable);
-        Self::Report(reportable.report())
+        let report = reportable.report().with_code(reportable.code());
+        Self::Report(match proxy::hint(report.details()) {
+            Some(hint) => report.with_note(hint),
+            None => report,
+        })
     }
 
     fn do_the_thing(self, wrapper: TextWrapper) -> ! {
         match self {
             Self::Report(report) => {
+                progress::done(Some(&report));
                 report.print(&wrapper);
                 std::process::exit(report.label.exit_code().into())
             }
@@ -236,9 +828,10 @@ impl Exit {
         }
     }
 
-    pub fn main(inner: impl FnOnce(&TextWrapper) -> Result<(), Self>) {
-        let wrapper = TextWrapper::with_splitter(textwrap::termwidth(), textwrap::NoHyphenation);
-        if let Err(exit) = inner(&wrapper) {
+    /// `inner` can replace the wrapper, once it knows what width to use.
+    pub fn main(inner: impl FnOnce(&mut TextWrapper) -> Result<(), Self>) {
+        let mut wrapper = wrapper(None);
+        if let Err(exit) = inner(&mut wrapper) {
             exit.do_the_thing(wrapper)
         }
     }
@@ -248,8 +841,156 @@ pub fn exec<E: Exec>(name: &str) {
     Exit::main(|wrapper| {
         let args = get_args(name);
         let input = E::from_iter_safe(&args).map_err(Exit::Clap)?;
-        init_logging(input.global_flags().noise_level);
+        let flags = input.global_flags();
+        if flags.width.is_some() {
+            *wrapper = self::wrapper(flags.width);
+        }
+        if flags.width.is_some() || flags.quiet.yes() {
+            ui::set(ui::Terminal::new(wrapper.clone()).with_quiet(flags.quiet));
+        }
+        progress::init(flags.format.unwrap_or_default(), flags.progress_fd)
+            .map_err(Exit::report)?;
+        logging::init(flags.noise_level, flags.quiet);
+        watchdog::init(flags.no_watchdog, flags.non_interactive);
+        proxy::init();
+        let Package {
+            manifest_path,
+            package,
+        } = input.package();
+        workspace::enter(manifest_path.as_deref(), package.as_deref()).map_err(Exit::report)?;
+        util::interrupt::install();
         log::debug!("raw args: {:#?}", args);
-        input.exec(wrapper).map_err(Exit::report)
+        // Dropped once the command's done, whether or not it succeeded.
+        let _vms = vms::ephemeral(flags.ephemeral);
+        input.exec(wrapper).map_err(Exit::report)?;
+        progress::done(None);
+        Ok(())
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        line,
+        expected,
+        case("run `cargo mobile init` again", &["run", "`cargo mobile init`", "again"]),
+        case("see `a b`, then `c d`.", &["see", "`a b`,", "then", "`c d`."]),
+        case("  indented `unclosed span", &["  indented", "`unclosed", "span"])
+    )]
+    fn test_words(line: &str, expected: &[&str]) {
+        assert_eq!(words(line), expected);
+    }
+
+    #[derive(Debug, StructOpt)]
+    enum Input {
+        Build,
+    }
+
+    #[rstest(
+        shell,
+        expected,
+        case(
+            clap::Shell::Bash,
+            "complete -F __cargo_test -o bashdefault -o default cargo"
+        ),
+        case(clap::Shell::Zsh, "compdef __cargo_test cargo"),
+        case(
+            clap::Shell::Fish,
+            "complete -c cargo -n \"__fish_use_subcommand\" -f -a \"test\""
+        )
+    )]
+    fn test_completions_are_for_cargo_subcommand(shell: clap::Shell, expected: &str) {
+        let script = completions::<Input>("test", shell, false);
+        assert!(script.contains(expected), "{}", script);
+        assert!(script.contains("cargo-test"), "{}", script);
+    }
+
+    #[test]
+    fn test_zsh_completions_can_be_sourced() {
+        let script = completions::<Input>("test", clap::Shell::Zsh, false);
+        assert!(!script.contains("_cargo-test \"$@\""), "{}", script);
+    }
+
+    #[rstest(
+        shell,
+        expected,
+        case(clap::Shell::Bash, "complete -F __cargo_test_devices"),
+        case(clap::Shell::Zsh, "compdef __cargo_test_devices cargo-test"),
+        case(clap::Shell::Fish, "complete -c cargo-test -l device")
+    )]
+    fn test_completions_complete_devices(shell: clap::Shell, expected: &str) {
+        let script = completions::<Input>("test", shell, true);
+        assert!(script.contains(expected), "{}", script);
+        assert!(script.contains("cargo-test devices --names"), "{}", script);
+        let script = completions::<Input>("test", shell, false);
+        assert!(!script.contains("devices --names"), "{}", script);
+    }
+
+    #[rstest(
+        width,
+        expected,
+        case(
+            60,
+            concat!(
+                "error: Failed to install the APK on device \"Pixel 7\" (serial\n",
+                "       1A2B3C4D5E) [android.device.apk-install-failed]\n",
+                "    `adb install` exited with status 1 after copying\n",
+                "    \"/home/me/my-cool-app/gen/android/app/build/outputs/apk/universal/debug/app-universal-debug.apk\".\n",
+                "    Make sure USB debugging is enabled, then run\n",
+                "    `cargo android run --device 1A2B3C4D5E` again.\n",
+                "\n",
+                "    adb: failed to install:\n",
+                "    INSTALL_FAILED_INSUFFICIENT_STORAGE\n",
+            )
+        ),
+        case(
+            80,
+            concat!(
+                "error: Failed to install the APK on device \"Pixel 7\" (serial 1A2B3C4D5E)\n",
+                "       [android.device.apk-install-failed]\n",
+                "    `adb install` exited with status 1 after copying\n",
+                "    \"/home/me/my-cool-app/gen/android/app/build/outputs/apk/universal/debug/app-universal-debug.apk\".\n",
+                "    Make sure USB debugging is enabled, then run\n",
+                "    `cargo android run --device 1A2B3C4D5E` again.\n",
+                "\n",
+                "    adb: failed to install: INSTALL_FAILED_INSUFFICIENT_STORAGE\n",
+            )
+        ),
+        case(
+            120,
+            concat!(
+                "error: Failed to install the APK on device \"Pixel 7\" (serial 1A2B3C4D5E) [android.device.apk-install-failed]\n",
+                "    `adb install` exited with status 1 after copying\n",
+                "    \"/home/me/my-cool-app/gen/android/app/build/outputs/apk/universal/debug/app-universal-debug.apk\". Make sure USB\n",
+                "    debugging is enabled, then run `cargo android run --device 1A2B3C4D5E` again.\n",
+                "\n",
+                "    adb: failed to install: INSTALL_FAILED_INSUFFICIENT_STORAGE\n",
+            )
+        )
+    )]
+    fn test_render(width: usize, expected: &str) {
+        let report = Report::error(
+            "Failed to install the APK on device \"Pixel 7\" (serial 1A2B3C4D5E)",
+            "`adb install` exited with status 1 after copying \"/home/me/my-cool-app/gen/android/app/build/outputs/apk/universal/debug/app-universal-debug.apk\". Make sure USB debugging is enabled, then run `cargo android run --device 1A2B3C4D5E` again.\n\nadb: failed to install: INSTALL_FAILED_INSUFFICIENT_STORAGE",
+        )
+        .with_code("android.device.apk-install-failed");
+        assert_eq!(report.render(&wrapper(Some(width)), false), expected);
+    }
+
+    #[rstest(
+        args,
+        case(&[]),
+        case(&["-vv", "--non-interactive", "--format=json", "--width=80"]),
+        case(&["--quiet", "--ephemeral", "--jobs=4", "--no-watchdog"])
+    )]
+    fn test_global_flags_args(args: &[&str]) {
+        let flags = GlobalFlags::from_iter_safe(
+            std::iter::once("cargo-mobile").chain(args.iter().copied()),
+        )
+        .unwrap();
+        assert_eq!(flags.args(), args);
+    }
+}
@@ -21,6 +21,7 @@ impl<'a> Git<'a> {
         bossy::Command::impure("git")
             .with_arg("-C")
             .with_arg(self.root)
+            .with_args(super::proxy::get().git_args())
     }
 
     pub fn command_parse(&self, arg_str: impl AsRef<str>) -> bossy::Command {
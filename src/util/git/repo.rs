@@ -1,4 +1,7 @@
-use crate::util::{self, Git};
+use crate::{
+    ui,
+    util::{self, proxy, Git},
+};
 use std::{
     ffi::OsStr,
     fmt::{self, Display},
@@ -80,8 +83,9 @@ impl Repo {
             Status::Stale
         } else {
             let git = self.git();
-            git.command_parse("fetch origin")
-                .run_and_wait()
+            // git only shows progress on a terminal, and `proxy::run_and_wait`
+            // pipes its stderr.
+            proxy::run_and_wait(git.command_parse("fetch --progress origin"))
                 .map_err(Error::FetchFailed)?;
             let local = git
                 .command_parse("rev-parse HEAD")
@@ -129,14 +133,15 @@ impl Repo {
                     }
                 })?;
             }
-            Git::new(parent)
-                .command_parse("clone --depth 1 --single-branch")
-                .with_arg(url)
-                .with_arg(path)
-                .run_and_wait()
-                .map_err(Error::CloneFailed)?;
+            proxy::run_and_wait(
+                Git::new(parent)
+                    .command_parse("clone --progress --depth 1 --single-branch")
+                    .with_arg(url)
+                    .with_arg(path),
+            )
+            .map_err(Error::CloneFailed)?;
         } else {
-            println!(
+            ui::status(format!(
                 "Updating `{}` repo...",
                 Path::new(
                     self.path()
@@ -144,10 +149,8 @@ impl Repo {
                         .expect("developer error: `Repo` path had no file name")
                 )
                 .display()
-            );
-            self.git()
-                .command_parse("fetch --depth 1")
-                .run_and_wait()
+            ));
+            proxy::run_and_wait(self.git().command_parse("fetch --progress --depth 1"))
                 .map_err(Error::FetchFailed)?;
             self.git()
                 .command_parse("reset --hard origin/master")
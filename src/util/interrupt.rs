@@ -0,0 +1,358 @@
+//! Ctrl-C handling. There's only ever one handler, which is installed by
+//! [`install`]; everything else hooks into it through guards, so nothing can
+//! forget to unregister itself.
+//!
+//! When the user hits Ctrl-C, we give our child processes a moment to exit,
+//! run whatever cleanup is registered, and exit with 130 (the conventional
+//! status for dying to `SIGINT`), unless something is holding a [`Survive`]
+//! guard, in which case execution carries on once cleanup has run.
+//!
+//! On Unix, children are found by walking the process tree. Windows has no
+//! `ps` to walk it with, so there we only know about the children that were
+//! registered with [`child`], which every long-running command (cargo,
+//! Gradle, xcodebuild, adb) is, by way of the watchdog.
+
+use once_cell_regex::exports::once_cell::sync::Lazy;
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex, Once,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// What the shell reports for a process killed by `SIGINT`.
+pub const EXIT_CODE: i32 = 130;
+
+// How long children get to exit on their own before we forward the interrupt,
+// and then how long they get to act on it. xcodebuild in particular needs a
+// moment to release its build database lock.
+const SETTLE: Duration = Duration::from_secs(1);
+const GRACE: Duration = Duration::from_secs(3);
+const POLL: Duration = Duration::from_millis(100);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static HANDLING: AtomicBool = AtomicBool::new(false);
+static SURVIVORS: AtomicUsize = AtomicUsize::new(0);
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Registry {
+    next_id: usize,
+    cleanups: Vec<(usize, Callback)>,
+}
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(Default::default);
+
+static CHILDREN: Lazy<Mutex<Vec<u32>>> = Lazy::new(Default::default);
+
+/// Whether the user has hit Ctrl-C, and it's still being dealt with. This is
+/// reset once the last [`Survive`] guard is dropped, since that's what
+/// carrying on after Ctrl-C looks like.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Installs the Ctrl-C handler. This is idempotent, and the guard constructors
+/// call it too, so it's only strictly necessary if you want the handler's
+/// behavior without registering anything.
+pub fn install() {
+    static HANDLER: Once = Once::new();
+    HANDLER.call_once(|| {
+        if let Err(err) = ctrlc::set_handler(handle) {
+            log::warn!("failed to set Ctrl-C handler: {}", err);
+        }
+    });
+}
+
+fn handle() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    // Hitting Ctrl-C again while we're still cleaning up means the user is
+    // out of patience.
+    if HANDLING.swap(true, Ordering::SeqCst) {
+        process::exit(EXIT_CODE);
+    }
+    stop_children();
+    let cleanups = {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+        std::mem::take(&mut registry.cleanups)
+    };
+    // Most recently registered first, like drop order.
+    for (_, cleanup) in cleanups.into_iter().rev() {
+        cleanup();
+    }
+    if SURVIVORS.load(Ordering::SeqCst) == 0 {
+        process::exit(EXIT_CODE);
+    }
+    HANDLING.store(false, Ordering::SeqCst);
+}
+
+fn wait_for_children(timeout: Duration) -> bool {
+    let start = Instant::now();
+    loop {
+        if !children_alive() {
+            return true;
+        }
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(POLL);
+    }
+}
+
+// A Ctrl-C typed into the terminal goes to the whole foreground process group,
+// so usually our children already have it. If it was sent to us alone, we pass
+// it along, since dying without waiting for them is what leaves daemons and
+// locks behind. That goes for grandchildren too (gradle's daemon is one, since
+// we run it through the wrapper script), so we signal the whole tree at once,
+// before any of it can exit and leave its own children orphaned.
+fn stop_children() {
+    if wait_for_children(SETTLE) {
+        return;
+    }
+    log::info!("forwarding interrupt to child processes");
+    let pids = descendants();
+    if pids.is_empty() {
+        return;
+    }
+    if let Err(err) = signal(&pids) {
+        log::warn!("failed to forward interrupt to child processes: {}", err);
+    }
+    if !wait_for_children(GRACE) {
+        log::warn!("child processes didn't exit after being interrupted");
+    }
+}
+
+fn children_alive() -> bool {
+    !descendants().is_empty()
+}
+
+fn registered_children() -> Vec<u32> {
+    CHILDREN
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
+
+#[cfg(unix)]
+fn signal(pids: &[u32]) -> bossy::Result<()> {
+    bossy::Command::impure("kill")
+        .with_arg("-INT")
+        .with_args(pids.iter().map(|pid| pid.to_string()))
+        .run_and_wait()
+        .map(|_| ())
+}
+
+// Console programs can't be sent Ctrl-C one at a time, and they've already
+// gotten the one the user typed, so all that's left is to end them (and
+// whatever they started, i.e. Gradle's daemon).
+#[cfg(not(unix))]
+fn signal(pids: &[u32]) -> bossy::Result<()> {
+    let mut command = bossy::Command::impure("taskkill").with_args(&["/T", "/F"]);
+    for pid in pids {
+        command.add_arg("/PID");
+        command.add_arg(pid.to_string());
+    }
+    command.run_and_wait().map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn descendants() -> Vec<u32> {
+    registered_children()
+        .into_iter()
+        .filter(|pid| {
+            bossy::Command::impure("tasklist")
+                .with_args(&["/FO", "CSV", "/NH", "/FI"])
+                .with_arg(format!("PID eq {}", pid))
+                .run_and_wait_for_str(|output| output.contains(&format!("\"{}\"", pid)))
+                .unwrap_or_else(|err| {
+                    log::warn!("failed to check on child process {}: {}", pid, err);
+                    false
+                })
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn descendants() -> Vec<u32> {
+    bossy::Command::impure("ps")
+        .with_args(&["-A", "-o", "pid=", "-o", "ppid=", "-o", "comm="])
+        .run_and_wait_for_str(|table| descendants_in(process::id(), table))
+        .unwrap_or_else(|err| {
+            log::warn!("failed to list child processes: {}", err);
+            // The ones we know about are better than nothing.
+            registered_children()
+        })
+}
+
+// Walks a `ps -o pid= -o ppid= -o comm=` table breadth-first from `root`, which
+// isn't included. Neither is the `ps` that printed the table, since it's one of
+// our children too.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn descendants_in(root: u32, table: &str) -> Vec<u32> {
+    let parents = table
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pid = fields.next()?.parse::<u32>().ok()?;
+            let ppid = fields.next()?.parse::<u32>().ok()?;
+            let comm = fields.collect::<Vec<_>>().join(" ");
+            if ppid == root && comm.rsplit('/').next() == Some("ps") {
+                None
+            } else {
+                Some((pid, ppid))
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut found = vec![root];
+    let mut next = 0;
+    while next < found.len() {
+        let parent = found[next];
+        found.extend(
+            parents
+                .iter()
+                .filter(|(pid, ppid)| *ppid == parent && !found.contains(pid))
+                .map(|(pid, _)| *pid)
+                .collect::<Vec<_>>(),
+        );
+        next += 1;
+    }
+    found.remove(0);
+    found
+}
+
+/// Runs a closure if the user hits Ctrl-C while the guard is alive. Dropping
+/// the guard unregisters the closure without running it.
+#[must_use = "the cleanup is unregistered as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct Cleanup {
+    id: usize,
+}
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+        registry.cleanups.retain(|(id, _)| *id != self.id);
+    }
+}
+
+pub fn on_interrupt(cleanup: impl FnOnce() + Send + 'static) -> Cleanup {
+    install();
+    let mut registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+    let id = registry.next_id;
+    registry.next_id += 1;
+    registry.cleanups.push((id, Box::new(cleanup)));
+    Cleanup { id }
+}
+
+/// A child process to wait for (and stop, if it doesn't stop on its own) when
+/// the user hits Ctrl-C, for as long as the guard is alive.
+#[must_use = "the child is unregistered as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct Child {
+    pid: u32,
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        let mut children = CHILDREN.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(index) = children.iter().position(|pid| *pid == self.pid) {
+            children.remove(index);
+        }
+    }
+}
+
+pub fn child(pid: u32) -> Child {
+    install();
+    CHILDREN
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(pid);
+    Child { pid }
+}
+
+/// Keeps us alive after Ctrl-C while the guard is alive, for when being
+/// interrupted is how the user says they're done (i.e. log streaming) rather
+/// than a reason to bail.
+#[must_use = "Ctrl-C exits again as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct Survive {
+    _private: (),
+}
+
+impl Drop for Survive {
+    fn drop(&mut self) {
+        // Whatever was interrupted has been dealt with, so the next Ctrl-C is
+        // a new one.
+        if SURVIVORS.fetch_sub(1, Ordering::SeqCst) == 1 {
+            INTERRUPTED.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+pub fn survive() -> Survive {
+    install();
+    SURVIVORS.fetch_add(1, Ordering::SeqCst);
+    Survive { _private: () }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_descendants_include_grandchildren() {
+        let table = "  1     0 init\n 10     1 cargo-mobile\n 11    10 gradlew\n 12    11 java\n 13    10 adb\n 20     1 sh\n 21    20 sleep\n";
+        assert_eq!(descendants_in(10, table), vec![11, 13, 12]);
+    }
+
+    #[test]
+    fn test_descendants_ignore_junk_and_ps() {
+        assert_eq!(
+            descendants_in(
+                10,
+                "PID PPID COMMAND\n 11 10 /usr/bin/adb\n\n 14 10 /bin/ps\n"
+            ),
+            vec![11]
+        );
+    }
+
+    #[test]
+    fn test_dropped_child_is_unregistered() {
+        let child = child(u32::MAX);
+        assert!(registered_children().contains(&u32::MAX));
+        drop(child);
+        assert!(!registered_children().contains(&u32::MAX));
+    }
+
+    #[test]
+    fn test_last_survivor_resets_interrupted() {
+        let outer = survive();
+        let inner = survive();
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        drop(inner);
+        assert!(interrupted());
+        drop(outer);
+        assert!(!interrupted());
+    }
+
+    #[test]
+    fn test_dropped_cleanup_is_unregistered() {
+        let cleanup = on_interrupt(|| {});
+        let id = cleanup.id;
+        let registered = |id| {
+            REGISTRY
+                .lock()
+                .unwrap()
+                .cleanups
+                .iter()
+                .any(|(other, _)| *other == id)
+        };
+        assert!(registered(id));
+        drop(cleanup);
+        assert!(!registered(id));
+    }
+}
@@ -0,0 +1,135 @@
+//! Our log, which goes to stderr so it can't end up in anything printed as a
+//! result, like `--format json` output. When progress events are going to
+//! stderr too, each line is sent as a `log` event instead, so the stream stays
+//! parseable.
+//!
+//! `RUST_LOG` works like it does anywhere else, down to single modules (i.e.
+//! `RUST_LOG=cargo_mobile::android::adb=debug`); `-v` and `-vv` only pick what
+//! to show when it isn't set.
+
+use super::progress;
+use crate::opts;
+use env_logger::{Builder, Env};
+use log::{Level, Log, Metadata, Record};
+use std::{
+    io::Write as _,
+    time::{Duration, Instant},
+};
+
+fn default_filter(noise_level: opts::NoiseLevel, quiet: opts::Quiet) -> &'static str {
+    if quiet.yes() {
+        return "error";
+    }
+    match noise_level {
+        opts::NoiseLevel::Polite => "warn",
+        opts::NoiseLevel::LoudAndProud => {
+            "cargo_mobile=info,cargo_android=info,cargo_apple=info,bossy=info,hit=info"
+        }
+        opts::NoiseLevel::FranklyQuitePedantic => {
+            "info,cargo_mobile=debug,cargo_android=debug,cargo_apple=debug,bossy=debug,hit=debug"
+        }
+    }
+}
+
+fn level_id(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+// The time is since we started, which says more about what's slow than the
+// time of day would.
+fn prefix(elapsed: Duration, module: &str) -> String {
+    format!("[{:>8.3}s {}]", elapsed.as_secs_f64(), module)
+}
+
+struct Logger {
+    inner: env_logger::Logger,
+    start: Instant,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        if progress::on_stderr() {
+            progress::log(
+                level_id(record.level()),
+                record.target(),
+                self.start.elapsed(),
+                &record.args().to_string(),
+            );
+        } else {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Starts logging, which has to come after progress events are set up.
+pub fn init(noise_level: opts::NoiseLevel, quiet: opts::Quiet) {
+    let start = Instant::now();
+    let env = Env::default().default_filter_or(default_filter(noise_level, quiet));
+    let inner = Builder::from_env(env)
+        .format(move |buf, record| {
+            let level = buf.default_styled_level(record.level());
+            writeln!(
+                buf,
+                "{} {:<5} {}",
+                prefix(start.elapsed(), record.target()),
+                level,
+                record.args()
+            )
+        })
+        .build();
+    log::set_max_level(inner.filter());
+    if let Err(err) = log::set_boxed_logger(Box::new(Logger { inner, start })) {
+        eprintln!("failed to start logging: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        noise_level,
+        quiet,
+        expected,
+        case(opts::NoiseLevel::Polite, opts::Quiet::No, "warn"),
+        case(opts::NoiseLevel::Polite, opts::Quiet::Yes, "error"),
+        case(
+            opts::NoiseLevel::LoudAndProud,
+            opts::Quiet::No,
+            "cargo_mobile=info,cargo_android=info,cargo_apple=info,bossy=info,hit=info"
+        )
+    )]
+    fn test_default_filter(noise_level: opts::NoiseLevel, quiet: opts::Quiet, expected: &str) {
+        assert_eq!(default_filter(noise_level, quiet), expected);
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(
+            prefix(Duration::from_millis(1250), "cargo_mobile::android::adb"),
+            "[   1.250s cargo_mobile::android::adb]"
+        );
+        assert_eq!(
+            prefix(Duration::from_secs(12345), "bossy"),
+            "[12345.000s bossy]"
+        );
+    }
+}
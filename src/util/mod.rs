@@ -1,9 +1,17 @@
 mod cargo;
 pub mod cli;
 mod git;
+pub mod interrupt;
 pub mod ln;
+mod logging;
+pub mod net;
 mod path;
+pub mod passthrough;
+pub mod progress;
 pub mod prompt;
+pub mod proxy;
+pub mod timings;
+pub mod watchdog;
 
 pub use self::{cargo::*, git::*, path::*};
 
@@ -37,14 +45,118 @@ pub fn list_display(list: &[impl Display]) -> String {
     }
 }
 
+/// Prints `rows` under `header`, with each column padded to its widest cell.
+pub fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let widths = (0..header.len())
+        .map(|column| {
+            std::iter::once(header[column].len())
+                .chain(rows.iter().map(|row| row[column].len()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    let header = header
+        .iter()
+        .map(|cell| cell.to_string())
+        .collect::<Vec<_>>();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+pub fn display_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+// Plain Levenshtein distance, which is plenty for catching typos in the
+// handful of names we ever compare against.
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if a == *b {
+                prev
+            } else {
+                1 + prev.min(row[j]).min(current)
+            };
+            prev = current;
+        }
+    }
+    row[b.len()]
+}
+
+/// The closest of `available` to `name` ignoring case, if any are close enough
+/// that `name` is probably a typo of it.
+pub fn suggest<'a>(name: &str, available: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let lower = name.to_lowercase();
+    available
+        .into_iter()
+        .map(|candidate| (distance(&lower, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= 2.max(name.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
 pub fn reverse_domain(domain: &str) -> String {
     domain.split('.').rev().collect::<Vec<_>>().join(".")
 }
 
-pub fn rustup_add(triple: &str) -> bossy::Result<bossy::ExitStatus> {
+pub fn rustup_add(triple: &str) -> bossy::Result<()> {
+    proxy::run_and_wait(bossy::Command::impure("rustup").with_args(&["target", "add", triple]))
+}
+
+pub fn installed_targets() -> bossy::Result<Vec<String>> {
+    bossy::Command::impure("rustup")
+        .with_args(&["target", "list", "--installed"])
+        .run_and_wait_for_str(|raw_list| {
+            raw_list
+                .lines()
+                .map(|line| line.trim().to_owned())
+                .filter(|line| !line.is_empty())
+                .collect()
+        })
+}
+
+/// The components of the toolchain cargo would use here, i.e. `rust-src`.
+/// Components that only exist for one target have it as a suffix.
+pub fn installed_components() -> bossy::Result<Vec<String>> {
     bossy::Command::impure("rustup")
-        .with_args(&["target", "add", triple])
-        .run_and_wait()
+        .with_args(&["component", "list", "--installed"])
+        .run_and_wait_for_str(|raw_list| {
+            raw_list
+                .lines()
+                .map(|line| line.trim().to_owned())
+                .filter(|line| !line.is_empty())
+                .collect()
+        })
+}
+
+/// Whether the toolchain cargo would use here can take `-Z` flags.
+pub fn toolchain_is_nightly() -> bossy::Result<bool> {
+    bossy::Command::impure("rustc")
+        .with_arg("--version")
+        .run_and_wait_for_str(|version| version.contains("-nightly") || version.contains("-dev"))
 }
 
 #[derive(Debug)]
@@ -55,23 +167,72 @@ pub enum HostTargetTripleError {
 impl Reportable for HostTargetTripleError {
     fn report(&self) -> Report {
         match self {
-            Self::CommandFailed(err) => Report::error("Failed to detect host target triple", err),
+            Self::CommandFailed(err) => err.report("Failed to detect host target triple"),
         }
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::CommandFailed(RunAndSearchError::LaunchFailed { .. }) => {
+                "util.host-target-triple.launch-failed"
+            }
+            Self::CommandFailed(RunAndSearchError::CommandFailed { .. }) => {
+                "util.host-target-triple.command-failed"
+            }
+            Self::CommandFailed(RunAndSearchError::SearchFailed { .. }) => {
+                "util.host-target-triple.search-failed"
+            }
+        }
+    }
+}
+
+/// Removes the escape codes used for colors and styles, which some tools
+/// output even when they're not writing to a terminal.
+pub fn strip_ansi(text: &str) -> std::borrow::Cow<'_, str> {
+    regex!(r"\x1b\[[0-9;]*m").replace_all(text, "")
+}
+
+// Wrappers like `sccache` and distro shims can add banners and warnings around
+// rustc's own output, so the `host:` line could be anywhere.
+fn parse_host_target_triple(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let line = strip_ansi(line);
+        line.trim()
+            .strip_prefix("host:")
+            .map(str::trim)
+            .filter(|triple| {
+                triple.contains('-')
+                    && triple
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+            })
+            .map(str::to_owned)
+    })
 }
 
 pub fn host_target_triple() -> Result<String, HostTargetTripleError> {
     // TODO: add fast paths
-    run_and_search(
+    match run_and_parse(
         &mut bossy::Command::impure_parse("rustc --verbose --version"),
-        regex!(r"host: ([\w-]+)"),
-        |_text, caps| {
-            let triple = caps[1].to_owned();
+        parse_host_target_triple,
+    ) {
+        Ok(triple) => {
             log::info!("detected host target triple {:?}", triple);
-            triple
-        },
-    )
-    .map_err(HostTargetTripleError::CommandFailed)
+            Ok(triple)
+        }
+        // Guessing wrong is much less of a problem than refusing to run, and
+        // the triple we were built for is almost certainly right.
+        Err(RunAndSearchError::SearchFailed { output, .. }) => {
+            let triple = env!("CARGO_MOBILE_BUILD_TARGET");
+            log::warn!(
+                "couldn't find the host target triple in `rustc --verbose --version` output, so falling back to {:?}, which is what cargo-mobile was built for: {:?}",
+                triple,
+                output
+            );
+            Ok(triple.to_owned())
+        }
+        Err(err) => Err(HostTargetTripleError::CommandFailed(err)),
+    }
 }
 
 #[derive(Debug, Error)]
@@ -114,6 +275,18 @@ impl Reportable for RustVersionError {
     fn report(&self) -> Report {
         Report::error("Failed to check Rust version", self)
     }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::CommandFailed(..) => "util.rust-version.command-failed",
+            Self::MajorInvalid { .. } => "util.rust-version.major-invalid",
+            Self::MinorInvalid { .. } => "util.rust-version.minor-invalid",
+            Self::PatchInvalid { .. } => "util.rust-version.patch-invalid",
+            Self::YearInvalid { .. } => "util.rust-version.year-invalid",
+            Self::MonthInvalid { .. } => "util.rust-version.month-invalid",
+            Self::DayInvalid { .. } => "util.rust-version.day-invalid",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -189,7 +362,7 @@ impl RustVersion {
             },
         )?
         */
-        Ok(Self{
+        Ok(Self {
             triple: (1, 49, 0),
             flavor: None,
             hash: "fffffffff".to_string(),
@@ -235,6 +408,21 @@ pub enum PipeError {
     WaitFailed(bossy::Error),
 }
 
+impl Reportable for PipeError {
+    fn report(&self) -> Report {
+        Report::error("Failed to pipe command output", self)
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::TxCommandFailed(_) => "util.pipe.tx-command-failed",
+            Self::RxCommandFailed(_) => "util.pipe.rx-command-failed",
+            Self::PipeFailed(_) => "util.pipe.pipe-failed",
+            Self::WaitFailed(_) => "util.pipe.wait-failed",
+        }
+    }
+}
+
 impl Display for PipeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -248,7 +436,28 @@ impl Display for PipeError {
     }
 }
 
-pub fn pipe(mut tx_command: bossy::Command, rx_command: bossy::Command) -> Result<bool, PipeError> {
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PipeOutcome {
+    /// The sending command didn't output anything, so the receiving command
+    /// was never run.
+    Empty,
+    Piped,
+    /// The receiving command exited before reading everything, like a pager
+    /// that was quit early. That's usually what the user wanted, so it's not
+    /// an error.
+    ReceiverExitedEarly,
+}
+
+impl PipeOutcome {
+    pub fn piped(self) -> bool {
+        !matches!(self, Self::Empty)
+    }
+}
+
+pub fn pipe(
+    mut tx_command: bossy::Command,
+    rx_command: bossy::Command,
+) -> Result<PipeOutcome, PipeError> {
     let tx_output = tx_command
         .run_and_wait_for_output()
         .map_err(PipeError::TxCommandFailed)?;
@@ -258,46 +467,123 @@ pub fn pipe(mut tx_command: bossy::Command, rx_command: bossy::Command) -> Resul
             .with_stdout(bossy::Stdio::inherit())
             .run()
             .map_err(PipeError::RxCommandFailed)?;
-        let pipe_result = rx_command
+        let pipe_result = match rx_command
             .stdin()
             .expect("developer error: `rx_command` stdin not captured")
             .write_all(tx_output.stdout())
-            .map_err(PipeError::PipeFailed);
+        {
+            Ok(()) => Ok(PipeOutcome::Piped),
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                log::info!("receiving command exited before reading all of its input");
+                Ok(PipeOutcome::ReceiverExitedEarly)
+            }
+            Err(err) => Err(PipeError::PipeFailed(err)),
+        };
+        // We always wait, so that the receiving command doesn't stick around
+        // as a zombie.
         let wait_result = rx_command.wait_for_output().map_err(PipeError::WaitFailed);
-        // We try to wait even if the pipe failed, but the pipe error has higher
-        // priority than the wait error, since it's likely to be more relevant.
-        pipe_result?;
+        // The pipe error has higher priority than the wait error, since it's
+        // likely to be more relevant.
+        let outcome = pipe_result?;
         wait_result?;
-        Ok(true)
+        Ok(outcome)
     } else {
-        Ok(false)
+        Ok(PipeOutcome::Empty)
+    }
+}
+
+fn display_status(status: Option<i32>) -> String {
+    status
+        .map(|code| format!("status {}", code))
+        .unwrap_or_else(|| "no status (it was probably killed)".to_owned())
+}
+
+// What a failed command printed to stderr is usually the useful part, but
+// some tools put their errors on stdout instead.
+fn display_output(stdout: &str, stderr: &str) -> String {
+    match (stdout.trim(), stderr.trim()) {
+        (_, stderr) if !stderr.is_empty() => stderr.to_owned(),
+        (stdout, _) if !stdout.is_empty() => stdout.to_owned(),
+        _ => "(no output)".to_owned(),
     }
 }
 
 #[derive(Debug, Error)]
 pub enum RunAndSearchError {
-    #[error(transparent)]
-    CommandFailed(#[from] bossy::Error),
-    #[error("{command:?} output failed to match regex: {output:?}")]
+    #[error("Failed to run {command:?}: {source}")]
+    LaunchFailed {
+        command: String,
+        source: bossy::Error,
+    },
+    #[error("{command:?} exited with {}: {}", display_status(*.status), display_output(.stdout, .stderr))]
+    CommandFailed {
+        command: String,
+        status: Option<i32>,
+        stdout: String,
+        stderr: String,
+    },
+    #[error("{command:?} succeeded, but its output wasn't what we expected: {output:?}")]
     SearchFailed { command: String, output: String },
 }
 
+impl RunAndSearchError {
+    pub fn report(&self, msg: &str) -> Report {
+        match self {
+            Self::LaunchFailed { .. } => Report::action_request(
+                msg,
+                format!("{}. Make sure it's installed and on your `PATH`.", self),
+            ),
+            Self::CommandFailed { .. } => Report::action_request(msg, self),
+            Self::SearchFailed { .. } => Report::error(
+                msg,
+                format!(
+                    "{}. This is probably a bug in cargo-mobile, so please report it, along with that output!",
+                    self
+                ),
+            ),
+        }
+    }
+}
+
+/// Runs `command`, and passes its stdout to `parse`, which returns `None` if
+/// the output wasn't what it expected. A command that exits with a non-zero
+/// status is always a `CommandFailed` error, even if its output would've
+/// parsed, since that output can't be trusted.
+pub fn run_and_parse<T>(
+    command: &mut bossy::Command,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<T, RunAndSearchError> {
+    let command_string = command.display().to_owned();
+    let output = command
+        .run_and_wait_for_output()
+        .map_err(|source| match source.output() {
+            Some(output) => RunAndSearchError::CommandFailed {
+                command: command_string.clone(),
+                status: source.code(),
+                stdout: String::from_utf8_lossy(output.stdout()).into_owned(),
+                stderr: String::from_utf8_lossy(output.stderr()).into_owned(),
+            },
+            None => RunAndSearchError::LaunchFailed {
+                command: command_string.clone(),
+                source,
+            },
+        })?;
+    let stdout = String::from_utf8_lossy(output.stdout());
+    parse(&stdout).ok_or_else(|| RunAndSearchError::SearchFailed {
+        command: command_string,
+        output: stdout.into_owned(),
+    })
+}
+
+/// Runs `command`, and passes its stdout to `f` if `re` matches it.
 pub fn run_and_search<T>(
     command: &mut bossy::Command,
     re: &Regex,
     f: impl FnOnce(&str, Captures<'_>) -> T,
 ) -> Result<T, RunAndSearchError> {
-    let command_string = command.display().to_owned();
-    Ok(command
-        .run_and_wait_for_str(|output| {
-            re.captures(output)
-                .ok_or_else(|| RunAndSearchError::SearchFailed {
-                    command: command_string,
-                    output: output.to_owned(),
-                })
-                .map(|caps| f(output, caps))
-        })
-        .map_err(RunAndSearchError::from)??)
+    run_and_parse(command, |output| {
+        re.captures(output).map(|caps| f(output, caps))
+    })
 }
 
 #[derive(Debug)]
@@ -341,3 +627,121 @@ pub fn installed_commit_msg() -> Result<Option<String>, InstalledCommitMsgError>
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[cfg(unix)]
+    fn sh(script: &str) -> bossy::Command {
+        bossy::Command::impure("sh").with_args(&["-c", script])
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_and_search_matched() {
+        let triple = run_and_search(
+            &mut sh("echo 'host: x86_64-unknown-linux-gnu'"),
+            regex!(r"host: ([\w-]+)"),
+            |_text, caps| caps[1].to_owned(),
+        )
+        .unwrap();
+        assert_eq!(triple, "x86_64-unknown-linux-gnu");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_and_search_command_failed() {
+        // The output matches, but the status says not to trust it.
+        let err = run_and_search(
+            &mut sh("echo 'host: x86_64-unknown-linux-gnu'; echo 'error: toolchain is broken' >&2; exit 3"),
+            regex!(r"host: ([\w-]+)"),
+            |_text, _caps| (),
+        )
+        .unwrap_err();
+        match &err {
+            RunAndSearchError::CommandFailed { status, stderr, .. } => {
+                assert_eq!(*status, Some(3));
+                assert_eq!(stderr.trim(), "error: toolchain is broken");
+            }
+            _ => panic!("expected `CommandFailed`, got {:?}", err),
+        }
+        assert!(err
+            .to_string()
+            .ends_with("exited with status 3: error: toolchain is broken"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_and_search_search_failed() {
+        let err = run_and_search(
+            &mut sh("echo 'something else entirely'"),
+            regex!(r"host: ([\w-]+)"),
+            |_text, _caps| (),
+        )
+        .unwrap_err();
+        match &err {
+            RunAndSearchError::SearchFailed { output, .. } => {
+                assert_eq!(output, "something else entirely\n")
+            }
+            _ => panic!("expected `SearchFailed`, got {:?}", err),
+        }
+    }
+
+    #[rstest(
+        tx,
+        rx,
+        expected,
+        case("printf ''", "cat >/dev/null", PipeOutcome::Empty),
+        case("printf 'hello'", "cat >/dev/null", PipeOutcome::Piped),
+        // This is more than a pipe's buffer holds, so it can't all be written
+        // before `head` exits.
+        case(
+            "head -c 1048576 /dev/zero",
+            "head -c 1 >/dev/null",
+            PipeOutcome::ReceiverExitedEarly
+        )
+    )]
+    #[cfg(unix)]
+    fn test_pipe(tx: &str, rx: &str, expected: PipeOutcome) {
+        assert_eq!(pipe(sh(tx), sh(rx)).unwrap(), expected);
+    }
+
+    #[rstest(
+        output,
+        expected,
+        case(
+            "rustc 1.49.0 (e1884a8e3 2020-12-29)\nbinary: rustc\ncommit-hash: e1884a8e3c3e813aada8254edfa120e85bf5ffca\ncommit-date: 2020-12-29\nhost: x86_64-unknown-linux-gnu\nrelease: 1.49.0\n",
+            Some("x86_64-unknown-linux-gnu")
+        ),
+        case(
+            "sccache: warning: The server looks like it shut down unexpectedly, compiling locally instead\nrustc 1.49.0 (e1884a8e3 2020-12-29)\nbinary: rustc\nhost: aarch64-apple-darwin\nrelease: 1.49.0\n",
+            Some("aarch64-apple-darwin")
+        ),
+        case(
+            "info: syncing channel updates for 'stable-x86_64-apple-darwin'\nwarning: the `rustup` wrapper for `host:` isn't configured\nrustc 1.49.0 (e1884a8e3 2020-12-29)\nhost: x86_64-apple-darwin\n",
+            Some("x86_64-apple-darwin")
+        ),
+        case(
+            "rustc 1.49.0 (e1884a8e3 2020-12-29)\r\nbinary: rustc\r\nhost: x86_64-pc-windows-msvc\r\nrelease: 1.49.0\r\n",
+            Some("x86_64-pc-windows-msvc")
+        ),
+        case(
+            "\x1b[1mrustc 1.49.0\x1b[0m\n\x1b[1mhost\x1b[0m: \x1b[32mx86_64-unknown-linux-gnu\x1b[0m\n",
+            Some("x86_64-unknown-linux-gnu")
+        ),
+        case("rustc 1.49.0 (e1884a8e3 2020-12-29)\nhost:\nrelease: 1.49.0\n", None),
+        case("error: toolchain 'stable' is not installed\n", None)
+    )]
+    fn test_parse_host_target_triple(output: &str, expected: Option<&str>) {
+        assert_eq!(parse_host_target_triple(output).as_deref(), expected);
+    }
+
+    #[test]
+    fn test_display_output() {
+        assert_eq!(display_output("out\n", "err\n"), "err");
+        assert_eq!(display_output("out\n", " \n"), "out");
+        assert_eq!(display_output("", ""), "(no output)");
+    }
+}
@@ -0,0 +1,219 @@
+//! Picking the host port a dev server gets forwarded to. A forward to a port
+//! that something else is already listening on doesn't fail; it just reaches
+//! that instead, so the app shows whatever the other server is serving. We
+//! check first, and say what's in the way.
+
+use crate::{
+    os::{self, PortOwner},
+    ui,
+    util::cli::{Report, Reportable},
+};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The env var the picked port is exported to the app's build as, so it can
+/// use `option_env!` to find its dev server.
+pub static PORT_VAR: &str = "CARGO_MOBILE_DEV_PORT";
+
+/// How long to wait for a connection to come through a forward.
+const REACH_TIMEOUT: Duration = Duration::from_secs(2);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Port {
+    Fixed(u16),
+    Auto,
+}
+
+impl FromStr for Port {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(Self::Auto);
+        }
+        match s.parse() {
+            Ok(0) | Err(_) => Err(format!(
+                "{:?} isn't a port; expected a number from 1 to 65535, or `auto`",
+                s
+            )),
+            Ok(port) => Ok(Self::Fixed(port)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    PortInUse { port: u16, owner: Option<PortOwner> },
+    FreePortFailed(io::Error),
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::PortInUse { port, owner } => {
+                let who = match owner {
+                    Some(owner) => format!("{} is listening on it", owner),
+                    None => format!(
+                        "Something's already listening on it (`lsof -i :{}` might say what)",
+                        port
+                    ),
+                };
+                Report::action_request(
+                    format!("Port {} is already in use", port),
+                    format!(
+                        "{}, so the forward would reach that instead of your dev server. Stop it, or pass `--port auto` to use a free port.",
+                        who
+                    ),
+                )
+            }
+            Self::FreePortFailed(err) => Report::error("Failed to find a free port", err),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::PortInUse { .. } => "net.port-in-use",
+            Self::FreePortFailed(..) => "net.free-port-failed",
+        }
+    }
+}
+
+fn bind(port: u16) -> io::Result<TcpListener> {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+}
+
+// A server could be listening on any of these, and which of them conflict
+// with each other varies by OS: on macOS, binding the wildcard address works
+// even with something on loopback, and IPv4 and IPv6 are separate anyway.
+const PROBE_ADDRS: [IpAddr; 4] = [
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    IpAddr::V4(Ipv4Addr::LOCALHOST),
+    IpAddr::V6(Ipv6Addr::LOCALHOST),
+];
+
+/// Whether something's listening on `port`, which we find out by trying to
+/// listen on it ourselves on each address it could be using. Addresses we
+/// can't use at all, like IPv6 on a machine without it, don't count.
+pub fn in_use(port: u16) -> bool {
+    PROBE_ADDRS.iter().any(|&addr| {
+        matches!(
+            TcpListener::bind((addr, port)),
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse
+        )
+    })
+}
+
+/// A port nothing's listening on, as picked by the OS. It's only free as of
+/// now, but the OS doesn't hand out the same one again right away.
+pub fn free_port() -> io::Result<u16> {
+    Ok(bind(0)?.local_addr()?.port())
+}
+
+/// The port to forward, which for [`Port::Auto`] is a free one, and which
+/// otherwise has to be free already.
+pub fn pick(port: Port) -> Result<u16, Error> {
+    match port {
+        Port::Fixed(port) if in_use(port) => Err(Error::PortInUse {
+            port,
+            owner: os::port_owner(port),
+        }),
+        Port::Fixed(port) => Ok(port),
+        Port::Auto => {
+            let port = free_port().map_err(Error::FreePortFailed)?;
+            ui::status(format!(
+                "Using port {} for the dev server, which the app can get from `{}`",
+                port, PORT_VAR
+            ));
+            Ok(port)
+        }
+    }
+}
+
+/// Listens on `port` while `connect` tries to reach it through a forward, and
+/// returns whether a connection came in. The OS accepts connections into the
+/// backlog on its own, so `connect` can be done and gone by the time we look.
+pub fn reached(port: u16, connect: impl FnOnce()) -> io::Result<bool> {
+    let listener = bind(port)?;
+    listener.set_nonblocking(true)?;
+    connect();
+    let start = Instant::now();
+    loop {
+        match listener.accept() {
+            Ok(_) => break Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if start.elapsed() >= REACH_TIMEOUT {
+                    break Ok(false);
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(err) => break Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+    use std::net::TcpStream;
+
+    #[rstest(
+        s,
+        expected,
+        case("auto", Ok(Port::Auto)),
+        case("8080", Ok(Port::Fixed(8080))),
+        case("0", Err(())),
+        case("65536", Err(())),
+        case("", Err(()))
+    )]
+    fn test_port_from_str(s: &str, expected: Result<Port, ()>) {
+        assert_eq!(s.parse::<Port>().map_err(|_| ()), expected);
+    }
+
+    #[test]
+    fn test_in_use() {
+        let listener = bind(0).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(in_use(port));
+        assert!(matches!(
+            pick(Port::Fixed(port)),
+            Err(Error::PortInUse { port: in_use, .. }) if in_use == port
+        ));
+        drop(listener);
+        assert!(!in_use(port));
+    }
+
+    #[rstest(
+        addr,
+        case(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        case(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        case(IpAddr::V6(Ipv6Addr::LOCALHOST))
+    )]
+    fn test_in_use_other_addrs(addr: IpAddr) {
+        // Not every machine has IPv6, in which case there's nothing to find.
+        let listener = match TcpListener::bind((addr, 0)) {
+            Ok(listener) => listener,
+            Err(_) => return,
+        };
+        let port = listener.local_addr().unwrap().port();
+        assert!(in_use(port));
+    }
+
+    #[test]
+    fn test_reached() {
+        let port = free_port().unwrap();
+        assert!(reached(port, || {
+            TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        })
+        .unwrap());
+        assert!(!reached(port, || ()).unwrap());
+    }
+}
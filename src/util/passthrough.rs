@@ -0,0 +1,72 @@
+//! Running a tool as if the user had run it themselves, once we've filled in
+//! what we already know, like which device to use. That's what `cargo android
+//! adb` and `cargo apple simctl` are for, so the tool gets our stdio (which
+//! interactive shells need), handles Ctrl-C itself, and its exit code is ours.
+
+use super::{
+    cli::{Report, Reportable},
+    interrupt, progress,
+};
+use std::process;
+
+#[derive(Debug)]
+pub enum Error {
+    Failed {
+        command: String,
+        cause: bossy::Error,
+    },
+    Exited {
+        command: String,
+        code: i32,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::Failed { command, cause } => {
+                Report::error(format!("Failed to run `{}`", command), cause)
+            }
+            Self::Exited { command, code } => Report::error(
+                format!("`{}` exited with code {}", command, code),
+                "Its output is above.",
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Failed { .. } => "util.passthrough.failed",
+            Self::Exited { .. } => "util.passthrough.exited",
+        }
+    }
+}
+
+// Its error (if it had one) is already on screen, so all that's left is to
+// say how it went to anything listening for progress events.
+fn exit(command: String, code: i32) -> ! {
+    let err = Error::Exited { command, code };
+    progress::done(Some(&err.report().with_code(err.code())));
+    process::exit(code)
+}
+
+/// Runs `command` with our stdio. If it exits with a non-zero code, so do we,
+/// without returning; `Error::Failed` is for when it couldn't be run at all,
+/// or was killed by a signal.
+pub fn run(mut command: bossy::Command) -> Result<(), Error> {
+    let display = command.display().to_owned();
+    log::info!("passing through to `{}`", display);
+    // Ctrl-C is the tool's to handle, i.e. to stop `adb logcat`.
+    let _survive = interrupt::survive();
+    match command.run_and_wait() {
+        Ok(_) => Ok(()),
+        Err(cause) => match cause.code() {
+            Some(code) => exit(display, code),
+            None if interrupt::interrupted() => exit(display, interrupt::EXIT_CODE),
+            None => Err(Error::Failed {
+                command: display,
+                cause,
+            }),
+        },
+    }
+}
@@ -36,6 +36,45 @@ pub fn temp_dir() -> PathBuf {
     std::env::temp_dir().join("com.brainiumstudios.cargo-mobile")
 }
 
+/// A scratch directory that's removed when dropped, so that bailing out
+/// partway through doesn't leave anything lying around.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates `name` in [`temp_dir`], suffixed with our pid so concurrent
+    /// runs don't collide. Anything left there by a previous run that
+    /// happened to have the same pid is cleared out first.
+    pub fn new(name: &str) -> Result<Self, (PathBuf, io::Error)> {
+        Self::new_in(&temp_dir(), name)
+    }
+
+    /// Like [`TempDir::new`], but in `parent`, for when what's in it is going
+    /// to be renamed somewhere on the same volume.
+    pub fn new_in(parent: &Path, name: &str) -> Result<Self, (PathBuf, io::Error)> {
+        let path = parent.join(format!("{}-{}", name, std::process::id()));
+        if path.exists() {
+            let _ = std::fs::remove_dir_all(&path);
+        }
+        match std::fs::create_dir_all(&path) {
+            Ok(()) => Ok(Self(path)),
+            Err(cause) => Err((path, cause)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.0) {
+            log::warn!("failed to remove temp dir {:?}: {}", self.0, err);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct PathNotPrefixed {
     path: PathBuf,
@@ -0,0 +1,402 @@
+//! Progress events for editors and other tools driving us, so that they don't
+//! have to scrape our human-readable output.
+//!
+//! With `--format json`, each phase's start and end is written as a line of
+//! JSON to stderr (or to the file descriptor given with `--progress-fd`), along
+//! with the output of the child processes we capture, and a `done` event once
+//! the command finishes. Phases are also what timings are recorded under, so
+//! the names in the timings summary and the phase IDs can't drift apart.
+
+use super::{
+    cli::{Report, Reportable},
+    interrupt, timings,
+};
+use crate::opts;
+use once_cell_regex::exports::once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, BufRead as _, BufReader, Write as _},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    Config,
+    Project,
+    DevTools,
+    RustupTargets,
+    AppleDeps,
+    AppleProject,
+    AndroidProject,
+    PostInitHooks,
+    Cargo,
+    CopyLibs,
+    Gradle,
+    Xcodebuild,
+    XcodebuildArchive,
+    XcodebuildExport,
+    Install,
+    Launch,
+}
+
+impl Phase {
+    pub const ALL: &'static [Self] = &[
+        Self::Config,
+        Self::Project,
+        Self::DevTools,
+        Self::RustupTargets,
+        Self::AppleDeps,
+        Self::AppleProject,
+        Self::AndroidProject,
+        Self::PostInitHooks,
+        Self::Cargo,
+        Self::CopyLibs,
+        Self::Gradle,
+        Self::Xcodebuild,
+        Self::XcodebuildArchive,
+        Self::XcodebuildExport,
+        Self::Install,
+        Self::Launch,
+    ];
+
+    /// The phase's name in progress events, which tools depend on, so these
+    /// can't ever change.
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::Config => "config",
+            Self::Project => "project",
+            Self::DevTools => "dev-tools",
+            Self::RustupTargets => "rustup-targets",
+            Self::AppleDeps => "apple-deps",
+            Self::AppleProject => "apple-project",
+            Self::AndroidProject => "android-project",
+            Self::PostInitHooks => "post-init-hooks",
+            Self::Cargo => "cargo",
+            Self::CopyLibs => "copy-libs",
+            Self::Gradle => "gradle",
+            Self::Xcodebuild => "xcodebuild",
+            Self::XcodebuildArchive => "xcodebuild-archive",
+            Self::XcodebuildExport => "xcodebuild-export",
+            Self::Install => "install",
+            Self::Launch => "launch",
+        }
+    }
+
+    /// The phase's name in the timings summary, i.e. `copy libs
+    /// aarch64-linux-android`.
+    pub fn timing_name(self, target: Option<&str>) -> String {
+        let name = self.id().replace('-', " ");
+        match target {
+            Some(target) => format!("{} {}", name, target),
+            None => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    FdOpenFailed { fd: u32, cause: io::Error },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::FdOpenFailed { fd, cause } => Report::error(
+                format!("Failed to open file descriptor {} for progress events", fd),
+                cause,
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::FdOpenFailed { .. } => "util.progress.fd-open-failed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Event<'a> {
+    PhaseStart {
+        phase: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target: Option<&'a str>,
+    },
+    PhaseEnd {
+        phase: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target: Option<&'a str>,
+        duration_secs: f64,
+        status: Status,
+    },
+    Output {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target: Option<&'a str>,
+        line: &'a str,
+    },
+    Log {
+        level: &'a str,
+        module: &'a str,
+        elapsed_secs: f64,
+        msg: &'a str,
+    },
+    Done {
+        status: Status,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        code: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        severity: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        msg: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        details: Option<&'a str>,
+    },
+}
+
+#[derive(Debug)]
+enum Sink {
+    Stderr,
+    Fd(File),
+}
+
+static SINK: Lazy<Mutex<Option<Sink>>> = Lazy::new(Default::default);
+
+fn with_sink<T>(f: impl FnOnce(&mut Option<Sink>) -> T) -> T {
+    f(&mut SINK.lock().unwrap_or_else(|err| err.into_inner()))
+}
+
+#[cfg(unix)]
+fn open_fd(fd: u32) -> Result<File, Error> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(format!("/dev/fd/{}", fd))
+        .map_err(|cause| Error::FdOpenFailed { fd, cause })
+}
+
+#[cfg(not(unix))]
+fn open_fd(fd: u32) -> Result<File, Error> {
+    Err(Error::FdOpenFailed {
+        fd,
+        cause: io::Error::new(
+            io::ErrorKind::Other,
+            "`--progress-fd` is only supported on Unix",
+        ),
+    })
+}
+
+/// Starts emitting events if `format` is JSON, or if `progress_fd` was given,
+/// in which case they go there instead of to stderr.
+pub fn init(format: opts::Format, progress_fd: Option<u32>) -> Result<(), Error> {
+    let sink = match progress_fd {
+        Some(fd) => Some(Sink::Fd(open_fd(fd)?)),
+        None if format.json() => Some(Sink::Stderr),
+        None => None,
+    };
+    with_sink(|current| *current = sink);
+    Ok(())
+}
+
+/// Whether events are being emitted at all.
+pub fn enabled() -> bool {
+    with_sink(|sink| sink.is_some())
+}
+
+/// Whether events are going to stderr, which means nothing else can write
+/// there without breaking the stream.
+pub fn on_stderr() -> bool {
+    with_sink(|sink| matches!(sink, Some(Sink::Stderr)))
+}
+
+fn write(event: Event<'_>) -> io::Result<()> {
+    with_sink(|sink| {
+        let sink = match sink {
+            Some(sink) => sink,
+            None => return Ok(()),
+        };
+        // Serializing straight to a string keeps `event` first.
+        let line = serde_json::to_string(&event).expect("developer error: event not serializable");
+        match sink {
+            Sink::Stderr => writeln!(io::stderr(), "{}", line),
+            Sink::Fd(file) => writeln!(file, "{}", line),
+        }
+    })
+}
+
+fn emit(event: Event<'_>) {
+    // If whoever's reading went away, there's no one left to tell. This has
+    // to come after the sink's unlocked, since logging can end up back here.
+    if let Err(err) = write(event) {
+        log::info!("failed to emit progress event: {}", err);
+    }
+}
+
+/// Runs `f` as `phase`, which is timed, and announced if events are enabled.
+/// `target` is which of several runs of the same phase this is, if there can
+/// be more than one.
+pub fn phase<T, E>(
+    phase: Phase,
+    target: Option<&str>,
+    f: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    emit(Event::PhaseStart {
+        phase: phase.id(),
+        target,
+    });
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    timings::record(phase.timing_name(target), elapsed);
+    emit(Event::PhaseEnd {
+        phase: phase.id(),
+        target,
+        duration_secs: elapsed.as_secs_f64(),
+        status: if result.is_ok() {
+            Status::Success
+        } else {
+            Status::Failure
+        },
+    });
+    result
+}
+
+/// Emits a line of a child process's output.
+pub fn output(target: Option<&str>, line: &str) {
+    emit(Event::Output { target, line })
+}
+
+/// Emits a line of our own log, which is how it gets out when events are going
+/// to stderr. A failure isn't logged, since that would just come back here.
+pub fn log(level: &str, module: &str, elapsed: Duration, msg: &str) {
+    let _ = write(Event::Log {
+        level,
+        module,
+        elapsed_secs: elapsed.as_secs_f64(),
+        msg,
+    });
+}
+
+/// Runs `command`, emitting its stderr as output events if events are enabled,
+/// since it'd otherwise end up interleaved with them. stdout is left alone.
+pub fn run_and_wait(command: bossy::Command) -> bossy::Result<()> {
+    if !enabled() {
+        let handle = command.run()?;
+        let _child = interrupt::child(handle.id());
+        return handle.wait_for_output().map(|_| ());
+    }
+    let mut handle = command.with_stderr_piped().run()?;
+    let _child = interrupt::child(handle.id());
+    {
+        let stderr = handle
+            .stderr()
+            .expect("developer error: child stderr not captured");
+        for line in BufReader::new(stderr).lines() {
+            match line {
+                Ok(line) => output(None, &line),
+                Err(err) => {
+                    log::warn!("failed to read child process output: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+    handle.wait_for_output().map(|_| ())
+}
+
+/// Emits the final event, which says whether the command succeeded, and why
+/// not if it didn't.
+pub fn done(report: Option<&Report>) {
+    emit(match report.filter(|report| report.exit_code() != 0) {
+        Some(report) => Event::Done {
+            status: Status::Failure,
+            code: report.code(),
+            severity: Some(report.label().id()),
+            msg: Some(report.msg()),
+            details: Some(report.details()),
+        },
+        None => Event::Done {
+            status: Status::Success,
+            code: None,
+            severity: None,
+            msg: None,
+            details: None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_phase_ids_are_unique_kebab_case() {
+        let mut ids = Phase::ALL
+            .iter()
+            .map(|phase| phase.id())
+            .collect::<Vec<_>>();
+        assert!(ids
+            .iter()
+            .all(|id| id.chars().all(|c| c.is_ascii_lowercase() || c == '-')));
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), Phase::ALL.len());
+        assert_eq!(
+            Phase::CopyLibs.timing_name(Some("aarch64-linux-android")),
+            "copy libs aarch64-linux-android"
+        );
+        assert_eq!(
+            Phase::XcodebuildArchive.timing_name(None),
+            "xcodebuild archive"
+        );
+    }
+
+    #[test]
+    fn test_event_json() {
+        assert_eq!(
+            serde_json::json!(Event::PhaseStart {
+                phase: Phase::RustupTargets.id(),
+                target: None,
+            }),
+            serde_json::json!({"event": "phase-start", "phase": "rustup-targets"})
+        );
+        assert_eq!(
+            serde_json::json!(Event::PhaseEnd {
+                phase: Phase::Cargo.id(),
+                target: Some("aarch64-linux-android"),
+                duration_secs: 1.5,
+                status: Status::Failure,
+            }),
+            serde_json::json!({
+                "event": "phase-end",
+                "phase": "cargo",
+                "target": "aarch64-linux-android",
+                "duration_secs": 1.5,
+                "status": "failure",
+            })
+        );
+        assert_eq!(
+            serde_json::json!(Event::Log {
+                level: "debug",
+                module: "cargo_mobile::android::adb",
+                elapsed_secs: 0.25,
+                msg: "running `adb devices`",
+            }),
+            serde_json::json!({
+                "event": "log",
+                "level": "debug",
+                "module": "cargo_mobile::android::adb",
+                "elapsed_secs": 0.25,
+                "msg": "running `adb devices`",
+            })
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use super::cli::TextWrapper;
 use colored::{Color, Colorize as _};
 use std::{
     fmt::Display,
@@ -38,6 +39,26 @@ pub fn default(
     })
 }
 
+/// Like [`default`], but asks again until `validate` accepts the response,
+/// saying which rule the response broke each time it doesn't.
+pub fn validated<E: Display>(
+    wrapper: &TextWrapper,
+    msg: impl Display,
+    default: Option<&str>,
+    validate: impl Fn(&str) -> Result<(), E>,
+) -> io::Result<String> {
+    loop {
+        let response = self::default(&msg, default, None)?;
+        match validate(&response) {
+            Ok(()) => return Ok(response),
+            Err(err) => println!(
+                "{}",
+                wrapper.fill(&format!("Sorry! {}", err)).bright_magenta()
+            ),
+        }
+    }
+}
+
 yes_or_no!(YesOrNo);
 
 pub fn yes_no(msg: impl Display, default: Option<YesOrNo>) -> io::Result<Option<YesOrNo>> {
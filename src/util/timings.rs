@@ -0,0 +1,249 @@
+//! Rough timings of the slow parts of a build, so that it's clear whether the
+//! time went to cargo, Gradle, Xcode, or the device.
+//!
+//! Phases are only recorded between [`start`] and the end of the session, which
+//! the build and run commands delimit; everything else is free to time itself
+//! (with [`progress::phase`](super::progress::phase)) without any of it going
+//! anywhere.
+
+use crate::opts;
+use once_cell_regex::exports::once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+#[derive(Debug)]
+struct Session {
+    path: PathBuf,
+    show: bool,
+    phases: Vec<(String, Duration)>,
+}
+
+static SESSION: Lazy<Mutex<Option<Session>>> = Lazy::new(Default::default);
+
+fn with_session<T>(f: impl FnOnce(&mut Option<Session>) -> T) -> T {
+    f(&mut SESSION.lock().unwrap_or_else(|err| err.into_inner()))
+}
+
+/// Starts collecting timings. The last run's timings are kept in
+/// `project_dir`, so that each summary can say what changed. They're only
+/// printed with `--timings` or `-v`, which happens when the guard is dropped
+/// unless something summarized them first.
+pub fn start(
+    project_dir: &Path,
+    timings: opts::Timings,
+    noise_level: opts::NoiseLevel,
+) -> PrintOnDrop {
+    with_session(|session| {
+        *session = Some(Session {
+            path: project_dir.join(".timings.json"),
+            show: timings.yes() || !noise_level.polite(),
+            phases: Vec::new(),
+        })
+    });
+    PrintOnDrop { _private: () }
+}
+
+#[must_use = "the summary is printed as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct PrintOnDrop {
+    _private: (),
+}
+
+impl Drop for PrintOnDrop {
+    fn drop(&mut self) {
+        print_summary();
+    }
+}
+
+pub fn record(phase: impl Into<String>, elapsed: Duration) {
+    with_session(|session| {
+        if let Some(session) = session {
+            session.phases.push((phase.into(), elapsed));
+        }
+    });
+}
+
+#[derive(Debug, Serialize)]
+pub struct Phase {
+    name: String,
+    secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta_secs: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    phases: Vec<Phase>,
+    total_secs: f64,
+}
+
+fn format_secs(secs: f64) -> String {
+    if secs < 10.0 {
+        format!("{:.1}s", secs)
+    } else if secs < 60.0 {
+        format!("{:.0}s", secs)
+    } else {
+        let secs = secs.round() as u64;
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+fn format_delta(delta: f64) -> String {
+    let sign = if delta < 0.0 { "-" } else { "+" };
+    format!("{}{}", sign, format_secs(delta.abs()))
+}
+
+impl Summary {
+    // Phases that happen more than once (i.e. installing several APKs) are
+    // added up, since that's what's comparable between runs.
+    fn new(phases: Vec<(String, Duration)>, last: &BTreeMap<String, f64>) -> Self {
+        let mut totals: Vec<(String, f64)> = Vec::new();
+        for (name, elapsed) in phases {
+            let secs = elapsed.as_secs_f64();
+            match totals.iter_mut().find(|(other, _)| *other == name) {
+                Some((_, total)) => *total += secs,
+                None => totals.push((name, secs)),
+            }
+        }
+        let total_secs = totals.iter().map(|(_, secs)| secs).sum();
+        Self {
+            phases: totals
+                .into_iter()
+                .map(|(name, secs)| Phase {
+                    delta_secs: last.get(&name).map(|last| secs - last),
+                    name,
+                    secs,
+                })
+                .collect(),
+            total_secs,
+        }
+    }
+
+    fn secs_by_name(&self) -> BTreeMap<&str, f64> {
+        self.phases
+            .iter()
+            .map(|phase| (phase.name.as_str(), phase.secs))
+            .collect()
+    }
+
+    pub fn render(&self) -> String {
+        let width = self
+            .phases
+            .iter()
+            .map(|phase| phase.name.len())
+            .max()
+            .unwrap_or_default()
+            .max("total".len());
+        let mut rendered = "Timings:\n".to_owned();
+        for phase in &self.phases {
+            let _ = write!(
+                rendered,
+                "  {:width$}  {:>6}",
+                phase.name,
+                format_secs(phase.secs),
+                width = width
+            );
+            if let Some(delta) = phase.delta_secs {
+                let _ = write!(rendered, "  {} vs last run", format_delta(delta));
+            }
+            rendered.push('\n');
+        }
+        let _ = writeln!(
+            rendered,
+            "  {:width$}  {:>6}",
+            "total",
+            format_secs(self.total_secs),
+            width = width
+        );
+        rendered
+    }
+}
+
+/// Ends the current session, recording its timings for next time. There's
+/// nothing to summarize if no session was started or nothing was timed.
+pub fn summarize() -> Option<Summary> {
+    let Session { path, phases, .. } = with_session(Option::take)?;
+    if phases.is_empty() {
+        return None;
+    }
+    let last = fs::read(&path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default();
+    let summary = Summary::new(phases, &last);
+    let result = path
+        .parent()
+        .map(fs::create_dir_all)
+        .transpose()
+        .and_then(|_| {
+            serde_json::to_vec_pretty(&summary.secs_by_name())
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+        })
+        .and_then(|bytes| fs::write(&path, bytes));
+    if let Err(err) = result {
+        log::warn!("failed to record timings in {:?}: {}", path, err);
+    }
+    Some(summary)
+}
+
+/// Ends the current session, printing its summary if that was asked for. Log
+/// streaming calls this before it starts, since it's usually only stopped with
+/// Ctrl-C.
+pub fn print_summary() {
+    let show = with_session(|session| session.as_ref().map(|session| session.show));
+    if let Some(show) = show {
+        if let Some(summary) = summarize() {
+            if show {
+                print!("{}", summary.render());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        secs,
+        expected,
+        case(0.3, "0.3s"),
+        case(8.0, "8.0s"),
+        case(41.4, "41s"),
+        case(92.0, "1m32s")
+    )]
+    fn test_format_secs(secs: f64, expected: &str) {
+        assert_eq!(format_secs(secs), expected);
+    }
+
+    #[test]
+    fn test_summary_adds_up_repeats_and_compares_to_last_run() {
+        let last = vec![("gradle".to_owned(), 29.0)].into_iter().collect();
+        let summary = Summary::new(
+            vec![
+                ("install".to_owned(), Duration::from_secs(3)),
+                ("gradle".to_owned(), Duration::from_secs(41)),
+                ("install".to_owned(), Duration::from_secs(5)),
+            ],
+            &last,
+        );
+        let phases = summary
+            .phases
+            .iter()
+            .map(|phase| (phase.name.as_str(), phase.secs, phase.delta_secs))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            phases,
+            vec![("install", 8.0, None), ("gradle", 41.0, Some(12.0))]
+        );
+        assert_eq!(summary.total_secs, 49.0);
+    }
+}
@@ -0,0 +1,587 @@
+//! Noticing when a child process has gone quiet for too long. Gradle,
+//! `xcodebuild`, and `adb` occasionally hang forever without a word (a stuck
+//! daemon, a device that dropped off mid-install), and without this we'd sit
+//! there right along with them.
+//!
+//! Watched commands have their output passed through us, so we can tell when
+//! there hasn't been any. After a while we warn, and after a while longer we
+//! ask whether to keep waiting, or if nobody's there to ask, stop the command
+//! and fail with [`Stalled`]. `--no-watchdog` turns all of this off, which
+//! also leaves the command's output alone.
+
+// Only Unix has a way to watch.
+#![cfg_attr(not(unix), allow(dead_code))]
+
+use super::{
+    cli::{Report, Reportable},
+    interrupt, progress, prompt,
+};
+use crate::opts::NonInteractive;
+use std::{
+    io::{self, BufRead as _, BufReader, Write as _},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+// Set on every watched command, so that a cargo-mobile it runs (like the one
+// Gradle runs to build the Rust lib) leaves the watching to us.
+static WATCHED_VAR: &str = "CARGO_MOBILE_WATCHED";
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+const MINUTE: u64 = 60;
+
+/// How long a command can be quiet before we warn, and before we give up on
+/// it, along with what tends to cause that.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    warn_after: Duration,
+    stall_after: Duration,
+    causes: &'static [&'static str],
+}
+
+impl Limits {
+    /// cargo, which can be quiet for a long time while one big crate builds.
+    pub const COMPILE: Self = Self::new(
+        10 * MINUTE,
+        45 * MINUTE,
+        &[
+            "a build script waiting for input, or for a lock held by another build",
+            "a network filesystem or disk that stopped responding",
+        ],
+    );
+
+    /// Gradle and `xcodebuild`, which print something for every task.
+    pub const PACKAGE: Self = Self::new(
+        5 * MINUTE,
+        20 * MINUTE,
+        &[
+            "a stuck Gradle daemon, which `./gradlew --stop` in `gen/android` stops",
+            "a locked keychain, which `codesign` waits on without saying so",
+            "a license prompt waiting for input, which `sudo xcodebuild -license` or `sdkmanager --licenses` gets out of the way",
+        ],
+    );
+
+    /// `adb`, which is quick unless the device has gone away.
+    pub const INSTALL: Self = Self::new(
+        MINUTE,
+        5 * MINUTE,
+        &[
+            "the device was disconnected or went to sleep",
+            "a prompt on the device asking whether to allow the install",
+            "a stuck adb server, which `adb kill-server` restarts",
+        ],
+    );
+
+    pub const fn new(
+        warn_after_secs: u64,
+        stall_after_secs: u64,
+        causes: &'static [&'static str],
+    ) -> Self {
+        Self {
+            warn_after: Duration::from_secs(warn_after_secs),
+            stall_after: Duration::from_secs(stall_after_secs),
+            causes,
+        }
+    }
+}
+
+fn display_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs / MINUTE {
+        0 => format!("{} second{}", secs, if secs == 1 { "" } else { "s" }),
+        1 => "1 minute".to_owned(),
+        minutes => format!("{} minutes", minutes),
+    }
+}
+
+/// A command that was stopped for being quiet too long.
+#[derive(Debug)]
+pub struct Stalled {
+    command: String,
+    silence: Duration,
+    causes: &'static [&'static str],
+}
+
+impl Reportable for Stalled {
+    fn report(&self) -> Report {
+        let causes = self
+            .causes
+            .iter()
+            .map(|cause| format!("- {}", cause))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Report::action_request(
+            format!("`{}` stalled", self.command),
+            format!(
+                "It didn't print anything for {}, so it was stopped. That's usually one of these:\n{}\n\nIf it's just slow, run again with `--no-watchdog`.",
+                display_duration(self.silence),
+                causes
+            ),
+        )
+    }
+
+    fn code(&self) -> &'static str {
+        "util.watchdog.stalled"
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    CommandFailed(bossy::Error),
+    Stalled(Stalled),
+}
+
+impl Error {
+    /// Turns this into the caller's error, which reports a stall differently
+    /// from the command failing.
+    pub fn split<E>(
+        self,
+        failed: impl FnOnce(bossy::Error) -> E,
+        stalled: impl FnOnce(Stalled) -> E,
+    ) -> E {
+        match self {
+            Self::CommandFailed(err) => failed(err),
+            Self::Stalled(err) => stalled(err),
+        }
+    }
+}
+
+/// Sets whether the watchdog's on, and whether it can ask before stopping
+/// anything.
+pub fn init(no_watchdog: bool, non_interactive: NonInteractive) {
+    DISABLED.store(no_watchdog, Ordering::SeqCst);
+    NON_INTERACTIVE.store(non_interactive.yes(), Ordering::SeqCst);
+}
+
+fn enabled() -> bool {
+    cfg!(unix) && !DISABLED.load(Ordering::SeqCst) && std::env::var_os(WATCHED_VAR).is_none()
+}
+
+/// Runs `command`, passing its output through while watching for it to stop.
+pub fn run_and_wait(command: bossy::Command, limits: Limits) -> Result<(), Error> {
+    if !enabled() {
+        return progress::run_and_wait(command).map_err(Error::CommandFailed);
+    }
+    run_with(command, limits, forward_stdout)
+}
+
+/// Runs `command`, passing each line of its stdout to `on_stdout`. Its stderr
+/// is passed through (or emitted as output events, if those are on).
+#[cfg(unix)]
+pub fn run_with(
+    command: bossy::Command,
+    limits: Limits,
+    on_stdout: impl FnMut(&[u8]) + Send,
+) -> Result<(), Error> {
+    unix::run_with(
+        command,
+        enabled().then(|| limits),
+        on_stdout,
+        forward_stderr,
+    )
+}
+
+#[cfg(not(unix))]
+pub fn run_with(
+    command: bossy::Command,
+    _limits: Limits,
+    on_stdout: impl FnMut(&[u8]) + Send,
+) -> Result<(), Error> {
+    run_unwatched(command, on_stdout)
+}
+
+/// Like [`run_and_wait`], but passes each line of the command's stderr to
+/// `on_stderr` instead, for when several commands run at once and their
+/// output can't just be interleaved. Its stdout is still passed through.
+#[cfg(unix)]
+pub fn run_and_wait_with_stderr(
+    command: bossy::Command,
+    limits: Limits,
+    on_stderr: impl FnMut(&[u8]) + Send,
+) -> Result<(), Error> {
+    if !enabled() {
+        return run_stderr_unwatched(command, on_stderr);
+    }
+    unix::run_with(command, Some(limits), forward_stdout, on_stderr)
+}
+
+#[cfg(not(unix))]
+pub fn run_and_wait_with_stderr(
+    command: bossy::Command,
+    _limits: Limits,
+    on_stderr: impl FnMut(&[u8]) + Send,
+) -> Result<(), Error> {
+    run_stderr_unwatched(command, on_stderr)
+}
+
+// Without a way to watch, stderr is left alone, and stdout is only read.
+fn run_unwatched(command: bossy::Command, on_stdout: impl FnMut(&[u8])) -> Result<(), Error> {
+    let mut handle = command
+        .with_stdout(bossy::Stdio::piped())
+        .with_stderr(bossy::Stdio::inherit())
+        .run()
+        .map_err(Error::CommandFailed)?;
+    let _child = interrupt::child(handle.id());
+    if let Some(stdout) = handle.stdout() {
+        read_lines(stdout, on_stdout);
+    }
+    handle
+        .wait_for_output()
+        .map(|_| ())
+        .map_err(Error::CommandFailed)
+}
+
+// Likewise, but only stderr is read, and stdout is left alone.
+fn run_stderr_unwatched(
+    command: bossy::Command,
+    on_stderr: impl FnMut(&[u8]),
+) -> Result<(), Error> {
+    let mut handle = command
+        .with_stderr_piped()
+        .run()
+        .map_err(Error::CommandFailed)?;
+    let _child = interrupt::child(handle.id());
+    if let Some(stderr) = handle.stderr() {
+        read_lines(stderr, on_stderr);
+    }
+    handle
+        .wait_for_output()
+        .map(|_| ())
+        .map_err(Error::CommandFailed)
+}
+
+fn forward_stdout(line: &[u8]) {
+    if progress::enabled() {
+        progress::output(None, &String::from_utf8_lossy(line));
+    } else {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let _ = stdout
+            .write_all(line)
+            .and_then(|()| stdout.write_all(b"\n"));
+    }
+}
+
+fn forward_stderr(line: &[u8]) {
+    if progress::enabled() {
+        progress::output(None, &String::from_utf8_lossy(line));
+    } else {
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        let _ = stderr
+            .write_all(line)
+            .and_then(|()| stderr.write_all(b"\n"));
+    }
+}
+
+fn read_lines(reader: impl io::Read, mut on_line: impl FnMut(&[u8])) {
+    for line in BufReader::new(reader).split(b'\n') {
+        match line {
+            Ok(line) => on_line(&line),
+            Err(err) => {
+                log::warn!("failed to read child process output: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+// Whether to keep waiting on a command that's been quiet for `silence`.
+fn keep_waiting(command: &str, silence: Duration) -> bool {
+    if NON_INTERACTIVE.load(Ordering::SeqCst) {
+        return false;
+    }
+    let msg = format!(
+        "`{}` hasn't printed anything for {}. Keep waiting?",
+        command,
+        display_duration(silence)
+    );
+    loop {
+        match prompt::yes_no(&msg, Some(prompt::YesOrNo::Yes)) {
+            Ok(Some(answer)) => break answer.yes(),
+            Ok(None) => (),
+            Err(err) => {
+                log::warn!("failed to ask whether to keep waiting: {}", err);
+                break false;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::{
+        io::Read,
+        net::Shutdown,
+        os::unix::{io::OwnedFd, net::UnixStream},
+        sync::{
+            atomic::AtomicUsize,
+            mpsc::{self, RecvTimeoutError},
+            Mutex,
+        },
+        thread,
+        time::Instant,
+    };
+
+    const POLL: Duration = Duration::from_secs(1);
+    // How long output gets to drain once the command's exited, in case it
+    // left something running that still has our end of the pipe.
+    const DRAIN: Duration = Duration::from_secs(2);
+    // How long a stopped command gets to exit before it's killed outright.
+    const GRACE: Duration = Duration::from_secs(5);
+
+    struct Activity(Mutex<Instant>);
+
+    impl Activity {
+        fn bump(&self) {
+            *self.0.lock().unwrap_or_else(|err| err.into_inner()) = Instant::now();
+        }
+
+        fn silence(&self) -> Duration {
+            self.0
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .elapsed()
+        }
+    }
+
+    // Counts every read as activity, so a progress bar that never finishes a
+    // line still counts.
+    struct Tracked<'a> {
+        stream: UnixStream,
+        activity: &'a Activity,
+    }
+
+    impl Read for Tracked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.stream.read(buf)?;
+            if n > 0 {
+                self.activity.bump();
+            }
+            Ok(n)
+        }
+    }
+
+    fn pipe() -> io::Result<(UnixStream, bossy::Stdio)> {
+        let (ours, theirs) = UnixStream::pair()?;
+        Ok((ours, bossy::Stdio::from(OwnedFd::from(theirs))))
+    }
+
+    // Only the command itself is signalled, since anything else we have
+    // running (like a log stream) isn't ours to stop. Wrapper scripts like
+    // `gradlew` `exec` into what they wrap, so that's what gets it.
+    fn signal(pid: u32, signal: &str) {
+        if let Err(err) = bossy::Command::impure("kill")
+            .with_args(&[signal, &pid.to_string()])
+            .run_and_wait()
+        {
+            log::warn!("failed to stop stalled command: {}", err);
+        }
+    }
+
+    enum Outcome {
+        Exited(bossy::Result<bossy::Output>),
+        Stalled {
+            silence: Duration,
+            causes: &'static [&'static str],
+        },
+    }
+
+    fn supervise(
+        done: &mpsc::Receiver<bossy::Result<bossy::Output>>,
+        activity: &Activity,
+        limits: Option<Limits>,
+        command: &str,
+    ) -> Outcome {
+        let mut warned = false;
+        loop {
+            match done.recv_timeout(POLL) {
+                Ok(result) => return Outcome::Exited(result),
+                Err(RecvTimeoutError::Disconnected) => {
+                    unreachable!("developer error: waiting thread exited without a result")
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+            let limits = match limits {
+                Some(limits) => limits,
+                None => continue,
+            };
+            let silence = activity.silence();
+            if silence < limits.warn_after {
+                warned = false;
+            } else if silence < limits.stall_after {
+                if !warned {
+                    log::warn!(
+                        "`{}` hasn't printed anything for {}",
+                        command,
+                        display_duration(silence)
+                    );
+                    warned = true;
+                }
+            } else if keep_waiting(command, silence) {
+                activity.bump();
+            } else {
+                return Outcome::Stalled {
+                    silence,
+                    causes: limits.causes,
+                };
+            }
+        }
+    }
+
+    pub fn run_with(
+        command: bossy::Command,
+        limits: Option<Limits>,
+        on_stdout: impl FnMut(&[u8]) + Send,
+        on_stderr: impl FnMut(&[u8]) + Send,
+    ) -> Result<(), Error> {
+        let pipes = pipe().and_then(|(stdout, child_stdout)| {
+            let (stderr, child_stderr) = pipe()?;
+            let ours = [stdout.try_clone()?, stderr.try_clone()?];
+            Ok((stdout, child_stdout, stderr, child_stderr, ours))
+        });
+        let (stdout, child_stdout, stderr, child_stderr, ours) = match pipes {
+            Ok(pipes) => pipes,
+            Err(err) => {
+                log::warn!("failed to capture output, so not watching it: {}", err);
+                return run_unwatched(command, on_stdout);
+            }
+        };
+        let command_string = command.display().to_owned();
+        let handle = command
+            .with_env_var(WATCHED_VAR, "1")
+            .with_stdout(child_stdout)
+            .with_stderr(child_stderr)
+            .run()
+            .map_err(Error::CommandFailed)?;
+        let pid = handle.id();
+        let _child = interrupt::child(pid);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(handle.wait_for_output());
+        });
+        let activity = Activity(Mutex::new(Instant::now()));
+        let open = AtomicUsize::new(2);
+        let outcome = thread::scope(|scope| {
+            let (activity, open) = (&activity, &open);
+            scope.spawn(move || {
+                read_lines(
+                    Tracked {
+                        stream: stdout,
+                        activity,
+                    },
+                    on_stdout,
+                );
+                open.fetch_sub(1, Ordering::SeqCst);
+            });
+            scope.spawn(move || {
+                read_lines(
+                    Tracked {
+                        stream: stderr,
+                        activity,
+                    },
+                    on_stderr,
+                );
+                open.fetch_sub(1, Ordering::SeqCst);
+            });
+            let outcome = match supervise(&rx, activity, limits, &command_string) {
+                Outcome::Exited(result) => Outcome::Exited(result),
+                stalled => {
+                    log::info!("stopping `{}`", command_string);
+                    signal(pid, "-TERM");
+                    if rx.recv_timeout(GRACE).is_err() {
+                        signal(pid, "-KILL");
+                    }
+                    stalled
+                }
+            };
+            let start = Instant::now();
+            while open.load(Ordering::SeqCst) > 0 && start.elapsed() < DRAIN {
+                thread::sleep(Duration::from_millis(50));
+            }
+            // Otherwise, whatever's still holding the other end would keep the
+            // readers (and so us) waiting.
+            for stream in &ours {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            outcome
+        });
+        match outcome {
+            Outcome::Exited(result) => result.map(|_| ()).map_err(Error::CommandFailed),
+            Outcome::Stalled { silence, causes } => Err(Error::Stalled(Stalled {
+                command: command_string,
+                silence,
+                causes,
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        secs,
+        expected,
+        case(1, "1 second"),
+        case(45, "45 seconds"),
+        case(90, "1 minute"),
+        case(300, "5 minutes")
+    )]
+    fn test_display_duration(secs: u64, expected: &str) {
+        assert_eq!(display_duration(Duration::from_secs(secs)), expected);
+    }
+
+    #[test]
+    fn test_packaging_gives_up_sooner_than_compiling() {
+        for limits in &[Limits::COMPILE, Limits::PACKAGE, Limits::INSTALL] {
+            assert!(limits.warn_after < limits.stall_after);
+        }
+        assert!(Limits::PACKAGE.stall_after < Limits::COMPILE.stall_after);
+        assert!(Limits::INSTALL.stall_after < Limits::PACKAGE.stall_after);
+    }
+
+    #[cfg(unix)]
+    const SHORT: Limits = Limits::new(1, 2, &["testing"]);
+
+    #[cfg(unix)]
+    #[test]
+    fn test_activity_resets_the_timer() {
+        NON_INTERACTIVE.store(true, Ordering::SeqCst);
+        let mut lines = 0;
+        // Quiet for less than the limit at a time, but longer than it overall.
+        let command = bossy::Command::impure("sh").with_args(&[
+            "-c",
+            "for i in 1 2 3 4 5 6 7 8; do echo tick; sleep 0.5; done",
+        ]);
+        unix::run_with(command, Some(SHORT), |_| lines += 1, forward_stderr).unwrap();
+        assert_eq!(lines, 8);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stalled_child_is_killed() {
+        NON_INTERACTIVE.store(true, Ordering::SeqCst);
+        let dir = crate::util::TempDir::new("watchdog-test").unwrap();
+        let pid_path = dir.path().join("pid");
+        let command = bossy::Command::impure("sh").with_args(&[
+            "-c",
+            &format!("echo $$ > '{}'; exec sleep 60", pid_path.display()),
+        ]);
+        let start = std::time::Instant::now();
+        let result = unix::run_with(command, Some(SHORT), |_| (), forward_stderr);
+        assert!(matches!(result, Err(Error::Stalled(_))));
+        assert!(start.elapsed() < Duration::from_secs(30));
+        let pid = std::fs::read_to_string(&pid_path).unwrap();
+        let alive = bossy::Command::impure("kill")
+            .with_args(&["-0", pid.trim()])
+            .run_and_wait()
+            .is_ok();
+        assert!(!alive, "stalled child {} is still running", pid.trim());
+    }
+}
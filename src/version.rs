@@ -0,0 +1,535 @@
+//! The app's version, which comes from the crate's version in `Cargo.toml`,
+//! and its build number, which comes from `app.build-number`. Both end up in
+//! the generated projects as `versionName`/`versionCode` and
+//! `CFBundleShortVersionString`/`CFBundleVersion`.
+
+use crate::{
+    config::app::App,
+    plan::Writer,
+    util::{
+        cli::{Report, Reportable},
+        Git,
+    },
+};
+use once_cell_regex::regex;
+use serde::{
+    de::{self, Deserializer, Visitor},
+    Deserialize, Serialize, Serializer,
+};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display},
+    fs, io,
+    num::ParseIntError,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Where the build number comes from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BuildNumber {
+    /// The number of commits on `HEAD`.
+    AutoGit,
+    /// Minutes since the Unix epoch, which stays under Android's limit of
+    /// 2100000000 for a good few millennia.
+    AutoTimestamp,
+    Explicit(u32),
+}
+
+impl Default for BuildNumber {
+    fn default() -> Self {
+        Self::Explicit(1)
+    }
+}
+
+impl Display for BuildNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AutoGit => write!(f, "auto-git"),
+            Self::AutoTimestamp => write!(f, "auto-timestamp"),
+            Self::Explicit(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+impl Serialize for BuildNumber {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Explicit(number) => serializer.serialize_u32(*number),
+            _ => serializer.serialize_str(&self.to_string()),
+        }
+    }
+}
+
+struct BuildNumberVisitor;
+
+impl<'de> Visitor<'de> for BuildNumberVisitor {
+    type Value = BuildNumber;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`\"auto-git\"`, `\"auto-timestamp\"`, or a positive integer"
+        )
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        match v {
+            "auto-git" => Ok(BuildNumber::AutoGit),
+            "auto-timestamp" => Ok(BuildNumber::AutoTimestamp),
+            _ => Err(E::invalid_value(de::Unexpected::Str(v), &self)),
+        }
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        match u32::try_from(v) {
+            Ok(number) if number > 0 => Ok(BuildNumber::Explicit(number)),
+            _ => Err(E::invalid_value(de::Unexpected::Signed(v), &self)),
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        match u32::try_from(v) {
+            Ok(number) if number > 0 => Ok(BuildNumber::Explicit(number)),
+            _ => Err(E::invalid_value(de::Unexpected::Unsigned(v), &self)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BuildNumber {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(BuildNumberVisitor)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    MetadataFailed(bossy::Error),
+    MetadataInvalid(serde_json::Error),
+    PackageMissing {
+        name: String,
+    },
+    ManifestInvalid(toml::de::Error),
+    CommitCountFailed(bossy::Error),
+    CommitCountInvalid(ParseIntError),
+    LastReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    LastInvalid {
+        path: PathBuf,
+        cause: ParseIntError,
+    },
+    BuildNumberDecreased {
+        path: PathBuf,
+        last: u32,
+        build_number: u32,
+    },
+    LastWriteFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        let msg = "Failed to determine app version";
+        match self {
+            Self::MetadataFailed(err) => {
+                Report::error(msg, format!("`cargo metadata` failed: {}", err))
+            }
+            Self::MetadataInvalid(err) => Report::error(
+                msg,
+                format!("`cargo metadata` output couldn't be parsed: {}", err),
+            ),
+            Self::PackageMissing { name } => Report::error(
+                msg,
+                format!("`cargo metadata` didn't list a package named {:?}", name),
+            ),
+            Self::ManifestInvalid(err) => {
+                Report::error(msg, format!("`Cargo.toml` couldn't be parsed: {}", err))
+            }
+            Self::CommitCountFailed(err) => Report::error(
+                msg,
+                format!(
+                    "`app.build-number` is `auto-git`, but counting commits failed: {}",
+                    err
+                ),
+            ),
+            Self::CommitCountInvalid(err) => Report::error(
+                msg,
+                format!("`git rev-list --count` output couldn't be parsed: {}", err),
+            ),
+            Self::LastReadFailed { path, cause } => Report::error(
+                msg,
+                format!("Failed to read last build number from {:?}: {}", path, cause),
+            ),
+            Self::LastInvalid { path, cause } => Report::error(
+                msg,
+                format!("Last build number in {:?} couldn't be parsed: {}", path, cause),
+            ),
+            Self::BuildNumberDecreased {
+                path,
+                last,
+                build_number,
+            } => Report::action_request(
+                format!(
+                    "Build number {} is lower than the last one generated, which was {}",
+                    build_number, last
+                ),
+                format!(
+                    "App stores reject builds that don't increase the build number, so raise `app.build-number` in your config. If going backwards is intentional, delete {:?} and try again.",
+                    path
+                ),
+            ),
+            Self::LastWriteFailed { path, cause } => Report::error(
+                msg,
+                format!("Failed to record build number in {:?}: {}", path, cause),
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::MetadataFailed(..) => "version.metadata-failed",
+            Self::MetadataInvalid(..) => "version.metadata-invalid",
+            Self::PackageMissing { .. } => "version.package-missing",
+            Self::ManifestInvalid(..) => "version.manifest-invalid",
+            Self::CommitCountFailed(..) => "version.commit-count-failed",
+            Self::CommitCountInvalid(..) => "version.commit-count-invalid",
+            Self::LastReadFailed { .. } => "version.last-read-failed",
+            Self::LastInvalid { .. } => "version.last-invalid",
+            Self::BuildNumberDecreased { .. } => "version.build-number-decreased",
+            Self::LastWriteFailed { .. } => "version.last-write-failed",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    name: String,
+    version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    package: Package,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Version {
+    /// The crate's version, i.e. `1.4.2`.
+    pub name: String,
+    pub code: u32,
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.code)
+    }
+}
+
+impl Version {
+    /// Asks cargo for the crate's version, since it could be inherited from
+    /// the workspace, and works out the build number from `app.build-number`.
+    pub fn resolve(app: &App) -> Result<Self, Error> {
+        let output = bossy::Command::impure("cargo")
+            .with_args(&["metadata", "--format-version", "1", "--no-deps"])
+            .with_arg("--manifest-path")
+            .with_arg(app.manifest_path())
+            .run_and_wait_for_str(|output| output.to_owned())
+            .map_err(Error::MetadataFailed)?;
+        let Metadata { packages } =
+            serde_json::from_str(&output).map_err(Error::MetadataInvalid)?;
+        let name = packages
+            .into_iter()
+            .find(|package| package.name == app.name())
+            .map(|package| package.version)
+            .ok_or_else(|| Error::PackageMissing {
+                name: app.name().to_owned(),
+            })?;
+        Ok(Self {
+            name,
+            code: Self::code(app)?,
+        })
+    }
+
+    /// Like [`Self::resolve`], but for a manifest that hasn't been written yet,
+    /// which means the version can't be inherited.
+    pub fn resolve_planned(app: &App, manifest: &[u8]) -> Result<Self, Error> {
+        let Manifest { package } = toml::from_slice(manifest).map_err(Error::ManifestInvalid)?;
+        Ok(Self {
+            name: package.version,
+            code: Self::code(app)?,
+        })
+    }
+
+    fn code(app: &App) -> Result<u32, Error> {
+        Ok(match app.build_number() {
+            BuildNumber::AutoGit => Git::new(app.root_dir())
+                .command()
+                .with_args(&["rev-list", "--count", "HEAD"])
+                .run_and_wait_for_str(|count| count.trim().parse())
+                .map_err(Error::CommitCountFailed)?
+                .map_err(Error::CommitCountInvalid)?,
+            BuildNumber::AutoTimestamp => {
+                let minutes = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+                    / 60;
+                minutes as u32
+            }
+            BuildNumber::Explicit(number) => number,
+        })
+    }
+
+    /// Makes sure the build number hasn't gone down since the last time the
+    /// project in `project_dir` was generated, and then records it for next
+    /// time.
+    pub fn check_and_record(&self, project_dir: &Path, writer: Writer) -> Result<(), Error> {
+        let path = project_dir.join(".build-number");
+        let last = match fs::read_to_string(&path) {
+            Ok(last) => Some(
+                last.trim()
+                    .parse::<u32>()
+                    .map_err(|cause| Error::LastInvalid {
+                        path: path.clone(),
+                        cause,
+                    })?,
+            ),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(cause) => return Err(Error::LastReadFailed { path, cause }),
+        };
+        if let Some(last) = last.filter(|last| *last > self.code) {
+            return Err(Error::BuildNumberDecreased {
+                path,
+                last,
+                build_number: self.code,
+            });
+        }
+        writer
+            .create_dir_all(project_dir)
+            .and_then(|()| writer.write(&path, self.code.to_string()))
+            .map_err(|cause| Error::LastWriteFailed { path, cause })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Bump {
+    Major,
+    Minor,
+    Patch,
+}
+
+impl FromStr for Bump {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "major" => Ok(Self::Major),
+            "minor" => Ok(Self::Minor),
+            "patch" => Ok(Self::Patch),
+            _ => Err(format!("{:?} isn't `major`, `minor`, or `patch`", s)),
+        }
+    }
+}
+
+impl Bump {
+    pub fn name_list() -> &'static [&'static str] {
+        &["major", "minor", "patch"]
+    }
+
+    // Anything after the numbers (i.e. `-beta.1`) is dropped, since it
+    // described the version being left behind.
+    fn apply(self, version: &str) -> Option<String> {
+        let caps = regex!(r"^(\d+)\.(\d+)\.(\d+)").captures(version)?;
+        let mut parts = [&caps[1], &caps[2], &caps[3]]
+            .iter()
+            .map(|part| part.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+        let index = match self {
+            Self::Major => 0,
+            Self::Minor => 1,
+            Self::Patch => 2,
+        };
+        parts[index] += 1;
+        for part in &mut parts[index + 1..] {
+            *part = 0;
+        }
+        Some(format!("{}.{}.{}", parts[0], parts[1], parts[2]))
+    }
+}
+
+#[derive(Debug)]
+pub enum BumpError {
+    ReadFailed { path: PathBuf, cause: io::Error },
+    VersionMissing { path: PathBuf },
+    VersionInherited { path: PathBuf },
+    VersionInvalid { path: PathBuf, version: String },
+    WriteFailed { path: PathBuf, cause: io::Error },
+}
+
+impl Reportable for BumpError {
+    fn report(&self) -> Report {
+        let msg = "Failed to bump version";
+        match self {
+            Self::ReadFailed { path, cause } => {
+                Report::error(msg, format!("Failed to read {:?}: {}", path, cause))
+            }
+            Self::VersionMissing { path } => Report::error(
+                msg,
+                format!("No `version` was found under `[package]` in {:?}", path),
+            ),
+            Self::VersionInherited { path } => Report::action_request(
+                msg,
+                format!(
+                    "The version in {:?} is inherited from the workspace, so bump it there instead",
+                    path
+                ),
+            ),
+            Self::VersionInvalid { path, version } => Report::error(
+                msg,
+                format!(
+                    "Version {:?} in {:?} isn't of the form `major.minor.patch`",
+                    version, path
+                ),
+            ),
+            Self::WriteFailed { path, cause } => {
+                Report::error(msg, format!("Failed to write {:?}: {}", path, cause))
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ReadFailed { .. } => "version.bump.read-failed",
+            Self::VersionMissing { .. } => "version.bump.version-missing",
+            Self::VersionInherited { .. } => "version.bump.version-inherited",
+            Self::VersionInvalid { .. } => "version.bump.version-invalid",
+            Self::WriteFailed { .. } => "version.bump.write-failed",
+        }
+    }
+}
+
+// Swaps out the value of `version` under `[package]`, leaving the rest of the
+// file exactly as it was.
+fn bump_contents(contents: &str, bump: Bump) -> Result<(String, String), BumpOutcome> {
+    let mut in_package = false;
+    let mut offset = 0;
+    for line in contents.split('\n') {
+        if let Some(caps) = regex!(r"^\s*\[\s*([^\]]+?)\s*\]").captures(line) {
+            in_package = &caps[1] == "package";
+        } else if in_package {
+            if regex!(r#"^\s*version\s*\.\s*workspace\s*="#).is_match(line)
+                || regex!(r#"^\s*version\s*=\s*\{"#).is_match(line)
+            {
+                return Err(BumpOutcome::Inherited);
+            }
+            if let Some(caps) = regex!(r#"^\s*version\s*=\s*"([^"]*)""#).captures(line) {
+                let old = caps.get(1).unwrap();
+                let new = bump
+                    .apply(old.as_str())
+                    .ok_or_else(|| BumpOutcome::Invalid(old.as_str().to_owned()))?;
+                let start = offset + old.start();
+                let end = offset + old.end();
+                let bumped = format!("{}{}{}", &contents[..start], new, &contents[end..]);
+                return Ok((bumped, new));
+            }
+        }
+        offset += line.len() + 1;
+    }
+    Err(BumpOutcome::Missing)
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum BumpOutcome {
+    Missing,
+    Inherited,
+    Invalid(String),
+}
+
+/// Bumps the version in the `Cargo.toml` at `manifest_path`, returning the new
+/// version.
+pub fn bump(manifest_path: &Path, bump: Bump) -> Result<String, BumpError> {
+    let path = manifest_path.to_owned();
+    let contents = fs::read_to_string(&path).map_err(|cause| BumpError::ReadFailed {
+        path: path.clone(),
+        cause,
+    })?;
+    let (bumped, new) = bump_contents(&contents, bump).map_err(|outcome| match outcome {
+        BumpOutcome::Missing => BumpError::VersionMissing { path: path.clone() },
+        BumpOutcome::Inherited => BumpError::VersionInherited { path: path.clone() },
+        BumpOutcome::Invalid(version) => BumpError::VersionInvalid {
+            path: path.clone(),
+            version,
+        },
+    })?;
+    fs::write(&path, bumped).map_err(|cause| BumpError::WriteFailed { path, cause })?;
+    Ok(new)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        bump,
+        version,
+        expected,
+        case(Bump::Patch, "0.1.9", Some("0.1.10")),
+        case(Bump::Minor, "0.1.9", Some("0.2.0")),
+        case(Bump::Major, "1.4.2-beta.1", Some("2.0.0")),
+        case(Bump::Patch, "1.4", None)
+    )]
+    fn test_bump_apply(bump: Bump, version: &str, expected: Option<&str>) {
+        assert_eq!(bump.apply(version).as_deref(), expected);
+    }
+
+    #[test]
+    fn test_bump_only_touches_package_version() {
+        let contents = "[package]\nname = \"fish\"\nversion   = \"0.3.1\" # keep me\n\n[dependencies]\nlog = { version = \"0.4\" }\n";
+        let (bumped, new) = bump_contents(contents, Bump::Minor).unwrap();
+        assert_eq!(new, "0.4.0");
+        assert_eq!(
+            bumped,
+            "[package]\nname = \"fish\"\nversion   = \"0.4.0\" # keep me\n\n[dependencies]\nlog = { version = \"0.4\" }\n"
+        );
+        assert_eq!(
+            bump_contents(
+                "[package]\nname = \"fish\"\nversion.workspace = true\n",
+                Bump::Patch
+            ),
+            Err(BumpOutcome::Inherited)
+        );
+    }
+
+    #[rstest(
+        raw,
+        expected,
+        case("\"auto-git\"", Some(BuildNumber::AutoGit)),
+        case("\"auto-timestamp\"", Some(BuildNumber::AutoTimestamp)),
+        case("42", Some(BuildNumber::Explicit(42))),
+        case("0", None),
+        case("\"auto\"", None)
+    )]
+    fn test_build_number_deserialize(raw: &str, expected: Option<BuildNumber>) {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            build_number: BuildNumber,
+        }
+        let parsed = toml::from_str::<Wrapper>(&format!("build_number = {}", raw))
+            .ok()
+            .map(|wrapper| wrapper.build_number);
+        assert_eq!(parsed, expected);
+    }
+}
@@ -0,0 +1,521 @@
+//! Simulators that cargo-mobile booted itself. They're tracked in
+//! `~/.cargo-mobile/vms.toml`, so that ones left running by a cancelled CI job
+//! can be found and shut down with `cargo mobile vms`. Ones that were already
+//! running when we got to them aren't ours to stop, so they aren't tracked.
+//! cargo-mobile doesn't boot emulators, so there aren't any of those.
+//!
+//! Several jobs can share a machine, so `vms.toml` is only ever changed while
+//! holding a lock on `vms.toml.lock`, and is replaced in one go so that it's
+//! never seen half-written.
+
+use crate::{
+    opts,
+    util::{
+        self,
+        cli::{Report, Reportable},
+        interrupt,
+    },
+};
+use fs2::FileExt as _;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    NoHomeDir(util::NoHomeDir),
+    ReadFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    ParseFailed {
+        path: PathBuf,
+        cause: toml::de::Error,
+    },
+    WriteFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    LockFailed {
+        path: PathBuf,
+        cause: io::Error,
+    },
+    NotFound {
+        query: String,
+    },
+    EnvFailed(crate::env::Error),
+    StopFailed {
+        instance: String,
+        cause: bossy::Error,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::NoHomeDir(err) => Report::error("Failed to find the booted instances", err),
+            Self::ReadFailed { path, cause } => {
+                Report::error(format!("Failed to read {:?}", path), cause)
+            }
+            Self::ParseFailed { path, cause } => {
+                Report::error(format!("Failed to parse {:?}", path), cause)
+            }
+            Self::WriteFailed { path, cause } => {
+                Report::error(format!("Failed to write {:?}", path), cause)
+            }
+            Self::LockFailed { path, cause } => {
+                Report::error(format!("Failed to lock {:?}", path), cause)
+            }
+            Self::NotFound { query } => Report::error(
+                format!("cargo-mobile didn't boot anything called {:?}", query),
+                "Run `cargo mobile vms list` to see what it booted; ones you booted yourself aren't tracked.",
+            ),
+            Self::EnvFailed(err) => err.report(),
+            Self::StopFailed { instance, cause } => {
+                Report::error(format!("Failed to shut down {}", instance), cause)
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::NoHomeDir(..) => "vms.no-home-dir",
+            Self::ReadFailed { .. } => "vms.read-failed",
+            Self::ParseFailed { .. } => "vms.parse-failed",
+            Self::WriteFailed { .. } => "vms.write-failed",
+            Self::LockFailed { .. } => "vms.lock-failed",
+            Self::NotFound { .. } => "vms.not-found",
+            Self::EnvFailed(err) => err.code(),
+            Self::StopFailed { .. } => "vms.stop-failed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    Simulator,
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Simulator => write!(f, "simulator"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Instance {
+    kind: Kind,
+    /// The simulator's UDID.
+    id: String,
+    name: String,
+    /// Seconds since the Unix epoch.
+    booted_at: u64,
+    /// The cargo-mobile process that booted it, which `--ephemeral` uses to
+    /// only shut down its own.
+    booted_by: u32,
+    /// When that process started, since its pid could've been reused by now.
+    /// This is `None` if `ps` couldn't tell us.
+    #[serde(default)]
+    booted_by_started: Option<String>,
+}
+
+impl Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({})", self.kind, self.name, self.id)
+    }
+}
+
+// i.e. "Tue Oct 14 09:12:03 2026", which is the same for as long as `pid`
+// lives, and different for whatever gets its pid next.
+fn started(pid: u32) -> Option<String> {
+    bossy::Command::impure("ps")
+        .with_args(&["-o", "lstart=", "-p"])
+        .with_arg(pid.to_string())
+        .run_and_wait_for_str(|output| output.trim().to_owned())
+        .map_err(|err| log::info!("failed to get the start time of process {}: {}", pid, err))
+        .ok()
+        .filter(|started| !started.is_empty())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// i.e. "3m ago"; precise enough to tell a leftover from last night apart from
+// one a job's using right now.
+fn ago(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+impl Instance {
+    /// An instance this process just booted.
+    pub fn new(kind: Kind, id: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            kind,
+            id: id.into(),
+            name: name.into(),
+            booted_at: now(),
+            booted_by: process::id(),
+            booted_by_started: started(process::id()),
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn booted_at(&self) -> SystemTime {
+        UNIX_EPOCH + std::time::Duration::from_secs(self.booted_at)
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        self.id == query || self.name.eq_ignore_ascii_case(query)
+    }
+
+    fn booted_by(&self, pid: u32, started: Option<&str>) -> bool {
+        self.booted_by == pid && self.booted_by_started.as_deref() == started
+    }
+
+    /// Whether it's still booted and answering, as far as `simctl bootstatus`
+    /// can tell.
+    pub fn responsive(&self) -> bool {
+        match self.kind {
+            Kind::Simulator => simulator::responsive(&self.id),
+        }
+    }
+
+    // Something that's gone already doesn't need stopping, so only ones that
+    // are still around are shut down.
+    fn shut_down(&self) -> Result<(), Error> {
+        if !self.responsive() {
+            log::info!("{} isn't running anymore", self);
+            return Ok(());
+        }
+        let result = match self.kind {
+            Kind::Simulator => simulator::shutdown(&self.id)?,
+        };
+        result.map_err(|cause| Error::StopFailed {
+            instance: self.to_string(),
+            cause,
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod simulator {
+    use super::Error;
+    use crate::{apple::simctl, env::Env};
+
+    pub fn responsive(udid: &str) -> bool {
+        Env::new()
+            .map(|env| simctl::responsive(&env, udid))
+            .unwrap_or(false)
+    }
+
+    pub fn shutdown(udid: &str) -> Result<bossy::Result<()>, Error> {
+        let env = Env::new().map_err(Error::EnvFailed)?;
+        Ok(simctl::shutdown(&env, udid))
+    }
+}
+
+// Simulators only run on macOS, so there's nothing to check on elsewhere.
+#[cfg(not(target_os = "macos"))]
+mod simulator {
+    use super::Error;
+
+    pub fn responsive(_udid: &str) -> bool {
+        false
+    }
+
+    pub fn shutdown(_udid: &str) -> Result<bossy::Result<()>, Error> {
+        Ok(Ok(()))
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct State {
+    #[serde(default)]
+    instances: Vec<Instance>,
+}
+
+// Held for as long as `vms.toml` is being read, changed, and written back.
+struct Lock {
+    _file: fs::File,
+}
+
+impl Lock {
+    fn acquire(path: &Path) -> Result<Self, Error> {
+        let lock_failed = |cause| Error::LockFailed {
+            path: path.to_owned(),
+            cause,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(lock_failed)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(path)
+            .map_err(lock_failed)?;
+        // This is released when the file is closed, even if we crash.
+        file.lock_exclusive().map_err(lock_failed)?;
+        Ok(Self { _file: file })
+    }
+}
+
+impl State {
+    fn path() -> Result<PathBuf, Error> {
+        util::install_dir()
+            .map(|dir| dir.join("vms.toml"))
+            .map_err(Error::NoHomeDir)
+    }
+
+    fn load() -> Result<Self, Error> {
+        Self::load_from(&Self::path()?)
+    }
+
+    fn load_from(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|cause| Error::ParseFailed {
+                path: path.to_owned(),
+                cause,
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(cause) => Err(Error::ReadFailed {
+                path: path.to_owned(),
+                cause,
+            }),
+        }
+    }
+
+    // Written next to `path` and then renamed over it, since a rename is
+    // atomic and a write isn't.
+    fn save_to(&self, path: &Path) -> Result<(), Error> {
+        let contents = toml::to_string(self).expect("developer error: state not serializable");
+        let temp = path.with_extension(format!("toml.{}.tmp", process::id()));
+        fs::write(&temp, contents)
+            .and_then(|()| fs::rename(&temp, path))
+            .map_err(|cause| {
+                let _ = fs::remove_file(&temp);
+                Error::WriteFailed {
+                    path: path.to_owned(),
+                    cause,
+                }
+            })
+    }
+
+    /// Loads the state, lets `f` change it, and saves it again, without
+    /// anyone else changing it in between.
+    fn update(f: impl FnOnce(&mut Self)) -> Result<(), Error> {
+        let path = Self::path()?;
+        let _lock = Lock::acquire(&path.with_extension("toml.lock"))?;
+        let mut state = Self::load_from(&path)?;
+        f(&mut state);
+        state.save_to(&path)
+    }
+
+    fn insert(&mut self, instance: Instance) {
+        self.instances
+            .retain(|other| (other.kind, &other.id) != (instance.kind, &instance.id));
+        self.instances.push(instance);
+    }
+
+    fn remove(&mut self, instance: &Instance) {
+        self.instances.retain(|other| other != instance);
+    }
+}
+
+/// Tracks an instance we just booted. Failing to isn't worth failing the
+/// command over.
+pub fn register(instance: Instance) {
+    log::info!("tracking {}", instance);
+    let result = State::update(|state| state.insert(instance));
+    if let Err(err) = result {
+        log::warn!("failed to track booted instance: {:?}", err);
+    }
+}
+
+/// The instances cargo-mobile booted, oldest first.
+pub fn list() -> Result<Vec<Instance>, Error> {
+    State::load().map(|state| state.instances)
+}
+
+/// Shuts down the instance named `query` (by name or ID), or every one if
+/// there's no `query`, and stops tracking them. Returns what was stopped.
+pub fn stop(query: Option<&str>) -> Result<Vec<Instance>, Error> {
+    let stopping = State::load()?
+        .instances
+        .iter()
+        .filter(|instance| query.map_or(true, |query| instance.matches(query)))
+        .cloned()
+        .collect::<Vec<_>>();
+    if let (Some(query), true) = (query, stopping.is_empty()) {
+        return Err(Error::NotFound {
+            query: query.to_owned(),
+        });
+    }
+    // Shutting down can take a while, so the lock's only taken to update
+    // the state afterward.
+    for instance in &stopping {
+        instance.shut_down()?;
+        State::update(|state| state.remove(instance))?;
+    }
+    Ok(stopping)
+}
+
+// Shuts down what this process booted, for `--ephemeral`.
+fn stop_own() {
+    let started = started(process::id());
+    let own = match list() {
+        Ok(instances) => instances
+            .into_iter()
+            .filter(|instance| instance.booted_by(process::id(), started.as_deref()))
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            log::warn!("failed to find booted instances: {:?}", err);
+            return;
+        }
+    };
+    for instance in own {
+        let result = instance
+            .shut_down()
+            .and_then(|()| State::update(|state| state.remove(&instance)));
+        match result {
+            Ok(()) => eprintln!("Shut down {}, since `--ephemeral` was passed", instance),
+            Err(err) => log::warn!("failed to shut down {}: {:?}", instance, err),
+        }
+    }
+}
+
+/// Shuts down whatever this process boots once it's dropped, or when the user
+/// hits Ctrl-C.
+#[must_use = "instances are shut down as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct Ephemeral {
+    _cleanup: interrupt::Cleanup,
+}
+
+impl Drop for Ephemeral {
+    fn drop(&mut self) {
+        stop_own();
+    }
+}
+
+pub fn ephemeral(ephemeral: opts::Ephemeral) -> Option<Ephemeral> {
+    if ephemeral.no() {
+        return None;
+    }
+    Some(Ephemeral {
+        _cleanup: interrupt::on_interrupt(stop_own),
+    })
+}
+
+/// Prints each instance with when it was booted and whether it's still
+/// responsive.
+pub fn print_list(instances: &[Instance]) {
+    if instances.is_empty() {
+        println!("cargo-mobile hasn't booted anything that's still tracked");
+        return;
+    }
+    let now = now();
+    for instance in instances {
+        println!(
+            "{}, booted {}, {}",
+            instance,
+            ago(now.saturating_sub(instance.booted_at)),
+            if instance.responsive() {
+                "responsive"
+            } else {
+                "not responding"
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        secs,
+        expected,
+        case(5, "5s ago"),
+        case(150, "2m ago"),
+        case(7200, "2h ago"),
+        case(200_000, "2d ago")
+    )]
+    fn test_ago(secs: u64, expected: &str) {
+        assert_eq!(ago(secs), expected);
+    }
+
+    #[test]
+    fn test_insert_replaces() {
+        let mut state = State::default();
+        state.insert(Instance::new(Kind::Simulator, "EFGH-5678", "iPhone SE"));
+        state.insert(Instance::new(Kind::Simulator, "ABCD-1234", "iPhone 15"));
+        state.insert(Instance::new(Kind::Simulator, "EFGH-5678", "iPad Air"));
+        assert_eq!(
+            state
+                .instances
+                .iter()
+                .map(Instance::name)
+                .collect::<Vec<_>>(),
+            vec!["iPhone 15", "iPad Air"]
+        );
+        let parsed: State = toml::from_str(&toml::to_string(&state).unwrap()).unwrap();
+        assert_eq!(parsed.instances, state.instances);
+        assert!(parsed.instances[0].matches("iphone 15"));
+        assert!(parsed.instances[1].matches("EFGH-5678"));
+    }
+
+    #[test]
+    fn test_booted_by() {
+        let mut instance = Instance::new(Kind::Simulator, "ABCD-1234", "iPhone 15");
+        instance.booted_by = 42;
+        instance.booted_by_started = Some("Tue Oct 14 09:12:03 2026".to_owned());
+        assert!(instance.booted_by(42, Some("Tue Oct 14 09:12:03 2026")));
+        // The same pid, but a different process.
+        assert!(!instance.booted_by(42, Some("Wed Oct 15 10:00:00 2026")));
+        assert!(!instance.booted_by(43, Some("Tue Oct 14 09:12:03 2026")));
+    }
+
+    #[test]
+    fn test_save_replaces() {
+        let dir = util::TempDir::new("vms-test").unwrap();
+        let path = dir.path().join("vms.toml");
+        let mut state = State::default();
+        state.insert(Instance::new(Kind::Simulator, "ABCD-1234", "iPhone 15"));
+        state.save_to(&path).unwrap();
+        state.insert(Instance::new(Kind::Simulator, "EFGH-5678", "iPad Air"));
+        state.save_to(&path).unwrap();
+        assert_eq!(State::load_from(&path).unwrap().instances, state.instances);
+        // Nothing's left behind besides the state itself.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,380 @@
+use crate::{
+    env::ExplicitEnv,
+    ui,
+    util::{
+        cli::{Report, Reportable, TextWrapper},
+        interrupt,
+    },
+};
+use ignore::gitignore::Gitignore;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher as _};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, BufRead as _},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+// Long enough to swallow the flurry of events an editor makes when saving.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum Error {
+    MetadataFailed(bossy::Error),
+    MetadataInvalid(serde_json::Error),
+    WatcherFailed(notify::Error),
+    WatchFailed { path: PathBuf, cause: notify::Error },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::MetadataFailed(err) => Report::error("Failed to run `cargo metadata`", err),
+            Self::MetadataInvalid(err) => {
+                Report::error("`cargo metadata` output couldn't be parsed", err)
+            }
+            Self::WatcherFailed(err) => Report::error("Failed to start file watcher", err),
+            Self::WatchFailed { path, cause } => {
+                Report::error(format!("Failed to watch {:?}", path), cause)
+            }
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::MetadataFailed(..) => "watch.metadata-failed",
+            Self::MetadataInvalid(..) => "watch.metadata-invalid",
+            Self::WatcherFailed(..) => "watch.watcher-failed",
+            Self::WatchFailed { .. } => "watch.watch-failed",
+        }
+    }
+}
+
+/// What prompted a redeploy.
+#[derive(Debug)]
+pub enum Trigger {
+    /// Sources changed, so only the Rust libs need to be rebuilt.
+    Changed(BTreeSet<PathBuf>),
+    /// Requested explicitly (or the first deploy), so everything gets rebuilt.
+    Rebuild,
+}
+
+enum Message {
+    Trigger(Trigger),
+    Quit,
+}
+
+#[derive(Debug, Deserialize)]
+struct Target {
+    src_path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    manifest_path: PathBuf,
+    targets: Vec<Target>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    workspace_root: PathBuf,
+}
+
+// The manifests and the dirs containing each target's root source file, which
+// covers `src`, `examples`, etc., along with `extra`. A build script puts the
+// whole package root in the mix, which is why events get filtered through
+// `.gitignore`.
+fn sources(
+    manifest_path: &Path,
+    extra: &[PathBuf],
+    env: &impl ExplicitEnv,
+) -> Result<(PathBuf, Vec<PathBuf>), Error> {
+    let output = bossy::Command::pure("cargo")
+        .with_env_vars(env.explicit_env())
+        .with_args(&["metadata", "--format-version", "1", "--no-deps"])
+        .with_arg("--manifest-path")
+        .with_arg(manifest_path)
+        .run_and_wait_for_str(|output| output.to_owned())
+        .map_err(Error::MetadataFailed)?;
+    let Metadata {
+        packages,
+        workspace_root,
+    } = serde_json::from_str(&output).map_err(Error::MetadataInvalid)?;
+    let mut paths = BTreeSet::new();
+    for package in packages {
+        for target in package.targets {
+            if let Some(dir) = target.src_path.parent() {
+                paths.insert(dir.to_owned());
+            }
+        }
+        paths.insert(package.manifest_path);
+    }
+    paths.extend(extra.iter().cloned());
+    // A dir containing another is already watched recursively.
+    let paths = paths
+        .iter()
+        .filter(|path| {
+            !paths
+                .iter()
+                .any(|other| other != *path && path.starts_with(other))
+        })
+        .cloned()
+        .collect();
+    Ok((workspace_root, paths))
+}
+
+fn changed_paths(event: DebouncedEvent) -> Vec<PathBuf> {
+    match event {
+        DebouncedEvent::Create(path)
+        | DebouncedEvent::Write(path)
+        | DebouncedEvent::Remove(path) => vec![path],
+        DebouncedEvent::Rename(from, to) => vec![from, to],
+        DebouncedEvent::Error(err, path) => {
+            log::warn!("file watcher error for {:?}: {}", path, err);
+            Vec::new()
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn load_gitignore(dir: &Path) -> Gitignore {
+    let path = dir.join(".gitignore");
+    if !path.is_file() {
+        return Gitignore::empty();
+    }
+    let (gitignore, err) = Gitignore::new(&path);
+    if let Some(err) = err {
+        log::warn!("failed to parse {:?}: {}", path, err);
+    }
+    gitignore
+}
+
+// Every dir can have its own `.gitignore`, which like in git takes precedence
+// over the ones above it. They're only loaded once something changes under
+// them, and loaded again if they change themselves.
+struct Ignores {
+    roots: Vec<PathBuf>,
+    gitignores: HashMap<PathBuf, Gitignore>,
+}
+
+impl Ignores {
+    fn new(roots: Vec<PathBuf>) -> Self {
+        Self {
+            roots,
+            gitignores: HashMap::new(),
+        }
+    }
+
+    fn ignored(&mut self, path: &Path) -> bool {
+        if path
+            .components()
+            .any(|component| component.as_os_str() == ".git")
+        {
+            return true;
+        }
+        if path.file_name().map_or(false, |name| name == ".gitignore") {
+            if let Some(dir) = path.parent() {
+                self.gitignores.remove(dir);
+            }
+        }
+        // Nothing above the outermost root is ours to look at.
+        let root = match self
+            .roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .min_by_key(|root| root.components().count())
+        {
+            Some(root) => root,
+            None => return false,
+        };
+        let is_dir = path.is_dir();
+        for dir in path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(root))
+        {
+            let gitignore = self
+                .gitignores
+                .entry(dir.to_owned())
+                .or_insert_with(|| load_gitignore(dir));
+            let matched = gitignore.matched_path_or_any_parents(path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            } else if matched.is_whitelist() {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+fn forward_events(events: Receiver<DebouncedEvent>, mut ignores: Ignores, tx: Sender<Message>) {
+    thread::spawn(move || {
+        for event in events {
+            let paths = changed_paths(event)
+                .into_iter()
+                .filter(|path| !ignores.ignored(path))
+                .collect::<BTreeSet<_>>();
+            if !paths.is_empty() && tx.send(Message::Trigger(Trigger::Changed(paths))).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// Stdin is line-buffered, so this only sees the key once Enter is pressed.
+fn listen_for_keys(tx: Sender<Message>) {
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let message = match line.as_ref().map(|line| line.trim()) {
+                Ok("r") => Message::Trigger(Trigger::Rebuild),
+                Ok(_) => continue,
+                Err(_) => break,
+            };
+            if tx.send(message).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// Blocks until something happens, then folds in whatever else piled up in the
+// meantime so that a burst of changes only causes one redeploy.
+fn next(rx: &Receiver<Message>) -> Option<Trigger> {
+    let mut trigger = match rx.recv().ok()? {
+        Message::Trigger(trigger) => trigger,
+        Message::Quit => return None,
+    };
+    for message in rx.try_iter() {
+        trigger = match (message, trigger) {
+            (Message::Quit, _) => return None,
+            (Message::Trigger(Trigger::Changed(mut more)), Trigger::Changed(mut paths)) => {
+                paths.append(&mut more);
+                Trigger::Changed(paths)
+            }
+            _ => Trigger::Rebuild,
+        };
+    }
+    Some(trigger)
+}
+
+/// Deploys once, then redeploys whenever the crate's sources (or `extra`,
+/// like an Xcode target's other sources) change, or the user asks for a full
+/// rebuild, until they hit Ctrl-C. Failed deploys are reported but don't stop
+/// the watcher, since the next change may fix them.
+pub fn watch<E: Reportable>(
+    manifest_path: &Path,
+    extra: &[PathBuf],
+    env: &impl ExplicitEnv,
+    wrapper: &TextWrapper,
+    mut deploy: impl FnMut(Trigger) -> Result<(), E>,
+) -> Result<(), Error> {
+    let (workspace_root, paths) = sources(manifest_path, extra, env)?;
+    let roots = std::iter::once(workspace_root)
+        .chain(paths.iter().filter(|path| path.is_dir()).cloned())
+        .collect();
+    let (events_tx, events_rx) = mpsc::channel();
+    let mut watcher: RecommendedWatcher =
+        notify::watcher(events_tx, DEBOUNCE).map_err(Error::WatcherFailed)?;
+    for path in &paths {
+        watcher
+            .watch(path, RecursiveMode::Recursive)
+            .map_err(|cause| Error::WatchFailed {
+                path: path.clone(),
+                cause,
+            })?;
+        log::info!("watching {:?}", path);
+    }
+    let (tx, rx) = mpsc::channel();
+    forward_events(events_rx, Ignores::new(roots), tx.clone());
+    listen_for_keys(tx.clone());
+    // Ctrl-C is delivered to the whole foreground process group, so a build in
+    // progress gets interrupted too; we stick around just long enough to say
+    // goodbye instead of reporting that as a failure.
+    let _survive = interrupt::survive();
+    let _quit = interrupt::on_interrupt(move || {
+        let _ = tx.send(Message::Quit);
+    });
+    let mut trigger = Trigger::Rebuild;
+    loop {
+        if let Trigger::Changed(paths) = &trigger {
+            for path in paths {
+                ui::status(format!("Changed: {}", path.display()));
+            }
+        }
+        match deploy(trigger) {
+            Ok(()) => {}
+            Err(_) if interrupt::interrupted() => break,
+            Err(err) => err.report().print(wrapper),
+        }
+        ui::status(
+            "Watching for changes; enter `r` to rebuild everything, or press Ctrl-C to stop.",
+        );
+        trigger = match next(&rx) {
+            Some(trigger) => trigger,
+            None => break,
+        };
+    }
+    ui::status("\nStopped watching.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn changed(path: &str) -> Message {
+        Message::Trigger(Trigger::Changed(
+            std::iter::once(PathBuf::from(path)).collect(),
+        ))
+    }
+
+    #[test]
+    fn test_next_coalesces_changes() {
+        let (tx, rx) = mpsc::channel();
+        tx.send(changed("src/lib.rs")).unwrap();
+        tx.send(changed("src/main.rs")).unwrap();
+        match next(&rx) {
+            Some(Trigger::Changed(paths)) => assert_eq!(paths.len(), 2),
+            other => panic!("expected changes, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_nested_gitignores() {
+        let dir = crate::util::TempDir::new("watch-test").unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src/generated")).unwrap();
+        std::fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        std::fs::write(root.join("src/.gitignore"), "generated/\n!keep.log\n").unwrap();
+        let mut ignores = Ignores::new(vec![root.to_owned()]);
+        assert!(ignores.ignored(&root.join("build.log")));
+        assert!(ignores.ignored(&root.join("src/generated/bindings.rs")));
+        assert!(ignores.ignored(&root.join(".git/index")));
+        assert!(!ignores.ignored(&root.join("src/lib.rs")));
+        // The deeper `.gitignore` wins.
+        assert!(ignores.ignored(&root.join("src/other.log")));
+        assert!(!ignores.ignored(&root.join("src/keep.log")));
+        // Outside of the roots, nothing's ignored.
+        assert!(!ignores.ignored(Path::new("/elsewhere/build.log")));
+        // Changing a `.gitignore` takes effect right away.
+        std::fs::write(root.join("src/.gitignore"), "").unwrap();
+        assert!(!ignores.ignored(&root.join("src/.gitignore")));
+        assert!(!ignores.ignored(&root.join("src/generated/bindings.rs")));
+    }
+
+    #[test]
+    fn test_next_prefers_rebuild_and_quit() {
+        let (tx, rx) = mpsc::channel();
+        tx.send(changed("src/lib.rs")).unwrap();
+        tx.send(Message::Trigger(Trigger::Rebuild)).unwrap();
+        assert!(matches!(next(&rx), Some(Trigger::Rebuild)));
+        tx.send(changed("src/lib.rs")).unwrap();
+        tx.send(Message::Quit).unwrap();
+        assert!(next(&rx).is_none());
+    }
+}
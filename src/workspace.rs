@@ -0,0 +1,407 @@
+//! Workspaces with more than one app in them, like a consumer app and an
+//! internal tool sharing library crates. Each app is a package with its own
+//! config, so it gets its own `gen` too. `-p` and `--manifest-path` pick
+//! which one a command's for, the same way they do for cargo, and `cargo
+//! mobile build --all` builds every one of them.
+
+use crate::{
+    config::{self, app::Platform, Config, Source},
+    opts, ui,
+    util::{
+        self,
+        cli::{GlobalFlags, Report, Reportable},
+    },
+};
+use serde::Deserialize;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    ManifestMissing(PathBuf),
+    MetadataFailed(bossy::Error),
+    MetadataInvalid(serde_json::Error),
+    PackageNotFound {
+        package: String,
+        suggestion: Option<String>,
+    },
+    ChangeDirFailed {
+        dir: PathBuf,
+        cause: io::Error,
+    },
+    DiscoverFailed(config::LoadError),
+    CurrentConfigFailed(config::CheckError),
+    ConfigInvalid {
+        package: String,
+        cause: config::CheckError,
+    },
+    NoApps,
+    NamesCollide {
+        what: &'static str,
+        name: String,
+        packages: (String, String),
+    },
+    CurrentExeFailed(io::Error),
+    BuildFailed {
+        package: String,
+        platform: Platform,
+        cause: bossy::Error,
+    },
+}
+
+impl Reportable for Error {
+    fn report(&self) -> Report {
+        match self {
+            Self::ManifestMissing(path) => Report::error(
+                "Failed to pick a package",
+                format!("The manifest path {:?} isn't a file", path),
+            ),
+            Self::MetadataFailed(err) => {
+                Report::error("Failed to list the workspace's packages", err)
+            }
+            Self::MetadataInvalid(err) => {
+                Report::error("`cargo metadata` output couldn't be parsed", err)
+            }
+            Self::PackageNotFound {
+                package,
+                suggestion,
+            } => {
+                let mut details = "Run `cargo metadata --no-deps` to see the workspace's packages.".to_owned();
+                if let Some(suggestion) = suggestion {
+                    details = format!("Did you mean `{}`? {}", suggestion, details);
+                }
+                Report::error(
+                    format!("There's no package named `{}` in the workspace", package),
+                    details,
+                )
+            }
+            Self::ChangeDirFailed { dir, cause } => Report::error(
+                format!("Failed to change to the package's dir {:?}", dir),
+                cause,
+            ),
+            Self::DiscoverFailed(err) => Report::error("Failed to look for an app's config", err),
+            Self::CurrentConfigFailed(err) => err.report(),
+            Self::ConfigInvalid { package, cause } => {
+                let report = cause.report();
+                Report::error(
+                    format!("The config for `{}` is invalid", package),
+                    format!("{}: {}", report.msg(), report.details()),
+                )
+            }
+            Self::NoApps => Report::error(
+                "There are no apps in the workspace",
+                "An app is a package with its own cargo-mobile config; run `cargo mobile init` in a package's dir to make one.",
+            ),
+            Self::NamesCollide {
+                what,
+                name,
+                packages: (first, second),
+            } => Report::error(
+                format!("`{}` and `{}` have the same {}", first, second, what),
+                format!(
+                    "Both are `{}`, so their builds would overwrite each other. Give one of them a different `app.name` or `app.domain` in its config.",
+                    name
+                ),
+            ),
+            Self::CurrentExeFailed(err) => {
+                Report::error("Failed to find the `cargo-mobile` executable", err)
+            }
+            Self::BuildFailed {
+                package,
+                platform,
+                cause,
+            } => Report::error(
+                format!("Failed to build `{}` for {}", package, platform),
+                cause,
+            ),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::ManifestMissing(..) => "workspace.manifest-missing",
+            Self::MetadataFailed(..) => "workspace.metadata-failed",
+            Self::MetadataInvalid(..) => "workspace.metadata-invalid",
+            Self::PackageNotFound { .. } => "workspace.package-not-found",
+            Self::ChangeDirFailed { .. } => "workspace.change-dir-failed",
+            Self::DiscoverFailed(..) => "workspace.discover-failed",
+            Self::CurrentConfigFailed(err) => err.code(),
+            Self::ConfigInvalid { .. } => "workspace.config-invalid",
+            Self::NoApps => "workspace.no-apps",
+            Self::NamesCollide { .. } => "workspace.names-collide",
+            Self::CurrentExeFailed(..) => "workspace.current-exe-failed",
+            Self::BuildFailed { .. } => "workspace.build-failed",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    name: String,
+    manifest_path: PathBuf,
+}
+
+impl Package {
+    fn dir(&self) -> &Path {
+        self.manifest_path
+            .parent()
+            .expect("developer error: manifest path has no parent")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+}
+
+fn metadata(manifest_path: Option<&Path>) -> Result<Metadata, Error> {
+    let mut command = bossy::Command::impure("cargo").with_args(&[
+        "metadata",
+        "--format-version",
+        "1",
+        "--no-deps",
+    ]);
+    if let Some(manifest_path) = manifest_path {
+        command.add_arg("--manifest-path").add_arg(manifest_path);
+    }
+    let output = command
+        .run_and_wait_for_str(|output| output.to_owned())
+        .map_err(Error::MetadataFailed)?;
+    serde_json::from_str(&output).map_err(Error::MetadataInvalid)
+}
+
+/// The dir of the package that `package` or `manifest_path` picks, like they
+/// would for cargo, or `None` if neither was given.
+pub fn package_dir(
+    manifest_path: Option<&Path>,
+    package: Option<&str>,
+) -> Result<Option<PathBuf>, Error> {
+    if let Some(manifest_path) = manifest_path {
+        if !manifest_path.is_file() {
+            return Err(Error::ManifestMissing(manifest_path.to_owned()));
+        }
+    }
+    let manifest_path = match (package, manifest_path) {
+        (Some(package), _) => {
+            let Metadata { packages } = metadata(manifest_path)?;
+            packages
+                .iter()
+                .find(|candidate| candidate.name == package)
+                .map(|package| package.manifest_path.clone())
+                .ok_or_else(|| Error::PackageNotFound {
+                    package: package.to_owned(),
+                    suggestion: util::suggest(
+                        package,
+                        packages.iter().map(|package| package.name.as_str()),
+                    )
+                    .map(str::to_owned),
+                })?
+        }
+        (None, Some(manifest_path)) => manifest_path.to_owned(),
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(
+        manifest_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+            .to_owned(),
+    ))
+}
+
+/// Runs the rest of the command from the dir of the package that `package`
+/// or `manifest_path` picks, so that the config and `gen` are that app's.
+pub fn enter(manifest_path: Option<&Path>, package: Option<&str>) -> Result<(), Error> {
+    if let Some(dir) = package_dir(manifest_path, package)? {
+        log::info!("running from the package dir {:?}", dir);
+        std::env::set_current_dir(&dir).map_err(|cause| Error::ChangeDirFailed { dir, cause })?;
+    }
+    Ok(())
+}
+
+/// An app in the workspace, which is a package that has its own config.
+#[derive(Debug)]
+pub struct App {
+    package: String,
+    config: Config,
+}
+
+impl App {
+    pub fn package(&self) -> &str {
+        &self.package
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Where these would collide, builds would overwrite each other's libs in
+    // the shared target dir, or installs would replace each other on devices.
+    fn unique_names(&self) -> Vec<(&'static str, String)> {
+        let app = self.config.app();
+        let names = app.names();
+        vec![
+            ("lib name", names.lib_name().to_owned()),
+            (
+                "Android application ID",
+                format!("{}.{}", app.reverse_domain(), names.identifier()),
+            ),
+            (
+                "bundle ID",
+                format!("{}.{}", app.reverse_domain(), names.xcode_product_name()),
+            ),
+        ]
+    }
+}
+
+/// The app the current dir is in.
+pub fn current() -> Result<App, Error> {
+    let config = Config::check(".").map_err(Error::CurrentConfigFailed)?;
+    Ok(App {
+        package: config.app().name().to_owned(),
+        config,
+    })
+}
+
+/// Every app in the workspace the current dir is in, in the order cargo lists
+/// their packages.
+pub fn apps() -> Result<Vec<App>, Error> {
+    let mut apps = Vec::new();
+    for package in metadata(None)?.packages {
+        let source = Source::discover(package.dir()).map_err(Error::DiscoverFailed)?;
+        let own = source.map_or(false, |source| {
+            package
+                .dir()
+                .canonicalize()
+                .map_or(false, |dir| source.root_dir() == dir)
+        });
+        if !own {
+            log::info!("`{}` has no config of its own", package.name);
+            continue;
+        }
+        let config = Config::check(package.dir()).map_err(|cause| Error::ConfigInvalid {
+            package: package.name.clone(),
+            cause,
+        })?;
+        apps.push(App {
+            package: package.name,
+            config,
+        });
+    }
+    Ok(apps)
+}
+
+fn collision(
+    apps: &[(&str, Vec<(&'static str, String)>)],
+) -> Option<(&'static str, String, (String, String))> {
+    for (i, (first, first_names)) in apps.iter().enumerate() {
+        for (second, second_names) in &apps[i + 1..] {
+            let same = first_names
+                .iter()
+                .zip(second_names)
+                .find(|((_, first), (_, second))| first == second);
+            if let Some(((what, name), _)) = same {
+                return Some((what, name.clone(), (first.to_string(), second.to_string())));
+            }
+        }
+    }
+    None
+}
+
+/// Makes sure no two apps would overwrite each other's builds.
+pub fn check_unique(apps: &[App]) -> Result<(), Error> {
+    let names = apps
+        .iter()
+        .map(|app| (app.package(), app.unique_names()))
+        .collect::<Vec<_>>();
+    match collision(&names) {
+        Some((what, name, packages)) => Err(Error::NamesCollide {
+            what,
+            name,
+            packages,
+        }),
+        None => Ok(()),
+    }
+}
+
+// Only the platforms that can be built for on this machine.
+fn buildable(platform: Platform) -> bool {
+    match platform {
+        Platform::Android => true,
+        Platform::Apple => cfg!(target_os = "macos"),
+    }
+}
+
+/// Builds every selected platform of each of `apps`, by running `cargo mobile
+/// <platform> build` from each app's dir, and stops at the first failure.
+/// `flags` are passed on to each build, so that they behave like this run.
+pub fn build(apps: &[App], profile: opts::Profile, flags: &GlobalFlags) -> Result<(), Error> {
+    if apps.is_empty() {
+        return Err(Error::NoApps);
+    }
+    check_unique(apps)?;
+    let exe = std::env::current_exe().map_err(Error::CurrentExeFailed)?;
+    for app in apps {
+        for &platform in app.config().app().platforms() {
+            if !buildable(platform) {
+                ui::status(format!(
+                    "Skipping {} for `{}`, since it can't be built on this machine",
+                    platform,
+                    app.package()
+                ));
+                continue;
+            }
+            ui::status(format!("Building `{}` for {}...", app.package(), platform));
+            let mut command = bossy::Command::impure(&exe)
+                .with_current_dir(app.config().app().root_dir())
+                .with_args(&[platform.name(), "build"])
+                .with_args(flags.args());
+            if !profile.debug() {
+                command.add_arg("--release");
+            }
+            command.run_and_wait().map_err(|cause| Error::BuildFailed {
+                package: app.package().to_owned(),
+                platform,
+                cause,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(lib: &str, domain: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("lib name", lib.to_owned()),
+            ("Android application ID", format!("{}.{}", domain, lib)),
+            ("bundle ID", format!("{}.{}", domain, lib.replace('_', "-"))),
+        ]
+    }
+
+    #[test]
+    fn test_collision() {
+        let apps = vec![
+            ("consumer", names("consumer", "com.example")),
+            ("tool", names("tool", "com.example")),
+        ];
+        assert_eq!(collision(&apps), None);
+        let apps = vec![
+            ("consumer", names("consumer", "com.example")),
+            ("tool", names("tool", "com.example")),
+            ("consumer-next", names("consumer", "com.example.next")),
+        ];
+        assert_eq!(
+            collision(&apps),
+            Some((
+                "lib name",
+                "consumer".to_owned(),
+                ("consumer".to_owned(), "consumer-next".to_owned())
+            ))
+        );
+    }
+}